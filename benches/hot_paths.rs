@@ -0,0 +1,142 @@
+//! Criterion baselines of the paths every edit goes through: applying diffs
+//! to documents of growing size, encoding and decoding them for the wire,
+//! and single keystrokes made and received through the engine.
+//!
+//! Run with `cargo bench --bench hot_paths`, and compare against a baseline
+//! saved with `-- --save-baseline <name>` using `-- --baseline <name>`.
+
+use async_trait::async_trait;
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use libp2p::PeerId;
+use p2p_notepad::{message::Message, Diff, Engine, Event, Incoming, MessageBuf, Notepad, NotepadError, Operation, Transport};
+
+/// Document sizes the apply path is measured at, in bytes.
+const SIZES: [usize; 3] = [10_000, 100_000, 1_000_000];
+/// Diffs in each batch, about what a burst of typing or a paste publishes.
+const DIFFS: usize = 1_000;
+
+/// A batch of inserts, deletes and string inserts spread over the first `len` bytes.
+fn batch(len: usize, diffs: usize) -> MessageBuf {
+    let messages = (0..diffs)
+        .map(|i| {
+            let index = i * 7919 % len;
+            match i % 3 {
+                0 => Diff { opcode: Operation::Ins, operand: Some('x'), index },
+                1 => Diff { opcode: Operation::Del, operand: None, index },
+                _ => Diff { opcode: Operation::InsStr("yz".to_string()), operand: None, index },
+            }
+        })
+        .collect();
+
+    MessageBuf { messages }
+}
+
+fn apply(c: &mut Criterion) {
+    let mut group = c.benchmark_group("apply_message_buf");
+
+    for len in SIZES {
+        let text = "a".repeat(len);
+        let message = batch(len / 2, DIFFS);
+
+        group.throughput(Throughput::Elements(DIFFS as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(len), &message, |b, message| {
+            b.iter_batched_ref(
+                || Notepad::new(text.clone()),
+                |notepad| notepad.apply_message_buf(black_box(message)).unwrap(),
+                BatchSize::LargeInput,
+            );
+        });
+    }
+
+    group.finish();
+}
+
+/// A transport every publish succeeds on, to one peer, without going anywhere.
+struct Discard(PeerId);
+
+#[async_trait]
+impl Transport for Discard {
+    fn peer_id(&self) -> PeerId {
+        self.0
+    }
+
+    fn publish(&mut self, _topic: &str, _data: Vec<u8>) -> Result<usize, NotepadError> {
+        Ok(1)
+    }
+
+    fn subscribe(&mut self, _topic: &str) -> Result<(), NotepadError> {
+        Ok(())
+    }
+
+    fn unsubscribe(&mut self, _topic: &str) -> Result<(), NotepadError> {
+        Ok(())
+    }
+
+    fn request(&mut self, _peer: &PeerId, _data: Vec<u8>) -> Result<(), NotepadError> {
+        Ok(())
+    }
+
+    fn respond(&mut self, _id: u64, _data: Vec<u8>) -> Result<(), NotepadError> {
+        Ok(())
+    }
+
+    async fn next_event(&mut self) -> Option<Event> {
+        None
+    }
+}
+
+/// A keystroke typed into the middle of the document, or deleting the one
+/// before, so the document keeps its size.
+fn keystroke(len: usize, i: u64) -> MessageBuf {
+    let index = len / 2;
+    let diff = match i % 2 {
+        0 => Diff { opcode: Operation::Ins, operand: Some('x'), index },
+        _ => Diff { opcode: Operation::Del, operand: None, index },
+    };
+
+    MessageBuf { messages: vec![diff] }
+}
+
+/// Keystrokes made locally and received from a peer through [`Engine`], with
+/// everything an edit goes through on the way: checks, history, attribution
+/// and publishing.
+fn engine(c: &mut Criterion) {
+    let mut group = c.benchmark_group("engine");
+    group.throughput(Throughput::Elements(1));
+
+    for len in SIZES {
+        let mut transport = Discard(PeerId::random());
+        let mut engine = Engine::new("bench", "notepad-", Notepad::new("a".repeat(len)));
+        let mut i = 0;
+        group.bench_function(BenchmarkId::new("edit", len), |b| b.iter(|| {
+            i += 1;
+            engine.edit(&mut transport, keystroke(len, i)).unwrap();
+        }));
+
+        let mut transport = Discard(PeerId::random());
+        let mut engine = Engine::new("bench", "notepad-", Notepad::new("a".repeat(len)));
+        let peer = PeerId::random();
+        let mut seq = 0;
+        group.bench_function(BenchmarkId::new("receive", len), |b| b.iter(|| {
+            seq += 1;
+            let data = Message::Diffs { document: "main".to_string(), seq, diffs: keystroke(len, seq) }.into();
+            engine.receive(&mut transport, Incoming { topic: engine.topic().to_string(), source: Some(peer), data });
+        }));
+    }
+
+    group.finish();
+}
+
+fn codec(c: &mut Criterion) {
+    let message = batch(100_000, DIFFS);
+    let data: Vec<u8> = message.clone().into();
+
+    let mut group = c.benchmark_group("codec");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.bench_function("encode", |b| b.iter(|| Vec::<u8>::from(black_box(message.clone()))));
+    group.bench_function("decode", |b| b.iter(|| MessageBuf::decode(black_box(&data)).unwrap()));
+    group.finish();
+}
+
+criterion_group!(benches, apply, codec, engine);
+criterion_main!(benches);
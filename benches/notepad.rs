@@ -0,0 +1,46 @@
+//! Times single keystrokes to a large document applied to the rope a
+//! `Notepad` holds against the same keystrokes applied to a plain string.
+//!
+//! Run with `cargo bench --bench notepad`.
+
+use std::time::{Duration, Instant};
+
+use p2p_notepad::{Diff, Notepad, Operation};
+
+fn keystrokes(len: usize, count: usize) -> Vec<Diff> {
+    (0..count)
+        .map(|i| {
+            let index = i * 7919 % len;
+            if i % 2 == 0 {
+                Diff { opcode: Operation::Ins, operand: Some('x'), index }
+            } else {
+                Diff { opcode: Operation::Del, operand: None, index }
+            }
+        })
+        .collect()
+}
+
+fn time(mut f: impl FnMut()) -> Duration {
+    let start = Instant::now();
+    f();
+    start.elapsed()
+}
+
+fn main() {
+    for len in [100_000, 1_000_000] {
+        let text = "a".repeat(len);
+        let diffs = keystrokes(len / 2, 10_000);
+
+        let mut string = text.clone();
+        let by_string = time(|| diffs.iter().for_each(|diff| match diff.opcode {
+            Operation::Ins => string.insert(diff.index, diff.operand.unwrap()),
+            _ => { string.remove(diff.index); },
+        }));
+
+        let mut notepad = Notepad::new(text);
+        let by_rope = time(|| diffs.iter().for_each(|diff| notepad.apply_diff(diff).unwrap()));
+
+        assert_eq!(notepad.text(), string);
+        println!("{len} chars, {} keystrokes: string {by_string:?}, rope {by_rope:?}", diffs.len());
+    }
+}
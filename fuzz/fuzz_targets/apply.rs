@@ -0,0 +1,30 @@
+//! Applies arbitrary diffs to arbitrary text, which has to fail on diffs
+//! that don't fit rather than panic, as peers send whichever they like.
+//!
+//! Run with `cargo +nightly fuzz run apply` from the crate root.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use p2p_notepad::{Diff, MessageBuf, Notepad, Operation};
+
+fuzz_target!(|input: (String, Vec<(u8, Option<char>, String, usize)>)| {
+    let (text, diffs) = input;
+    let messages = diffs
+        .into_iter()
+        .map(|(opcode, operand, text, index)| {
+            let opcode = match opcode % 5 {
+                0 => Operation::Del,
+                1 => Operation::Ins,
+                2 => Operation::Rep,
+                3 => Operation::DelRange(text.len()),
+                _ => Operation::InsStr(text),
+            };
+
+            Diff { opcode, operand, index }
+        })
+        .collect();
+
+    let mut notepad = Notepad { text };
+    let _ = notepad.apply_message_buf(&MessageBuf { messages });
+});
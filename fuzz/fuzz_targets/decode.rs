@@ -0,0 +1,15 @@
+//! Feeds arbitrary bytes to the decoders of what peers publish, which has to
+//! fail rather than panic.
+//!
+//! Run with `cargo +nightly fuzz run decode` from the crate root.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use p2p_notepad::{message::Message, MessageBuf};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = MessageBuf::decode(data);
+    let _ = MessageBuf::decode_fixed(data);
+    let _ = Message::try_from(data.to_vec());
+});
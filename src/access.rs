@@ -0,0 +1,72 @@
+use std::collections::HashSet;
+
+use libp2p::PeerId;
+
+/// Which peers are listened to, across every room: none that are blocked,
+/// and only those allowed if an allowlist is given. Anything else they send
+/// is dropped before it is looked at.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Access {
+    blocked: HashSet<PeerId>,
+    /// Only these peers are listened to, if set.
+    allowed: Option<HashSet<PeerId>>,
+}
+
+impl Access {
+    /// An allowlist is only kept if `allowed` isn't empty.
+    pub fn new(allowed: &[PeerId], blocked: &[PeerId]) -> Self {
+        Self {
+            blocked: blocked.iter().copied().collect(),
+            allowed: (!allowed.is_empty()).then(|| allowed.iter().copied().collect()),
+        }
+    }
+
+    pub fn admits(&self, peer_id: &PeerId) -> bool {
+        !self.blocked.contains(peer_id) && self.allowed.as_ref().is_none_or(|allowed| allowed.contains(peer_id))
+    }
+
+    /// Unblocks `peer_id`, and adds it to the allowlist if there is one.
+    pub fn allow(&mut self, peer_id: PeerId) {
+        self.blocked.remove(&peer_id);
+
+        if let Some(allowed) = &mut self.allowed {
+            allowed.insert(peer_id);
+        }
+    }
+
+    /// Blocks `peer_id`, and takes it off the allowlist if there is one.
+    pub fn block(&mut self, peer_id: PeerId) {
+        self.blocked.insert(peer_id);
+
+        if let Some(allowed) = &mut self.allowed {
+            allowed.remove(&peer_id);
+        }
+    }
+
+    pub fn blocked(&self) -> impl Iterator<Item = &PeerId> {
+        self.blocked.iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn blocks_and_allows() {
+        let (a, b) = (PeerId::random(), PeerId::random());
+
+        let mut access = Access::new(&[], &[a]);
+        assert!(!access.admits(&a) && access.admits(&b));
+        access.allow(a);
+        assert!(access.admits(&a));
+
+        let mut access = Access::new(&[a], &[]);
+        assert!(access.admits(&a) && !access.admits(&b));
+        access.allow(b);
+        assert!(access.admits(&b));
+        access.block(a);
+        access.allow(a);
+        assert!(access.admits(&a));
+    }
+}
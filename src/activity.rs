@@ -0,0 +1,100 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::{Duration, Instant}
+};
+
+use libp2p::PeerId;
+
+/// Time covered by one bar of the graph.
+pub const BUCKET_LEN: Duration = Duration::from_secs(60);
+
+/// Number of bars kept per peer, older activity is forgotten.
+pub const BUCKETS: u64 = 60;
+
+const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Operations applied per peer over time, for the `graph` command. Local
+/// edits are recorded under `None`.
+#[derive(Debug)]
+pub struct Activity {
+    start: Instant,
+    /// Operations by peer and bucket number, counted from `start`.
+    ops: HashMap<Option<PeerId>, BTreeMap<u64, usize>>,
+}
+
+impl Activity {
+    pub fn new(start: Instant) -> Self {
+        Self { start, ops: HashMap::new() }
+    }
+
+    pub fn record(&mut self, source: Option<PeerId>, ops: usize, now: Instant) {
+        let bucket = self.bucket(now);
+        let buckets = self.ops.entry(source).or_default();
+
+        *buckets.entry(bucket).or_default() += ops;
+        buckets.retain(|&old, _| old + BUCKETS > bucket);
+    }
+
+    /// Operations per bucket for each peer over the last [`BUCKETS`] buckets,
+    /// oldest first, busiest peers first.
+    pub fn histograms(&self, now: Instant) -> Vec<(Option<PeerId>, Vec<usize>)> {
+        let last = self.bucket(now);
+        let first = (last + 1).saturating_sub(BUCKETS);
+
+        let mut histograms: Vec<_> = self.ops
+            .iter()
+            .map(|(source, buckets)| (*source, (first..=last).map(|bucket| buckets.get(&bucket).copied().unwrap_or(0)).collect::<Vec<_>>()))
+            .filter(|(_, counts)| counts.iter().any(|&count| count > 0))
+            .collect();
+        histograms.sort_by_key(|(_, counts)| std::cmp::Reverse(counts.iter().sum::<usize>()));
+
+        histograms
+    }
+
+    pub fn memory(&self) -> usize {
+        self.ops.values().map(|buckets| buckets.len() * size_of::<(u64, usize)>()).sum()
+    }
+
+    fn bucket(&self, now: Instant) -> u64 {
+        now.saturating_duration_since(self.start).as_secs() / BUCKET_LEN.as_secs()
+    }
+}
+
+/// Counts drawn as bars scaled to the largest, e.g. `▁▁▄█`.
+pub fn sparkline(counts: &[usize]) -> String {
+    let max = counts.iter().copied().max().unwrap_or(0).max(1);
+
+    counts.iter().map(|&count| match count {
+        0 => ' ',
+        count => BARS[(count * BARS.len()).div_ceil(max) - 1],
+    }).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn records_activity() {
+        let start = Instant::now();
+        let mut activity = Activity::new(start);
+        let peer = PeerId::random();
+
+        activity.record(None, 1, start);
+        activity.record(Some(peer), 4, start + BUCKET_LEN * 2);
+        activity.record(Some(peer), 4, start + BUCKET_LEN * 2);
+
+        let histograms = activity.histograms(start + BUCKET_LEN * 2);
+        assert_eq!(histograms, vec![(Some(peer), vec![0, 0, 8]), (None, vec![1, 0, 0])]);
+
+        let histograms = activity.histograms(start + BUCKET_LEN * (BUCKETS as u32 + 1));
+        assert_eq!(histograms[0].1.len() as u64, BUCKETS);
+        assert_eq!(histograms.len(), 1);
+    }
+
+    #[test]
+    fn draws_sparklines() {
+        assert_eq!(sparkline(&[0, 1, 4, 8]), " ▁▄█");
+        assert_eq!(sparkline(&[]), "");
+    }
+}
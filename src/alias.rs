@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+
+use crate::error::NotepadError;
+
+/// Stands for what follows the alias name when it is typed, e.g. the `120`
+/// of `sig:120`.
+const ARGS: &str = "{}";
+
+/// Commands run by typing a single name, defined in the config with
+/// `alias <name> <command>[;<command>...]`. In the commands `{}` stands for
+/// anything typed after the name and a colon, and `\n` for a line break,
+/// which config files can't hold otherwise. Aliases may shadow built-in
+/// commands, and aren't expanded again inside other aliases.
+#[derive(Debug, Default, PartialEq)]
+pub struct Aliases {
+    aliases: HashMap<String, Vec<String>>,
+}
+
+impl Aliases {
+    /// Adds an alias from its definition, `<name> <command>[;<command>...]`.
+    pub fn define(&mut self, definition: &str) -> Result<(), NotepadError> {
+        let expected = || NotepadError::command("Expected format `alias <name> <command>[;<command>...]`");
+        let (name, commands) = definition.trim().split_once(char::is_whitespace).ok_or_else(expected)?;
+
+        if name.contains(':') {
+            return Err(NotepadError::command(format!("Alias `{name}` can't contain `:`")));
+        }
+
+        let commands: Vec<_> = commands
+            .split(';')
+            .map(|command| command.trim().replace("\\n", "\n"))
+            .filter(|command| !command.is_empty())
+            .collect();
+
+        if commands.is_empty() {
+            return Err(expected());
+        }
+
+        self.aliases.insert(name.to_string(), commands);
+
+        Ok(())
+    }
+
+    /// The commands `line` stands for, if it starts with an alias.
+    pub fn expand(&self, line: &str) -> Option<Vec<String>> {
+        let (name, args) = line.split_once(':').unwrap_or((line, ""));
+
+        self.aliases
+            .get(name)
+            .map(|commands| commands.iter().map(|command| command.replace(ARGS, args)).collect())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Vec<String>)> {
+        self.aliases.iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn expands_aliases() {
+        let mut aliases = Aliases::default();
+        aliases.define("sig inss:{}:-- \\nalice").unwrap();
+        aliases.define("mtg  doc new:Meeting ; insl:1:# Meeting {}").unwrap();
+
+        assert_eq!(aliases.expand("sig:12"), Some(vec!["inss:12:-- \nalice".to_string()]));
+        assert_eq!(aliases.expand("mtg:2025-01-02"), Some(vec!["doc new:Meeting".to_string(), "insl:1:# Meeting 2025-01-02".to_string()]));
+        assert_eq!(aliases.expand("mtg"), Some(vec!["doc new:Meeting".to_string(), "insl:1:# Meeting ".to_string()]));
+        assert_eq!(aliases.expand("see"), None);
+
+        assert!(aliases.define("empty ;").is_err());
+        assert!(aliases.define("a:b see").is_err());
+        assert!(aliases.define("lonely").is_err());
+    }
+}
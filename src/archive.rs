@@ -0,0 +1,106 @@
+use crate::{
+    container::{self, Section, SectionKind},
+    document::{DocumentMeta, Documents},
+    error::NotepadError,
+    message::Message
+};
+
+/// The documents of a room as written by `export` and read by `import`.
+///
+/// Stored as a container holding a metadata section (an encoded `Meta`
+/// message) followed by a text section for every document.
+#[derive(Debug, PartialEq)]
+pub struct Archive {
+    pub documents: Vec<(DocumentMeta, String)>,
+}
+
+impl Archive {
+    pub fn new(documents: &Documents) -> Self {
+        Self {
+            documents: documents
+                .iter()
+                .map(|document| (document.meta.clone(), document.notepad.text().to_string()))
+                .collect()
+        }
+    }
+
+    pub fn encode(self, passphrase: Option<&str>) -> Vec<u8> {
+        container::encode(self.into_sections(), passphrase)
+    }
+
+    pub fn decode(data: &[u8], passphrase: Option<&str>) -> Result<Self, NotepadError> {
+        Self::from_sections(container::decode(data, passphrase)?)
+    }
+
+    /// A metadata and a text section for every document.
+    pub fn into_sections(self) -> Vec<Section> {
+        self.documents
+            .into_iter()
+            .flat_map(|(meta, text)| [
+                Section { kind: SectionKind::Metadata, data: Message::Meta(meta).into() },
+                Section { kind: SectionKind::Text, data: text.into_bytes() },
+            ])
+            .collect()
+    }
+
+    pub fn from_sections(sections: Vec<Section>) -> Result<Self, NotepadError> {
+        let mut documents = Vec::new();
+        let mut sections = sections.into_iter();
+
+        while let Some(meta) = sections.next() {
+            let (SectionKind::Metadata, Some(Section { kind: SectionKind::Text, data: text })) = (meta.kind, sections.next()) else {
+                return Err(NotepadError::Decode("Expected a metadata and a text section per document"));
+            };
+
+            let Message::Meta(meta) = meta.data.try_into()? else {
+                return Err(NotepadError::Decode("Invalid document metadata"));
+            };
+            let text = String::from_utf8(text).map_err(|_| NotepadError::Decode("Document text is not valid UTF-8"))?;
+
+            documents.push((meta, text));
+        }
+
+        Ok(Archive { documents })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::notepad::Notepad;
+
+    fn def_archive() -> Archive {
+        let mut documents = Documents::new(Notepad::new("hello world".to_string()));
+        documents.create("todo").unwrap();
+
+        Archive::new(&documents)
+    }
+
+    #[test]
+    fn plain_round_trip() {
+        let data = def_archive().encode(None);
+        let archive = Archive::decode(&data, None).unwrap();
+
+        assert_eq!(archive.documents.len(), 2);
+        assert!(archive.documents.iter().any(|(meta, text)| meta.name == "main" && text == "hello world"));
+    }
+
+    #[test]
+    fn encrypted_round_trip() {
+        let archive = def_archive();
+        let documents = archive.documents.iter().map(|(meta, text)| (meta.clone(), text.clone())).collect();
+        let data = archive.encode(Some("secret"));
+
+        assert!(Archive::decode(&data, None).is_err());
+        assert_eq!(Archive::decode(&data, Some("secret")).unwrap(), Archive { documents });
+    }
+
+    #[test]
+    fn invalid_archives() {
+        let text_only = container::encode(vec![Section { kind: SectionKind::Text, data: vec![] }], None);
+        let meta_only = container::encode(vec![Section { kind: SectionKind::Metadata, data: vec![] }], None);
+
+        assert!(Archive::decode(&text_only, None).is_err());
+        assert!(Archive::decode(&meta_only, None).is_err());
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::{BTreeMap, HashMap};
+
+use libp2p::PeerId;
+
+use crate::error::NotepadError;
+
+/// Largest attachment that can be shared. Blobs are sent in one response and kept in memory.
+pub const MAX_ATTACHMENT_LEN: usize = 1_048_576;
+
+/// Times a download that doesn't match its hash is requested again before giving up.
+pub const MAX_RETRIES: usize = 3;
+
+/// Number of hex characters of the blake3 hash attachments are referenced by.
+pub const HASH_LEN: usize = 12;
+
+/// The short content hash an attachment is referenced by.
+pub fn hash(data: &[u8]) -> String {
+    blake3::hash(data).to_hex()[..HASH_LEN].to_string()
+}
+
+/// Inline reference to an attachment, as inserted into documents.
+pub fn reference(hash: &str) -> String {
+    format!("[attachment:{hash}]")
+}
+
+/// What is announced on the topic when an attachment is shared. The blob
+/// itself is only sent to peers asking for it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttachmentMeta {
+    pub hash: String,
+    pub name: String,
+    pub size: u32,
+}
+
+/// Attachments announced in the room, with the blobs that are held locally.
+#[derive(Debug, Default)]
+pub struct Attachments {
+    /// Announced attachments by hash, with the peer that announced them, `None` if it was this one.
+    known: BTreeMap<String, (AttachmentMeta, Option<PeerId>)>,
+    blobs: HashMap<String, Vec<u8>>,
+    /// Corrupted downloads by hash, see [`Attachments::retry`].
+    retries: HashMap<String, usize>,
+}
+
+impl Attachments {
+    /// Stores a local file as an attachment, returning the metadata to announce.
+    pub fn add(&mut self, name: &str, data: Vec<u8>) -> Result<AttachmentMeta, NotepadError> {
+        if data.len() > MAX_ATTACHMENT_LEN {
+            return Err(NotepadError::command(format!("Attachments can be at most {MAX_ATTACHMENT_LEN} bytes")));
+        }
+
+        let meta = AttachmentMeta { hash: hash(&data), name: name.to_string(), size: data.len() as u32 };
+
+        self.known.insert(meta.hash.clone(), (meta.clone(), None));
+        self.blobs.insert(meta.hash.clone(), data);
+
+        Ok(meta)
+    }
+
+    /// Records an attachment announced by `source`. Attachments held locally keep their source.
+    pub fn announce(&mut self, meta: AttachmentMeta, source: Option<PeerId>) {
+        if meta.hash.len() != HASH_LEN || meta.size as usize > MAX_ATTACHMENT_LEN || self.blobs.contains_key(&meta.hash) {
+            return;
+        }
+
+        self.known.insert(meta.hash.clone(), (meta, source));
+    }
+
+    pub fn get(&self, hash: &str) -> Option<&[u8]> {
+        self.blobs.get(hash).map(Vec::as_slice)
+    }
+
+    /// Peer to download the attachment from.
+    pub fn source(&self, hash: &str) -> Option<PeerId> {
+        self.known.get(hash).and_then(|(_, source)| *source)
+    }
+
+    /// Stores a downloaded blob if it is an announced attachment and matches its hash.
+    pub fn receive(&mut self, hash: &str, data: Vec<u8>) -> Result<&AttachmentMeta, NotepadError> {
+        let (meta, _) = self.known.get(hash).ok_or_else(|| NotepadError::command(format!("Unknown attachment: {hash}")))?;
+
+        if data.len() != meta.size as usize || self::hash(&data) != hash {
+            return Err(NotepadError::Integrity("Attachment doesn't match its hash"));
+        }
+
+        self.retries.remove(hash);
+        self.blobs.insert(hash.to_string(), data);
+
+        Ok(meta)
+    }
+
+    /// Records a corrupted download of `hash`, returning whether it should be requested again.
+    pub fn retry(&mut self, hash: &str) -> bool {
+        let retries = self.retries.entry(hash.to_string()).or_default();
+        *retries += 1;
+
+        *retries <= MAX_RETRIES
+    }
+
+    /// Every announced attachment, with whether its blob is held locally.
+    pub fn iter(&self) -> impl Iterator<Item = (&AttachmentMeta, bool)> {
+        self.known.values().map(|(meta, _)| (meta, self.blobs.contains_key(&meta.hash)))
+    }
+
+    /// Approximate bytes held by the metadata and blobs.
+    pub fn memory(&self) -> usize {
+        let known: usize = self.known.values().map(|(meta, _)| size_of::<(AttachmentMeta, Option<PeerId>)>() + meta.hash.capacity() * 2 + meta.name.capacity()).sum();
+        let blobs: usize = self.blobs.iter().map(|(hash, data)| size_of::<(String, Vec<u8>)>() + hash.capacity() + data.capacity()).sum();
+
+        known + blobs
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn downloads_are_verified() {
+        let mut local = Attachments::default();
+        let mut remote = Attachments::default();
+        let peer_id = PeerId::random();
+
+        let meta = local.add("a.txt", b"hello".to_vec()).unwrap();
+        assert_eq!(meta.hash.len(), HASH_LEN);
+        assert_eq!(local.get(&meta.hash), Some(b"hello".as_slice()));
+
+        assert!(remote.receive(&meta.hash, b"hello".to_vec()).is_err());
+
+        remote.announce(meta.clone(), Some(peer_id));
+        assert_eq!(remote.source(&meta.hash), Some(peer_id));
+        assert_eq!(remote.iter().collect::<Vec<_>>(), vec![(&meta, false)]);
+
+        assert!(matches!(remote.receive(&meta.hash, b"hellp".to_vec()), Err(NotepadError::Integrity(_))));
+        assert!((0..MAX_RETRIES).all(|_| remote.retry(&meta.hash)));
+        assert!(!remote.retry(&meta.hash));
+        assert_eq!(remote.receive(&meta.hash, b"hello".to_vec()).unwrap(), &meta);
+        assert_eq!(remote.get(&meta.hash), Some(b"hello".as_slice()));
+    }
+
+    #[test]
+    fn oversized_attachments() {
+        let mut attachments = Attachments::default();
+
+        assert!(attachments.add("big", vec![0; MAX_ATTACHMENT_LEN + 1]).is_err());
+
+        attachments.announce(AttachmentMeta { hash: "0".repeat(HASH_LEN), name: "big".to_string(), size: u32::MAX }, None);
+        assert_eq!(attachments.iter().count(), 0);
+    }
+}
@@ -0,0 +1,137 @@
+use libp2p::PeerId;
+
+use crate::diff::{MessageBuf, Operation};
+
+/// Who wrote each part of a document, as runs of bytes by author. Text whose
+/// author isn't known, such as text that arrived in a snapshot, is credited
+/// to `None`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Attribution {
+    runs: Vec<(Option<PeerId>, usize)>,
+}
+
+impl Attribution {
+    /// Credits the text `diffs` inserted to `author`, given the `inverse`
+    /// returned when applying them to text that was `before` bytes long. If
+    /// the text changed without being attributed, say by a snapshot, what
+    /// was there is credited to nobody first.
+    pub fn record(&mut self, before: usize, diffs: &MessageBuf, inverse: &MessageBuf, author: Option<PeerId>) {
+        if self.len() != before {
+            self.runs = (before > 0).then_some((None, before)).into_iter().collect();
+        }
+
+        // The inverse reverts the diffs last to first.
+        for (diff, undo) in diffs.messages.iter().zip(inverse.messages.iter().rev()) {
+            let removed = match &diff.opcode {
+                Operation::Del | Operation::Rep => undo.operand.map_or(0, char::len_utf8),
+                Operation::DelRange(len) => *len,
+                Operation::Ins | Operation::InsStr(_) => 0,
+            };
+            let inserted = match &diff.opcode {
+                Operation::Ins | Operation::Rep => diff.operand.map_or(0, char::len_utf8),
+                Operation::InsStr(text) => text.len(),
+                Operation::Del | Operation::DelRange(_) => 0,
+            };
+
+            self.splice(diff.index, removed, inserted, author);
+        }
+    }
+
+    /// Bytes of text attributed.
+    pub fn len(&self) -> usize {
+        self.runs.iter().map(|(_, len)| len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.runs.is_empty()
+    }
+
+    /// `text` split into runs by author, all of it unknown if the
+    /// attribution doesn't cover it exactly.
+    pub fn runs<'a>(&self, text: &'a str) -> Vec<(Option<PeerId>, &'a str)> {
+        if self.len() != text.len() {
+            return (!text.is_empty()).then_some((None, text)).into_iter().collect();
+        }
+
+        let mut start = 0;
+        self.runs
+            .iter()
+            .map(|&(author, len)| {
+                let run = text.get(start..start + len).unwrap_or_default();
+                start += len;
+                (author, run)
+            })
+            .collect()
+    }
+
+    /// Replaces `removed` bytes at `index` with `inserted` bytes by `author`.
+    fn splice(&mut self, index: usize, removed: usize, inserted: usize, author: Option<PeerId>) {
+        let first = self.split(index);
+        let last = self.split(index + removed);
+        self.runs.splice(first..last, (inserted > 0).then_some((author, inserted)));
+
+        self.runs.dedup_by(|next, run| {
+            let same = next.0 == run.0;
+            if same {
+                run.1 += next.1;
+            }
+            same
+        });
+    }
+
+    /// Splits the run across byte `at`, returning the index of the run that starts there.
+    fn split(&mut self, at: usize) -> usize {
+        let mut start = 0;
+
+        for i in 0..self.runs.len() {
+            let (author, len) = self.runs[i];
+            if start == at {
+                return i;
+            }
+            if at < start + len {
+                self.runs[i].1 = at - start;
+                self.runs.insert(i + 1, (author, start + len - at));
+                return i + 1;
+            }
+            start += len;
+        }
+
+        self.runs.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{diff::Diff, notepad::Notepad};
+
+    fn edit(notepad: &mut Notepad, attribution: &mut Attribution, diffs: Vec<Diff>, author: PeerId) {
+        let diffs = MessageBuf { messages: diffs };
+        let before = notepad.text().len();
+        let inverse = notepad.apply_inverting(&diffs).unwrap();
+
+        attribution.record(before, &diffs, &inverse, Some(author));
+    }
+
+    #[test]
+    fn credits_text_to_its_authors() {
+        let (a, b) = (PeerId::random(), PeerId::random());
+        let mut notepad = Notepad::new("hello".to_string());
+        let mut attribution = Attribution::default();
+
+        edit(&mut notepad, &mut attribution, vec![Diff { opcode: Operation::InsStr(" wörld".to_string()), operand: None, index: 5 }], a);
+        assert_eq!(attribution.runs(notepad.text()), vec![(None, "hello"), (Some(a), " wörld")]);
+
+        edit(&mut notepad, &mut attribution, vec![
+            Diff { opcode: Operation::Rep, operand: Some('W'), index: 6 },
+            Diff { opcode: Operation::DelRange(3), index: 1, operand: None },
+        ], b);
+        assert_eq!(attribution.runs(notepad.text()), vec![(None, "ho"), (Some(a), " "), (Some(b), "W"), (Some(a), "örld")]);
+
+        edit(&mut notepad, &mut attribution, vec![Diff { opcode: Operation::Del, operand: None, index: 4 }], a);
+        assert_eq!(attribution.runs(notepad.text()), vec![(None, "ho"), (Some(a), " "), (Some(b), "W"), (Some(a), "rld")]);
+
+        notepad.set_text("replaced".to_string());
+        assert_eq!(attribution.runs(notepad.text()), vec![(None, "replaced")]);
+    }
+}
@@ -0,0 +1,140 @@
+use std::collections::{HashMap, VecDeque};
+
+use libp2p::PeerId;
+
+use crate::diff::{Diff, MessageBuf};
+
+/// Edits held to backfill peers with, older ones are forgotten.
+pub const MAX_BACKLOG: usize = 4096;
+
+/// Most missed edits sent to a peer rather than a snapshot.
+pub const MAX_GAP: usize = 1024;
+
+/// Share of the documents' text, in percent, the missed edits may encode to
+/// before a snapshot is sent instead.
+pub const MAX_GAP_SHARE: usize = 50;
+
+/// An edit as a peer that missed it is sent it, by the peer that made it
+/// and the number it gave the edit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Missed {
+    pub peer: PeerId,
+    pub seq: u64,
+    pub document: String,
+    pub diffs: MessageBuf,
+    /// User that signed the edit, if any.
+    pub author: Option<PeerId>,
+}
+
+impl Missed {
+    /// Approximate bytes the edit is sent in.
+    fn len(&self) -> usize {
+        let mut data = Vec::new();
+        self.diffs.messages.iter().for_each(|diff| diff.push_postcard(&mut data));
+
+        self.document.len() + data.len()
+    }
+}
+
+/// The edits lately applied to the room's documents, local ones and the
+/// ones peers published alike, in the order they were applied. A peer that
+/// missed a few of them is sent those rather than every document whole.
+#[derive(Debug, Default)]
+pub struct Backlog {
+    edits: VecDeque<Missed>,
+    /// Number of the last edit held from each peer, so edits arriving again
+    /// or out of order aren't held twice.
+    newest: HashMap<PeerId, u64>,
+}
+
+impl Backlog {
+    pub fn record(&mut self, edit: Missed) {
+        if self.newest.get(&edit.peer).is_some_and(|&newest| edit.seq <= newest) {
+            return;
+        }
+
+        if self.edits.len() == MAX_BACKLOG {
+            self.edits.pop_front();
+        }
+
+        self.newest.insert(edit.peer, edit.seq);
+        self.edits.push_back(edit);
+    }
+
+    /// The edits up to `ours` a peer that applied those up to `theirs` missed,
+    /// in the order they were applied here. `None` if the peer has nothing to
+    /// build on, some weren't held or they'd take more than sending the
+    /// `text_len` bytes of the documents, so a snapshot is sent instead.
+    pub fn missed(&self, ours: &[(PeerId, u64)], theirs: &[(PeerId, u64)], text_len: usize) -> Option<Vec<Missed>> {
+        if theirs.is_empty() {
+            return None;
+        }
+
+        let applied = |peer: PeerId| theirs.iter().find(|&&(other, _)| other == peer).map_or(0, |&(_, seq)| seq);
+        let missed: Vec<_> = self.edits
+            .iter()
+            .filter(|edit| edit.seq > applied(edit.peer) && ours.iter().any(|&(peer, seq)| peer == edit.peer && edit.seq <= seq))
+            .cloned()
+            .collect();
+
+        // Edits are held once each, so the gap is whole if every edit in it is held.
+        let gap: u64 = ours.iter().map(|&(peer, seq)| seq.saturating_sub(applied(peer))).sum();
+        if missed.len() as u64 != gap || missed.len() > MAX_GAP {
+            return None;
+        }
+
+        let len: usize = missed.iter().map(Missed::len).sum();
+        (len * 100 <= text_len * MAX_GAP_SHARE).then_some(missed)
+    }
+
+    /// Forgets every edit, for when a document's text was replaced whole and
+    /// the edits before no longer lead to it.
+    pub fn clear(&mut self) {
+        self.edits.clear();
+        self.newest.clear();
+    }
+
+    /// Approximate bytes held by the edits.
+    pub fn memory(&self) -> usize {
+        self.edits.iter().map(|edit| size_of::<Missed>() + edit.document.capacity() + edit.diffs.messages.capacity() * size_of::<Diff>()).sum()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::diff::Operation;
+
+    fn edit(peer: PeerId, seq: u64) -> Missed {
+        let diffs = MessageBuf { messages: vec![Diff { opcode: Operation::Ins, operand: Some('a'), index: 0 }] };
+
+        Missed { peer, seq, document: "main".to_string(), diffs, author: None }
+    }
+
+    #[test]
+    fn small_gaps_are_backfilled() {
+        let (a, b) = (PeerId::random(), PeerId::random());
+        let mut backlog = Backlog::default();
+
+        for seq in 1..=3 {
+            backlog.record(edit(a, seq));
+        }
+        backlog.record(edit(a, 2));
+        backlog.record(edit(b, 2));
+
+        let ours = [(a, 3), (b, 2)];
+        let seqs = |missed: Vec<Missed>| missed.iter().map(|edit| (edit.peer, edit.seq)).collect::<Vec<_>>();
+        assert_eq!(backlog.missed(&ours, &[(a, 1), (b, 1)], 1000).map(seqs), Some(vec![(a, 2), (a, 3), (b, 2)]));
+        assert_eq!(backlog.missed(&ours, &[(a, 3), (b, 2)], 1000), Some(Vec::new()));
+
+        // Edit 1 of b was never held, and a peer that applied nothing has nothing to build on.
+        assert_eq!(backlog.missed(&ours, &[(a, 3)], 1000), None);
+        assert_eq!(backlog.missed(&ours, &[], 1000), None);
+
+        // A snapshot of a short text is sent instead.
+        assert_eq!(backlog.missed(&ours, &[(a, 1), (b, 1)], 10), None);
+
+        backlog.clear();
+        assert_eq!(backlog.missed(&ours, &[(a, 1), (b, 1)], 1000), None);
+    }
+}
@@ -0,0 +1,197 @@
+use std::{
+    io::{self, Write},
+    sync::mpsc::{self, Receiver, Sender},
+    thread::{self, JoinHandle}
+};
+
+use crate::{error::NotepadError, storage::Storage};
+
+/// A [`Storage`] whose writes are done on a thread of their own, fed
+/// through a channel, so a slow disk never stalls the loop serving the
+/// network and stdin. Writes are done in order and reads wait for the
+/// writes queued before them. Failed writes are printed, as nothing waits
+/// on them.
+#[derive(Debug)]
+pub struct BackgroundStorage {
+    /// `None` only while dropping, to let the thread finish.
+    jobs: Option<Sender<Job>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+#[derive(Debug)]
+enum Job {
+    Save(String, Vec<u8>),
+    Append(String, Vec<u8>),
+    Truncate(String, usize, Sender<Result<(), NotepadError>>),
+    Rewrite(String, Vec<u8>),
+    Load(String, Sender<Result<Option<Vec<u8>>, NotepadError>>),
+    Journal(String, Sender<Result<Vec<u8>, NotepadError>>),
+}
+
+impl BackgroundStorage {
+    pub fn spawn(mut storage: Box<dyn Storage + Send>) -> Self {
+        let (jobs, receiver): (_, Receiver<Job>) = mpsc::channel();
+
+        let thread = thread::spawn(move || {
+            for job in receiver {
+                // Reads and truncations are answered, their callers waiting.
+                let result = match job {
+                    Job::Save(name, data) => storage.save(&name, &data),
+                    Job::Append(name, data) => storage.append(&name, &data),
+                    Job::Rewrite(name, data) => storage.rewrite(&name, &data),
+                    Job::Truncate(name, len, reply) => {
+                        let _ = reply.send(storage.truncate(&name, len));
+                        continue;
+                    },
+                    Job::Load(name, reply) => {
+                        let _ = reply.send(storage.load(&name));
+                        continue;
+                    },
+                    Job::Journal(name, reply) => {
+                        let _ = reply.send(storage.journal(&name));
+                        continue;
+                    },
+                };
+
+                if let Err(e) = result {
+                    println!("{e}");
+                }
+            }
+        });
+
+        Self { jobs: Some(jobs), thread: Some(thread) }
+    }
+
+    fn queue(&self, job: Job) -> Result<(), NotepadError> {
+        self.jobs
+            .as_ref()
+            .and_then(|jobs| jobs.send(job).ok())
+            .ok_or_else(|| io::Error::other("storage thread is gone").into())
+    }
+
+    /// Queues a job and waits for its answer.
+    fn ask<T>(&self, job: impl FnOnce(Sender<Result<T, NotepadError>>) -> Job) -> Result<T, NotepadError> {
+        let (reply, answer) = mpsc::channel();
+        self.queue(job(reply))?;
+
+        answer.recv().map_err(|_| NotepadError::from(io::Error::other("storage thread is gone")))?
+    }
+}
+
+impl Storage for BackgroundStorage {
+    fn load(&self, name: &str) -> Result<Option<Vec<u8>>, NotepadError> {
+        self.ask(|reply| Job::Load(name.to_string(), reply))
+    }
+
+    fn save(&mut self, name: &str, data: &[u8]) -> Result<(), NotepadError> {
+        self.queue(Job::Save(name.to_string(), data.to_vec()))
+    }
+
+    fn journal(&self, name: &str) -> Result<Vec<u8>, NotepadError> {
+        self.ask(|reply| Job::Journal(name.to_string(), reply))
+    }
+
+    fn append(&mut self, name: &str, data: &[u8]) -> Result<(), NotepadError> {
+        self.queue(Job::Append(name.to_string(), data.to_vec()))
+    }
+
+    fn truncate(&mut self, name: &str, len: usize) -> Result<(), NotepadError> {
+        self.ask(|reply| Job::Truncate(name.to_string(), len, reply))
+    }
+
+    fn rewrite(&mut self, name: &str, data: &[u8]) -> Result<(), NotepadError> {
+        self.queue(Job::Rewrite(name.to_string(), data.to_vec()))
+    }
+}
+
+/// Finishes the writes still queued, as on exit the workspace is saved last.
+impl Drop for BackgroundStorage {
+    fn drop(&mut self) {
+        self.jobs.take();
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Writes to a writer on a thread of their own, like [`BackgroundStorage`], for
+/// the log file and capture. Writes are copied and never block.
+#[derive(Debug)]
+pub struct BackgroundWriter {
+    writes: Option<Sender<Vec<u8>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl BackgroundWriter {
+    pub fn spawn(mut writer: impl Write + Send + 'static) -> Self {
+        let (writes, receiver): (_, Receiver<Vec<u8>>) = mpsc::channel();
+
+        let thread = thread::spawn(move || {
+            for data in receiver {
+                // Errors aren't printed, as printing may end up back here when this is the log.
+                let _ = writer.write_all(&data);
+            }
+            let _ = writer.flush();
+        });
+
+        Self { writes: Some(writes), thread: Some(thread) }
+    }
+}
+
+impl Write for BackgroundWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writes
+            .as_ref()
+            .and_then(|writes| writes.send(buf.to_vec()).ok())
+            .ok_or_else(|| io::Error::other("writer thread is gone"))?;
+
+        Ok(buf.len())
+    }
+
+    /// Writes are flushed by the thread once done, nothing waits for them.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for BackgroundWriter {
+    fn drop(&mut self) {
+        self.writes.take();
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::Files;
+
+    #[test]
+    fn reads_follow_queued_writes() {
+        let dir = std::env::temp_dir().join(format!("p2p-notepad-background-{}", rand::random::<u64>()));
+        let mut storage = BackgroundStorage::spawn(Box::new(Files::new(dir.clone())));
+
+        storage.save("workspace", b"saved").unwrap();
+        storage.append("room.oplog", b"abc").unwrap();
+        storage.append("room.oplog", b"def").unwrap();
+        assert_eq!(storage.load("workspace").unwrap(), Some(b"saved".to_vec()));
+        assert_eq!(storage.journal("room.oplog").unwrap(), b"abcdef");
+
+        storage.truncate("room.oplog", 2).unwrap();
+        storage.append("room.oplog", b"g").unwrap();
+        drop(storage);
+        assert_eq!(std::fs::read(dir.join("room.oplog")).unwrap(), b"abg");
+
+        let path = dir.join("log");
+        let mut writer = BackgroundWriter::spawn(std::fs::File::create(&path).unwrap());
+        writeln!(writer, "hello").unwrap();
+        drop(writer);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "hello\n");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,242 @@
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::PathBuf,
+    time::{Duration, Instant}
+};
+
+use libp2p::PeerId;
+
+use crate::{
+    error::NotepadError,
+    oplog
+};
+
+/// Most bytes of operation log sent to the backup peer in one request.
+pub const MAX_BATCH: usize = 256 * 1024;
+
+/// How long to wait for the backup peer to answer before asking again.
+pub const BACKUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Where this peer backs up its operation log: a peer started with
+/// `--backup-dir`, and the key its backups are stored under there. The key
+/// outlives this peer's id, which is new every launch, so a backup can be
+/// fetched from another machine.
+#[derive(Debug)]
+pub struct BackupTarget {
+    pub peer: PeerId,
+    /// Hash of the backup key, naming this peer's backups on the backup peer.
+    pub owner: String,
+    /// Framed log records not yet sent.
+    pending: Vec<u8>,
+    /// Records sent and not acknowledged yet, with the length of the backup
+    /// they extend and when they were sent.
+    in_flight: Option<(u64, Vec<u8>, Instant)>,
+    /// Length of the backup on the peer, once it said.
+    len: Option<u64>,
+    /// When the peer was last asked the length of the backup.
+    asked: Option<Instant>,
+    /// Whether the backup was requested to restore from it.
+    pub restoring: bool,
+}
+
+impl BackupTarget {
+    pub fn new(peer: PeerId, key: &str) -> Self {
+        Self { peer, owner: owner(key), pending: Vec::new(), in_flight: None, len: None, asked: None, restoring: false }
+    }
+
+    /// The records to send next and the length of the backup they extend.
+    /// Only one batch is in flight at a time. Until the peer has said how
+    /// long the backup is, this is an empty batch extending any length,
+    /// which only asks.
+    pub fn next(&mut self, now: Instant) -> Option<(Option<u64>, Vec<u8>)> {
+        let due = |sent: Instant| now.saturating_duration_since(sent) >= BACKUP_TIMEOUT;
+
+        if let Some((offset, frames, sent)) = &mut self.in_flight {
+            if !due(*sent) {
+                return None;
+            }
+
+            *sent = now;
+            return Some((Some(*offset), frames.clone()));
+        }
+
+        let Some(len) = self.len else {
+            if self.pending.is_empty() || self.asked.is_some_and(|asked| !due(asked)) {
+                return None;
+            }
+
+            self.asked = Some(now);
+            return Some((None, Vec::new()));
+        };
+
+        let frames = self.take()?;
+        self.in_flight = Some((len, frames.clone(), now));
+
+        Some((Some(len), frames))
+    }
+
+    /// Takes the length of the backup the peer answered with, returning the
+    /// bytes of records it confirmed holding. A batch that doesn't account
+    /// for the length was refused, as the backup changed since it was sent,
+    /// and is sent again after what the backup holds.
+    pub fn ack(&mut self, len: u64) -> usize {
+        self.len = Some(len);
+
+        match self.in_flight.take() {
+            Some((offset, frames, _)) if offset + frames.len() as u64 == len => frames.len(),
+            Some((_, frames, _)) => {
+                self.pending.splice(0..0, frames);
+                0
+            },
+            None => 0,
+        }
+    }
+
+    /// Queues framed log records to be sent by [`BackupTarget::next`].
+    pub fn push(&mut self, frames: &[u8]) {
+        self.pending.extend(frames);
+    }
+
+    /// Takes the records waiting to be sent, up to about [`MAX_BATCH`] bytes
+    /// cut at a record boundary.
+    fn take(&mut self) -> Option<Vec<u8>> {
+        if self.pending.is_empty() {
+            return None;
+        }
+
+        let (_, mut len) = oplog::read(&self.pending[..self.pending.len().min(MAX_BATCH)]);
+        if len == 0 {
+            // A single record over the batch size goes alone.
+            len = oplog::read(&self.pending).1;
+        }
+
+        Some(self.pending.drain(..len).collect())
+    }
+
+    /// Forgets the records waiting and the backup they were for, for when
+    /// the room they were made in is left.
+    pub fn clear(&mut self) {
+        self.pending.clear();
+        self.in_flight = None;
+        self.len = None;
+        self.asked = None;
+        self.restoring = false;
+    }
+
+    pub fn memory(&self) -> usize {
+        self.pending.capacity() + self.in_flight.as_ref().map_or(0, |(_, frames, _)| frames.capacity())
+    }
+}
+
+/// Name a backup key is stored under, so the key itself never leaves this peer.
+pub fn owner(key: &str) -> String {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(b"p2p-notepad backup owner");
+    hasher.update(key.as_bytes());
+
+    hasher.finalize().to_hex()[..32].to_string()
+}
+
+/// Operation logs backed up here by other peers, one directory per owner
+/// holding a log per room.
+#[derive(Debug)]
+pub struct Backups {
+    dir: PathBuf,
+}
+
+impl Backups {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// Appends framed records to a backed up log if it is `offset` bytes
+    /// long, or whatever its length if `None`, returning the log's length.
+    /// Only whole records with matching checksums are taken.
+    pub fn store(&self, owner: &str, log: &str, offset: Option<u64>, frames: &[u8]) -> Result<u64, NotepadError> {
+        let path = self.path(owner, log)?;
+
+        if oplog::read(frames).1 != frames.len() {
+            return Err(NotepadError::Integrity("Backup holds a torn or corrupted record"));
+        }
+
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let len = file.metadata()?.len();
+
+        if offset.is_some_and(|offset| offset != len) {
+            return Ok(len);
+        }
+
+        file.write_all(frames)?;
+
+        Ok(len + frames.len() as u64)
+    }
+
+    /// The backed up log, empty if there is none.
+    pub fn load(&self, owner: &str, log: &str) -> Result<Vec<u8>, NotepadError> {
+        match std::fs::read(self.path(owner, log)?) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            result => Ok(result?),
+        }
+    }
+
+    /// Path of a backed up log. Names come from peers, so only the ones
+    /// this build makes are accepted, keeping them inside the directory.
+    fn path(&self, owner: &str, log: &str) -> Result<PathBuf, NotepadError> {
+        let hex = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_hexdigit());
+
+        if !hex(owner) || !log.strip_suffix(".oplog").is_some_and(hex) {
+            return Err(NotepadError::Integrity("Invalid backup name"));
+        }
+
+        Ok(self.dir.join(owner).join(log))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::diff::MessageBuf;
+
+    #[test]
+    fn stores_whole_records() {
+        let dir = std::env::temp_dir().join(format!("p2p-notepad-backup-{}", rand::random::<u64>()));
+        let backups = Backups::new(dir.clone());
+        let owner = owner("secret");
+        let log = oplog::name("room");
+
+        let now = Instant::now();
+        let mut target = BackupTarget::new(PeerId::random(), "secret");
+        assert!(target.next(now).is_none());
+
+        target.push(&oplog::text_frame("main", "hello"));
+        target.push(&oplog::diffs_frame("main", &MessageBuf::between("hello", "help")));
+        assert_eq!(target.next(now), Some((None, Vec::new())));
+        assert!(target.next(now).is_none());
+        assert_eq!(target.ack(backups.store(&owner, &log, None, &[]).unwrap()), 0);
+
+        let (offset, frames) = target.next(now).unwrap();
+        assert_eq!(offset, Some(0));
+        assert!(target.next(now).is_none());
+
+        let len = backups.store(&owner, &log, offset, &frames).unwrap();
+        assert_eq!(len, frames.len() as u64);
+        // An acknowledgement lost on the way back: the batch is sent again, and not stored twice.
+        assert_eq!(target.next(now + BACKUP_TIMEOUT), Some((offset, frames.clone())));
+        assert_eq!(target.ack(backups.store(&owner, &log, offset, &frames).unwrap()), frames.len());
+        assert!(target.next(now + BACKUP_TIMEOUT).is_none());
+
+        assert!(backups.store(&owner, &log, None, &frames[..frames.len() - 1]).is_err());
+        assert!(backups.store("../etc", &log, None, &frames).is_err());
+        assert!(backups.store(&owner, "passwd", None, &frames).is_err());
+
+        assert_eq!(oplog::read(&backups.load(&owner, &log).unwrap()).0.len(), 2);
+        assert!(backups.load(&owner, &oplog::name("other")).unwrap().is_empty());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
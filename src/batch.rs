@@ -0,0 +1,171 @@
+use std::time::{Duration, Instant};
+
+use crate::diff::{Diff, Granularity, MessageBuf, Operation};
+
+/// Longest edits are held at word granularity while typing goes on, should
+/// the window never pass without a keystroke.
+pub const MAX_WORD_HOLD: Duration = Duration::from_secs(1);
+
+/// Local edits held back for a short window, so that keystrokes typed in
+/// quick succession are published as one message instead of one each. The
+/// edits are applied locally straight away, only publishing waits.
+///
+/// At word granularity the characters of a word typed or deleted are held
+/// as a single operation, and edits are held until the window passes
+/// without a keystroke, for at most [`MAX_WORD_HOLD`].
+#[derive(Debug, Default)]
+pub struct EditBatch {
+    /// How long the first edit held waits for others, zero to publish every edit straight away.
+    pub window: Duration,
+    pub granularity: Granularity,
+    /// Document the held edits are to, their diffs in order, when the first
+    /// was held and when the last was.
+    pending: Option<(String, MessageBuf, Instant, Instant)>,
+    /// Character the last edit held typed or deleted, as words end where spacing does.
+    last: Option<char>,
+}
+
+impl EditBatch {
+    /// Holds `diffs` to `document` after those already held, returning the
+    /// edits held before them if those were to another document, which are
+    /// to be published now to stay in order. `inverse` reverts the diffs, it
+    /// tells what characters were deleted at word granularity.
+    pub fn push(&mut self, document: String, mut diffs: MessageBuf, inverse: &MessageBuf, now: Instant) -> Option<(String, MessageBuf)> {
+        let published = match &mut self.pending {
+            Some((held, _, _, last)) if *held == document => {
+                *last = now;
+                None
+            },
+            _ => {
+                self.last = None;
+                self.pending.replace((document, MessageBuf::default(), now, now)).map(|(document, diffs, _, _)| (document, diffs))
+            },
+        };
+        let Some((_, pending, _, _)) = &mut self.pending else {
+            return published;
+        };
+
+        match self.granularity {
+            Granularity::Char => pending.messages.append(&mut diffs.messages),
+            // The inverse reverts the diffs last to first.
+            Granularity::Word => for (diff, inverse) in diffs.messages.into_iter().zip(inverse.messages.iter().rev()) {
+                self.last = push_word(pending, diff, inverse, self.last);
+            },
+        }
+
+        published
+    }
+
+    /// When the edits held are due to be published, if any are.
+    pub fn due(&self) -> Option<Instant> {
+        self.pending.as_ref().map(|(_, _, since, last)| match self.granularity {
+            Granularity::Char => *since + self.window,
+            Granularity::Word => (*last + self.window).min(*since + self.window.max(MAX_WORD_HOLD)),
+        })
+    }
+
+    /// Takes the edits held if they are due at `now`.
+    pub fn take_due(&mut self, now: Instant) -> Option<(String, MessageBuf)> {
+        self.due().is_some_and(|due| due <= now).then(|| self.take()).flatten()
+    }
+
+    /// Takes the edits held, due or not.
+    pub fn take(&mut self) -> Option<(String, MessageBuf)> {
+        self.pending.take().map(|(document, diffs, _, _)| (document, diffs))
+    }
+
+    /// Number of diffs held.
+    pub fn len(&self) -> usize {
+        self.pending.as_ref().map_or(0, |(_, diffs, _, _)| diffs.messages.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_none()
+    }
+}
+
+/// Adds `diff` to the `pending` diffs as a word operation, joining the one
+/// before if it continues the same word, and returns the character typed
+/// or deleted. Words end where spacing does, after `last` was spacing.
+fn push_word(pending: &mut MessageBuf, diff: Diff, inverse: &Diff, last: Option<char>) -> Option<char> {
+    let (diff, c) = match (&diff.opcode, diff.operand, inverse.operand) {
+        (Operation::Ins, Some(c), _) => (Diff { opcode: Operation::InsStr(c.to_string()), operand: None, index: diff.index }, c),
+        (Operation::Del, _, Some(c)) => (Diff { opcode: Operation::DelRange(c.len_utf8()), operand: None, index: diff.index }, c),
+        _ => {
+            pending.messages.push(diff);
+            return None;
+        },
+    };
+    let same_word = last.is_some_and(|last| !last.is_whitespace() || c.is_whitespace());
+
+    match (pending.messages.last_mut(), &diff.opcode) {
+        (Some(Diff { opcode: Operation::InsStr(text), index, .. }), Operation::InsStr(typed)) if same_word && *index + text.len() == diff.index => {
+            text.push_str(typed);
+        },
+        // Deleting forwards stays at the index, backspacing moves back over the character.
+        (Some(Diff { opcode: Operation::DelRange(len), index, .. }), &Operation::DelRange(deleted))
+            if same_word && (*index == diff.index || diff.index + deleted == *index) => {
+            *len += deleted;
+            *index = diff.index;
+        },
+        _ => pending.messages.push(diff),
+    }
+
+    Some(c)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::notepad::Notepad;
+
+    fn ins(index: usize, char: char) -> MessageBuf {
+        MessageBuf { messages: vec![Diff { opcode: Operation::Ins, operand: Some(char), index }] }
+    }
+
+    #[test]
+    fn coalesces_edits_to_one_document() {
+        let now = Instant::now();
+        let mut batch = EditBatch { window: Duration::from_millis(100), ..Default::default() };
+
+        assert!(batch.push("a".to_string(), ins(0, 'x'), &MessageBuf::default(), now).is_none());
+        assert!(batch.push("a".to_string(), ins(1, 'y'), &MessageBuf::default(), now + Duration::from_millis(60)).is_none());
+        assert_eq!(batch.len(), 2);
+        assert_eq!(batch.due(), Some(now + Duration::from_millis(100)));
+        assert!(batch.take_due(now + Duration::from_millis(99)).is_none());
+
+        let (document, diffs) = batch.push("b".to_string(), ins(0, 'z'), &MessageBuf::default(), now).unwrap();
+        assert_eq!((document.as_str(), diffs.messages.len()), ("a", 2));
+
+        assert_eq!(batch.take_due(now + Duration::from_millis(100)).unwrap().0, "b");
+        assert!(batch.is_empty());
+        assert_eq!(batch.due(), None);
+    }
+
+    #[test]
+    fn words_are_held_as_one_operation() {
+        let now = Instant::now();
+        let mut batch = EditBatch { window: Duration::from_millis(100), granularity: Granularity::Word, ..Default::default() };
+        let mut notepad = Notepad::new("ab".to_string());
+        let mut type_in = |batch: &mut EditBatch, diffs: MessageBuf, at: u64| {
+            let inverse = notepad.apply_moving(&diffs, &mut []).unwrap();
+            batch.push("a".to_string(), diffs, &inverse, now + Duration::from_millis(at))
+        };
+        let del = |index: usize| MessageBuf { messages: vec![Diff { opcode: Operation::Del, operand: None, index }] };
+
+        for (i, c) in "hé yo".chars().enumerate() {
+            type_in(&mut batch, ins("ab".len() + "hé yo".char_indices().nth(i).unwrap().0, c), i as u64 * 60);
+        }
+        // Backspacing over "yo", then deleting "a" forwards.
+        type_in(&mut batch, del(7), 300);
+        type_in(&mut batch, del(6), 360);
+        type_in(&mut batch, del(0), 420);
+
+        // Held while typing goes on, up to the most words are held for.
+        assert_eq!(batch.due(), Some(now + Duration::from_millis(520)));
+        let (_, diffs) = batch.take().unwrap();
+        let ops: Vec<_> = diffs.messages.iter().map(ToString::to_string).collect();
+        assert_eq!(ops, ["inss:2:hé ", "inss:6:yo", "delr:6:2", "delr:0:1"]);
+        assert_eq!(notepad.text(), "bhé ");
+    }
+}
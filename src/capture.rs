@@ -0,0 +1,108 @@
+use std::{
+    fmt::Write as _,
+    fs::OpenOptions,
+    io::{LineWriter, Write},
+    path::Path
+};
+
+use crate::{
+    background::BackgroundWriter,
+    error::NotepadError,
+    message::Message,
+    transport::Incoming
+};
+
+/// Appends every received payload to a file, one line each with the topic,
+/// source, hex encoded bytes and decode result separated by tabs. Lines
+/// are written in the background, see [`BackgroundWriter`].
+#[derive(Debug)]
+pub struct Capture {
+    file: BackgroundWriter,
+}
+
+impl Capture {
+    pub fn open(path: &Path) -> Result<Self, NotepadError> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(Self { file: BackgroundWriter::spawn(LineWriter::new(file)) })
+    }
+
+    pub fn record(&mut self, incoming: &Incoming) -> Result<(), NotepadError> {
+        // In one write, so the line is queued whole.
+        self.file.write_all((line(incoming) + "\n").as_bytes())?;
+
+        Ok(())
+    }
+}
+
+fn line(incoming: &Incoming) -> String {
+    let source = incoming.source.map_or("-".to_string(), |peer_id| peer_id.to_string());
+    let result = match Message::try_from(incoming.data.clone()) {
+        Ok(message) => format!("ok {message:?}"),
+        Err(e) => format!("err {e}"),
+    };
+
+    let mut data = String::with_capacity(incoming.data.len() * 2);
+    for byte in &incoming.data {
+        write!(data, "{byte:02x}").expect("writing to a string can't fail");
+    }
+
+    format!("{}\t{source}\t{data}\t{result}", incoming.topic)
+}
+
+/// Reads back the payloads of a capture file, ignoring the recorded decode results.
+pub fn replay(capture: &str) -> Result<Vec<Incoming>, NotepadError> {
+    capture.lines().filter(|line| !line.is_empty()).map(|line| {
+        let mut fields = line.split('\t');
+        let (Some(topic), Some(source), Some(data)) = (fields.next(), fields.next(), fields.next()) else {
+            return Err(NotepadError::Decode("Truncated capture line"));
+        };
+
+        let source = match source {
+            "-" => None,
+            source => Some(source.parse().map_err(|_| NotepadError::Decode("Invalid capture source"))?),
+        };
+
+        if data.len() % 2 != 0 {
+            return Err(NotepadError::Decode("Invalid capture payload"));
+        }
+
+        let data = (0..data.len())
+            .step_by(2)
+            .map(|i| data.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+            .collect::<Option<Vec<u8>>>()
+            .ok_or(NotepadError::Decode("Invalid capture payload"))?;
+
+        Ok(Incoming { topic: topic.to_string(), source, data })
+    }).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use libp2p::PeerId;
+
+    use super::*;
+
+    #[test]
+    fn capture_round_trip() {
+        let incoming = vec![
+            Incoming { topic: "room".to_string(), source: Some(PeerId::random()), data: Message::Clipboard("hi".to_string()).into() },
+            Incoming { topic: "room".to_string(), source: None, data: vec![0xff, 0x00] },
+        ];
+
+        let capture: String = incoming.iter().map(|incoming| line(incoming) + "\n").collect();
+        let lines: Vec<_> = capture.lines().collect();
+
+        assert!(lines[0].ends_with("\tok Clipboard(\"hi\")"));
+        assert!(lines[1].starts_with("room\t-\tff00\terr "));
+        assert_eq!(replay(&capture).unwrap(), incoming);
+    }
+
+    #[test]
+    fn invalid_capture() {
+        assert!(replay("room").is_err());
+        assert!(replay("room\t-\tfff").is_err());
+        assert!(replay("room\t-\tzz").is_err());
+        assert!(replay("room\tnot-a-peer\t00").is_err());
+    }
+}
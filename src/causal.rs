@@ -0,0 +1,203 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    time::{Duration, Instant}
+};
+
+use libp2p::PeerId;
+
+/// Most edits held back per peer while waiting for an earlier one. Past
+/// this the missing edits are given up on, they were likely lost.
+pub const MAX_HELD: usize = 64;
+
+/// How long an edit is held back waiting for an earlier one before the gap is skipped.
+pub const HOLD_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Puts each peer's edits back in the order the peer made them. Gossipsub
+/// doesn't keep order, so a `del` could otherwise arrive before the `ins`
+/// it removes. Every peer numbers its edits from 1; a peer's first edit seen
+/// sets where its order starts, so joining a room late doesn't wait forever.
+#[derive(Debug)]
+pub struct Reorder<T> {
+    /// Sequence number expected next from each peer.
+    next: HashMap<PeerId, u64>,
+    /// Edits that arrived ahead of an earlier one, by peer and sequence number.
+    held: HashMap<PeerId, BTreeMap<u64, (T, Instant)>>,
+    /// Peers whose missing edits were asked for, see [`Reorder::stalled`].
+    asked: HashSet<PeerId>,
+}
+
+impl<T> Default for Reorder<T> {
+    fn default() -> Self {
+        Self { next: HashMap::new(), held: HashMap::new(), asked: HashSet::new() }
+    }
+}
+
+impl<T> Reorder<T> {
+    /// Takes edit `seq` from `peer`, returning every edit that can now be
+    /// applied in order. Edits seen before are dropped.
+    pub fn receive(&mut self, peer: PeerId, seq: u64, item: T, now: Instant) -> Vec<T> {
+        let next = *self.next.entry(peer).or_insert(seq);
+
+        if seq < next {
+            return Vec::new();
+        }
+
+        let held = self.held.entry(peer).or_default();
+        held.insert(seq, (item, now));
+
+        if held.len() > MAX_HELD {
+            self.skip_gap(peer);
+        }
+
+        self.release(peer)
+    }
+
+    /// Starts the order of `peer` at `next`, for when its earlier edits are
+    /// already included in a snapshot. Returns the held edits that follow.
+    pub fn start(&mut self, peer: PeerId, next: u64) -> Vec<T> {
+        self.next.insert(peer, next);
+
+        if let Some(held) = self.held.get_mut(&peer) {
+            held.retain(|&seq, _| seq >= next);
+        }
+
+        self.release(peer)
+    }
+
+    /// A peer whose edits have been missing for longer than [`HOLD_TIMEOUT`],
+    /// to ask for them before the gap is skipped. Each gap is only given once.
+    pub fn stalled(&mut self, now: Instant) -> Option<PeerId> {
+        self.asked.retain(|peer| self.held.contains_key(peer));
+
+        let peer = self.held
+            .iter()
+            .find(|(peer, held)| !self.asked.contains(peer) && held.values().any(|(_, arrived)| now.saturating_duration_since(*arrived) > HOLD_TIMEOUT))
+            .map(|(&peer, _)| peer)?;
+
+        self.asked.insert(peer);
+        Some(peer)
+    }
+
+    /// Gives up on edits that have been missing for longer than [`HOLD_TIMEOUT`],
+    /// returning the edits held back behind them with the peer that made them.
+    pub fn expire(&mut self, now: Instant) -> Vec<(PeerId, T)> {
+        let stale: Vec<_> = self.held
+            .iter()
+            .filter(|(_, held)| held.values().any(|(_, arrived)| now.saturating_duration_since(*arrived) > HOLD_TIMEOUT))
+            .map(|(&peer, _)| peer)
+            .collect();
+
+        stale.into_iter().flat_map(|peer| {
+            self.skip_gap(peer);
+            self.release(peer).into_iter().map(move |item| (peer, item))
+        }).collect()
+    }
+
+    /// Number of the last edit applied from each peer, for comparing with
+    /// another peer's, see [`crate::engine::Engine::verify`].
+    pub fn versions(&self) -> impl Iterator<Item = (PeerId, u64)> + '_ {
+        self.next.iter().map(|(&peer, &next)| (peer, next.saturating_sub(1))).filter(|&(_, seq)| seq > 0)
+    }
+
+    /// Edits held back across every peer.
+    pub fn held(&self) -> usize {
+        self.held.values().map(BTreeMap::len).sum()
+    }
+
+    /// Forgets a peer, for when it leaves the room.
+    pub fn forget(&mut self, peer: &PeerId) {
+        self.next.remove(peer);
+        self.held.remove(peer);
+        self.asked.remove(peer);
+    }
+
+    /// Moves the expected sequence number of `peer` up to its earliest held edit.
+    fn skip_gap(&mut self, peer: PeerId) {
+        if let Some(&first) = self.held.get(&peer).and_then(|held| held.keys().next()) {
+            self.next.insert(peer, first);
+        }
+    }
+
+    fn release(&mut self, peer: PeerId) -> Vec<T> {
+        let (Some(next), Some(held)) = (self.next.get_mut(&peer), self.held.get_mut(&peer)) else {
+            return Vec::new();
+        };
+        let mut ready = Vec::new();
+
+        while let Some((item, _)) = held.remove(next) {
+            ready.push(item);
+            *next += 1;
+        }
+
+        if held.is_empty() {
+            self.held.remove(&peer);
+        }
+
+        ready
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reorders_edits() {
+        let mut reorder = Reorder::default();
+        let peer = PeerId::random();
+        let now = Instant::now();
+
+        assert_eq!(reorder.receive(peer, 1, "a", now), vec!["a"]);
+        assert!(reorder.receive(peer, 3, "c", now).is_empty());
+        assert_eq!(reorder.held(), 1);
+        assert_eq!(reorder.receive(peer, 2, "b", now), vec!["b", "c"]);
+        assert!(reorder.receive(peer, 2, "b", now).is_empty());
+        assert_eq!(reorder.held(), 0);
+    }
+
+    #[test]
+    fn late_joiners_start_anywhere() {
+        let mut reorder = Reorder::default();
+        let (a, b) = (PeerId::random(), PeerId::random());
+        let now = Instant::now();
+
+        assert_eq!(reorder.receive(a, 40, "a", now), vec!["a"]);
+        assert_eq!(reorder.receive(b, 7, "b", now), vec!["b"]);
+        assert_eq!(reorder.receive(a, 41, "c", now), vec!["c"]);
+    }
+
+    #[test]
+    fn starts_after_snapshots() {
+        let mut reorder = Reorder::default();
+        let peer = PeerId::random();
+        let now = Instant::now();
+
+        reorder.receive(peer, 1, 1, now);
+        reorder.receive(peer, 3, 3, now);
+        reorder.receive(peer, 6, 6, now);
+        assert_eq!(reorder.start(peer, 5), Vec::<u64>::new());
+        assert_eq!(reorder.held(), 1);
+        assert_eq!(reorder.receive(peer, 5, 5, now), vec![5, 6]);
+    }
+
+    #[test]
+    fn gaps_are_skipped() {
+        let mut reorder = Reorder::default();
+        let peer = PeerId::random();
+        let now = Instant::now();
+
+        reorder.receive(peer, 1, 1, now);
+        assert!(reorder.receive(peer, 3, 3, now).is_empty());
+        assert!(reorder.expire(now + HOLD_TIMEOUT).is_empty());
+        assert_eq!(reorder.expire(now + HOLD_TIMEOUT + Duration::from_secs(1)), vec![(peer, 3)]);
+
+        assert!(reorder.receive(peer, 5, 5, now).is_empty());
+        let late = now + HOLD_TIMEOUT + Duration::from_secs(1);
+        assert_eq!(reorder.stalled(late), Some(peer));
+        assert_eq!(reorder.stalled(late), None);
+        assert_eq!(reorder.receive(peer, 4, 4, now), vec![4, 5]);
+
+        let released: Vec<_> = (7..=7 + MAX_HELD as u64).flat_map(|seq| reorder.receive(peer, seq, seq, now)).collect();
+        assert_eq!(released, (7..=7 + MAX_HELD as u64).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,36 @@
+//! The system clipboard, for `copy` and `pastec`. Only there when built with
+//! the `clipboard` feature, as reaching it takes a display server on Linux,
+//! otherwise both fail saying so.
+
+use crate::error::NotepadError;
+
+/// Puts `text` onto the system clipboard.
+#[cfg(feature = "clipboard")]
+pub fn set(text: &str) -> Result<(), NotepadError> {
+    arboard::Clipboard::new()
+        .and_then(|mut clipboard| clipboard.set_text(text))
+        .map_err(|e| NotepadError::command(format!("Couldn't copy to the clipboard: {e}")))
+}
+
+/// The text on the system clipboard.
+#[cfg(feature = "clipboard")]
+pub fn get() -> Result<String, NotepadError> {
+    arboard::Clipboard::new()
+        .and_then(|mut clipboard| clipboard.get_text())
+        .map_err(|e| NotepadError::command(format!("Couldn't read the clipboard: {e}")))
+}
+
+#[cfg(not(feature = "clipboard"))]
+pub fn set(_text: &str) -> Result<(), NotepadError> {
+    Err(unsupported())
+}
+
+#[cfg(not(feature = "clipboard"))]
+pub fn get() -> Result<String, NotepadError> {
+    Err(unsupported())
+}
+
+#[cfg(not(feature = "clipboard"))]
+fn unsupported() -> NotepadError {
+    NotepadError::command("Built without the clipboard, rebuild with `--features clipboard`")
+}
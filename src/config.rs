@@ -0,0 +1,1067 @@
+use std::{
+    io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration
+};
+
+use libp2p::{multiaddr::Protocol, Multiaddr, PeerId};
+
+use crate::{
+    alias::Aliases,
+    diff::Granularity,
+    error::NotepadError,
+    journal,
+    keymap::{Keymap, Preset},
+    log::Rotation,
+    matrix::Bridge,
+    message,
+    network::Validation,
+    oplog,
+    retry::RetryPolicy,
+    sanitize::ControlChars,
+    scoring::Scoring,
+    storage::Backend,
+    telemetry::Endpoint
+};
+
+/// Optional libp2p behaviours, gossipsub is always enabled.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Behaviours {
+    /// Discover peers on the local network.
+    pub mdns: bool,
+    /// Discover peers beyond the local network through a Kademlia DHT.
+    pub kad: bool,
+    /// Relay connections for peers that can't be reached directly.
+    pub relay: bool,
+    /// Serve as a rendezvous point, keeping the rooms peers register at
+    /// this node so others can find them.
+    pub rendezvous: bool,
+    /// Exchange listen addresses and protocols with connected peers.
+    pub identify: bool,
+    /// Keep connections alive and measure round trip times.
+    pub ping: bool,
+    /// Send and answer direct requests, needed to download attachments.
+    pub request_response: bool,
+    /// Learn from peers whether this one is reachable from outside, and
+    /// only reserve a slot on the relay if it isn't.
+    pub autonat: bool,
+}
+
+impl Default for Behaviours {
+    fn default() -> Self {
+        Self {
+            mdns: true,
+            kad: false,
+            relay: false,
+            rendezvous: false,
+            identify: true,
+            ping: true,
+            request_response: true,
+            autonat: false,
+        }
+    }
+}
+
+impl FromStr for Behaviours {
+    type Err = NotepadError;
+
+    /// Parses a comma separated list of behaviours to enable, e.g. `mdns,kad,ping`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut behaviours = Behaviours {
+            mdns: false,
+            kad: false,
+            relay: false,
+            rendezvous: false,
+            identify: false,
+            ping: false,
+            request_response: false,
+            autonat: false,
+        };
+
+        for name in s.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            let enabled = match name {
+                "mdns" => &mut behaviours.mdns,
+                "kad" => &mut behaviours.kad,
+                "relay" => &mut behaviours.relay,
+                "rendezvous" => &mut behaviours.rendezvous,
+                "identify" => &mut behaviours.identify,
+                "ping" => &mut behaviours.ping,
+                "request-response" => &mut behaviours.request_response,
+                "autonat" => &mut behaviours.autonat,
+                _ => return Err(NotepadError::command(format!("Unknown behaviour: {name:?}"))),
+            };
+
+            *enabled = true;
+        }
+
+        Ok(behaviours)
+    }
+}
+
+/// Transports listened on, see [`Config::listen_addresses`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transports {
+    pub tcp: bool,
+    pub quic: bool,
+}
+
+impl Default for Transports {
+    fn default() -> Self {
+        Self { tcp: true, quic: true }
+    }
+}
+
+impl FromStr for Transports {
+    type Err = NotepadError;
+
+    /// Parses a comma separated list of transports to listen on, e.g. `tcp`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut transports = Transports { tcp: false, quic: false };
+
+        for name in s.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            match name {
+                "tcp" => transports.tcp = true,
+                "quic" => transports.quic = true,
+                _ => return Err(NotepadError::command(format!("Unknown transport: {name:?}"))),
+            }
+        }
+
+        Ok(transports)
+    }
+}
+
+impl Transports {
+    /// Whether `address` is over an enabled transport. Others, such as
+    /// memory addresses, are always allowed.
+    pub fn allows(&self, address: &Multiaddr) -> bool {
+        address.iter().all(|protocol| match protocol {
+            Protocol::QuicV1 | Protocol::Quic => self.quic,
+            Protocol::Tcp(_) => self.tcp,
+            _ => true,
+        })
+    }
+}
+
+/// Settings that can be changed while running with `set:<key>:<value>`,
+/// named like their arguments. A value of 0 turns snapshots off.
+pub const TUNABLES: [&str; 6] = ["peer-timeout", "snapshot-secs", "snapshot-ops", "control-chars", "memory-budget", "autosave-idle"];
+
+/// Config file read on launch when it exists and no other is given with `--config`.
+pub const DEFAULT_FILE: &str = "config.toml";
+
+#[derive(Debug, PartialEq)]
+pub struct Config {
+    /// Publish to every peer subscribed to the room rather than only to mesh peers.
+    /// Costs bandwidth, but small rooms don't have to wait for the mesh to form.
+    pub flood_publish: bool,
+    /// Keep peers with shorter ping round trips in the room's mesh, see [`crate::fanout::Fanout`].
+    pub latency_mesh: bool,
+    /// How often gossipsub maintains the mesh and gossips about recent messages.
+    pub heartbeat: Duration,
+    /// How gossipsub checks messages, see [`Validation`].
+    pub validation: Validation,
+    /// How gossipsub scores peers, see [`Scoring`].
+    pub scoring: Scoring,
+    /// Attempts and backoff given to failed publishes, set with `--publish-retries`
+    /// and `--publish-backoff`.
+    pub retry: RetryPolicy,
+    /// How long local edits are held to be published together, see [`crate::batch::EditBatch`].
+    pub batch_window: Duration,
+    /// Whether local edits are published a character or a word at a time, see [`Granularity`].
+    pub granularity: Granularity,
+    /// Act as the room host and publish a full snapshot at this interval.
+    pub snapshot_interval: Option<Duration>,
+    /// Act as the room host and publish a full snapshot after this many applied operations.
+    pub snapshot_ops: Option<usize>,
+    pub behaviours: Behaviours,
+    /// Addresses to listen on, any port over tcp and quic if none are given.
+    /// IPv6 ones, such as `/ip6/::/tcp/4001`, are listened on alongside.
+    pub listen: Vec<Multiaddr>,
+    /// Transports listened on, the addresses of others are left out.
+    pub transports: Transports,
+    /// Kademlia nodes to join the DHT through, each ending in `/p2p/<peer id>`.
+    pub bootstrap: Vec<Multiaddr>,
+    /// Peers dialed on startup and again whenever the connection drops, each
+    /// ending in `/p2p/<peer id>`, for networks where mDNS is blocked.
+    pub peers: Vec<Multiaddr>,
+    /// Relay to listen through and reach peers behind NAT with, ending in
+    /// `/p2p/<peer id>`. Relayed connections are upgraded to direct ones by
+    /// hole punching where the NATs allow it.
+    pub relay: Option<Multiaddr>,
+    /// Rendezvous point every room joined is registered at, ending in
+    /// `/p2p/<peer id>`, to find the other peers in it beyond the local network.
+    pub rendezvous: Option<Multiaddr>,
+    /// Room joined on launch, unless a workspace is restored.
+    pub room: String,
+    /// Directory the identity, workspace and operation logs are kept in
+    /// unless given paths of their own, see [`Config::from_args`].
+    pub data_dir: Option<PathBuf>,
+    /// Prepended to room names to form gossipsub topics, so rooms don't
+    /// collide with other gossipsub applications using generic topic names,
+    /// nor with nodes speaking another wire format, see [`message::topic_prefix`].
+    pub topic_prefix: String,
+    /// File the node's keypair is kept in, so its peer id stays the same
+    /// across runs. Created on the first run, a new identity every run if unset.
+    pub identity: Option<PathBuf>,
+    /// Nickname announced to the room.
+    pub nickname: Option<String>,
+    /// Forget peers whose last presence heartbeat is older than this.
+    pub peer_timeout: Duration,
+    /// What to do with control characters peers send, set with `--control-chars`
+    /// or `set:control-chars`.
+    pub control_chars: ControlChars,
+    /// Bytes of state to stay under, by dropping caches and refusing large imports and snapshots.
+    pub memory_budget: Option<usize>,
+    /// Bytes no document may grow past, by local edits or a peer's.
+    pub max_document_size: Option<usize>,
+    /// Save the workspace and export the room once every other peer has
+    /// left and there was no input for this long, see [`crate::vacancy::Vacancy`].
+    pub autosave_idle: Option<Duration>,
+    /// Record every received payload to this file for debugging.
+    pub capture: Option<PathBuf>,
+    /// Save the session to this file and restore it on the next launch.
+    pub workspace: Option<PathBuf>,
+    /// Log every change to each room's documents in this directory and rebuild them from it on launch.
+    pub oplog: Option<PathBuf>,
+    /// Bytes an operation log grows to before it is compacted to the text of
+    /// each document, see [`crate::oplog::OpLog::compact`].
+    pub oplog_compact: Option<usize>,
+    /// Most operations kept in memory for `hist` and `at`, see [`crate::journal::Journal`].
+    pub journal_entries: usize,
+    /// What the workspace and operation logs are kept in, see [`Backend`].
+    pub storage: Backend,
+    /// Describe every change in plain sentences rather than redrawing documents, for screen readers.
+    pub plain_output: bool,
+    /// Print one JSON object per line for every event rather than sentences, see [`crate::output`].
+    pub json: bool,
+    /// Apply and show what peers write, but refuse local edits, see [`crate::engine::Engine::observe`].
+    pub observe: bool,
+    /// Acknowledge the edits applied to their authors and follow who applied
+    /// this peer's, see [`crate::engine::Engine::acknowledge`].
+    pub acks: bool,
+    /// Exchange digests with the room in the background and catch up when
+    /// copies stay apart, see [`crate::engine::Engine::repair`].
+    pub repair: bool,
+    /// Publish edits as ops of a sequence CRDT, so concurrent edits converge,
+    /// see [`crate::engine::Engine::crdt`].
+    pub crdt: bool,
+    /// Read commands from stdin rather than editing in the full screen editor.
+    /// Always the case when stdin or stdout isn't a terminal, or output is plain or JSON.
+    pub commands: bool,
+    /// Write logs to this file instead of the terminal.
+    pub log_file: Option<PathBuf>,
+    pub log_rotation: Rotation,
+    /// Post anonymous usage reports here. Off unless set.
+    pub telemetry: Option<Endpoint>,
+    /// Serve the HTTP control API on this address, see [`crate::control`]. Off unless set.
+    pub http: Option<SocketAddr>,
+    /// Token requests to the control API must carry as `Authorization: Bearer
+    /// <token>`. Required to serve it on anything but a loopback address.
+    pub http_token: Option<String>,
+    /// Keys of the full screen editor, see [`crate::keymap`].
+    pub keymap: Keymap,
+    /// Bridge chat with a Matrix room through this homeserver, see [`crate::matrix`]. Off unless set.
+    pub matrix: Option<Endpoint>,
+    /// Id of the Matrix room chat is bridged with.
+    pub matrix_room: Option<String>,
+    /// Access token of the Matrix account the bridge posts as.
+    pub matrix_token: Option<String>,
+    /// Run in the background, taking commands on this Unix socket rather than stdin, see [`crate::ipc`].
+    pub daemon: Option<PathBuf>,
+    /// Serve a directory of public rooms that peers opt into, as hosts and relays do.
+    pub directory: bool,
+    /// Peer serving the room directory used by `rooms` and `room list`.
+    pub directory_peer: Option<PeerId>,
+    /// Keep the operation logs other peers back up here, in this directory.
+    pub backup_dir: Option<PathBuf>,
+    /// Peer the operation log is backed up to as it grows, see `backup restore`.
+    pub backup_peer: Option<PeerId>,
+    /// Secret the backups are stored under on the backup peer, needed to restore them elsewhere.
+    pub backup_key: Option<String>,
+    /// Peers listened to, everyone not blocked if empty, see [`crate::access::Access`].
+    pub allowed: Vec<PeerId>,
+    /// Peers whose messages and requests are dropped.
+    pub blocked: Vec<PeerId>,
+    /// Also refuse connections from blocked peers, rather than only dropping what they send.
+    pub refuse_blocked: bool,
+    /// Commands run by typing a single name, see [`Aliases`].
+    pub aliases: Aliases,
+    /// Word list or hunspell `.dic` file checked by `spell`.
+    pub dictionary: Option<PathBuf>,
+    /// Config file the arguments were read from, and `config save` writes to.
+    pub file: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            flood_publish: true,
+            latency_mesh: true,
+            heartbeat: Duration::from_secs(10),
+            validation: Validation::default(),
+            scoring: Scoring::default(),
+            retry: RetryPolicy::default(),
+            batch_window: Duration::from_millis(100),
+            granularity: Granularity::Char,
+            snapshot_interval: None,
+            snapshot_ops: None,
+            behaviours: Behaviours::default(),
+            listen: Vec::new(),
+            transports: Transports::default(),
+            bootstrap: Vec::new(),
+            peers: Vec::new(),
+            relay: None,
+            rendezvous: None,
+            room: "test-net".to_string(),
+            data_dir: None,
+            topic_prefix: message::topic_prefix(),
+            identity: None,
+            nickname: None,
+            peer_timeout: Duration::from_secs(30),
+            control_chars: ControlChars::default(),
+            memory_budget: None,
+            max_document_size: None,
+            autosave_idle: None,
+            capture: None,
+            workspace: None,
+            oplog: None,
+            oplog_compact: Some(oplog::COMPACT_AFTER),
+            journal_entries: journal::MAX_ENTRIES,
+            storage: Backend::default(),
+            plain_output: false,
+            json: false,
+            observe: false,
+            acks: false,
+            repair: true,
+            crdt: false,
+            commands: false,
+            log_file: None,
+            log_rotation: Rotation::default(),
+            telemetry: None,
+            http: None,
+            http_token: None,
+            keymap: Keymap::default(),
+            matrix: None,
+            matrix_room: None,
+            matrix_token: None,
+            daemon: None,
+            directory: false,
+            directory_peer: None,
+            backup_dir: None,
+            backup_peer: None,
+            backup_key: None,
+            allowed: Vec::new(),
+            blocked: Vec::new(),
+            refuse_blocked: false,
+            aliases: Aliases::default(),
+            dictionary: None,
+            file: None,
+        }
+    }
+}
+
+impl Config {
+    /// Reads [`DEFAULT_FILE`] unless another config file is given, then the
+    /// arguments, then fills in the paths not given from the data directory, if there is one.
+    pub fn from_args(args: impl Iterator<Item = String>) -> Result<Self, NotepadError> {
+        let args: Vec<_> = args.collect();
+        let mut config = Config::default();
+
+        if !args.iter().any(|arg| arg == "--config") && Path::new(DEFAULT_FILE).exists() {
+            config.apply(["--config".to_string(), DEFAULT_FILE.to_string()].into_iter())?;
+        }
+        config.apply(args.into_iter())?;
+
+        if let Some(dir) = config.data_dir.clone() {
+            config.identity.get_or_insert_with(|| dir.join("identity.key"));
+            config.workspace.get_or_insert_with(|| dir.join("workspace"));
+            config.oplog.get_or_insert_with(|| dir.join("oplog"));
+        }
+
+        if config.http.is_some_and(|address| !address.ip().is_loopback()) && config.http_token.is_none() {
+            return Err(NotepadError::command("Serving the control API beyond this machine takes a `--http-token`"));
+        }
+        if config.matrix.is_some() && (config.matrix_room.is_none() || config.matrix_token.is_none()) {
+            return Err(NotepadError::command("Bridging with Matrix takes a `--matrix-room` and a `--matrix-token`"));
+        }
+
+        Ok(config)
+    }
+
+    /// The Matrix room chat is bridged with, if any.
+    pub fn matrix_bridge(&self) -> Option<Bridge> {
+        Some(Bridge { homeserver: self.matrix.clone()?, room: self.matrix_room.clone()?, token: self.matrix_token.clone()? })
+    }
+
+    /// Changes one of the [`TUNABLES`] while running.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), NotepadError> {
+        if !TUNABLES.contains(&key) {
+            return Err(NotepadError::command(format!("Unknown setting `{key}`, expected one of {}", TUNABLES.join(", "))));
+        }
+
+        self.apply([format!("--{key}"), value.to_string()].into_iter())
+    }
+
+    /// Current value of a tunable setting as written to the config file, `None` when it is off.
+    pub fn tunable(&self, key: &str) -> Option<String> {
+        match key {
+            "peer-timeout" => Some(self.peer_timeout.as_secs().to_string()),
+            "snapshot-secs" => self.snapshot_interval.map(|interval| interval.as_secs().to_string()),
+            "snapshot-ops" => self.snapshot_ops.map(|ops| ops.to_string()),
+            "control-chars" => Some(self.control_chars.to_string()),
+            "memory-budget" => self.memory_budget.map(|budget| budget.to_string()),
+            "autosave-idle" => self.autosave_idle.map(|idle| idle.as_secs().to_string()),
+            _ => None,
+        }
+    }
+
+    /// Writes the current tunable settings to the config file, keeping its other lines.
+    pub fn save(&self) -> Result<&Path, NotepadError> {
+        let path = self.file.as_deref().ok_or_else(|| NotepadError::command("No config file, start with `--config <path>`"))?;
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        let toml = is_toml(path);
+        let key_of = |line: &str| match toml {
+            true => toml_setting(line).map_or(String::new(), |(key, _)| key),
+            false => setting(line).0.to_string(),
+        };
+        let mut lines: Vec<_> = text.lines().filter(|line| !TUNABLES.contains(&key_of(line).as_str())).map(str::to_string).collect();
+
+        let tunables = TUNABLES.iter().filter_map(|key| {
+            let value = self.tunable(key)?;
+
+            Some(match toml {
+                true if value.parse::<u64>().is_err() => format!("{key} = {value:?}"),
+                true => format!("{key} = {value}"),
+                false => format!("{key} {value}"),
+            })
+        });
+        // Before the first table in TOML, so they don't end up in it.
+        let at = match toml {
+            true => {
+                let table = lines.iter().position(|line| line.trim_start().starts_with('[')).unwrap_or(lines.len());
+                lines[..table].iter().rposition(|line| !line.trim().is_empty()).map_or(0, |last| last + 1)
+            },
+            false => lines.len(),
+        };
+        lines.splice(at..at, tunables);
+
+        std::fs::write(path, lines.join("\n") + "\n")?;
+
+        Ok(path)
+    }
+
+    /// Applies arguments in order, so later ones override earlier ones and the config file.
+    fn apply(&mut self, mut args: impl Iterator<Item = String>) -> Result<(), NotepadError> {
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--flood-publish" => {
+                    self.flood_publish = value(&mut args, "--flood-publish <true|false>")?;
+                },
+                "--latency-mesh" => {
+                    self.latency_mesh = value(&mut args, "--latency-mesh <true|false>")?;
+                },
+                "--heartbeat" => {
+                    let millis = value(&mut args, "--heartbeat <milliseconds>")?;
+                    self.heartbeat = Duration::from_millis(millis);
+                },
+                "--validation-mode" => {
+                    self.validation = value(&mut args, "--validation-mode <strict|permissive|anonymous|none>")?;
+                },
+                "--scoring" => {
+                    self.scoring.enabled = value(&mut args, "--scoring <true|false>")?;
+                },
+                "--topic-weight" => {
+                    self.scoring.topic_weight = value(&mut args, "--topic-weight <weight>")?;
+                    self.scoring.validate()?;
+                },
+                "--invalid-message-weight" => {
+                    self.scoring.invalid_message_weight = value(&mut args, "--invalid-message-weight <weight>")?;
+                    self.scoring.validate()?;
+                },
+                "--graylist-threshold" => {
+                    self.scoring.graylist_threshold = value(&mut args, "--graylist-threshold <score>")?;
+                    self.scoring.validate()?;
+                },
+                "--publish-retries" => {
+                    self.retry.attempts = value(&mut args, "--publish-retries <count>")?;
+                },
+                "--publish-backoff" => {
+                    let millis = value(&mut args, "--publish-backoff <milliseconds>")?;
+                    self.retry.backoff = Duration::from_millis(millis);
+                },
+                "--batch-window" => {
+                    let millis = value(&mut args, "--batch-window <milliseconds>")?;
+                    self.batch_window = Duration::from_millis(millis);
+                },
+                "--diff-granularity" => {
+                    self.granularity = value(&mut args, "--diff-granularity <char|word>")?;
+                },
+                "--snapshot-secs" => {
+                    let secs = value(&mut args, "--snapshot-secs <seconds>")?;
+                    self.snapshot_interval = (secs > 0).then(|| Duration::from_secs(secs));
+                },
+                "--snapshot-ops" => {
+                    let ops = value(&mut args, "--snapshot-ops <count>")?;
+                    self.snapshot_ops = (ops > 0).then_some(ops);
+                },
+                "--behaviours" => {
+                    self.behaviours = value(&mut args, "--behaviours <mdns,kad,relay,rendezvous,identify,ping,request-response,autonat>")?;
+                },
+                "--mdns" => {
+                    self.behaviours.mdns = value(&mut args, "--mdns <true|false>")?;
+                },
+                // Given once per address, `--port` listens on one port over tcp and quic.
+                "--listen" => {
+                    self.listen.push(value(&mut args, "--listen <multiaddr>")?);
+                },
+                "--transports" => {
+                    self.transports = value(&mut args, "--transports <tcp,quic>")?;
+                },
+                "--port" => {
+                    let port: u16 = value(&mut args, "--port <port>")?;
+                    self.listen.push(format!("/ip4/0.0.0.0/udp/{port}/quic-v1").parse().expect("address is valid"));
+                    self.listen.push(format!("/ip4/0.0.0.0/tcp/{port}").parse().expect("address is valid"));
+                },
+                "--room" => {
+                    self.room = value(&mut args, "--room <room>")?;
+                },
+                "--data-dir" => {
+                    self.data_dir = Some(value(&mut args, "--data-dir <directory>")?);
+                },
+                "--topic-prefix" => {
+                    self.topic_prefix = value(&mut args, "--topic-prefix <prefix>")?;
+                },
+                "--identity" => {
+                    self.identity = Some(value(&mut args, "--identity <path>")?);
+                },
+                "--nick" => {
+                    self.nickname = Some(value(&mut args, "--nick <name>")?);
+                },
+                "--peer-timeout" => {
+                    let secs = value(&mut args, "--peer-timeout <seconds>")?;
+                    self.peer_timeout = Duration::from_secs(secs);
+                },
+                "--control-chars" => {
+                    self.control_chars = value(&mut args, "--control-chars <keep|strip|escape>")?;
+                },
+                "--memory-budget" => {
+                    self.memory_budget = Some(value(&mut args, "--memory-budget <bytes>")?);
+                },
+                "--max-document-size" => {
+                    self.max_document_size = Some(value(&mut args, "--max-document-size <bytes>")?);
+                },
+                "--autosave-idle" => {
+                    let secs = value(&mut args, "--autosave-idle <seconds>")?;
+                    self.autosave_idle = (secs > 0).then(|| Duration::from_secs(secs));
+                },
+                "--capture" => {
+                    self.capture = Some(value(&mut args, "--capture <path>")?);
+                },
+                "--workspace" => {
+                    self.workspace = Some(value(&mut args, "--workspace <path>")?);
+                },
+                "--oplog" => {
+                    self.oplog = Some(value(&mut args, "--oplog <directory>")?);
+                },
+                "--oplog-compact" => {
+                    self.oplog_compact = Some(value(&mut args, "--oplog-compact <bytes>")?).filter(|&bytes| bytes > 0);
+                },
+                "--journal-entries" => {
+                    self.journal_entries = value(&mut args, "--journal-entries <count>")?;
+                },
+                "--storage" => {
+                    self.storage = value(&mut args, "--storage <files|sled:<path>>")?;
+                },
+                "--plain-output" => {
+                    self.plain_output = value(&mut args, "--plain-output <true|false>")?;
+                },
+                "--json" => {
+                    self.json = value(&mut args, "--json <true|false>")?;
+                },
+                "--observe" => {
+                    self.observe = value(&mut args, "--observe <true|false>")?;
+                },
+                "--acks" => {
+                    self.acks = value(&mut args, "--acks <true|false>")?;
+                },
+                "--repair" => {
+                    self.repair = value(&mut args, "--repair <true|false>")?;
+                },
+                "--crdt" => {
+                    self.crdt = value(&mut args, "--crdt <true|false>")?;
+                },
+                "--commands" => {
+                    self.commands = value(&mut args, "--commands <true|false>")?;
+                },
+                "--log-file" => {
+                    self.log_file = Some(value(&mut args, "--log-file <path>")?);
+                },
+                "--log-rotate" => {
+                    self.log_rotation = value(&mut args, "--log-rotate <daily|bytes>")?;
+                },
+                "--telemetry" => {
+                    self.telemetry = Some(value(&mut args, "--telemetry <http://host[:port][/path]>")?);
+                },
+                "--http" => {
+                    self.http = Some(value(&mut args, "--http <address:port>")?);
+                },
+                "--http-token" => {
+                    self.http_token = Some(value(&mut args, "--http-token <token>")?);
+                },
+                "--keys" => {
+                    let preset: Preset = value(&mut args, "--keys <default|vim|emacs>")?;
+                    self.keymap.preset(preset);
+                },
+                "--bind" => {
+                    let definition: String = value(&mut args, "--bind <key> <binding>")?;
+                    self.keymap.bind(&definition, false)?;
+                },
+                "--bind-normal" => {
+                    let definition: String = value(&mut args, "--bind-normal <key> <binding>")?;
+                    self.keymap.bind(&definition, true)?;
+                },
+                "--matrix" => {
+                    self.matrix = Some(value(&mut args, "--matrix <http://homeserver[:port]>")?);
+                },
+                "--matrix-room" => {
+                    self.matrix_room = Some(value(&mut args, "--matrix-room <room id>")?);
+                },
+                "--matrix-token" => {
+                    self.matrix_token = Some(value(&mut args, "--matrix-token <access token>")?);
+                },
+                "--daemon" => {
+                    self.daemon = Some(value(&mut args, "--daemon <socket path>")?);
+                },
+                "--directory" => {
+                    self.directory = value(&mut args, "--directory <true|false>")?;
+                },
+                "--directory-peer" => {
+                    self.directory_peer = Some(value(&mut args, "--directory-peer <peer id>")?);
+                },
+                "--backup-dir" => {
+                    self.backup_dir = Some(value(&mut args, "--backup-dir <directory>")?);
+                },
+                "--backup-peer" => {
+                    self.backup_peer = Some(value(&mut args, "--backup-peer <peer id>")?);
+                },
+                // Given once per peer.
+                "--allow" => {
+                    self.allowed.push(value(&mut args, "--allow <peer id>")?);
+                },
+                "--block" => {
+                    self.blocked.push(value(&mut args, "--block <peer id>")?);
+                },
+                "--refuse-blocked" => {
+                    self.refuse_blocked = value(&mut args, "--refuse-blocked <true|false>")?;
+                },
+                "--backup-key" => {
+                    self.backup_key = Some(value(&mut args, "--backup-key <key>")?);
+                },
+                // Given once per node. Enables kad, unless `--behaviours` comes after.
+                "--bootstrap" => {
+                    let address: Multiaddr = value(&mut args, "--bootstrap <multiaddr>/p2p/<peer id>")?;
+                    if !matches!(address.iter().last(), Some(Protocol::P2p(_))) {
+                        return Err(NotepadError::command(format!("Bootstrap address `{address}` doesn't end in /p2p/<peer id>")));
+                    }
+
+                    self.bootstrap.push(address);
+                    self.behaviours.kad = true;
+                },
+                // Given once per peer.
+                "--peer" => {
+                    let address: Multiaddr = value(&mut args, "--peer <multiaddr>/p2p/<peer id>")?;
+                    if !matches!(address.iter().last(), Some(Protocol::P2p(_))) {
+                        return Err(NotepadError::command(format!("Peer address `{address}` doesn't end in /p2p/<peer id>")));
+                    }
+
+                    self.peers.push(address);
+                },
+                "--relay" => {
+                    let address: Multiaddr = value(&mut args, "--relay <multiaddr>/p2p/<peer id>")?;
+                    if !matches!(address.iter().last(), Some(Protocol::P2p(_))) {
+                        return Err(NotepadError::command(format!("Relay address `{address}` doesn't end in /p2p/<peer id>")));
+                    }
+
+                    self.relay = Some(address);
+                },
+                "--rendezvous" => {
+                    let address: Multiaddr = value(&mut args, "--rendezvous <multiaddr>/p2p/<peer id>")?;
+                    if !matches!(address.iter().last(), Some(Protocol::P2p(_))) {
+                        return Err(NotepadError::command(format!("Rendezvous address `{address}` doesn't end in /p2p/<peer id>")));
+                    }
+
+                    self.rendezvous = Some(address);
+                },
+                "--alias" => {
+                    let definition: String = value(&mut args, "--alias <name> <command>[;<command>...]")?;
+                    self.aliases.define(&definition)?;
+                },
+                "--dictionary" => {
+                    self.dictionary = Some(value(&mut args, "--dictionary <path>")?);
+                },
+                "--config" => {
+                    let path: PathBuf = value(&mut args, "--config <path>")?;
+                    self.load(&path)?;
+                    self.file = Some(path);
+                },
+                _ => return Err(NotepadError::command(format!("Unknown argument: {arg:?}"))),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Applies a config file of `<argument> <value>` lines, arguments named
+    /// without their dashes, or of `<argument> = <value>` lines if it is TOML, see [`toml_setting`].
+    fn load(&mut self, path: &Path) -> Result<(), NotepadError> {
+        let toml = is_toml(path);
+
+        for line in std::fs::read_to_string(path)?.lines() {
+            let (key, values) = match toml {
+                true => toml_setting(line)?,
+                false => {
+                    let (key, value) = setting(line);
+                    (key.to_string(), vec![value.to_string()])
+                },
+            };
+
+            if key.is_empty() || key.starts_with('#') {
+                continue;
+            }
+
+            if key == "config" {
+                return Err(NotepadError::command("Config files can't include other config files"));
+            }
+
+            for value in values {
+                self.apply([format!("--{key}"), value].into_iter())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Addresses to listen on: those given, or any port of every IPv4
+    /// interface, over the transports enabled.
+    pub fn listen_addresses(&self) -> Vec<Multiaddr> {
+        let listen = match self.listen.is_empty() {
+            true => ["/ip4/0.0.0.0/udp/0/quic-v1", "/ip4/0.0.0.0/tcp/0"].map(|address| address.parse().expect("address is valid")).to_vec(),
+            false => self.listen.clone(),
+        };
+
+        listen.into_iter().filter(|address| self.transports.allows(address)).collect()
+    }
+
+    /// Whether this node publishes periodic snapshots for its room.
+    pub fn is_host(&self) -> bool {
+        self.snapshot_interval.is_some() || self.snapshot_ops.is_some()
+    }
+}
+
+/// Splits a config file line into its key and value.
+fn setting(line: &str) -> (&str, &str) {
+    let line = line.trim();
+
+    line.split_once(char::is_whitespace).map_or((line, ""), |(key, value)| (key, value.trim()))
+}
+
+fn is_toml(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "toml")
+}
+
+/// Splits a line of a TOML config file into its key and values. Values are
+/// strings, numbers, booleans or arrays of them on one line, each array item
+/// given as the argument once. Tables only group settings, the keys in
+/// them are argument names all the same, and may use underscores for dashes.
+fn toml_setting(line: &str) -> Result<(String, Vec<String>), NotepadError> {
+    let line = line.trim();
+
+    if line.is_empty() || line.starts_with('#') || line.starts_with('[') {
+        return Ok((String::new(), Vec::new()));
+    }
+
+    let (key, value) = line.split_once('=').ok_or_else(|| NotepadError::command(format!("Expected `<key> = <value>` in the config file, got {line:?}")))?;
+    let key = key.trim().trim_matches('"').replace('_', "-");
+    let value = value.trim();
+
+    let values = match value.strip_prefix('[').and_then(|items| items.strip_suffix(']')) {
+        Some(items) => items.split(',').map(str::trim).filter(|item| !item.is_empty()).map(toml_value).collect(),
+        None => vec![toml_value(value)],
+    };
+
+    Ok((key, values))
+}
+
+/// A TOML value as an argument, strings unquoted.
+fn toml_value(value: &str) -> String {
+    match value.strip_prefix('"').and_then(|value| value.strip_suffix('"')) {
+        Some(string) => string.replace("\\\"", "\"").replace("\\\\", "\\"),
+        None => value.to_string(),
+    }
+}
+
+fn value<T: FromStr>(args: &mut impl Iterator<Item = String>, format: &str) -> Result<T, NotepadError> {
+    args
+        .next()
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| NotepadError::command(format!("Expected format `{format}`")))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(args: &[&str]) -> impl Iterator<Item = String> {
+        args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>().into_iter()
+    }
+
+    #[test]
+    fn default_args() {
+        let config = Config::from_args(args(&[])).unwrap();
+
+        assert_eq!(config, Config::default());
+        assert!(!config.is_host());
+    }
+
+    #[test]
+    fn enum_args() {
+        let config = Config::from_args(args(&["--flood-publish", "false", "--control-chars", "escape", "--plain-output", "true", "--commands", "true", "--observe", "true", "--latency-mesh", "false", "--json", "true", "--acks", "true", "--repair", "false", "--crdt", "true"])).unwrap();
+        assert!(!config.flood_publish && !config.latency_mesh && !config.repair && config.crdt);
+        assert!(config.plain_output && config.commands && config.observe && config.json && config.acks);
+        assert_eq!(config.control_chars, ControlChars::Escape);
+
+        assert!(Config::from_args(args(&["--flood-publish"])).is_err());
+        assert!(Config::from_args(args(&["--flood-publish", "maybe"])).is_err());
+        assert!(Config::from_args(args(&["--unknown"])).is_err());
+    }
+
+    #[test]
+    fn number_args() {
+        let config = Config::from_args(args(&["--snapshot-secs", "300", "--snapshot-ops", "50", "--peer-timeout", "60", "--memory-budget", "1000", "--max-document-size", "500"])).unwrap();
+
+        assert_eq!(config.snapshot_interval, Some(Duration::from_secs(300)));
+        assert_eq!(config.peer_timeout, Duration::from_secs(60));
+        assert_eq!(config.memory_budget, Some(1000));
+        assert_eq!(config.max_document_size, Some(500));
+        assert_eq!(config.snapshot_ops, Some(50));
+        assert!(config.is_host());
+
+        let config = Config::from_args(args(&["--oplog-compact", "0", "--journal-entries", "100"])).unwrap();
+        assert_eq!(config.oplog_compact, None);
+        assert_eq!(config.journal_entries, 100);
+
+        let config = Config::from_args(args(&["--autosave-idle", "600"])).unwrap();
+        assert_eq!(config.autosave_idle, Some(Duration::from_secs(600)));
+
+        let config = Config::from_args(args(&["--publish-retries", "3", "--publish-backoff", "250", "--batch-window", "0"])).unwrap();
+        assert_eq!(config.retry, RetryPolicy { attempts: 3, backoff: Duration::from_millis(250) });
+        assert_eq!(config.batch_window, Duration::ZERO);
+
+        let config = Config::from_args(args(&["--diff-granularity", "word"])).unwrap();
+        assert_eq!(config.granularity, Granularity::Word);
+        assert!(Config::from_args(args(&["--diff-granularity", "line"])).is_err());
+
+        let config = Config::from_args(args(&["--scoring", "false", "--topic-weight", "0.5", "--invalid-message-weight", "-20", "--graylist-threshold", "-40"])).unwrap();
+        assert_eq!(config.scoring, Scoring { enabled: false, topic_weight: 0.5, invalid_message_weight: -20.0, graylist_threshold: -40.0 });
+        assert!(Config::from_args(args(&["--graylist-threshold", "5"])).is_err());
+    }
+
+    #[test]
+    fn string_args() {
+        let config = Config::from_args(args(&["--capture", "payloads.log", "--nick", "alice", "--topic-prefix", "", "--workspace", "session", "--dictionary", "en.dic", "--oplog", "log", "--identity", "node.key"])).unwrap();
+        assert_eq!(config.identity, Some(PathBuf::from("node.key")));
+        assert_eq!(config.oplog, Some(PathBuf::from("log")));
+        assert_eq!(config.storage, Backend::Files);
+        assert_eq!(config.dictionary, Some(PathBuf::from("en.dic")));
+        assert_eq!(config.topic_prefix, "");
+        assert_eq!(config.capture, Some(PathBuf::from("payloads.log")));
+        assert_eq!(config.workspace, Some(PathBuf::from("session")));
+        assert_eq!(config.nickname.as_deref(), Some("alice"));
+
+        assert!(Config::from_args(args(&["--capture"])).is_err());
+
+        let config = Config::from_args(args(&["--storage", "sled:rooms.db"])).unwrap();
+        assert_eq!(config.storage, Backend::Sled(PathBuf::from("rooms.db")));
+        assert!(Config::from_args(args(&["--storage", "sqlite"])).is_err());
+
+        let config = Config::from_args(args(&["--log-file", "notepad.log", "--log-rotate", "daily"])).unwrap();
+        assert_eq!(config.log_file, Some(PathBuf::from("notepad.log")));
+        assert_eq!(config.log_rotation, Rotation::Daily);
+
+        let config = Config::from_args(args(&["--telemetry", "http://localhost:8080/usage"])).unwrap();
+        assert_eq!(config.telemetry.unwrap().port, 8080);
+        assert!(Config::from_args(args(&["--telemetry", "localhost"])).is_err());
+
+        let config = Config::from_args(args(&["--http", "127.0.0.1:7070"])).unwrap();
+        assert_eq!(config.http, Some("127.0.0.1:7070".parse().unwrap()));
+        assert!(Config::from_args(args(&["--http", "0.0.0.0:7070"])).is_err());
+        assert!(Config::from_args(args(&["--http", "0.0.0.0:7070", "--http-token", "secret"])).is_ok());
+
+        let config = Config::from_args(args(&["--bind", "ctrl+s catch-up", "--keys", "vim", "--bind-normal", "w next-doc"])).unwrap();
+        let mut keymap = Keymap::new(Preset::Vim);
+        keymap.bind("ctrl+s catch-up", false).unwrap();
+        keymap.bind("w next-doc", true).unwrap();
+        assert_eq!(config.keymap, keymap);
+        assert!(Config::from_args(args(&["--keys", "nano"])).is_err());
+        assert!(Config::from_args(args(&["--bind", "ctrl+s"])).is_err());
+
+        let config = Config::from_args(args(&["--matrix", "http://localhost:8008", "--matrix-room", "!room:example.org", "--matrix-token", "secret"])).unwrap();
+        assert_eq!(config.matrix_bridge().unwrap().room, "!room:example.org");
+        assert!(Config::from_args(args(&["--matrix", "http://localhost:8008", "--matrix-room", "!room:example.org"])).is_err());
+
+        let config = Config::from_args(args(&["--daemon", "/tmp/notepad.sock"])).unwrap();
+        assert_eq!(config.daemon, Some(PathBuf::from("/tmp/notepad.sock")));
+    }
+
+    #[test]
+    fn deployment_args() {
+        let config = Config::from_args(args(&["--port", "4001", "--listen", "/ip6/::/tcp/4002", "--room", "standup", "--mdns", "false"])).unwrap();
+        assert_eq!(config.listen, vec![
+            "/ip4/0.0.0.0/udp/4001/quic-v1".parse().unwrap(),
+            "/ip4/0.0.0.0/tcp/4001".parse().unwrap(),
+            "/ip6/::/tcp/4002".parse().unwrap(),
+        ]);
+        assert_eq!(config.room, "standup");
+        assert!(!config.behaviours.mdns && config.behaviours.ping);
+        assert!(Config::from_args(args(&["--port", "70000"])).is_err());
+
+        let config = Config::from_args(args(&["--port", "4001", "--listen", "/ip6/::/udp/4002/quic-v1", "--transports", "tcp"])).unwrap();
+        assert_eq!(config.listen_addresses(), vec!["/ip4/0.0.0.0/tcp/4001".parse::<Multiaddr>().unwrap()]);
+        assert_eq!(Config::default().listen_addresses().len(), 2);
+        assert!(Config::from_args(args(&["--transports", "tcp,ws"])).is_err());
+
+        let config = Config::from_args(args(&["--data-dir", "data", "--oplog", "logs"])).unwrap();
+        assert_eq!(config.identity, Some(PathBuf::from("data/identity.key")));
+        assert_eq!(config.workspace, Some(PathBuf::from("data/workspace")));
+        assert_eq!(config.oplog, Some(PathBuf::from("logs")));
+    }
+
+    #[test]
+    fn directory_args() {
+        let peer_id = PeerId::random();
+        let config = Config::from_args(args(&["--directory", "true", "--directory-peer", &peer_id.to_string()])).unwrap();
+
+        assert!(config.directory);
+        assert_eq!(config.directory_peer, Some(peer_id));
+        assert!(Config::from_args(args(&["--directory-peer", "alice"])).is_err());
+
+        let config = Config::from_args(args(&["--backup-peer", &peer_id.to_string(), "--backup-key", "secret", "--backup-dir", "backups"])).unwrap();
+        assert_eq!((config.backup_peer, config.backup_key.as_deref()), (Some(peer_id), Some("secret")));
+        assert_eq!(config.backup_dir, Some(PathBuf::from("backups")));
+
+        let other = PeerId::random();
+        let config = Config::from_args(args(&["--block", &peer_id.to_string(), "--block", &other.to_string(), "--refuse-blocked", "true"])).unwrap();
+        assert_eq!(config.blocked, vec![peer_id, other]);
+        assert!(config.refuse_blocked && config.allowed.is_empty());
+        assert!(Config::from_args(args(&["--allow", "alice"])).is_err());
+    }
+
+    #[test]
+    fn runtime_settings() {
+        let mut config = Config::default();
+
+        config.set("snapshot-ops", "20").unwrap();
+        config.set("control-chars", "keep").unwrap();
+        assert_eq!(config.snapshot_ops, Some(20));
+        assert_eq!(config.tunable("control-chars").as_deref(), Some("keep"));
+
+        config.set("snapshot-ops", "0").unwrap();
+        assert!(!config.is_host());
+
+        assert!(config.set("nick", "alice").is_err());
+        assert!(config.set("peer-timeout", "soon").is_err());
+        assert!(config.save().is_err());
+    }
+
+    #[test]
+    fn config_file() {
+        let path = std::env::temp_dir().join(format!("p2p-notepad-config-{}", rand::random::<u64>()));
+        std::fs::write(&path, "# host\nnick alice\nsnapshot-secs 60\nalias sig inss:{}:-- alice\n").unwrap();
+
+        let mut config = Config::from_args(args(&["--config", path.to_str().unwrap(), "--snapshot-secs", "30"])).unwrap();
+        assert_eq!(config.nickname.as_deref(), Some("alice"));
+        assert_eq!(config.snapshot_interval, Some(Duration::from_secs(30)));
+        assert_eq!(config.aliases.expand("sig:4"), Some(vec!["inss:4:-- alice".to_string()]));
+
+        config.set("memory-budget", "1000").unwrap();
+        config.save().unwrap();
+
+        let saved = std::fs::read_to_string(&path).unwrap();
+        assert!(saved.starts_with("# host\nnick alice\n") && saved.contains("alias sig inss:{}:-- alice\n"));
+        assert!(saved.contains("snapshot-secs 30\n") && saved.contains("memory-budget 1000\n"));
+        assert_eq!(Config::from_args(args(&["--config", path.to_str().unwrap()])).unwrap(), config);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn toml_config_file() {
+        let path = std::env::temp_dir().join(format!("p2p-notepad-config-{}.toml", rand::random::<u64>()));
+        let node = "/ip4/203.0.113.7/tcp/4001/p2p/12D3KooWDpJ7As7BWAwRMfu1VU2WCqNjvq387JEYKDBj4kx6nXTN";
+        std::fs::write(&path, format!(concat!(
+            "room = \"standup\"\nautosave_idle = 600\n\n",
+            "[transports]\nlisten = [\"/ip4/0.0.0.0/tcp/4001\", \"/ip4/0.0.0.0/udp/4001/quic-v1\"]\n\n",
+            "[gossipsub]\nheartbeat = 500\nvalidation-mode = \"permissive\"\nflood-publish = false\n\n",
+            "[peers]\nbootstrap = [\"{0}\"]\npeer = [\"{0}\"]\n",
+        ), node)).unwrap();
+
+        let mut config = Config::from_args(args(&["--config", path.to_str().unwrap(), "--heartbeat", "1000"])).unwrap();
+        assert_eq!(config.room, "standup");
+        assert_eq!(config.autosave_idle, Some(Duration::from_secs(600)));
+        assert_eq!(config.listen.len(), 2);
+        assert_eq!(config.heartbeat, Duration::from_secs(1));
+        assert_eq!(config.validation, Validation::Permissive);
+        assert!(!config.flood_publish);
+        assert_eq!(config.bootstrap, vec![node.parse().unwrap()]);
+        assert_eq!(config.peers, config.bootstrap);
+
+        config.set("control-chars", "escape").unwrap();
+        config.save().unwrap();
+
+        let saved = std::fs::read_to_string(&path).unwrap();
+        assert!(saved.contains("control-chars = \"escape\"\nautosave-idle = 600\n\n[transports]\n"));
+        assert!(!saved.contains("autosave_idle"));
+        assert_eq!(Config::from_args(args(&["--config", path.to_str().unwrap(), "--heartbeat", "1000"])).unwrap(), config);
+
+        std::fs::write(&path, "room standup\n").unwrap();
+        assert!(Config::from_args(args(&["--config", path.to_str().unwrap()])).is_err());
+        assert!(Config::from_args(args(&["--validation-mode", "lenient"])).is_err());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn behaviours_arg() {
+        let config = Config::from_args(args(&["--behaviours", "kad, ping, autonat, rendezvous"])).unwrap();
+
+        assert!(config.behaviours.kad && config.behaviours.ping && config.behaviours.autonat && config.behaviours.rendezvous);
+        assert!(!config.behaviours.mdns && !config.behaviours.identify);
+
+        let config = Config::from_args(args(&["--behaviours", ""])).unwrap();
+        assert!(!config.behaviours.ping);
+
+        assert!(Config::from_args(args(&["--behaviours", "mdns,bogus"])).is_err());
+
+        let node = "/ip4/203.0.113.7/tcp/4001/p2p/12D3KooWDpJ7As7BWAwRMfu1VU2WCqNjvq387JEYKDBj4kx6nXTN";
+        let config = Config::from_args(args(&["--bootstrap", node])).unwrap();
+        assert!(config.behaviours.kad && config.behaviours.mdns);
+        assert_eq!(config.bootstrap, vec![node.parse().unwrap()]);
+        assert!(Config::from_args(args(&["--bootstrap", "/ip4/203.0.113.7/tcp/4001"])).is_err());
+        assert!(Config::from_args(args(&["--peer", "/ip4/203.0.113.7/tcp/4001"])).is_err());
+
+        let config = Config::from_args(args(&["--relay", node])).unwrap();
+        assert_eq!(config.relay, Some(node.parse().unwrap()));
+        assert!(Config::from_args(args(&["--relay", "/ip4/203.0.113.7/tcp/4001"])).is_err());
+
+        let config = Config::from_args(args(&["--rendezvous", node])).unwrap();
+        assert_eq!(config.rendezvous, Some(node.parse().unwrap()));
+        assert!(Config::from_args(args(&["--rendezvous", "/ip4/203.0.113.7/tcp/4001"])).is_err());
+    }
+}
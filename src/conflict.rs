@@ -0,0 +1,103 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    ops::Range,
+    time::{Duration, Instant}
+};
+
+use libp2p::PeerId;
+
+/// How long after a local edit a remote edit to the same region counts as a conflict.
+pub const CONFLICT_WINDOW: Duration = Duration::from_secs(5);
+
+/// Size in characters of the regions conflicts are grouped by.
+pub const REGION_LEN: usize = 32;
+
+/// Counts remote edits landing in a region of a document that was edited
+/// locally moments before. Diffs are applied by index without being
+/// transformed, so these are the edits most likely to have landed in the
+/// wrong place.
+#[derive(Debug, Default)]
+pub struct Conflicts {
+    /// Recent local edits as document id, region and time, oldest first.
+    local: VecDeque<(String, usize, Instant)>,
+    by_peer: HashMap<Option<PeerId>, usize>,
+    by_region: BTreeMap<(String, usize), usize>,
+}
+
+impl Conflicts {
+    pub fn local_edit(&mut self, document: &str, index: usize, now: Instant) {
+        self.expire(now);
+        self.local.push_back((document.to_string(), index / REGION_LEN, now));
+    }
+
+    /// Records a conflict if `index` is in a region edited locally within
+    /// [`CONFLICT_WINDOW`], returning whether it was one.
+    pub fn remote_edit(&mut self, source: Option<PeerId>, document: &str, index: usize, now: Instant) -> bool {
+        self.expire(now);
+
+        let region = index / REGION_LEN;
+        let conflict = self.local.iter().any(|(id, local_region, _)| id == document && *local_region == region);
+
+        if conflict {
+            *self.by_peer.entry(source).or_default() += 1;
+            *self.by_region.entry((document.to_string(), region)).or_default() += 1;
+        }
+
+        conflict
+    }
+
+    /// Conflict counts by the peer whose edit conflicted.
+    pub fn by_peer(&self) -> impl Iterator<Item = (Option<&PeerId>, usize)> {
+        self.by_peer.iter().map(|(peer_id, &count)| (peer_id.as_ref(), count))
+    }
+
+    /// Conflict counts by document id and character range.
+    pub fn by_region(&self) -> impl Iterator<Item = (&str, Range<usize>, usize)> {
+        self.by_region.iter().map(|((document, region), &count)| (document.as_str(), self::region(region * REGION_LEN), count))
+    }
+
+    /// Approximate bytes held by the recent local edits and counts.
+    pub fn memory(&self) -> usize {
+        let local: usize = self.local.iter().map(|(document, _, _)| size_of::<(String, usize, Instant)>() + document.capacity()).sum();
+        let by_peer = self.by_peer.len() * size_of::<(Option<PeerId>, usize)>();
+        let by_region: usize = self.by_region.keys().map(|(document, _)| size_of::<((String, usize), usize)>() + document.capacity()).sum();
+
+        local + by_peer + by_region
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while self.local.front().is_some_and(|(_, _, at)| now.saturating_duration_since(*at) > CONFLICT_WINDOW) {
+            self.local.pop_front();
+        }
+    }
+}
+
+/// The region conflicts at `index` are grouped in.
+pub fn region(index: usize) -> Range<usize> {
+    let start = index / REGION_LEN * REGION_LEN;
+
+    start..start + REGION_LEN
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn counts_remote_edits_near_local_ones() {
+        let mut conflicts = Conflicts::default();
+        let peer_id = PeerId::random();
+        let start = Instant::now();
+
+        conflicts.local_edit("main", 10, start);
+
+        assert!(conflicts.remote_edit(Some(peer_id), "main", 20, start));
+        assert!(!conflicts.remote_edit(Some(peer_id), "main", 40, start));
+        assert!(!conflicts.remote_edit(Some(peer_id), "todo", 10, start));
+        assert!(!conflicts.remote_edit(Some(peer_id), "main", 10, start + CONFLICT_WINDOW * 2));
+
+        assert_eq!(conflicts.by_peer().collect::<Vec<_>>(), vec![(Some(&peer_id), 1)]);
+        assert_eq!(conflicts.by_region().collect::<Vec<_>>(), vec![("main", 0..REGION_LEN, 1)]);
+        assert_eq!(region(REGION_LEN + 1), REGION_LEN..2 * REGION_LEN);
+    }
+}
@@ -0,0 +1,196 @@
+use argon2::Argon2;
+use chacha20poly1305::{
+    aead::{
+        Aead, KeyInit
+    },
+    ChaCha20Poly1305, Key, Nonce
+};
+
+use crate::error::NotepadError;
+
+/// Every file written by the notepad starts with these bytes.
+const MAGIC: &[u8; 4] = b"P2PN";
+const VERSION: u8 = 1;
+
+const PLAIN: u8 = 0;
+const ENCRYPTED: u8 = 1;
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SectionKind {
+    Metadata,
+    Text,
+    /// Session state of a saved workspace, see [`Workspace`](crate::workspace::Workspace).
+    Workspace,
+    /// Preferences of the rooms joined, see [`RoomSettings`](crate::room::RoomSettings).
+    RoomSettings,
+}
+
+impl TryFrom<u8> for SectionKind {
+    type Error = NotepadError;
+
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        match byte {
+            0 => Ok(SectionKind::Metadata),
+            1 => Ok(SectionKind::Text),
+            2 => Ok(SectionKind::Workspace),
+            3 => Ok(SectionKind::RoomSettings),
+            _ => Err(NotepadError::Decode("Invalid section kind byte"))
+        }
+    }
+}
+
+/// A zstd-compressed block of a container file.
+#[derive(Debug, PartialEq)]
+pub struct Section {
+    pub kind: SectionKind,
+    pub data: Vec<u8>,
+}
+
+/// Writes `sections` as a container: magic bytes, version, an encryption
+/// flag, then each section as kind, compressed length and compressed data.
+/// With a passphrase the sections are encrypted with ChaCha20-Poly1305
+/// under an Argon2 derived key.
+pub fn encode(sections: Vec<Section>, passphrase: Option<&str>) -> Vec<u8> {
+    let mut body = Vec::new();
+
+    for Section { kind, data } in sections {
+        let data = zstd::encode_all(data.as_slice(), 0).expect("compressing into memory can't fail");
+
+        body.push(kind as u8);
+        body.extend((data.len() as u32).to_le_bytes());
+        body.extend(data);
+    }
+
+    let mut data = MAGIC.to_vec();
+    data.push(VERSION);
+
+    match passphrase {
+        None => {
+            data.push(PLAIN);
+            data.extend(body);
+        },
+        Some(passphrase) => {
+            let salt: [u8; SALT_LEN] = rand::random();
+            let nonce: [u8; NONCE_LEN] = rand::random();
+
+            let ciphertext = cipher(passphrase, &salt)
+                .encrypt(Nonce::from_slice(&nonce), body.as_slice())
+                .expect("encrypting into memory can't fail");
+
+            data.push(ENCRYPTED);
+            data.extend(salt);
+            data.extend(nonce);
+            data.extend(ciphertext);
+        }
+    }
+
+    data
+}
+
+pub fn decode(data: &[u8], passphrase: Option<&str>) -> Result<Vec<Section>, NotepadError> {
+    let data = data.strip_prefix(MAGIC).ok_or(NotepadError::Decode("Not a p2p notepad file"))?;
+
+    let body = match (data, passphrase) {
+        ([VERSION, PLAIN, body @ ..], _) => body.to_vec(),
+        ([VERSION, ENCRYPTED, data @ ..], Some(passphrase)) => {
+            if data.len() < SALT_LEN + NONCE_LEN {
+                return Err(NotepadError::Decode("Truncated file"));
+            }
+
+            let (salt, data) = data.split_at(SALT_LEN);
+            let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+
+            cipher(passphrase, salt)
+                .decrypt(Nonce::from_slice(nonce), ciphertext)
+                .map_err(|_| NotepadError::Decode("Wrong passphrase or corrupted file"))?
+        },
+        ([VERSION, ENCRYPTED, ..], None) => return Err(NotepadError::Decode("File is encrypted, a passphrase is required")),
+        ([VERSION, ..], _) => return Err(NotepadError::Decode("Invalid encryption flag byte")),
+        _ => return Err(NotepadError::Decode("Unsupported file version")),
+    };
+
+    let mut sections = Vec::new();
+    let mut body = body.as_slice();
+
+    while let Some((&kind, rest)) = body.split_first() {
+        if rest.len() < 4 {
+            return Err(NotepadError::Decode("Truncated file"));
+        }
+
+        let (len, rest) = rest.split_at(4);
+        let len = u32::from_le_bytes(len.try_into().expect("split at 4 bytes")) as usize;
+
+        if rest.len() < len {
+            return Err(NotepadError::Decode("Truncated file"));
+        }
+
+        let (data, rest) = rest.split_at(len);
+        body = rest;
+
+        sections.push(Section {
+            kind: kind.try_into()?,
+            data: zstd::decode_all(data).map_err(|_| NotepadError::Decode("Invalid section compression"))?,
+        });
+    }
+
+    Ok(sections)
+}
+
+fn cipher(passphrase: &str, salt: &[u8]) -> ChaCha20Poly1305 {
+    ChaCha20Poly1305::new(Key::from_slice(&derive_key(passphrase, salt)))
+}
+
+/// A ChaCha20-Poly1305 key derived from `passphrase` and `salt` with Argon2.
+pub fn derive_key(passphrase: &str, salt: &[u8]) -> [u8; 32] {
+    let mut key = [0; 32];
+
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .expect("salt and key lengths are valid");
+
+    key
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn def_sections() -> Vec<Section> {
+        vec![
+            Section { kind: SectionKind::Metadata, data: vec![1, 2, 3] },
+            Section { kind: SectionKind::Text, data: "hello world ".repeat(100).into_bytes() },
+        ]
+    }
+
+    #[test]
+    fn plain_round_trip() {
+        let data = encode(def_sections(), None);
+
+        assert_eq!(&data[..6], b"P2PN\x01\x00");
+        assert!(data.len() < 1200);
+        assert_eq!(decode(&data, None).unwrap(), def_sections());
+    }
+
+    #[test]
+    fn encrypted_round_trip() {
+        let data = encode(def_sections(), Some("secret"));
+
+        assert_eq!(data[5], ENCRYPTED);
+        assert!(decode(&data, None).is_err());
+        assert!(decode(&data, Some("wrong")).is_err());
+        assert_eq!(decode(&data, Some("secret")).unwrap(), def_sections());
+    }
+
+    #[test]
+    fn invalid_containers() {
+        assert!(decode(b"", None).is_err());
+        assert!(decode(b"hello world", None).is_err());
+        assert!(decode(b"P2PN\x02\x00", None).is_err());
+        assert!(decode(b"P2PN\x01\x02", None).is_err());
+        assert!(decode(b"P2PN\x01\x00\x00\x09\x00\x00\x00", None).is_err());
+        assert!(decode(b"P2PN\x01\x00\x07\x00\x00\x00\x00", None).is_err());
+    }
+}
@@ -0,0 +1,302 @@
+//! The HTTP control API, for scripts and GUIs to drive a node, served with
+//! axum. Only built with the `control` feature, otherwise `--http` fails
+//! saying so.
+
+use std::net::SocketAddr;
+#[cfg(feature = "control")]
+use std::{net::IpAddr, sync::Arc, time::Duration};
+
+#[cfg(feature = "control")]
+use axum::{
+    extract::{rejection::StringRejection, DefaultBodyLimit, State},
+    http::{header, HeaderMap, Method, StatusCode, Uri},
+    middleware::{self, Next},
+    response::IntoResponse,
+    routing::{get, post},
+    Router
+};
+#[cfg(feature = "control")]
+use tokio::{net::TcpListener, time};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{
+    error::NotepadError,
+    output::string
+};
+
+/// Longest request body taken, enough for a large batch of edits.
+#[cfg(feature = "control")]
+const MAX_BODY: usize = 1024 * 1024;
+/// Longest a request is given to be sent and answered, so stalled
+/// connections don't pile up.
+#[cfg(feature = "control")]
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What a script or GUI asks of the node through the control API.
+#[derive(Debug, PartialEq)]
+pub enum Request {
+    /// `GET /document`: the active document of the current room.
+    Document,
+    /// `POST /edits`: diffs to the active document, one per line in the
+    /// syntax they are entered in on stdin, such as `ins:3:a`.
+    Edits(String),
+    /// `POST /room`: joins the room named by the body.
+    Room(String),
+    /// `GET /peers`: the peers in the current room.
+    Peers,
+}
+
+/// A JSON answer with its HTTP status.
+#[derive(Debug, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+impl Response {
+    pub fn ok(body: String) -> Self {
+        Self { status: 200, body }
+    }
+
+    /// An error as `{"error": "..."}`.
+    pub fn error(status: u16, message: impl std::fmt::Display) -> Self {
+        Self { status, body: format!("{{\"error\":{}}}", string(&message.to_string())) }
+    }
+}
+
+#[cfg(feature = "control")]
+impl IntoResponse for Response {
+    fn into_response(self) -> axum::response::Response {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        (status, [(header::CONTENT_TYPE, "application/json")], self.body).into_response()
+    }
+}
+
+/// A request waiting for the main loop, which holds the engine, to answer it.
+#[derive(Debug)]
+pub struct Call {
+    pub request: Request,
+    pub reply: oneshot::Sender<Response>,
+}
+
+/// What every handler of the control API shares.
+#[cfg(feature = "control")]
+#[derive(Clone)]
+struct Control {
+    token: Option<Arc<str>>,
+    calls: mpsc::Sender<Call>,
+}
+
+/// Serves the control API on `address` until the node exits, passing every
+/// request on to `calls`. With a `token`, requests without it are refused,
+/// [`Config`](crate::config::Config) only leaves it out on loopback addresses.
+/// Requests sent by web pages on other sites are always refused, and without
+/// a token so are those whose `Host` isn't this machine.
+#[cfg(feature = "control")]
+pub async fn serve(address: SocketAddr, token: Option<String>, calls: mpsc::Sender<Call>) -> Result<(), NotepadError> {
+    let listener = TcpListener::bind(address).await?;
+    println!("Serving the control API on http://{address}");
+
+    axum::serve(listener, router(token, calls)).await?;
+    Ok(())
+}
+
+#[cfg(not(feature = "control"))]
+pub async fn serve(_address: SocketAddr, _token: Option<String>, _calls: mpsc::Sender<Call>) -> Result<(), NotepadError> {
+    Err(NotepadError::command("Built without the control API, rebuild with `--features control`"))
+}
+
+/// The endpoints of the control API behind [`guard`].
+#[cfg(feature = "control")]
+fn router(token: Option<String>, calls: mpsc::Sender<Call>) -> Router {
+    let control = Control { token: token.map(Arc::from), calls };
+
+    Router::new()
+        .route("/document", get(|State(control): State<Control>| async move {
+            ask(&control, Request::Document).await
+        }))
+        .route("/edits", post(|State(control): State<Control>, body: Result<String, StringRejection>| async move {
+            match body {
+                Ok(body) => ask(&control, Request::Edits(body)).await,
+                Err(rejection) => rejected(rejection),
+            }
+        }))
+        .route("/room", post(|State(control): State<Control>, body: Result<String, StringRejection>| async move {
+            match body {
+                Ok(body) if !body.trim().is_empty() => ask(&control, Request::Room(body.trim().to_string())).await,
+                Ok(_) => Response::error(400, "Expected the room name as the body"),
+                Err(rejection) => rejected(rejection),
+            }
+        }))
+        .route("/peers", get(|State(control): State<Control>| async move {
+            ask(&control, Request::Peers).await
+        }))
+        .fallback(|method: Method, uri: Uri| async move {
+            Response::error(404, format!("No endpoint {method} {}", uri.path()))
+        })
+        .layer(DefaultBodyLimit::max(MAX_BODY))
+        .layer(middleware::from_fn_with_state(control.clone(), guard))
+        .with_state(control)
+}
+
+/// Refuses requests a web page could have made, then those without the token.
+///
+/// Browsers send an `Origin` with cross-site requests, so any that isn't this
+/// machine is refused. Without a token the node only listens on loopback, but
+/// a page can still reach it by rebinding its own name to `127.0.0.1`, so the
+/// `Host` must name this machine too.
+#[cfg(feature = "control")]
+async fn guard(State(control): State<Control>, request: axum::extract::Request, next: Next) -> axum::response::Response {
+    if let Err(response) = check(&control, request.headers()) {
+        return response.into_response();
+    }
+
+    time::timeout(REQUEST_TIMEOUT, next.run(request)).await
+        .unwrap_or_else(|_| Response::error(408, "Timed out on the request").into_response())
+}
+
+/// The error to refuse a request with `headers` with, if it is refused.
+#[cfg(feature = "control")]
+fn check(control: &Control, headers: &HeaderMap) -> Result<(), Response> {
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+
+    if !header(header::ORIGIN).is_none_or(local_origin) {
+        return Err(Response::error(403, "Requests from other sites are refused"));
+    }
+    if control.token.is_none() && !header(header::HOST).is_some_and(local_host) {
+        return Err(Response::error(403, "The Host header must name this machine"));
+    }
+    if !authorized(control.token.as_deref(), header(header::AUTHORIZATION)) {
+        return Err(Response::error(401, "Missing or wrong control API token"));
+    }
+    Ok(())
+}
+
+/// Passes `request` on to the main loop and waits for its answer.
+#[cfg(feature = "control")]
+async fn ask(control: &Control, request: Request) -> Response {
+    let (reply, answer) = oneshot::channel();
+    match control.calls.send(Call { request, reply }).await {
+        Ok(()) => answer.await.unwrap_or_else(|_| Response::error(503, "The node is shutting down")),
+        Err(_) => Response::error(503, "The node is shutting down"),
+    }
+}
+
+/// A body that couldn't be read, such as one too long or not UTF-8, as JSON.
+#[cfg(feature = "control")]
+fn rejected(rejection: StringRejection) -> Response {
+    Response::error(rejection.status().as_u16(), rejection.body_text())
+}
+
+/// Whether the `Authorization` header carries `token`, when one is needed.
+/// Hashes are compared, as they are in constant time.
+#[cfg(feature = "control")]
+fn authorized(token: Option<&str>, authorization: Option<&str>) -> bool {
+    let Some(token) = token else {
+        return true;
+    };
+
+    authorization
+        .and_then(|authorization| authorization.strip_prefix("Bearer "))
+        .is_some_and(|given| blake3::hash(given.trim().as_bytes()) == blake3::hash(token.as_bytes()))
+}
+
+/// Whether a `Host`, with or without its port, names this machine.
+#[cfg(feature = "control")]
+fn local_host(host: &str) -> bool {
+    let name = match host.strip_prefix('[') {
+        Some(rest) => rest.split(']').next().unwrap_or_default(),
+        None => host.split(':').next().unwrap_or_default(),
+    };
+    name.eq_ignore_ascii_case("localhost") || name.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+/// Whether an `Origin` is a page served from this machine.
+#[cfg(feature = "control")]
+fn local_origin(origin: &str) -> bool {
+    origin.strip_prefix("http://")
+        .or_else(|| origin.strip_prefix("https://"))
+        .is_some_and(local_host)
+}
+
+#[cfg(all(test, feature = "control"))]
+mod test {
+    use axum::{body::Body, http};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    /// Sends a request through the router, answering calls with the request
+    /// they carry, and returns the status and body.
+    async fn send(token: Option<&str>, request: http::request::Builder, body: &str) -> (u16, String) {
+        let (calls, mut call) = mpsc::channel::<Call>(1);
+        tokio::spawn(async move {
+            while let Some(call) = call.recv().await {
+                let _ = call.reply.send(Response::ok(format!("{:?}", call.request)));
+            }
+        });
+
+        let response = router(token.map(str::to_string), calls)
+            .oneshot(request.body(Body::from(body.to_string())).unwrap())
+            .await
+            .unwrap();
+        let status = response.status().as_u16();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    fn local(method: &str, path: &str) -> http::request::Builder {
+        http::Request::builder().method(method).uri(path).header("host", "127.0.0.1:7070")
+    }
+
+    #[tokio::test]
+    async fn requests_are_routed() {
+        assert_eq!(send(None, local("GET", "/document"), "").await, (200, "Document".to_string()));
+        assert_eq!(send(None, local("POST", "/edits"), "ins:0:a").await, (200, "Edits(\"ins:0:a\")".to_string()));
+        assert_eq!(send(None, local("POST", "/room"), " notes\n").await, (200, "Room(\"notes\")".to_string()));
+        assert_eq!(send(None, local("GET", "/peers"), "").await, (200, "Peers".to_string()));
+        assert_eq!(send(None, local("POST", "/room"), "").await.0, 400);
+        assert_eq!(send(None, local("GET", "/nothing"), "").await.0, 404);
+        assert_eq!(send(None, local("POST", "/edits"), &"a".repeat(MAX_BODY + 1)).await.0, 413);
+    }
+
+    #[tokio::test]
+    async fn foreign_requests_are_refused() {
+        let rebound = http::Request::builder().method("POST").uri("/edits").header("host", "evil.example:7070");
+        assert_eq!(send(None, rebound, "ins:0:a").await.0, 403);
+
+        let cross_site = local("POST", "/room").header("origin", "https://evil.example");
+        assert_eq!(send(None, cross_site, "notes").await.0, 403);
+        let sandboxed = local("POST", "/room").header("origin", "null");
+        assert_eq!(send(None, sandboxed, "notes").await.0, 403);
+
+        let same_machine = local("GET", "/document").header("origin", "http://localhost:3000");
+        assert_eq!(send(None, same_machine, "").await.0, 200);
+
+        let remote = http::Request::builder().method("GET").uri("/peers").header("host", "192.0.2.1:7070");
+        assert_eq!(send(Some("secret"), remote, "").await.0, 401);
+        let remote = http::Request::builder().method("GET").uri("/peers").header("host", "192.0.2.1:7070")
+            .header("authorization", "Bearer secret");
+        assert_eq!(send(Some("secret"), remote, "").await.0, 200);
+    }
+
+    #[test]
+    fn hosts_are_checked() {
+        assert!(local_host("localhost"));
+        assert!(local_host("127.0.0.1:7070"));
+        assert!(local_host("[::1]:7070"));
+        assert!(!local_host("localhost.evil.example"));
+        assert!(!local_host("192.0.2.1:7070"));
+        assert!(local_origin("http://[::1]:3000"));
+        assert!(!local_origin("localhost"));
+    }
+
+    #[test]
+    fn tokens_are_checked() {
+        assert!(authorized(None, None));
+        assert!(authorized(Some("secret"), Some("Bearer secret")));
+        assert!(!authorized(Some("secret"), Some("Bearer other")));
+        assert!(!authorized(Some("secret"), Some("secret")));
+        assert!(!authorized(Some("secret"), None));
+    }
+}
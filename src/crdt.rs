@@ -0,0 +1,543 @@
+//! A sequence CRDT the engine publishes edits as when
+//! [`Engine::crdt`](crate::engine::Engine::crdt) is on, so copies edited
+//! concurrently converge whatever order the edits arrive in. The notepad
+//! still holds the text, edits are translated between its byte indices and
+//! the ids here on the way out and in.
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::{
+    diff::{Diff, MessageBuf, Operation},
+    error::NotepadError,
+    varint
+};
+
+/// Identifies an inserted character for good, however the text around it
+/// changes. Ordered by Lamport counter, ties broken by replica.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Id {
+    pub counter: u64,
+    pub replica: u64,
+}
+
+/// An edit addressed by [`Id`] rather than by index, so it means the same
+/// thing on every replica whatever was applied before it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Op {
+    /// Inserts `value` right after the character `after`, or at the start.
+    Insert {
+        id: Id,
+        after: Option<Id>,
+        value: char,
+    },
+    Delete(Id),
+}
+
+/// Most ops held per replica while waiting for the character they refer
+/// to. Every character typed is an op, so this is a paste's worth; past it
+/// the oldest are given up on, they likely refer to one that never existed.
+pub const MAX_PENDING: usize = 4096;
+
+#[derive(Debug, Clone, PartialEq)]
+struct Element {
+    id: Id,
+    /// The character it was inserted after, to insert it again on merging.
+    after: Option<Id>,
+    value: char,
+    /// What the character is in the text here, `None` if it was filtered
+    /// out. Peers are still sent `value`.
+    shown: Option<char>,
+    /// Deleted characters are kept as tombstones, later inserts may still refer to them.
+    deleted: bool,
+}
+
+/// A replicated growable array (RGA). Replicas that have applied the same
+/// ops hold the same text, in whatever order the ops arrived.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sequence {
+    replica: u64,
+    /// Replica the characters of the text it started from are numbered as,
+    /// a hash of the text, so copies started from the same text agree.
+    origin: u64,
+    /// Highest counter seen, new local ops are numbered after it.
+    clock: u64,
+    elements: Vec<Element>,
+    /// Ops that refer to a character that hasn't arrived yet, by the
+    /// replica they came from.
+    pending: HashMap<u64, VecDeque<Op>>,
+}
+
+impl Sequence {
+    /// An empty sequence edited as `replica`, which must differ between replicas.
+    pub fn new(replica: u64) -> Self {
+        Self::from_text(replica, "")
+    }
+
+    /// A sequence holding `text`, its characters numbered as [`Sequence::origin`].
+    pub fn from_text(replica: u64, text: &str) -> Self {
+        let origin = u64::from_le_bytes(blake3::hash(text.as_bytes()).as_bytes()[..8].try_into().expect("hash is 32 bytes"));
+        let mut elements = Vec::new();
+        let mut after = None;
+
+        for (counter, value) in (1..).zip(text.chars()) {
+            let id = Id { counter, replica: origin };
+            elements.push(Element { id, after, value, shown: Some(value), deleted: false });
+            after = Some(id);
+        }
+
+        Self { replica, origin, clock: elements.len() as u64, elements, pending: HashMap::new() }
+    }
+
+    /// The same sequence, edited as `replica` from now on.
+    pub fn with_replica(self, replica: u64) -> Self {
+        Self { replica, ..self }
+    }
+
+    pub fn text(&self) -> String {
+        self.visible().filter_map(|element| element.shown).collect()
+    }
+
+    /// Whether the sequence holds the text of `chars`, without collecting its own.
+    pub fn matches(&self, chars: impl IntoIterator<Item = char>) -> bool {
+        self.visible().filter_map(|element| element.shown).eq(chars)
+    }
+
+    /// Translates diffs just applied to the text into ops, applying them.
+    pub fn local(&mut self, diffs: &MessageBuf) -> Vec<Op> {
+        let mut ops = Vec::new();
+
+        for diff in &diffs.messages {
+            let Some(index) = self.char_index(diff.index) else {
+                continue;
+            };
+
+            match &diff.opcode {
+                Operation::Ins => ops.extend(diff.operand.and_then(|value| self.insert(index, value))),
+                Operation::Del => ops.extend(self.delete(index)),
+                Operation::Rep => {
+                    ops.extend(self.delete(index));
+                    ops.extend(diff.operand.and_then(|value| self.insert(index, value)));
+                },
+                Operation::InsStr(text) => {
+                    for (i, value) in text.chars().enumerate() {
+                        ops.extend(self.insert(index + i, value));
+                    }
+                },
+                Operation::DelRange(len) => {
+                    let mut deleted = 0;
+                    while deleted < *len {
+                        let Some(value) = self.visible().nth(index).and_then(|element| element.shown) else {
+                            break;
+                        };
+                        ops.extend(self.delete(index));
+                        deleted += value.len_utf8();
+                    }
+                },
+            }
+        }
+
+        ops
+    }
+
+    /// Inserts `value` before the visible character at `index`, counted in characters.
+    pub fn insert(&mut self, index: usize, value: char) -> Option<Op> {
+        let after = match index {
+            0 => None,
+            index => Some(self.visible().nth(index - 1)?.id),
+        };
+
+        self.clock += 1;
+        let op = Op::Insert { id: Id { counter: self.clock, replica: self.replica }, after, value };
+        self.integrate(&op, Some);
+
+        Some(op)
+    }
+
+    /// Deletes the visible character at `index`, counted in characters.
+    pub fn delete(&mut self, index: usize) -> Option<Op> {
+        let op = Op::Delete(self.visible().nth(index)?.id);
+        self.integrate(&op, Some);
+
+        Some(op)
+    }
+
+    /// Applies a remote op from replica `from`, returning the diffs it and
+    /// the ops waiting for it made to the text. Ops already applied are
+    /// ignored and ops that arrive before what they refer to wait until it
+    /// does. Characters inserted are shown as `filter` turns them, those it
+    /// drops are kept hidden so ops referring to them still apply.
+    pub fn apply(&mut self, from: u64, op: Op, filter: impl Fn(char) -> Option<char> + Copy) -> Vec<Diff> {
+        let mut diffs = Vec::new();
+
+        if !self.ready(&op) {
+            let pending = self.pending.entry(from).or_default();
+            if pending.len() == MAX_PENDING {
+                pending.pop_front();
+            }
+            pending.push_back(op);
+            return diffs;
+        }
+
+        diffs.extend(self.integrate(&op, filter));
+        while let Some(op) = self.take_ready() {
+            diffs.extend(self.integrate(&op, filter));
+        }
+
+        diffs
+    }
+
+    /// Takes an op waiting for a character that arrived since.
+    fn take_ready(&mut self) -> Option<Op> {
+        let (&from, i) = self.pending.iter().find_map(|(from, pending)| Some((from, pending.iter().position(|op| self.ready(op))?)))?;
+        let pending = self.pending.get_mut(&from).expect("replica has ops pending");
+        let op = pending.remove(i);
+        if pending.is_empty() {
+            self.pending.remove(&from);
+        }
+
+        op
+    }
+
+    /// Applies every op that led to `other` but the characters of the text
+    /// it started from, returning the diffs they made to the text.
+    pub fn merge(&mut self, other: &Sequence) -> Vec<Diff> {
+        other.missing(self).into_iter().flat_map(|op| self.apply(other.replica, op, Some)).collect()
+    }
+
+    /// Shows every character as `filter` turns it, for a sequence taken
+    /// from a peer whose characters weren't filtered here yet.
+    pub fn filter(&mut self, filter: impl Fn(char) -> Option<char>) {
+        for element in &mut self.elements {
+            element.shown = filter(element.value);
+        }
+    }
+
+    /// Ops that led to this sequence but not to `other`, leaving out the
+    /// characters of the text it started from, in an order they apply in.
+    pub fn missing(&self, other: &Sequence) -> Vec<Op> {
+        // Characters always come after the one they were inserted after.
+        let inserts = self.elements.iter()
+            .filter(|element| element.id.replica != self.origin && other.position(&element.id).is_none())
+            .map(|element| Op::Insert { id: element.id, after: element.after, value: element.value });
+        let deletes = self.elements.iter()
+            .filter(|element| element.deleted && other.position(&element.id).is_none_or(|position| !other.elements[position].deleted))
+            .map(|element| Op::Delete(element.id));
+
+        inserts.chain(deletes).chain(self.pending.values().flatten().cloned()).collect()
+    }
+
+    /// Ops still waiting for the character they refer to.
+    pub fn pending(&self) -> usize {
+        self.pending.values().map(VecDeque::len).sum()
+    }
+
+    fn ready(&self, op: &Op) -> bool {
+        match op {
+            Op::Insert { after, .. } => after.is_none_or(|after| self.position(&after).is_some()),
+            Op::Delete(id) => self.position(id).is_some(),
+        }
+    }
+
+    /// Applies an op that is ready, returning the diff it made to the text.
+    fn integrate(&mut self, op: &Op, filter: impl Fn(char) -> Option<char>) -> Option<Diff> {
+        match *op {
+            Op::Insert { id, .. } if self.position(&id).is_some() => None,
+            Op::Insert { id, after, value } => {
+                let mut i = after.and_then(|after| self.position(&after)).map_or(0, |position| position + 1);
+
+                // Concurrent inserts after the same character, and everything
+                // inserted after them, go first when they have a higher id.
+                while self.elements.get(i).is_some_and(|element| element.id > id) {
+                    i += 1;
+                }
+
+                self.clock = self.clock.max(id.counter);
+                let shown = filter(value);
+                self.elements.insert(i, Element { id, after, value, shown, deleted: false });
+
+                shown.map(|shown| Diff { opcode: Operation::Ins, operand: Some(shown), index: self.byte_index(i) })
+            },
+            Op::Delete(id) => {
+                let position = self.position(&id).expect("op is ready");
+                if self.elements[position].deleted {
+                    return None;
+                }
+
+                self.elements[position].deleted = true;
+                self.elements[position].shown.map(|_| Diff { opcode: Operation::Del, operand: None, index: self.byte_index(position) })
+            },
+        }
+    }
+
+    fn position(&self, id: &Id) -> Option<usize> {
+        self.elements.iter().position(|element| element.id == *id)
+    }
+
+    /// Byte index in the text of the element at `position`.
+    fn byte_index(&self, position: usize) -> usize {
+        self.elements[..position].iter().filter(|element| !element.deleted).filter_map(|element| element.shown).map(char::len_utf8).sum()
+    }
+
+    /// Index in characters of the byte index `index` of the text, if it is
+    /// the start of a character or the end.
+    fn char_index(&self, index: usize) -> Option<usize> {
+        let mut bytes = 0;
+
+        for (i, element) in self.visible().enumerate() {
+            if bytes >= index {
+                return (bytes == index).then_some(i);
+            }
+            bytes += element.shown.map_or(0, char::len_utf8);
+        }
+
+        (bytes == index).then(|| self.visible().count())
+    }
+
+    /// Approximate bytes held, tombstones included.
+    pub fn memory(&self) -> usize {
+        (self.elements.capacity() * size_of::<Element>()) + self.pending.values().map(|pending| pending.capacity() * size_of::<Op>()).sum::<usize>()
+    }
+
+    /// Every character with its id and what it was inserted after,
+    /// tombstones included, for a replica to merge.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        push_id(&mut data, Id { counter: self.clock, replica: self.origin });
+
+        for element in &self.elements {
+            push_id(&mut data, element.id);
+            data.push(u8::from(element.after.is_some()) | u8::from(element.deleted) << 1);
+            if let Some(after) = element.after {
+                push_id(&mut data, after);
+            }
+            varint::push(&mut data, element.value as usize);
+        }
+
+        data
+    }
+
+    /// A sequence written by [`Sequence::encode`], edited as `replica`.
+    pub fn decode(replica: u64, data: &[u8]) -> Result<Self, NotepadError> {
+        let (Id { counter: clock, replica: origin }, mut data) = split_id(data)?;
+        let mut elements = Vec::new();
+
+        while !data.is_empty() {
+            let (id, rest) = split_id(data)?;
+            let (&flags, mut rest) = rest.split_first().ok_or(NotepadError::Decode("Truncated sequence"))?;
+            let after = match flags & 1 {
+                0 => None,
+                _ => {
+                    let (after, after_rest) = split_id(rest)?;
+                    rest = after_rest;
+                    Some(after)
+                },
+            };
+            let (value, rest) = varint::split(rest)?;
+            let value = u32::try_from(value).ok().and_then(char::from_u32).ok_or(NotepadError::Decode("Invalid sequence character"))?;
+
+            elements.push(Element { id, after, value, shown: Some(value), deleted: flags & 2 != 0 });
+            data = rest;
+        }
+
+        Ok(Self { replica, origin, clock, elements, pending: HashMap::new() })
+    }
+
+    /// Characters in the text, neither deleted nor filtered out.
+    fn visible(&self) -> impl Iterator<Item = &Element> {
+        self.elements.iter().filter(|element| !element.deleted && element.shown.is_some())
+    }
+}
+
+impl Op {
+    pub fn encode(&self, data: &mut Vec<u8>) {
+        match *self {
+            Op::Insert { id, after, value } => {
+                data.push(u8::from(after.is_some()));
+                push_id(data, id);
+                if let Some(after) = after {
+                    push_id(data, after);
+                }
+                varint::push(data, value as usize);
+            },
+            Op::Delete(id) => {
+                data.push(DELETE);
+                push_id(data, id);
+            },
+        }
+    }
+
+    /// Splits an op written by [`Op::encode`] off the front of `data`.
+    pub fn decode(data: &[u8]) -> Result<(Self, &[u8]), NotepadError> {
+        let (&tag, data) = data.split_first().ok_or(NotepadError::Decode("Truncated op"))?;
+        let (id, data) = split_id(data)?;
+
+        match tag {
+            DELETE => Ok((Op::Delete(id), data)),
+            0 | 1 => {
+                let (after, data) = match tag {
+                    1 => split_id(data).map(|(after, data)| (Some(after), data))?,
+                    _ => (None, data),
+                };
+                let (value, data) = varint::split(data)?;
+                let value = u32::try_from(value).ok().and_then(char::from_u32).ok_or(NotepadError::Decode("Invalid op character"))?;
+
+                Ok((Op::Insert { id, after, value }, data))
+            },
+            _ => Err(NotepadError::Decode("Invalid op")),
+        }
+    }
+}
+
+/// Tag of a delete, after those of inserts without and with a character before.
+const DELETE: u8 = 2;
+
+/// An id as its counter, then its replica in 8 bytes as replicas are hashes.
+fn push_id(data: &mut Vec<u8>, id: Id) {
+    varint::push(data, id.counter as usize);
+    data.extend(id.replica.to_le_bytes());
+}
+
+fn split_id(data: &[u8]) -> Result<(Id, &[u8]), NotepadError> {
+    let (counter, data) = varint::split(data)?;
+    let (replica, data) = data.split_first_chunk::<8>().ok_or(NotepadError::Decode("Truncated id"))?;
+
+    Ok((Id { counter: counter as u64, replica: u64::from_le_bytes(*replica) }, data))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::notepad::Notepad;
+
+    fn typed(sequence: &mut Sequence, index: usize, text: &str) -> Vec<Op> {
+        text.chars().enumerate().map(|(i, c)| sequence.insert(index + i, c).unwrap()).collect()
+    }
+
+    #[test]
+    fn local_edits() {
+        let mut a = Sequence::new(1);
+
+        typed(&mut a, 0, "hello");
+        a.delete(0).unwrap();
+        a.insert(0, 'j').unwrap();
+
+        assert_eq!(a.text(), "jello");
+        assert!(a.insert(9, 'x').is_none());
+        assert!(a.delete(5).is_none());
+    }
+
+    #[test]
+    fn concurrent_edits_converge() {
+        let (mut a, mut b) = (Sequence::new(1), Sequence::new(2));
+        for op in typed(&mut a, 0, "ac") {
+            b.apply(0, op, Some);
+        }
+
+        let a_ops = typed(&mut a, 1, "b");
+        let mut b_ops = typed(&mut b, 1, "x");
+        b_ops.extend(b.delete(0));
+
+        for op in b_ops {
+            a.apply(0, op, Some);
+        }
+        for op in a_ops {
+            b.apply(0, op, Some);
+        }
+
+        assert_eq!(a.text(), b.text());
+        assert!(a.text() == "bxc" || a.text() == "xbc");
+    }
+
+    #[test]
+    fn out_of_order_ops_wait() {
+        let mut a = Sequence::new(1);
+        let mut ops = typed(&mut a, 0, "hi");
+        ops.extend(a.delete(0));
+
+        let mut b = Sequence::new(2);
+        for op in ops.iter().rev().cloned() {
+            b.apply(0, op, Some);
+        }
+        assert_eq!(b.text(), "i");
+        assert_eq!(b.pending(), 0);
+
+        // Applying the ops again changes nothing.
+        for op in ops {
+            b.apply(0, op, Some);
+        }
+        assert_eq!(b.text(), "i");
+    }
+
+    #[test]
+    fn diffs_translate_into_ops_and_back() {
+        let (mut a, mut b) = (Sequence::from_text(1, "hello world"), Sequence::from_text(2, "hello world"));
+        let diffs = MessageBuf { messages: vec![
+            Diff { opcode: Operation::InsStr("é!".to_string()), operand: None, index: 5 },
+            Diff { opcode: Operation::DelRange(5), operand: None, index: 0 },
+            Diff { opcode: Operation::Rep, operand: Some('e'), index: 0 },
+        ] };
+
+        let ops = a.local(&diffs);
+        assert_eq!(a.text(), "e! world");
+
+        let mut notepad = Notepad::new("hello world".to_string());
+        for op in ops {
+            notepad.apply_message_buf(&MessageBuf { messages: b.apply(0, op, Some) }).unwrap();
+        }
+        assert_eq!(notepad.text(), "e! world");
+        assert!(b.matches(notepad.chars()));
+    }
+
+    #[test]
+    fn sequences_merge() {
+        let (mut a, mut b) = (Sequence::from_text(1, "ab"), Sequence::from_text(2, "ab"));
+        typed(&mut a, 1, "x");
+        typed(&mut b, 2, "y");
+        b.delete(0).unwrap();
+
+        let a = Sequence::decode(3, &a.encode()).unwrap();
+        assert_eq!(a.text(), "axb");
+
+        let mut notepad = Notepad::new(b.text());
+        notepad.apply_message_buf(&MessageBuf { messages: b.merge(&a) }).unwrap();
+        assert_eq!((b.text().as_str(), notepad.text()), ("xby", "xby"));
+        assert!(a.missing(&b).is_empty());
+        assert_eq!(b.missing(&a).len(), 2);
+
+        // Copies started from other text only take the edits made since.
+        let mut c = Sequence::from_text(4, "cd");
+        c.merge(&a);
+        assert_eq!(c.text(), "cd");
+        assert_eq!(c.pending(), 1);
+    }
+
+    #[test]
+    fn filtered_characters_stay_hidden() {
+        let mut a = Sequence::new(1);
+        let ops = typed(&mut a, 0, "a\rb");
+        let mut b = Sequence::new(2);
+
+        let diffs: Vec<_> = ops.into_iter().flat_map(|op| b.apply(0, op, |c| (c != '\r').then_some(c))).collect();
+        assert_eq!(diffs.len(), 2);
+        assert_eq!(b.text(), "ab");
+
+        // Ops referring to the hidden character still apply, and peers are sent it.
+        for op in typed(&mut a, 2, "c").into_iter().chain(a.delete(1)) {
+            b.apply(0, op, Some);
+        }
+        assert_eq!((a.text().as_str(), b.text().as_str()), ("acb", "acb"));
+        assert!(b.missing(&a).is_empty());
+    }
+
+    #[test]
+    fn pending_ops_are_bounded() {
+        let mut a = Sequence::new(1);
+        let unknown = Id { counter: 1, replica: 9 };
+
+        for counter in 0..MAX_PENDING as u64 + 10 {
+            a.apply(7, Op::Insert { id: Id { counter, replica: 7 }, after: Some(unknown), value: 'x' }, Some);
+        }
+        a.apply(8, Op::Delete(unknown), Some);
+        assert_eq!(a.pending(), MAX_PENDING + 1);
+    }
+}
@@ -0,0 +1,90 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant}
+};
+
+use libp2p::PeerId;
+
+/// Least time between publishing a moved cursor, so holding an arrow key
+/// down doesn't flood the room.
+pub const CURSOR_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a peer's cursor is shown after it was last published. Cursors
+/// are published at least every [`crate::presence::PRESENCE_INTERVAL`], so
+/// this is a few missed ones.
+pub const CURSOR_TTL: Duration = Duration::from_secs(30);
+
+/// Topic the cursors of the room with `topic` are published on, apart from
+/// its edits so that peers not showing them can leave it be.
+pub fn topic(topic: &str) -> String {
+    format!("{topic}/cursors")
+}
+
+#[derive(Debug)]
+struct Cursor {
+    document: String,
+    index: usize,
+    seen: Instant,
+}
+
+/// Where the other peers' cursors are, as they last published them.
+#[derive(Debug, Default)]
+pub struct Cursors {
+    cursors: HashMap<PeerId, Cursor>,
+}
+
+impl Cursors {
+    pub fn update(&mut self, peer_id: PeerId, document: String, index: usize, now: Instant) {
+        self.cursors.insert(peer_id, Cursor { document, index, seen: now });
+    }
+
+    pub fn remove(&mut self, peer_id: &PeerId) {
+        self.cursors.remove(peer_id);
+    }
+
+    /// Forgets cursors not published again within [`CURSOR_TTL`].
+    pub fn expire(&mut self, now: Instant) {
+        self.cursors.retain(|_, cursor| now.saturating_duration_since(cursor.seen) <= CURSOR_TTL);
+    }
+
+    /// The peers with a cursor in `document` and where, in order through the document.
+    pub fn in_document(&self, document: &str) -> Vec<(PeerId, usize)> {
+        let mut cursors: Vec<_> = self.cursors
+            .iter()
+            .filter(|(_, cursor)| cursor.document == document)
+            .map(|(&peer_id, cursor)| (peer_id, cursor.index))
+            .collect();
+        cursors.sort_by_key(|&(peer_id, index)| (index, peer_id));
+
+        cursors
+    }
+
+    pub fn clear(&mut self) {
+        self.cursors.clear();
+    }
+
+    pub fn memory(&self) -> usize {
+        self.cursors.values().map(|cursor| size_of::<(PeerId, Cursor)>() + cursor.document.capacity()).sum()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn stale_cursors_expire() {
+        let (a, b) = (PeerId::random(), PeerId::random());
+        let now = Instant::now();
+        let mut cursors = Cursors::default();
+
+        cursors.update(a, "main".to_string(), 7, now);
+        cursors.update(b, "main".to_string(), 2, now + CURSOR_TTL);
+        cursors.update(b, "main".to_string(), 3, now + CURSOR_TTL);
+        assert_eq!(cursors.in_document("main"), vec![(b, 3), (a, 7)]);
+        assert!(cursors.in_document("notes").is_empty());
+
+        cursors.expire(now + CURSOR_TTL + Duration::from_secs(1));
+        assert_eq!(cursors.in_document("main"), vec![(b, 3)]);
+    }
+}
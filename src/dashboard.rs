@@ -0,0 +1,261 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    time::{Duration, Instant}
+};
+
+use libp2p::PeerId;
+use ratatui::{
+    crossterm::event::{Event, KeyCode, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout},
+    style::{Modifier, Style},
+    widgets::{Block, Paragraph, Row, Table, TableState},
+    Frame
+};
+
+/// How a room is doing, as far as this peer can tell.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Health {
+    /// The current room, synced with its peers.
+    Synced,
+    /// The current room, waiting for a peer's documents.
+    Syncing,
+    /// The current room, with messages waiting to be published.
+    Pending(usize),
+    /// The current room, cut off from every peer, see [`crate::partition::Partition`].
+    Partitioned,
+    /// The current room, without any other peer.
+    Alone,
+    /// Followed for notifications, see [`crate::watch::Watch`].
+    Watching,
+    /// Left, its documents kept until it is joined again.
+    Parked,
+}
+
+impl fmt::Display for Health {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Health::Synced => write!(f, "synced"),
+            Health::Syncing => write!(f, "syncing"),
+            Health::Pending(count) => write!(f, "{count} pending"),
+            Health::Partitioned => write!(f, "partitioned"),
+            Health::Alone => write!(f, "no peers"),
+            Health::Watching => write!(f, "watching"),
+            Health::Parked => write!(f, "left"),
+        }
+    }
+}
+
+/// A line of the dashboard, see [`crate::engine::Engine::rooms`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary {
+    pub room: String,
+    pub topic: String,
+    pub current: bool,
+    /// Edits that arrived since the room was last the current one.
+    pub unread: usize,
+    /// Peers heard from within the peer timeout.
+    pub peers: usize,
+    pub last_edit: Option<Instant>,
+    pub health: Health,
+}
+
+#[derive(Debug, Default)]
+struct Room {
+    name: String,
+    unread: usize,
+    last_edit: Option<Instant>,
+    /// When each peer was last heard from, for rooms whose peers aren't tracked by presence.
+    seen: HashMap<PeerId, Instant>,
+}
+
+/// What is known of every room joined or watched, by topic, for the dashboard.
+#[derive(Debug, Default)]
+pub struct Rooms {
+    rooms: HashMap<String, Room>,
+}
+
+impl Rooms {
+    /// Records the name of the room with `topic`, which private rooms can't be told by.
+    pub fn name(&mut self, topic: &str, room: &str) {
+        self.rooms.entry(topic.to_string()).or_default().name = room.to_string();
+    }
+
+    pub fn name_of(&self, topic: &str) -> Option<&str> {
+        self.rooms.get(topic).map(|room| room.name.as_str())
+    }
+
+    /// Records an edit to a room, counted as unread unless it is being read.
+    pub fn edit(&mut self, topic: &str, unread: bool, now: Instant) {
+        let room = self.rooms.entry(topic.to_string()).or_default();
+
+        room.last_edit = Some(now);
+        room.unread += usize::from(unread);
+    }
+
+    /// Records hearing from a peer in a room.
+    pub fn seen(&mut self, topic: &str, peer_id: PeerId, now: Instant) {
+        self.rooms.entry(topic.to_string()).or_default().seen.insert(peer_id, now);
+    }
+
+    /// Marks every edit to a room read, as it becomes the current one.
+    pub fn read(&mut self, topic: &str) {
+        if let Some(room) = self.rooms.get_mut(topic) {
+            room.unread = 0;
+        }
+    }
+
+    pub fn unread(&self, topic: &str) -> usize {
+        self.rooms.get(topic).map_or(0, |room| room.unread)
+    }
+
+    pub fn last_edit(&self, topic: &str) -> Option<Instant> {
+        self.rooms.get(topic).and_then(|room| room.last_edit)
+    }
+
+    /// Peers heard from in a room within `timeout`.
+    pub fn peers(&self, topic: &str, timeout: Duration, now: Instant) -> usize {
+        self.rooms.get(topic).map_or(0, |room| room.seen.values().filter(|&&seen| now.saturating_duration_since(seen) <= timeout).count())
+    }
+
+    pub fn memory(&self) -> usize {
+        self.rooms
+            .iter()
+            .map(|(topic, room)| topic.capacity() + room.name.capacity() + size_of::<Room>() + room.seen.len() * size_of::<(PeerId, Instant)>())
+            .sum()
+    }
+}
+
+/// What a keystroke on the dashboard asks for.
+#[derive(Debug, PartialEq)]
+pub enum Action {
+    /// Make the room with this topic the current one and edit it.
+    Open(String),
+    /// Go back to editing the current room.
+    Close,
+    Quit,
+}
+
+/// Full screen table of every room, one selected, see [`Summary`].
+#[derive(Debug, Default)]
+pub struct Dashboard {
+    selected: usize,
+}
+
+impl Dashboard {
+    /// Takes a terminal event, moving the selection through `rooms` or
+    /// returning what it asks for. Digits open the room at that row.
+    pub fn event(&mut self, rooms: &[Summary], event: Event) -> Option<Action> {
+        let Event::Key(pressed) = event else {
+            return None;
+        };
+        if pressed.kind == KeyEventKind::Release {
+            return None;
+        }
+
+        let ctrl = pressed.modifiers.contains(KeyModifiers::CONTROL);
+        self.selected = self.selected.min(rooms.len().saturating_sub(1));
+
+        match pressed.code {
+            KeyCode::Char('c' | 'q') if ctrl => return Some(Action::Quit),
+            KeyCode::Char('r') if ctrl => return Some(Action::Close),
+            KeyCode::Esc => return Some(Action::Close),
+            KeyCode::Char(_) if ctrl => {},
+            KeyCode::Up => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down => self.selected = (self.selected + 1).min(rooms.len().saturating_sub(1)),
+            KeyCode::Enter => return rooms.get(self.selected).map(|room| Action::Open(room.topic.clone())),
+            KeyCode::Char(digit @ '1'..='9') => {
+                let row = digit as usize - '1' as usize;
+                return rooms.get(row).map(|room| Action::Open(room.topic.clone()));
+            },
+            _ => {},
+        }
+
+        None
+    }
+
+    pub fn draw(&mut self, frame: &mut Frame, rooms: &[Summary], now: Instant) {
+        let [body, help] = Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+
+        let rows = rooms.iter().enumerate().map(|(i, room)| {
+            let marker = if room.current { "*" } else { " " };
+            let last_edit = room.last_edit.map_or("never".to_string(), |at| format!("{}s ago", now.saturating_duration_since(at).as_secs()));
+
+            Row::new([
+                format!("{marker}{}", i + 1),
+                room.room.clone(),
+                room.unread.to_string(),
+                room.peers.to_string(),
+                last_edit,
+                room.health.to_string(),
+            ])
+        });
+        let widths = [
+            Constraint::Length(3),
+            Constraint::Min(10),
+            Constraint::Length(7),
+            Constraint::Length(6),
+            Constraint::Length(12),
+            Constraint::Length(12),
+        ];
+        let table = Table::new(rows, widths)
+            .header(Row::new(["", "Room", "Unread", "Peers", "Last edit", "Sync"]).style(Style::new().add_modifier(Modifier::BOLD)))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+            .block(Block::bordered().title(" Rooms "));
+
+        let mut state = TableState::new().with_selected(Some(self.selected.min(rooms.len().saturating_sub(1))));
+        frame.render_stateful_widget(table, body, &mut state);
+        frame.render_widget(Paragraph::new("Enter or 1-9 to open a room, Esc or Ctrl+R to go back"), help);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ratatui::crossterm::event::KeyEvent;
+
+    use super::*;
+
+    #[test]
+    fn counts_unread_edits_and_recent_peers() {
+        let mut rooms = Rooms::default();
+        let (a, b) = (PeerId::random(), PeerId::random());
+        let now = Instant::now();
+
+        rooms.name("topic", "team");
+        rooms.edit("topic", true, now);
+        rooms.seen("topic", a, now);
+        rooms.edit("topic", true, now + Duration::from_secs(20));
+        rooms.seen("topic", b, now + Duration::from_secs(20));
+        rooms.edit("topic", false, now + Duration::from_secs(25));
+        assert_eq!((rooms.name_of("topic"), rooms.unread("topic")), (Some("team"), 2));
+        assert_eq!(rooms.last_edit("topic"), Some(now + Duration::from_secs(25)));
+        assert_eq!(rooms.peers("topic", Duration::from_secs(30), now + Duration::from_secs(40)), 1);
+
+        rooms.read("topic");
+        assert_eq!(rooms.unread("topic"), 0);
+        assert_eq!(rooms.peers("other", Duration::from_secs(30), now), 0);
+    }
+
+    #[test]
+    fn keys_pick_a_room() {
+        let summary = |topic: &str| Summary {
+            room: topic.to_string(),
+            topic: topic.to_string(),
+            current: false,
+            unread: 0,
+            peers: 0,
+            last_edit: None,
+            health: Health::Parked,
+        };
+        let rooms = [summary("a"), summary("b")];
+        let mut dashboard = Dashboard::default();
+        let mut press = |code| dashboard.event(&rooms, Event::Key(KeyEvent::from(code)));
+
+        assert_eq!(press(KeyCode::Down), None);
+        assert_eq!(press(KeyCode::Down), None);
+        assert_eq!(press(KeyCode::Enter), Some(Action::Open("b".to_string())));
+        assert_eq!(press(KeyCode::Char('1')), Some(Action::Open("a".to_string())));
+        assert_eq!(press(KeyCode::Char('3')), None);
+        assert_eq!(press(KeyCode::Esc), Some(Action::Close));
+    }
+}
@@ -0,0 +1,172 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    fmt,
+    time::{Duration, Instant}
+};
+
+use libp2p::PeerId;
+
+/// Number of local edits whose delivery status is kept for display.
+pub const RECENT_EDITS: usize = 5;
+
+/// How long the peers an edit was sent to have to acknowledge it before
+/// those that didn't are reported.
+pub const ACK_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, PartialEq)]
+pub enum Delivery {
+    /// No peers were subscribed to the room when the edit was published.
+    Pending,
+    /// Published to this many peers subscribed to the room.
+    Delivered(usize),
+}
+
+impl fmt::Display for Delivery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Delivery::Pending => write!(f, "pending"),
+            Delivery::Delivered(n) => write!(f, "delivered to {n}"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Recent {
+    summary: String,
+    delivery: Delivery,
+    /// Number of the edit and the peers that applied it, when their
+    /// acknowledgements are awaited.
+    acks: Option<(u64, HashSet<PeerId>)>,
+    published: Instant,
+    reported: bool,
+}
+
+impl Recent {
+    /// Peers that applied the edit of how many it was sent to, if awaited.
+    fn applied(&self) -> Option<(usize, usize)> {
+        let (_, applied) = self.acks.as_ref()?;
+        let sent = match self.delivery {
+            Delivery::Pending => 0,
+            Delivery::Delivered(n) => n,
+        };
+
+        // Peers joining since are counted too.
+        Some((applied.len(), sent.max(applied.len())))
+    }
+}
+
+/// The delivery of the recent local edits and, when acknowledgements are
+/// awaited, which peers applied each. An acknowledgement covers every edit
+/// up to the one it numbers, as each peer applies a peer's edits in order,
+/// and an edit every peer it was sent to applied is no longer shown.
+#[derive(Debug, Default)]
+pub struct RecentEdits {
+    edits: VecDeque<Recent>,
+}
+
+impl RecentEdits {
+    pub fn push(&mut self, summary: String, delivery: Delivery) {
+        self.insert(Recent { summary, delivery, acks: None, published: Instant::now(), reported: false });
+    }
+
+    /// Waits for the `peers` the edit numbered `seq` was sent to to apply it.
+    pub fn published(&mut self, seq: u64, summary: String, peers: usize, now: Instant) {
+        self.insert(Recent { summary, delivery: Delivery::Delivered(peers), acks: Some((seq, HashSet::new())), published: now, reported: false });
+    }
+
+    fn insert(&mut self, edit: Recent) {
+        if self.edits.len() == RECENT_EDITS {
+            self.edits.pop_front();
+        }
+
+        self.edits.push_back(edit);
+    }
+
+    /// Records that `peer` applied the edits up to the one numbered `seq`,
+    /// dropping those every peer they were sent to applied.
+    pub fn applied(&mut self, peer: PeerId, seq: u64) {
+        for (_, applied) in self.edits.iter_mut().filter_map(|edit| edit.acks.as_mut()).filter(|(awaited, _)| *awaited <= seq) {
+            applied.insert(peer);
+        }
+
+        self.edits.retain(|edit| edit.applied().is_none_or(|(applied, sent)| applied < sent));
+    }
+
+    /// The summary and delivery of each recent edit, with how many peers
+    /// applied it of how many it was sent to if that is awaited.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Delivery, Option<(usize, usize)>)> {
+        self.edits.iter().map(|edit| (edit.summary.as_str(), &edit.delivery, edit.applied()))
+    }
+
+    /// The edits some peers stayed silent about for [`ACK_TIMEOUT`], each
+    /// reported once, with how many peers applied them of how many they were sent to.
+    pub fn overdue(&mut self, now: Instant) -> Vec<(String, usize, usize)> {
+        self.edits
+            .iter_mut()
+            .filter(|edit| !edit.reported && now.saturating_duration_since(edit.published) > ACK_TIMEOUT)
+            .filter_map(|edit| {
+                let (applied, sent) = edit.applied()?;
+                edit.reported = true;
+                Some((edit.summary.clone(), applied, sent))
+            })
+            .collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.edits.clear();
+    }
+
+    /// Approximate bytes held by the summaries and the peers that applied them.
+    pub fn memory(&self) -> usize {
+        self.edits.iter().map(|edit| {
+            size_of::<Recent>() + edit.summary.capacity() + edit.acks.as_ref().map_or(0, |(_, applied)| applied.capacity() * size_of::<PeerId>())
+        }).sum()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn keeps_most_recent_edits() {
+        let mut recent = RecentEdits::default();
+
+        for i in 0..=RECENT_EDITS {
+            recent.push(format!("del:{i}"), Delivery::Pending);
+        }
+
+        let summaries: Vec<_> = recent.iter().map(|(summary, _, _)| summary).collect();
+        assert_eq!(summaries.len(), RECENT_EDITS);
+        assert_eq!(summaries[0], "del:1");
+    }
+
+    #[test]
+    fn delivery_display() {
+        assert_eq!(Delivery::Pending.to_string(), "pending");
+        assert_eq!(Delivery::Delivered(3).to_string(), "delivered to 3");
+    }
+
+    #[test]
+    fn acknowledged_edits_are_cleared() {
+        let (a, b) = (PeerId::random(), PeerId::random());
+        let now = Instant::now();
+        let mut recent = RecentEdits::default();
+
+        recent.push("del:0".to_string(), Delivery::Pending);
+        recent.published(1, "ins:0:a".to_string(), 2, now);
+        recent.published(2, "ins:1:b".to_string(), 2, now);
+        recent.applied(a, 1);
+        recent.applied(b, 2);
+        // The first edit is confirmed by both peers, so it is no longer shown.
+        assert_eq!(recent.iter().map(|(summary, _, applied)| (summary, applied)).collect::<Vec<_>>(), vec![("del:0", None), ("ins:1:b", Some((1, 2)))]);
+
+        let late = now + ACK_TIMEOUT + Duration::from_secs(1);
+        assert!(recent.overdue(now).is_empty());
+        assert_eq!(recent.overdue(late), vec![("ins:1:b".to_string(), 1, 2)]);
+        assert!(recent.overdue(late).is_empty());
+
+        recent.applied(a, 2);
+        assert_eq!(recent.iter().map(|(summary, _, _)| summary).collect::<Vec<_>>(), vec!["del:0"]);
+    }
+}
@@ -0,0 +1,58 @@
+use crate::{
+    diff::{Diff, Operation},
+    notepad::Notepad
+};
+
+/// A diff in words, e.g. `inserted "a" at position 3`.
+pub fn diff(diff: &Diff) -> String {
+    let index = diff.index;
+    let operand = diff.operand.map(char).unwrap_or_default();
+
+    match &diff.opcode {
+        Operation::Ins => format!("inserted {operand} at position {index}"),
+        Operation::Del => format!("deleted the character at position {index}"),
+        Operation::Rep => format!("replaced the character at position {index} with {operand}"),
+        Operation::InsStr(text) => format!("inserted \"{text}\" at position {index}"),
+        Operation::DelRange(len) => format!("deleted {len} bytes from position {index}"),
+    }
+}
+
+/// A document as a heading line followed by its text, without any quoting or escapes.
+pub fn document(name: &str, notepad: &Notepad) -> String {
+    let chars = notepad.text().chars().count();
+    let lines = notepad.text().lines().count();
+
+    format!("Document `{name}`, {chars} characters on {lines} lines, checksum {}:\n{}", notepad.checksum(), notepad.text())
+}
+
+/// Names characters that are hard to tell apart when read aloud.
+fn char(c: char) -> String {
+    match c {
+        ' ' => "a space".to_string(),
+        '\n' => "a new line".to_string(),
+        '\t' => "a tab".to_string(),
+        c => format!("\"{c}\""),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn describes_diffs() {
+        let describe = |opcode, operand| diff(&Diff { opcode, operand, index: 3 });
+
+        assert_eq!(describe(Operation::Ins, Some('a')), "inserted \"a\" at position 3");
+        assert_eq!(describe(Operation::Ins, Some('\n')), "inserted a new line at position 3");
+        assert_eq!(describe(Operation::Del, None), "deleted the character at position 3");
+        assert_eq!(describe(Operation::Rep, Some(' ')), "replaced the character at position 3 with a space");
+    }
+
+    #[test]
+    fn describes_documents() {
+        let notepad = Notepad::new("a\nb".to_string());
+
+        assert_eq!(document("main", &notepad), format!("Document `main`, 3 characters on 2 lines, checksum {}:\na\nb", notepad.checksum()));
+    }
+}
@@ -1,97 +1,721 @@
-#[derive(Debug, PartialEq)]
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::NotepadError,
+    varint
+};
+
+/// An edit at a byte index of the text. Published as postcard, see
+/// [`Diff::push_postcard`], and stored in the varint layout of [`Diff::encode`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Diff {
     pub opcode: Operation,
     pub operand: Option<char>,
-    pub index: u8,
+    pub index: usize,
+}
+
+impl Diff {
+    /// Appends the diff as its opcode, the operand as UTF-8 (a zero byte when
+    /// there is none) and a varint index. Range operations carry their string
+    /// or length as a varint byte length, with its bytes for a string, in
+    /// place of the operand.
+    pub fn encode(&self, data: &mut Vec<u8>) {
+        data.push(self.opcode.code());
+
+        match (&self.opcode, self.operand) {
+            (Operation::InsStr(text), _) => {
+                varint::push(data, text.len());
+                data.extend(text.as_bytes());
+            },
+            (Operation::DelRange(len), _) => varint::push(data, *len),
+            (_, Some(c)) => data.extend(c.encode_utf8(&mut [0; 4]).as_bytes()),
+            (_, None) => data.push(0),
+        }
+
+        varint::push(data, self.index);
+    }
+
+    /// Appends the diff serialized with postcard, as diffs are published.
+    /// Postcard writes fields and variants in declaration order, so the
+    /// layout only changes by adding them at the end, and a build that
+    /// changes it in any other way takes a new message tag.
+    pub fn push_postcard(&self, data: &mut Vec<u8>) {
+        *data = postcard::to_extend(self, std::mem::take(data)).expect("serializing into memory can't fail");
+    }
+
+    /// Splits a diff written by [`Diff::encode`] off the front of `data`.
+    pub fn decode(data: &[u8]) -> Result<(Self, &[u8]), NotepadError> {
+        let (diff, data) = DiffRef::decode(data)?;
+
+        Ok((diff.to_diff(), data))
+    }
+
+    /// The diff with its string borrowed, as [`DiffRef`] decodes it.
+    pub fn borrowed(&self) -> DiffRef<'_> {
+        let opcode = match &self.opcode {
+            Operation::Del => OperationRef::Del,
+            Operation::Ins => OperationRef::Ins,
+            Operation::Rep => OperationRef::Rep,
+            Operation::InsStr(text) => OperationRef::InsStr(text),
+            Operation::DelRange(len) => OperationRef::DelRange(*len),
+        };
+
+        DiffRef { opcode, operand: self.operand, index: self.index }
+    }
+}
+
+/// A diff decoded in place, its string borrowed from the payload rather
+/// than copied, for payloads only looked at, such as those of watched rooms.
+/// Made into a [`Diff`] with [`DiffRef::to_diff`] when it is kept.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct DiffRef<'a> {
+    #[serde(borrow)]
+    pub opcode: OperationRef<'a>,
+    pub operand: Option<char>,
+    pub index: usize,
+}
+
+/// An [`Operation`] borrowing the string it inserts. Its variants are in
+/// the same order, so it deserializes from a serialized `Operation`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum OperationRef<'a> {
+    Del,
+    Ins,
+    Rep,
+    InsStr(&'a str),
+    DelRange(usize),
+}
+
+impl<'a> DiffRef<'a> {
+    /// Splits a diff written by [`Diff::encode`] off the front of `data`, without allocating.
+    pub fn decode(data: &'a [u8]) -> Result<(Self, &'a [u8]), NotepadError> {
+        let [opcode, data @ ..] = data else {
+            return Err(NotepadError::Decode("Truncated diff"));
+        };
+
+        let (opcode, operand, data) = match *opcode {
+            INS_STR => {
+                let (len, data) = varint::split(data)?;
+                let text = data.get(..len).ok_or(NotepadError::Decode("Truncated diff"))?;
+                let text = std::str::from_utf8(text).map_err(|_| NotepadError::Decode("Text is not valid UTF-8"))?;
+
+                (OperationRef::InsStr(text), None, &data[len..])
+            },
+            DEL_RANGE => {
+                let (len, data) = varint::split(data)?;
+
+                (OperationRef::DelRange(len), None, data)
+            },
+            opcode => {
+                let (operand, data) = split_operand(data)?;
+                let opcode = match opcode.try_into()? {
+                    Operation::Del => OperationRef::Del,
+                    Operation::Ins => OperationRef::Ins,
+                    _ => OperationRef::Rep,
+                };
+
+                (opcode, operand, data)
+            },
+        };
+        let (index, data) = varint::split(data)?;
+
+        Ok((DiffRef { opcode, operand, index }, data))
+    }
+
+    /// Splits a diff written by [`Diff::push_postcard`] off the front of `data`, without allocating.
+    pub fn split_postcard(data: &'a [u8]) -> Result<(Self, &'a [u8]), NotepadError> {
+        postcard::take_from_bytes(data).map_err(|_| NotepadError::Decode("Invalid serialized diff"))
+    }
+
+    pub fn to_diff(&self) -> Diff {
+        let opcode = match self.opcode {
+            OperationRef::Del => Operation::Del,
+            OperationRef::Ins => Operation::Ins,
+            OperationRef::Rep => Operation::Rep,
+            OperationRef::InsStr(text) => Operation::InsStr(text.to_string()),
+            OperationRef::DelRange(len) => Operation::DelRange(len),
+        };
+
+        Diff { opcode, operand: self.operand, index: self.index }
+    }
+}
+
+/// Diffs decoded one at a time as they are iterated, see [`DiffRef`]. A
+/// diff that doesn't decode ends the iteration with its error.
+#[derive(Debug, Clone)]
+pub struct Diffs<'a> {
+    data: &'a [u8],
+    /// Written by [`Diff::push_postcard`] rather than [`Diff::encode`].
+    postcard: bool,
+}
+
+impl<'a> Diffs<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, postcard: false }
+    }
+
+    /// Diffs serialized with postcard, as they are published.
+    pub fn postcard(data: &'a [u8]) -> Self {
+        Self { data, postcard: true }
+    }
+}
+
+impl<'a> Iterator for Diffs<'a> {
+    type Item = Result<DiffRef<'a>, NotepadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+
+        let decoded = if self.postcard { DiffRef::split_postcard(self.data) } else { DiffRef::decode(self.data) };
+
+        match decoded {
+            Ok((diff, rest)) => {
+                self.data = rest;
+                Some(Ok(diff))
+            },
+            Err(e) => {
+                self.data = &[];
+                Some(Err(e))
+            },
+        }
+    }
+}
+
+impl fmt::Display for Diff {
+    /// Formats the diff in the same syntax used to enter it on stdin.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match &self.opcode {
+            Operation::Del => "del",
+            Operation::Ins => "ins",
+            Operation::Rep => "rep",
+            Operation::InsStr(text) => return write!(f, "inss:{}:{text}", self.index),
+            Operation::DelRange(len) => return write!(f, "delr:{}:{len}", self.index),
+        };
+
+        write!(f, "{op}:{}", self.index)?;
+
+        if let Some(operand) = self.operand {
+            write!(f, ":{operand}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Maximum number of diffs published in a single gossip message, larger
+/// buffers are streamed as several ordered chunks.
+pub const CHUNK_LEN: usize = 1024;
+
+/// Longest text of an insert run in bytes, so it fits a length-prefixed string on the wire.
+pub const MAX_RUN_TEXT: usize = u8::MAX as usize;
+
+/// Most characters inserted and deleted that [`compute`] looks for the
+/// shortest edit with. Past this, texts differing all over are replaced from
+/// the first difference to the last instead, in bounded time and memory.
+pub const MAX_EDIT_DISTANCE: usize = 1024;
+
+/// Diffs collapsed into a single operation, see [`MessageBuf::compress`].
+#[derive(Debug, PartialEq)]
+pub enum Run {
+    /// A diff that isn't part of a run.
+    Diff(Diff),
+    /// Inserts of consecutive characters, each right after the one before, as typing produces.
+    Insert {
+        index: usize,
+        text: String,
+    },
+    /// Deletes repeated at the same index, removing `count` characters from it onwards.
+    Delete {
+        index: usize,
+        count: usize,
+    },
 }
 
-#[derive(Debug, Default, PartialEq)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct MessageBuf {
     pub messages: Vec<Diff>,
 }
 
-#[derive(Debug, PartialEq)]
+impl MessageBuf {
+    /// Short description of the buffer for status output.
+    pub fn summary(&self) -> String {
+        match self.messages.as_slice() {
+            [diff] => diff.to_string(),
+            messages => format!("{} ops", messages.len()),
+        }
+    }
+
+    /// Most bytes the diffs can grow a text by, negative if they shrink it.
+    /// Each character deleted or replaced counts as a single byte.
+    pub fn max_growth(&self) -> isize {
+        self.messages.iter().map(|diff| {
+            let inserted = match &diff.opcode {
+                Operation::Ins | Operation::Rep => diff.operand.map_or(0, char::len_utf8),
+                Operation::InsStr(text) => text.len(),
+                Operation::Del | Operation::DelRange(_) => 0,
+            };
+            let removed = match &diff.opcode {
+                Operation::Del | Operation::Rep => 1,
+                Operation::DelRange(len) => *len,
+                Operation::Ins | Operation::InsStr(_) => 0,
+            };
+
+            inserted as isize - removed as isize
+        }).sum()
+    }
+
+    /// Collapses runs of inserts and deletes. Expanding the runs gives back the same diffs.
+    pub fn compress(self) -> Vec<Run> {
+        let mut runs = Vec::new();
+
+        for diff in self.messages {
+            match (runs.last_mut(), &diff) {
+                (Some(Run::Insert { index, text }), Diff { opcode: Operation::Ins, operand: Some(c), index: next })
+                    if *index + text.len() == *next && text.len() + c.len_utf8() <= MAX_RUN_TEXT => text.push(*c),
+                (Some(Run::Delete { index, count }), Diff { opcode: Operation::Del, operand: None, index: next })
+                    if index == next => *count += 1,
+                _ => runs.push(match diff {
+                    Diff { opcode: Operation::Ins, operand: Some(c), index } => Run::Insert { index, text: c.to_string() },
+                    Diff { opcode: Operation::Del, operand: None, index } => Run::Delete { index, count: 1 },
+                    diff => Run::Diff(diff),
+                }),
+            }
+        }
+
+        runs
+    }
+
+    pub fn expand(runs: Vec<Run>) -> Self {
+        let mut messages = Vec::new();
+
+        for run in runs {
+            match run {
+                Run::Diff(diff) => messages.push(diff),
+                Run::Insert { index, text } => {
+                    messages.extend(text.char_indices().map(|(offset, c)| Diff { opcode: Operation::Ins, operand: Some(c), index: index + offset }));
+                },
+                Run::Delete { index, count } => {
+                    messages.extend((0..count).map(|_| Diff { opcode: Operation::Del, operand: None, index }));
+                },
+            }
+        }
+
+        MessageBuf { messages }
+    }
+
+    /// Diffs turning `old` into `new`. The text between their common start
+    /// and end is deleted and the new text inserted in its place.
+    pub fn between(old: &str, new: &str) -> Self {
+        let prefix = old
+            .char_indices()
+            .zip(new.chars())
+            .find(|((_, a), b)| a != b)
+            .map_or(old.len().min(new.len()), |((i, _), _)| i);
+        let (old, new) = (&old[prefix..], &new[prefix..]);
+        let suffix: usize = old
+            .chars()
+            .rev()
+            .zip(new.chars().rev())
+            .take_while(|(a, b)| a == b)
+            .map(|(c, _)| c.len_utf8())
+            .sum();
+
+        let deletes = old[..old.len() - suffix].chars().map(|_| Diff { opcode: Operation::Del, operand: None, index: prefix });
+        let inserts = new[..new.len() - suffix].char_indices().map(|(offset, c)| Diff { opcode: Operation::Ins, operand: Some(c), index: prefix + offset });
+
+        MessageBuf { messages: deletes.chain(inserts).collect() }
+    }
+
+    /// Splits the buffer into ordered chunks of at most `chunk_len` diffs.
+    /// Applying the chunks in order is equivalent to applying the whole buffer.
+    pub fn into_chunks(self, chunk_len: usize) -> Vec<MessageBuf> {
+        let chunk_len = chunk_len.max(1);
+        let mut chunks = Vec::new();
+        let mut messages = self.messages.into_iter().peekable();
+
+        while messages.peek().is_some() {
+            chunks.push(MessageBuf { messages: messages.by_ref().take(chunk_len).collect() });
+        }
+
+        chunks
+    }
+}
+
+const INS_STR: u8 = 3;
+const DEL_RANGE: u8 = 4;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Operation {
     Del,
     Ins,
-    Rep
+    Rep,
+    /// Inserts a string at the index, so a paste or a typed word is one diff.
+    InsStr(String),
+    /// Deletes this many bytes from the index onwards.
+    DelRange(usize),
+}
+
+impl Operation {
+    /// Byte the operation is encoded as.
+    pub fn code(&self) -> u8 {
+        match self {
+            Operation::Del => 0,
+            Operation::Ins => 1,
+            Operation::Rep => 2,
+            Operation::InsStr(_) => INS_STR,
+            Operation::DelRange(_) => DEL_RANGE,
+        }
+    }
 }
 
 impl TryFrom<u8> for Operation {
-    type Error = &'static str;
+    type Error = NotepadError;
 
+    /// Decodes the operations without a payload of their own, as range
+    /// operations can't be given one in the fixed layout.
     fn try_from(byte: u8) -> Result<Self, Self::Error> {
         match byte {
             0 => Ok(Operation::Del),
             1 => Ok(Operation::Ins),
             2 => Ok(Operation::Rep),
-            _ => Err("Invalid opcode byte")
+            _ => Err(NotepadError::Decode("Invalid opcode byte"))
         }
     }
     
 }
 
-impl From<Vec<u8>> for MessageBuf {
-    fn from(data: Vec<u8>) -> Self {
+impl MessageBuf {
+    /// Decodes diffs in the fixed three byte layout with a single byte index,
+    /// as published by peers that predate varint indices.
+    pub fn decode_fixed(data: &[u8]) -> Result<Self, NotepadError> {
         let mut messages = Vec::new();
 
-        if data.len() % 3 != 0 {
-            panic!("Data length must be a multiple of 3");
+        if !data.len().is_multiple_of(3) {
+            return Err(NotepadError::Decode("Data length must be a multiple of 3"));
         }
 
         for chunk in data.chunks(3) {
-            let opcode = chunk[0].try_into().unwrap(); 
+            let opcode = chunk[0].try_into()?;
 
-            let operand = match chunk[1] {
-                0 => None,
-                c => Some(c as char)
-            };
+            messages.push(Diff { opcode, operand: operand_char(chunk[1]), index: chunk[2].into() });
+        }
 
-            let index = chunk[2];
+        Ok(MessageBuf { messages })
+    }
+}
 
-            messages.push(Diff { opcode, operand, index });
-        }
+impl MessageBuf {
+    /// Decodes diffs written by [`Diff::encode`] straight from `data`, which
+    /// is only copied from for the strings of the diffs.
+    pub fn decode(data: &[u8]) -> Result<Self, NotepadError> {
+        let messages = Diffs::new(data).map(|diff| diff.map(|diff| diff.to_diff())).collect::<Result<_, _>>()?;
 
-        MessageBuf { messages }
+        Ok(MessageBuf { messages })
+    }
+
+    /// Decodes diffs written by [`Diff::push_postcard`] straight from `data`.
+    pub fn decode_postcard(data: &[u8]) -> Result<Self, NotepadError> {
+        let messages = Diffs::postcard(data).map(|diff| diff.map(|diff| diff.to_diff())).collect::<Result<_, _>>()?;
+
+        Ok(MessageBuf { messages })
+    }
+}
+
+impl TryFrom<Vec<u8>> for MessageBuf {
+    type Error = NotepadError;
+
+    fn try_from(data: Vec<u8>) -> Result<Self, Self::Error> {
+        MessageBuf::decode(&data)
     }
 }
 
-impl Into<Vec<u8>> for MessageBuf {
-    fn into(self) -> Vec<u8> {
+impl From<MessageBuf> for Vec<u8> {
+    fn from(message: MessageBuf) -> Self {
         let mut data = Vec::new();
-        for Diff { opcode, operand, index } in self.messages {
-            let opcode_byte = opcode as u8;
-            let operand_byte = match operand {
-                Some(c) => c as u8,
-                None => 0,
-            };
-            data.push(opcode_byte);
-            data.push(operand_byte);
-            data.push(index);
+        for diff in message.messages {
+            diff.encode(&mut data);
         }
 
         data
     }
 }
 
+/// Splits a UTF-8 encoded operand off the front of `data`, its length is given by the leading byte.
+fn split_operand(data: &[u8]) -> Result<(Option<char>, &[u8]), NotepadError> {
+    let len = match data.first() {
+        None => return Err(NotepadError::Decode("Truncated diff")),
+        Some(0) => return Ok((None, &data[1..])),
+        Some(0x01..=0x7f) => 1,
+        Some(0xc0..=0xdf) => 2,
+        Some(0xe0..=0xef) => 3,
+        Some(0xf0..=0xf7) => 4,
+        Some(_) => return Err(NotepadError::Decode("Invalid operand")),
+    };
+
+    if data.len() < len {
+        return Err(NotepadError::Decode("Truncated diff"));
+    }
+
+    let (operand, data) = data.split_at(len);
+    let operand = std::str::from_utf8(operand).map_err(|_| NotepadError::Decode("Operand is not valid UTF-8"))?;
+
+    Ok((operand.chars().next(), data))
+}
+
+/// Operand of the fixed layout, which only carried a single byte.
+fn operand_char(byte: u8) -> Option<char> {
+    match byte {
+        0 => None,
+        c => Some(c as char)
+    }
+}
+
+/// Diffs turning `old` into `new` with the fewest characters deleted and
+/// inserted, found with Myers' algorithm. Neighbouring changes are merged
+/// into one range delete and one string insert, applied in order.
+pub fn compute(old: &str, new: &str) -> MessageBuf {
+    let prefix = old
+        .char_indices()
+        .zip(new.chars())
+        .find(|((_, a), b)| a != b)
+        .map_or(old.len().min(new.len()), |((i, _), _)| i);
+    let suffix: usize = old[prefix..]
+        .chars()
+        .rev()
+        .zip(new[prefix..].chars().rev())
+        .take_while(|(a, b)| a == b)
+        .map(|(c, _)| c.len_utf8())
+        .sum();
+
+    let a: Vec<char> = old[prefix..old.len() - suffix].chars().collect();
+    let b: Vec<char> = new[prefix..new.len() - suffix].chars().collect();
+    let edits = shortest_edit(&a, &b).unwrap_or_else(|| {
+        a.iter().map(|_| Edit::Delete).chain(b.iter().map(|&c| Edit::Insert(c))).collect()
+    });
+
+    let mut messages = Vec::new();
+    let mut index = prefix;
+    let mut a = a.iter();
+
+    for edit in edits {
+        match (edit, messages.last_mut()) {
+            (Edit::Keep, _) => index += a.next().map_or(0, |c| c.len_utf8()),
+            (Edit::Delete, Some(Diff { opcode: Operation::DelRange(len), index: at, .. })) if *at == index => {
+                *len += a.next().map_or(0, |c| c.len_utf8());
+            },
+            (Edit::Delete, _) => {
+                let len = a.next().map_or(0, |c| c.len_utf8());
+                messages.push(Diff { opcode: Operation::DelRange(len), operand: None, index });
+            },
+            (Edit::Insert(c), Some(Diff { opcode: Operation::InsStr(text), index: at, .. })) if *at + text.len() == index => {
+                text.push(c);
+                index += c.len_utf8();
+            },
+            (Edit::Insert(c), _) => {
+                messages.push(Diff { opcode: Operation::InsStr(c.to_string()), operand: None, index });
+                index += c.len_utf8();
+            },
+        }
+    }
+
+    MessageBuf { messages }
+}
+
+/// Diffs turning `old` into `new` a word at a time, see [`words`]: every
+/// word removed is one range delete and every word added one string insert,
+/// so history reads as the words changed rather than their characters.
+pub fn compute_words(old: &str, new: &str) -> MessageBuf {
+    let (a, b) = (words(old), words(new));
+    let edits = shortest_edit(&a, &b).unwrap_or_else(|| {
+        a.iter().map(|_| Edit::Delete).chain(b.iter().map(|&word| Edit::Insert(word))).collect()
+    });
+
+    let mut messages = Vec::new();
+    let mut index = 0;
+    let mut a = a.iter();
+
+    for edit in edits {
+        match edit {
+            Edit::Keep => index += a.next().map_or(0, |word| word.len()),
+            Edit::Delete => {
+                let len = a.next().map_or(0, |word| word.len());
+                messages.push(Diff { opcode: Operation::DelRange(len), operand: None, index });
+            },
+            Edit::Insert(word) => {
+                messages.push(Diff { opcode: Operation::InsStr(word.to_string()), operand: None, index });
+                index += word.len();
+            },
+        }
+    }
+
+    MessageBuf { messages }
+}
+
+/// The words of `text` in order, each with the spacing after it. Spacing
+/// at the very start is a word of its own.
+pub fn words(text: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut start = 0;
+    let mut spacing = false;
+
+    for (i, c) in text.char_indices() {
+        if spacing && !c.is_whitespace() {
+            words.push(&text[start..i]);
+            start = i;
+        }
+        spacing = c.is_whitespace();
+    }
+    if start < text.len() {
+        words.push(&text[start..]);
+    }
+
+    words
+}
+
+/// How finely local edits are published and whole texts diffed.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Granularity {
+    /// An operation per character typed or deleted.
+    #[default]
+    Char,
+    /// An operation per word typed or deleted, see [`compute_words`] and
+    /// [`crate::batch::EditBatch`].
+    Word,
+}
+
+impl Granularity {
+    /// Diffs turning `old` into `new` at this granularity.
+    pub fn compute(self, old: &str, new: &str) -> MessageBuf {
+        match self {
+            Granularity::Char => compute(old, new),
+            Granularity::Word => compute_words(old, new),
+        }
+    }
+}
+
+impl FromStr for Granularity {
+    type Err = NotepadError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "char" => Ok(Granularity::Char),
+            "word" => Ok(Granularity::Word),
+            _ => Err(NotepadError::command(format!("Unknown diff granularity `{s}`, expected char or word"))),
+        }
+    }
+}
+
+/// A step of an edit script, taken against the old sequence.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Edit<T> {
+    Keep,
+    Delete,
+    Insert(T),
+}
+
+/// The shortest edit script turning `a` into `b`, or `None` if it is longer
+/// than [`MAX_EDIT_DISTANCE`]. The furthest reaching x of every diagonal `k`
+/// the round reached is kept for each round `d`, to walk back through.
+pub fn shortest_edit<T: PartialEq + Copy>(a: &[T], b: &[T]) -> Option<Vec<Edit<T>>> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = (a.len() + b.len()).min(MAX_EDIT_DISTANCE) as isize;
+    let offset = max + 1;
+    let mut v = vec![0isize; 2 * offset as usize + 1];
+    let mut trace = Vec::new();
+
+    for d in 0..=max {
+        for k in (-d..=d).step_by(2) {
+            let at = |k: isize| v[(k + offset) as usize];
+            let mut x = if k == -d || (k != d && at(k - 1) < at(k + 1)) { at(k + 1) } else { at(k - 1) + 1 };
+            let mut y = x - k;
+
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[(k + offset) as usize] = x;
+
+            if x >= n && y >= m {
+                trace.push(v[(offset - d) as usize..=(offset + d) as usize].to_vec());
+                return Some(backtrack(&trace, b, n, m));
+            }
+        }
+
+        trace.push(v[(offset - d) as usize..=(offset + d) as usize].to_vec());
+    }
+
+    None
+}
+
+fn backtrack<T: Copy>(trace: &[Vec<isize>], b: &[T], n: isize, m: isize) -> Vec<Edit<T>> {
+    let mut edits = Vec::new();
+    let (mut x, mut y) = (n, m);
+
+    for d in (1..trace.len() as isize).rev() {
+        let at = |k: isize| trace[d as usize - 1][(k + d - 1) as usize];
+        let k = x - y;
+        let from = if k == -d || (k != d && at(k - 1) < at(k + 1)) { k + 1 } else { k - 1 };
+        let (from_x, from_y) = (at(from), at(from) - from);
+
+        // The diagonal run after the move, then the move itself.
+        let moved = if from == k + 1 { (from_x, from_y + 1) } else { (from_x + 1, from_y) };
+        while x > moved.0 && y > moved.1 {
+            edits.push(Edit::Keep);
+            x -= 1;
+            y -= 1;
+        }
+
+        edits.push(if from == k + 1 { Edit::Insert(b[from_y as usize]) } else { Edit::Delete });
+        (x, y) = (from_x, from_y);
+    }
+
+    edits.extend((0..x).map(|_| Edit::Keep));
+    edits.reverse();
+
+    edits
+}
+
 #[cfg(test)]
 mod test {
+    use proptest::{collection::vec, prelude::*};
+
     use super::*;
+    use crate::notepad::Notepad;
 
     #[test]
     fn byte_to_operation() {
-        assert_eq!(0.try_into(), Ok(Operation::Del));
-        assert_eq!(1.try_into(), Ok(Operation::Ins));
-        assert_eq!(2.try_into(), Ok(Operation::Rep));
+        assert_eq!(Operation::try_from(0).unwrap(), Operation::Del);
+        assert_eq!(Operation::try_from(1).unwrap(), Operation::Ins);
+        assert_eq!(Operation::try_from(2).unwrap(), Operation::Rep);
         let e: Result<Operation, _> = 3.try_into();
         assert!(e.is_err());
     }
 
     #[test]
     fn byte_from_operation() {
-        assert_eq!(Operation::Del as u8, 0);
-        assert_eq!(Operation::Ins as u8, 1);
-        assert_eq!(Operation::Rep as u8, 2);
+        assert_eq!(Operation::Del.code(), 0);
+        assert_eq!(Operation::Ins.code(), 1);
+        assert_eq!(Operation::Rep.code(), 2);
+        assert_eq!(Operation::DelRange(3).code(), 4);
+    }
+
+    #[test]
+    fn range_round_trip() {
+        let message = || MessageBuf { messages: vec![
+            Diff { opcode: Operation::InsStr("a:é".repeat(100)), operand: None, index: 7 },
+            Diff { opcode: Operation::DelRange(300), operand: None, index: 2 },
+            Diff { opcode: Operation::Ins, operand: Some('b'), index: 0 },
+        ] };
+
+        let data: Vec<u8> = message().into();
+        assert_eq!(MessageBuf::try_from(data.clone()).unwrap(), message());
+        assert!(MessageBuf::try_from(data[..10].to_vec()).is_err());
+        assert_eq!(message().messages[1].to_string(), "delr:2:300");
+        assert_eq!(message().max_growth(), 400 - 300 + 1);
     }
 
     fn def_message() -> MessageBuf {
@@ -117,9 +741,244 @@ mod test {
     fn from_message_buf() {
         let data = vec![1, 97, 0, 1, 98, 0, 0, 0, 1];
 
-        let message: MessageBuf = data.into();
+        let message: MessageBuf = data.try_into().unwrap();
 
         assert_eq!(message, def_message());
     }
 
+    #[test]
+    fn wide_index_round_trip() {
+        let message = || MessageBuf { messages: vec![Diff { opcode: Operation::Del, operand: None, index: 300 }] };
+
+        let data: Vec<u8> = message().into();
+        assert_eq!(data, vec![0, 0, 0xac, 0x02]);
+        assert_eq!(MessageBuf::try_from(data).unwrap(), message());
+    }
+
+    #[test]
+    fn multibyte_operands_round_trip() {
+        let message = || MessageBuf {
+            messages: ['é', 'λ', '€', '🦀'].into_iter().zip(0..).map(|(c, index)| Diff { opcode: Operation::Ins, operand: Some(c), index }).collect()
+        };
+
+        let data: Vec<u8> = message().into();
+        assert_eq!(&data[..4], &[1, 0xc3, 0xa9, 0]);
+        assert_eq!(MessageBuf::try_from(data).unwrap(), message());
+    }
+
+    #[test]
+    fn diffs_decode_in_place() {
+        let message = || MessageBuf { messages: vec![
+            Diff { opcode: Operation::InsStr("héllo".to_string()), operand: None, index: 2 },
+            Diff { opcode: Operation::Rep, operand: Some('x'), index: 0 },
+        ] };
+        let data: Vec<u8> = message().into();
+
+        let diffs: Vec<_> = Diffs::new(&data).collect::<Result<_, _>>().unwrap();
+        assert_eq!(diffs[0].opcode, OperationRef::InsStr("héllo"));
+        assert!(std::ptr::eq(data[2..].as_ptr(), match diffs[0].opcode { OperationRef::InsStr(text) => text.as_ptr(), _ => unreachable!() }));
+        assert_eq!(diffs.iter().map(DiffRef::to_diff).collect::<Vec<_>>(), message().messages);
+        assert_eq!(message().messages[1].borrowed(), diffs[1]);
+
+        let mut diffs = Diffs::new(&data[..data.len() - 1]);
+        assert!(diffs.next().unwrap().is_ok());
+        assert!(diffs.next().unwrap().is_err());
+        assert!(diffs.next().is_none());
+    }
+
+    #[test]
+    fn diffs_round_trip_through_postcard() {
+        let message = || MessageBuf { messages: vec![
+            Diff { opcode: Operation::InsStr("héllo".to_string()), operand: None, index: 300 },
+            Diff { opcode: Operation::Rep, operand: Some('🦀'), index: 0 },
+            Diff { opcode: Operation::DelRange(4), operand: None, index: 7 },
+        ] };
+        let mut data = Vec::new();
+        message().messages.iter().for_each(|diff| diff.push_postcard(&mut data));
+
+        // The operation's variant, then its string, borrowed when decoded in place.
+        assert_eq!(&data[..7], &[3, 6, b'h', 0xc3, 0xa9, b'l', b'l']);
+        let diffs: Vec<_> = Diffs::postcard(&data).collect::<Result<_, _>>().unwrap();
+        assert!(std::ptr::eq(data[2..].as_ptr(), match diffs[0].opcode { OperationRef::InsStr(text) => text.as_ptr(), _ => unreachable!() }));
+        assert_eq!(MessageBuf::decode_postcard(&data).unwrap(), message());
+
+        assert!(MessageBuf::decode_postcard(&data[..data.len() - 1]).is_err());
+        assert!(MessageBuf::decode_postcard(&[9, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn fixed_message_buf() {
+        let message = MessageBuf::decode_fixed(&[1, 97, 200, 0, 0, 1]).unwrap();
+
+        assert_eq!(message.messages[0], Diff { opcode: Operation::Ins, operand: Some('a'), index: 200 });
+        assert!(MessageBuf::decode_fixed(&[1, 97]).is_err());
+    }
+
+    #[test]
+    fn invalid_message_buf() {
+        assert!(MessageBuf::try_from(vec![1, 97]).is_err());
+        assert!(MessageBuf::try_from(vec![1, 97, 0x80]).is_err());
+        assert!(MessageBuf::try_from(vec![3, 97, 0]).is_err());
+        assert!(MessageBuf::try_from(vec![1, 0xc3, 0]).is_err());
+        assert!(MessageBuf::try_from(vec![1, 0xff, 0]).is_err());
+        assert!(MessageBuf::try_from(vec![1, 0xe2, 0x82]).is_err());
+    }
+
+    #[test]
+    fn diff_display() {
+        let message = def_message();
+
+        assert_eq!(message.messages[0].to_string(), "ins:0:a");
+        assert_eq!(message.messages[2].to_string(), "del:1");
+        assert_eq!(message.summary(), "3 ops");
+    }
+
+    #[test]
+    fn compress_runs() {
+        let diff = |opcode, operand, index| Diff { opcode, operand, index };
+        let typed = || MessageBuf { messages: vec![
+            diff(Operation::Ins, Some('a'), 3),
+            diff(Operation::Ins, Some('é'), 4),
+            diff(Operation::Ins, Some('b'), 6),
+            diff(Operation::Ins, Some('c'), 0),
+            diff(Operation::Del, None, 5),
+            diff(Operation::Del, None, 5),
+            diff(Operation::Rep, Some('x'), 1),
+        ] };
+
+        let runs = typed().compress();
+        assert_eq!(runs, vec![
+            Run::Insert { index: 3, text: "aéb".to_string() },
+            Run::Insert { index: 0, text: "c".to_string() },
+            Run::Delete { index: 5, count: 2 },
+            Run::Diff(diff(Operation::Rep, Some('x'), 1)),
+        ]);
+        assert_eq!(MessageBuf::expand(runs), typed());
+    }
+
+    #[test]
+    fn long_insert_runs_are_split() {
+        let typed = || MessageBuf {
+            messages: (0..200).map(|i| Diff { opcode: Operation::Ins, operand: Some('é'), index: i * 2 }).collect()
+        };
+
+        let runs = typed().compress();
+        assert!(matches!(&runs[..], [Run::Insert { text, .. }, Run::Insert { index: 254, .. }] if text.len() == 254));
+        assert_eq!(MessageBuf::expand(runs), typed());
+    }
+
+    #[test]
+    fn diffs_between_texts() {
+        let apply = |old: &str, new: &str| {
+            let mut notepad = Notepad::new(old.to_string());
+            let diffs = MessageBuf::between(old, new);
+
+            notepad.apply_message_buf(&diffs).unwrap();
+            assert_eq!(notepad.text(), new);
+            diffs.messages.len()
+        };
+
+        assert_eq!(apply("hello world", "hello world"), 0);
+        assert_eq!(apply("hello world", "hello, world"), 1);
+        assert_eq!(apply("héllo", "hallo"), 2);
+        assert_eq!(apply("aaa", "aa"), 1);
+        assert_eq!(apply("", "λ🦀"), 2);
+        assert_eq!(apply("λ🦀", ""), 2);
+    }
+
+    #[test]
+    fn computes_shortest_diffs() {
+        let apply = |old: &str, new: &str| {
+            let mut notepad = Notepad::new(old.to_string());
+            let diffs = compute(old, new);
+
+            notepad.apply_message_buf(&diffs).unwrap();
+            assert_eq!(notepad.text(), new);
+            diffs.messages.iter().map(ToString::to_string).collect::<Vec<_>>()
+        };
+
+        assert!(apply("hello world", "hello world").is_empty());
+        assert_eq!(apply("abcabba", "cbabac"), ["delr:0:2", "inss:1:b", "delr:4:1", "inss:5:c"].map(str::to_string));
+        assert_eq!(apply("the cat sat", "the dog sat down"), ["delr:4:3", "inss:4:dog", "inss:11: down"].map(str::to_string));
+        assert_eq!(apply("héllo wörld", "hallo world!"), ["delr:1:2", "inss:1:a", "delr:7:2", "inss:7:o", "inss:11:!"].map(str::to_string));
+        apply("", "λ🦀");
+        apply("λ🦀", "");
+
+        let old: String = (0..3000).map(|i| if i % 2 == 0 { 'a' } else { 'b' }).collect();
+        let new: String = (0..3000).map(|i| if i % 3 == 0 { 'a' } else { 'c' }).collect();
+        apply(&old, &new);
+    }
+
+    #[test]
+    fn computes_word_diffs() {
+        let apply = |old: &str, new: &str| {
+            let mut notepad = Notepad::new(old.to_string());
+            let diffs = compute_words(old, new);
+
+            notepad.apply_message_buf(&diffs).unwrap();
+            assert_eq!(notepad.text(), new);
+            diffs.messages.iter().map(ToString::to_string).collect::<Vec<_>>()
+        };
+
+        assert_eq!(words("  the cat\nsat "), ["  ", "the ", "cat\n", "sat "]);
+        assert!(apply("hello world", "hello world").is_empty());
+        assert_eq!(apply("the cat sat", "the dog sat down"), ["delr:4:4", "delr:4:3", "inss:4:dog ", "inss:8:sat ", "inss:12:down"].map(str::to_string));
+        assert_eq!(apply("héllo wörld", "héllo wörld!"), ["delr:7:6", "inss:7:wörld!"].map(str::to_string));
+        apply("", "λ 🦀");
+        apply("λ 🦀", "");
+    }
+
+    #[test]
+    fn message_buf_into_chunks() {
+        let chunks = def_message().into_chunks(2);
+
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].messages, def_message().messages[..2]);
+        assert_eq!(chunks[1].messages, def_message().messages[2..]);
+
+        assert_eq!(def_message().into_chunks(3), vec![def_message()]);
+        assert!(MessageBuf::default().into_chunks(2).is_empty());
+    }
+
+    /// Any diff that survives encoding: a zero byte stands for no operand,
+    /// and range operations carry their string or length in its place.
+    fn arbitrary_diff() -> impl Strategy<Value = Diff> {
+        let opcode = prop_oneof![
+            Just(Operation::Del),
+            Just(Operation::Ins),
+            Just(Operation::Rep),
+            ".{0,20}".prop_map(Operation::InsStr),
+            (0..64usize).prop_map(Operation::DelRange),
+        ];
+        let operand = proptest::option::of(any::<char>().prop_filter("no zero operand", |&c| c != '\0'));
+
+        (opcode, operand, 0..64usize).prop_map(|(opcode, operand, index)| {
+            let operand = operand.filter(|_| matches!(opcode, Operation::Del | Operation::Ins | Operation::Rep));
+
+            Diff { opcode, operand, index }
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn arbitrary_diffs_round_trip(messages in vec(arbitrary_diff(), 0..20)) {
+            let message = MessageBuf { messages };
+            let data: Vec<u8> = message.clone().into();
+
+            prop_assert_eq!(MessageBuf::decode(&data).unwrap(), message);
+        }
+
+        #[test]
+        fn arbitrary_bytes_decode_without_panicking(data in vec(any::<u8>(), 0..256)) {
+            let _ = MessageBuf::decode(&data);
+            let _ = MessageBuf::decode_fixed(&data);
+        }
+
+        #[test]
+        fn arbitrary_diffs_apply_without_panicking(text in ".{0,40}", messages in vec(arbitrary_diff(), 0..20)) {
+            let mut notepad = Notepad::new(text);
+
+            let _ = notepad.apply_message_buf(&MessageBuf { messages });
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,65 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant}
+};
+
+use crate::presence::PRESENCE_INTERVAL;
+
+/// Listings not refreshed within this long are dropped. Listed rooms refresh
+/// theirs with every presence heartbeat, so a few can be missed.
+pub const LISTING_TTL: Duration = Duration::from_secs(3 * PRESENCE_INTERVAL.as_secs());
+
+/// A public room advertised in a directory so others can find it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoomListing {
+    pub room: String,
+    pub description: String,
+    /// Peers in the room when it was last listed, including the one listing it.
+    pub participants: u32,
+}
+
+/// Rooms listed with this peer, served to anyone asking with `rooms`.
+/// Rooms are only listed by peers that opt in.
+#[derive(Debug, Default)]
+pub struct Directory {
+    listings: HashMap<String, (RoomListing, Instant)>,
+}
+
+impl Directory {
+    /// Adds or refreshes the listing of a room, replacing what any other peer listed it as.
+    pub fn list(&mut self, listing: RoomListing, now: Instant) {
+        self.listings.insert(listing.room.clone(), (listing, now));
+    }
+
+    /// Listings refreshed within [`LISTING_TTL`], busiest rooms first. Stale ones are dropped.
+    pub fn listings(&mut self, now: Instant) -> Vec<RoomListing> {
+        self.listings.retain(|_, (_, listed)| now.saturating_duration_since(*listed) <= LISTING_TTL);
+
+        let mut listings: Vec<_> = self.listings.values().map(|(listing, _)| listing.clone()).collect();
+        listings.sort_by(|a, b| b.participants.cmp(&a.participants).then_with(|| a.room.cmp(&b.room)));
+
+        listings
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn listing(room: &str, participants: u32) -> RoomListing {
+        RoomListing { room: room.to_string(), description: String::new(), participants }
+    }
+
+    #[test]
+    fn listings_expire() {
+        let mut directory = Directory::default();
+        let start = Instant::now();
+
+        directory.list(listing("quiet", 1), start);
+        directory.list(listing("busy", 5), start + Duration::from_secs(20));
+        assert_eq!(directory.listings(start + LISTING_TTL), vec![listing("busy", 5), listing("quiet", 1)]);
+
+        directory.list(listing("busy", 4), start + Duration::from_secs(25));
+        assert_eq!(directory.listings(start + LISTING_TTL + Duration::from_secs(1)), vec![listing("busy", 4)]);
+    }
+}
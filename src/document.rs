@@ -0,0 +1,472 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    str::FromStr
+};
+
+use crate::{
+    attribution::Attribution,
+    error::NotepadError,
+    notepad::Notepad,
+    whiteboard::Whiteboard
+};
+
+/// Id and name of the document every room starts with.
+pub const DEFAULT_DOCUMENT: &str = "main";
+
+/// Maximum length of a document name in bytes, names are length-prefixed by a single byte on the wire.
+pub const MAX_NAME_LEN: usize = u8::MAX as usize;
+
+/// Replicated description of a document. Messages route by `id`, which
+/// never changes, so renaming a document doesn't affect edits in flight.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DocumentMeta {
+    pub id: String,
+    pub name: String,
+    /// Archived documents are hidden and reject edits. The flag is kept as a
+    /// tombstone so peers that missed the archive don't bring the document back.
+    pub archived: bool,
+    pub line_ending: LineEnding,
+    pub settings: DocumentSettings,
+}
+
+impl DocumentMeta {
+    fn new(id: String, name: String) -> Self {
+        Self { id, name, archived: false, line_ending: LineEnding::default(), settings: DocumentSettings::default() }
+    }
+}
+
+/// How a document is laid out, replicated with its metadata so every
+/// collaborator renders it the same way.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DocumentSettings {
+    /// Language the text is written in, e.g. `rust` or `markdown`. Empty for plain text.
+    pub language: String,
+    /// Columns a tab expands to.
+    pub tab_width: u8,
+    /// Column long lines wrap at, if any.
+    pub wrap: Option<u16>,
+}
+
+impl Default for DocumentSettings {
+    fn default() -> Self {
+        Self { language: String::new(), tab_width: 4, wrap: None }
+    }
+}
+
+impl DocumentSettings {
+    /// Text with tabs expanded to spaces and long lines wrapped, for display only.
+    pub fn layout(&self, text: &str) -> String {
+        let tab_width = usize::from(self.tab_width.max(1));
+        let mut laid_out = String::with_capacity(text.len());
+
+        for (i, line) in text.split('\n').enumerate() {
+            if i > 0 {
+                laid_out.push('\n');
+            }
+
+            let mut column = 0;
+            for c in line.chars() {
+                let width = |column| if c == '\t' { tab_width - column % tab_width } else { 1 };
+
+                if self.wrap.is_some_and(|wrap| column > 0 && column + width(column) > usize::from(wrap)) {
+                    laid_out.push('\n');
+                    column = 0;
+                }
+
+                let width = width(column);
+                if c == '\t' {
+                    laid_out.extend(std::iter::repeat_n(' ', width));
+                } else {
+                    laid_out.push(c);
+                }
+                column += width;
+            }
+        }
+
+        laid_out
+    }
+}
+
+/// Line ending a document is written out with. Text is always held with `\n`
+/// line endings, so indices mean the same thing on every peer.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    Crlf,
+}
+
+impl LineEnding {
+    /// Converts text held with `\n` line endings to this line ending.
+    pub fn apply(self, text: &str) -> String {
+        match self {
+            LineEnding::Lf => text.to_string(),
+            LineEnding::Crlf => text.replace('\n', "\r\n"),
+        }
+    }
+
+    /// Converts `\r\n` and lone `\r` line endings to `\n`.
+    pub fn normalize(text: &str) -> String {
+        text.replace("\r\n", "\n").replace('\r', "\n")
+    }
+}
+
+impl FromStr for LineEnding {
+    type Err = NotepadError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lf" => Ok(LineEnding::Lf),
+            "crlf" => Ok(LineEnding::Crlf),
+            _ => Err(NotepadError::command(format!("Unknown line ending: {s:?}"))),
+        }
+    }
+}
+
+impl fmt::Display for LineEnding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LineEnding::Lf => write!(f, "lf"),
+            LineEnding::Crlf => write!(f, "crlf"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Document {
+    pub meta: DocumentMeta,
+    pub notepad: Notepad,
+}
+
+/// A state of a document labelled by name, to go back to later.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tag {
+    /// Id of the document tagged.
+    pub document: String,
+    pub text: String,
+}
+
+/// The documents of a room, keyed by id, and which one local edits target.
+#[derive(Debug)]
+pub struct Documents {
+    documents: BTreeMap<String, Document>,
+    active: String,
+    /// Whiteboards drawn on in the room, by name, see [`Whiteboard`].
+    pub boards: BTreeMap<String, Whiteboard>,
+    /// Who wrote the text of each document, by id, see [`Attribution`].
+    pub authors: BTreeMap<String, Attribution>,
+    /// States of the documents labelled with `tag`, by name.
+    pub tags: BTreeMap<String, Tag>,
+}
+
+impl Documents {
+    pub fn new(notepad: Notepad) -> Self {
+        let meta = DocumentMeta::new(DEFAULT_DOCUMENT.to_string(), DEFAULT_DOCUMENT.to_string());
+
+        Self {
+            documents: BTreeMap::from([(meta.id.clone(), Document { meta, notepad })]),
+            active: DEFAULT_DOCUMENT.to_string(),
+            boards: BTreeMap::new(),
+            authors: BTreeMap::new(),
+            tags: BTreeMap::new(),
+        }
+    }
+
+    pub fn active_meta(&self) -> &DocumentMeta {
+        &self.documents[&self.active].meta
+    }
+
+    pub fn active(&self) -> &Notepad {
+        &self.documents[&self.active].notepad
+    }
+
+    pub fn active_mut(&mut self) -> &mut Notepad {
+        &mut self.documents.get_mut(&self.active).expect("active document exists").notepad
+    }
+
+    /// Returns the document with `id`, creating it if a peer edited a document we haven't seen yet.
+    /// Until its metadata arrives the document is named after its id.
+    pub fn get_or_create(&mut self, id: &str) -> &mut Notepad {
+        &mut self.documents
+            .entry(id.to_string())
+            .or_insert_with(|| Document {
+                meta: DocumentMeta::new(id.to_string(), id.to_string()),
+                notepad: Notepad::default(),
+            })
+            .notepad
+    }
+
+    /// Creates an empty document with a fresh id and returns its metadata.
+    pub fn create(&mut self, name: &str) -> Result<DocumentMeta, NotepadError> {
+        validate_name(name)?;
+
+        if self.find(name).is_some() {
+            return Err(NotepadError::command(format!("Document already exists: `{name}`")));
+        }
+
+        let meta = DocumentMeta::new(format!("{:016x}", rand::random::<u64>()), name.to_string());
+
+        self.documents.insert(meta.id.clone(), Document { meta: meta.clone(), notepad: Notepad::default() });
+
+        Ok(meta)
+    }
+
+    /// Renames the active document and returns its updated metadata.
+    pub fn rename_active(&mut self, name: &str) -> Result<DocumentMeta, NotepadError> {
+        validate_name(name)?;
+
+        if self.find(name).is_some() {
+            return Err(NotepadError::command(format!("Document already exists: `{name}`")));
+        }
+
+        let document = self.documents.get_mut(&self.active).expect("active document exists");
+        document.meta.name = name.to_string();
+
+        Ok(document.meta.clone())
+    }
+
+    pub fn set_line_ending(&mut self, line_ending: LineEnding) -> DocumentMeta {
+        let document = self.documents.get_mut(&self.active).expect("active document exists");
+        document.meta.line_ending = line_ending;
+
+        document.meta.clone()
+    }
+
+    pub fn set_settings(&mut self, settings: DocumentSettings) -> DocumentMeta {
+        let document = self.documents.get_mut(&self.active).expect("active document exists");
+        document.meta.settings = settings;
+
+        document.meta.clone()
+    }
+
+    /// Archives a document by name and returns its updated metadata. The last
+    /// visible document can't be archived, since local edits need a target.
+    pub fn archive(&mut self, name: &str) -> Result<DocumentMeta, NotepadError> {
+        let id = self.find(name)
+            .ok_or_else(|| NotepadError::command(format!("Unknown document: `{name}`")))?
+            .id
+            .clone();
+
+        if self.visible().count() == 1 {
+            return Err(NotepadError::command(format!("Cannot archive the only document: `{name}`")));
+        }
+
+        let document = self.documents.get_mut(&id).expect("document was just found");
+        document.meta.archived = true;
+        let meta = document.meta.clone();
+
+        self.leave_archived();
+
+        Ok(meta)
+    }
+
+    /// Restores an archived document by name and returns its updated metadata.
+    pub fn restore(&mut self, name: &str) -> Result<DocumentMeta, NotepadError> {
+        if self.find(name).is_some() {
+            return Err(NotepadError::command(format!("Document already exists: `{name}`")));
+        }
+
+        let document = self.documents
+            .values_mut()
+            .find(|document| document.meta.archived && document.meta.name == name)
+            .ok_or_else(|| NotepadError::command(format!("Unknown archived document: `{name}`")))?;
+        document.meta.archived = false;
+
+        Ok(document.meta.clone())
+    }
+
+    pub fn is_archived(&self, id: &str) -> bool {
+        self.documents.get(id).is_some_and(|document| document.meta.archived)
+    }
+
+    /// Applies metadata received from a peer, returning the previous name if the document was renamed.
+    pub fn update_meta(&mut self, meta: DocumentMeta) -> Option<String> {
+        let renamed = match self.documents.get_mut(&meta.id) {
+            Some(document) => {
+                let previous = std::mem::replace(&mut document.meta, meta);
+                (previous.name != document.meta.name).then_some(previous.name)
+            },
+            None => {
+                self.documents.insert(meta.id.clone(), Document { meta, notepad: Notepad::default() });
+                None
+            }
+        };
+
+        self.leave_archived();
+
+        renamed
+    }
+
+    /// Switches the active document by name.
+    pub fn switch(&mut self, name: &str) -> Result<(), NotepadError> {
+        self.active = self.find(name)
+            .ok_or_else(|| NotepadError::command(format!("Unknown document: `{name}`")))?
+            .id
+            .clone();
+
+        Ok(())
+    }
+
+    /// All documents, including archived ones.
+    pub fn get(&self, id: &str) -> Option<&Document> {
+        self.documents.get(id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Document> {
+        self.documents.values()
+    }
+
+    /// Documents that aren't archived.
+    pub fn visible(&self) -> impl Iterator<Item = &Document> {
+        self.iter().filter(|document| !document.meta.archived)
+    }
+
+    /// Switches to the visible document after the active one, or before it,
+    /// in the order of their ids, wrapping around.
+    pub fn step(&mut self, forward: bool) -> &DocumentMeta {
+        let ids: Vec<_> = self.visible().map(|document| document.meta.id.clone()).collect();
+
+        if let Some(i) = ids.iter().position(|id| *id == self.active) {
+            let next = if forward { (i + 1) % ids.len() } else { (i + ids.len() - 1) % ids.len() };
+            self.active = ids[next].clone();
+        }
+
+        self.active_meta()
+    }
+
+    fn find(&self, name: &str) -> Option<&DocumentMeta> {
+        self.visible().map(|document| &document.meta).find(|meta| meta.name == name)
+    }
+
+    /// Moves off the active document if it was archived.
+    fn leave_archived(&mut self) {
+        if !self.is_archived(&self.active) {
+            return;
+        }
+
+        let next = self.visible().next().map(|document| document.meta.id.clone());
+
+        if let Some(id) = next {
+            self.active = id;
+        }
+    }
+}
+
+fn validate_name(name: &str) -> Result<(), NotepadError> {
+    if name.is_empty() || name.len() > MAX_NAME_LEN {
+        return Err(NotepadError::command("Document names must be between 1 and 255 bytes"));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn create_and_switch() {
+        let mut documents = Documents::new(Notepad::new("hello world".to_string()));
+
+        assert_eq!(documents.active_meta().name, DEFAULT_DOCUMENT);
+        assert!(documents.switch("todo").is_err());
+
+        let meta = documents.create("todo").unwrap();
+        assert_ne!(meta.id, meta.name);
+        assert!(documents.create("todo").is_err());
+        assert!(documents.create("").is_err());
+
+        documents.switch("todo").unwrap();
+        assert_eq!(documents.active_meta(), &meta);
+        assert_eq!(documents.active().text(), "");
+
+        let names: Vec<_> = documents.iter().map(|document| document.meta.name.as_str()).collect();
+        assert_eq!(names.len(), 2);
+        assert!(names.contains(&"todo"));
+    }
+
+    #[test]
+    fn remote_documents_are_created() {
+        let mut documents = Documents::new(Notepad::default());
+
+        documents.get_or_create("1234").set_text("a".to_string());
+
+        assert_eq!(documents.iter().count(), 2);
+        assert_eq!(documents.get_or_create("1234").text(), "a");
+        assert!(documents.switch("1234").is_ok());
+    }
+
+    #[test]
+    fn rename_keeps_id() {
+        let mut documents = Documents::new(Notepad::default());
+
+        let meta = documents.rename_active("notes").unwrap();
+        assert_eq!(meta, DocumentMeta::new(DEFAULT_DOCUMENT.to_string(), "notes".to_string()));
+        assert!(documents.switch(DEFAULT_DOCUMENT).is_err());
+
+        let previous = documents.update_meta(DocumentMeta::new(DEFAULT_DOCUMENT.to_string(), "minutes".to_string()));
+        assert_eq!(previous.as_deref(), Some("notes"));
+        assert_eq!(documents.update_meta(DocumentMeta::new("1234".to_string(), "new".to_string())), None);
+        documents.get_or_create(DEFAULT_DOCUMENT).set_text("a".to_string());
+
+        assert_eq!(documents.active_meta().name, "minutes");
+        assert_eq!(documents.active().text(), "a");
+        assert!(documents.switch("new").is_ok());
+    }
+
+    #[test]
+    fn archive_and_restore() {
+        let mut documents = Documents::new(Notepad::default());
+
+        assert!(documents.archive(DEFAULT_DOCUMENT).is_err());
+
+        let todo = documents.create("todo").unwrap();
+        let meta = documents.archive(DEFAULT_DOCUMENT).unwrap();
+
+        assert!(meta.archived);
+        assert!(documents.is_archived(DEFAULT_DOCUMENT));
+        assert_eq!(documents.active_meta(), &todo);
+        assert_eq!(documents.visible().count(), 1);
+        assert!(documents.switch(DEFAULT_DOCUMENT).is_err());
+
+        let meta = documents.restore(DEFAULT_DOCUMENT).unwrap();
+        assert!(!meta.archived);
+        assert!(documents.restore(DEFAULT_DOCUMENT).is_err());
+        assert!(documents.switch(DEFAULT_DOCUMENT).is_ok());
+    }
+
+    #[test]
+    fn remote_archive_leaves_active_document() {
+        let mut documents = Documents::new(Notepad::default());
+        let todo = documents.create("todo").unwrap();
+
+        documents.update_meta(DocumentMeta { archived: true, ..DocumentMeta::new(DEFAULT_DOCUMENT.to_string(), DEFAULT_DOCUMENT.to_string()) });
+
+        assert_eq!(documents.active_meta(), &todo);
+    }
+
+    #[test]
+    fn line_endings() {
+        assert_eq!(LineEnding::normalize("a\r\nb\rc\n"), "a\nb\nc\n");
+        assert_eq!(LineEnding::Crlf.apply("a\nb"), "a\r\nb");
+        assert_eq!(LineEnding::Lf.apply("a\nb"), "a\nb");
+        assert_eq!("crlf".parse::<LineEnding>().unwrap(), LineEnding::Crlf);
+
+        let mut documents = Documents::new(Notepad::default());
+        assert_eq!(documents.set_line_ending(LineEnding::Crlf).line_ending, LineEnding::Crlf);
+    }
+
+    #[test]
+    fn settings_layout() {
+        let settings = DocumentSettings { tab_width: 4, ..DocumentSettings::default() };
+        assert_eq!(settings.layout("a\tb\n\tc"), "a   b\n    c");
+
+        let settings = DocumentSettings { tab_width: 4, wrap: Some(6), ..DocumentSettings::default() };
+        assert_eq!(settings.layout("abcdefgh\nabcde\tf"), "abcdef\ngh\nabcde\n    f");
+
+        let mut documents = Documents::new(Notepad::default());
+        let settings = DocumentSettings { language: "rust".to_string(), ..DocumentSettings::default() };
+        assert_eq!(documents.set_settings(settings.clone()).settings, settings);
+    }
+}
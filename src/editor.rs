@@ -0,0 +1,377 @@
+use std::ops::Range;
+
+use ratatui::{
+    crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout},
+    style::{Color, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, Paragraph},
+    Frame
+};
+
+use crate::{
+    diff::{Diff, MessageBuf, Operation},
+    keymap::{Binding, Keymap}
+};
+
+/// What a keystroke asks of the engine.
+#[derive(Debug, PartialEq)]
+pub enum Action {
+    /// Diffs to apply to the active document and publish.
+    Edit(MessageBuf),
+    Undo,
+    Redo,
+    /// Show every room, see [`crate::dashboard::Dashboard`].
+    Dashboard,
+    /// Jump to the earliest remote edit not shown yet, see [`crate::unread::Unseen`].
+    CatchUp,
+    /// Switch to the next document, or the previous, see [`crate::document::Documents::step`].
+    NextDocument,
+    PreviousDocument,
+    Quit,
+}
+
+/// Full screen editor for the active document. Keystrokes are turned into
+/// diffs against the text at the cursor, which is a byte offset kept by the
+/// engine so remote edits before it move it along.
+#[derive(Debug, Default)]
+pub struct Editor {
+    keymap: Keymap,
+    /// Whether keys move and edit rather than type, with a modal keymap.
+    normal: bool,
+    /// First line shown, moved to keep the cursor on screen.
+    scroll: usize,
+    /// Lines of text shown at the last draw.
+    height: usize,
+    /// Where the selection started, kept while the cursor is moved with Shift held.
+    anchor: Option<usize>,
+    /// Shown under the text, e.g. why an edit was refused.
+    pub status: String,
+    /// Bytes of the text other peers selected, highlighted when drawn.
+    pub selections: Vec<Range<usize>>,
+}
+
+impl Editor {
+    pub fn new(keymap: Keymap) -> Self {
+        Self { normal: keymap.modal(), keymap, ..Self::default() }
+    }
+
+    pub fn keymap(&self) -> &Keymap {
+        &self.keymap
+    }
+
+    /// Takes a terminal event, moving `cursor` within `text` or returning
+    /// what it asks for. The cursor is moved past an edit before it is
+    /// applied, as it is made at the cursor.
+    pub fn event(&mut self, text: &str, cursor: &mut usize, event: Event) -> Option<Action> {
+        *cursor = clamp(text, *cursor);
+
+        match event {
+            Event::Key(pressed) if pressed.kind != KeyEventKind::Release => {
+                let selecting = pressed.modifiers.contains(KeyModifiers::SHIFT)
+                    && matches!(pressed.code, KeyCode::Left | KeyCode::Right | KeyCode::Up | KeyCode::Down | KeyCode::Home | KeyCode::End);
+                self.anchor = if selecting { Some(self.anchor.unwrap_or(*cursor)) } else { None };
+
+                self.key(text, cursor, pressed)
+            },
+            Event::Paste(pasted) => {
+                self.anchor = None;
+                let pasted = pasted.replace("\r\n", "\n").replace('\r', "\n");
+                let index = *cursor;
+                *cursor += pasted.len();
+                let diff = Diff { opcode: Operation::InsStr(pasted), operand: None, index };
+
+                Some(Action::Edit(MessageBuf { messages: vec![diff] }))
+            },
+            _ => None,
+        }
+    }
+
+    /// The text between where Shift was first held and `cursor`, if any.
+    pub fn selection(&self, cursor: usize) -> Option<Range<usize>> {
+        let anchor = self.anchor?;
+
+        Some(anchor.min(cursor)..anchor.max(cursor)).filter(|range| !range.is_empty())
+    }
+
+    /// Lines of text on screen as of the last draw.
+    pub fn visible(&self) -> Range<usize> {
+        self.scroll..self.scroll + self.height
+    }
+
+    /// Draws the text with `title` above it and the status below, scrolled
+    /// to show the cursor, with the selections highlighted: this peer's
+    /// reversed, others' on blue. Where the named `others` have theirs is shown
+    /// right of the status, and how many `unseen` edits are off screen
+    /// under the text.
+    pub fn draw(&mut self, frame: &mut Frame, title: &str, text: &str, cursor: usize, others: &[(String, usize)], unseen: &[usize]) {
+        let [body, footer] = Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+        let others = others
+            .iter()
+            .map(|(name, index)| {
+                let (line, column) = position(text, *index);
+                format!("{name} at {}:{}", line + 1, column + 1)
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        let width = u16::try_from(others.chars().count()).unwrap_or(u16::MAX);
+        let [status, others_area] = Layout::horizontal([Constraint::Min(0), Constraint::Length(width)]).areas(footer);
+        let mut block = Block::bordered().title(title);
+        let inner = block.inner(body);
+
+        let (line, column) = position(text, clamp(text, cursor));
+        let height = usize::from(inner.height).max(1);
+        if line < self.scroll {
+            self.scroll = line;
+        } else if line >= self.scroll + height {
+            self.scroll = line + 1 - height;
+        }
+        self.height = height;
+
+        let unseen_lines: Vec<_> = unseen.iter().map(|&index| position(text, index).0).collect();
+        let above = unseen_lines.iter().filter(|&&line| line < self.scroll).count();
+        let below = unseen_lines.iter().filter(|&&line| line >= self.scroll + height).count();
+        let catch_up = self.keymap.key(Binding::CatchUp).map_or(String::new(), |key| format!(", {key} to catch up"));
+        let marker = match (above, below) {
+            (0, 0) => None,
+            (above, 0) => Some(format!(" {above} unread {} above{catch_up} ", changes(above))),
+            (0, below) => Some(format!(" {below} unread {} below{catch_up} ", changes(below))),
+            (above, below) => Some(format!(" {above} unread above and {below} below{catch_up} ")),
+        };
+        if let Some(marker) = marker {
+            block = block.title_bottom(Line::from(marker).right_aligned());
+        }
+
+        let own = self.selection(cursor);
+        let mut start = 0;
+        let mut lines = Vec::with_capacity(height);
+        for (i, content) in text.split('\n').enumerate().take(self.scroll + height) {
+            if i >= self.scroll {
+                lines.push(highlight(text, start..start + content.len(), own.as_ref(), &self.selections));
+            }
+            start += content.len() + 1;
+        }
+        frame.render_widget(Paragraph::new(lines).block(block), body);
+        let mode = if self.keymap.modal() && !self.normal { "-- INSERT -- " } else { "" };
+        frame.render_widget(Paragraph::new(format!("{mode}{}", self.status)), status);
+        frame.render_widget(Paragraph::new(others), others_area);
+
+        let x = inner.x.saturating_add(u16::try_from(column).unwrap_or(u16::MAX)).min(inner.right().saturating_sub(1));
+        frame.set_cursor_position((x, inner.y + (line - self.scroll) as u16));
+    }
+
+    /// Takes a keystroke, see [`Editor::event`]. Unbound characters are
+    /// typed, unless in normal mode or held with Ctrl.
+    fn key(&mut self, text: &str, cursor: &mut usize, key: KeyEvent) -> Option<Action> {
+        let (line, column) = position(text, *cursor);
+
+        match self.keymap.binding(key, self.normal) {
+            Some(Binding::Quit) => return Some(Action::Quit),
+            Some(Binding::Undo) => return Some(Action::Undo),
+            Some(Binding::Redo) => return Some(Action::Redo),
+            Some(Binding::Dashboard) => return Some(Action::Dashboard),
+            Some(Binding::CatchUp) => return Some(Action::CatchUp),
+            Some(Binding::NextDocument) => return Some(Action::NextDocument),
+            Some(Binding::PreviousDocument) => return Some(Action::PreviousDocument),
+            Some(Binding::Newline) => return Some(insert(cursor, '\n')),
+            Some(Binding::DeleteBack) if *cursor > 0 => {
+                *cursor = previous(text, *cursor);
+                return Some(delete(*cursor));
+            },
+            Some(Binding::DeleteForward) if *cursor < text.len() => return Some(delete(*cursor)),
+            Some(Binding::Left) if *cursor > 0 => *cursor = previous(text, *cursor),
+            Some(Binding::Right) if *cursor < text.len() => *cursor = next(text, *cursor),
+            Some(Binding::Up) if line > 0 => *cursor = index(text, line - 1, column),
+            Some(Binding::Down) => *cursor = index(text, line + 1, column),
+            Some(Binding::LineStart) => *cursor = index(text, line, 0),
+            Some(Binding::LineEnd) => *cursor = index(text, line, usize::MAX),
+            Some(Binding::DocumentStart) => *cursor = 0,
+            Some(Binding::DocumentEnd) => *cursor = text.len(),
+            Some(Binding::InsertMode) => self.normal = false,
+            Some(Binding::Append) => {
+                *cursor = index(text, line, column + 1);
+                self.normal = false;
+            },
+            Some(Binding::NormalMode) => self.normal = self.keymap.modal(),
+            Some(_) => {},
+            None if self.normal || key.modifiers.contains(KeyModifiers::CONTROL) => {},
+            None => if let KeyCode::Char(c) = key.code {
+                return Some(insert(cursor, c));
+            },
+        }
+
+        None
+    }
+}
+
+/// The line at bytes `line` of `text`, split where selections start and end
+/// so they can be highlighted.
+fn highlight<'a>(text: &'a str, line: Range<usize>, own: Option<&Range<usize>>, others: &[Range<usize>]) -> Line<'a> {
+    let overlaps = |range: &&Range<usize>| range.start < line.end && range.end > line.start;
+    let mut cuts: Vec<usize> = own
+        .iter()
+        .copied()
+        .chain(others)
+        .filter(overlaps)
+        .flat_map(|range| [range.start, range.end])
+        .map(|cut| clamp(text, cut.clamp(line.start, line.end)))
+        .chain([line.start, line.end])
+        .collect();
+    cuts.sort_unstable();
+    cuts.dedup();
+
+    let spans: Vec<_> = cuts
+        .windows(2)
+        .map(|cut| {
+            let (start, end) = (cut[0], cut[1]);
+            let within = |range: &Range<usize>| range.start <= start && end <= range.end;
+            let style = if own.is_some_and(within) {
+                Style::new().reversed()
+            } else if others.iter().any(within) {
+                Style::new().bg(Color::Blue)
+            } else {
+                Style::new()
+            };
+
+            Span::styled(&text[start..end], style)
+        })
+        .collect();
+
+    Line::from(spans)
+}
+
+fn changes(count: usize) -> &'static str {
+    if count == 1 { "change" } else { "changes" }
+}
+
+fn insert(cursor: &mut usize, c: char) -> Action {
+    let diff = Diff { opcode: Operation::Ins, operand: Some(c), index: *cursor };
+    *cursor += c.len_utf8();
+
+    Action::Edit(MessageBuf { messages: vec![diff] })
+}
+
+fn delete(index: usize) -> Action {
+    Action::Edit(MessageBuf { messages: vec![Diff { opcode: Operation::Del, operand: None, index }] })
+}
+
+/// `cursor` kept within `text` and on a character boundary.
+fn clamp(text: &str, cursor: usize) -> usize {
+    (0..=cursor.min(text.len())).rev().find(|&i| text.is_char_boundary(i)).unwrap_or(0)
+}
+
+fn previous(text: &str, cursor: usize) -> usize {
+    text[..cursor].char_indices().next_back().map_or(0, |(i, _)| i)
+}
+
+fn next(text: &str, cursor: usize) -> usize {
+    text[cursor..].chars().next().map_or(cursor, |c| cursor + c.len_utf8())
+}
+
+/// Line and column, in characters, of the cursor, both from 0.
+pub fn position(text: &str, cursor: usize) -> (usize, usize) {
+    let before = &text[..clamp(text, cursor)];
+    let start = before.rfind('\n').map_or(0, |i| i + 1);
+
+    (before.matches('\n').count(), before[start..].chars().count())
+}
+
+/// Offset of a column of a line, or the end of the line if it is shorter,
+/// or of the text if there are fewer lines.
+fn index(text: &str, line: usize, column: usize) -> usize {
+    let mut start = 0;
+
+    for (i, content) in text.split('\n').enumerate() {
+        if i == line {
+            return start + content.char_indices().nth(column).map_or(content.len(), |(offset, _)| offset);
+        }
+
+        start += content.len() + 1;
+    }
+
+    text.len()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{keymap::Preset, notepad::Notepad};
+
+    fn press(editor: &mut Editor, notepad: &mut Notepad, cursor: &mut usize, code: KeyCode) -> Option<Action> {
+        match editor.event(notepad.text(), cursor, Event::Key(KeyEvent::from(code))) {
+            Some(Action::Edit(diffs)) => {
+                notepad.apply_message_buf(&diffs).unwrap();
+                None
+            },
+            action => action,
+        }
+    }
+
+    #[test]
+    fn keystrokes_edit_at_the_cursor() {
+        let mut editor = Editor::default();
+        let mut notepad = Notepad::new("hé\nworld".to_string());
+        let mut cursor = 0;
+
+        for code in [KeyCode::End, KeyCode::Char('!'), KeyCode::Down, KeyCode::Backspace, KeyCode::Home, KeyCode::Delete, KeyCode::Char('W')] {
+            press(&mut editor, &mut notepad, &mut cursor, code);
+        }
+        assert_eq!(notepad.text(), "hé!\nWold");
+        assert_eq!(position(notepad.text(), cursor), (1, 1));
+
+        press(&mut editor, &mut notepad, &mut cursor, KeyCode::Up);
+        press(&mut editor, &mut notepad, &mut cursor, KeyCode::Right);
+        press(&mut editor, &mut notepad, &mut cursor, KeyCode::Enter);
+        assert_eq!(notepad.text(), "hé\n!\nWold");
+
+        // Backspace at the start of a line joins it to the one before.
+        press(&mut editor, &mut notepad, &mut cursor, KeyCode::Backspace);
+        assert_eq!(notepad.text(), "hé!\nWold");
+        assert_eq!(cursor, "hé".len());
+
+        assert_eq!(press(&mut editor, &mut notepad, &mut cursor, KeyCode::Esc), Some(Action::Quit));
+        let ctrl_z = KeyEvent::new(KeyCode::Char('z'), KeyModifiers::CONTROL);
+        assert_eq!(editor.event(notepad.text(), &mut cursor, Event::Key(ctrl_z)), Some(Action::Undo));
+
+        let pasted = Event::Paste("a\r\nb".to_string());
+        let Some(Action::Edit(diffs)) = editor.event(notepad.text(), &mut cursor, pasted) else { panic!("paste isn't an edit") };
+        notepad.apply_message_buf(&diffs).unwrap();
+        assert_eq!((&notepad.text()[..cursor], &notepad.text()[cursor..]), ("héa\nb", "!\nWold"));
+    }
+
+    #[test]
+    fn vim_keys_edit_in_normal_mode() {
+        let mut editor = Editor::new(Keymap::new(Preset::Vim));
+        let mut notepad = Notepad::new("hello\nworld".to_string());
+        let mut cursor = 0;
+
+        for code in [KeyCode::Char('j'), KeyCode::Char('x'), KeyCode::Char('$'), KeyCode::Char('i'), KeyCode::Char('!'), KeyCode::Esc, KeyCode::Char('0'), KeyCode::Char('a'), KeyCode::Char('o')] {
+            press(&mut editor, &mut notepad, &mut cursor, code);
+        }
+        assert_eq!(notepad.text(), "hello\noorld!");
+
+        press(&mut editor, &mut notepad, &mut cursor, KeyCode::Esc);
+        assert_eq!(press(&mut editor, &mut notepad, &mut cursor, KeyCode::Char('u')), Some(Action::Undo));
+        assert_eq!(press(&mut editor, &mut notepad, &mut cursor, KeyCode::Char(']')), Some(Action::NextDocument));
+    }
+
+    #[test]
+    fn shift_selects() {
+        let mut editor = Editor::default();
+        let text = "hello\nworld";
+        let mut cursor = 2;
+        let shift = |code| Event::Key(KeyEvent::new(code, KeyModifiers::SHIFT));
+
+        editor.event(text, &mut cursor, shift(KeyCode::End));
+        editor.event(text, &mut cursor, shift(KeyCode::Down));
+        assert_eq!(editor.selection(cursor), Some(2..11));
+
+        editor.event(text, &mut cursor, Event::Key(KeyEvent::from(KeyCode::Left)));
+        assert_eq!(editor.selection(cursor), None);
+
+        let line = highlight(text, 0..5, Some(&(2..4)), &[3..8, 9..11]);
+        let spans: Vec<_> = line.spans.iter().map(|span| span.content.as_ref()).collect();
+        assert_eq!(spans, ["he", "l", "l", "o"]);
+        assert_eq!(line.spans[3].style.bg, Some(Color::Blue));
+    }
+}
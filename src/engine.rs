@@ -0,0 +1,4679 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    ops::Range,
+    time::{Duration, Instant, SystemTime}
+};
+
+use libp2p::PeerId;
+
+use crate::{
+    access::Access,
+    activity::Activity,
+    attachment::{self, AttachmentMeta, Attachments},
+    backup::{BackupTarget, Backups},
+    batch::EditBatch,
+    causal::Reorder,
+    crdt::{Op, Sequence},
+    conflict::{self, Conflicts},
+    cursors::{self, Cursors, CURSOR_INTERVAL},
+    dashboard::{Health, Rooms, Summary},
+    delivery::{Delivery, RecentEdits},
+    describe,
+    editor,
+    directory::{Directory, RoomListing},
+    diff::{self, Diff, MessageBuf, Operation, CHUNK_LEN},
+    document::{self, Documents, LineEnding, Tag},
+    error::NotepadError,
+    archive::Archive,
+    backfill::{Backlog, Missed},
+    history::History,
+    journal::Journal,
+    latency::Latency,
+    lines,
+    lock::{Claim, Claims, Locks},
+    manifest::{self, Admission, RoomManifest},
+    matrix,
+    memory::{self, MemoryUsage},
+    merge,
+    message::{self, Message, Presence, Reading, Selection, Snapshot},
+    sanitize::ControlChars,
+    seal::RoomKey,
+    notepad::Notepad,
+    oplog::{self, OpLog, Record},
+    output,
+    pacing::Pacer,
+    patch,
+    partition::Partition,
+    presence::{self, Peers, PRESENCE_INTERVAL},
+    retry::{Outbox, RetryPolicy},
+    room::{RoomPrefs, RoomSettings},
+    session::{Session, SessionSummary},
+    sources::Sources,
+    storage::Storage,
+    transport::{Event, Incoming, Transport},
+    unread::Unseen,
+    users::Users,
+    vacancy::Vacancy,
+    watch::Watch,
+    whiteboard::BoardOp,
+    workspace::Workspace
+};
+
+/// Least time between snapshot requests made because a snapshot arrived corrupted.
+pub const SNAPSHOT_RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long edits are held back waiting for the answer to a sync request
+/// before they are applied anyway.
+pub const SYNC_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often a digest of the active document is published in the
+/// background, for peers whose copies stay apart to repair them.
+pub const REPAIR_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Digests in a row from a peer that don't match before this peer catches
+/// up from it, as a single one may only miss edits on their way.
+const REPAIR_MISMATCHES: u32 = 2;
+
+/// Most diffs of a peer's edit applied at once. Larger edits, such as a
+/// file a peer imported, are applied a chunk at a time between the node's
+/// other work, see [`Engine::apply_chunk`]. A fraction of [`CHUNK_LEN`], as
+/// peers already send large edits in messages of that many diffs.
+pub const REMOTE_CHUNK: usize = CHUNK_LEN / 4;
+
+/// Edits to a document, with the user that signed them when the peer that
+/// published them is shared by several, see [`Users`].
+type Edit = (String, MessageBuf, Option<PeerId>);
+
+/// An edit from a peer, with the number the peer gave it.
+type NumberedEdit = (PeerId, u64, Edit);
+
+/// The room's documents and the sync logic around them, independent of how
+/// messages travel between peers.
+#[derive(Debug)]
+pub struct Engine {
+    pub documents: Documents,
+    /// Documents of the other rooms joined, by topic, kept until they are joined again.
+    parked: HashMap<String, Documents>,
+    /// Rooms followed for notifications without keeping their documents, by topic, see [`Engine::watch`].
+    pub watches: HashMap<String, Watch>,
+    /// Unread edits and recent peers of every room, see [`Engine::rooms`].
+    pub dashboard: Rooms,
+    /// Delivery of the recent local edits, and which peers applied them if
+    /// [`Engine::acknowledge`] is set.
+    pub recent_edits: RecentEdits,
+    /// Last text shared to the room clipboard and who shared it.
+    pub clipboard: Option<(Option<PeerId>, String)>,
+    /// Chat heard from peers, waiting to be posted to the Matrix room chat
+    /// is bridged with, see [`crate::matrix`]. `None` unless bridging.
+    pub bridged: Option<Vec<String>>,
+    pub peers: Peers,
+    pub conflicts: Conflicts,
+    pub latency: Latency,
+    /// Operations per peer over time, see `graph`.
+    pub activity: Activity,
+    pub attachments: Attachments,
+    /// Preferences of every room joined, see [`Engine::prefs_mut`].
+    pub rooms: RoomSettings,
+    /// Whether control characters in incoming text are kept, stripped or
+    /// escaped before it reaches a document, see [`ControlChars`].
+    pub control_chars: ControlChars,
+    /// Describe every remote change in words instead of periodically redrawing the document.
+    pub plain_output: bool,
+    /// Whether this peer hosts the room and answers snapshot requests.
+    pub host: bool,
+    /// Bytes of state to stay under, see [`Engine::enforce_memory_budget`].
+    pub memory_budget: Option<usize>,
+    /// Bytes no document may grow past, see [`Engine::check_size`].
+    pub max_document_size: Option<usize>,
+    /// Rooms listed with this peer, if it serves a directory.
+    pub directory: Option<Directory>,
+    /// Peer serving the directory that `rooms` asks and rooms are listed with.
+    pub directory_peer: Option<PeerId>,
+    /// Description the current room is listed with, see [`Engine::list_room`].
+    listing: Option<String>,
+    /// Requirements of the current room, with the peer that created it or
+    /// `None` if this one did, see [`Engine::create_room`].
+    manifest: Option<(Option<PeerId>, RoomManifest)>,
+    /// How the peers that acknowledged the manifest take part in the room.
+    admissions: HashMap<PeerId, Admission>,
+    /// Write access the room's creator granted or revoked, by peer, see [`Engine::permit`].
+    permissions: BTreeMap<PeerId, bool>,
+    /// Number of the last edit published, see [`Reorder`].
+    seq: u64,
+    /// Edits from peers held back until the ones they follow arrive.
+    pub reorder: Reorder<Edit>,
+    /// Whether this peer has caught up on the room from another peer since joining.
+    synced: bool,
+    /// While waiting for the answer to a sync request, when it was sent and
+    /// the numbered edits held back until the documents arrive.
+    sync: Option<(Instant, Vec<NumberedEdit>)>,
+    /// Peers in the room connected to directly, learnt from their
+    /// subscriptions, as sync requests can't reach the others.
+    neighbours: HashSet<PeerId>,
+    /// Peers to catch up from, best first, see [`Sources`].
+    sources: Sources,
+    /// Text of the documents edited while waiting for a sync, as it was
+    /// before, to merge the edits made meanwhile into those caught up on.
+    sync_base: HashMap<String, String>,
+    /// Whether an edit from a peer didn't fit this peer's copy of a document
+    /// since joining or last catching up, so the copies diverged.
+    pub diverged: bool,
+    /// Peer whose edit didn't fit, to catch up from, see [`Engine::resync`].
+    resync: Option<PeerId>,
+    /// When a digest was last published, see [`REPAIR_INTERVAL`].
+    last_digest: Option<Instant>,
+    /// Digests in a row that didn't match this peer's copy, by peer, see [`Engine::receive_digest`].
+    mismatches: HashMap<PeerId, u32>,
+    /// Time-boxed session in progress, see [`Engine::end_session`].
+    pub session: Option<Session>,
+    /// People sharing this node, whose edits are signed as theirs.
+    pub users: Users,
+    /// Local edits that can be undone and redone, see [`Engine::undo`].
+    history: History,
+    /// Every operation applied to the room's documents, for `hist`.
+    pub journal: Journal,
+    /// Edits lately applied, to send peers that missed some, see [`Backlog`].
+    backlog: Backlog,
+    /// Local edits peers may have missed, merged in after losing every peer, see [`Partition`].
+    partition: Partition,
+    /// How long payloads held in the [`Outbox`] wait between attempts, and
+    /// how many they get.
+    pub retry: RetryPolicy,
+    /// Local edits waiting to be published together, see [`Engine::flush_edits`].
+    pub batch: EditBatch,
+    /// Payloads waiting to be published, see [`Engine::flush_outbox`].
+    outbox: Outbox,
+    /// Payloads still waiting to be published to the other rooms joined, by
+    /// topic, tried again once the room is joined again.
+    outboxes: HashMap<String, Outbox>,
+    /// Bulk messages waiting to be published, see [`Engine::flush_bulk`].
+    bulk: Pacer<Message>,
+    /// Chunks of peers' edits waiting to be applied, in order, see [`REMOTE_CHUNK`].
+    chunks: VecDeque<(Option<PeerId>, Edit)>,
+    /// Log of the current room, while the directory is set.
+    oplog: Option<OpLog>,
+    /// Bytes appended to the operation log before it is compacted, see [`Engine::compact_oplog`].
+    pub oplog_compact: Option<usize>,
+    /// Peer the current room's operation log is backed up to, see [`Engine::restore_backup`].
+    pub backup: Option<BackupTarget>,
+    /// Operation logs other peers back up here.
+    pub backups: Option<Backups>,
+    /// Operations applied to any document since the last snapshot was published.
+    pub ops_since_snapshot: usize,
+    /// When snapshots were last requested after a corrupted one, see [`SNAPSHOT_RETRY_INTERVAL`].
+    last_snapshot_request: Option<Instant>,
+    /// Remote operations applied to the active document since it was last rendered, unless output is plain.
+    pub ops_since_render: usize,
+    /// Byte offset of the editor's cursor in the active document, moved past
+    /// remote edits before it. `None` unless the editor is running.
+    pub cursor: Option<usize>,
+    /// The cursor as last published, with when, see [`Engine::publish_cursor`].
+    cursor_sent: Option<(String, usize, Instant)>,
+    /// Where the other peers' cursors are.
+    pub cursors: Cursors,
+    /// Documents locked for turn-taking, see [`Engine::lock`].
+    pub locks: Locks,
+    /// Lines peers said they are editing, see [`Engine::claim`].
+    pub claims: Claims,
+    /// Lines of the active document the editor last showed, announced in
+    /// presence. `None` unless the editor is running.
+    reading: Option<Range<usize>>,
+    /// Bytes of the active document selected for peers to look at, see [`Engine::select`].
+    selection: Option<Range<usize>>,
+    /// Remote edits to the active document not shown by the editor yet.
+    pub unseen: Unseen,
+    /// Whether every other peer left the room, to save it if the user is idle too.
+    pub vacancy: Vacancy,
+    /// Peers listened to, in every room.
+    pub access: Access,
+    /// Only follow rooms, refusing local edits and drawing in every one of
+    /// them, to project a document or mirror it elsewhere.
+    pub observe: bool,
+    /// Acknowledge the edits applied to their authors with [`Message::Applied`],
+    /// and follow which peers applied this peer's own, see [`Engine::recent_edits`].
+    pub acknowledge: bool,
+    /// Publish digests of the active document in the background and catch up
+    /// from peers whose copies stay apart, see [`REPAIR_INTERVAL`].
+    pub repair: bool,
+    /// Publish edits as ops of a sequence CRDT rather than diffs, so copies
+    /// edited concurrently converge, see [`crate::crdt`]. Rooms created with
+    /// it require it, see [`manifest::CRDT`], so peers without it follow them read-only.
+    pub crdt: bool,
+    /// Sequence of every document edited or synced with the CRDT, by id.
+    sequences: HashMap<String, Sequence>,
+    /// Keys of the private rooms joined or watched, by topic, see [`RoomKey`].
+    keys: HashMap<String, RoomKey>,
+    room: String,
+    topic_prefix: String,
+    /// Gossipsub topic of the room, see [`room_topic`].
+    topic: String,
+}
+
+impl Engine {
+    pub fn new(room: &str, topic_prefix: &str, notepad: Notepad) -> Self {
+        Self {
+            documents: Documents::new(notepad),
+            parked: HashMap::new(),
+            watches: HashMap::new(),
+            dashboard: Rooms::default(),
+            recent_edits: RecentEdits::default(),
+            clipboard: None,
+            bridged: None,
+            peers: Peers::default(),
+            conflicts: Conflicts::default(),
+            latency: Latency::default(),
+            activity: Activity::new(Instant::now()),
+            attachments: Attachments::default(),
+            rooms: RoomSettings::default(),
+            control_chars: ControlChars::default(),
+            plain_output: false,
+            host: false,
+            memory_budget: None,
+            max_document_size: None,
+            directory: None,
+            directory_peer: None,
+            listing: None,
+            manifest: None,
+            admissions: HashMap::new(),
+            permissions: BTreeMap::new(),
+            seq: 0,
+            reorder: Reorder::default(),
+            synced: false,
+            sync: None,
+            sync_base: HashMap::new(),
+            neighbours: HashSet::new(),
+            sources: Sources::default(),
+            diverged: false,
+            resync: None,
+            last_digest: None,
+            mismatches: HashMap::new(),
+            session: None,
+            users: Users::default(),
+            history: History::default(),
+            journal: Journal::default(),
+            backlog: Backlog::default(),
+            partition: Partition::default(),
+            retry: RetryPolicy::default(),
+            batch: EditBatch::default(),
+            outbox: Outbox::default(),
+            outboxes: HashMap::new(),
+            bulk: Pacer::default(),
+            chunks: VecDeque::new(),
+            oplog: None,
+            oplog_compact: None,
+            backup: None,
+            backups: None,
+            ops_since_snapshot: 0,
+            last_snapshot_request: None,
+            ops_since_render: 0,
+            cursor: None,
+            cursor_sent: None,
+            cursors: Cursors::default(),
+            locks: Locks::default(),
+            claims: Claims::default(),
+            reading: None,
+            selection: None,
+            unseen: Unseen::default(),
+            vacancy: Vacancy::default(),
+            access: Access::default(),
+            observe: false,
+            acknowledge: false,
+            repair: false,
+            crdt: false,
+            sequences: HashMap::new(),
+            keys: HashMap::new(),
+            room: room.to_string(),
+            topic_prefix: topic_prefix.to_string(),
+            topic: room_topic(topic_prefix, room, None),
+        }
+    }
+
+    pub fn room(&self) -> &str {
+        &self.room
+    }
+
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    /// Key of the current room if it is private, see [`RoomKey`].
+    pub fn room_key(&self) -> Option<&RoomKey> {
+        self.keys.get(&self.topic)
+    }
+
+    /// Leaves the current room and joins `room`, privately if it has a passphrase.
+    pub fn switch_room(&mut self, transport: &mut impl Transport, room: &str, passphrase: Option<&str>) -> Result<(), NotepadError> {
+        let key = passphrase.map(|passphrase| RoomKey::derive(room, passphrase));
+        let topic = room_topic(&self.topic_prefix, room, key.as_ref());
+        if let Some(key) = key {
+            self.keys.insert(topic.clone(), key);
+        }
+
+        self.join(transport, room, topic)
+    }
+
+    /// Joins `room` as its creator and publishes its manifest, refreshed with
+    /// every heartbeat: a passphrase is required if it was given one, and
+    /// documents may not outgrow this peer's memory budget. The room starts
+    /// from `template` if one is given, its hash in the manifest so every
+    /// peer can tell the snapshot that follows is the one the room was made with.
+    ///
+    /// The room joined is named `<room>@<peer id>` after this peer, so no
+    /// other peer can publish its manifest or permissions.
+    pub fn create_room(&mut self, transport: &mut impl Transport, room: &str, passphrase: Option<&str>, template: Option<&str>) -> Result<RoomManifest, NotepadError> {
+        let template = template.map(LineEnding::normalize);
+        if let Some(budget) = self.memory_budget.filter(|&budget| template.as_ref().is_some_and(|text| text.len() > budget)) {
+            return Err(NotepadError::command(format!("The template exceeds the memory budget of {}", memory::bytes(budget))));
+        }
+
+        let room = match owner(room) {
+            Some(owner) if owner == transport.peer_id() => room.to_string(),
+            _ => format!("{room}@{}", transport.peer_id()),
+        };
+        self.switch_room(transport, &room, passphrase)?;
+
+        let manifest = RoomManifest {
+            flags: (if passphrase.is_some() { manifest::PRIVATE } else { 0 }) | (if self.crdt { manifest::CRDT } else { 0 }),
+            max_document_size: self.max_document_size.or(self.memory_budget).map(|max| max.try_into().unwrap_or(u32::MAX)),
+            genesis: template.as_ref().map(|text| *blake3::hash(text.as_bytes()).as_bytes()),
+        };
+        self.manifest = Some((None, manifest));
+        self.publish(transport, Message::Manifest(manifest));
+
+        if let Some(text) = template {
+            self.documents.active_mut().set_text(text.clone());
+            self.log_text(&self.documents.active_meta().id.clone());
+            self.publish_bulk(transport, Message::Snapshot(Snapshot { document: self.documents.active_meta().id.clone(), text }));
+        }
+
+        Ok(manifest)
+    }
+
+    /// Grants `peer_id` write access to the room, or revokes it, which every
+    /// peer follows as long as this one created the room. Refreshed with
+    /// every heartbeat, like the manifest.
+    pub fn permit(&mut self, transport: &mut impl Transport, peer_id: PeerId, write: bool) -> Result<(), NotepadError> {
+        if owner(&self.room) != Some(transport.peer_id()) {
+            return Err(NotepadError::command("Only the peer that created the room with `room create` grants write access"));
+        }
+
+        self.permissions.insert(peer_id, write);
+        self.publish(transport, Message::Permission { peer: peer_id, write });
+
+        Ok(())
+    }
+
+    /// Follows write access granted or revoked by the room's creator, the
+    /// only peer whose permissions are trusted: the room is named after its
+    /// key, see [`owner`], and gossipsub signs every message with the
+    /// publishing peer's key.
+    fn receive_permission(&mut self, transport: &impl Transport, source: Option<PeerId>, peer_id: PeerId, write: bool) {
+        let Some(source) = source else {
+            return;
+        };
+
+        let creator = match owner(&self.room) {
+            Some(creator) if creator == source => creator,
+            _ => return println!("Ignored a permission from {}, only the room's creator grants them", self.peers.display_name(&source)),
+        };
+        if self.permissions.insert(peer_id, write) == Some(write) {
+            return;
+        }
+
+        let granted = if write { "granted" } else { "revoked" };
+        if peer_id == transport.peer_id() {
+            self.prefs_mut().read_only = !write;
+            println!("{} {granted} this peer write access to room `{}`", self.peers.display_name(&creator), self.room);
+        } else if !self.quiet() {
+            println!("{} {granted} {} write access", self.peers.display_name(&creator), self.peers.display_name(&peer_id));
+        }
+    }
+
+    /// Why edits from `source` are dropped, if they are.
+    fn refuses_edits(&self, source: Option<PeerId>) -> Option<(PeerId, &'static str)> {
+        let peer_id = source?;
+
+        if self.admissions.get(&peer_id).is_some_and(|admission| *admission != Admission::Full) {
+            Some((peer_id, "which takes part read-only"))
+        } else if self.permissions.get(&peer_id) == Some(&false) {
+            Some((peer_id, "whose write access the room's creator revoked"))
+        } else {
+            None
+        }
+    }
+
+    /// Requirements of the current room, if its creator published them.
+    pub fn manifest(&self) -> Option<RoomManifest> {
+        self.manifest.map(|(_, manifest)| manifest)
+    }
+
+    /// Takes the manifest published for the room by the peer that created
+    /// it, the one the room is named after, see [`owner`], acknowledging it
+    /// and following what it allows.
+    fn receive_manifest(&mut self, transport: &mut impl Transport, source: Option<PeerId>, manifest: RoomManifest) {
+        let Some(peer_id) = source else {
+            return;
+        };
+        let name = self.peers.display_name(&peer_id);
+
+        if owner(&self.room) != Some(peer_id) {
+            return println!("Ignored a room manifest from {name}, only the peer room `{}` is named after publishes one", self.room);
+        }
+        if self.manifest == Some((Some(peer_id), manifest)) {
+            return;
+        }
+
+        let private = self.topic != room_topic(&self.topic_prefix, &self.room, None);
+        let largest = self.documents.iter().map(|document| document.notepad.len()).max().unwrap_or(0);
+        let (admission, reason) = manifest.admit(private, self.crdt, largest);
+
+        self.manifest = Some((Some(peer_id), manifest));
+        self.publish(transport, Message::ManifestAck(admission));
+        println!("Room `{}` was created by {name} and {manifest}", self.room);
+
+        match (admission, reason) {
+            (Admission::ReadOnly, Some(reason)) => {
+                self.prefs_mut().read_only = true;
+                println!("Joined read-only: {reason}");
+            },
+            (Admission::Refused, Some(reason)) => {
+                println!("Left the room: {reason}, switch rooms with `swi`");
+
+                if let Err(e) = transport.unsubscribe(&self.topic) {
+                    println!("{e}");
+                }
+            },
+            _ => {},
+        }
+    }
+
+    /// Follows `room` without joining it, notifying when text matching one of
+    /// `patterns` is inserted or changes, see [`Watch`].
+    pub fn watch(&mut self, transport: &mut impl Transport, room: &str, passphrase: Option<&str>, patterns: Vec<String>) -> Result<(), NotepadError> {
+        if patterns.is_empty() {
+            return Err(NotepadError::command("Give at least one pattern to watch for"));
+        }
+
+        let key = passphrase.map(|passphrase| RoomKey::derive(room, passphrase));
+        let topic = room_topic(&self.topic_prefix, room, key.as_ref());
+        if topic != self.topic {
+            transport.subscribe(&topic)?;
+        }
+        if let Some(key) = key {
+            self.keys.insert(topic.clone(), key);
+        }
+
+        self.watches.insert(topic, Watch::new(room, patterns));
+
+        Ok(())
+    }
+
+    /// Stops watching `room`, leaving it unless it is the current room.
+    pub fn unwatch(&mut self, transport: &mut impl Transport, room: &str) -> Result<(), NotepadError> {
+        let topic = self.watches
+            .iter()
+            .find(|(_, watch)| watch.room == room)
+            .map(|(topic, _)| topic.clone())
+            .ok_or_else(|| NotepadError::command(format!("Room `{room}` isn't watched")))?;
+
+        self.watches.remove(&topic);
+
+        if topic != self.topic {
+            transport.unsubscribe(&topic)?;
+        }
+
+        Ok(())
+    }
+
+    /// Leaves every room before the node exits: publishes the edits held in
+    /// the batch, tries what is waiting in the outbox once more and
+    /// unsubscribes, returning the number of payloads that still couldn't be
+    /// published to any room.
+    pub fn leave(&mut self, transport: &mut impl Transport) -> usize {
+        self.publish_batch(transport);
+        self.release_locks(transport);
+
+        let now = Instant::now();
+        self.outbox.wake(now);
+        self.flush_outbox(transport, now);
+
+        let topics = [self.topic.clone(), self.cursor_topic()].into_iter().chain(self.watches.keys().cloned());
+        for topic in topics.collect::<HashSet<_>>() {
+            if let Err(e) = transport.unsubscribe(&topic) {
+                println!("{e}");
+            }
+        }
+
+        self.outbox.len() + self.outboxes.values().map(Outbox::len).sum::<usize>()
+    }
+
+    /// Restores a saved session: its documents, the active one and the
+    /// nickname, then rejoins its room and asks the host to catch us up.
+    pub fn restore(&mut self, transport: &mut impl Transport, workspace: Workspace) -> Result<(), NotepadError> {
+        if let Some(key) = workspace.key {
+            self.keys.insert(workspace.topic.clone(), RoomKey::from_bytes(key));
+        }
+        self.join(transport, &workspace.room, workspace.topic)?;
+
+        for (meta, text) in workspace.archive.documents {
+            let id = meta.id.clone();
+
+            self.documents.update_meta(meta);
+            *self.documents.get_or_create(&id) = Notepad::new(text);
+            self.log_text(&id);
+        }
+
+        if let Some(name) = self.documents.get(&workspace.active).map(|document| document.meta.name.clone()) {
+            self.documents.switch(&name)?;
+        }
+
+        self.peers.nickname = workspace.nickname;
+        self.rooms = workspace.rooms;
+        self.publish(transport, self.presence());
+        self.request_snapshots(transport, Instant::now());
+
+        Ok(())
+    }
+
+    /// Keeps a log of the changes to each room's documents in `storage`,
+    /// starting with the current room. Unless `replay` is unset, the
+    /// documents are first rebuilt from what the log already holds.
+    pub fn open_oplog(&mut self, storage: Box<dyn Storage>, replay: bool) -> Result<(), NotepadError> {
+        let (oplog, recovered) = OpLog::open(storage, &self.topic)?;
+
+        if recovered.torn > 0 {
+            println!("Dropped {} of the operation log cut short by a crash", memory::bytes(recovered.torn));
+        }
+
+        if replay && !recovered.records.is_empty() {
+            println!("Replaying {} changes from the operation log", recovered.records.len());
+            self.replay(recovered.records);
+        }
+
+        self.oplog = Some(oplog);
+
+        Ok(())
+    }
+
+    /// Rewrites the current room's operation log as the text of each of its
+    /// documents and their tags, unless that would take more room than the edits logged.
+    /// Returns the size of the log before and after. The backup peer keeps
+    /// its own copy, which isn't compacted.
+    pub fn compact_oplog(&mut self) -> Result<(usize, usize), NotepadError> {
+        let oplog = self.oplog.as_mut().ok_or_else(|| NotepadError::command("No operation log is kept, set one with `--oplog <directory>`"))?;
+        let texts = self.documents.iter().map(|document| oplog::text_frame(&document.meta.id, document.notepad.text()));
+        let tags = self.documents.tags.iter().map(|(name, tag)| oplog::tag_frame(&tag.document, name, &tag.text));
+        let frames = texts.chain(tags).collect::<Vec<_>>().concat();
+        let before = oplog.len();
+
+        if frames.len() < before {
+            oplog.compact(&frames)?;
+        }
+        self.journal.compact();
+
+        Ok((before, oplog.len()))
+    }
+
+    /// Applies logged changes to the documents, in the order they were made.
+    fn replay(&mut self, records: Vec<Record>) {
+        for record in records {
+            match record {
+                Record::Diffs { document, diffs } => if let Err(e) = self.documents.get_or_create(&document).apply_message_buf(&diffs) {
+                    println!("Dropped logged edit: {e}");
+                },
+                Record::Text { document, text } => self.documents.get_or_create(&document).set_text(text),
+                Record::Tag { document, name, text } => {
+                    self.documents.tags.insert(name, Tag { document, text });
+                },
+            }
+        }
+    }
+
+    /// Asks the backup peer for its copy of the current room's operation log,
+    /// which is replayed on top of the documents once it arrives. Meant for a
+    /// fresh launch after losing the machine the work was done on.
+    pub fn restore_backup(&mut self, transport: &mut impl Transport) -> Result<(), NotepadError> {
+        let target = self.backup.as_mut().ok_or_else(|| NotepadError::command("No backup peer, set one with `--backup-peer <peer id>` and `--backup-key <key>`"))?;
+
+        transport.request(&target.peer, Message::BackupRequest { owner: target.owner.clone(), log: oplog::name(&self.topic) }.into())?;
+        target.restoring = true;
+
+        Ok(())
+    }
+
+    /// Sends the backup peer the next batch of changes logged since the last, if it is due.
+    fn send_backup(&mut self, transport: &mut impl Transport, now: Instant) {
+        let Some(target) = &mut self.backup else {
+            return;
+        };
+
+        if let Some((offset, frames)) = target.next(now) {
+            let message = Message::Backup { owner: target.owner.clone(), log: oplog::name(&self.topic), offset, frames };
+
+            if let Err(e) = transport.request(&target.peer, message.into()) {
+                println!("Backup error: {e}");
+            }
+        }
+    }
+
+    /// Answers a peer backing up its operation log here, or asking for its backup.
+    fn serve_backup(&self, message: Message) -> Result<Message, NotepadError> {
+        let backups = self.backups.as_ref().ok_or(NotepadError::command("This peer doesn't keep backups"))?;
+
+        match message {
+            Message::Backup { owner, log, offset, frames } => {
+                let len = backups.store(&owner, &log, offset, &frames)?;
+
+                Ok(Message::BackupAck { log, len })
+            },
+            Message::BackupRequest { owner, log } => {
+                let frames = backups.load(&owner, &log)?;
+
+                Ok(Message::Backup { owner, log, offset: None, frames })
+            },
+            _ => Err(NotepadError::command("Not a backup message")),
+        }
+    }
+
+    /// Records diffs applied to `document` in the operation log, if one is kept.
+    fn log_diffs(&mut self, document: &str, diffs: &MessageBuf) {
+        if self.oplog.is_some() || self.backup.is_some() {
+            self.log(oplog::diffs_frame(document, diffs));
+        }
+    }
+
+    /// Records the current text of `document` in the operation log, if one
+    /// is kept, and in the journal that it was replaced.
+    fn log_text(&mut self, document: &str) {
+        self.journal.replaced(document);
+        self.backlog.clear();
+        let Some(document) = self.documents.get(document) else {
+            return;
+        };
+
+        if self.oplog.is_some() || self.backup.is_some() {
+            self.log(oplog::text_frame(&document.meta.id, document.notepad.text()));
+        }
+    }
+
+    /// Appends a framed record to the operation log and queues it for the
+    /// backup peer, compacting the log once enough has been appended.
+    fn log(&mut self, frame: Vec<u8>) {
+        if let Some(Err(e)) = self.oplog.as_mut().map(|oplog| oplog.append(&frame)) {
+            println!("Operation log error: {e}");
+        }
+
+        if self.oplog.as_ref().zip(self.oplog_compact).is_some_and(|(oplog, max)| oplog.appended() > max) {
+            match self.compact_oplog() {
+                Ok((before, after)) => println!("Compacted the operation log from {} to {}", memory::bytes(before), memory::bytes(after)),
+                Err(e) => println!("Operation log error: {e}"),
+            }
+        }
+
+        if let Some(target) = &mut self.backup {
+            target.push(&frame);
+        }
+    }
+
+    /// Asks the host to publish snapshots, at most once every [`SNAPSHOT_RETRY_INTERVAL`].
+    fn request_snapshots(&mut self, transport: &mut impl Transport, now: Instant) {
+        if self.last_snapshot_request.is_some_and(|last| now.saturating_duration_since(last) < SNAPSHOT_RETRY_INTERVAL) {
+            return;
+        }
+
+        self.last_snapshot_request = Some(now);
+        self.publish(transport, Message::SnapshotRequest);
+    }
+
+    fn join(&mut self, transport: &mut impl Transport, room: &str, topic: String) -> Result<(), NotepadError> {
+        self.publish_batch(transport);
+        self.publish_held(transport);
+        self.release_locks(transport);
+
+        // Rooms still watched stay subscribed once left.
+        if !self.watches.contains_key(&self.topic) {
+            if let Err(e) = transport.unsubscribe(&self.topic) {
+                println!("{e}");
+            }
+        }
+        if let Err(e) = transport.unsubscribe(&self.cursor_topic()) {
+            println!("{e}");
+        }
+
+        if topic != self.topic {
+            // The documents parked are those of the room left, whole.
+            self.finish_chunks();
+            // Kept to join it again from the dashboard.
+            self.dashboard.name(&self.topic, &self.room);
+            let parked = self.parked.remove(&topic);
+            let replay = parked.is_none();
+            let previous = std::mem::replace(&mut self.documents, parked.unwrap_or_else(|| Documents::new(Notepad::default())));
+
+            let outbox = std::mem::replace(&mut self.outbox, self.outboxes.remove(&topic).unwrap_or_default());
+            if !outbox.is_empty() {
+                self.outboxes.insert(self.topic.clone(), outbox);
+            }
+
+            self.parked.insert(std::mem::replace(&mut self.topic, topic), previous);
+
+            if let Some(target) = &mut self.backup {
+                target.clear();
+            }
+
+            if let Some(oplog) = self.oplog.take() {
+                if let Err(e) = self.open_oplog(oplog.into_storage(), replay) {
+                    println!("Operation log error: {e}");
+                }
+            }
+        }
+
+        self.room = room.to_string();
+        self.dashboard.read(&self.topic);
+        self.peers.clear();
+        self.neighbours.clear();
+        self.sources.clear();
+        self.sequences.clear();
+        self.reorder = Reorder::default();
+        match self.bulk.clear() {
+            0 => {},
+            dropped => println!("Dropped {dropped} messages still waiting to be published to the room left"),
+        }
+        self.synced = false;
+        self.sync = None;
+        self.diverged = false;
+        self.resync = None;
+        self.last_digest = None;
+        self.mismatches.clear();
+        self.listing = None;
+        self.manifest = None;
+        self.admissions.clear();
+        self.permissions.clear();
+        self.history.clear();
+        self.journal.clear();
+        self.backlog.clear();
+        self.partition.clear();
+        self.cursors.clear();
+        self.recent_edits.clear();
+        self.selection = None;
+        self.locks.clear();
+        self.claims.clear();
+        self.cursor_sent = None;
+        self.unseen.clear();
+        self.vacancy = Vacancy::default();
+
+        if self.prefs().is_some_and(|prefs| prefs.read_only) {
+            println!("Room `{room}` is read-only");
+        }
+
+        transport.subscribe(&self.cursor_topic())?;
+        transport.subscribe(&self.topic)
+    }
+
+    /// Name of the room with `topic`, if it is the current one or was joined or watched.
+    pub fn room_of(&self, topic: &str) -> Option<&str> {
+        if topic == self.topic {
+            return Some(&self.room);
+        }
+
+        self.watches.get(topic).map(|watch| watch.room.as_str()).or_else(|| self.dashboard.name_of(topic))
+    }
+
+    /// Makes the room with `topic` the current one again, one that was
+    /// joined or watched before, so its name (and passphrase) are known.
+    pub fn reopen(&mut self, transport: &mut impl Transport, topic: &str) -> Result<(), NotepadError> {
+        let room = self.room_of(topic).ok_or_else(|| NotepadError::command("Unknown room"))?.to_string();
+
+        self.join(transport, &room, topic.to_string())
+    }
+
+    /// A summary of the current room, then those left and those watched, by name.
+    pub fn rooms(&self, timeout: Duration, now: Instant) -> Vec<Summary> {
+        let health = if self.partition.is_lost() {
+            Health::Partitioned
+        } else if self.sync.is_some() {
+            Health::Syncing
+        } else if !self.outbox.is_empty() || !self.bulk.is_empty() {
+            Health::Pending(self.outbox.len() + self.bulk.len())
+        } else if self.peers.iter().next().is_none() {
+            Health::Alone
+        } else {
+            Health::Synced
+        };
+        let mut rooms = vec![Summary {
+            room: self.room.clone(),
+            topic: self.topic.clone(),
+            current: true,
+            unread: 0,
+            peers: self.peers.iter().count(),
+            last_edit: self.dashboard.last_edit(&self.topic),
+            health,
+        }];
+
+        let mut others: Vec<_> = self.parked.keys()
+            .chain(self.watches.keys())
+            .filter(|&topic| *topic != self.topic)
+            .cloned()
+            .collect();
+        others.sort();
+        others.dedup();
+
+        let mut others: Vec<_> = others.into_iter().map(|topic| Summary {
+            room: self.room_of(&topic).unwrap_or(&topic).to_string(),
+            current: false,
+            unread: self.dashboard.unread(&topic),
+            peers: self.dashboard.peers(&topic, timeout, now),
+            last_edit: self.dashboard.last_edit(&topic),
+            health: match self.outboxes.get(&topic) {
+                _ if self.watches.contains_key(&topic) => Health::Watching,
+                Some(outbox) => Health::Pending(outbox.len()),
+                None => Health::Parked,
+            },
+            topic,
+        }).collect();
+        others.sort_by(|a, b| a.room.cmp(&b.room));
+        rooms.extend(others);
+
+        rooms
+    }
+
+    /// Topic the room's cursors are published on, see [`cursors::topic`].
+    pub fn cursor_topic(&self) -> String {
+        cursors::topic(&self.topic)
+    }
+
+    /// Publishes where the editor's cursor is, if it moved and wasn't
+    /// published in the last [`CURSOR_INTERVAL`], or anyway once in a
+    /// [`PRESENCE_INTERVAL`] so peers keep showing it.
+    pub fn publish_cursor(&mut self, transport: &mut impl Transport, now: Instant) {
+        let Some(index) = self.cursor else {
+            return;
+        };
+        let document = &self.documents.active_meta().id;
+
+        let due = self.cursor_sent.as_ref().is_none_or(|(sent_document, sent_index, sent)| {
+            let since = now.saturating_duration_since(*sent);
+            since >= PRESENCE_INTERVAL || since >= CURSOR_INTERVAL && (sent_document, *sent_index) != (document, index)
+        });
+        if !due {
+            return;
+        }
+
+        self.cursor_sent = Some((document.clone(), index, now));
+        let message = Message::Cursor { document: document.clone(), index: index as u64 };
+
+        if let Err(e) = transport.publish(&self.cursor_topic(), self.seal(message.into())) {
+            output::error(&format!("Publish error: {e}"));
+        }
+    }
+
+    /// Applies operations to the room's whiteboard named `board`, drawing a
+    /// new one if there is none, and publishes them like edits.
+    pub fn draw(&mut self, transport: &mut impl Transport, board: &str, ops: Vec<BoardOp>) -> Result<(), NotepadError> {
+        self.writable()?;
+        if board.is_empty() || board.len() > document::MAX_NAME_LEN {
+            return Err(NotepadError::command(format!("Board names are 1 to {} bytes long", document::MAX_NAME_LEN)));
+        }
+        if ops.is_empty() {
+            return Ok(());
+        }
+
+        let whiteboard = self.documents.boards.entry(board.to_string()).or_default();
+        for op in &ops {
+            whiteboard.apply(op.clone());
+        }
+        self.dashboard.edit(&self.topic, false, Instant::now());
+
+        self.send(transport, Message::Board { board: board.to_string(), ops }.into(), true)?;
+
+        Ok(())
+    }
+
+    /// Applies a local edit to the active document and publishes it in chunks,
+    /// recording the delivery of each chunk.
+    pub fn edit(&mut self, transport: &mut impl Transport, message: MessageBuf) -> Result<(), NotepadError> {
+        let inverse = self.commit(transport, message)?;
+        self.history.record(&self.documents.active_meta().id, inverse);
+
+        Ok(())
+    }
+
+    /// Inserts bytes `range` of the active document of `room`, this room or
+    /// another joined one, into the active document at `index`, published
+    /// like any other edit. Returns the text inserted.
+    pub fn copy_from(&mut self, transport: &mut impl Transport, room: &str, range: Range<usize>, index: usize) -> Result<String, NotepadError> {
+        let documents = if room == self.room {
+            &self.documents
+        } else {
+            self.parked.iter()
+                .find(|(topic, _)| self.room_of(topic) == Some(room))
+                .map(|(_, documents)| documents)
+                .ok_or_else(|| NotepadError::command(format!("Not in room `{room}`, join it first")))?
+        };
+        let text = documents.active().text()
+            .get(range.clone())
+            .filter(|text| !text.is_empty())
+            .ok_or_else(|| NotepadError::command(format!("`{}..{}` isn't a non-empty range of whole characters in `{room}`", range.start, range.end)))?
+            .to_string();
+
+        self.edit(transport, MessageBuf { messages: vec![Diff { opcode: Operation::InsStr(text.clone()), operand: None, index }] })?;
+
+        Ok(text)
+    }
+
+    /// Labels the current text of the active document `name`, to go back to
+    /// with [`Engine::restore_tag`]. Tags are kept in the operation log, and
+    /// a tag given again is moved.
+    pub fn tag(&mut self, name: &str) {
+        let tag = Tag { document: self.documents.active_meta().id.clone(), text: self.documents.active().text().to_string() };
+        let frame = (self.oplog.is_some() || self.backup.is_some()).then(|| oplog::tag_frame(&tag.document, name, &tag.text));
+
+        // Tagged first, so a compaction of the log on appending keeps it.
+        self.documents.tags.insert(name.to_string(), tag);
+        if let Some(frame) = frame {
+            self.log(frame);
+        }
+    }
+
+    /// Reverts the active document to its text when tagged `name`, publishing
+    /// the diffs from its current text like any other edit so peers follow.
+    /// Returns the number of diffs.
+    pub fn restore_tag(&mut self, transport: &mut impl Transport, name: &str) -> Result<usize, NotepadError> {
+        let tag = self.documents.tags.get(name).ok_or_else(|| NotepadError::command(format!("No tag `{name}`, see `tags`")))?;
+        if tag.document != self.documents.active_meta().id {
+            let document = self.documents.get(&tag.document).map_or(tag.document.as_str(), |document| &document.meta.name);
+            return Err(NotepadError::command(format!("Tag `{name}` is of document `{document}`, switch to it with `doc:{document}`")));
+        }
+
+        let message = self.batch.granularity.compute(self.documents.active().text(), &tag.text);
+        let diffs = message.messages.len();
+        if diffs > 0 {
+            self.edit(transport, message)?;
+        }
+
+        Ok(diffs)
+    }
+
+    /// The changes to the active document since the tag or operation number
+    /// `since`, as a unified diff, see [`patch::unified`].
+    pub fn patch_since(&self, since: &str) -> Result<String, NotepadError> {
+        let meta = self.documents.active_meta();
+        let text = self.documents.active().text();
+
+        let old = match (self.documents.tags.get(since), since.parse::<u64>()) {
+            (Some(tag), _) if tag.document == meta.id => tag.text.clone(),
+            (Some(_), _) => return Err(NotepadError::command(format!("Tag `{since}` is of another document"))),
+            (None, Ok(seq)) => self.journal.at(seq, &meta.id, text)?,
+            (None, Err(_)) => return Err(NotepadError::command(format!("No tag `{since}`, see `tags`, or operation number, see `hist`"))),
+        };
+
+        Ok(patch::unified(&old, text, &format!("a/{}", meta.name), &format!("b/{}", meta.name)))
+    }
+
+    /// Applies the unified diff `patch` to the active document, see
+    /// [`patch::apply`], publishing the changes like any other edit.
+    /// Returns the number of diffs.
+    pub fn apply_patch(&mut self, transport: &mut impl Transport, patch: &str) -> Result<usize, NotepadError> {
+        let text = self.documents.active().text();
+        let message = self.batch.granularity.compute(text, &patch::apply(text, patch)?);
+        let diffs = message.messages.len();
+
+        if diffs > 0 {
+            self.edit(transport, message)?;
+        }
+
+        Ok(diffs)
+    }
+
+    /// Reverts the last local edit to the active document that wasn't undone
+    /// yet, publishing the reverting diffs like any other edit.
+    pub fn undo(&mut self, transport: &mut impl Transport) -> Result<String, NotepadError> {
+        let document = self.documents.active_meta().id.clone();
+        let message = self.history.undo(&document).ok_or(NotepadError::command("Nothing to undo"))?;
+        let summary = message.summary();
+
+        let inverse = self.commit(transport, message)?;
+        self.history.undone(&document, inverse);
+
+        Ok(summary)
+    }
+
+    /// Makes the last undone edit to the active document again.
+    pub fn redo(&mut self, transport: &mut impl Transport) -> Result<String, NotepadError> {
+        let document = self.documents.active_meta().id.clone();
+        let message = self.history.redo(&document).ok_or(NotepadError::command("Nothing to redo"))?;
+        let summary = message.summary();
+
+        let inverse = self.commit(transport, message)?;
+        self.history.redone(&document, inverse);
+
+        Ok(summary)
+    }
+
+    /// Takes the lock on the active document for turn-taking: until this peer
+    /// unlocks it or leaves the room, other peers refuse local edits to it.
+    /// Fails if another peer holds it.
+    pub fn lock(&mut self, transport: &mut impl Transport) -> Result<(), NotepadError> {
+        self.writable()?;
+        let meta = self.documents.active_meta();
+
+        if let Err(holder) = self.locks.lock(&meta.id) {
+            return Err(NotepadError::command(format!("`{}` is locked by {}", meta.name, self.peers.display_name(&holder))));
+        }
+
+        let document = meta.id.clone();
+        self.publish(transport, Message::Lock { document, held: true });
+
+        Ok(())
+    }
+
+    /// Releases this peer's lock on the active document.
+    pub fn unlock(&mut self, transport: &mut impl Transport) -> Result<(), NotepadError> {
+        let meta = self.documents.active_meta();
+
+        if !self.locks.unlock(&meta.id) {
+            return Err(NotepadError::command(format!("This peer doesn't hold the lock on `{}`", meta.name)));
+        }
+
+        let document = meta.id.clone();
+        self.publish(transport, Message::Lock { document, held: false });
+
+        Ok(())
+    }
+
+    /// Releases every lock and the claim this peer holds, before leaving the room.
+    fn release_locks(&mut self, transport: &mut impl Transport) {
+        let own: Vec<_> = self.locks.own().cloned().collect();
+
+        for document in own {
+            self.locks.unlock(&document);
+            self.publish(transport, Message::Lock { document, held: false });
+        }
+        if let Some(claim) = self.claims.unclaim() {
+            self.publish(transport, Message::Claim { document: claim.document, range: None });
+        }
+    }
+
+    /// Claims lines `first` to `last` of the active document, numbered from
+    /// 1, as being edited by this peer, in place of any claim before. Peers
+    /// are warned when their edits land on them, but not stopped, and the
+    /// claim stays on the same lines as edits move them.
+    pub fn claim(&mut self, transport: &mut impl Transport, first: usize, last: usize) -> Result<(), NotepadError> {
+        self.writable()?;
+        let document = self.documents.active_meta().id.clone();
+        let range = lines::range(self.documents.active().text(), first, last)?;
+
+        if let Some(claim) = self.claims.own().filter(|claim| claim.document != document) {
+            let claim = Message::Claim { document: claim.document.clone(), range: None };
+            self.publish(transport, claim);
+        }
+        self.claims.claim(&document, range.clone());
+        self.publish(transport, Message::Claim { document, range: Some(range.start as u64..range.end as u64) });
+
+        Ok(())
+    }
+
+    /// Releases this peer's claim.
+    pub fn unclaim(&mut self, transport: &mut impl Transport) -> Result<(), NotepadError> {
+        let claim = self.claims.unclaim().ok_or_else(|| NotepadError::command("This peer claimed no lines"))?;
+        self.publish(transport, Message::Claim { document: claim.document, range: None });
+
+        Ok(())
+    }
+
+    /// The claims on the active document, this peer's as `None`, with the
+    /// first and last line they cover, numbered from 1.
+    pub fn claimed_lines(&self) -> Vec<(Option<PeerId>, (usize, usize))> {
+        let text = self.documents.active().text();
+
+        self.claims
+            .in_document(&self.documents.active_meta().id)
+            .into_iter()
+            .map(|(peer_id, range)| (peer_id, lines::covered(text, range)))
+            .collect()
+    }
+
+    /// Warns of the diffs of a local edit to `document` that land on lines
+    /// another peer claimed.
+    fn warn_claimed(&self, document: &str, message: &MessageBuf) {
+        let Some(notepad) = self.documents.get(document).map(|document| &document.notepad) else {
+            return;
+        };
+
+        let mut warned = HashSet::new();
+        for diff in &message.messages {
+            for (peer_id, range) in self.claims.at(document, diff.index) {
+                if warned.insert(peer_id) {
+                    // Only put together when a claim is edited, not on every edit.
+                    let (first, last) = lines::covered(notepad.text(), range);
+                    println!("Editing lines {first} to {last}, which {} claimed", self.peers.display_name(&peer_id));
+                }
+            }
+        }
+    }
+
+    /// Follows a claim made or released by `source`.
+    fn receive_claim(&mut self, source: Option<PeerId>, document: String, range: Option<Range<u64>>) {
+        let Some(source) = source else {
+            return;
+        };
+        let range = range.map(|range| range.start as usize..range.end as usize);
+
+        self.claims.receive(source, range.map(|range| Claim { document, range }), Instant::now());
+    }
+
+    /// Follows a lock taken or released by `source`, telling the user when
+    /// a document changes hands.
+    fn receive_lock(&mut self, transport: &impl Transport, source: Option<PeerId>, document: String, held: bool) {
+        let Some(source) = source else {
+            return;
+        };
+        let lost = self.locks.holds(&document);
+
+        if !self.locks.receive(transport.peer_id(), source, &document, held, Instant::now()) {
+            return;
+        }
+
+        let name = self.documents.get(&document).map_or(document.as_str(), |document| &document.meta.name);
+        let holder = self.peers.display_name(&source);
+        match (held, lost) {
+            (true, true) => println!("{holder} locked `{name}` at the same time and keeps it, this peer's lock is released"),
+            (true, false) => println!("{holder} locked `{name}`, local edits to it are refused until they unlock it"),
+            (false, _) => println!("{holder} unlocked `{name}`"),
+        }
+    }
+
+    /// Forgets the locks of a peer that left or timed out, telling the user
+    /// the documents are free again.
+    fn free_locks(&mut self, peer_id: &PeerId, name: &str) {
+        for document in self.locks.forget(peer_id) {
+            let document = self.documents.get(&document).map_or(document.as_str(), |document| &document.meta.name);
+            println!("`{document}` is unlocked, {name} left");
+        }
+    }
+
+    /// Fails if another peer holds the lock on `document`.
+    fn check_lock(&self, document: &str) -> Result<(), NotepadError> {
+        match self.locks.holder(document) {
+            Some(holder) => Err(NotepadError::command(format!(
+                "`{}` is locked by {}, wait for them to unlock it",
+                self.documents.active_meta().name, self.peers.display_name(&holder)
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Fails if `diffs` could grow `document` past the maximum document size.
+    /// Diffs that shrink a document always fit, even if it is still over.
+    fn check_size(&self, document: &str, diffs: &MessageBuf) -> Result<(), NotepadError> {
+        self.check_growth(document, diffs.max_growth())
+    }
+
+    /// Fails if growing `document` by `growth` bytes takes it past the maximum document size.
+    fn check_growth(&self, document: &str, growth: isize) -> Result<(), NotepadError> {
+        let Some(max) = self.max_document_size else {
+            return Ok(());
+        };
+        let len = self.documents.get(document).map_or(0, |document| document.notepad.len());
+
+        if growth > 0 && len.saturating_add_signed(growth) > max {
+            return Err(NotepadError::command(format!("The edit would grow the document past the maximum size of {}", memory::bytes(max))));
+        }
+
+        Ok(())
+    }
+
+    /// Fails if local edits are refused, when observing or the room is read-only.
+    fn writable(&self) -> Result<(), NotepadError> {
+        if self.observe {
+            return Err(NotepadError::command("Observing, allow edits with `ro:off`"));
+        }
+        if self.prefs().is_some_and(|prefs| prefs.read_only) {
+            return Err(NotepadError::command("This room is read-only, allow edits with `room read-only:off`"));
+        }
+
+        Ok(())
+    }
+
+    /// Applies and publishes a local edit, returning the diffs that revert it.
+    fn commit(&mut self, transport: &mut impl Transport, message: MessageBuf) -> Result<MessageBuf, NotepadError> {
+        self.writable()?;
+        // The edit is made to the text peers will have applied every edit before it to.
+        self.finish_chunks();
+        self.check_lock(&self.documents.active_meta().id)?;
+        self.check_size(&self.documents.active_meta().id, &message)?;
+
+        let document = self.documents.active_meta().id.clone();
+        let before = self.documents.active().len();
+        if self.crdt {
+            self.sequence(transport.peer_id(), &document);
+        } else if self.sync.is_some() && !self.sync_base.contains_key(&document) {
+            self.sync_base.insert(document.clone(), self.documents.active().text().to_string());
+        }
+        self.warn_claimed(&document, &message);
+        let mut moving = self.unseen.moving(&document);
+        moving.extend(self.claims.moving(&document));
+        moving.extend(self.selection.iter_mut().flat_map(|range| [&mut range.start, &mut range.end]));
+        let inverse = self.documents.active_mut().apply_moving(&message, &mut moving)?;
+        let ops = match self.sequences.get_mut(&document) {
+            Some(sequence) if self.crdt => sequence.local(&message),
+            _ => Vec::new(),
+        };
+        self.log_diffs(&document, &message);
+        self.ops_since_snapshot += message.messages.len();
+        self.partition.record(&document, inverse.clone(), Instant::now());
+
+        let author = self.users.active().map(|user| {
+            self.peers.user(user.peer_id(), user.nickname.clone());
+            user.peer_id()
+        });
+        let credited = author.unwrap_or(transport.peer_id());
+        self.documents.authors.entry(document.clone()).or_default().record(before, &message, &inverse, Some(credited));
+        self.journal.record(Some(credited), &document, &message, &inverse, SystemTime::now());
+
+        if let Some(session) = &mut self.session {
+            session.record(author, message.messages.len());
+        }
+
+        let now = Instant::now();
+        self.activity.record(author, message.messages.len(), now);
+        self.dashboard.edit(&self.topic, false, now);
+        for diff in &message.messages {
+            self.conflicts.local_edit(&document, diff.index, now);
+        }
+
+        if self.crdt {
+            // Ops converge whatever peers did meanwhile, so nothing is held back.
+            self.publish_ops(transport, document, ops, message.summary());
+        } else if self.partition.is_lost() {
+            // Merged into the room's documents once a peer is back.
+            self.recent_edits.push(message.summary(), Delivery::Pending);
+        } else if self.sync.is_some() {
+            // Merged into the documents caught up on once they arrive.
+            self.recent_edits.push(message.summary(), Delivery::Pending);
+        } else if self.batch.window.is_zero() {
+            self.publish_diffs(transport, document, message);
+        } else if let Some((document, message)) = self.batch.push(document, message, &inverse, now) {
+            self.publish_diffs(transport, document, message);
+        }
+
+        Ok(inverse)
+    }
+
+    /// Publishes the local edits held in the batch once its window has passed.
+    pub fn flush_edits(&mut self, transport: &mut impl Transport, now: Instant) {
+        if let Some((document, message)) = self.batch.take_due(now) {
+            self.publish_diffs(transport, document, message);
+        }
+    }
+
+    /// When the local edits held in the batch are due to be published, if any are.
+    pub fn next_edits(&self) -> Option<Instant> {
+        self.batch.due()
+    }
+
+    /// Publishes the local edits held in the batch straight away, before text
+    /// that includes them is sent in a snapshot or sync, or the room is left.
+    fn publish_batch(&mut self, transport: &mut impl Transport) {
+        if let Some((document, message)) = self.batch.take() {
+            self.publish_diffs(transport, document, message);
+        }
+    }
+
+    /// Publishes a local edit to `document` in chunks, recording the delivery of each.
+    fn publish_diffs(&mut self, transport: &mut impl Transport, document: String, message: MessageBuf) {
+        self.bulk.interactive(Instant::now());
+        let chunks = message.into_chunks(CHUNK_LEN);
+        let total = chunks.len();
+
+        for (i, chunk) in chunks.into_iter().enumerate() {
+            let summary = chunk.summary();
+            let len = chunk.messages.len();
+            let seq = self.seq + 1;
+            let author = self.users.active().map(|user| user.peer_id());
+            let missed = Missed { peer: transport.peer_id(), seq, document: document.clone(), diffs: chunk.clone(), author };
+            let runs = chunk.compress();
+
+            let message = if runs.len() < len {
+                Message::Runs { document: document.clone(), seq, runs }
+            } else {
+                Message::Diffs { document: document.clone(), seq, diffs: MessageBuf::expand(runs) }
+            };
+
+            if self.publish_numbered(transport, seq, message, summary) {
+                self.backlog.record(missed);
+                if total > 1 {
+                    println!("Sent chunk {}/{total}", i + 1);
+                }
+            }
+        }
+    }
+
+    /// Publishes a local edit to `document` as ops of its sequence, in
+    /// chunks like [`Engine::publish_diffs`].
+    fn publish_ops(&mut self, transport: &mut impl Transport, document: String, ops: Vec<Op>, summary: String) {
+        self.bulk.interactive(Instant::now());
+
+        for ops in ops.chunks(CHUNK_LEN) {
+            let seq = self.seq + 1;
+            self.publish_numbered(transport, seq, Message::Ops { document: document.clone(), seq, ops: ops.to_vec() }, summary.clone());
+        }
+    }
+
+    /// Signs an edit numbered `seq` as the active user, if any, and publishes
+    /// it, recording its delivery. Returns whether it was published.
+    fn publish_numbered(&mut self, transport: &mut impl Transport, seq: u64, message: Message, summary: String) -> bool {
+        let mut message_bytes: Vec<u8> = message.into();
+
+        if let Some(user) = self.users.active() {
+            match user.sign(message_bytes) {
+                Ok(signed) => message_bytes = Message::Signed(signed).into(),
+                Err(e) => {
+                    output::error(&format!("Publish error: {e}"));
+                    return false;
+                },
+            }
+        }
+
+        let delivery = match self.send(transport, message_bytes, true) {
+            Ok(0) => Delivery::Pending,
+            Ok(peers) => Delivery::Delivered(peers),
+            Err(e) => {
+                output::error(&format!("Publish error: {e}"));
+                return false;
+            }
+        };
+
+        self.seq = seq;
+
+        match delivery {
+            Delivery::Delivered(peers) if self.acknowledge => self.recent_edits.published(seq, summary, peers, Instant::now()),
+            delivery => self.recent_edits.push(summary, delivery),
+        }
+        true
+    }
+
+    /// What a character a peer inserts into a sequence becomes in the text,
+    /// as with diffs in [`Engine::apply_now`]: filtered by
+    /// [`Engine::control_chars`], and dropped if it is `\r`.
+    fn char_filter(&self) -> impl Fn(char) -> Option<char> + Copy {
+        let control_chars = self.control_chars;
+
+        move |c| control_chars.filter(c).filter(|&c| c != '\r')
+    }
+
+    /// The sequence of `document`, started from its text the first time.
+    /// Should the text have been replaced some other way since, the
+    /// sequence follows it with ops of its own rather than starting again,
+    /// as the ids peers' ops refer to would be gone.
+    fn sequence(&mut self, peer_id: PeerId, document: &str) -> &mut Sequence {
+        // Chunks still waiting were translated from ops already in the sequence.
+        self.finish_chunks();
+
+        let notepad = self.documents.get(document).map(|document| &document.notepad);
+        let text = || notepad.map_or("", Notepad::text);
+        let sequence = self.sequences.entry(document.to_string()).or_insert_with(|| Sequence::from_text(replica(&peer_id), text()));
+        // Compared character by character, the text is only put together when it was replaced.
+        if !sequence.matches(notepad.into_iter().flat_map(Notepad::chars)) {
+            sequence.local(&diff::compute(&sequence.text(), text()));
+        }
+
+        sequence
+    }
+
+    /// Preferences of the current room, if any were set.
+    pub fn prefs(&self) -> Option<&RoomPrefs> {
+        self.rooms.get(&self.topic)
+    }
+
+    pub fn prefs_mut(&mut self) -> &mut RoomPrefs {
+        self.rooms.get_mut(&self.topic)
+    }
+
+    fn quiet(&self) -> bool {
+        self.prefs().is_some_and(|prefs| prefs.quiet)
+    }
+
+    fn muted(&self, source: Option<PeerId>) -> bool {
+        source.is_some_and(|peer_id| self.prefs().is_some_and(|prefs| prefs.muted.contains(&peer_id)))
+    }
+
+    /// Inserts a pasted block as one batched edit. The CLI has no cursor, so
+    /// pastes go at the end of the active document.
+    pub fn paste(&mut self, transport: &mut impl Transport, text: &str) -> Result<(), NotepadError> {
+        let start = self.documents.active().len();
+        let messages = LineEnding::normalize(text)
+            .char_indices()
+            .map(|(offset, c)| Diff { opcode: Operation::Ins, operand: Some(c), index: start + offset })
+            .collect();
+
+        self.edit(transport, MessageBuf { messages })
+    }
+
+    /// Shares `data` as an attachment: announces it to the room and inserts a
+    /// reference to it at `index` of the active document.
+    pub fn attach(&mut self, transport: &mut impl Transport, name: &str, data: Vec<u8>, index: usize) -> Result<AttachmentMeta, NotepadError> {
+        let hash = attachment::hash(&data);
+        let reference = attachment::reference(&hash);
+
+        let meta = self.attachments.add(name, data)?;
+        let messages = reference.chars().zip(index..).map(|(c, index)| Diff { opcode: Operation::Ins, operand: Some(c), index }).collect();
+
+        self.edit(transport, MessageBuf { messages })?;
+        self.publish(transport, Message::Attachment(meta.clone()));
+
+        Ok(meta)
+    }
+
+    /// Asks the peer that announced attachment `hash` to send it.
+    pub fn fetch(&self, transport: &mut impl Transport, hash: &str) -> Result<(), NotepadError> {
+        let peer_id = self.attachments
+            .source(hash)
+            .ok_or_else(|| NotepadError::command(format!("No peer to download attachment {hash} from")))?;
+
+        transport.request(&peer_id, Message::AttachmentRequest(hash.to_string()).into())
+    }
+
+    /// Lists the current room with the directory peer, refreshed with every
+    /// heartbeat until another room is joined. Private rooms can't be listed.
+    pub fn list_room(&mut self, transport: &mut impl Transport, description: &str) -> Result<(), NotepadError> {
+        if self.topic != room_topic(&self.topic_prefix, &self.room, None) {
+            return Err(NotepadError::command("Private rooms can't be listed"));
+        }
+
+        self.listing = Some(description.to_string());
+        self.send_listing(transport)
+    }
+
+    /// Stops refreshing the listing, the directory drops it once it goes stale.
+    pub fn unlist_room(&mut self) {
+        self.listing = None;
+    }
+
+    fn send_listing(&self, transport: &mut impl Transport) -> Result<(), NotepadError> {
+        let Some(description) = &self.listing else {
+            return Ok(());
+        };
+        let peer_id = self.directory_peer.ok_or_else(|| NotepadError::command("No directory peer, set one with `--directory-peer <peer id>`"))?;
+        let listing = RoomListing {
+            room: self.room.clone(),
+            description: description.clone(),
+            participants: (self.peers.iter().count() + 1).try_into().unwrap_or(u32::MAX),
+        };
+
+        transport.request(&peer_id, Message::Listing(listing).into())
+    }
+
+    /// Asks the directory peer for the rooms listed with it.
+    pub fn query_directory(&self, transport: &mut impl Transport) -> Result<(), NotepadError> {
+        let peer_id = self.directory_peer.ok_or_else(|| NotepadError::command("No directory peer, set one with `--directory-peer <peer id>`"))?;
+
+        transport.request(&peer_id, Message::DirectoryRequest.into())
+    }
+
+    /// Ends the session in progress, publishing its summary for the active
+    /// document and turning the room read-only if the session asked to.
+    pub fn end_session(&mut self, transport: &mut impl Transport) -> Option<SessionSummary> {
+        let session = self.session.take()?;
+        let summary = session.summary(&self.documents.active_meta().name, self.documents.active().checksum());
+
+        self.publish(transport, Message::SessionSummary(summary.clone()));
+
+        if session.read_only {
+            self.prefs_mut().read_only = true;
+        }
+
+        Some(summary)
+    }
+
+    /// Publishes a message that doesn't need its delivery tracked.
+    pub fn publish(&mut self, transport: &mut impl Transport, message: Message) {
+        if let Err(e) = self.send(transport, message.into(), false) {
+            output::error(&format!("Publish error: {e}"));
+        }
+    }
+
+    /// Queues a large message, such as a snapshot, to be published in the
+    /// background, see [`Pacer`]. It goes straight away unless edits were
+    /// just published or the lane has used its budget.
+    pub fn publish_bulk(&mut self, transport: &mut impl Transport, message: Message) {
+        self.bulk.push(message);
+        self.flush_bulk(transport, Instant::now());
+    }
+
+    /// Publishes the bulk messages the lane lets through now.
+    pub fn flush_bulk(&mut self, transport: &mut impl Transport, now: Instant) {
+        while let Some(mut message) = self.bulk.next(now) {
+            // Snapshots take the text as it is now, with any edits published
+            // since they were queued, which peers have applied before them.
+            if let Message::Snapshot(snapshot) = &mut message {
+                self.publish_batch(transport);
+                match self.documents.get(&snapshot.document) {
+                    Some(document) => document.notepad.text().clone_into(&mut snapshot.text),
+                    None => continue,
+                }
+            }
+
+            let data: Vec<u8> = message.into();
+            self.bulk.sent(data.len());
+
+            if let Err(e) = self.send(transport, data, false) {
+                output::error(&format!("Publish error: {e}"));
+            }
+        }
+    }
+
+    /// When the bulk lane next lets messages through, if any are waiting.
+    pub fn next_bulk(&self) -> Option<Instant> {
+        self.bulk.due()
+    }
+
+    /// Seals a payload with the room key if the room is private, see [`RoomKey`].
+    fn seal(&self, data: Vec<u8>) -> Vec<u8> {
+        match self.room_key() {
+            Some(key) => key.seal(&data),
+            None => data,
+        }
+    }
+
+    /// Publishes `data` to the room, sealed if it is private. Transient
+    /// failures are queued to be tried again with backoff, as are payloads
+    /// nobody received if `hold` is set, which then also wait behind those
+    /// queued before them to keep edits in order.
+    fn send(&mut self, transport: &mut impl Transport, data: Vec<u8>, hold: bool) -> Result<usize, NotepadError> {
+        let data = self.seal(data);
+        let now = Instant::now();
+        let dropped = if hold && !self.outbox.is_empty() {
+            self.outbox.push(data, 0, now)
+        } else {
+            match transport.publish(&self.topic, data.clone()) {
+                Ok(0) if hold => self.outbox.push(data, 0, now + self.retry.backoff),
+                Err(NotepadError::Transient(_)) => self.outbox.push(data, 1, now + self.retry.delay(1)),
+                result => return result,
+            }
+        };
+
+        if dropped > 0 {
+            println!("Dropped {dropped} of the oldest messages waiting to be published");
+        }
+
+        Ok(0)
+    }
+
+    /// Tries the payloads waiting to be published again, once they are due.
+    pub fn flush_outbox(&mut self, transport: &mut impl Transport, now: Instant) {
+        let topic = &self.topic;
+        let flushed = self.outbox.flush(&self.retry, now, |data| transport.publish(topic, data));
+
+        for e in flushed.failed {
+            output::error(&format!("Publish error: {e}"));
+        }
+    }
+
+    /// When the oldest payload waiting to be published is next tried.
+    pub fn next_retry(&self) -> Option<Instant> {
+        self.outbox.due()
+    }
+
+    /// The payloads waiting to be published to each room, the current one
+    /// first, described in words, with when the current room's are next tried.
+    pub fn pending(&self) -> Vec<(&str, Vec<String>, Option<Instant>)> {
+        let describe = |topic: &str, outbox: &Outbox, documents: Option<&Documents>| {
+            outbox.iter().map(|data| describe_payload(data, self.keys.get(topic), documents)).collect()
+        };
+        let mut pending = vec![(self.room.as_str(), describe(&self.topic, &self.outbox, Some(&self.documents)), self.outbox.due())];
+
+        let mut others: Vec<_> = self.outboxes
+            .iter()
+            .map(|(topic, outbox)| (self.room_of(topic).unwrap_or(topic), describe(topic, outbox, self.parked.get(topic)), None))
+            .collect();
+        others.sort_by(|a, b| a.0.cmp(b.0));
+        pending.extend(others);
+
+        pending
+    }
+
+    /// Publishes the metadata of every document and the text of those not archived.
+    pub fn publish_snapshots(&mut self, transport: &mut impl Transport) {
+        // Strokes already drawn are ignored, so boards are sent whole.
+        let boards = self.documents.boards.iter().map(|(board, whiteboard)| Message::Board {
+            board: board.clone(),
+            ops: whiteboard.strokes().iter().cloned().map(BoardOp::Draw).collect(),
+        });
+        let messages: Vec<_> = self.documents.iter().flat_map(|document| {
+            let snapshot = (!document.meta.archived).then(|| Message::Snapshot(Snapshot {
+                document: document.meta.id.clone(),
+                text: document.notepad.text().to_string()
+            }));
+
+            std::iter::once(Message::Meta(document.meta.clone())).chain(snapshot)
+        }).chain(boards).collect();
+
+        for message in messages {
+            self.publish_bulk(transport, message);
+        }
+
+        self.ops_since_snapshot = 0;
+    }
+
+    /// Changes the nickname this peer announces and lets the room know straight away.
+    pub fn set_nickname(&mut self, transport: &mut impl Transport, nickname: &str) -> Result<(), NotepadError> {
+        presence::validate_nickname(nickname)?;
+
+        if self.peers.nickname_users(nickname) > 0 && self.peers.nickname.as_deref() != Some(nickname) {
+            println!("Nickname `{nickname}` is already used in this room, peers will see you with a suffix");
+        }
+
+        self.peers.nickname = Some(nickname.to_string());
+        self.publish(transport, self.presence());
+
+        Ok(())
+    }
+
+    /// Replaces documents with those from `archive` and publishes them, unless
+    /// that would take the engine over its memory budget.
+    pub fn import(&mut self, transport: &mut impl Transport, archive: Archive) -> Result<(), NotepadError> {
+        let size: usize = archive.documents.iter().map(|(_, text)| text.len()).sum();
+
+        if let Some(budget) = self.memory_budget.filter(|&budget| self.memory_usage().total() + size > budget) {
+            return Err(NotepadError::command(format!(
+                "Importing {} would exceed the memory budget of {}", memory::bytes(size), memory::bytes(budget)
+            )));
+        }
+        if let Some(max) = self.max_document_size.filter(|&max| archive.documents.iter().any(|(_, text)| text.len() > max)) {
+            return Err(NotepadError::command(format!("A document in the archive is over the maximum size of {}", memory::bytes(max))));
+        }
+
+        for (meta, text) in archive.documents {
+            let snapshot = Snapshot { document: meta.id.clone(), text: LineEnding::normalize(&text) };
+            let archived = meta.archived;
+
+            self.documents.update_meta(meta.clone());
+            self.documents.get_or_create(&meta.id).set_text(snapshot.text.clone());
+            self.log_text(&meta.id);
+
+            self.publish_bulk(transport, Message::Meta(meta));
+            if !archived {
+                self.publish_bulk(transport, Message::Snapshot(snapshot));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the active document with `text` and publishes it. Peers
+    /// editing it as a sequence drop snapshots, so they are sent the edits.
+    pub fn import_text(&mut self, transport: &mut impl Transport, text: &str) -> Result<(), NotepadError> {
+        if self.crdt {
+            return self.load_text(transport, text);
+        }
+        if let Some(budget) = self.memory_budget.filter(|&budget| self.memory_usage().total() + text.len() > budget) {
+            return Err(NotepadError::command(format!(
+                "Importing {} would exceed the memory budget of {}", memory::bytes(text.len()), memory::bytes(budget)
+            )));
+        }
+        if let Some(max) = self.max_document_size.filter(|&max| text.len() > max) {
+            return Err(NotepadError::command(format!("Importing {} would exceed the maximum document size of {}", memory::bytes(text.len()), memory::bytes(max))));
+        }
+
+        let text = LineEnding::normalize(text);
+        self.documents.active_mut().set_text(text.clone());
+        self.log_text(&self.documents.active_meta().id.clone());
+        self.publish_bulk(transport, Message::Snapshot(Snapshot { document: self.documents.active_meta().id.clone(), text }));
+
+        Ok(())
+    }
+
+    /// Turns the active document into `text` with the fewest edits, see
+    /// [`diff::compute`] and [`diff::compute_words`], published to the room like any local edit.
+    pub fn load_text(&mut self, transport: &mut impl Transport, text: &str) -> Result<(), NotepadError> {
+        if let Some(budget) = self.memory_budget.filter(|&budget| self.memory_usage().total() + text.len() > budget) {
+            return Err(NotepadError::command(format!(
+                "Loading {} would exceed the memory budget of {}", memory::bytes(text.len()), memory::bytes(budget)
+            )));
+        }
+
+        let diffs = self.batch.granularity.compute(self.documents.active().text(), &LineEnding::normalize(text));
+        if diffs.messages.is_empty() {
+            return Ok(());
+        }
+
+        self.edit(transport, diffs)
+    }
+
+    /// Text of the active document with its line ending applied.
+    pub fn export_text(&self) -> String {
+        self.documents.active_meta().line_ending.apply(self.documents.active().text())
+    }
+
+    pub fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            documents: std::iter::once(&self.documents)
+                .chain(self.parked.values())
+                .flat_map(Documents::iter)
+                .map(|document| document.notepad.memory())
+                .sum(),
+            clipboard: self.clipboard.as_ref().map_or(0, |(_, text)| text.capacity()),
+            attachments: self.attachments.memory(),
+            caches: self.recent_edits.memory() + self.conflicts.memory() + self.latency.memory() + self.activity.memory() + self.outbox.memory() + self.outboxes.values().map(Outbox::memory).sum::<usize>() + self.history.memory() + self.journal.memory() + self.backlog.memory() + self.sources.memory() + self.partition.memory() + self.cursors.memory() + self.dashboard.memory() + self.unseen.memory()
+                + self.watches.values().map(Watch::memory).sum::<usize>()
+                + self.sequences.values().map(Sequence::memory).sum::<usize>()
+                + self.bulk.iter().map(|message| size_of::<Message>() + if let Message::Snapshot(snapshot) = message { snapshot.text.capacity() } else { 0 }).sum::<usize>()
+                + self.backup.as_ref().map_or(0, BackupTarget::memory),
+        }
+    }
+
+    /// When over the memory budget, drops the clipboard and the conflict and
+    /// latency statistics and shrinks document buffers, warning if that
+    /// wasn't enough. Document text itself is never dropped.
+    pub fn enforce_memory_budget(&mut self) {
+        let Some(budget) = self.memory_budget else {
+            return;
+        };
+
+        if self.memory_usage().total() <= budget {
+            return;
+        }
+
+        self.clipboard = None;
+        self.conflicts = Conflicts::default();
+        self.latency = Latency::default();
+
+        for id in self.documents.iter().map(|document| document.meta.id.clone()).collect::<Vec<_>>() {
+            self.documents.get_or_create(&id).shrink();
+        }
+
+        let total = self.memory_usage().total();
+        if total > budget {
+            println!("Memory usage of {} is over the budget of {}, see `mem`", memory::bytes(total), memory::bytes(budget));
+        }
+    }
+
+    /// Announces this peer to the room and forgets peers that have gone quiet for longer than `timeout`.
+    pub fn heartbeat(&mut self, transport: &mut impl Transport, timeout: Duration, now: Instant) {
+        self.publish(transport, self.presence());
+        self.publish_cursor(transport, now);
+        self.cursors.expire(now);
+        self.flush_outbox(transport, now);
+        self.send_backup(transport, now);
+
+        if let Some((None, manifest)) = self.manifest {
+            self.publish(transport, Message::Manifest(manifest));
+
+            let permissions: Vec<_> = self.permissions.iter().map(|(&peer, &write)| (peer, write)).collect();
+            for (peer, write) in permissions {
+                self.publish(transport, Message::Permission { peer, write });
+            }
+        }
+
+        let own: Vec<_> = self.locks.own().cloned().collect();
+        for document in own {
+            self.publish(transport, Message::Lock { document, held: true });
+        }
+        for (document, holder) in self.locks.expire(now) {
+            let document = self.documents.get(&document).map_or(document.as_str(), |document| &document.meta.name);
+            println!("The lock {} held on `{document}` expired", self.peers.display_name(&holder));
+        }
+        if let Some(claim) = self.claims.own().cloned() {
+            let range = Some(claim.range.start as u64..claim.range.end as u64);
+            self.publish(transport, Message::Claim { document: claim.document, range });
+        }
+        self.claims.expire(now);
+        for (summary, applied, expected) in self.recent_edits.overdue(now) {
+            output::error(&format!("`{summary}` applied by {applied}/{expected} peers, the others stayed silent"));
+        }
+
+        if let Err(e) = self.send_listing(transport) {
+            println!("{e}");
+        }
+
+        if self.sync.as_ref().is_some_and(|(sent, _)| now.saturating_duration_since(*sent) > SYNC_TIMEOUT) {
+            match self.sources.unanswered().and_then(|(_, versions)| self.ask_sync(transport, None, versions)) {
+                Some(peer_id) => println!("No answer to the sync request, catching up from {} instead", self.peers.display_name(&peer_id)),
+                None => {
+                    println!("No answer to the sync request, applying edits as they come");
+                    self.release_sync(transport);
+                },
+            }
+        }
+
+        // Edits missing for a while are asked of the peer that made them before the gap is skipped.
+        if let Some(peer_id) = self.sync.is_none().then(|| self.reorder.stalled(now)).flatten() {
+            self.request_backfill(transport, peer_id);
+        }
+        if self.sync.is_none() {
+            for (peer_id, edit) in self.reorder.expire(now) {
+                self.apply_remote(Some(peer_id), edit);
+            }
+        }
+        self.resync(transport);
+        self.publish_digest(transport, now);
+
+        let quiet = self.quiet();
+        let pruned = self.peers.prune(timeout, now);
+        for (peer_id, name) in &pruned {
+            self.reorder.forget(peer_id);
+            self.cursors.remove(peer_id);
+            self.claims.remove(peer_id);
+            self.free_locks(peer_id, name);
+
+            if !quiet {
+                println!("Peer {name} timed out");
+            }
+        }
+
+        if !pruned.is_empty() && self.peers.iter().next().is_none() {
+            self.vacancy.emptied(now);
+        }
+
+        // The last peer was heard from up to a timeout before it timed out.
+        self.partition.expire(timeout * 2, now);
+        if !pruned.is_empty() && self.peers.iter().next().is_none() && self.partition.lose() {
+            // Edits waiting for peers are merged in instead once one is back.
+            self.outbox.clear();
+            println!("Lost every peer in the room, edits are held to be merged once one is back");
+        }
+        if self.partition.is_lost() && self.peers.iter().next().is_some() {
+            self.request_sync(transport);
+        }
+
+        self.enforce_memory_budget();
+    }
+
+    /// Marks this peer as away or back, letting the room know straight away when it changes.
+    pub fn set_away(&mut self, transport: &mut impl Transport, away: bool) {
+        if self.peers.away != away {
+            self.peers.away = away;
+            self.publish(transport, self.presence());
+        }
+    }
+
+    /// Selects bytes `range` of the active document for peers to look at,
+    /// or clears the selection if `None` or empty, letting the room know
+    /// straight away when it changes. The selection moves along with edits.
+    pub fn select(&mut self, transport: &mut impl Transport, range: Option<Range<usize>>) {
+        let range = range.filter(|range| !range.is_empty());
+
+        if self.selection != range {
+            self.selection = range;
+            self.publish(transport, self.presence());
+        }
+    }
+
+    pub fn selection(&self) -> Option<Range<usize>> {
+        self.selection.clone()
+    }
+
+    fn presence(&self) -> Message {
+        let document = &self.documents.active_meta().id;
+        let reading = self.reading.clone().map(|lines| Reading { document: document.clone(), lines });
+        let selection = self.selection.clone().map(|range| Selection { document: document.clone(), range });
+
+        Message::Presence(Presence { nickname: self.peers.nickname.clone(), away: self.peers.away, reading, selection })
+    }
+
+    /// Records the lines of the active document the editor shows, so remote
+    /// edits on them are seen. Peers are told with the next heartbeat.
+    pub fn view(&mut self, lines: Range<usize>) {
+        let document = &self.documents.active_meta().id;
+
+        self.unseen.view(document, self.documents.active().text(), lines.clone());
+        self.reading = Some(lines);
+    }
+
+    /// Moves the cursor to the earliest remote edit to the active document
+    /// that wasn't shown yet, returning its line and column.
+    pub fn catch_up(&mut self) -> Option<(usize, usize)> {
+        let text = self.documents.active().text();
+        let index = self.unseen.catch_up(&self.documents.active_meta().id)?.min(text.len());
+
+        if let Some(cursor) = &mut self.cursor {
+            *cursor = index;
+        }
+
+        Some(editor::position(text, index))
+    }
+
+    /// Publishes a probe for peers to ack, see [`Latency`].
+    pub fn probe(&mut self, transport: &mut impl Transport) {
+        let id = self.latency.probe(Instant::now());
+
+        self.publish(transport, Message::Probe(id));
+    }
+
+    /// Publishes a hash of the active document with the edits applied to it,
+    /// for every peer to compare with its own copy and answer in kind.
+    pub fn verify(&mut self, transport: &mut impl Transport) {
+        let document = self.documents.active_meta().id.clone();
+        let hash = *blake3::hash(self.documents.active().text().as_bytes()).as_bytes();
+        let versions = self.versions(transport);
+
+        self.publish(transport, Message::Verify { document, hash, versions, answer: false });
+    }
+
+    /// The number of the last edit applied from every peer, this one included, by peer.
+    fn versions(&self, transport: &impl Transport) -> Vec<(PeerId, u64)> {
+        let mut versions: Vec<_> = self.reorder.versions().collect();
+        if self.seq > 0 {
+            versions.push((transport.peer_id(), self.seq));
+        }
+        versions.sort();
+
+        versions
+    }
+
+    /// Compares a peer's hash of `document` with this one's, answering with ours if it wasn't an answer.
+    fn receive_verify(&mut self, transport: &mut impl Transport, source: Option<PeerId>, document: String, hash: [u8; 32], versions: Vec<(PeerId, u64)>, answer: bool) {
+        let Some(peer_id) = source else {
+            return;
+        };
+        self.sources.advertised(peer_id, versions.iter().map(|&(_, seq)| seq).sum());
+        let name = self.peers.display_name(&peer_id);
+        let Some(ours) = self.documents.get(&document) else {
+            return println!("{name} verified document `{document}`, which this peer doesn't have");
+        };
+        let title = ours.meta.name.clone();
+        let our_hash = *blake3::hash(ours.notepad.text().as_bytes()).as_bytes();
+        let our_versions = self.versions(transport);
+
+        if our_hash == hash {
+            println!("In sync with {name} on `{title}`");
+        } else if our_versions == versions {
+            println!("Diverged from {name} on `{title}`, though both applied the same edits");
+        } else {
+            println!("Differs from {name} on `{title}`, which applied other edits, some may still be on their way");
+        }
+
+        if !answer {
+            self.publish(transport, Message::Verify { document, hash: our_hash, versions: our_versions, answer: true });
+        }
+    }
+
+    /// Publishes a digest of the active document once every [`REPAIR_INTERVAL`], if repairing.
+    fn publish_digest(&mut self, transport: &mut impl Transport, now: Instant) {
+        if !self.repair || self.last_digest.is_some_and(|last| now.saturating_duration_since(last) < REPAIR_INTERVAL) {
+            return;
+        }
+        self.last_digest = Some(now);
+
+        let document = self.documents.active_meta().id.clone();
+        let hash = *blake3::hash(self.documents.active().text().as_bytes()).as_bytes();
+        let versions = self.versions(transport);
+
+        self.publish(transport, Message::Digest { document, hash, versions });
+    }
+
+    /// Compares a peer's digest of `document` with this one's copy, catching
+    /// up from the peer once [`REPAIR_MISMATCHES`] in a row didn't match.
+    /// Of two peers apart, only the one that applied fewer edits catches up,
+    /// or with as many the one with the higher peer id, so they don't both
+    /// take on each other's copies.
+    fn receive_digest(&mut self, transport: &mut impl Transport, source: Option<PeerId>, document: String, hash: [u8; 32], versions: Vec<(PeerId, u64)>) {
+        let Some(peer_id) = source else {
+            return;
+        };
+        self.sources.advertised(peer_id, versions.iter().map(|&(_, seq)| seq).sum());
+        if !self.repair {
+            return;
+        }
+        let Some(ours) = self.documents.get(&document) else {
+            return;
+        };
+        if *blake3::hash(ours.notepad.text().as_bytes()).as_bytes() == hash {
+            self.mismatches.remove(&peer_id);
+            return;
+        }
+
+        let applied = |versions: &[(PeerId, u64)]| versions.iter().map(|&(_, seq)| seq).sum::<u64>();
+        let (ours, theirs) = (applied(&self.versions(transport)), applied(&versions));
+        if ours > theirs || ours == theirs && transport.peer_id() < peer_id {
+            return;
+        }
+
+        let mismatches = self.mismatches.entry(peer_id).or_default();
+        *mismatches += 1;
+        if *mismatches >= REPAIR_MISMATCHES {
+            self.mismatches.remove(&peer_id);
+            self.diverged = true;
+            self.resync.get_or_insert(peer_id);
+            self.resync(transport);
+        }
+    }
+
+    /// Stops listening to `peer_id` in every room, forgetting it was in the current one.
+    pub fn block(&mut self, peer_id: PeerId) {
+        self.access.block(peer_id);
+        self.peers.remove(&peer_id);
+        self.cursors.remove(&peer_id);
+    }
+
+    /// Handles anything another peer sent: topic payloads are applied and
+    /// attachment requests answered. Anything from a peer [`Access`]
+    /// doesn't admit is dropped, but for it leaving a topic.
+    pub fn handle(&mut self, transport: &mut impl Transport, event: Event) {
+        let source = match &event {
+            Event::Message(incoming) => incoming.source,
+            Event::Request { peer, .. } | Event::Response { peer, .. } | Event::Subscribed { peer, .. } => Some(*peer),
+            Event::Unsubscribed { .. } => None,
+        };
+        if source.is_some_and(|peer_id| !self.access.admits(&peer_id)) {
+            return;
+        }
+
+        if matches!(event, Event::Request { .. } | Event::Response { .. }) {
+            self.finish_chunks();
+        }
+
+        match event {
+            Event::Message(incoming) => self.receive(transport, incoming),
+            Event::Request { id, peer, data } => {
+                let response = match (Message::try_from(data), &mut self.directory) {
+                    (Ok(Message::AttachmentRequest(hash)), _) => Message::AttachmentData {
+                        data: self.attachments.get(&hash).map(<[u8]>::to_vec),
+                        hash,
+                    }.into(),
+                    (Ok(Message::Listing(listing)), Some(directory)) => {
+                        directory.list(listing.clone(), Instant::now());
+                        Message::Listing(listing).into()
+                    },
+                    (Ok(Message::DirectoryRequest), Some(directory)) => Message::Directory(directory.listings(Instant::now())).into(),
+                    (Ok(Message::SyncRequest { versions }), _) => self.answer_sync(transport, peer, versions).into(),
+                    (Ok(message @ (Message::Backup { .. } | Message::BackupRequest { .. })), _) => match self.serve_backup(message) {
+                        Ok(response) => response.into(),
+                        Err(e) => {
+                            println!("Dropped backup from {}: {e}", self.peers.display_name(&peer));
+                            Vec::new()
+                        },
+                    },
+                    _ => {
+                        println!("Dropped invalid request from {}", self.peers.display_name(&peer));
+                        Vec::new()
+                    },
+                };
+
+                if let Err(e) = transport.respond(id, response) {
+                    println!("{e}");
+                }
+            },
+            Event::Subscribed { peer, topic } if topic == self.topic => {
+                // The newcomer learns this peer's nickname without waiting for the heartbeat.
+                if self.peers.joining(peer) {
+                    self.publish(transport, self.presence());
+                }
+
+                // Edits that reached nobody go to the newcomer straight away.
+                let now = Instant::now();
+                self.outbox.wake(now);
+                self.flush_outbox(transport, now);
+
+                self.neighbours.insert(peer);
+                self.request_sync(transport);
+            },
+            Event::Unsubscribed { peer, topic } if topic == self.topic => {
+                self.neighbours.remove(&peer);
+                if let Some(name) = self.peers.remove(&peer) {
+                    self.reorder.forget(&peer);
+                    self.cursors.remove(&peer);
+                    self.claims.remove(&peer);
+                    self.free_locks(&peer, &name);
+
+                    if !self.quiet() {
+                        println!("{name} left #{}", self.room());
+                    }
+                    if self.peers.iter().next().is_none() {
+                        self.vacancy.emptied(Instant::now());
+                    }
+                }
+            },
+            Event::Subscribed { peer, topic } => {
+                let room = self.topic.strip_prefix(&self.topic_prefix);
+
+                if let Some((version, _)) = message::topic_version(&topic).filter(|&(version, other)| version != message::VERSION && Some(other) == room) {
+                    println!(
+                        "{} is in this room with version {version} of the wire format rather than {}, so you can't see each other's edits until one of you updates",
+                        self.peers.display_name(&peer), message::VERSION
+                    );
+                }
+            },
+            Event::Unsubscribed { .. } => {},
+            Event::Response { peer, data } => {
+                let name = self.peers.display_name(&peer);
+
+                match Message::try_from(data) {
+                    Ok(Message::AttachmentData { hash, data: None }) => println!("Peer {name} doesn't hold attachment {hash}"),
+                    Ok(Message::AttachmentData { data: Some(data), .. }) if self.memory_budget.is_some_and(|budget| self.memory_usage().total() + data.len() > budget) => {
+                        println!("Dropped attachment of {} over the memory budget", memory::bytes(data.len()));
+                    },
+                    Ok(Message::AttachmentData { hash, data: Some(data) }) => match self.attachments.receive(&hash, data).cloned() {
+                        Ok(meta) => println!("Downloaded attachment `{}` ({}), save it with `save:{hash}:<path>`", meta.name, memory::bytes(meta.size as usize)),
+                        Err(e @ NotepadError::Integrity(_)) if self.attachments.retry(&hash) => {
+                            println!("Dropped attachment from {name}: {e}, downloading it again");
+
+                            if let Err(e) = self.fetch(transport, &hash) {
+                                println!("{e}");
+                            }
+                        },
+                        Err(e) => println!("Dropped attachment from {name}: {e}"),
+                    },
+                    Ok(Message::Listing(_)) => {},
+                    Ok(Message::Sync { seq, versions, sequences, archive }) => self.finish_sync(transport, peer, seq, versions, sequences, archive),
+                    Ok(Message::Backfill(missed)) => self.finish_backfill(transport, peer, missed),
+                    Ok(Message::Directory(listings)) if listings.is_empty() => println!("No rooms are listed with {name}"),
+                    Ok(Message::Directory(listings)) => {
+                        println!("Rooms listed with {name}:");
+                        for listing in listings {
+                            println!("  {} ({} peers) {}", listing.room, listing.participants, listing.description);
+                        }
+                    },
+                    Ok(Message::BackupAck { log, len }) if log == oplog::name(&self.topic) => {
+                        if let Some(target) = &mut self.backup {
+                            target.ack(len);
+                        }
+                        self.send_backup(transport, Instant::now());
+                    },
+                    Ok(Message::Backup { log, frames, .. }) if log == oplog::name(&self.topic) && self.backup.as_ref().is_some_and(|target| target.restoring) => {
+                        let (records, len) = oplog::read(&frames);
+                        let documents: HashSet<_> = records.iter().map(|record| match record {
+                            Record::Diffs { document, .. } | Record::Text { document, .. } | Record::Tag { document, .. } => document.clone(),
+                        }).collect();
+                        let tags: Vec<_> = records.iter().filter_map(|record| match record {
+                            Record::Tag { document, name, text } => Some(oplog::tag_frame(document, name, text)),
+                            _ => None,
+                        }).collect();
+
+                        println!("Restoring {} changes from the backup on {name}", records.len());
+                        if len < frames.len() {
+                            println!("Dropped {} of the backup that didn't form whole records", memory::bytes(frames.len() - len));
+                        }
+
+                        self.replay(records);
+                        for document in documents {
+                            self.log_text(&document);
+                        }
+                        for frame in tags {
+                            self.log(frame);
+                        }
+
+                        if let Some(target) = &mut self.backup {
+                            target.restoring = false;
+                        }
+                    },
+                    _ => println!("Dropped invalid response from {name}"),
+                }
+            },
+        }
+    }
+
+    /// Asks the best peer to catch up from for the room's documents, unless this
+    /// peer already caught up, hosts the room or has published edits of its own
+    /// that it would lose. After losing every peer it asks again whatever, to
+    /// merge the edits held since.
+    fn request_sync(&mut self, transport: &mut impl Transport) {
+        let rejoining = self.partition.is_lost();
+        if self.sync.is_some() || !rejoining && (self.synced || self.host || self.seq > 0) {
+            return;
+        }
+
+        self.ask_sync(transport, None, Vec::new());
+    }
+
+    /// Sends a sync request with `versions` to the best peer to catch up from
+    /// not asked yet, `preferred` first, trying the next should the request
+    /// fail. Returns the peer asked, if any could be. Edits held back while
+    /// waiting on a peer that didn't answer stay held for the next.
+    fn ask_sync(&mut self, transport: &mut impl Transport, preferred: Option<PeerId>, versions: Vec<(PeerId, u64)>) -> Option<PeerId> {
+        if self.sync.is_none() {
+            self.sources.begin();
+        }
+
+        // Transports that don't report subscriptions reach every peer.
+        let own = transport.peer_id();
+        let reachable: Vec<_> = if self.neighbours.is_empty() {
+            self.peers.iter().map(|(&peer_id, _)| peer_id).collect()
+        } else {
+            self.neighbours.iter().copied().collect()
+        };
+        let candidates: HashSet<_> = reachable.into_iter().chain(preferred).filter(|&peer_id| peer_id != own).collect();
+        let candidates = candidates.into_iter().map(|peer_id| (peer_id, transport.rtt(&peer_id))).collect();
+
+        for peer_id in self.sources.rank(preferred, candidates) {
+            match transport.request(&peer_id, Message::SyncRequest { versions: versions.clone() }.into()) {
+                Ok(()) => {
+                    let held = self.sync.take().map(|(_, held)| held).unwrap_or_default();
+                    self.sync = Some((Instant::now(), held));
+                    self.sources.asked(peer_id, versions);
+                    return Some(peer_id);
+                },
+                Err(e) => {
+                    println!("{e}");
+                    self.sources.failed(peer_id);
+                },
+            }
+        }
+
+        None
+    }
+
+    /// Adopts the documents a peer sent in answer to a sync request, then
+    /// applies the edits held back meanwhile that they don't already include.
+    /// Edits of other peers up to `versions` are in the documents too, so
+    /// they are skipped if they arrive again.
+    fn finish_sync(&mut self, transport: &mut impl Transport, peer_id: PeerId, seq: u64, versions: Vec<(PeerId, u64)>, sequences: Vec<(String, Sequence)>, archive: Archive) {
+        self.sources.answered(peer_id);
+        let size: usize = archive.documents.iter().map(|(_, text)| text.len()).sum();
+
+        if self.memory_budget.is_some_and(|budget| size > budget) {
+            println!("Dropped sync of {} over the memory budget", memory::bytes(size));
+        } else {
+            let mut merged = Vec::new();
+            let mut missing = Vec::new();
+            let mut sequences: HashMap<_, _> = sequences.into_iter().collect();
+
+            for (meta, text) in archive.documents {
+                let id = meta.id.clone();
+                let mut text = self.control_chars.filter_str(&LineEnding::normalize(&text));
+
+                let ours = || self.documents.get(&id).map(|document| document.notepad.text().to_string()).unwrap_or_default();
+                let base = if let Some(theirs) = sequences.remove(&id) {
+                    // Their sequence takes in ours, and they are sent the ops only we had.
+                    let mut sequence = theirs.with_replica(replica(&transport.peer_id()));
+                    if let Some(ours) = self.sequences.remove(&id).filter(|sequence| sequence.matches(ours().chars())) {
+                        let ops = ours.missing(&sequence);
+                        sequence.merge(&ours);
+                        if !ops.is_empty() {
+                            missing.push((id.clone(), ops));
+                        }
+                    }
+
+                    sequence.filter(self.char_filter());
+                    text = sequence.text();
+                    self.sequences.insert(id.clone(), sequence);
+                    self.sync_base.remove(&id);
+                    None
+                } else if self.partition.is_lost() {
+                    let ours = ours();
+                    Some((self.partition.base(&id, &ours), ours))
+                } else {
+                    self.sync_base.remove(&id).map(|base| (base, ours()))
+                };
+
+                if let Some((base, ours)) = base {
+                    let diffs = diff::compute(&text, &merge::three_way(&base, &ours, &text));
+
+                    if !diffs.messages.is_empty() {
+                        merged.push((id.clone(), diffs));
+                    }
+                }
+
+                self.documents.update_meta(meta);
+                *self.documents.get_or_create(&id) = Notepad::new(text);
+                self.log_text(&id);
+            }
+
+            self.synced = true;
+            self.diverged = false;
+            println!("Caught up on the room from {}", self.peers.display_name(&peer_id));
+
+            if self.partition.is_lost() {
+                self.rejoin(transport, merged);
+            } else if !merged.is_empty() {
+                let edits = self.publish_merged(transport, merged);
+                println!("Merged in {edits} operations made while catching up");
+            }
+            for (document, ops) in missing {
+                self.publish_ops(transport, document, ops, "Merged edits".to_string());
+            }
+
+            for edit in self.reorder.start(peer_id, seq + 1) {
+                self.apply_remote(Some(peer_id), edit);
+            }
+            let own = transport.peer_id();
+            for (source, seq) in versions.into_iter().filter(|&(source, _)| source != peer_id && source != own) {
+                for edit in self.reorder.start(source, seq + 1) {
+                    self.apply_remote(Some(source), edit);
+                }
+            }
+        }
+
+        self.release_sync(transport);
+    }
+
+    /// Answers a sync request from `peer_id` with the edits it missed, if
+    /// few enough are and every one is held, otherwise with every document.
+    fn answer_sync(&mut self, transport: &mut impl Transport, peer_id: PeerId, versions: Vec<(PeerId, u64)>) -> Message {
+        self.publish_batch(transport);
+
+        let ours: Vec<_> = self.versions(transport).into_iter().filter(|&(source, _)| source != peer_id).collect();
+        let len = self.documents.iter().map(|document| document.notepad.len()).sum();
+        // Ops of the sequence CRDT aren't held, so those peers are always sent every document.
+        if let Some(missed) = self.backlog.missed(&ours, &versions, len).filter(|_| !self.crdt) {
+            return Message::Backfill(missed);
+        }
+
+        let mut sequences = Vec::new();
+        if self.crdt {
+            let own = transport.peer_id();
+            let ids: Vec<_> = self.documents.iter().map(|document| document.meta.id.clone()).collect();
+            for id in ids {
+                let sequence = self.sequence(own, &id).clone();
+                sequences.push((id, sequence));
+            }
+        }
+
+        Message::Sync { seq: self.seq, versions: self.reorder.versions().collect(), sequences, archive: Archive::new(&self.documents) }
+    }
+
+    /// Asks for the edits this peer is missing, sending what it applied so
+    /// the peer can answer with only those, unless already catching up.
+    /// `peer_id`, whose edits are missing, is asked first.
+    fn request_backfill(&mut self, transport: &mut impl Transport, peer_id: PeerId) {
+        if self.sync.is_some() {
+            return;
+        }
+
+        let versions = self.versions(transport);
+        self.ask_sync(transport, Some(peer_id), versions);
+    }
+
+    /// Applies the edits a peer sent in answer to a sync request, as this
+    /// peer only missed a few, in the order the peer applied them.
+    fn finish_backfill(&mut self, transport: &mut impl Transport, peer_id: PeerId, missed: Vec<Missed>) {
+        self.sources.answered(peer_id);
+
+        // Local edits made meanwhile are published first, as they don't include the missed ones.
+        self.publish_held(transport);
+
+        let own = transport.peer_id();
+        let now = Instant::now();
+        let count = missed.len();
+        for edit in missed.into_iter().filter(|edit| edit.peer != own) {
+            self.backlog.record(edit.clone());
+
+            let Missed { peer, seq, document, diffs, author } = edit;
+            for edit in self.reorder.receive(peer, seq, (document, diffs, author), now) {
+                self.apply_remote(Some(peer), edit);
+            }
+        }
+
+        self.synced = true;
+        if !self.quiet() {
+            println!("Caught up on {count} missed edits from {}", self.peers.display_name(&peer_id));
+        }
+
+        self.release_sync(transport);
+    }
+
+    /// Applies and publishes the diffs merging the edits held while every peer
+    /// was gone into the documents just caught up on, then publishes edits again.
+    fn rejoin(&mut self, transport: &mut impl Transport, merged: Vec<(String, MessageBuf)>) {
+        let edits = self.publish_merged(transport, merged);
+
+        self.partition.rejoined();
+        println!("Back in touch with the room, merged in {edits} operations made meanwhile");
+    }
+
+    /// Applies and publishes diffs merging held local edits into documents
+    /// just caught up on, returning how many there were.
+    fn publish_merged(&mut self, transport: &mut impl Transport, merged: Vec<(String, MessageBuf)>) -> usize {
+        let edits = merged.iter().map(|(_, diffs)| diffs.messages.len()).sum();
+
+        for (document, diffs) in merged {
+            if let Err(e) = self.documents.get_or_create(&document).apply_message_buf(&diffs) {
+                println!("Dropped merged edit: {e}");
+                continue;
+            }
+
+            self.log_diffs(&document, &diffs);
+            self.publish_diffs(transport, document, diffs);
+        }
+
+        edits
+    }
+
+    /// Publishes the local edits held while waiting for a sync that didn't
+    /// bring their documents, as diffs from the text before them.
+    fn publish_held(&mut self, transport: &mut impl Transport) {
+        let held: Vec<_> = self.sync_base.drain().collect();
+
+        for (document, base) in held {
+            let Some(ours) = self.documents.get(&document) else {
+                continue;
+            };
+            let diffs = self.batch.granularity.compute(&base, ours.notepad.text());
+
+            if !diffs.messages.is_empty() {
+                self.publish_diffs(transport, document, diffs);
+            }
+        }
+    }
+
+    /// Stops waiting for a sync, applying the edits held back meanwhile.
+    fn release_sync(&mut self, transport: &mut impl Transport) {
+        self.publish_held(transport);
+        let Some((_, held)) = self.sync.take() else {
+            return;
+        };
+
+        let now = Instant::now();
+        for (peer_id, seq, edit) in held {
+            for edit in self.reorder.receive(peer_id, seq, edit, now) {
+                self.apply_remote(Some(peer_id), edit);
+            }
+        }
+    }
+
+    /// Applies a peer's edit, or queues it behind the chunks still waiting
+    /// if it is larger than [`REMOTE_CHUNK`] or any are.
+    fn apply_remote(&mut self, source: Option<PeerId>, (document, diffs, author): Edit) {
+        if self.chunks.is_empty() && diffs.messages.len() <= REMOTE_CHUNK {
+            return self.apply_now(source, (document, diffs, author));
+        }
+
+        let mut messages = diffs.messages;
+        while !messages.is_empty() {
+            let rest = messages.split_off(messages.len().min(REMOTE_CHUNK));
+            self.chunks.push_back((source, (document.clone(), MessageBuf { messages }, author)));
+            messages = rest;
+        }
+    }
+
+    /// Whether chunks of peers' edits are waiting to be applied.
+    pub fn applying(&self) -> bool {
+        !self.chunks.is_empty()
+    }
+
+    /// Applies the next chunk of a peer's edit, returning how many chunks are left.
+    pub fn apply_chunk(&mut self) -> usize {
+        if let Some((source, edit)) = self.chunks.pop_front() {
+            self.apply_now(source, edit);
+        }
+
+        self.chunks.len()
+    }
+
+    /// Applies every chunk still waiting, before anything that needs the text
+    /// as peers that applied the edits whole have it.
+    fn finish_chunks(&mut self) {
+        while self.apply_chunk() > 0 {}
+    }
+
+    /// Applies edits from `source` to a document, once they are in order.
+    /// Edits signed by a user are credited to the user instead.
+    fn apply_now(&mut self, source: Option<PeerId>, (document, diffs, author): Edit) {
+        let sender = source;
+        let source = author.or(source);
+
+        if self.documents.is_archived(&document) {
+            println!("Dropped edit to archived document");
+            return;
+        }
+
+        let mut diffs = self.control_chars.filter_diffs(diffs);
+        // Text is held with `\n` line endings, a peer's `\r` would only leave mixed endings behind.
+        diffs.messages.retain(|diff| diff.operand != Some('\r'));
+        for diff in &mut diffs.messages {
+            if let Operation::InsStr(text) = &mut diff.opcode {
+                text.retain(|c| c != '\r');
+            }
+        }
+
+        if let Err(e) = self.check_size(&document, &diffs) {
+            let name = source.map_or("Unknown peer".to_string(), |peer_id| self.peers.display_name(&peer_id));
+            return output::error(&format!("Dropped edit from {name}: {e}"));
+        }
+
+        self.apply_checked(sender, (document, diffs, author));
+    }
+
+    /// Applies edits from `source` that passed the checks of
+    /// [`Engine::apply_now`] to a document.
+    fn apply_checked(&mut self, sender: Option<PeerId>, (document, diffs, author): Edit) {
+        let source = author.or(sender);
+        let active = document == self.documents.active_meta().id;
+        let before = self.documents.get_or_create(&document).len();
+        let applied = if active {
+            let mut moving = self.unseen.moving(&document);
+            moving.extend(&mut self.cursor);
+            moving.extend(self.claims.moving(&document));
+            moving.extend(self.selection.iter_mut().flat_map(|range| [&mut range.start, &mut range.end]));
+
+            self.documents.get_or_create(&document).apply_moving(&diffs, &mut moving)
+        } else {
+            self.documents.get_or_create(&document).apply_moving(&diffs, &mut self.claims.moving(&document))
+        };
+
+        match applied {
+            Ok(inverse) => {
+                self.documents.authors.entry(document.clone()).or_default().record(before, &diffs, &inverse, source);
+                self.journal.record(source, &document, &diffs, &inverse, SystemTime::now());
+                self.log_diffs(&document, &diffs);
+
+                // Later diffs don't move the last one, so it is where the edit ended up.
+                if let Some(last) = diffs.messages.last().filter(|_| active) {
+                    self.unseen.push(&document, last.index);
+                }
+
+                if output::is_json() {
+                    let peer = source.map_or(String::new(), |peer_id| peer_id.to_string());
+                    let notepad = self.documents.get_or_create(&document);
+                    output::event("applied", &[
+                        ("peer", (&peer).into()),
+                        ("document", (&document).into()),
+                        ("ops", diffs.messages.len().into()),
+                        ("checksum", (&notepad.checksum()).into()),
+                    ], None);
+                }
+            },
+            Err(e) => {
+                output::error(&format!("Dropped edit: {e}"));
+
+                self.diverged = true;
+                if let Some(peer_id) = sender {
+                    self.resync.get_or_insert(peer_id);
+                }
+            },
+        }
+        self.dashboard.edit(&self.topic, false, Instant::now());
+
+        self.ops_since_snapshot += diffs.messages.len();
+        if let Some(session) = &mut self.session {
+            session.record(source, diffs.messages.len());
+        }
+
+        let now = Instant::now();
+        self.activity.record(source, diffs.messages.len(), now);
+        let conflicted: Vec<_> = diffs.messages.iter().map(|diff| diff.index).filter(|&index| self.conflicts.remote_edit(source, &document, index, now)).collect();
+        if let (Some(&first), Some(&last)) = (conflicted.iter().min(), conflicted.iter().max()) {
+            self.warn_conflict(source, &document, conflict::region(first).start..conflict::region(last).end);
+        }
+
+        if self.plain_output {
+            let name = source.map_or("Unknown peer".to_string(), |peer_id| self.peers.display_name(&peer_id));
+            let document = self.documents.get(&document).map_or(document.as_str(), |document| &document.meta.name);
+
+            for diff in &diffs.messages {
+                println!("{name} {} in `{document}`", describe::diff(diff));
+            }
+        } else if active {
+            self.ops_since_render += diffs.messages.len();
+        }
+    }
+
+    /// Warns that a peer's edit landed in `range` of `document` moments after
+    /// this peer edited there, showing the text there now. Neither edit was
+    /// moved for the other, so either may have landed out of place.
+    fn warn_conflict(&self, source: Option<PeerId>, document: &str, range: Range<usize>) {
+        let Some(ours) = self.documents.get(document) else {
+            return;
+        };
+        let text = ours.notepad.text();
+        let (mut start, mut end) = (range.start.min(text.len()), range.end.min(text.len()));
+        while !text.is_char_boundary(start) {
+            start -= 1;
+        }
+        while !text.is_char_boundary(end) {
+            end += 1;
+        }
+
+        let name = source.map_or("Unknown peer".to_string(), |peer_id| self.peers.display_name(&peer_id));
+        let peer = source.map_or(String::new(), |peer_id| peer_id.to_string());
+        let line = format!(
+            "{name} edited `{}` at {start}..{end} right where you just did, now {:?}. See `verify` to check your copies agree",
+            ours.meta.name,
+            &text[start..end]
+        );
+        output::event("conflict", &[("peer", (&peer).into()), ("document", document.into()), ("start", start.into()), ("end", end.into())], Some(&line));
+    }
+
+    /// Applies a payload published by another peer, answering it if needed.
+    pub fn receive(&mut self, transport: &mut impl Transport, incoming: Incoming) {
+        if incoming.topic == self.cursor_topic() {
+            return self.receive_cursor(incoming);
+        }
+
+        if incoming.topic != self.topic && self.watches.contains_key(&incoming.topic) {
+            return self.receive_watched(incoming);
+        }
+
+        if incoming.topic != self.topic {
+            return self.receive_parked(incoming);
+        }
+
+        let message = decode(incoming.data, self.keys.get(&self.topic));
+
+        if let Some(session) = &mut self.session {
+            session.seen(incoming.source);
+        }
+        // Edits queue behind the chunks, anything else may read or replace the text.
+        if !matches!(message, Ok(Message::Diffs { .. } | Message::Ops { .. } | Message::Signed(_))) {
+            self.finish_chunks();
+        }
+
+        match message {
+            Ok(Message::Diffs { document, seq, diffs }) => self.receive_edit(transport, incoming.source, seq, (document, diffs, None)),
+            Ok(Message::Ops { document, seq, ops }) => self.receive_ops(transport, incoming.source, seq, (document, ops, None)),
+            Ok(Message::Signed(signed)) => {
+                let author = match signed.verify() {
+                    Ok(author) => author,
+                    Err(e) => return println!("Dropped signed edit: {e}"),
+                };
+
+                match decode(signed.payload, None) {
+                    Ok(Message::Diffs { document, seq, diffs }) => {
+                        self.peers.user(author, signed.nickname);
+                        self.receive_edit(transport, incoming.source, seq, (document, diffs, Some(author)));
+                    },
+                    Ok(Message::Ops { document, seq, ops }) => {
+                        self.peers.user(author, signed.nickname);
+                        self.receive_ops(transport, incoming.source, seq, (document, ops, Some(author)));
+                    },
+                    Ok(_) => println!("Dropped signed message that isn't an edit"),
+                    Err(e) => println!("Dropped signed edit: {e}"),
+                }
+            },
+            Ok(Message::Snapshot(snapshot)) if self.memory_budget.is_some_and(|budget| snapshot.text.len() > budget) => {
+                println!("Dropped snapshot of {} over the memory budget", memory::bytes(snapshot.text.len()));
+            },
+            Ok(Message::Snapshot(snapshot)) if self.max_document_size.is_some_and(|max| snapshot.text.len() > max) => {
+                println!("Dropped snapshot of {} over the maximum document size", memory::bytes(snapshot.text.len()));
+            },
+            Ok(Message::Snapshot(snapshot)) if self.crdt && self.sequences.contains_key(&snapshot.document) => {
+                println!("Dropped snapshot of a document edited as a sequence, its ops keep it in step");
+            },
+            Ok(Message::Snapshot(mut snapshot)) => {
+                snapshot.text = self.control_chars.filter_str(&LineEnding::normalize(&snapshot.text));
+                let notepad = self.documents.get_or_create(&snapshot.document);
+
+                if snapshot.text != notepad.text() {
+                    let genesis = self.manifest.and_then(|(_, manifest)| manifest.genesis);
+                    let from_template = genesis.is_some_and(|genesis| genesis == *blake3::hash(snapshot.text.as_bytes()).as_bytes());
+                    notepad.set_text(snapshot.text);
+                    self.log_text(&snapshot.document);
+                    let notepad = self.documents.get_or_create(&snapshot.document);
+
+                    if from_template {
+                        println!("Started from the room's template [{}]", notepad.checksum());
+                    } else if self.plain_output {
+                        let document = self.documents.get(&snapshot.document).expect("document was just updated");
+                        println!("Replaced with the host snapshot. {}", describe::document(&document.meta.name, &document.notepad));
+                    } else {
+                        println!("Notepad diverged from the host snapshot, restored: {notepad:?} [{}]", notepad.checksum());
+                    }
+                }
+            },
+            Ok(Message::Meta(meta)) => {
+                let name = meta.name.clone();
+                let archived = meta.archived;
+                let was_archived = self.documents.is_archived(&meta.id);
+
+                if let Some(previous) = self.documents.update_meta(meta) {
+                    println!("Document `{previous}` was renamed to `{name}`");
+                }
+
+                match (was_archived, archived) {
+                    (false, true) => println!("Document `{name}` was archived"),
+                    (true, false) => println!("Document `{name}` was restored"),
+                    _ => {}
+                }
+            },
+            Ok(Message::Clipboard(_) | Message::Attachment(_) | Message::Chat(_)) if self.muted(incoming.source) => {},
+            Ok(Message::Chat(text)) => {
+                let text = self.control_chars.filter_str(&text);
+                let name = incoming.source.map_or("Unknown peer".to_string(), |peer_id| self.peers.display_name(&peer_id));
+                let peer = incoming.source.map_or(String::new(), |peer_id| peer_id.to_string());
+
+                output::event("chat", &[("peer", (&peer).into()), ("name", (&name).into()), ("text", (&text).into())], Some(&format!("<{name}> {text}")));
+                // Chat another peer relayed from Matrix is already there.
+                if let Some(bridged) = self.bridged.as_mut().filter(|_| !text.starts_with(matrix::RELAYED)) {
+                    bridged.push(format!("#{} <{name}> {text}", self.room));
+                }
+            },
+            Ok(Message::Clipboard(text)) => {
+                let text = self.control_chars.filter_str(&text);
+                println!("Clipboard updated with {} characters, see it with `clip get`", text.chars().count());
+                self.clipboard = Some((incoming.source, text));
+            },
+            Ok(Message::Presence(Presence { nickname, away, reading, selection })) => {
+                let Some(peer_id) = incoming.source else {
+                    return;
+                };
+
+                let previous = self.peers.seen(peer_id, nickname.clone(), away, reading, selection, Instant::now());
+                if previous.is_none() {
+                    self.vacancy.occupied();
+                    // Transports that don't report subscriptions reach every peer.
+                    if self.neighbours.is_empty() || self.neighbours.contains(&peer_id) {
+                        self.request_sync(transport);
+                    }
+                }
+                let name = self.peers.display_name(&peer_id);
+                let was_away = previous.as_ref().is_some_and(|peer| peer.away);
+                let joined = previous.is_none() && self.peers.joined(&peer_id);
+
+                match (previous.is_none(), was_away, away) {
+                    _ if self.quiet() => {},
+                    (true, _, true) if joined => println!("{name} joined #{}, away", self.room()),
+                    (true, _, false) if joined => println!("{name} joined #{}", self.room()),
+                    (true, _, true) => println!("Peer {name} is in the room, away"),
+                    (true, _, false) => println!("Peer {name} is in the room"),
+                    (false, false, true) => println!("Peer {name} is away"),
+                    (false, true, false) => println!("Peer {name} is back"),
+                    _ => {}
+                }
+
+                let renamed = previous.is_none_or(|peer| peer.nickname != nickname);
+
+                match nickname {
+                    Some(nickname) if renamed && self.peers.nickname.as_ref() == Some(&nickname) => {
+                        println!("Peer {name} announced your nickname `{nickname}`, change yours with `nick:<name>` to avoid confusion");
+                    },
+                    Some(nickname) if renamed && self.peers.nickname_users(&nickname) > 1 => {
+                        println!("Several peers use the nickname `{nickname}`, showing this one as `{name}`");
+                    },
+                    _ => {}
+                }
+            },
+            Ok(Message::Probe(id)) => self.publish(transport, Message::ProbeAck(id)),
+            Ok(Message::ProbeAck(id)) => {
+                if let Some(peer_id) = incoming.source {
+                    self.latency.ack(peer_id, id, Instant::now());
+                }
+            },
+            Ok(Message::Attachment(meta)) => {
+                let name = incoming.source.map_or("unknown".to_string(), |peer_id| self.peers.display_name(&peer_id));
+
+                println!("Peer {name} attached `{}` ({}), download it with `fetch:{}`", meta.name, memory::bytes(meta.size as usize), meta.hash);
+                self.attachments.announce(meta, incoming.source);
+            },
+            Ok(Message::SnapshotRequest) => {
+                if self.host {
+                    self.publish_snapshots(transport);
+                }
+            },
+            Ok(Message::Board { board, ops }) => {
+                if let Some((peer_id, reason)) = self.refuses_edits(incoming.source) {
+                    return println!("Dropped drawing from {}, {reason}", self.peers.display_name(&peer_id));
+                }
+
+                let count = ops.len();
+                let whiteboard = self.documents.boards.entry(board.clone()).or_default();
+                for op in ops {
+                    whiteboard.apply(op);
+                }
+                self.dashboard.edit(&self.topic, true, Instant::now());
+
+                if self.plain_output {
+                    let name = incoming.source.map_or("Unknown peer".to_string(), |peer_id| self.peers.display_name(&peer_id));
+                    println!("{name} changed {count} strokes on board `{board}`, see it with `board:{board}`");
+                }
+            },
+            Ok(Message::Runs { .. }) => unreachable!("runs are expanded into diffs"),
+            Ok(Message::Sealed { .. }) => unreachable!("sealed messages are opened"),
+            Ok(Message::AttachmentRequest(_) | Message::AttachmentData { .. }) => {
+                println!("Dropped attachment transfer published to the room");
+            },
+            Ok(Message::Listing(_) | Message::DirectoryRequest | Message::Directory(_)) => {
+                println!("Dropped directory message published to the room");
+            },
+            Ok(Message::SyncRequest { .. } | Message::Sync { .. } | Message::Backfill(_)) => {
+                println!("Dropped sync published to the room");
+            },
+            Ok(Message::Backup { .. } | Message::BackupRequest { .. } | Message::BackupAck { .. }) => {
+                println!("Dropped backup published to the room");
+            },
+            Ok(Message::Cursor { .. }) => {},
+            Ok(Message::Lock { document, held }) => self.receive_lock(transport, incoming.source, document, held),
+            Ok(Message::Claim { document, range }) => self.receive_claim(incoming.source, document, range),
+            Ok(Message::Applied { author, seq }) if self.acknowledge && author == transport.peer_id() => {
+                if let Some(peer_id) = incoming.source {
+                    self.recent_edits.applied(peer_id, seq);
+                }
+            },
+            // Acknowledgements of other peers' edits are for them only.
+            Ok(Message::Applied { .. }) => {},
+            Ok(Message::Manifest(manifest)) => self.receive_manifest(transport, incoming.source, manifest),
+            Ok(Message::Permission { peer, write }) => self.receive_permission(transport, incoming.source, peer, write),
+            Ok(Message::Verify { document, hash, versions, answer }) => self.receive_verify(transport, incoming.source, document, hash, versions, answer),
+            Ok(Message::Digest { document, hash, versions }) => self.receive_digest(transport, incoming.source, document, hash, versions),
+            Ok(Message::ManifestAck(admission)) => {
+                let Some(peer_id) = incoming.source else {
+                    return;
+                };
+
+                if self.admissions.insert(peer_id, admission) != Some(admission) && !self.quiet() {
+                    println!("Peer {} acknowledged the room manifest, taking part {admission}", self.peers.display_name(&peer_id));
+                }
+            },
+            Ok(Message::SessionSummary(summary)) => {
+                let name = incoming.source.map_or("Unknown peer".to_string(), |peer_id| self.peers.display_name(&peer_id));
+                let matches = self.documents
+                    .visible()
+                    .find(|document| document.meta.name == summary.document)
+                    .is_some_and(|document| document.notepad.checksum() == summary.checksum);
+
+                println!(
+                    "{name} ended a session on `{}`: {} participants, {} ops, checksum {} ({})",
+                    summary.document, summary.participants, summary.ops, summary.checksum,
+                    if matches { "matches ours" } else { "differs from ours" },
+                );
+            },
+            Err(e @ NotepadError::Integrity(_)) => {
+                println!("Dropped corrupted message: {e}");
+                self.request_snapshots(transport, Instant::now());
+            },
+            Err(e) => println!("Dropped invalid message: {e}"),
+        }
+    }
+
+    /// Applies ops of a document's sequence as the diffs they make to its
+    /// text. They aren't put in order or held while catching up like diffs,
+    /// the sequence holds ops back until what they refer to arrives and
+    /// merges whatever a sync brings. Ops are checked like diffs before the
+    /// sequence takes them, so it always holds the text.
+    fn receive_ops(&mut self, transport: &mut impl Transport, source: Option<PeerId>, seq: u64, (document, ops, author): (String, Vec<Op>, Option<PeerId>)) {
+        if let Some((peer_id, reason)) = self.refuses_edits(source) {
+            return println!("Dropped edit from {}, {reason}", self.peers.display_name(&peer_id));
+        }
+        if self.documents.is_archived(&document) {
+            return println!("Dropped edit to archived document");
+        }
+
+        let filter = self.char_filter();
+        let growth: usize = ops.iter().filter_map(|op| match op {
+            Op::Insert { value, .. } => filter(*value).map(char::len_utf8),
+            Op::Delete(_) => None,
+        }).sum();
+        if let Err(e) = self.check_growth(&document, growth as isize) {
+            let name = author.or(source).map_or("Unknown peer".to_string(), |peer_id| self.peers.display_name(&peer_id));
+            return output::error(&format!("Dropped edit from {name}: {e}"));
+        }
+
+        let from = source.map_or(0, |peer_id| replica(&peer_id));
+        let sequence = self.sequence(transport.peer_id(), &document);
+        let messages: Vec<_> = ops.into_iter().flat_map(|op| sequence.apply(from, op, filter)).collect();
+        if !messages.is_empty() {
+            // Chunks were applied before the sequence was, and the diffs were checked as ops.
+            self.apply_checked(source, (document, MessageBuf { messages }, author));
+        }
+
+        if let Some(peer_id) = source.filter(|_| self.acknowledge) {
+            self.publish(transport, Message::Applied { author: peer_id, seq });
+        }
+    }
+
+    fn receive_edit(&mut self, transport: &mut impl Transport, source: Option<PeerId>, seq: u64, edit: Edit) {
+        if let Some((peer_id, reason)) = self.refuses_edits(source) {
+            return println!("Dropped edit from {}, {reason}", self.peers.display_name(&peer_id));
+        }
+
+        if let Some(peer) = source.filter(|_| seq > 0) {
+            let (document, diffs, author) = edit.clone();
+            self.backlog.record(Missed { peer, seq, document, diffs, author });
+        }
+
+        match (source, &mut self.sync) {
+            (Some(peer_id), Some((_, held))) if seq > 0 => held.push((peer_id, seq, edit)),
+            (Some(peer_id), None) if seq > 0 => {
+                let applied = self.reorder.receive(peer_id, seq, edit, Instant::now());
+                let acknowledge = self.acknowledge && !applied.is_empty();
+                for edit in applied {
+                    self.apply_remote(Some(peer_id), edit);
+                }
+
+                if acknowledge {
+                    let seq = self.reorder.versions().find(|&(author, _)| author == peer_id).map(|(_, seq)| seq);
+                    if let Some(seq) = seq {
+                        self.publish(transport, Message::Applied { author: peer_id, seq });
+                    }
+                }
+            },
+            (source, _) => self.apply_remote(source, edit),
+        }
+
+        self.resync(transport);
+    }
+
+    /// Catches up on the room as this peer's copies diverged from the room's,
+    /// from the peer whose edit didn't fit first, unless already catching up.
+    fn resync(&mut self, transport: &mut impl Transport) {
+        if self.sync.is_some() {
+            return;
+        }
+        let Some(peer_id) = self.resync.take() else {
+            return;
+        };
+
+        if let Some(peer_id) = self.ask_sync(transport, Some(peer_id), Vec::new()) {
+            println!("Copies of the room diverged, catching up from {}", self.peers.display_name(&peer_id));
+        }
+    }
+
+    /// Handles a message published to a watched room, printing the changes
+    /// that match its patterns. Edits are only searched, so they are decoded
+    /// in place instead of into diffs of their own.
+    fn receive_watched(&mut self, incoming: Incoming) {
+        let Some(watch) = self.watches.get_mut(&incoming.topic) else {
+            return;
+        };
+        if let Some(peer_id) = incoming.source {
+            self.dashboard.seen(&incoming.topic, peer_id, Instant::now());
+        }
+        let mut name = incoming.source.map_or("Unknown peer".to_string(), |peer_id| self.peers.display_name(&peer_id));
+
+        // An edit that breaks off is searched up to where it does.
+        if let Some((document, diffs)) = message::diffs_view(&incoming.data) {
+            self.dashboard.edit(&incoming.topic, true, Instant::now());
+            let patterns = watch.inserted(diffs.map_while(Result::ok));
+
+            if !patterns.is_empty() {
+                println!("[{}] {name} wrote {} in `{document}`", watch.room, patterns.join(", "));
+            }
+            return;
+        }
+
+        let message = match decode(incoming.data, self.keys.get(&incoming.topic)) {
+            Ok(Message::Signed(signed)) if signed.verify().is_ok() => {
+                name = signed.nickname;
+                decode(signed.payload, None)
+            },
+            message => message,
+        };
+
+        match message {
+            Ok(Message::Diffs { document, diffs, .. }) => {
+                self.dashboard.edit(&incoming.topic, true, Instant::now());
+                let patterns = watch.inserted(diffs.messages.iter().map(Diff::borrowed));
+
+                if !patterns.is_empty() {
+                    println!("[{}] {name} wrote {} in `{document}`", watch.room, patterns.join(", "));
+                }
+            },
+            Ok(Message::Snapshot(snapshot)) => {
+                for (added, line) in watch.snapshot(&snapshot.document, &LineEnding::normalize(&snapshot.text)) {
+                    let change = if added { "now reads" } else { "no longer reads" };
+                    println!("[{}] A line of `{}` {change}: {}", watch.room, snapshot.document, self.control_chars.filter_str(&line));
+                }
+            },
+            _ => {},
+        }
+    }
+
+    /// Takes a cursor published on the room's cursor topic. Anything else
+    /// there is dropped, as is a cursor past the end of its document.
+    fn receive_cursor(&mut self, incoming: Incoming) {
+        let (Some(peer_id), Ok(Message::Cursor { document, index })) = (incoming.source, decode(incoming.data, self.keys.get(&self.topic))) else {
+            return;
+        };
+        let Some(index) = self.documents.get(&document).and_then(|document| (index <= document.notepad.len() as u64).then_some(index as usize)) else {
+            return;
+        };
+
+        self.cursors.update(peer_id, document, index, Instant::now());
+    }
+
+    fn receive_parked(&mut self, incoming: Incoming) {
+        let Some(documents) = self.parked.get_mut(&incoming.topic) else {
+            return;
+        };
+        if let Some(peer_id) = incoming.source {
+            self.dashboard.seen(&incoming.topic, peer_id, Instant::now());
+        }
+
+        let message = decode(incoming.data, self.keys.get(&incoming.topic));
+
+        match message {
+            Ok(Message::Diffs { document, diffs, .. }) => {
+                let diffs = self.control_chars.filter_diffs(diffs);
+
+                match documents.get_or_create(&document).apply_message_buf(&diffs) {
+                    Ok(()) => self.dashboard.edit(&incoming.topic, true, Instant::now()),
+                    Err(e) => println!("Dropped edit: {e}"),
+                }
+            },
+            Ok(Message::Snapshot(snapshot)) => {
+                documents.get_or_create(&snapshot.document).set_text(self.control_chars.filter_str(&LineEnding::normalize(&snapshot.text)));
+            },
+            Ok(Message::Board { board, ops }) => {
+                let whiteboard = documents.boards.entry(board).or_default();
+                for op in ops {
+                    whiteboard.apply(op);
+                }
+                self.dashboard.edit(&incoming.topic, true, Instant::now());
+            },
+            _ => {},
+        }
+    }
+}
+
+/// A payload waiting to be published in words, see [`Engine::pending`].
+fn describe_payload(data: &[u8], key: Option<&RoomKey>, documents: Option<&Documents>) -> String {
+    let message = decode(data.to_vec(), key).and_then(|message| match message {
+        Message::Signed(signed) => decode(signed.payload, None),
+        message => Ok(message),
+    });
+
+    match message {
+        Ok(Message::Diffs { document, diffs, .. }) => {
+            let name = documents.and_then(|documents| documents.get(&document)).map_or(document.as_str(), |document| &document.meta.name);
+            format!("{} to `{name}`", diffs.summary())
+        },
+        Ok(_) => "A message other than an edit".to_string(),
+        Err(e) => format!("Unreadable payload: {e}"),
+    }
+}
+
+/// Decodes a payload, opening it with `key` if it is sealed and expanding
+/// runs into the diffs they stand for. With a key, every payload must
+/// arrive sealed, see [`RoomKey`].
+fn decode(data: Vec<u8>, key: Option<&RoomKey>) -> Result<Message, NotepadError> {
+    let message = match (Message::try_from(data)?, key) {
+        (Message::Sealed { nonce, ciphertext }, Some(key)) => key.open(&nonce, &ciphertext)?,
+        (Message::Sealed { .. }, None) => return Err(NotepadError::Decode("Sealed message from a room joined without its passphrase")),
+        (_, Some(_)) => return Err(NotepadError::Decode("Unsealed message in a private room")),
+        (message, None) => message,
+    };
+
+    Ok(match message {
+        Message::Runs { document, seq, runs } => Message::Diffs { document, seq, diffs: MessageBuf::expand(runs) },
+        message => message,
+    })
+}
+
+/// The peer that created `room` with [`Engine::create_room`], which names it
+/// `<name>@<peer id>`. A peer id is derived from the peer's public key, so
+/// only that peer can sign its manifest and permissions. Other rooms have no
+/// creator.
+fn owner(room: &str) -> Option<PeerId> {
+    room.rsplit_once('@').and_then(|(_, peer_id)| peer_id.parse().ok())
+}
+
+/// Replica a peer edits sequences as, taken from its peer id.
+fn replica(peer_id: &PeerId) -> u64 {
+    u64::from_le_bytes(blake3::hash(&peer_id.to_bytes()).as_bytes()[..8].try_into().expect("hash is 32 bytes"))
+}
+
+/// The gossipsub topic for `room`. Rooms with a passphrase use a MAC of the
+/// room name keyed by the passphrase instead of the name, so outsiders can't
+/// find the topic by guessing the room name.
+fn room_topic(topic_prefix: &str, room: &str, key: Option<&RoomKey>) -> String {
+    match key {
+        None => format!("{topic_prefix}{room}"),
+        Some(key) => format!("{topic_prefix}{}", key.topic_id(room)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::FutureExt;
+
+    use super::*;
+    use crate::{
+        causal::HOLD_TIMEOUT,
+        delivery::ACK_TIMEOUT,
+        diff::{Diff, Operation},
+        document::Documents,
+        loopback::Loopback,
+        pacing::INTERACTIVE_GRACE,
+        storage::Files,
+        whiteboard::{Color, Stroke}
+    };
+
+    fn def_peer(transport: &mut Loopback) -> Engine {
+        let engine = Engine::new("room", "p2p-notepad/v1/", Notepad::new("hello world".to_string()));
+        transport.subscribe(engine.topic()).unwrap();
+
+        engine
+    }
+
+    async fn receive_next(engine: &mut Engine, transport: &mut Loopback) {
+        let event = transport.next_event().await.unwrap();
+
+        engine.handle(transport, event);
+    }
+
+    /// Handles every event already waiting, in order.
+    fn receive_all(engine: &mut Engine, transport: &mut Loopback) {
+        while let Some(Some(event)) = transport.next_event().now_or_never() {
+            engine.handle(transport, event);
+        }
+    }
+
+    fn ins(index: usize, char: char) -> MessageBuf {
+        MessageBuf { messages: vec![Diff { opcode: Operation::Ins, operand: Some(char), index }] }
+    }
+
+    #[tokio::test]
+    async fn edits_sync_between_peers() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+
+        a.edit(&mut a_transport, ins(0, 'X')).unwrap();
+        receive_next(&mut b, &mut b_transport).await;
+
+        assert_eq!(b.documents.active().text(), "Xhello world");
+        assert_eq!(b.ops_since_render, 1);
+        assert_eq!(a.recent_edits.iter().next().unwrap().1, &Delivery::Delivered(1));
+
+        assert!(a.edit(&mut a_transport, ins(100, 'X')).is_err());
+        assert_eq!(a.recent_edits.iter().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn applied_edits_are_acknowledged() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+        a.acknowledge = true;
+        b.acknowledge = true;
+
+        a.edit(&mut a_transport, ins(0, 'X')).unwrap();
+        a.edit(&mut a_transport, ins(1, 'Y')).unwrap();
+        assert_eq!(a.recent_edits.iter().map(|(_, _, applied)| applied).collect::<Vec<_>>(), vec![Some((0, 1)), Some((0, 1))]);
+
+        receive_next(&mut b, &mut b_transport).await;
+        receive_next(&mut a, &mut a_transport).await;
+        assert_eq!(a.recent_edits.iter().map(|(_, _, applied)| applied).collect::<Vec<_>>(), vec![Some((0, 1))]);
+
+        receive_next(&mut b, &mut b_transport).await;
+        receive_next(&mut a, &mut a_transport).await;
+        assert_eq!(a.recent_edits.iter().count(), 0);
+        assert!(a.recent_edits.overdue(Instant::now() + ACK_TIMEOUT * 2).is_empty());
+    }
+
+    #[tokio::test]
+    async fn edits_are_credited_to_their_authors() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+
+        a.edit(&mut a_transport, ins(0, 'X')).unwrap();
+        receive_next(&mut b, &mut b_transport).await;
+        b.edit(&mut b_transport, ins(1, 'Y')).unwrap();
+
+        let runs = vec![(Some(a_transport.peer_id()), "X"), (Some(b_transport.peer_id()), "Y"), (None, "hello world")];
+        assert_eq!(b.documents.authors["main"].runs(b.documents.active().text()), runs);
+
+        let journal: Vec<_> = b.journal.recent(10).map(|entry| (entry.seq, entry.author, entry.diff.index)).collect();
+        assert_eq!(journal, vec![(1, Some(a_transport.peer_id()), 0), (2, Some(b_transport.peer_id()), 1)]);
+    }
+
+    #[tokio::test]
+    async fn verify_is_answered_with_the_peer_hash() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+
+        a.edit(&mut a_transport, ins(0, 'X')).unwrap();
+        receive_next(&mut b, &mut b_transport).await;
+        assert_eq!(a.versions(&a_transport), b.versions(&b_transport));
+
+        b.documents.active_mut().set_text("Xhello world!".to_string());
+        a.verify(&mut a_transport);
+        receive_next(&mut b, &mut b_transport).await;
+
+        let Some(Event::Message(incoming)) = a_transport.next_event().await else {
+            panic!("expected an answer");
+        };
+        let Ok(Message::Verify { hash, answer: true, .. }) = Message::try_from(incoming.data) else {
+            panic!("expected a verify answer");
+        };
+        assert_eq!(hash, *blake3::hash(b"Xhello world!").as_bytes());
+    }
+
+    #[tokio::test]
+    async fn remote_edits_move_the_cursor() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+        b.cursor = Some("hello ".len());
+
+        a.edit(&mut a_transport, ins(0, 'X')).unwrap();
+        receive_next(&mut b, &mut b_transport).await;
+        a.edit(&mut a_transport, ins(7, '!')).unwrap();
+        receive_next(&mut b, &mut b_transport).await;
+
+        assert_eq!(&b.documents.active().text()[b.cursor.unwrap()..], "!world");
+    }
+
+    #[tokio::test]
+    async fn edits_off_screen_stay_unread_until_caught_up() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+        let text = "one\ntwo\nthree\nfour".to_string();
+        a.documents.active_mut().set_text(text.clone());
+        b.documents.active_mut().set_text(text.clone());
+        b.cursor = Some(0);
+        b.view(0..2);
+
+        a.edit(&mut a_transport, ins(text.find("four").unwrap(), '4')).unwrap();
+        receive_next(&mut b, &mut b_transport).await;
+        a.edit(&mut a_transport, ins(text.find("two").unwrap(), '2')).unwrap();
+        receive_next(&mut b, &mut b_transport).await;
+        b.view(0..2);
+        assert_eq!(b.unseen.in_document(&b.documents.active_meta().id).len(), 1);
+
+        // Local edits before an unread one move it along.
+        b.edit(&mut b_transport, ins(0, '1')).unwrap();
+        assert_eq!(b.catch_up(), Some((3, 0)));
+        assert_eq!(&b.documents.active().text()[b.cursor.unwrap()..], "4four");
+        assert_eq!(b.catch_up(), None);
+
+        receive_next(&mut a, &mut a_transport).await;
+        b.set_away(&mut b_transport, true);
+        receive_next(&mut a, &mut a_transport).await;
+        let (_, peer) = a.peers.iter().next().unwrap();
+        assert_eq!(peer.reading.as_ref().map(|reading| reading.lines.clone()), Some(0..2));
+    }
+
+    #[tokio::test]
+    async fn cursors_reach_peers_and_expire() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+        b_transport.subscribe(&b.cursor_topic()).unwrap();
+        let document = a.documents.active_meta().id.clone();
+        let now = Instant::now();
+
+        // Nothing is published until the editor has a cursor.
+        a.publish_cursor(&mut a_transport, now);
+        a.cursor = Some("hello ".len());
+        a.publish_cursor(&mut a_transport, now);
+        receive_next(&mut b, &mut b_transport).await;
+        assert_eq!(b.cursors.in_document(&document), vec![(a_transport.peer_id(), 6)]);
+
+        // A cursor moved again straight away waits for the next interval.
+        a.cursor = Some(0);
+        a.publish_cursor(&mut a_transport, now);
+        a.publish_cursor(&mut a_transport, now + CURSOR_INTERVAL);
+        receive_next(&mut b, &mut b_transport).await;
+        assert_eq!(b.cursors.in_document(&document), vec![(a_transport.peer_id(), 0)]);
+
+        b.cursors.expire(Instant::now() + cursors::CURSOR_TTL * 2);
+        assert!(b.cursors.in_document(&document).is_empty());
+    }
+
+    #[tokio::test]
+    async fn subscriptions_announce_peers_joining_and_leaving() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+        let b_id = b_transport.peer_id();
+        a.peers.nickname = Some("alice".to_string());
+        b.peers.nickname = Some("bob".to_string());
+
+        // Subscribing to another topic isn't joining the room.
+        a.handle(&mut a_transport, Event::Subscribed { peer: b_id, topic: "elsewhere".to_string() });
+        a.handle(&mut a_transport, Event::Subscribed { peer: b_id, topic: a.topic().to_string() });
+        receive_next(&mut b, &mut b_transport).await;
+        assert_eq!(b.peers.display_name(&a_transport.peer_id()), "alice");
+
+        // Seeing alice for the first time, bob asks her for a sync first.
+        receive_next(&mut a, &mut a_transport).await;
+        b.heartbeat(&mut b_transport, Duration::from_secs(30), Instant::now());
+        receive_next(&mut a, &mut a_transport).await;
+        assert_eq!(a.peers.display_name(&b_id), "bob");
+        assert!(!a.peers.joined(&b_id));
+
+        a.handle(&mut a_transport, Event::Unsubscribed { peer: b_id, topic: a.topic().to_string() });
+        assert_eq!(a.peers.iter().count(), 0);
+
+        // With bob gone, the room is saved once alice is idle long enough.
+        let idle = Duration::from_secs(60);
+        assert!(a.vacancy.due(Instant::now() - idle, idle, Instant::now() + idle));
+    }
+
+    #[tokio::test]
+    async fn rooms_count_unread_edits_until_reopened() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+        let room = a.topic().to_string();
+
+        a.watch(&mut a_transport, "room", None, vec!["todo".to_string()]).unwrap();
+        a.switch_room(&mut a_transport, "other", None).unwrap();
+        b.edit(&mut b_transport, ins(0, 'X')).unwrap();
+        receive_next(&mut a, &mut a_transport).await;
+
+        let rooms = a.rooms(Duration::from_secs(30), Instant::now());
+        assert_eq!(rooms.iter().map(|room| (room.room.as_str(), room.current, room.unread, room.health)).collect::<Vec<_>>(), vec![
+            ("other", true, 0, Health::Alone),
+            ("room", false, 1, Health::Watching),
+        ]);
+        assert_eq!(rooms[1].peers, 1);
+        assert!(rooms[1].last_edit.is_some());
+
+        a.reopen(&mut a_transport, &room).unwrap();
+        assert_eq!(a.room(), "room");
+        assert_eq!(a.rooms(Duration::from_secs(30), Instant::now())[1].room, "other");
+        assert_eq!(a.dashboard.unread(&room), 0);
+        assert_eq!(a.room_of(&cursors::topic(&room)), None);
+    }
+
+    #[tokio::test]
+    async fn edits_overtake_bulk_snapshots() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+
+        a.edit(&mut a_transport, ins(0, 'X')).unwrap();
+        a.publish_snapshots(&mut a_transport);
+        a.edit(&mut a_transport, ins(0, 'Y')).unwrap();
+        assert!(a.next_bulk().is_some());
+
+        receive_next(&mut b, &mut b_transport).await;
+        receive_next(&mut b, &mut b_transport).await;
+        assert_eq!(b.documents.active().text(), "YXhello world");
+        assert!(b_transport.next_event().now_or_never().is_none());
+
+        // The snapshot has the edits made while it waited.
+        a.flush_bulk(&mut a_transport, Instant::now() + INTERACTIVE_GRACE);
+        assert_eq!(a.next_bulk(), None);
+        b.documents.active_mut().set_text(String::new());
+        receive_next(&mut b, &mut b_transport).await;
+        receive_next(&mut b, &mut b_transport).await;
+        assert_eq!(b.documents.active().text(), "YXhello world");
+    }
+
+    #[tokio::test]
+    async fn edits_past_index_255_sync() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+        let long = "a".repeat(300);
+        a.documents.active_mut().set_text(long.clone());
+        b.documents.active_mut().set_text(long.clone());
+
+        a.edit(&mut a_transport, ins(300, 'X')).unwrap();
+        receive_next(&mut b, &mut b_transport).await;
+
+        assert_eq!(b.documents.active().text(), format!("{long}X"));
+    }
+
+    #[tokio::test]
+    async fn multibyte_edits_sync() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+
+        a.edit(&mut a_transport, ins(0, 'λ')).unwrap();
+        receive_next(&mut b, &mut b_transport).await;
+        a.edit(&mut a_transport, ins(2, '🦀')).unwrap();
+        receive_next(&mut b, &mut b_transport).await;
+
+        assert_eq!(b.documents.active().text(), "λ🦀hello world");
+    }
+
+    #[tokio::test]
+    async fn rooms_are_listed_in_the_directory() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+        a.directory = Some(Directory::default());
+        b.directory_peer = Some(a_transport.peer_id());
+
+        b.list_room(&mut b_transport, "open notes").unwrap();
+        receive_next(&mut a, &mut a_transport).await;
+        receive_next(&mut b, &mut b_transport).await;
+
+        let listings = a.directory.as_mut().unwrap().listings(Instant::now());
+        assert_eq!(listings, vec![RoomListing { room: b.room().to_string(), description: "open notes".to_string(), participants: 1 }]);
+
+        b.switch_room(&mut b_transport, "private", Some("secret")).unwrap();
+        assert!(b.list_room(&mut b_transport, "hidden").is_err());
+        assert!(a.query_directory(&mut a_transport).is_err());
+    }
+
+    #[tokio::test]
+    async fn logs_are_backed_up_to_a_peer() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut c_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+        let mut c = def_peer(&mut c_transport);
+        let dir = std::env::temp_dir().join(format!("p2p-notepad-backups-{}", rand::random::<u64>()));
+        a.backup = Some(BackupTarget::new(b_transport.peer_id(), "secret"));
+        b.backups = Some(Backups::new(dir.clone()));
+
+        a.edit(&mut a_transport, ins(0, 'X')).unwrap();
+        a.send_backup(&mut a_transport, Instant::now());
+        receive_next(&mut b, &mut b_transport).await;
+        receive_next(&mut c, &mut c_transport).await;
+        receive_next(&mut b, &mut b_transport).await;
+        receive_next(&mut a, &mut a_transport).await;
+        receive_next(&mut b, &mut b_transport).await;
+        receive_next(&mut a, &mut a_transport).await;
+
+        c.documents.active_mut().set_text("hello world".to_string());
+        c.backup = Some(BackupTarget::new(b_transport.peer_id(), "secret"));
+        c.restore_backup(&mut c_transport).unwrap();
+        receive_next(&mut b, &mut b_transport).await;
+        receive_next(&mut c, &mut c_transport).await;
+        assert_eq!(c.documents.active().text(), "Xhello world");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn watched_rooms_keep_matching_lines() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+
+        b.switch_room(&mut b_transport, "elsewhere", None).unwrap();
+        assert!(b.watch(&mut b_transport, "room", None, Vec::new()).is_err());
+        b.watch(&mut b_transport, "room", None, vec!["@bob".to_string()]).unwrap();
+
+        a.edit(&mut a_transport, MessageBuf { messages: vec![Diff { opcode: Operation::InsStr("@bob ".to_string()), operand: None, index: 0 }] }).unwrap();
+        a.publish_snapshots(&mut a_transport);
+        a.flush_bulk(&mut a_transport, Instant::now() + INTERACTIVE_GRACE);
+        for _ in 0..3 {
+            receive_next(&mut b, &mut b_transport).await;
+        }
+
+        assert_eq!(b.documents.active().text(), "");
+        let watch = b.watches.values_mut().next().unwrap();
+        assert_eq!(watch.snapshot(&a.documents.active_meta().id, ""), vec![(false, "@bob hello world".to_string())]);
+
+        b.unwatch(&mut b_transport, "room").unwrap();
+        assert!(b.unwatch(&mut b_transport, "room").is_err());
+    }
+
+    #[tokio::test]
+    async fn sessions_end_with_a_summary() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+        a.session = Some(Session::new(Duration::from_secs(60), true, Instant::now()));
+
+        a.edit(&mut a_transport, ins(0, 'X')).unwrap();
+        receive_next(&mut b, &mut b_transport).await;
+        b.edit(&mut b_transport, ins(0, 'Y')).unwrap();
+        receive_next(&mut a, &mut a_transport).await;
+
+        let summary = a.end_session(&mut a_transport).unwrap();
+        assert_eq!((summary.participants, summary.ops), (2, 2));
+        assert_eq!(summary.checksum, b.documents.active().checksum());
+        assert!(a.prefs().unwrap().read_only);
+        assert!(a.end_session(&mut a_transport).is_none());
+
+        receive_next(&mut b, &mut b_transport).await;
+    }
+
+    #[tokio::test]
+    async fn pastes_are_appended() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+
+        a.paste(&mut a_transport, "\r\nins:0:x\r\n").unwrap();
+        receive_next(&mut b, &mut b_transport).await;
+
+        assert_eq!(b.documents.active().text(), "hello world\nins:0:x\n");
+    }
+
+    #[tokio::test]
+    async fn documents_are_rebuilt_from_the_oplog() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+        let dir = std::env::temp_dir().join(format!("p2p-notepad-oplogs-{}", rand::random::<u64>()));
+        b.open_oplog(Box::new(Files::new(dir.clone())), true).unwrap();
+
+        a.edit(&mut a_transport, ins(0, 'X')).unwrap();
+        receive_next(&mut b, &mut b_transport).await;
+        b.edit(&mut b_transport, ins(1, 'Y')).unwrap();
+        b.import_text(&mut b_transport, "replaced").unwrap();
+        b.edit(&mut b_transport, ins(0, '>')).unwrap();
+        b.switch_room(&mut b_transport, "other", None).unwrap();
+        b.edit(&mut b_transport, ins(0, 'Z')).unwrap();
+
+        let mut restarted = def_peer(&mut Loopback::default());
+        restarted.open_oplog(Box::new(Files::new(dir.clone())), true).unwrap();
+        assert_eq!(restarted.documents.active().text(), ">replaced");
+
+        restarted.switch_room(&mut Loopback::default(), "other", None).unwrap();
+        assert_eq!(restarted.documents.active().text(), "Z");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn compacted_oplogs_keep_the_text() {
+        let mut transport = Loopback::default();
+        let mut a = def_peer(&mut transport);
+        let dir = std::env::temp_dir().join(format!("p2p-notepad-oplogs-{}", rand::random::<u64>()));
+        a.open_oplog(Box::new(Files::new(dir.clone())), true).unwrap();
+        a.oplog_compact = Some(200);
+
+        for _ in 0..20 {
+            a.edit(&mut transport, ins(0, 'X')).unwrap();
+        }
+        let (before, after) = a.compact_oplog().unwrap();
+        assert!(after < before && before <= 200);
+        a.edit(&mut transport, ins(0, '>')).unwrap();
+
+        let mut restarted = def_peer(&mut Loopback::default());
+        restarted.open_oplog(Box::new(Files::new(dir.clone())), true).unwrap();
+        assert_eq!(restarted.documents.active().text(), a.documents.active().text());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn users_sign_their_edits() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+        let alice = a.users.add("alice").unwrap().peer_id();
+        a.users.add("bob").unwrap();
+
+        a.edit(&mut a_transport, ins(0, 'B')).unwrap();
+        a.users.switch(Some("alice")).unwrap();
+        a.edit(&mut a_transport, ins(0, 'A')).unwrap();
+        receive_next(&mut b, &mut b_transport).await;
+        receive_next(&mut b, &mut b_transport).await;
+
+        assert_eq!(b.documents.active().text(), "ABhello world");
+        assert_eq!(b.peers.display_name(&alice), "alice");
+        let histograms = b.activity.histograms(Instant::now());
+        assert_eq!(histograms.len(), 2);
+        assert!(histograms.iter().all(|(source, _)| *source != Some(a_transport.peer_id())));
+    }
+
+    #[tokio::test]
+    async fn loaded_text_is_sent_as_edits() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+
+        a.load_text(&mut a_transport, "hello there\r\n").unwrap();
+        receive_next(&mut b, &mut b_transport).await;
+
+        assert_eq!(b.documents.active().text(), "hello there\n");
+        assert_eq!(a.recent_edits.iter().next().unwrap().0, "4 ops");
+    }
+
+    #[test]
+    fn edits_are_applied_in_order() {
+        let mut transport = Loopback::default();
+        let mut a = def_peer(&mut transport);
+        let (peer_id, topic) = (PeerId::random(), a.topic().to_string());
+        let incoming = |seq, diff| Incoming {
+            topic: topic.clone(),
+            source: Some(peer_id),
+            data: Message::Diffs { document: "main".to_string(), seq, diffs: MessageBuf { messages: vec![diff] } }.into(),
+        };
+        let ins = |seq, c| incoming(seq, Diff { opcode: Operation::Ins, operand: Some(c), index: 0 });
+        let del = |seq| incoming(seq, Diff { opcode: Operation::Del, operand: None, index: 0 });
+
+        a.receive(&mut transport, ins(1, '>'));
+        a.receive(&mut transport, del(3));
+        assert_eq!(a.documents.active().text(), ">hello world");
+        assert_eq!(a.reorder.held(), 1);
+
+        a.receive(&mut transport, ins(2, 'X'));
+        assert_eq!(a.documents.active().text(), ">hello world");
+        assert_eq!(a.reorder.held(), 0);
+    }
+
+    #[tokio::test]
+    async fn rooms_keep_their_documents() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+
+        a.edit(&mut a_transport, ins(0, 'X')).unwrap();
+        b.switch_room(&mut b_transport, "other", None).unwrap();
+        assert_eq!(b.documents.active().text(), "");
+
+        receive_next(&mut b, &mut b_transport).await;
+        assert_eq!(b.documents.active().text(), "");
+
+        b.switch_room(&mut b_transport, "room", None).unwrap();
+        assert_eq!(b.documents.active().text(), "Xhello world");
+    }
+
+    #[test]
+    fn copies_between_rooms() {
+        let mut transport = Loopback::default();
+        let mut engine = def_peer(&mut transport);
+
+        engine.switch_room(&mut transport, "summary", None).unwrap();
+        assert_eq!(engine.copy_from(&mut transport, "room", 0..5, 0).unwrap(), "hello");
+        engine.copy_from(&mut transport, "summary", 0..4, 5).unwrap();
+        assert_eq!(engine.documents.active().text(), "hellohell");
+
+        assert!(engine.copy_from(&mut transport, "room", 5..40, 0).is_err());
+        assert!(engine.copy_from(&mut transport, "unknown", 0..1, 0).is_err());
+    }
+
+    #[tokio::test]
+    async fn late_joiners_catch_up() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+        a.documents.active_mut().set_text("written before b joined".to_string());
+        a.edit(&mut a_transport, ins(0, '>')).unwrap();
+        receive_next(&mut b, &mut b_transport).await;
+
+        a.heartbeat(&mut a_transport, Duration::from_secs(30), Instant::now());
+        receive_next(&mut b, &mut b_transport).await;
+        assert!(b.sync.is_some());
+
+        a.edit(&mut a_transport, ins(0, '!')).unwrap();
+        receive_next(&mut b, &mut b_transport).await;
+        receive_next(&mut a, &mut a_transport).await;
+        receive_next(&mut b, &mut b_transport).await;
+
+        assert!(b.synced);
+        assert_eq!(b.documents.active().text(), "!>written before b joined");
+    }
+
+    #[tokio::test]
+    async fn edits_made_while_catching_up_are_kept() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+        a.documents.active_mut().set_text("hello world, and more".to_string());
+
+        a.heartbeat(&mut a_transport, Duration::from_secs(30), Instant::now());
+        receive_next(&mut b, &mut b_transport).await;
+        assert!(b.sync.is_some());
+
+        b.edit(&mut b_transport, ins(0, '>')).unwrap();
+        receive_all(&mut a, &mut a_transport);
+        receive_all(&mut b, &mut b_transport);
+        assert!(b.synced);
+        assert_eq!(b.documents.active().text(), ">hello world, and more");
+
+        receive_all(&mut a, &mut a_transport);
+        assert_eq!(a.documents.active().text(), ">hello world, and more");
+    }
+
+    #[tokio::test]
+    async fn concurrent_ops_converge() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+        a.crdt = true;
+        b.crdt = true;
+
+        a.edit(&mut a_transport, ins(5, 'x')).unwrap();
+        b.edit(&mut b_transport, ins(5, 'y')).unwrap();
+        b.edit(&mut b_transport, MessageBuf { messages: vec![Diff { opcode: Operation::Del, operand: None, index: 0 }] }).unwrap();
+        receive_all(&mut a, &mut a_transport);
+        receive_all(&mut b, &mut b_transport);
+
+        assert_eq!(a.documents.active().text(), b.documents.active().text());
+        assert!(["ellox", "elloy"].iter().any(|start| a.documents.active().text().starts_with(start)));
+        assert!(a.documents.active().text().contains('x') && a.documents.active().text().contains('y'));
+    }
+
+    #[tokio::test]
+    async fn filtered_ops_keep_the_sequence() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+        a.crdt = true;
+        b.crdt = true;
+
+        let insert = |text: &str, index| MessageBuf { messages: vec![Diff { opcode: Operation::InsStr(text.to_string()), operand: None, index }] };
+        a.edit(&mut a_transport, insert("\r\x07!", 5)).unwrap();
+        receive_all(&mut b, &mut b_transport);
+        assert_eq!(b.documents.active().text(), "hello! world");
+
+        // Ops after the characters b dropped still land where a put them.
+        a.edit(&mut a_transport, insert("?", 8)).unwrap();
+        receive_all(&mut b, &mut b_transport);
+        assert_eq!(b.documents.active().text(), "hello!? world");
+        assert!(b.sequences["main"].matches(b.documents.active().chars()));
+    }
+
+    #[tokio::test]
+    async fn synced_edits_are_not_applied_again() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+        let edit = Incoming {
+            topic: a.topic().to_string(),
+            source: Some(PeerId::random()),
+            data: Message::Diffs { document: "main".to_string(), seq: 1, diffs: ins(0, '>') }.into(),
+        };
+        a.receive(&mut a_transport, edit.clone());
+
+        a.heartbeat(&mut a_transport, Duration::from_secs(30), Instant::now());
+        receive_next(&mut b, &mut b_transport).await;
+        receive_next(&mut a, &mut a_transport).await;
+        receive_next(&mut b, &mut b_transport).await;
+        assert!(b.synced);
+        assert_eq!(b.documents.active().text(), ">hello world");
+
+        // Arriving late through gossip, the edit is already in the synced text.
+        b.receive(&mut b_transport, edit);
+        assert_eq!(b.documents.active().text(), ">hello world");
+    }
+
+    #[tokio::test]
+    async fn large_edits_are_applied_in_chunks() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+        let messages = (0..REMOTE_CHUNK * 2 + 1).map(|index| Diff { opcode: Operation::Ins, operand: Some('x'), index }).collect();
+
+        a.edit(&mut a_transport, MessageBuf { messages }).unwrap();
+        receive_next(&mut b, &mut b_transport).await;
+        assert!(b.applying() && b.documents.active().text() == "hello world");
+
+        // Edits after it wait their turn, a local one finishes it first.
+        a.edit(&mut a_transport, ins(0, 'A')).unwrap();
+        receive_next(&mut b, &mut b_transport).await;
+        assert_eq!(b.apply_chunk(), 3);
+        assert_eq!(b.documents.active().text().len(), "hello world".len() + REMOTE_CHUNK);
+        b.edit(&mut b_transport, ins(0, 'B')).unwrap();
+        assert!(!b.applying());
+        assert_eq!(b.documents.active().text(), format!("BA{}hello world", "x".repeat(REMOTE_CHUNK * 2 + 1)));
+    }
+
+    #[tokio::test]
+    async fn diverged_copies_catch_up() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+        a.documents.active_mut().set_text("a much longer text than b has".to_string());
+
+        a.publish(&mut a_transport, Message::Diffs { document: "main".to_string(), seq: 0, diffs: ins(20, '!') });
+        receive_next(&mut b, &mut b_transport).await;
+        assert!(b.diverged && b.sync.is_some());
+        assert_eq!(b.documents.active().text(), "hello world");
+
+        receive_next(&mut a, &mut a_transport).await;
+        receive_next(&mut b, &mut b_transport).await;
+        assert!(!b.diverged);
+        assert_eq!(b.documents.active().text(), "a much longer text than b has");
+    }
+
+    #[tokio::test]
+    async fn copies_apart_are_repaired_in_the_background() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+        (a.repair, b.repair) = (true, true);
+        // As if b missed an edit of a's for good.
+        a.documents.active_mut().set_text("hello world!".to_string());
+        a.seq = 1;
+        let now = Instant::now();
+
+        a.publish_digest(&mut a_transport, now);
+        a.publish_digest(&mut a_transport, now + REPAIR_INTERVAL / 2);
+        receive_next(&mut b, &mut b_transport).await;
+        assert!(b.sync.is_none());
+
+        a.publish_digest(&mut a_transport, now + REPAIR_INTERVAL);
+        receive_next(&mut b, &mut b_transport).await;
+        assert!(b.diverged && b.sync.is_some());
+
+        receive_next(&mut a, &mut a_transport).await;
+        receive_next(&mut b, &mut b_transport).await;
+        assert_eq!(b.documents.active().text(), "hello world!");
+    }
+
+    #[tokio::test]
+    async fn plain_output_skips_renders() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+        b.plain_output = true;
+
+        a.edit(&mut a_transport, ins(0, 'X')).unwrap();
+        receive_next(&mut b, &mut b_transport).await;
+
+        assert_eq!(b.documents.active().text(), "Xhello world");
+        assert_eq!(b.ops_since_render, 0);
+    }
+
+    #[tokio::test]
+    async fn concurrent_edits_are_reported() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+
+        a.edit(&mut a_transport, ins(0, 'X')).unwrap();
+        b.edit(&mut b_transport, ins(1, 'Y')).unwrap();
+        receive_next(&mut b, &mut b_transport).await;
+
+        assert_eq!(b.conflicts.by_peer().collect::<Vec<_>>(), vec![(Some(&a_transport.peer_id()), 1)]);
+    }
+
+    #[tokio::test]
+    async fn probes_are_acked() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+
+        a.probe(&mut a_transport);
+        receive_next(&mut b, &mut b_transport).await;
+        receive_next(&mut a, &mut a_transport).await;
+
+        let (peer_id, percentiles) = a.latency.percentiles().next().unwrap();
+        assert_eq!(peer_id, &b_transport.peer_id());
+        assert_eq!(percentiles.samples, 1);
+    }
+
+    #[tokio::test]
+    async fn control_chars_are_stripped() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+
+        a.edit(&mut a_transport, ins(0, '\x1b')).unwrap();
+        a.publish(&mut a_transport, Message::Clipboard("\x1b[2J".to_string()));
+        receive_next(&mut b, &mut b_transport).await;
+        receive_next(&mut b, &mut b_transport).await;
+
+        assert_eq!(b.documents.active().text(), "hello world");
+        assert_eq!(b.clipboard, Some((Some(a_transport.peer_id()), "[2J".to_string())));
+    }
+
+    #[tokio::test]
+    async fn line_endings_are_normalized() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+
+        a.edit(&mut a_transport, ins(0, '\r')).unwrap();
+        receive_next(&mut b, &mut b_transport).await;
+        assert_eq!(b.documents.active().text(), "hello world");
+
+        a.import_text(&mut a_transport, "a\r\nb").unwrap();
+        a.flush_bulk(&mut a_transport, Instant::now() + INTERACTIVE_GRACE);
+        receive_next(&mut b, &mut b_transport).await;
+        assert_eq!(b.documents.active().text(), "a\nb");
+
+        b.documents.set_line_ending(LineEnding::Crlf);
+        assert_eq!(b.export_text(), "a\r\nb");
+    }
+
+    #[test]
+    fn memory_budget() {
+        let mut transport = Loopback::default();
+        let mut engine = def_peer(&mut transport);
+        engine.memory_budget = Some(200);
+        engine.clipboard = Some((None, "x".repeat(400)));
+
+        engine.enforce_memory_budget();
+        assert_eq!(engine.clipboard, None);
+        assert!(engine.memory_usage().total() <= 200);
+
+        let archive = Archive::new(&Documents::new(Notepad::new("x".repeat(400))));
+        assert!(engine.import(&mut transport, archive).is_err());
+        assert_eq!(engine.documents.active().text(), "hello world");
+    }
+
+    #[tokio::test]
+    async fn missed_edits_are_backfilled() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let peer = |transport: &mut Loopback| {
+            let engine = Engine::new("room", "p2p-notepad/v1/", Notepad::new("x".repeat(1000)));
+            transport.subscribe(engine.topic()).unwrap();
+            engine
+        };
+        let mut a = peer(&mut a_transport);
+        let mut b = peer(&mut b_transport);
+
+        a.edit(&mut a_transport, ins(0, 'a')).unwrap();
+        receive_next(&mut b, &mut b_transport).await;
+        a.edit(&mut a_transport, ins(0, 'b')).unwrap();
+        b_transport.next_event().await.unwrap();
+        a.edit(&mut a_transport, ins(0, 'c')).unwrap();
+        receive_next(&mut b, &mut b_transport).await;
+        assert_eq!(b.reorder.held(), 1);
+
+        // The gap is asked of a, which answers with the edits rather than the document.
+        b.heartbeat(&mut b_transport, Duration::from_secs(30), Instant::now() + HOLD_TIMEOUT + Duration::from_secs(1));
+        assert!(b.sync.is_some());
+        receive_all(&mut a, &mut a_transport);
+        let Some(Event::Response { data, .. }) = b_transport.next_event().await else {
+            panic!("expected a response");
+        };
+        assert!(matches!(Message::try_from(data.clone()), Ok(Message::Backfill(missed)) if missed.len() == 2));
+
+        b.handle(&mut b_transport, Event::Response { peer: a_transport.peer_id(), data });
+        assert!(b.sync.is_none());
+        assert_eq!(b.documents.active().text(), a.documents.active().text());
+        assert!(b.documents.active().text().starts_with("cbax"));
+    }
+
+    #[tokio::test]
+    async fn sync_moves_on_to_the_next_source() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut c_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut c = def_peer(&mut c_transport);
+        let (a_id, b_id) = (a_transport.peer_id(), b_transport.peer_id());
+        a.documents.active_mut().set_text("from a".to_string());
+
+        // b advertised having applied more edits, so it is asked first.
+        let document = c.documents.active_meta().id.clone();
+        let data = Message::Digest { document, hash: [0; 32], versions: vec![(b_id, 5)] }.into();
+        c.handle(&mut c_transport, Event::Message(Incoming { topic: c.topic().to_string(), source: Some(b_id), data }));
+        c.neighbours.extend([a_id, b_id]);
+        c.request_sync(&mut c_transport);
+        assert!(matches!(b_transport.next_event().await, Some(Event::Request { .. })));
+
+        // b never answers, so a is asked instead.
+        c.heartbeat(&mut c_transport, Duration::from_secs(30), Instant::now() + SYNC_TIMEOUT + Duration::from_secs(1));
+        receive_all(&mut a, &mut a_transport);
+        receive_all(&mut c, &mut c_transport);
+        assert!(c.sync.is_none());
+        assert_eq!(c.documents.active().text(), "from a");
+    }
+
+    #[tokio::test]
+    async fn snapshot_restores_diverged_peer() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+
+        b.documents.active_mut().set_text("diverged".to_string());
+        a.publish_snapshots(&mut a_transport);
+
+        while b.documents.active().text() != a.documents.active().text() {
+            receive_next(&mut b, &mut b_transport).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn heartbeat_prunes_quiet_peers() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+        let timeout = Duration::from_secs(30);
+
+        b.heartbeat(&mut b_transport, timeout, Instant::now());
+        receive_next(&mut a, &mut a_transport).await;
+        assert_eq!(a.peers.iter().next().unwrap().0, &b_transport.peer_id());
+
+        a.heartbeat(&mut a_transport, timeout, Instant::now());
+        assert_eq!(a.peers.iter().count(), 1);
+
+        a.heartbeat(&mut a_transport, timeout, Instant::now() + timeout * 2);
+        assert_eq!(a.peers.iter().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn edits_are_merged_after_losing_every_peer() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+        let timeout = Duration::from_secs(30);
+        a.synced = true;
+
+        b.heartbeat(&mut b_transport, timeout, Instant::now());
+        receive_next(&mut a, &mut a_transport).await;
+
+        // Neither hears the other any more.
+        a_transport.unsubscribe(a.topic()).unwrap();
+        b_transport.unsubscribe(b.topic()).unwrap();
+        a.heartbeat(&mut a_transport, timeout, Instant::now() + timeout * 2);
+        assert!(a.partition.is_lost());
+
+        a.edit(&mut a_transport, ins(11, '!')).unwrap();
+        b.edit(&mut b_transport, ins(0, '>')).unwrap();
+
+        a_transport.subscribe(a.topic()).unwrap();
+        b_transport.subscribe(b.topic()).unwrap();
+        b.heartbeat(&mut b_transport, timeout, Instant::now());
+        // The presence, the sync request and its answer, then the merged edit.
+        receive_next(&mut a, &mut a_transport).await;
+        receive_next(&mut b, &mut b_transport).await;
+        receive_next(&mut a, &mut a_transport).await;
+        receive_next(&mut b, &mut b_transport).await;
+
+        assert!(!a.partition.is_lost());
+        assert_eq!(a.documents.active().text(), ">hello world!");
+        assert_eq!(b.documents.active().text(), ">hello world!");
+    }
+
+    #[tokio::test]
+    async fn shared_nickname_is_disambiguated() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+
+        a.set_nickname(&mut a_transport, "alice").unwrap();
+        b.set_nickname(&mut b_transport, "alice").unwrap();
+        receive_next(&mut a, &mut a_transport).await;
+        receive_next(&mut b, &mut b_transport).await;
+
+        assert!(a.peers.display_name(&b_transport.peer_id()).starts_with("alice#"));
+        assert!(b.peers.display_name(&a_transport.peer_id()).starts_with("alice#"));
+
+        assert!(a.set_nickname(&mut a_transport, "").is_err());
+        assert!(a.set_nickname(&mut a_transport, "al#ice").is_err());
+    }
+
+    #[tokio::test]
+    async fn away_status_is_announced() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+
+        b.set_away(&mut b_transport, true);
+        receive_next(&mut a, &mut a_transport).await;
+        assert!(a.peers.iter().next().unwrap().1.away);
+
+        b.set_away(&mut b_transport, true);
+        b.set_away(&mut b_transport, false);
+        receive_next(&mut a, &mut a_transport).await;
+        assert!(!a.peers.iter().next().unwrap().1.away);
+    }
+
+    #[tokio::test]
+    async fn selections_are_shared() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+        let document = a.documents.active_meta().id.clone();
+
+        a.select(&mut a_transport, Some(6..11));
+        receive_next(&mut b, &mut b_transport).await;
+        assert_eq!(b.peers.selections(&document), vec![(a_transport.peer_id(), 6..11)]);
+        // B catches up on the room first, its edits are held meanwhile.
+        receive_all(&mut a, &mut a_transport);
+        receive_all(&mut b, &mut b_transport);
+
+        // An edit before it moves the selection along.
+        b.edit(&mut b_transport, ins(0, '>')).unwrap();
+        receive_all(&mut a, &mut a_transport);
+        assert_eq!(a.selection(), Some(7..12));
+
+        a.select(&mut a_transport, Some(3..3));
+        receive_all(&mut b, &mut b_transport);
+        assert!(b.peers.selections(&document).is_empty());
+    }
+
+    #[tokio::test]
+    async fn room_prefs() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+
+        b.prefs_mut().read_only = true;
+        assert!(b.edit(&mut b_transport, ins(0, 'X')).is_err());
+
+        b.prefs_mut().muted.insert(a_transport.peer_id());
+        a.publish(&mut a_transport, Message::Clipboard("hi".to_string()));
+        a.edit(&mut a_transport, ins(0, 'X')).unwrap();
+        receive_next(&mut b, &mut b_transport).await;
+        receive_next(&mut b, &mut b_transport).await;
+        assert_eq!(b.clipboard, None);
+        assert_eq!(b.documents.active().text(), "Xhello world");
+
+        b.switch_room(&mut b_transport, "elsewhere", None).unwrap();
+        assert_eq!(b.prefs(), None);
+        b.edit(&mut b_transport, ins(0, 'X')).unwrap();
+
+        b.observe = true;
+        assert!(b.edit(&mut b_transport, ins(0, 'X')).is_err());
+    }
+
+    #[tokio::test]
+    async fn blocked_peers_are_ignored() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+
+        b.block(a_transport.peer_id());
+        a.edit(&mut a_transport, ins(0, 'X')).unwrap();
+        receive_next(&mut b, &mut b_transport).await;
+        assert_eq!(b.documents.active().text(), "hello world");
+
+        b.access.allow(a_transport.peer_id());
+        a.publish(&mut a_transport, Message::Clipboard("hi".to_string()));
+        receive_next(&mut b, &mut b_transport).await;
+        assert_eq!(b.clipboard, Some((Some(a_transport.peer_id()), "hi".to_string())));
+    }
+
+    #[tokio::test]
+    async fn documents_stay_under_the_maximum_size() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+
+        a.max_document_size = Some(12);
+        b.max_document_size = Some(11);
+        assert!(a.load_text(&mut a_transport, "hello world, and more").is_err());
+        a.edit(&mut a_transport, ins(0, 'X')).unwrap();
+        assert!(a.edit(&mut a_transport, ins(0, 'Y')).is_err());
+
+        receive_next(&mut b, &mut b_transport).await;
+        assert_eq!(b.documents.active().text(), "hello world");
+        a.edit(&mut a_transport, MessageBuf { messages: vec![Diff { opcode: Operation::Del, operand: None, index: 0 }] }).unwrap();
+    }
+
+    #[tokio::test]
+    async fn switch_room_changes_topic() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+
+        b.switch_room(&mut b_transport, "elsewhere", None).unwrap();
+        assert_eq!(b.room(), "elsewhere");
+        assert_eq!(b.topic(), "p2p-notepad/v1/elsewhere");
+
+        a.edit(&mut a_transport, ins(0, 'X')).unwrap();
+        assert_eq!(a.recent_edits.iter().next().unwrap().1, &Delivery::Pending);
+    }
+
+    #[tokio::test]
+    async fn private_rooms_seal_every_payload() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut c_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+        let mut c = def_peer(&mut c_transport);
+
+        a.switch_room(&mut a_transport, "private", Some("secret")).unwrap();
+        b.switch_room(&mut b_transport, "private", Some("secret")).unwrap();
+        // Found the topic, but not the passphrase.
+        c.join(&mut c_transport, "private", a.topic().to_string()).unwrap();
+
+        a.publish(&mut a_transport, Message::Clipboard("the plan".to_string()));
+        receive_next(&mut b, &mut b_transport).await;
+        assert_eq!(b.clipboard, Some((Some(a_transport.peer_id()), "the plan".to_string())));
+
+        let Some(Event::Message(incoming)) = c_transport.next_event().await else {
+            panic!("expected a message");
+        };
+        assert!(matches!(Message::try_from(incoming.data.clone()), Ok(Message::Sealed { .. })));
+        c.receive(&mut c_transport, incoming);
+        assert_eq!(c.clipboard, None);
+
+        c.publish(&mut c_transport, Message::Clipboard("forged".to_string()));
+        receive_next(&mut b, &mut b_transport).await;
+        assert_eq!(b.clipboard.as_ref().unwrap().1, "the plan");
+
+        a.edit(&mut a_transport, ins(0, 'X')).unwrap();
+        receive_next(&mut b, &mut b_transport).await;
+        assert_eq!(b.documents.active().text(), "X");
+
+        let Some(Event::Message(incoming)) = c_transport.next_event().await else {
+            panic!("expected a message");
+        };
+        assert!(matches!(Message::try_from(incoming.data.clone()), Ok(Message::Sealed { .. })));
+        c.receive(&mut c_transport, incoming);
+        assert_eq!(c.documents.active().text(), "");
+    }
+
+    #[tokio::test]
+    async fn whiteboards_sync_between_peers() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+        let stroke = Stroke { id: 1, color: Color::Red, points: vec![(0, 0), (10, 10)] };
+
+        a.draw(&mut a_transport, "arch", vec![BoardOp::Draw(stroke.clone())]).unwrap();
+        receive_next(&mut b, &mut b_transport).await;
+        assert_eq!(b.documents.boards["arch"].strokes(), [stroke]);
+
+        let erase = b.documents.boards["arch"].erase_at((5, 5));
+        b.draw(&mut b_transport, "arch", erase).unwrap();
+        receive_next(&mut a, &mut a_transport).await;
+        assert!(a.documents.boards["arch"].strokes().is_empty());
+
+        assert!(a.draw(&mut a_transport, "", vec![BoardOp::Erase(1)]).is_err());
+    }
+
+    #[tokio::test]
+    async fn manifest_limits_unsupported_peers() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+
+        b.switch_room(&mut b_transport, &format!("team@{}", a_transport.peer_id()), None).unwrap();
+        let created = a.create_room(&mut a_transport, "team", None, None).unwrap();
+        assert_eq!(created.flags, 0);
+
+        receive_next(&mut b, &mut b_transport).await;
+        receive_next(&mut a, &mut a_transport).await;
+        assert_eq!(b.manifest(), Some(created));
+        assert_eq!(a.admissions.values().collect::<Vec<_>>(), vec![&Admission::Full]);
+
+        let crdt = RoomManifest { flags: manifest::CRDT, max_document_size: None, genesis: None };
+        a.manifest = Some((None, crdt));
+        a.publish(&mut a_transport, Message::Manifest(crdt));
+        receive_next(&mut b, &mut b_transport).await;
+        receive_next(&mut a, &mut a_transport).await;
+        assert!(b.prefs().unwrap().read_only);
+
+        b.prefs_mut().read_only = false;
+        b.edit(&mut b_transport, ins(0, 'X')).unwrap();
+        receive_next(&mut a, &mut a_transport).await;
+        assert_eq!(a.documents.active().text(), "");
+
+        a.crdt = true;
+        assert_eq!(a.create_room(&mut a_transport, "notes", None, None).unwrap().flags, manifest::CRDT);
+    }
+
+    #[tokio::test]
+    async fn creators_grant_and_revoke_write_access() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+
+        b.switch_room(&mut b_transport, &format!("team@{}", a_transport.peer_id()), None).unwrap();
+        a.create_room(&mut a_transport, "team", None, None).unwrap();
+        receive_next(&mut b, &mut b_transport).await;
+        receive_next(&mut a, &mut a_transport).await;
+        assert!(b.permit(&mut b_transport, a_transport.peer_id(), false).is_err());
+
+        a.permit(&mut a_transport, b_transport.peer_id(), false).unwrap();
+        receive_next(&mut b, &mut b_transport).await;
+        assert!(b.prefs().unwrap().read_only);
+        assert!(b.edit(&mut b_transport, ins(0, 'X')).is_err());
+
+        b.prefs_mut().read_only = false;
+        b.edit(&mut b_transport, ins(0, 'X')).unwrap();
+        receive_next(&mut a, &mut a_transport).await;
+        assert_eq!(a.documents.active().text(), "");
+
+        a.permit(&mut a_transport, b_transport.peer_id(), true).unwrap();
+        receive_next(&mut b, &mut b_transport).await;
+        assert!(!b.prefs().unwrap().read_only);
+    }
+
+    #[tokio::test]
+    async fn only_creators_publish_manifests() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut c_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+        let mut c = def_peer(&mut c_transport);
+        let read_only = RoomManifest { flags: manifest::ROLES, max_document_size: None, genesis: None };
+
+        // Rooms not made with `room create` have no creator to claim them.
+        c.publish(&mut c_transport, Message::Manifest(read_only));
+        receive_next(&mut a, &mut a_transport).await;
+        assert_eq!(a.manifest(), None);
+
+        let room = format!("team@{}", a_transport.peer_id());
+        b.switch_room(&mut b_transport, &room, None).unwrap();
+        c.switch_room(&mut c_transport, &room, None).unwrap();
+        // Racing the creator, or revoking write access in its room, is ignored.
+        c.publish(&mut c_transport, Message::Manifest(read_only));
+        c.publish(&mut c_transport, Message::Permission { peer: b_transport.peer_id(), write: false });
+        receive_all(&mut b, &mut b_transport);
+        assert_eq!(b.manifest(), None);
+        assert!(!b.prefs().is_some_and(|prefs| prefs.read_only));
+        assert!(c.permit(&mut c_transport, b_transport.peer_id(), false).is_err());
+
+        let created = a.create_room(&mut a_transport, "team", None, None).unwrap();
+        assert_eq!(a.room(), room);
+        receive_next(&mut b, &mut b_transport).await;
+        assert_eq!(b.manifest(), Some(created));
+    }
+
+    #[tokio::test]
+    async fn rooms_start_from_templates() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+
+        b.switch_room(&mut b_transport, &format!("standup@{}", a_transport.peer_id()), None).unwrap();
+        let created = a.create_room(&mut a_transport, "standup", None, Some("# Standup\r\n- done\n")).unwrap();
+        assert_eq!(created.genesis, Some(*blake3::hash(b"# Standup\n- done\n").as_bytes()));
+        assert_eq!(a.documents.active().text(), "# Standup\n- done\n");
+
+        receive_next(&mut b, &mut b_transport).await;
+        receive_next(&mut b, &mut b_transport).await;
+        assert_eq!(b.manifest(), Some(created));
+        assert_eq!(b.documents.active().text(), "# Standup\n- done\n");
+
+        a.memory_budget = Some(4);
+        assert!(a.create_room(&mut a_transport, "retro", None, Some("# Retro")).is_err());
+        assert_eq!(a.room(), format!("standup@{}", a_transport.peer_id()));
+    }
+
+    #[tokio::test]
+    async fn undo_and_redo_are_published() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+
+        a.edit(&mut a_transport, MessageBuf { messages: vec![
+            Diff { opcode: Operation::Rep, operand: Some('j'), index: 0 },
+            Diff { opcode: Operation::Del, operand: None, index: 5 },
+        ] }).unwrap();
+        receive_next(&mut b, &mut b_transport).await;
+        assert_eq!(b.documents.active().text(), "jelloworld");
+
+        a.undo(&mut a_transport).unwrap();
+        receive_next(&mut b, &mut b_transport).await;
+        assert_eq!(b.documents.active().text(), "hello world");
+        assert!(a.undo(&mut a_transport).is_err());
+
+        a.redo(&mut a_transport).unwrap();
+        receive_next(&mut b, &mut b_transport).await;
+        assert_eq!(b.documents.active().text(), "jelloworld");
+        assert!(a.redo(&mut a_transport).is_err());
+    }
+
+    #[tokio::test]
+    async fn tags_are_restored_for_everyone() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+        let dir = std::env::temp_dir().join(format!("p2p-notepad-oplogs-{}", rand::random::<u64>()));
+        a.open_oplog(Box::new(Files::new(dir.clone())), true).unwrap();
+
+        a.tag("v1");
+        a.edit(&mut a_transport, ins(0, 'X')).unwrap();
+        receive_next(&mut b, &mut b_transport).await;
+        assert_eq!(b.documents.active().text(), "Xhello world");
+        assert!(a.patch_since("v1").unwrap().ends_with("@@ -1 +1 @@\n-hello world\n\\ No newline at end of file\n+Xhello world\n\\ No newline at end of file\n"));
+        assert_eq!(a.patch_since("1").unwrap(), "");
+
+        assert_eq!(a.restore_tag(&mut a_transport, "v1").unwrap(), 1);
+        receive_next(&mut b, &mut b_transport).await;
+        assert_eq!(b.documents.active().text(), "hello world");
+        assert_eq!(a.restore_tag(&mut a_transport, "v1").unwrap(), 0);
+        assert!(a.restore_tag(&mut a_transport, "v2").is_err());
+
+        let mut restarted = def_peer(&mut Loopback::default());
+        restarted.open_oplog(Box::new(Files::new(dir.clone())), true).unwrap();
+        assert_eq!(restarted.documents.tags, a.documents.tags);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn locked_documents_refuse_edits() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+
+        a.lock(&mut a_transport).unwrap();
+        receive_next(&mut b, &mut b_transport).await;
+        assert_eq!(b.locks.holder(&b.documents.active_meta().id), Some(a_transport.peer_id()));
+        assert!(b.edit(&mut b_transport, ins(0, 'X')).is_err());
+        assert!(b.lock(&mut b_transport).is_err());
+
+        a.edit(&mut a_transport, ins(0, 'A')).unwrap();
+        receive_next(&mut b, &mut b_transport).await;
+        assert_eq!(b.documents.active().text(), "Ahello world");
+
+        a.unlock(&mut a_transport).unwrap();
+        assert!(a.unlock(&mut a_transport).is_err());
+        receive_next(&mut b, &mut b_transport).await;
+        b.edit(&mut b_transport, ins(0, 'B')).unwrap();
+        assert_eq!(b.documents.active().text(), "BAhello world");
+    }
+
+    #[tokio::test]
+    async fn claims_stay_on_their_lines() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+        let insert = |text: &str| MessageBuf { messages: vec![Diff { opcode: Operation::InsStr(text.to_string()), operand: None, index: 0 }] };
+
+        a.edit(&mut a_transport, insert("one\ntwo\n")).unwrap();
+        receive_next(&mut b, &mut b_transport).await;
+        assert!(a.claim(&mut a_transport, 2, 4).is_err());
+        a.claim(&mut a_transport, 2, 3).unwrap();
+        receive_next(&mut b, &mut b_transport).await;
+        assert_eq!(b.claimed_lines(), vec![(Some(a_transport.peer_id()), (2, 3))]);
+
+        // Lines inserted above push the claim down on both peers.
+        b.edit(&mut b_transport, insert("zero\n")).unwrap();
+        receive_next(&mut a, &mut a_transport).await;
+        assert_eq!(b.claimed_lines(), vec![(Some(a_transport.peer_id()), (3, 4))]);
+        assert_eq!(a.claimed_lines(), vec![(None, (3, 4))]);
+
+        a.unclaim(&mut a_transport).unwrap();
+        receive_next(&mut b, &mut b_transport).await;
+        assert!(b.claimed_lines().is_empty());
+        assert!(a.unclaim(&mut a_transport).is_err());
+    }
+
+    #[tokio::test]
+    async fn edits_wait_for_peers() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+
+        a.edit(&mut a_transport, ins(0, 'X')).unwrap();
+        a.edit(&mut a_transport, ins(0, 'Y')).unwrap();
+        let retry = a.next_retry().unwrap();
+
+        let mut b = def_peer(&mut b_transport);
+        a.flush_outbox(&mut a_transport, retry - Duration::from_millis(1));
+        a.flush_outbox(&mut a_transport, retry);
+        assert!(a.next_retry().is_none());
+
+        receive_next(&mut b, &mut b_transport).await;
+        receive_next(&mut b, &mut b_transport).await;
+        assert_eq!(b.documents.active().text(), "YXhello world");
+    }
+
+    #[tokio::test]
+    async fn edits_are_batched_before_publishing() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+        a.batch.window = Duration::from_millis(100);
+
+        a.edit(&mut a_transport, ins(0, 'X')).unwrap();
+        a.edit(&mut a_transport, ins(1, 'Y')).unwrap();
+        assert_eq!(a.documents.active().text(), "XYhello world");
+        assert!(b_transport.next_event().now_or_never().is_none());
+
+        let due = a.next_edits().unwrap();
+        a.flush_edits(&mut a_transport, due - Duration::from_millis(1));
+        assert!(b_transport.next_event().now_or_never().is_none());
+        a.flush_edits(&mut a_transport, due);
+        assert_eq!(a.next_edits(), None);
+
+        receive_next(&mut b, &mut b_transport).await;
+        assert_eq!(b.documents.active().text(), "XYhello world");
+        assert!(b_transport.next_event().now_or_never().is_none());
+        assert_eq!(a.recent_edits.iter().count(), 1);
+
+        // Snapshots carry the text with the held edits, so those go first.
+        a.edit(&mut a_transport, ins(0, 'Z')).unwrap();
+        a.publish_snapshots(&mut a_transport);
+        a.flush_bulk(&mut a_transport, Instant::now() + INTERACTIVE_GRACE);
+        assert_eq!(a.next_edits(), None);
+        receive_all(&mut b, &mut b_transport);
+        assert_eq!(b.documents.active().text(), "ZXYhello world");
+    }
+
+    #[tokio::test]
+    async fn unpublished_edits_wait_in_their_room() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+
+        a.edit(&mut a_transport, ins(0, 'X')).unwrap();
+        a.switch_room(&mut a_transport, "other", None).unwrap();
+        a.edit(&mut a_transport, ins(0, 'Y')).unwrap();
+        let pending: Vec<_> = a.pending().into_iter().map(|(room, payloads, _)| (room.to_string(), payloads.len())).collect();
+        assert_eq!(pending, vec![("other".to_string(), 1), ("room".to_string(), 1)]);
+
+        // Rejoining, the edit goes to the first peer to subscribe without waiting for the retry.
+        let mut b = def_peer(&mut b_transport);
+        a.switch_room(&mut a_transport, "room", None).unwrap();
+        a.handle(&mut a_transport, Event::Subscribed { peer: b_transport.peer_id(), topic: a.topic().to_string() });
+        assert!(a.next_retry().is_none());
+        assert_eq!(a.pending()[1].0, "other");
+
+        receive_next(&mut b, &mut b_transport).await;
+        receive_next(&mut b, &mut b_transport).await;
+        receive_next(&mut a, &mut a_transport).await;
+        receive_next(&mut b, &mut b_transport).await;
+        assert_eq!(b.documents.active().text(), "Xhello world");
+    }
+
+    #[tokio::test]
+    async fn leaving_publishes_held_edits() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+        a.batch.window = Duration::from_secs(60);
+
+        a.edit(&mut a_transport, ins(0, 'X')).unwrap();
+        assert_eq!(a.leave(&mut a_transport), 0);
+        assert_eq!(a.next_edits(), None);
+
+        receive_next(&mut b, &mut b_transport).await;
+        assert_eq!(b.documents.active().text(), "Xhello world");
+    }
+
+    #[tokio::test]
+    async fn attachments_are_fetched_on_demand() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+
+        let meta = a.attach(&mut a_transport, "a.txt", b"hello".to_vec(), 0).unwrap();
+        receive_next(&mut b, &mut b_transport).await;
+        receive_next(&mut b, &mut b_transport).await;
+
+        let reference = attachment::reference(&meta.hash);
+        assert_eq!(b.documents.active().text(), format!("{reference}hello world"));
+        assert_eq!(b.attachments.get(&meta.hash), None);
+
+        b.fetch(&mut b_transport, &meta.hash).unwrap();
+        receive_next(&mut a, &mut a_transport).await;
+        receive_next(&mut b, &mut b_transport).await;
+        assert_eq!(b.attachments.get(&meta.hash), Some(b"hello".as_slice()));
+
+        assert!(a.attach(&mut a_transport, "b.txt", vec![1], 250).is_err());
+        assert!(b.fetch(&mut b_transport, "unknown").is_err());
+    }
+
+    #[tokio::test]
+    async fn restore_rejoins_and_backfills() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        a.host = true;
+        a.switch_room(&mut a_transport, "private", Some("secret")).unwrap();
+        a.edit(&mut a_transport, ins(0, 'X')).unwrap();
+
+        let mut saved = def_peer(&mut Loopback::default());
+        saved.switch_room(&mut Loopback::default(), "private", Some("secret")).unwrap();
+        saved.documents.create("todo").unwrap();
+        saved.documents.switch("todo").unwrap();
+        saved.peers.nickname = Some("bob".to_string());
+
+        let mut b = def_peer(&mut b_transport);
+        b.restore(&mut b_transport, Workspace::new(&saved)).unwrap();
+        assert_eq!((b.room(), b.topic()), (saved.room(), saved.topic()));
+        assert_eq!(b.documents.active_meta().name, "todo");
+
+        receive_next(&mut a, &mut a_transport).await;
+        receive_next(&mut a, &mut a_transport).await;
+        assert_eq!(a.peers.display_name(&b_transport.peer_id()), "bob");
+        a.flush_bulk(&mut a_transport, Instant::now() + INTERACTIVE_GRACE);
+
+        while b.documents.get("main").unwrap().notepad.text() != "X" {
+            receive_next(&mut b, &mut b_transport).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn corrupted_snapshots_are_requested_again() {
+        let mut a_transport = Loopback::default();
+        let mut b_transport = a_transport.connect();
+        let mut a = def_peer(&mut a_transport);
+        let mut b = def_peer(&mut b_transport);
+        a.host = true;
+
+        let mut data: Vec<u8> = Message::Snapshot(Snapshot { document: "main".to_string(), text: "corrupted".to_string() }).into();
+        data[9] ^= 1;
+        a_transport.publish(a.topic(), data.clone()).unwrap();
+        a_transport.publish(a.topic(), data).unwrap();
+
+        receive_next(&mut b, &mut b_transport).await;
+        receive_next(&mut b, &mut b_transport).await;
+        assert_eq!(b.documents.active().text(), "hello world");
+
+        // One request despite two corrupted snapshots, answered with fresh ones.
+        receive_next(&mut a, &mut a_transport).await;
+        assert!(a_transport.next_event().now_or_never().is_none());
+        assert!(matches!(b_transport.next_event().await, Some(Event::Message(_))));
+    }
+
+    #[test]
+    fn private_room_topics() {
+        let key = |room, passphrase| RoomKey::derive(room, passphrase);
+        let public = room_topic("p/", "room", None);
+        let private = room_topic("p/", "room", Some(&key("room", "secret")));
+
+        assert_eq!(public, "p/room");
+        assert!(private.starts_with("p/") && !private.contains("room"));
+        assert_eq!(private, room_topic("p/", "room", Some(&key("room", "secret"))));
+        assert_ne!(private, room_topic("p/", "room", Some(&key("room", "other"))));
+        assert_ne!(private, room_topic("p/", "other", Some(&key("other", "secret"))));
+    }
+}
@@ -0,0 +1,39 @@
+use std::{
+    fmt, io
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum NotepadError {
+    /// A payload or file couldn't be decoded.
+    #[error("decode error: {0}")]
+    Decode(&'static str),
+    /// A diff doesn't fit the document it was applied to.
+    #[error("cannot apply `{diff}`: {reason}")]
+    Apply {
+        diff: String,
+        reason: &'static str,
+    },
+    /// Content doesn't match the hash it was sent with.
+    #[error("integrity error: {0}")]
+    Integrity(&'static str),
+    #[error("network error: {0}")]
+    Network(String),
+    /// A publish that failed for a passing reason and may succeed if tried again.
+    #[error("temporary network error: {0}")]
+    Transient(String),
+    #[error("storage error: {0}")]
+    Storage(#[from] io::Error),
+    /// A command or argument was malformed or refers to something that doesn't exist.
+    #[error("{0}")]
+    Command(String),
+}
+
+impl NotepadError {
+    pub fn network(e: impl fmt::Display) -> Self {
+        NotepadError::Network(e.to_string())
+    }
+
+    pub fn command(e: impl fmt::Display) -> Self {
+        NotepadError::Command(e.to_string())
+    }
+}
@@ -0,0 +1,76 @@
+use crate::error::NotepadError;
+
+/// Undoes the escapes of text typed in a command, so characters that can't
+/// be typed on one line, or would split the command, can still be inserted:
+/// `\n`, `\t`, `\\`, `\:` and `\xNN` for the character with hex code `NN`.
+pub fn unescape(s: &str) -> Result<String, NotepadError> {
+    let mut text = String::with_capacity(s.len());
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => text.push('\n'),
+            Some('t') => text.push('\t'),
+            Some('\\') => text.push('\\'),
+            Some(':') => text.push(':'),
+            Some('x') => {
+                let code: String = chars.by_ref().take(2).collect();
+                let code = u8::from_str_radix(&code, 16)
+                    .ok()
+                    .filter(|_| code.len() == 2)
+                    .ok_or_else(|| NotepadError::command(format!("Expected two hex digits after `\\x`, got `{code}`")))?;
+
+                text.push(char::from(code));
+            },
+            Some(c) => return Err(NotepadError::command(format!("Unknown escape `\\{c}`, use `\\\\` for a backslash"))),
+            None => return Err(NotepadError::command("Text ends in a lone `\\`, use `\\\\` for a backslash")),
+        }
+    }
+
+    Ok(text)
+}
+
+/// Splits escaped text at its first `:` that isn't escaped, into the two
+/// parts still escaped.
+pub fn split(s: &str) -> Option<(&str, &str)> {
+    let mut escaped = false;
+
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            ':' => return Some((&s[..i], &s[i + 1..])),
+            _ => {},
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn escapes_are_undone() {
+        assert_eq!(unescape(r"a\nb\tc\\d\:e\x41").unwrap(), "a\nb\tc\\d:eA");
+        assert_eq!(unescape("plain: text").unwrap(), "plain: text");
+
+        assert!(unescape(r"\q").is_err());
+        assert!(unescape(r"\x4").is_err());
+        assert!(unescape(r"\xzz").is_err());
+        assert!(unescape("end\\").is_err());
+    }
+
+    #[test]
+    fn splits_at_unescaped_colon() {
+        assert_eq!(split(r"a\:b:c:d"), Some((r"a\:b", "c:d")));
+        assert_eq!(split(r"a\\:b"), Some((r"a\\", "b")));
+        assert_eq!(split(r"a\:b"), None);
+    }
+}
@@ -0,0 +1,82 @@
+use std::{
+    collections::HashMap,
+    time::Duration
+};
+
+use libp2p::PeerId;
+
+/// Round trip at or beyond which a peer gets no preference.
+pub const SLOW_RTT: Duration = Duration::from_millis(400);
+
+/// Highest application score, given to a peer with no round trip at all.
+/// Gossipsub weighs it 10 times, staying under the opportunistic graft
+/// threshold so that faster peers are grafted in every time it checks.
+pub const MAX_SCORE: f64 = 1.0;
+
+/// Weight of the newest round trip in a peer's smoothed one.
+const SMOOTHING: f64 = 0.25;
+
+/// Round trips to connected peers as measured by pings, smoothed, from which
+/// gossipsub is given scores that keep faster peers in the room's mesh. The
+/// mesh drops its lowest scoring peers when it grows too large and grafts in
+/// ones scoring above its median, so messages take the faster paths.
+#[derive(Debug, Default)]
+pub struct Fanout {
+    /// Smoothed and last round trip, by peer.
+    rtts: HashMap<PeerId, (Duration, Duration)>,
+}
+
+impl Fanout {
+    /// Takes a ping round trip to `peer_id`, returning the peer's new score.
+    pub fn sample(&mut self, peer_id: PeerId, rtt: Duration) -> f64 {
+        let smoothed = self.rtts
+            .get(&peer_id)
+            .map_or(rtt, |(previous, _)| previous.mul_f64(1.0 - SMOOTHING) + rtt.mul_f64(SMOOTHING));
+        self.rtts.insert(peer_id, (smoothed, rtt));
+
+        score(smoothed)
+    }
+
+    pub fn remove(&mut self, peer_id: &PeerId) {
+        self.rtts.remove(peer_id);
+    }
+
+    /// The smoothed round trip to `peer_id`, if it was pinged.
+    pub fn rtt(&self, peer_id: &PeerId) -> Option<Duration> {
+        self.rtts.get(peer_id).map(|&(smoothed, _)| smoothed)
+    }
+
+    /// The last round trip to `peer_id`, if it was pinged.
+    pub fn last(&self, peer_id: &PeerId) -> Option<Duration> {
+        self.rtts.get(peer_id).map(|&(_, last)| last)
+    }
+}
+
+/// Score for a round trip, falling from [`MAX_SCORE`] to 0 at [`SLOW_RTT`].
+/// Slow peers aren't scored below 0, which would stop gossip to them.
+pub fn score(rtt: Duration) -> f64 {
+    MAX_SCORE * (1.0 - rtt.as_secs_f64() / SLOW_RTT.as_secs_f64()).max(0.0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn faster_peers_score_higher() {
+        let mut fanout = Fanout::default();
+        let (near, far) = (PeerId::random(), PeerId::random());
+
+        assert_eq!(fanout.sample(near, Duration::from_millis(100)), 0.75);
+        assert_eq!(fanout.sample(far, Duration::from_millis(800)), 0.0);
+
+        // One slow ping only moves a quarter of the way.
+        fanout.sample(near, Duration::from_millis(500));
+        assert_eq!(fanout.rtt(&near), Some(Duration::from_millis(200)));
+        assert_eq!(fanout.last(&near), Some(Duration::from_millis(500)));
+        assert!(score(fanout.rtt(&near).unwrap()) > score(fanout.rtt(&far).unwrap()));
+
+        fanout.remove(&far);
+        assert_eq!(fanout.rtt(&far), None);
+    }
+}
@@ -0,0 +1,198 @@
+/// A command typed at the prompt, as `help` shows it.
+#[derive(Debug, PartialEq)]
+pub struct Command {
+    /// What the command starts with, up to its first `:`.
+    pub name: &'static str,
+    pub syntax: &'static str,
+    pub example: &'static str,
+    pub about: &'static str,
+}
+
+const fn command(name: &'static str, syntax: &'static str, example: &'static str, about: &'static str) -> Command {
+    Command { name, syntax, example, about }
+}
+
+/// Every command, in the order `help` lists them: editing first, then
+/// documents, rooms and peers, and the rest.
+pub const COMMANDS: &[Command] = &[
+    command("ins", "ins:index:char", "ins:0:H", "Insert a character"),
+    command("del", "del:index", "del:5", "Delete the character at an index"),
+    command("rep", "rep:index:char", "rep:0:h", "Replace the character at an index"),
+    command("inss", "inss:index:text", r"inss:0:Hello\n", "Insert text, which may be escaped"),
+    command("pas", "pas:index:text", "pas:0:pasted text", "Insert pasted text"),
+    command("delr", "delr:start:len", "delr:0:5", "Delete a range"),
+    command("copy", "copy:start:end, copy:<room>:<start>..<end>", "copy:notes:0..120", "Copy a range to the system clipboard, or insert one from another joined room's document"),
+    command("pastec", "pastec:index", "pastec:0", "Insert the system clipboard"),
+    command("insl", "insl:line:text", "insl:2:a new second line", "Insert a line before a line, numbered from 1"),
+    command("dell", "dell:line", "dell:2", "Delete a line"),
+    command("repl", "repl:line:text", "repl:2:the second line", "Replace a line"),
+    command("repa", "repa:pattern:replacement", "repa:colour:color", "Replace every match of a pattern"),
+    command("undo", "undo", "undo", "Undo your last edit"),
+    command("redo", "redo", "redo", "Redo the edit undone last"),
+    command("see", "see[:who]", "see:who", "Show the document, or who wrote each part of it"),
+    command("view", "view[:line]", "view:41", "Show a page of the document with line numbers and index ranges"),
+    command("hist", "hist[:n]", "hist:20", "Show the last operations"),
+    command("at", "at:<n>", "at:42", "Show the document after an operation from `hist`"),
+    command("catchup", "catchup", "catchup", "Show the earliest change not seen yet"),
+    command("find", "find:text", "find:todo", "Find text"),
+    command("findr", "findr:pattern", r"findr:\d+", "Find matches of a regular expression"),
+    command("spell", "spell", "spell", "Check spelling against the dictionary"),
+    command("links", "links", "links", "List the links in the document"),
+    command("open", "open:<n>", "open:1", "Open a link from `links`"),
+    command("doc", "doc:name", "doc:notes", "Switch to a document"),
+    command("docs", "docs", "docs", "List the documents, same as `doc list`"),
+    command("doc new", "doc new:name", "doc new:notes", "Create a document and switch to it"),
+    command("doc rename", "doc rename:name", "doc rename:todo", "Rename the document"),
+    command("doc archive", "doc archive:name", "doc archive:old", "Hide a document"),
+    command("doc restore", "doc restore:name", "doc restore:old", "Bring back an archived document, as the room host"),
+    command("doc eol", "doc eol:lf|crlf", "doc eol:crlf", "Set the line endings used when saving"),
+    command("doc lang", "doc lang:<language>", "doc lang:rust", "Set the language of the document"),
+    command("doc tab", "doc tab:<width>", "doc tab:4", "Set the tab width"),
+    command("doc wrap", "doc wrap:<columns|off>", "doc wrap:80", "Set where lines wrap"),
+    command("doc view", "doc view", "doc view", "Show the document's layout"),
+    command("tag", "tag:name", "tag:draft", "Name the current version"),
+    command("tags", "tags", "tags", "List the tagged versions"),
+    command("restore", "restore:name", "restore:draft", "Go back to a tagged version"),
+    command("lock", "lock", "lock", "Lock the document so only you edit it"),
+    command("unlock", "unlock", "unlock", "Let others edit the document again"),
+    command("claim", "claim:first:last", "claim:3:10", "Claim lines, warning others editing them"),
+    command("unclaim", "unclaim", "unclaim", "Release the lines claimed"),
+    command("claims", "claims", "claims", "List the lines claimed"),
+    command("select", "select[:first:last]", "select:3:10", "Select lines for peers to see, or clear the selection"),
+    command("save", "save[:path] or save:hash:path", "save:notes.txt", "Save the document, or an attachment, to a file"),
+    command("load", "load[:path]", "load:notes.txt", "Replace the document with a file"),
+    command("write", "write:<path>", "write:notes.txt", "Write the document to a file"),
+    command("init", "init", "init", "Publish the documents as the room's initial state"),
+    command("export", "export:path", "export:room.pad", "Export the room's documents"),
+    command("export --encrypt", "export --encrypt:path:passphrase", "export --encrypt:room.pad:secret", "Export the room's documents encrypted"),
+    command("export --text", "export --text:path", "export --text:notes.txt", "Export the document as text"),
+    command("export --patch", "export --patch:path:<tag or operation number>", "export --patch:changes.diff:draft", "Export the changes since a version as a patch"),
+    command("apply", "apply:path", "apply:changes.diff", "Apply a patch to the document"),
+    command("import", "import:path[:passphrase]", "import:room.pad", "Import an export"),
+    command("import --text", "import --text:path", "import --text:notes.txt", "Import text as a new document"),
+    command("replay", "replay:path", "replay:capture.log", "Feed captured payloads through the engine again"),
+    command("attach", "attach:path[:index]", "attach:photo.png:0", "Attach a file at an index"),
+    command("attachments", "attachments", "attachments", "List the attachments"),
+    command("fetch", "fetch:hash", "fetch:3f2a...", "Download an attachment"),
+    command("board", "board:<board>", "board:sketch", "Show a whiteboard"),
+    command("board draw", "board draw:<board>:<color> <x>,<y> <x>,<y>...", "board draw:sketch:red 0,0 10,10", "Draw on a whiteboard"),
+    command("board erase", "board erase:<board>:<x>,<y>", "board erase:sketch:5,5", "Erase the stroke at a point"),
+    command("board clear", "board clear:<board>", "board clear:sketch", "Clear a whiteboard"),
+    command("room", "room", "room", "Show the room's settings"),
+    command("rooms", "rooms", "rooms", "Look up the rooms listed in the directory"),
+    command("room list", "room list[:description]", "room list:weekly notes", "List the room in the directory"),
+    command("room create", "room create:<room>[:passphrase]", "room create:team", "Create a room named after this peer and join it"),
+    command("room create --from", "room create --from:<room>:<path>", "room create --from:team:room.pad", "Create a room from an export"),
+    command("room manifest", "room manifest", "room manifest", "Show how the room was created"),
+    command("room unlist", "room unlist", "room unlist", "Stop listing the room in the directory"),
+    command("room read-only", "room read-only:on|off", "room read-only:on", "Make the room read-only"),
+    command("room quiet", "room quiet:on|off", "room quiet:on", "Stop announcing peers joining and leaving"),
+    command("room mute", "room mute:<peer>", "room mute:alice", "Ignore a peer in this room"),
+    command("room unmute", "room unmute:<peer>", "room unmute:alice", "Stop ignoring a peer"),
+    command("room grant", "room grant:<peer>", "room grant:alice", "Give a peer write access"),
+    command("room revoke", "room revoke:<peer>", "room revoke:alice", "Take back a peer's write access"),
+    command("allow", "allow:<peer>", "allow:alice", "Unblock a peer"),
+    command("block", "block:<peer>", "block:alice", "Block a peer"),
+    command("ro", "ro[:on|off]", "ro:on", "Only watch, without editing"),
+    command("swi", "swi:room[:passphrase]", "swi:team", "Switch to another room"),
+    command("watch", "watch:<room>[:pattern|pattern...]", "watch:team:urgent|todo", "Watch another room for changes"),
+    command("watch list", "watch list", "watch list", "List the rooms watched"),
+    command("unwatch", "unwatch:<room>", "unwatch:team", "Stop watching a room"),
+    command("backup restore", "backup restore", "backup restore", "Restore the room from the backup peer"),
+    command("peers", "peers", "peers", "List the connected peers and those in the room"),
+    command("nick", "nick:name", "nick:alice", "Set your nickname"),
+    command("user", "user[:nickname]", "user:alice", "Edit as a local user, or as this node"),
+    command("user new", "user new:nickname", "user new:bob", "Add a local user"),
+    command("user list", "user list", "user list", "List the local users"),
+    command("say", "say:text", "say:hi all", "Send a chat message"),
+    command("matrix", "matrix:snap", "matrix:snap", "Post the document to the Matrix room chat is bridged with"),
+    command("clip set", "clip set:text", "clip set:shared snippet", "Share a clipboard with the room"),
+    command("clip get", "clip get", "clip get", "Show the shared clipboard"),
+    command("session", "session", "session", "Show the editing session"),
+    command("session start", "session start:<duration>[:read-only]", "session start:30m", "Start a timed editing session"),
+    command("session end", "session end", "session end", "End the editing session"),
+    command("dial", "dial:<multiaddr>", "dial:/ip4/10.0.0.2/udp/4001/quic-v1", "Connect to a peer"),
+    command("dial --relay", "dial --relay:<peer id>", "dial --relay:12D3KooW...", "Connect to a peer through the relay"),
+    command("bootstrap", "bootstrap[:multiaddr]", "bootstrap", "Look up the room in the DHT"),
+    command("mesh", "mesh", "mesh", "List the peers in the room by round trip"),
+    command("pending", "pending", "pending", "List the edits waiting to be published"),
+    command("probe", "probe", "probe", "Measure the latency to peers, see `stats`"),
+    command("verify", "verify", "verify", "Check that peers' copies of the document match"),
+    command("stats", "stats", "stats", "Show the latency to peers"),
+    command("conflicts", "conflicts", "conflicts", "Count remote edits landing where you were typing"),
+    command("graph", "graph", "graph", "Show recent operations by peer"),
+    command("dashboard", "dashboard", "dashboard", "Summarise every room"),
+    command("mem", "mem", "mem", "Show the memory used"),
+    command("compact", "compact", "compact", "Compact the operation log"),
+    command("log", "log:<filter>", "log:p2p_notepad::engine=debug", "Change what is logged"),
+    command("set", "set:key:value", "set:peer-timeout:30", "Change a setting"),
+    command("config save", "config save", "config save", "Save the settings to the config file"),
+    command("telemetry status", "telemetry status", "telemetry status", "Show what telemetry would send"),
+    command("aliases", "aliases", "aliases", "List the aliases from the config"),
+    command("run", "run:<path>[:abort]", "run:template.txt:abort", "Run the commands in a file, stopping at the first that fails with `abort`"),
+    command("help", "help[:command]", "help:ins", "List the commands, or show one"),
+    command("quit", "quit", "quit", "Leave"),
+];
+
+pub fn get(name: &str) -> Option<&'static Command> {
+    COMMANDS.iter().find(|command| command.name == name)
+}
+
+/// Of `names`, the one closest to the unknown command `typed`, if any is a
+/// likely typo of it: a few characters added, removed or changed.
+pub fn closest<'a>(typed: &str, names: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let allowed = (typed.chars().count() / 3).clamp(1, 3);
+
+    names
+        .into_iter()
+        .map(|name| (distance(typed, name), name))
+        .filter(|&(distance, _)| distance <= allowed)
+        .min()
+        .map(|(_, name)| name)
+}
+
+/// How many characters have to be inserted, deleted, replaced or swapped
+/// with the next to turn `a` into `b`.
+fn distance(a: &str, b: &str) -> usize {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    // Distances from each prefix of `a` to each prefix of `b`.
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+
+    for i in 0..=a.len() {
+        for j in 0..=b.len() {
+            d[i][j] = match (i, j) {
+                (0, j) => j,
+                (i, 0) => i,
+                (i, j) => {
+                    let mut best = (d[i - 1][j] + 1).min(d[i][j - 1] + 1).min(d[i - 1][j - 1] + usize::from(a[i - 1] != b[j - 1]));
+                    if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                        best = best.min(d[i - 2][j - 2] + 1);
+                    }
+                    best
+                },
+            };
+        }
+    }
+
+    d[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn suggests_the_closest_command() {
+        let names = || COMMANDS.iter().map(|command| command.name);
+
+        assert_eq!(distance("kitten", "sitting"), 3);
+        assert_eq!(distance("isn", "ins"), 1);
+        assert_eq!(closest("isn", names()), Some("ins"));
+        assert_eq!(closest("dco new", names()), Some("doc new"));
+        assert_eq!(closest("unlcok", names()), Some("unlock"));
+        assert_eq!(closest("xyzzy", names()), None);
+
+        assert!(COMMANDS.iter().all(|command| command.syntax.starts_with(command.name) && command.example.starts_with(command.name)));
+        assert_eq!(get("dell").map(|command| command.syntax), Some("dell:line"));
+    }
+}
@@ -0,0 +1,99 @@
+use crate::diff::{Diff, MessageBuf};
+
+/// Local edits kept for undo, per direction. Older ones are forgotten.
+pub const MAX_HISTORY: usize = 100;
+
+/// Inverses of the local edits, so they can be undone and redone. Each entry
+/// is the document edited and the diffs that revert the edit, at the
+/// positions it was made: remote edits made since may leave them not fitting.
+#[derive(Debug, Default)]
+pub struct History {
+    undo: Vec<(String, MessageBuf)>,
+    redo: Vec<(String, MessageBuf)>,
+}
+
+impl History {
+    /// Records the inverse of a new local edit, which makes the undone edits
+    /// of the document impossible to redo.
+    pub fn record(&mut self, document: &str, inverse: MessageBuf) {
+        self.redo.retain(|(id, _)| id != document);
+        push(&mut self.undo, document, inverse);
+    }
+
+    /// Takes the inverse of the last edit to `document` that wasn't undone.
+    pub fn undo(&mut self, document: &str) -> Option<MessageBuf> {
+        take(&mut self.undo, document)
+    }
+
+    /// Takes the inverse of the last undo of `document`.
+    pub fn redo(&mut self, document: &str) -> Option<MessageBuf> {
+        take(&mut self.redo, document)
+    }
+
+    /// Records the inverse of an undo, so it can be redone.
+    pub fn undone(&mut self, document: &str, inverse: MessageBuf) {
+        push(&mut self.redo, document, inverse);
+    }
+
+    /// Records the inverse of a redo, so it can be undone again.
+    pub fn redone(&mut self, document: &str, inverse: MessageBuf) {
+        push(&mut self.undo, document, inverse);
+    }
+
+    /// Forgets every edit, for when the documents they were made to are left.
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+
+    pub fn memory(&self) -> usize {
+        self.undo.iter().chain(&self.redo).map(|(id, diffs)| id.capacity() + diffs.messages.capacity() * size_of::<Diff>()).sum()
+    }
+}
+
+fn push(stack: &mut Vec<(String, MessageBuf)>, document: &str, inverse: MessageBuf) {
+    if inverse.messages.is_empty() {
+        return;
+    }
+
+    if stack.len() == MAX_HISTORY {
+        stack.remove(0);
+    }
+
+    stack.push((document.to_string(), inverse));
+}
+
+fn take(stack: &mut Vec<(String, MessageBuf)>, document: &str) -> Option<MessageBuf> {
+    let i = stack.iter().rposition(|(id, _)| id == document)?;
+
+    Some(stack.remove(i).1)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::diff::Operation;
+
+    fn del(index: usize) -> MessageBuf {
+        MessageBuf { messages: vec![Diff { opcode: Operation::Del, operand: None, index }] }
+    }
+
+    #[test]
+    fn undoes_per_document() {
+        let mut history = History::default();
+
+        history.record("main", del(0));
+        history.record("notes", del(1));
+        history.record("main", del(2));
+
+        assert_eq!(history.undo("main"), Some(del(2)));
+        history.undone("main", del(3));
+        assert_eq!(history.undo("notes"), Some(del(1)));
+        assert_eq!(history.undo("notes"), None);
+
+        history.record("main", del(4));
+        assert_eq!(history.redo("main"), None);
+        assert_eq!(history.undo("main"), Some(del(4)));
+        assert_eq!(history.undo("main"), Some(del(0)));
+    }
+}
@@ -0,0 +1,64 @@
+use std::{
+    io::{self, Write},
+    path::Path
+};
+
+use libp2p::identity::Keypair;
+
+use crate::error::NotepadError;
+
+/// The node's keypair kept at `path`, so peers know it again across runs.
+/// One is generated and saved there on the first run.
+pub fn load_or_create(path: &Path) -> Result<Keypair, NotepadError> {
+    match std::fs::read(path) {
+        Ok(data) => Keypair::from_protobuf_encoding(&data).map_err(|_| NotepadError::Decode("Invalid identity file")),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let keypair = Keypair::generate_ed25519();
+            save(path, &keypair)?;
+
+            Ok(keypair)
+        },
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Writes `keypair` to `path`, readable only by its owner where that can be set.
+fn save(path: &Path, keypair: &Keypair) -> Result<(), NotepadError> {
+    let data = keypair.to_protobuf_encoding().map_err(|_| NotepadError::Decode("Unsupported identity key"))?;
+
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    options.open(path)?.write_all(&data)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn identity_persists_across_runs() {
+        let dir = std::env::temp_dir().join(format!("p2p-notepad-identity-{}", rand::random::<u64>()));
+        let path = dir.join("keys").join("identity.key");
+
+        let first = load_or_create(&path).unwrap();
+        let second = load_or_create(&path).unwrap();
+        assert_eq!(first.public().to_peer_id(), second.public().to_peer_id());
+
+        std::fs::write(&path, b"not a key").unwrap();
+        assert!(load_or_create(&path).is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
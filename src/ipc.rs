@@ -0,0 +1,204 @@
+use std::{
+    cell::RefCell,
+    future::Future,
+    path::Path,
+    time::Duration
+};
+
+use tokio::sync::{mpsc, oneshot};
+
+use crate::error::NotepadError;
+
+/// A command that came over the daemon socket, answered with everything
+/// printed while it ran, see [`capture`].
+#[derive(Debug)]
+pub struct Command {
+    pub line: String,
+    pub reply: oneshot::Sender<String>,
+}
+
+/// Longest command taken from a client, enough for a long pasted line.
+const MAX_COMMAND: usize = 1024 * 1024;
+/// Longest a client is given to send its command, so stalled connections
+/// don't pile up.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+tokio::task_local! {
+    /// Output of the command being run for a client, if one is. Kept per
+    /// task rather than per thread, as the task running commands can move
+    /// between worker threads while it waits, and tasks it spawned print
+    /// nothing meant for the client.
+    static CAPTURED: RefCell<Option<String>>;
+}
+
+/// Runs `task`, the one that runs the commands of clients, so that what it
+/// prints can be kept for them, see [`capture`].
+pub async fn scope<F: Future>(task: F) -> F::Output {
+    CAPTURED.scope(RefCell::new(None), task).await
+}
+
+/// Keeps a printed line for the client of the command being run, if any.
+pub fn keep(line: &str) {
+    let _ = CAPTURED.try_with(|captured| {
+        if let Some(captured) = &mut *captured.borrow_mut() {
+            captured.push_str(line);
+            captured.push('\n');
+        }
+    });
+}
+
+/// Starts keeping what this task prints for a client, if it runs in a
+/// [`scope`].
+pub fn capture() {
+    let _ = CAPTURED.try_with(|captured| captured.replace(Some(String::new())));
+}
+
+/// Stops keeping what is printed, returning what was.
+pub fn captured() -> String {
+    CAPTURED.try_with(|captured| captured.take()).ok().flatten().unwrap_or_default()
+}
+
+/// Takes commands on the Unix socket at `path` until the node exits, one per
+/// connection: the client writes the command, shuts its side down and reads
+/// the output until the daemon closes the connection. Commands over a
+/// MiB, or not sent within ten seconds, are answered with an error instead
+/// of being run. A socket left behind by
+/// an earlier run is replaced, but not one a daemon still listens on, nor
+/// anything that isn't a socket.
+#[cfg(unix)]
+pub async fn serve(path: &Path, commands: mpsc::Sender<Command>) -> Result<(), NotepadError> {
+    use std::{io::ErrorKind, os::unix::fs::FileTypeExt};
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{UnixListener, UnixStream},
+        time
+    };
+
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if !metadata.file_type().is_socket() => {
+            return Err(NotepadError::command(format!("{} isn't a socket, so it is left alone", path.display())));
+        },
+        Ok(_) if UnixStream::connect(path).await.is_ok() => {
+            return Err(NotepadError::command(format!("A daemon is already listening on {}", path.display())));
+        },
+        Ok(_) => std::fs::remove_file(path)?,
+        Err(e) if e.kind() == ErrorKind::NotFound => {},
+        Err(e) => return Err(e.into()),
+    }
+    let listener = UnixListener::bind(path)?;
+
+    loop {
+        let (mut stream, _) = listener.accept().await?;
+        let commands = commands.clone();
+
+        tokio::spawn(async move {
+            let mut line = String::new();
+            let mut limited = (&mut stream).take(MAX_COMMAND as u64 + 1);
+            let refused = match time::timeout(READ_TIMEOUT, limited.read_to_string(&mut line)).await {
+                Ok(Ok(_)) if line.len() > MAX_COMMAND => "Command is too long\n",
+                Ok(Ok(_)) => "",
+                Ok(Err(_)) => return,
+                Err(_) => "Timed out reading the command\n",
+            };
+            if !refused.is_empty() {
+                let _ = stream.write_all(refused.as_bytes()).await;
+                return;
+            }
+
+            let (reply, output) = oneshot::channel();
+            let line = line.trim_end_matches(['\r', '\n']).to_string();
+            if commands.send(Command { line, reply }).await.is_err() {
+                return;
+            }
+
+            if let Ok(output) = output.await {
+                let _ = stream.write_all(output.as_bytes()).await;
+            }
+        });
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn serve(_path: &Path, _commands: mpsc::Sender<Command>) -> Result<(), NotepadError> {
+    Err(NotepadError::command("Daemon mode needs Unix domain sockets, which this platform lacks"))
+}
+
+/// Runs `command` on the daemon listening at `path`, returning its output.
+#[cfg(unix)]
+pub async fn send(path: &Path, command: &str) -> Result<String, NotepadError> {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::UnixStream
+    };
+
+    let mut stream = UnixStream::connect(path).await?;
+    stream.write_all(command.as_bytes()).await?;
+    stream.shutdown().await?;
+
+    let mut output = String::new();
+    stream.read_to_string(&mut output).await?;
+
+    Ok(output)
+}
+
+#[cfg(not(unix))]
+pub async fn send(_path: &Path, _command: &str) -> Result<String, NotepadError> {
+    Err(NotepadError::command("Daemon mode needs Unix domain sockets, which this platform lacks"))
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn commands_are_answered_with_their_output() {
+        let path = std::env::temp_dir().join(format!("p2p-notepad-{}.sock", std::process::id()));
+        let (sender, mut commands) = mpsc::channel(1);
+
+        let server = {
+            let path = path.clone();
+            tokio::spawn(async move { serve(&path, sender).await })
+        };
+        while !path.exists() {
+            tokio::task::yield_now().await;
+        }
+
+        let client = tokio::spawn({
+            let path = path.clone();
+            async move { send(&path, "see\n").await }
+        });
+
+        let command = commands.recv().await.unwrap();
+        assert_eq!(command.line, "see");
+        let output = scope(async {
+            capture();
+            keep("hello");
+            // Other tasks print nothing for the client.
+            tokio::spawn(async { keep("elsewhere") }).await.unwrap();
+            captured()
+        }).await;
+        command.reply.send(output).unwrap();
+
+        assert_eq!(client.await.unwrap().unwrap(), "hello\n");
+        // Commands too long to take are refused without being run.
+        let long = "a".repeat(MAX_COMMAND + 1);
+        assert_eq!(send(&path, &long).await.unwrap(), "Command is too long\n");
+        assert!(commands.try_recv().is_err());
+
+        // A second daemon leaves the socket of the first alone.
+        assert!(serve(&path, mpsc::channel(1).0).await.is_err());
+        server.abort();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn only_sockets_are_replaced() {
+        let path = std::env::temp_dir().join(format!("p2p-notepad-{}.txt", std::process::id()));
+        std::fs::write(&path, "notes").unwrap();
+
+        assert!(serve(&path, mpsc::channel(1).0).await.is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "notes");
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,199 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{SystemTime, UNIX_EPOCH}
+};
+
+use libp2p::PeerId;
+
+use crate::{
+    diff::{Diff, MessageBuf},
+    error::NotepadError,
+    notepad::Notepad
+};
+
+/// Operations kept for `hist` by default, older ones are forgotten, see [`Journal::limit`].
+pub const MAX_ENTRIES: usize = 10_000;
+
+/// One operation applied to a document of the room.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// Counts the operations applied since the room was joined, from 1.
+    pub seq: u64,
+    pub at: SystemTime,
+    /// Peer or user that made the operation, `None` if unknown.
+    pub author: Option<PeerId>,
+    pub document: String,
+    pub diff: Diff,
+    /// Reverts `diff`, to step back to before it, see [`Journal::at`].
+    pub inverse: Diff,
+}
+
+impl Entry {
+    /// Time of day the operation was applied, as `HH:MM:SS` in UTC.
+    pub fn clock(&self) -> String {
+        let secs = self.at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() % 86_400;
+
+        format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    }
+}
+
+/// Every operation applied to the room's documents, local edits and the
+/// ones peers published alike, newest last.
+#[derive(Debug)]
+pub struct Journal {
+    entries: VecDeque<Entry>,
+    seq: u64,
+    /// Number of the last operation before each document's text was last
+    /// replaced whole, by a snapshot, sync or import, which can't be stepped back over.
+    replaced: HashMap<String, u64>,
+    /// Most operations kept.
+    limit: usize,
+}
+
+impl Default for Journal {
+    fn default() -> Self {
+        Self { entries: VecDeque::new(), seq: 0, replaced: HashMap::new(), limit: MAX_ENTRIES }
+    }
+}
+
+impl Journal {
+    /// Keeps at most `limit` operations, forgetting the oldest beyond it.
+    pub fn limit(&mut self, limit: usize) {
+        self.limit = limit;
+        self.entries.drain(..self.entries.len().saturating_sub(limit));
+    }
+
+    /// Records `diffs` applied to `document`, given the `inverse` returned when applying them.
+    pub fn record(&mut self, author: Option<PeerId>, document: &str, diffs: &MessageBuf, inverse: &MessageBuf, at: SystemTime) {
+        // The inverse reverts the diffs last to first.
+        for (diff, inverse) in diffs.messages.iter().zip(inverse.messages.iter().rev()) {
+            if self.entries.len() >= self.limit {
+                self.entries.pop_front();
+            }
+
+            self.seq += 1;
+            self.entries.push_back(Entry {
+                seq: self.seq,
+                at,
+                author,
+                document: document.to_string(),
+                diff: diff.clone(),
+                inverse: inverse.clone(),
+            });
+        }
+    }
+
+    /// Notes that the text of `document` was replaced whole.
+    pub fn replaced(&mut self, document: &str) {
+        self.replaced.insert(document.to_string(), self.seq);
+    }
+
+    /// The text of `document` as it was right after operation `seq`, given
+    /// its current `text`, found by reverting every later operation to it.
+    pub fn at(&self, seq: u64, document: &str, text: &str) -> Result<String, NotepadError> {
+        if seq > self.seq {
+            return Err(NotepadError::command(format!("Only {} operations were applied so far", self.seq)));
+        }
+        if let Some(first) = self.entries.front().map(|entry| entry.seq).filter(|&first| seq + 1 < first) {
+            return Err(NotepadError::command(format!("Operations before #{first} were forgotten")));
+        }
+        if let Some(&replaced) = self.replaced.get(document).filter(|&&replaced| replaced > seq) {
+            return Err(NotepadError::command(format!("The document was replaced whole after operation #{replaced}")));
+        }
+
+        let mut notepad = Notepad::new(text.to_string());
+        for entry in self.entries.iter().rev().take_while(|entry| entry.seq > seq).filter(|entry| entry.document == document) {
+            notepad.apply_diff(&entry.inverse)?;
+        }
+
+        Ok(notepad.into_text())
+    }
+
+    /// The last `n` operations, oldest first.
+    pub fn recent(&self, n: usize) -> impl Iterator<Item = &Entry> {
+        self.entries.iter().skip(self.entries.len().saturating_sub(n))
+    }
+
+    /// Forgets the operations made before their document was last replaced
+    /// whole, which can't be stepped back over, for when the operation log
+    /// is compacted and no longer holds them either.
+    pub fn compact(&mut self) {
+        let replaced = &self.replaced;
+        self.entries.retain(|entry| replaced.get(&entry.document).is_none_or(|&replaced| entry.seq > replaced));
+    }
+
+    /// Forgets every operation, for when the room is left.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.replaced.clear();
+        self.seq = 0;
+    }
+
+    pub fn memory(&self) -> usize {
+        self.entries.iter().map(|entry| size_of::<Entry>() + entry.document.capacity()).sum::<usize>()
+            + self.replaced.keys().map(|document| size_of::<(String, u64)>() + document.capacity()).sum::<usize>()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::diff::Operation;
+
+    fn apply(journal: &mut Journal, notepad: &mut Notepad, diffs: Vec<Diff>, at: SystemTime) {
+        let diffs = MessageBuf { messages: diffs };
+        let inverse = notepad.apply_inverting(&diffs).unwrap();
+
+        journal.record(None, "main", &diffs, &inverse, at);
+    }
+
+    fn ins(index: usize, char: char) -> Diff {
+        Diff { opcode: Operation::Ins, operand: Some(char), index }
+    }
+
+    #[test]
+    fn keeps_the_latest_operations() {
+        let mut journal = Journal::default();
+        let mut notepad = Notepad::default();
+        let at = UNIX_EPOCH + std::time::Duration::from_secs(86_400 + 3723);
+
+        apply(&mut journal, &mut notepad, vec![ins(0, 'a'), ins(1, 'b'), ins(2, 'c')], at);
+        let recent: Vec<_> = journal.recent(2).map(|entry| (entry.seq, entry.diff.index)).collect();
+        assert_eq!(recent, vec![(2, 1), (3, 2)]);
+        assert_eq!(journal.recent(10).next().unwrap().clock(), "01:02:03");
+
+        apply(&mut journal, &mut notepad, (0..MAX_ENTRIES).map(|_| ins(0, 'x')).collect(), at);
+        assert_eq!(journal.recent(MAX_ENTRIES + 1).count(), MAX_ENTRIES);
+        assert_eq!(journal.recent(1).next().unwrap().seq, MAX_ENTRIES as u64 + 3);
+        assert!(journal.at(1, "main", notepad.text()).is_err());
+
+        journal.limit(2);
+        assert_eq!(journal.recent(10).count(), 2);
+        apply(&mut journal, &mut notepad, vec![ins(0, 'y')], at);
+        assert_eq!(journal.recent(10).map(|entry| entry.diff.operand).collect::<Vec<_>>(), vec![Some('x'), Some('y')]);
+    }
+
+    #[test]
+    fn steps_back_to_earlier_text() {
+        let mut journal = Journal::default();
+        let mut notepad = Notepad::new("hello".to_string());
+
+        apply(&mut journal, &mut notepad, vec![ins(5, '!'), Diff { opcode: Operation::Rep, operand: Some('j'), index: 0 }], UNIX_EPOCH);
+        apply(&mut journal, &mut notepad, vec![Diff { opcode: Operation::DelRange(2), operand: None, index: 1 }], UNIX_EPOCH);
+        assert_eq!(notepad.text(), "jlo!");
+
+        assert_eq!(journal.at(0, "main", notepad.text()).unwrap(), "hello");
+        assert_eq!(journal.at(1, "main", notepad.text()).unwrap(), "hello!");
+        assert_eq!(journal.at(2, "main", notepad.text()).unwrap(), "jello!");
+        assert_eq!(journal.at(3, "main", notepad.text()).unwrap(), "jlo!");
+        assert!(journal.at(4, "main", notepad.text()).is_err());
+
+        journal.replaced("main");
+        assert!(journal.at(2, "main", notepad.text()).is_err());
+        assert_eq!(journal.at(3, "main", notepad.text()).unwrap(), "jlo!");
+
+        journal.compact();
+        assert_eq!(journal.recent(10).count(), 0);
+        assert_eq!(journal.at(3, "main", notepad.text()).unwrap(), "jlo!");
+    }
+}
@@ -0,0 +1,370 @@
+//! Keys of the full screen editor, from a preset in the config file with
+//! `keys <default|vim|emacs>`, changed one at a time with `bind <key>
+//! <binding>`, or `bind-normal` for the normal mode of modal presets.
+
+use std::{collections::HashMap, fmt, str::FromStr};
+
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use crate::error::NotepadError;
+
+/// A set of bindings to start from.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Preset {
+    /// Arrows to move, Ctrl+Z and Ctrl+Y to undo and redo, Esc to quit.
+    #[default]
+    Default,
+    /// Modal: keys move and edit in normal mode, `i` starts typing and Esc stops.
+    Vim,
+    /// Ctrl+F, B, N and P to move, Ctrl+A and E to the ends of lines.
+    Emacs,
+}
+
+impl FromStr for Preset {
+    type Err = NotepadError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "default" => Ok(Preset::Default),
+            "vim" => Ok(Preset::Vim),
+            "emacs" => Ok(Preset::Emacs),
+            _ => Err(NotepadError::command(format!("Unknown key preset `{s}`, expected default, vim or emacs"))),
+        }
+    }
+}
+
+/// What a key is bound to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Binding {
+    Left,
+    Right,
+    Up,
+    Down,
+    LineStart,
+    LineEnd,
+    DocumentStart,
+    DocumentEnd,
+    Newline,
+    DeleteBack,
+    DeleteForward,
+    Undo,
+    Redo,
+    /// Starts typing, in modal presets.
+    InsertMode,
+    /// Starts typing after the character at the cursor, in modal presets.
+    Append,
+    /// Stops typing, in modal presets.
+    NormalMode,
+    NextDocument,
+    PreviousDocument,
+    /// Shows every room, to switch to another.
+    Dashboard,
+    CatchUp,
+    Quit,
+}
+
+const BINDINGS: [(&str, Binding); 21] = [
+    ("left", Binding::Left),
+    ("right", Binding::Right),
+    ("up", Binding::Up),
+    ("down", Binding::Down),
+    ("line-start", Binding::LineStart),
+    ("line-end", Binding::LineEnd),
+    ("doc-start", Binding::DocumentStart),
+    ("doc-end", Binding::DocumentEnd),
+    ("newline", Binding::Newline),
+    ("delete-back", Binding::DeleteBack),
+    ("delete-forward", Binding::DeleteForward),
+    ("undo", Binding::Undo),
+    ("redo", Binding::Redo),
+    ("insert-mode", Binding::InsertMode),
+    ("append", Binding::Append),
+    ("normal-mode", Binding::NormalMode),
+    ("next-doc", Binding::NextDocument),
+    ("previous-doc", Binding::PreviousDocument),
+    ("dashboard", Binding::Dashboard),
+    ("catch-up", Binding::CatchUp),
+    ("quit", Binding::Quit),
+];
+
+impl FromStr for Binding {
+    type Err = NotepadError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        BINDINGS.iter().find(|(name, _)| *name == s).map(|&(_, binding)| binding).ok_or_else(|| {
+            let names: Vec<_> = BINDINGS.iter().map(|(name, _)| *name).collect();
+            NotepadError::command(format!("Unknown binding `{s}`, expected one of {}", names.join(", ")))
+        })
+    }
+}
+
+/// Names of the keys that aren't characters.
+const KEYS: [(&str, KeyCode); 13] = [
+    ("esc", KeyCode::Esc),
+    ("enter", KeyCode::Enter),
+    ("tab", KeyCode::Tab),
+    ("backspace", KeyCode::Backspace),
+    ("delete", KeyCode::Delete),
+    ("left", KeyCode::Left),
+    ("right", KeyCode::Right),
+    ("up", KeyCode::Up),
+    ("down", KeyCode::Down),
+    ("home", KeyCode::Home),
+    ("end", KeyCode::End),
+    ("pageup", KeyCode::PageUp),
+    ("pagedown", KeyCode::PageDown),
+];
+
+/// A key with the modifiers held, like `ctrl+z`. Shift isn't one, as it
+/// is part of the character typed, and held with keys that move to select.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Key {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl From<KeyEvent> for Key {
+    fn from(key: KeyEvent) -> Self {
+        Self { code: key.code, modifiers: key.modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT) }
+    }
+}
+
+impl FromStr for Key {
+    type Err = NotepadError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut modifiers = KeyModifiers::NONE;
+        let mut rest = s;
+
+        loop {
+            if let Some(after) = rest.strip_prefix("ctrl+").filter(|after| !after.is_empty()) {
+                modifiers |= KeyModifiers::CONTROL;
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix("alt+").filter(|after| !after.is_empty()) {
+                modifiers |= KeyModifiers::ALT;
+                rest = after;
+            } else {
+                break;
+            }
+        }
+
+        let mut chars = rest.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) => KeyCode::Char(c),
+            _ => KEYS.iter().find(|(name, _)| *name == rest).map(|&(_, code)| code)
+                .ok_or_else(|| NotepadError::command(format!("Unknown key `{s}`, expected a character or one of {} after any `ctrl+` and `alt+`", KEYS.map(|(name, _)| name).join(", "))))?,
+        };
+
+        Ok(Self { code, modifiers })
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            write!(f, "Ctrl+")?;
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            write!(f, "Alt+")?;
+        }
+
+        match self.code {
+            KeyCode::Char(c) => write!(f, "{}", c.to_uppercase()),
+            code => match KEYS.iter().find(|&&(_, known)| known == code) {
+                Some((name, _)) => write!(f, "{}{}", name[..1].to_uppercase(), &name[1..]),
+                None => write!(f, "{code:?}"),
+            },
+        }
+    }
+}
+
+/// The bindings of the editor's keys. Unbound characters are typed, unless
+/// in the normal mode of a modal preset or held with Ctrl.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Keymap {
+    /// Bindings in normal mode, for modal presets.
+    normal: Option<HashMap<Key, Binding>>,
+    /// Bindings while typing.
+    insert: HashMap<Key, Binding>,
+    /// Bindings made over the preset's, kept when the preset changes,
+    /// with whether they are for normal mode.
+    overrides: Vec<(bool, Key, Binding)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::new(Preset::Default)
+    }
+}
+
+impl Keymap {
+    pub fn new(preset: Preset) -> Self {
+        let bindings = |keys: &[(&str, Binding)]| keys.iter().map(|&(key, binding)| (key.parse().expect("preset keys are valid"), binding)).collect();
+        let arrows = [
+            ("left", Binding::Left),
+            ("right", Binding::Right),
+            ("up", Binding::Up),
+            ("down", Binding::Down),
+            ("home", Binding::LineStart),
+            ("end", Binding::LineEnd),
+            // Ctrl+C is read as a key rather than a signal while the editor runs.
+            ("ctrl+c", Binding::Quit),
+        ];
+        let typing = [("enter", Binding::Newline), ("backspace", Binding::DeleteBack), ("delete", Binding::DeleteForward)];
+
+        let (normal, insert): (Option<Vec<_>>, Vec<_>) = match preset {
+            Preset::Default => (None, vec![
+                ("esc", Binding::Quit),
+                ("ctrl+q", Binding::Quit),
+                ("ctrl+z", Binding::Undo),
+                ("ctrl+y", Binding::Redo),
+                ("ctrl+r", Binding::Dashboard),
+                ("ctrl+n", Binding::CatchUp),
+                ("ctrl+pagedown", Binding::NextDocument),
+                ("ctrl+pageup", Binding::PreviousDocument),
+            ]),
+            Preset::Vim => (Some(vec![
+                ("h", Binding::Left),
+                ("j", Binding::Down),
+                ("k", Binding::Up),
+                ("l", Binding::Right),
+                ("0", Binding::LineStart),
+                ("$", Binding::LineEnd),
+                ("g", Binding::DocumentStart),
+                ("G", Binding::DocumentEnd),
+                ("x", Binding::DeleteForward),
+                ("X", Binding::DeleteBack),
+                ("u", Binding::Undo),
+                ("ctrl+r", Binding::Redo),
+                ("i", Binding::InsertMode),
+                ("a", Binding::Append),
+                ("]", Binding::NextDocument),
+                ("[", Binding::PreviousDocument),
+                ("ctrl+w", Binding::Dashboard),
+                ("n", Binding::CatchUp),
+                ("q", Binding::Quit),
+            ]), vec![("esc", Binding::NormalMode)]),
+            Preset::Emacs => (None, vec![
+                ("ctrl+b", Binding::Left),
+                ("ctrl+f", Binding::Right),
+                ("ctrl+p", Binding::Up),
+                ("ctrl+n", Binding::Down),
+                ("ctrl+a", Binding::LineStart),
+                ("ctrl+e", Binding::LineEnd),
+                ("alt+<", Binding::DocumentStart),
+                ("alt+>", Binding::DocumentEnd),
+                ("ctrl+d", Binding::DeleteForward),
+                ("ctrl+/", Binding::Undo),
+                ("ctrl+_", Binding::Undo),
+                ("alt+/", Binding::Redo),
+                ("alt+n", Binding::NextDocument),
+                ("alt+p", Binding::PreviousDocument),
+                ("alt+r", Binding::Dashboard),
+                ("alt+u", Binding::CatchUp),
+                ("ctrl+q", Binding::Quit),
+            ]),
+        };
+
+        Self {
+            normal: normal.map(|normal| bindings(&[&arrows[..], &normal].concat())),
+            insert: bindings(&[&arrows[..], &typing, &insert].concat()),
+            overrides: Vec::new(),
+        }
+    }
+
+    /// Starts over from `preset`, keeping the bindings made over the last one.
+    pub fn preset(&mut self, preset: Preset) {
+        let overrides = std::mem::take(&mut self.overrides);
+
+        *self = Self::new(preset);
+        for (normal, key, binding) in overrides {
+            self.apply(normal, key, binding);
+        }
+    }
+
+    /// Binds a key from its definition, `<key> <binding>`, while typing or
+    /// in the normal mode of modal presets.
+    pub fn bind(&mut self, definition: &str, normal: bool) -> Result<(), NotepadError> {
+        let (key, binding) = definition.trim().split_once(char::is_whitespace)
+            .ok_or_else(|| NotepadError::command("Expected format `bind <key> <binding>`"))?;
+        let (key, binding) = (key.parse()?, binding.trim().parse()?);
+
+        self.apply(normal, key, binding);
+
+        Ok(())
+    }
+
+    fn apply(&mut self, normal: bool, key: Key, binding: Binding) {
+        self.overrides.push((normal, key, binding));
+
+        match (normal, &mut self.normal) {
+            (true, Some(bindings)) => bindings.insert(key, binding),
+            // Bindings for normal mode make a preset modal.
+            (true, None) => self.normal.insert(HashMap::new()).insert(key, binding),
+            (false, _) => self.insert.insert(key, binding),
+        };
+    }
+
+    /// Whether the editor starts in normal mode rather than typing.
+    pub fn modal(&self) -> bool {
+        self.normal.is_some()
+    }
+
+    /// What `key` is bound to, in normal mode or while typing.
+    pub fn binding(&self, key: KeyEvent, normal: bool) -> Option<Binding> {
+        let bindings = self.normal.as_ref().filter(|_| normal).unwrap_or(&self.insert);
+
+        bindings.get(&Key::from(key)).copied()
+    }
+
+    /// The shortest key bound to `binding`, to mention it, preferring those bound while typing.
+    pub fn key(&self, binding: Binding) -> Option<String> {
+        let keys = |bindings: &HashMap<Key, Binding>| bindings.iter()
+            .filter(|&(_, &bound)| bound == binding)
+            .map(|(key, _)| key.to_string())
+            .min_by_key(|key| (key.len(), key.clone()));
+
+        keys(&self.insert).or_else(|| self.normal.as_ref().and_then(keys))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn key(s: &str) -> KeyEvent {
+        let key: Key = s.parse().unwrap();
+        KeyEvent::new(key.code, key.modifiers)
+    }
+
+    #[test]
+    fn keys_are_bound() {
+        let mut keymap = Keymap::default();
+        assert_eq!(keymap.binding(key("ctrl+z"), false), Some(Binding::Undo));
+        assert_eq!(keymap.binding(KeyEvent::new(KeyCode::Left, KeyModifiers::SHIFT), false), Some(Binding::Left));
+        assert_eq!(keymap.binding(key("z"), false), None);
+        assert_eq!(keymap.key(Binding::CatchUp).as_deref(), Some("Ctrl+N"));
+        assert_eq!(keymap.key(Binding::Quit).as_deref(), Some("Esc"));
+
+        keymap.bind("ctrl+s  catch-up", false).unwrap();
+        keymap.bind("alt++ next-doc", false).unwrap();
+        assert_eq!(keymap.binding(key("ctrl+s"), false), Some(Binding::CatchUp));
+        assert_eq!(keymap.binding(KeyEvent::new(KeyCode::Char('+'), KeyModifiers::ALT), false), Some(Binding::NextDocument));
+        assert!(keymap.bind("ctrl+s", false).is_err());
+        assert!(keymap.bind("hyper+s undo", false).is_err());
+        assert!(keymap.bind("ctrl+s fly", false).is_err());
+
+        // Bindings made are kept over a new preset.
+        keymap.preset(Preset::Vim);
+        assert!(keymap.modal());
+        assert_eq!(keymap.binding(key("j"), true), Some(Binding::Down));
+        assert_eq!(keymap.binding(key("j"), false), None);
+        assert_eq!(keymap.binding(key("esc"), false), Some(Binding::NormalMode));
+        assert_eq!(keymap.binding(key("ctrl+s"), false), Some(Binding::CatchUp));
+
+        let emacs = Keymap::new(Preset::Emacs);
+        assert!(!emacs.modal());
+        assert_eq!(emacs.binding(key("ctrl+n"), false), Some(Binding::Down));
+        assert_eq!(emacs.binding(key("alt+>"), false), Some(Binding::DocumentEnd));
+    }
+}
@@ -0,0 +1,119 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant}
+};
+
+use libp2p::PeerId;
+
+/// Number of round trips kept per peer.
+pub const MAX_SAMPLES: usize = 100;
+
+/// Acks arriving later than this are ignored.
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Round trip times of probes published to the room, measured from each peer's ack.
+#[derive(Debug, Default)]
+pub struct Latency {
+    /// Probes awaiting acks, by id, with when they were sent.
+    pending: HashMap<u64, Instant>,
+    samples: HashMap<PeerId, VecDeque<Duration>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Percentiles {
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub samples: usize,
+}
+
+impl Latency {
+    /// Starts a probe, returning the id to publish.
+    pub fn probe(&mut self, now: Instant) -> u64 {
+        self.pending.retain(|_, sent| now.saturating_duration_since(*sent) <= PROBE_TIMEOUT);
+
+        let id = rand::random();
+        self.pending.insert(id, now);
+
+        id
+    }
+
+    /// Records `peer_id` acking probe `id`, returning the round trip if the probe is ours.
+    /// Every peer acks the same probe, so it stays pending until it times out.
+    pub fn ack(&mut self, peer_id: PeerId, id: u64, now: Instant) -> Option<Duration> {
+        let round_trip = now.saturating_duration_since(*self.pending.get(&id)?);
+
+        if round_trip > PROBE_TIMEOUT {
+            return None;
+        }
+
+        let samples = self.samples.entry(peer_id).or_default();
+        if samples.len() == MAX_SAMPLES {
+            samples.pop_front();
+        }
+        samples.push_back(round_trip);
+
+        Some(round_trip)
+    }
+
+    /// Approximate bytes held by pending probes and samples.
+    pub fn memory(&self) -> usize {
+        let pending = self.pending.len() * size_of::<(u64, Instant)>();
+        let samples: usize = self.samples.values().map(|samples| size_of::<PeerId>() + samples.len() * size_of::<Duration>()).sum();
+
+        pending + samples
+    }
+
+    pub fn percentiles(&self) -> impl Iterator<Item = (&PeerId, Percentiles)> {
+        self.samples.iter().map(|(peer_id, samples)| {
+            let mut sorted: Vec<_> = samples.iter().copied().collect();
+            sorted.sort();
+
+            let percentile = |p: usize| sorted[(sorted.len() * p / 100).min(sorted.len() - 1)];
+
+            (peer_id, Percentiles {
+                p50: percentile(50),
+                p90: percentile(90),
+                p99: percentile(99),
+                samples: sorted.len(),
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn percentiles_from_acks() {
+        let mut latency = Latency::default();
+        let peer_id = PeerId::random();
+        let start = Instant::now();
+
+        for ms in 1..=100 {
+            let id = latency.probe(start);
+            assert_eq!(latency.ack(peer_id, id, start + Duration::from_millis(ms)), Some(Duration::from_millis(ms)));
+        }
+
+        assert_eq!(latency.ack(peer_id, 7, start), None);
+
+        let (_, percentiles) = latency.percentiles().next().unwrap();
+        assert_eq!(percentiles, Percentiles {
+            p50: Duration::from_millis(51),
+            p90: Duration::from_millis(91),
+            p99: Duration::from_millis(100),
+            samples: MAX_SAMPLES,
+        });
+    }
+
+    #[test]
+    fn late_acks_are_ignored() {
+        let mut latency = Latency::default();
+        let start = Instant::now();
+        let id = latency.probe(start);
+
+        assert_eq!(latency.ack(PeerId::random(), id, start + PROBE_TIMEOUT * 2), None);
+        assert_eq!(latency.percentiles().count(), 0);
+    }
+}
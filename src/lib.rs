@@ -0,0 +1,183 @@
+//! A peer to peer notepad: documents shared in rooms over libp2p gossipsub.
+//!
+//! The [`Engine`] holds a room's documents and the sync logic, driven through
+//! any [`Transport`]. [`Network`] is the libp2p transport, built from a
+//! [`SwarmFactory`] so embedders can set up the same swarm and behaviours.
+//!
+//! The document logic, [`Notepad`] and [`Diff`] with the CRDT and merging
+//! around them, builds without the `native` feature, which brings in tokio
+//! and libp2p, so it also compiles to `wasm32-unknown-unknown` and, with the
+//! `wasm` feature, is bound for a web frontend in [`wasm`](crate::wasm).
+
+pub mod crdt;
+pub mod diff;
+pub mod error;
+pub mod escape;
+pub mod lines;
+pub mod merge;
+pub mod notepad;
+pub mod patch;
+pub mod rope;
+pub mod varint;
+
+/// Prints like the standard `println`, through [`output::print`] so that the
+/// modules below print JSON events when asked to.
+#[cfg(feature = "native")]
+macro_rules! println {
+    () => {
+        $crate::output::print("")
+    };
+    ($($arg:tt)*) => {
+        $crate::output::print(&format!($($arg)*))
+    };
+}
+
+#[cfg(feature = "native")]
+pub mod access;
+#[cfg(feature = "native")]
+pub mod activity;
+#[cfg(feature = "native")]
+pub mod alias;
+#[cfg(feature = "native")]
+pub mod attachment;
+#[cfg(feature = "native")]
+pub mod attribution;
+#[cfg(feature = "native")]
+pub mod background;
+#[cfg(feature = "native")]
+pub mod backfill;
+#[cfg(feature = "native")]
+pub mod batch;
+#[cfg(feature = "native")]
+pub mod backup;
+#[cfg(feature = "native")]
+pub mod archive;
+#[cfg(feature = "native")]
+pub mod capture;
+#[cfg(feature = "native")]
+pub mod causal;
+#[cfg(feature = "native")]
+pub mod clipboard;
+#[cfg(feature = "native")]
+pub mod config;
+#[cfg(feature = "native")]
+pub mod conflict;
+#[cfg(feature = "native")]
+pub mod container;
+#[cfg(feature = "native")]
+pub mod control;
+#[cfg(feature = "native")]
+pub mod cursors;
+#[cfg(feature = "native")]
+pub mod dashboard;
+#[cfg(feature = "native")]
+pub mod delivery;
+#[cfg(feature = "native")]
+pub mod describe;
+#[cfg(feature = "native")]
+pub mod directory;
+#[cfg(feature = "native")]
+pub mod document;
+#[cfg(feature = "native")]
+pub mod editor;
+#[cfg(feature = "native")]
+pub mod engine;
+#[cfg(feature = "native")]
+pub mod fanout;
+#[cfg(feature = "native")]
+pub mod help;
+#[cfg(feature = "native")]
+pub mod history;
+#[cfg(feature = "native")]
+pub mod identity;
+#[cfg(feature = "native")]
+pub mod ipc;
+#[cfg(feature = "native")]
+pub mod journal;
+#[cfg(feature = "native")]
+pub mod keymap;
+#[cfg(feature = "native")]
+pub mod latency;
+#[cfg(feature = "native")]
+pub mod links;
+#[cfg(feature = "native")]
+pub mod lock;
+#[cfg(feature = "native")]
+pub mod log;
+#[cfg(feature = "native")]
+pub mod manifest;
+#[cfg(feature = "native")]
+pub mod matrix;
+#[cfg(feature = "native")]
+pub mod memory;
+#[cfg(all(test, feature = "native"))]
+pub mod loopback;
+#[cfg(feature = "native")]
+pub mod message;
+#[cfg(feature = "native")]
+pub mod network;
+#[cfg(feature = "native")]
+pub mod oplog;
+#[cfg(feature = "native")]
+pub mod output;
+#[cfg(feature = "native")]
+pub mod pacing;
+#[cfg(feature = "native")]
+pub mod partition;
+#[cfg(feature = "native")]
+pub mod paste;
+#[cfg(feature = "native")]
+pub mod presence;
+#[cfg(feature = "native")]
+pub mod rendezvous;
+#[cfg(feature = "native")]
+pub mod retry;
+#[cfg(feature = "native")]
+pub mod room;
+#[cfg(feature = "native")]
+pub mod sanitize;
+#[cfg(feature = "native")]
+pub mod scoring;
+#[cfg(feature = "native")]
+pub mod script;
+#[cfg(feature = "native")]
+pub mod seal;
+#[cfg(feature = "native")]
+pub mod search;
+#[cfg(feature = "native")]
+pub mod session;
+#[cfg(feature = "native")]
+pub mod sources;
+#[cfg(feature = "native")]
+pub mod spell;
+#[cfg(feature = "native")]
+pub mod storage;
+#[cfg(feature = "native")]
+pub mod telemetry;
+#[cfg(feature = "native")]
+pub mod transport;
+#[cfg(feature = "native")]
+pub mod unread;
+#[cfg(feature = "native")]
+pub mod users;
+#[cfg(feature = "native")]
+pub mod vacancy;
+#[cfg(feature = "native")]
+pub mod watch;
+#[cfg(feature = "native")]
+pub mod whiteboard;
+#[cfg(feature = "native")]
+pub mod workspace;
+
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use diff::{Diff, MessageBuf, Operation};
+#[cfg(feature = "native")]
+pub use engine::Engine;
+pub use error::NotepadError;
+#[cfg(feature = "native")]
+pub use network::{MyBehaviour, Network, SwarmFactory};
+pub use notepad::Notepad;
+#[cfg(feature = "native")]
+pub use transport::{Event, Incoming, Transport};
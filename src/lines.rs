@@ -0,0 +1,196 @@
+use std::ops::Range;
+
+use crate::{
+    diff::{Diff, MessageBuf, Operation},
+    error::NotepadError
+};
+
+/// Lines `view` shows at once.
+pub const PAGE_LINES: usize = 40;
+
+/// An edit to a whole line, numbered from 1 as `see` shows them. Lines are
+/// turned into byte offsets against the local text before anything is
+/// published, so peers apply the same range diffs whatever their own text.
+#[derive(Debug, PartialEq)]
+pub enum LineEdit {
+    /// Inserts a line before the given one, or after the last with one past it.
+    Insert(usize, String),
+    /// Deletes a line along with its line break.
+    Delete(usize),
+    /// Replaces the text of a line, keeping its line break.
+    Replace(usize, String),
+}
+
+impl LineEdit {
+    /// Diffs making the edit to `text`.
+    pub fn diffs(&self, text: &str) -> Result<MessageBuf, NotepadError> {
+        let (line, insert) = match self {
+            LineEdit::Insert(line, insert) | LineEdit::Replace(line, insert) => (*line, insert.as_str()),
+            LineEdit::Delete(line) => (*line, ""),
+        };
+
+        if insert.contains('\n') {
+            return Err(NotepadError::command("A line can't hold a line break, edit one line at a time"));
+        }
+
+        let count = text.lines().count();
+        let out_of_range = || NotepadError::command(format!("No line {line}, the document has {count} lines"));
+        let mut messages = Vec::new();
+
+        match self {
+            LineEdit::Insert(..) if line == count + 1 => {
+                let insert = if text.is_empty() || text.ends_with('\n') { format!("{insert}\n") } else { format!("\n{insert}") };
+                messages.push(insert_str(text.len(), insert));
+            },
+            LineEdit::Insert(..) => {
+                let (start, _) = span(text, line).ok_or_else(out_of_range)?;
+                messages.push(insert_str(start, format!("{insert}\n")));
+            },
+            LineEdit::Delete(_) => {
+                let (mut start, mut end) = span(text, line).ok_or_else(out_of_range)?;
+
+                if text[end..].starts_with('\n') {
+                    end += 1;
+                } else {
+                    // The last line has no break of its own, so the one before it goes.
+                    start = start.saturating_sub(1);
+                }
+                messages.push(Diff { opcode: Operation::DelRange(end - start), operand: None, index: start });
+            },
+            LineEdit::Replace(..) => {
+                let (start, end) = span(text, line).ok_or_else(out_of_range)?;
+
+                if end > start {
+                    messages.push(Diff { opcode: Operation::DelRange(end - start), operand: None, index: start });
+                }
+                if !insert.is_empty() {
+                    messages.push(insert_str(start, insert.to_string()));
+                }
+            },
+        }
+
+        Ok(MessageBuf { messages })
+    }
+}
+
+/// Byte range of lines `first` to `last`, numbered from 1, along with the
+/// line break after the last.
+pub fn range(text: &str, first: usize, last: usize) -> Result<Range<usize>, NotepadError> {
+    let count = text.lines().count();
+    if first == 0 || first > last || last > count {
+        return Err(NotepadError::command(format!("No lines {first} to {last}, the document has {count} lines")));
+    }
+
+    let (start, _) = span(text, first).expect("line is in the text");
+    let (_, end) = span(text, last).expect("line is in the text");
+
+    Ok(start..if text[end..].starts_with('\n') { end + 1 } else { end })
+}
+
+/// The first and last line, numbered from 1, of the byte range `range` of `text`.
+pub fn covered(text: &str, range: Range<usize>) -> (usize, usize) {
+    let bytes = text.as_bytes();
+    let line = |index: usize| bytes[..index.min(bytes.len())].iter().filter(|&&byte| byte == b'\n').count() + 1;
+
+    (line(range.start), line(range.end.saturating_sub(1).max(range.start)))
+}
+
+/// Up to `len` lines of `text` from line `first` on, numbered from 1, each
+/// with the index range of its text, as `view` shows them.
+pub fn numbered(text: &str, first: usize, len: usize) -> Result<Vec<String>, NotepadError> {
+    let count = text.lines().count();
+    if first == 0 || first > count.max(1) {
+        return Err(NotepadError::command(format!("No line {first}, the document has {count} lines")));
+    }
+
+    let mut start = 0;
+    let mut lines = Vec::new();
+    for (i, content) in text.split_inclusive('\n').enumerate() {
+        let line = content.trim_end_matches('\n');
+        if i + 1 >= first && lines.len() < len {
+            lines.push((i + 1, format!("{start}..{}", start + line.len()), line));
+        }
+
+        start += content.len();
+    }
+
+    let number_width = count.to_string().len();
+    let range_width = lines.iter().map(|(_, range, _)| range.len()).max().unwrap_or_default();
+
+    Ok(lines.into_iter().map(|(number, range, line)| format!("{number:>number_width$} {range:<range_width$} | {line}")).collect())
+}
+
+/// Byte range of the text of `line`, without its line break.
+fn span(text: &str, line: usize) -> Option<(usize, usize)> {
+    let mut start = 0;
+
+    for (i, content) in text.split_inclusive('\n').enumerate() {
+        if i + 1 == line {
+            return Some((start, start + content.trim_end_matches('\n').len()));
+        }
+
+        start += content.len();
+    }
+
+    None
+}
+
+fn insert_str(index: usize, text: String) -> Diff {
+    Diff { opcode: Operation::InsStr(text), operand: None, index }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::notepad::Notepad;
+
+    fn apply(text: &str, edit: LineEdit) -> String {
+        let mut notepad = Notepad::new(text.to_string());
+        notepad.apply_message_buf(&edit.diffs(text).unwrap()).unwrap();
+
+        notepad.into_text()
+    }
+
+    #[test]
+    fn edits_lines() {
+        assert_eq!(apply("a\nb\n", LineEdit::Insert(2, "x".to_string())), "a\nx\nb\n");
+        assert_eq!(apply("a\nb\n", LineEdit::Insert(3, "x".to_string())), "a\nb\nx\n");
+        assert_eq!(apply("a\nb", LineEdit::Insert(3, "x".to_string())), "a\nb\nx");
+        assert_eq!(apply("", LineEdit::Insert(1, "x".to_string())), "x\n");
+
+        assert_eq!(apply("a\nb\nc", LineEdit::Delete(2)), "a\nc");
+        assert_eq!(apply("a\nb\nc", LineEdit::Delete(3)), "a\nb");
+        assert_eq!(apply("a", LineEdit::Delete(1)), "");
+
+        assert_eq!(apply("a\nbé\n", LineEdit::Replace(2, "xy".to_string())), "a\nxy\n");
+        assert_eq!(apply("a\n\nc", LineEdit::Replace(2, "b".to_string())), "a\nb\nc");
+        assert_eq!(apply("a\nb", LineEdit::Replace(1, String::new())), "\nb");
+
+        assert!(LineEdit::Delete(3).diffs("a\nb\n").is_err());
+        assert!(LineEdit::Insert(0, "x".to_string()).diffs("a").is_err());
+        assert!(LineEdit::Replace(1, "x\ny".to_string()).diffs("a").is_err());
+    }
+
+    #[test]
+    fn ranges_of_lines() {
+        let text = "a\nbc\nd";
+
+        assert_eq!(range(text, 1, 2).unwrap(), 0..5);
+        assert_eq!(range(text, 3, 3).unwrap(), 5..6);
+        assert!(range(text, 0, 1).is_err() && range(text, 2, 1).is_err() && range(text, 1, 4).is_err());
+
+        assert_eq!(covered(text, 0..5), (1, 2));
+        assert_eq!(covered(text, 5..6), (3, 3));
+        assert_eq!(covered(text, 2..2), (2, 2));
+    }
+
+    #[test]
+    fn numbers_lines() {
+        let text = "one\n\nthree\nfour\nfive\nsix\nseven\neight\nnine\nten\n";
+
+        assert_eq!(numbered(text, 1, 3).unwrap(), vec![" 1 0..3  | one", " 2 4..4  | ", " 3 5..10 | three"]);
+        assert_eq!(numbered(text, 10, 5).unwrap(), vec!["10 42..45 | ten"]);
+        assert!(numbered("", 1, 5).unwrap().is_empty());
+        assert!(numbered(text, 0, 5).is_err() && numbered(text, 11, 5).is_err());
+    }
+}
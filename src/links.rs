@@ -0,0 +1,77 @@
+use std::process::Command;
+
+use crate::error::NotepadError;
+
+/// Schemes recognised as the start of a link. Anything else, `file:` or
+/// `javascript:` included, is left as text so peers can't get it launched.
+const SCHEMES: &[&str] = &["https://", "http://"];
+
+/// Punctuation ending a sentence rather than the link it follows.
+const TRAILING: &[char] = &['.', ',', ';', ':', '!', '?', '\'', '"', ')', ']', '>'];
+
+/// A link found in a document, with its line counting from 0.
+#[derive(Debug, PartialEq)]
+pub struct Link<'a> {
+    pub line: usize,
+    pub url: &'a str,
+}
+
+/// The `http` and `https` links in `text`, in order. A link runs to the next
+/// whitespace, less any punctuation closing the sentence around it.
+pub fn find(text: &str) -> Vec<Link<'_>> {
+    let mut links = Vec::new();
+
+    for (line, mut rest) in text.lines().enumerate() {
+        while let Some(start) = SCHEMES.iter().filter_map(|scheme| rest.find(scheme)).min() {
+            let candidate = &rest[start..];
+            let end = candidate.find(char::is_whitespace).unwrap_or(candidate.len());
+            let url = candidate[..end].trim_end_matches(TRAILING);
+
+            if !SCHEMES.contains(&url) {
+                links.push(Link { line, url });
+            }
+
+            rest = &candidate[end..];
+        }
+    }
+
+    links
+}
+
+/// Opens `url` in the default browser, without going through a shell.
+pub fn open(url: &str) -> Result<(), NotepadError> {
+    if !SCHEMES.iter().any(|scheme| url.starts_with(scheme)) {
+        return Err(NotepadError::command(format!("Refusing to open `{url}`, only http and https links are opened")));
+    }
+
+    let mut command = if cfg!(target_os = "windows") {
+        let mut command = Command::new("rundll32");
+        command.arg("url.dll,FileProtocolHandler");
+        command
+    } else if cfg!(target_os = "macos") {
+        Command::new("open")
+    } else {
+        Command::new("xdg-open")
+    };
+
+    command.arg(url).spawn()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn finds_links() {
+        let text = "see https://example.com/a?b=1, then\n(http://x.org) and https:// or ftp://no.\nhttps://a.bhttps://c.d";
+
+        assert_eq!(find(text), vec![
+            Link { line: 0, url: "https://example.com/a?b=1" },
+            Link { line: 1, url: "http://x.org" },
+            Link { line: 2, url: "https://a.bhttps://c.d" },
+        ]);
+        assert!(open("file:///etc/passwd").is_err());
+    }
+}
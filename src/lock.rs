@@ -0,0 +1,285 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    ops::Range,
+    time::{Duration, Instant}
+};
+
+use libp2p::PeerId;
+
+/// How long a peer's lock is honoured after it last announced it. Locks
+/// held are announced again every [`crate::presence::PRESENCE_INTERVAL`],
+/// so this is a few missed ones, after which a holder that disappeared
+/// without unlocking is taken to be gone.
+pub const LOCK_TTL: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+struct Lock {
+    holder: PeerId,
+    seen: Instant,
+}
+
+/// Documents locked for turn-taking: those this peer locked, which only it
+/// edits, and those other peers locked, which this peer doesn't edit.
+#[derive(Debug, Default)]
+pub struct Locks {
+    own: BTreeSet<String>,
+    others: HashMap<String, Lock>,
+}
+
+impl Locks {
+    /// The peer holding the lock on `document`, unless it is free or this peer holds it.
+    pub fn holder(&self, document: &str) -> Option<PeerId> {
+        self.others.get(document).map(|lock| lock.holder)
+    }
+
+    pub fn holds(&self, document: &str) -> bool {
+        self.own.contains(document)
+    }
+
+    /// Documents this peer holds the lock on.
+    pub fn own(&self) -> impl Iterator<Item = &String> {
+        self.own.iter()
+    }
+
+    /// Takes the lock on `document`, failing with the peer holding it if it isn't free.
+    pub fn lock(&mut self, document: &str) -> Result<(), PeerId> {
+        match self.holder(document) {
+            Some(holder) => Err(holder),
+            None => {
+                self.own.insert(document.to_string());
+                Ok(())
+            },
+        }
+    }
+
+    /// Releases this peer's lock on `document`, returning whether it held it.
+    pub fn unlock(&mut self, document: &str) -> bool {
+        self.own.remove(document)
+    }
+
+    /// Follows a lock on `document` announced or released by `holder`,
+    /// returning whether it changed hands. When two peers lock a document
+    /// at once, the one with the lower peer id keeps it, so every peer ends
+    /// up agreeing without another round of messages.
+    pub fn receive(&mut self, local: PeerId, holder: PeerId, document: &str, held: bool, now: Instant) -> bool {
+        if !held {
+            let released = self.holder(document) == Some(holder);
+            if released {
+                self.others.remove(document);
+            }
+
+            return released;
+        }
+
+        if self.own.contains(document) {
+            if local < holder {
+                return false;
+            }
+            self.own.remove(document);
+        }
+
+        match self.others.get_mut(document) {
+            Some(lock) if lock.holder == holder => {
+                lock.seen = now;
+                false
+            },
+            Some(lock) if lock.holder < holder => false,
+            _ => {
+                self.others.insert(document.to_string(), Lock { holder, seen: now });
+                true
+            },
+        }
+    }
+
+    /// Forgets the locks of peers that didn't announce them again within
+    /// [`LOCK_TTL`], returning the documents freed and who held them.
+    pub fn expire(&mut self, now: Instant) -> Vec<(String, PeerId)> {
+        let expired: Vec<_> = self.others
+            .iter()
+            .filter(|(_, lock)| now.saturating_duration_since(lock.seen) > LOCK_TTL)
+            .map(|(document, lock)| (document.clone(), lock.holder))
+            .collect();
+
+        for (document, _) in &expired {
+            self.others.remove(document);
+        }
+
+        expired
+    }
+
+    /// Frees the documents locked by a peer that left, returning them.
+    pub fn forget(&mut self, peer_id: &PeerId) -> Vec<String> {
+        let freed: Vec<_> = self.others.iter().filter(|(_, lock)| lock.holder == *peer_id).map(|(document, _)| document.clone()).collect();
+
+        for document in &freed {
+            self.others.remove(document);
+        }
+
+        freed
+    }
+
+    pub fn clear(&mut self) {
+        self.own.clear();
+        self.others.clear();
+    }
+}
+
+/// Text a peer said it is editing, by byte offsets kept moving along with
+/// the edits before it, so it stays on the same lines.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Claim {
+    pub document: String,
+    pub range: Range<usize>,
+}
+
+/// Regions of documents peers claimed, one each. Unlike a lock, a claim
+/// refuses nothing, edits landing in another peer's claim are only warned of.
+#[derive(Debug, Default)]
+pub struct Claims {
+    own: Option<Claim>,
+    others: HashMap<PeerId, (Claim, Instant)>,
+}
+
+impl Claims {
+    pub fn own(&self) -> Option<&Claim> {
+        self.own.as_ref()
+    }
+
+    /// Claims `range` of `document` for this peer, in place of any claim before.
+    pub fn claim(&mut self, document: &str, range: Range<usize>) {
+        self.own = Some(Claim { document: document.to_string(), range });
+    }
+
+    /// Releases this peer's claim, returning it.
+    pub fn unclaim(&mut self) -> Option<Claim> {
+        self.own.take()
+    }
+
+    /// Follows a claim announced by `peer_id`, or its release if `None`.
+    pub fn receive(&mut self, peer_id: PeerId, claim: Option<Claim>, now: Instant) {
+        match claim {
+            Some(claim) => self.others.insert(peer_id, (claim, now)),
+            None => self.others.remove(&peer_id),
+        };
+    }
+
+    /// The other peers' claims on `document` that `index` falls in.
+    pub fn at(&self, document: &str, index: usize) -> Vec<(PeerId, Range<usize>)> {
+        self.others
+            .iter()
+            .filter(|(_, (claim, _))| claim.document == document && claim.range.contains(&index))
+            .map(|(&peer_id, (claim, _))| (peer_id, claim.range.clone()))
+            .collect()
+    }
+
+    /// Every claim on `document`, this peer's as `None`, in order through it.
+    pub fn in_document(&self, document: &str) -> Vec<(Option<PeerId>, Range<usize>)> {
+        let mut claims: Vec<_> = self.own
+            .iter()
+            .map(|claim| (None, claim))
+            .chain(self.others.iter().map(|(&peer_id, (claim, _))| (Some(peer_id), claim)))
+            .filter(|(_, claim)| claim.document == document)
+            .map(|(peer_id, claim)| (peer_id, claim.range.clone()))
+            .collect();
+        claims.sort_by_key(|(peer_id, range)| (range.start, *peer_id));
+
+        claims
+    }
+
+    /// The ends of every claim on `document`, to move along with edits before them.
+    pub fn moving(&mut self, document: &str) -> Vec<&mut usize> {
+        self.own
+            .iter_mut()
+            .chain(self.others.values_mut().map(|(claim, _)| claim))
+            .filter(|claim| claim.document == document)
+            .flat_map(|claim| [&mut claim.range.start, &mut claim.range.end])
+            .collect()
+    }
+
+    /// Forgets claims not announced again within [`LOCK_TTL`].
+    pub fn expire(&mut self, now: Instant) {
+        self.others.retain(|_, (_, seen)| now.saturating_duration_since(*seen) <= LOCK_TTL);
+    }
+
+    pub fn remove(&mut self, peer_id: &PeerId) {
+        self.others.remove(peer_id);
+    }
+
+    pub fn clear(&mut self) {
+        self.own = None;
+        self.others.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn locks_are_exclusive_and_expire() {
+        let (local, a, b) = (PeerId::random(), PeerId::random(), PeerId::random());
+        let now = Instant::now();
+        let mut locks = Locks::default();
+
+        assert!(locks.receive(local, a, "main", true, now));
+        assert_eq!(locks.lock("main"), Err(a));
+        assert!(!locks.receive(local, b, "main", false, now));
+        assert_eq!(locks.holder("main"), Some(a));
+
+        // Announced again, so it outlives the first announcement.
+        assert!(!locks.receive(local, a, "main", true, now + LOCK_TTL));
+        assert!(locks.expire(now + LOCK_TTL + Duration::from_secs(1)).is_empty());
+        assert_eq!(locks.expire(now + 2 * LOCK_TTL + Duration::from_secs(1)), vec![("main".to_string(), a)]);
+
+        assert_eq!(locks.lock("main"), Ok(()));
+        assert!(locks.holds("main") && locks.holder("main").is_none());
+        assert!(locks.unlock("main") && !locks.unlock("main"));
+
+        locks.receive(local, b, "notes", true, now);
+        assert_eq!(locks.forget(&b), vec!["notes".to_string()]);
+        assert_eq!(locks.holder("notes"), None);
+    }
+
+    #[test]
+    fn lower_peer_id_wins_a_tie() {
+        let (a, b) = (PeerId::random(), PeerId::random());
+        let (low, high) = (a.min(b), a.max(b));
+        let now = Instant::now();
+
+        let mut low_locks = Locks::default();
+        low_locks.lock("main").unwrap();
+        assert!(!low_locks.receive(low, high, "main", true, now));
+        assert!(low_locks.holds("main"));
+
+        let mut high_locks = Locks::default();
+        high_locks.lock("main").unwrap();
+        assert!(high_locks.receive(high, low, "main", true, now));
+        assert!(!high_locks.holds("main"));
+        assert_eq!(high_locks.holder("main"), Some(low));
+    }
+
+    #[test]
+    fn claims_move_with_edits() {
+        let peer_id = PeerId::random();
+        let now = Instant::now();
+        let mut claims = Claims::default();
+        let claim = |range| Some(Claim { document: "main".to_string(), range });
+
+        claims.claim("main", 0..3);
+        claims.receive(peer_id, claim(10..20), now);
+        assert_eq!(claims.at("main", 19), vec![(peer_id, 10..20)]);
+        assert!(claims.at("main", 20).is_empty() && claims.at("notes", 15).is_empty());
+
+        // Two bytes inserted before the other claim.
+        for position in claims.moving("main") {
+            if *position > 5 {
+                *position += 2;
+            }
+        }
+        assert_eq!(claims.in_document("main"), vec![(None, 0..3), (Some(peer_id), 12..22)]);
+
+        claims.expire(now + LOCK_TTL + Duration::from_secs(1));
+        assert_eq!(claims.unclaim(), claim(0..3));
+        assert!(claims.in_document("main").is_empty());
+    }
+}
@@ -0,0 +1,181 @@
+use std::{
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH}
+};
+
+use crate::error::NotepadError;
+
+/// Number of rotated files kept next to the log file, `<path>.1` being the newest.
+pub const KEEP: usize = 5;
+
+/// When the log file is moved aside and a new one started.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rotation {
+    /// At the first write of every UTC day.
+    Daily,
+    /// Before a write would take the file over this many bytes.
+    Size(u64),
+}
+
+impl Default for Rotation {
+    fn default() -> Self {
+        Rotation::Size(10 * 1_048_576)
+    }
+}
+
+impl FromStr for Rotation {
+    type Err = NotepadError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "daily" => Ok(Rotation::Daily),
+            bytes => bytes
+                .parse()
+                .ok()
+                .filter(|&bytes| bytes > 0)
+                .map(Rotation::Size)
+                .ok_or_else(|| NotepadError::command(format!("Unknown log rotation: {s:?}"))),
+        }
+    }
+}
+
+/// A log file that rotates itself, keeping the last [`KEEP`] files.
+#[derive(Debug)]
+pub struct RotatingFile {
+    path: PathBuf,
+    rotation: Rotation,
+    file: File,
+    len: u64,
+    /// UTC day the file was last written on.
+    day: u64,
+}
+
+impl RotatingFile {
+    pub fn open(path: &Path, rotation: Rotation) -> Result<Self, NotepadError> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let metadata = file.metadata()?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            rotation,
+            len: metadata.len(),
+            day: day(metadata.modified().unwrap_or_else(|_| SystemTime::now())),
+            file,
+        })
+    }
+
+    fn should_rotate(&self, len: usize, now: SystemTime) -> bool {
+        match self.rotation {
+            Rotation::Daily => day(now) != self.day,
+            Rotation::Size(max) => self.len > 0 && self.len + len as u64 > max,
+        }
+    }
+
+    /// Shifts `<path>.N` to `<path>.N+1`, dropping the oldest, and starts a new file.
+    fn rotate(&mut self) -> io::Result<()> {
+        for i in (1..KEEP).rev() {
+            match fs::rename(rotated(&self.path, i), rotated(&self.path, i + 1)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+
+        fs::rename(&self.path, rotated(&self.path, 1))?;
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.len = 0;
+
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let now = SystemTime::now();
+
+        if self.should_rotate(buf.len(), now) {
+            self.rotate()?;
+        }
+
+        let written = self.file.write(buf)?;
+        self.len += written as u64;
+        self.day = day(now);
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Days since the epoch, in UTC.
+fn day(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs() / 86_400)
+}
+
+fn rotated(path: &Path, i: usize) -> PathBuf {
+    let mut name = OsString::from(path);
+    name.push(format!(".{i}"));
+
+    name.into()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("p2p-notepad-log-{}", rand::random::<u64>()));
+        fs::create_dir(&dir).unwrap();
+
+        dir
+    }
+
+    #[test]
+    fn rotates_by_size() {
+        let dir = temp_dir();
+        let path = dir.join("notepad.log");
+        let mut file = RotatingFile::open(&path, Rotation::Size(10)).unwrap();
+
+        for i in 0..10 {
+            writeln!(file, "line {i}").unwrap();
+        }
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "line 9\n");
+        assert_eq!(fs::read_to_string(rotated(&path, 1)).unwrap(), "line 8\n");
+        assert_eq!(fs::read_to_string(rotated(&path, KEEP)).unwrap(), format!("line {}\n", 9 - KEEP));
+        assert!(!rotated(&path, KEEP + 1).exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rotates_daily() {
+        let dir = temp_dir();
+        let path = dir.join("notepad.log");
+        let mut file = RotatingFile::open(&path, Rotation::Daily).unwrap();
+
+        writeln!(file, "today").unwrap();
+        writeln!(file, "still today").unwrap();
+        assert!(!rotated(&path, 1).exists());
+
+        file.day -= 1;
+        writeln!(file, "tomorrow").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "tomorrow\n");
+        assert_eq!(fs::read_to_string(rotated(&path, 1)).unwrap(), "today\nstill today\n");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn parse_rotation() {
+        assert_eq!("daily".parse::<Rotation>().unwrap(), Rotation::Daily);
+        assert_eq!("1024".parse::<Rotation>().unwrap(), Rotation::Size(1024));
+        assert!("0".parse::<Rotation>().is_err());
+        assert!("hourly".parse::<Rotation>().is_err());
+    }
+}
@@ -0,0 +1,175 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        Arc, Mutex
+    }
+};
+
+use async_trait::async_trait;
+use libp2p::PeerId;
+use tokio::sync::mpsc;
+
+use crate::{
+    error::NotepadError,
+    transport::{Event, Incoming, Transport}
+};
+
+#[derive(Debug)]
+struct Member {
+    peer_id: PeerId,
+    topics: HashSet<String>,
+    sender: mpsc::UnboundedSender<Event>,
+}
+
+#[derive(Debug, Default)]
+struct Bus {
+    members: Vec<Member>,
+    /// Who made each unanswered request.
+    requests: HashMap<u64, PeerId>,
+    next_request: u64,
+}
+
+impl Bus {
+    fn send(&self, peer_id: &PeerId, event: Event) -> Result<(), NotepadError> {
+        self.members
+            .iter()
+            .find(|member| member.peer_id == *peer_id)
+            .and_then(|member| member.sender.send(event).ok())
+            .ok_or_else(|| NotepadError::network(format!("Peer {peer_id} isn't on the bus")))
+    }
+}
+
+/// An in-memory transport where every member of the same bus receives
+/// what the others publish on topics it is subscribed to.
+#[derive(Debug)]
+pub struct Loopback {
+    peer_id: PeerId,
+    bus: Arc<Mutex<Bus>>,
+    receiver: mpsc::UnboundedReceiver<Event>,
+}
+
+impl Loopback {
+    /// Creates a new member on the same bus as `self`.
+    pub fn connect(&self) -> Self {
+        Self::join(self.bus.clone())
+    }
+
+    fn join(bus: Arc<Mutex<Bus>>) -> Self {
+        let peer_id = PeerId::random();
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        bus.lock().expect("bus lock poisoned").members.push(Member { peer_id, topics: HashSet::new(), sender });
+
+        Self { peer_id, bus, receiver }
+    }
+
+    fn with_member<T>(&self, f: impl FnOnce(&mut Member) -> T) -> T {
+        let mut bus = self.bus.lock().expect("bus lock poisoned");
+        let member = bus.members.iter_mut().find(|member| member.peer_id == self.peer_id).expect("member joined the bus");
+
+        f(member)
+    }
+}
+
+impl Default for Loopback {
+    fn default() -> Self {
+        Self::join(Arc::default())
+    }
+}
+
+#[async_trait]
+impl Transport for Loopback {
+    fn peer_id(&self) -> PeerId {
+        self.peer_id
+    }
+
+    fn publish(&mut self, topic: &str, data: Vec<u8>) -> Result<usize, NotepadError> {
+        let bus = self.bus.lock().expect("bus lock poisoned");
+        let mut sent = 0;
+
+        for member in bus.members.iter().filter(|member| member.peer_id != self.peer_id && member.topics.contains(topic)) {
+            let incoming = Incoming { topic: topic.to_string(), source: Some(self.peer_id), data: data.clone() };
+
+            if member.sender.send(Event::Message(incoming)).is_ok() {
+                sent += 1;
+            }
+        }
+
+        Ok(sent)
+    }
+
+    fn subscribe(&mut self, topic: &str) -> Result<(), NotepadError> {
+        self.with_member(|member| member.topics.insert(topic.to_string()));
+
+        Ok(())
+    }
+
+    fn unsubscribe(&mut self, topic: &str) -> Result<(), NotepadError> {
+        self.with_member(|member| member.topics.remove(topic));
+
+        Ok(())
+    }
+
+    fn request(&mut self, peer: &PeerId, data: Vec<u8>) -> Result<(), NotepadError> {
+        let mut bus = self.bus.lock().expect("bus lock poisoned");
+        let id = bus.next_request;
+
+        bus.next_request += 1;
+        bus.requests.insert(id, self.peer_id);
+        bus.send(peer, Event::Request { id, peer: self.peer_id, data })
+    }
+
+    fn respond(&mut self, id: u64, data: Vec<u8>) -> Result<(), NotepadError> {
+        let mut bus = self.bus.lock().expect("bus lock poisoned");
+        let requester = bus.requests.remove(&id).ok_or_else(|| NotepadError::network(format!("Unknown request {id}")))?;
+
+        bus.send(&requester, Event::Response { peer: self.peer_id, data })
+    }
+
+    async fn next_event(&mut self) -> Option<Event> {
+        self.receiver.recv().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn loopback_delivers_to_subscribers() {
+        let mut a = Loopback::default();
+        let mut b = a.connect();
+        let mut c = a.connect();
+
+        b.subscribe("room").unwrap();
+        c.subscribe("other").unwrap();
+
+        assert_eq!(a.publish("room", vec![1, 2, 3]).unwrap(), 1);
+        assert_eq!(b.next_event().await, Some(Event::Message(Incoming {
+            topic: "room".to_string(),
+            source: Some(a.peer_id()),
+            data: vec![1, 2, 3],
+        })));
+
+        b.unsubscribe("room").unwrap();
+        assert_eq!(a.publish("room", vec![4]).unwrap(), 0);
+        assert!(c.receiver.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn loopback_requests() {
+        let mut a = Loopback::default();
+        let mut b = a.connect();
+
+        a.request(&b.peer_id(), vec![1]).unwrap();
+        let Some(Event::Request { id, peer, data }) = b.next_event().await else {
+            panic!("expected a request");
+        };
+        assert_eq!((peer, data), (a.peer_id(), vec![1]));
+
+        b.respond(id, vec![2]).unwrap();
+        assert_eq!(a.next_event().await, Some(Event::Response { peer: b.peer_id(), data: vec![2] }));
+        assert!(b.respond(id, vec![2]).is_err());
+        assert!(a.request(&PeerId::random(), vec![]).is_err());
+    }
+}
@@ -1,237 +1,1931 @@
-mod diff; 
-mod notepad;
-
-
-use std::{
-    collections::hash_map::DefaultHasher,
-    error::Error,
-    hash::{
-        Hash, Hasher
-    },
-    time::Duration
-};
-use diff::{Diff, MessageBuf, Operation};
-use futures::stream::StreamExt;
-use libp2p::{
-    gossipsub, mdns, noise, tcp, yamux,
-    swarm::{
-        NetworkBehaviour, SwarmEvent
-    }
+use std::{collections::VecDeque, io::{IsTerminal, Write}, path::{Path, PathBuf}, sync::Mutex, time::Duration};
+use p2p_notepad::{
+    access::Access,
+    activity::{self, BUCKET_LEN, BUCKETS},
+    archive::Archive,
+    attachment, capture,
+    background::{BackgroundStorage, BackgroundWriter},
+    backup::{BackupTarget, Backups},
+    capture::Capture,
+    clipboard,
+    config::{self, Config},
+    control::{self, Request, Response},
+    describe,
+    diff::{self, Diff, MessageBuf, Operation},
+    directory::Directory,
+    document::{Documents, LineEnding},
+    dashboard::{self, Dashboard},
+    editor::{self, Action, Editor},
+    engine::Engine,
+    escape,
+    help,
+    ipc,
+    keymap::Binding,
+    lines::{self, LineEdit},
+    links,
+    log::RotatingFile,
+    matrix::{self, Heard, Post},
+    error::NotepadError,
+    memory,
+    message::Message,
+    network::Network,
+    notepad::Notepad,
+    output,
+    paste::{self, Input, Paste},
+    presence::{AWAY_AFTER, PRESENCE_INTERVAL},
+    script::Script,
+    search::{self, Regex},
+    session::{self, Session},
+    spell::{self, Dictionary},
+    storage::{Backend, Files, Sled, Storage},
+    telemetry::{self, Telemetry, REPORT_INTERVAL},
+    transport::{Event, Transport},
+    whiteboard::{self, BoardOp, Color, Stroke},
+    workspace::Workspace
 };
-use notepad::Notepad;
 use tokio::{
-    io, select,
-    io::AsyncBufReadExt
+    io, select, signal,
+    io::AsyncBufReadExt,
+    sync::{mpsc, oneshot},
+    time::{self, Instant, Interval, MissedTickBehavior}
 };
-use tracing_subscriber::EnvFilter;
+use libp2p::PeerId;
+use ratatui::DefaultTerminal;
+use tracing_subscriber::{fmt, prelude::*, reload, EnvFilter};
 
-#[derive(NetworkBehaviour)]
-struct MyBehaviour {
-    gossipsub: gossipsub::Behaviour,
-    mdns: mdns::tokio::Behaviour,
+/// Prints like the standard `println`, as a JSON event when printing JSON
+/// and also kept for the client of a command that came over the daemon
+/// socket, see [`output::print`].
+macro_rules! println {
+    () => {
+        output::print("")
+    };
+    ($($arg:tt)*) => {
+        output::print(&format!($($arg)*))
+    };
 }
 
+/// Longest the swarm is given to send what is still queued, then to close
+/// its connections, when quitting.
+const SHUTDOWN_GRACE: Duration = Duration::from_millis(500);
+
+/// Minimum time between renders of remote changes, so bursts of incoming
+/// operations are coalesced into a few updates per second.
+const RENDER_INTERVAL: Duration = Duration::from_millis(250);
+
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    let _ = tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::from_default_env())
-        .try_init();
-
-    let mut swarm = libp2p::SwarmBuilder::with_new_identity()
-        .with_tokio()
-        .with_tcp(
-            tcp::Config::default(), 
-            noise::Config::new, 
-            yamux::Config::default
-        )?
-        .with_quic()
-        .with_behaviour(|key| {
-            let message_id_fn = |message: &gossipsub::Message| {
-                let mut s = DefaultHasher::new();
-
-                message.sequence_number.hash(&mut s);
-                message.data.hash(&mut s);
-
-                gossipsub::MessageId::from(s.finish().to_string())
-            };
-
-            let gossipsub_config = gossipsub::ConfigBuilder::default()
-                .heartbeat_interval(Duration::from_secs(10))
-                .validation_mode(gossipsub::ValidationMode::Strict)
-                .message_id_fn(message_id_fn)
-                .build()
-                .map_err(|msg| io::Error::new(io::ErrorKind::Other, msg))?;
-
-            let gossipsub = gossipsub::Behaviour::new(
-                gossipsub::MessageAuthenticity::Signed(key.clone()), 
-                gossipsub_config,
-            )?;
-
-            let mdns =
-                mdns::tokio::Behaviour::new(mdns::Config::default(), key.public().to_peer_id())?;
-
-            Ok(MyBehaviour { gossipsub, mdns })
-        })?
-        .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(60)))
-        .build();
-
-    let mut current_topic = gossipsub::IdentTopic::new("test-net");
-
-    swarm.behaviour_mut().gossipsub.subscribe(&current_topic)?;
-
-    let mut stdin = io::BufReader::new(io::stdin()).lines();
-
-    swarm.listen_on("/ip4/0.0.0.0/udp/0/quic-v1".parse()?)?;
-    swarm.listen_on("/ip4/0.0.0.0/tcp/0".parse()?)?;
+async fn main() -> Result<(), NotepadError> {
+    // What the node prints is kept for daemon clients by task, see `ipc::capture`.
+    ipc::scope(run()).await
+}
+
+async fn run() -> Result<(), NotepadError> {
+    // `ctl` runs a command on a daemon instead of starting a node.
+    let mut args = std::env::args().skip(1).peekable();
+    if args.next_if_eq("ctl").is_some() {
+        return ctl(args).await;
+    }
+
+    let mut config = Config::from_args(args)?;
+    output::set_json(config.json);
+
+    // Behind a reload layer, so `log:<filter>` can change it without a restart.
+    let (filter, log_filter) = match &config.log_file {
+        Some(_) => reload::Layer::new(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"))),
+        None => reload::Layer::new(EnvFilter::from_default_env()),
+    };
+    match &config.log_file {
+        Some(path) => {
+            let file = RotatingFile::open(path, config.log_rotation)?;
+
+            let _ = tracing_subscriber::registry()
+                .with(filter)
+                .with(fmt::layer().with_ansi(false).with_writer(Mutex::new(BackgroundWriter::spawn(file))))
+                .try_init();
+        },
+        None => {
+            let _ = tracing_subscriber::registry()
+                .with(filter)
+                .with(fmt::layer())
+                .try_init();
+        },
+    }
+
+    let mut network = Network::new(&config)?;
+    let mut engine = Engine::new(&config.room, &config.topic_prefix, Notepad::default());
+    engine.memory_budget = config.memory_budget;
+    engine.max_document_size = config.max_document_size;
+    engine.oplog_compact = config.oplog_compact;
+    engine.journal.limit(config.journal_entries);
+    engine.batch.window = config.batch_window;
+    engine.batch.granularity = config.granularity;
+    engine.access = Access::new(&config.allowed, &config.blocked);
+    engine.control_chars = config.control_chars;
+    engine.host = config.is_host();
+    engine.plain_output = config.plain_output;
+    engine.observe = config.observe;
+    engine.acknowledge = config.acks;
+    engine.repair = config.repair;
+    engine.crdt = config.crdt;
+    engine.directory = config.directory.then(Directory::default);
+    engine.directory_peer = config.directory_peer;
+    engine.retry = config.retry;
+    engine.backups = config.backup_dir.clone().map(Backups::new);
+    engine.backup = match (config.backup_peer, &config.backup_key) {
+        (Some(peer), Some(key)) => Some(BackupTarget::new(peer, key)),
+        (None, None) => None,
+        _ => return Err(NotepadError::command("Backing up needs both `--backup-peer <peer id>` and `--backup-key <key>`")),
+    };
+
+    // Sled databases can only be opened once, every store shares the one.
+    // Each is written to in the background, off the loop below.
+    let database = match &config.storage {
+        Backend::Files => None,
+        Backend::Sled(path) => Some(Sled::open(path)?),
+    };
+    let store = |dir: &Path| -> Box<dyn Storage> {
+        let storage: Box<dyn Storage + Send> = match &database {
+            Some(database) => Box::new(database.scoped(&dir.to_string_lossy())),
+            None => Box::new(Files::new(dir.to_path_buf())),
+        };
+
+        Box::new(BackgroundStorage::spawn(storage))
+    };
+
+    if let Some(dir) = &config.oplog {
+        engine.open_oplog(store(dir), true)?;
+    }
+
+    // The workspace is named after its file, in the store of its directory.
+    let mut workspace = config.workspace.as_deref().map(|path| {
+        let name = path.file_name().map_or_else(|| "workspace".into(), |name| name.to_string_lossy().into_owned());
+        (store(path.parent().unwrap_or(Path::new(""))), name)
+    });
+
+    match workspace.as_ref().map(|(storage, name)| Workspace::load(storage.as_ref(), name)).transpose()?.flatten() {
+        Some(workspace) => {
+            println!("Restoring workspace in room `{}`", workspace.room);
+            engine.restore(&mut network, workspace)?;
+        },
+        None => {
+            network.subscribe(&engine.cursor_topic())?;
+            network.subscribe(engine.topic())?;
+        },
+    }
+
+    if let Some(nickname) = &config.nickname {
+        engine.set_nickname(&mut network, nickname)?;
+    }
+
+    let dictionary = config.dictionary.as_deref().map(Dictionary::load).transpose()?;
+    // Daemons take commands on their socket and leave stdin alone.
+    let mut stdin = config.daemon.is_none().then(|| io::BufReader::new(io::stdin()).lines());
+    let (command_sender, mut commands) = mpsc::channel(16);
+    if let Some(path) = config.daemon.clone() {
+        println!("Running as a daemon, send commands with `ctl {} <command>`", path.display());
+        tokio::spawn(async move {
+            if let Err(e) = ipc::serve(&path, command_sender).await {
+                println!("Daemon socket error: {e}");
+            }
+        });
+    }
+    // Where the output of the command from the daemon socket goes once it, and any alias it ran, is done.
+    let mut reply: Option<oneshot::Sender<String>> = None;
+    let mut paste = Paste::default();
+    // Commands left to run from the last alias typed.
+    let mut queued = VecDeque::new();
+    // Commands left to run from the file given to `run`, after those of an alias.
+    let mut script: Option<Script> = None;
+    // Terminals then mark pastes, so pasted lines aren't run as commands.
+    let terminal = config.daemon.is_none() && std::io::stdout().is_terminal();
+    if terminal {
+        bracketed_paste(paste::ENABLE);
+    }
+
+    // The editor takes over the terminal, reading keystrokes on a thread of
+    // their own as the terminal is read blocking.
+    let mut screen = if !config.commands && !config.plain_output && !config.json && terminal && std::io::stdin().is_terminal() {
+        Some(ratatui::try_init()?)
+    } else {
+        None
+    };
+    let mut editor = Editor::new(config.keymap.clone());
+    let mut dashboard: Option<Dashboard> = None;
+    let (key_sender, mut keys) = mpsc::unbounded_channel();
+    if let Some(screen) = &mut screen {
+        engine.cursor = Some(0);
+        draw(screen, &mut editor, None, &mut engine, config.peer_timeout, true);
+
+        std::thread::spawn(move || {
+            while let Ok(event) = ratatui::crossterm::event::read() {
+                if key_sender.send(event).is_err() {
+                    break;
+                }
+            }
+        });
+    }
 
     println!("Enter messages via STDIN and they will be sent to connected peers using Gossipsub");
 
-    let mut current_notepad = Notepad::default();
-    current_notepad.text = "hello world".to_string();
+    if config.is_host() {
+        println!("Hosting room, publishing periodic snapshots");
+    }
+
+    let mut render_timer = time::interval(RENDER_INTERVAL);
+    render_timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut presence_timer = time::interval(PRESENCE_INTERVAL);
+    presence_timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut capture = config.capture.as_deref().map(Capture::open).transpose()?;
+
+    let mut last_input = Instant::now();
+    let mut telemetry = Telemetry::new(std::time::Instant::now());
+    let mut telemetry_timer = time::interval_at(Instant::now() + REPORT_INTERVAL, REPORT_INTERVAL);
+
+    let mut snapshot_timer = config.snapshot_interval
+        .map(|period| time::interval_at(Instant::now() + period, period));
+
+    // The sender is kept even without a server, so that no call ever arrives rather than the channel closing.
+    let (call_sender, mut calls) = mpsc::channel(16);
+    if let Some(address) = config.http {
+        let (token, calls) = (config.http_token.clone(), call_sender.clone());
+        tokio::spawn(async move {
+            if let Err(e) = control::serve(address, token, calls).await {
+                println!("Control API error: {e}");
+            }
+        });
+    }
+
+    // Likewise for the Matrix bridge, so nothing is ever heard without one.
+    let (post_sender, posts) = mpsc::channel(64);
+    let (heard_sender, mut heard) = mpsc::channel(64);
+    if let Some(bridge) = config.matrix_bridge() {
+        engine.bridged = Some(Vec::new());
+        let heard = heard_sender.clone();
+        tokio::spawn(async move {
+            if let Err(e) = matrix::serve(bridge, posts, heard).await {
+                println!("Matrix bridge error: {e}");
+            }
+        });
+    }
 
     loop {
+        if let Some(finished) = script.take_if(|running| running.is_empty()) {
+            println!("Finished running `{}`", finished.path);
+        }
+        if queued.is_empty() && script.is_none() {
+            if let Some(reply) = reply.take() {
+                let _ = reply.send(ipc::captured());
+            }
+        }
+
         select! {
-            Ok(Some(line)) = stdin.next_line() => {
+            Some(event) = keys.recv(), if screen.is_some() => {
+                last_input = Instant::now();
+                engine.set_away(&mut network, false);
+
+                // Joining a room prints, which is drawn over.
+                let mut clear = false;
+
+                if let Some(view) = &mut dashboard {
+                    match view.event(&engine.rooms(config.peer_timeout, std::time::Instant::now()), event) {
+                        Some(dashboard::Action::Open(topic)) if topic == engine.topic() => dashboard = None,
+                        Some(dashboard::Action::Open(topic)) => {
+                            clear = true;
+                            dashboard = None;
+                            editor.status = match engine.reopen(&mut network, &topic) {
+                                Ok(()) => format!("Joined room `{}`", engine.room()),
+                                Err(e) => e.to_string(),
+                            };
+                            engine.cursor = Some(0);
+                        },
+                        Some(dashboard::Action::Close) => dashboard = None,
+                        Some(dashboard::Action::Quit) => break,
+                        None => {},
+                    }
+                } else {
+                    let mut cursor = engine.cursor.unwrap_or_default();
+                    match editor.event(engine.documents.active().text(), &mut cursor, event) {
+                        Some(Action::Edit(diffs)) => match engine.edit(&mut network, diffs) {
+                            Ok(()) => engine.cursor = Some(cursor),
+                            Err(e) => editor.status = e.to_string(),
+                        },
+                        Some(Action::Undo) => editor.status = engine.undo(&mut network).map_or_else(|e| e.to_string(), |summary| format!("Undid {summary}")),
+                        Some(Action::Redo) => editor.status = engine.redo(&mut network).map_or_else(|e| e.to_string(), |summary| format!("Redid {summary}")),
+                        Some(Action::Dashboard) => dashboard = Some(Dashboard::default()),
+                        Some(Action::CatchUp) => editor.status = match engine.catch_up() {
+                            Some((line, column)) => format!("Caught up to the edit at {}:{}", line + 1, column + 1),
+                            None => "No unread changes".to_string(),
+                        },
+                        Some(action @ (Action::NextDocument | Action::PreviousDocument)) => {
+                            engine.select(&mut network, None);
+                            let name = &engine.documents.step(action == Action::NextDocument).name;
+                            editor.status = format!("Switched to document `{name}`");
+                            engine.cursor = Some(0);
+                        },
+                        Some(Action::Quit) => break,
+                        None => engine.cursor = Some(cursor),
+                    }
+                    engine.select(&mut network, editor.selection(engine.cursor.unwrap_or_default()));
+                    engine.publish_cursor(&mut network, std::time::Instant::now());
+                }
+
+                if let Some(screen) = &mut screen {
+                    draw(screen, &mut editor, dashboard.as_mut(), &mut engine, config.peer_timeout, clear);
+                }
+            },
+            Ok(Some((line, typed))) = next_line(stdin.as_mut(), &mut commands, &mut reply, &mut queued, &mut script), if screen.is_none() => {
+                let line = match if typed { paste.feed(line) } else { Input::Command(line) } {
+                    // Commands from an alias aren't expanded again.
+                    Input::Command(line) if typed => match config.aliases.expand(&line) {
+                        Some(commands) => {
+                            queued.extend(commands);
+                            continue;
+                        },
+                        None => line,
+                    },
+                    Input::Command(line) => line,
+                    Input::Paste(text) => {
+                        last_input = Instant::now();
+                        engine.set_away(&mut network, false);
+
+                        match engine.paste(&mut network, &text) {
+                            Ok(()) => println!("Pasted {} characters, checksum: {}", text.chars().count(), engine.documents.active().checksum()),
+                            Err(e) => println!("{e}"),
+                        }
+                        continue;
+                    },
+                    Input::Pending => continue,
+                };
                 let mut parts = line.splitn(3, ':');
                 let op = parts.next().unwrap();
                 let value = parts.next();
                 let char = parts.next();
                 
                 let mut message = MessageBuf::default();
+                // Whether the command was unknown or its edit failed, which stops a script run with `abort`.
+                let mut failed = false;
+                telemetry.command(op);
+                last_input = Instant::now();
+                engine.set_away(&mut network, false);
 
                 match op {
+                    "see" if value == Some("who") => {
+                        let document = engine.documents.active_meta().id.clone();
+                        let text = &engine.documents.active().text();
+                        let attribution = engine.documents.authors.get(&document).cloned().unwrap_or_default();
+                        let name = |author: Option<PeerId>| author.map_or("unknown".to_string(), |peer_id| engine.peers.display_name(&peer_id));
+
+                        if config.plain_output {
+                            for (author, text) in attribution.runs(text) {
+                                println!("{}: {text:?}", name(author));
+                            }
+                        } else {
+                            // Each author in a color of their own, in the order they first appear.
+                            let mut authors = Vec::new();
+                            let mut colored = String::new();
+                            for (author, text) in attribution.runs(text) {
+                                let i = authors.iter().position(|known| *known == author).unwrap_or_else(|| {
+                                    authors.push(author);
+                                    authors.len() - 1
+                                });
+                                colored.push_str(&format!("\x1b[{}m{text}\x1b[0m", 31 + i % 6));
+                            }
+
+                            println!("{colored}");
+                            for (i, author) in authors.into_iter().enumerate() {
+                                println!("  \x1b[{}m{}\x1b[0m", 31 + i % 6, name(author));
+                            }
+                        }
+                    },
                     "see" => {
-                        println!("current notepad: {current_notepad:?}");
+                        let notepad = engine.documents.active();
+                        if config.plain_output {
+                            println!("In room `{}`. {}", engine.room(), describe::document(&engine.documents.active_meta().name, notepad));
+                        } else {
+                            println!("current notepad `{}` in room `{}`: {notepad:?} [{}]", engine.documents.active_meta().name, engine.room(), notepad.checksum());
+                        }
+                        for (summary, delivery, applied) in engine.recent_edits.iter() {
+                            match applied {
+                                Some((applied, expected)) => println!("  `{summary}`: {delivery}, applied by {applied}/{expected} peers"),
+                                None => println!("  `{summary}`: {delivery}"),
+                            }
+                        }
+                        for (peer_id, index) in engine.cursors.in_document(&engine.documents.active_meta().id) {
+                            let (line, column) = editor::position(notepad.text(), index);
+                            println!("  {} is at line {}, column {}", engine.peers.display_name(&peer_id), line + 1, column + 1);
+                        }
+                        for (peer_id, range) in engine.peers.selections(&engine.documents.active_meta().id) {
+                            let (first, last) = lines::covered(notepad.text(), range.clone());
+                            let selected = notepad.text().get(range.start.min(notepad.text().len())..range.end.min(notepad.text().len())).unwrap_or_default();
+                            println!("  {} selected lines {first} to {last}: {selected:?}", engine.peers.display_name(&peer_id));
+                        }
+                        // The whole document was printed.
+                        engine.unseen.clear();
                     },
-                    "swi" => {
-                        if let Some(value) = value {
-                            swarm.behaviour_mut().gossipsub.unsubscribe(&current_topic)?;
-                            current_topic = gossipsub::IdentTopic::new(value);
-                            swarm.behaviour_mut().gossipsub.subscribe(&current_topic)?;
-                            println!("Switching to room: `{:?}`", value);        
+                    "view" => match value.map_or(Ok(1), str::parse::<usize>) {
+                        Ok(first) => {
+                            let text = &engine.documents.active().text();
+                            let count = text.lines().count();
+
+                            match lines::numbered(text, first, lines::PAGE_LINES) {
+                                Ok(page) => {
+                                    println!("`{}` in room `{}`, {count} lines [{}]", engine.documents.active_meta().name, engine.room(), engine.documents.active().checksum());
+                                    for line in &page {
+                                        println!("{line}");
+                                    }
+
+                                    let last = first + page.len() - 1;
+                                    if last < count {
+                                        println!("Lines {first} to {last}, `view:{}` for the next ones", last + 1);
+                                    }
+                                },
+                                Err(e) => println!("{e}"),
+                            }
+                        },
+                        Err(_) => println!("Expected format `view[:line]`"),
+                    },
+                    "hist" => match value.map_or(Ok(20), str::parse::<usize>) {
+                        Ok(n) => {
+                            for entry in engine.journal.recent(n) {
+                                let author = entry.author.map_or("unknown".to_string(), |peer_id| engine.peers.display_name(&peer_id));
+                                let document = engine.documents.get(&entry.document).map_or(entry.document.as_str(), |document| &document.meta.name);
+
+                                println!("{:>6} {} {author}: {} in `{document}`", entry.seq, entry.clock(), entry.diff);
+                            }
+                        },
+                        Err(_) => println!("Expected format `hist[:n]`"),
+                    },
+                    "at" => match value.map(str::parse::<u64>) {
+                        Some(Ok(seq)) => {
+                            let meta = engine.documents.active_meta();
+
+                            match engine.journal.at(seq, &meta.id, engine.documents.active().text()) {
+                                Ok(text) if config.plain_output => println!("After operation {seq}. {}", describe::document(&meta.name, &Notepad::new(text))),
+                                Ok(text) => println!("`{}` after operation #{seq}: {:?}", meta.name, Notepad::new(text)),
+                                Err(e) => println!("{e}"),
+                            }
+                        },
+                        _ => println!("Expected format `at:<n>`, with an operation number from `hist`"),
+                    },
+                    "catchup" => {
+                        let unread = engine.unseen.in_document(&engine.documents.active_meta().id).len();
+
+                        match engine.catch_up() {
+                            Some((line, column)) => {
+                                let text = engine.documents.active().text().split('\n').nth(line).unwrap_or_default();
+                                println!("Unread change at line {}, column {} ({} more): {text}", line + 1, column + 1, unread - 1);
+                            },
+                            None => println!("No unread changes, see the document with `see`"),
+                        }
+                    },
+                    "doc" => {
+                        if let Some(name) = value {
+                            engine.select(&mut network, None);
+                            match engine.documents.switch(name) {
+                                Ok(()) => println!("Switching to document: `{name}`"),
+                                Err(e) => println!("{e}, create it with `doc new:{name}`"),
+                            }
                         } else {
-                            println!("Expected format `swi:value`");
-                        } 
+                            println!("Expected format `doc:name`");
+                        }
+                    },
+                    "doc list" | "docs" => {
+                        for document in engine.documents.visible() {
+                            let marker = if document.meta == *engine.documents.active_meta() { "*" } else { " " };
+                            println!("{marker} {} [{}]", document.meta.name, document.notepad.checksum());
+                        }
+                    },
+                    "doc new" => {
+                        if let Some(name) = value {
+                            match engine.documents.create(name) {
+                                Ok(meta) => {
+                                    engine.publish(&mut network, Message::Meta(meta));
+                                    engine.select(&mut network, None);
+                                    match engine.documents.switch(name) {
+                                        Ok(()) => println!("Created document: `{name}`"),
+                                        Err(e) => println!("Created document `{name}`, but didn't switch to it: {e}"),
+                                    }
+                                },
+                                Err(e) => println!("{e}"),
+                            }
+                        } else {
+                            println!("Expected format `doc new:name`");
+                        }
+                    },
+                    "doc rename" => {
+                        if let Some(name) = value {
+                            match engine.documents.rename_active(name) {
+                                Ok(meta) => {
+                                    engine.publish(&mut network, Message::Meta(meta));
+                                    println!("Renamed document to: `{name}`");
+                                },
+                                Err(e) => println!("{e}"),
+                            }
+                        } else {
+                            println!("Expected format `doc rename:name`");
+                        }
+                    },
+                    "doc archive" => {
+                        if let Some(name) = value {
+                            match engine.documents.archive(name) {
+                                Ok(meta) => {
+                                    engine.publish(&mut network, Message::Meta(meta));
+                                    println!("Archived document: `{name}`");
+                                },
+                                Err(e) => println!("{e}"),
+                            }
+                        } else {
+                            println!("Expected format `doc archive:name`");
+                        }
+                    },
+                    "doc eol" => {
+                        match value.map(str::parse) {
+                            Some(Ok(line_ending)) => {
+                                let meta = engine.documents.set_line_ending(line_ending);
+                                engine.publish(&mut network, Message::Meta(meta));
+                                println!("Document will be exported with {line_ending} line endings");
+                            },
+                            Some(Err(e)) => println!("{e}"),
+                            None => println!("Expected format `doc eol:lf|crlf`"),
+                        }
+                    },
+                    "doc lang" | "doc tab" | "doc wrap" => {
+                        let mut settings = engine.documents.active_meta().settings.clone();
+                        let changed = match (op, value) {
+                            ("doc lang", Some(language)) => {
+                                settings.language = language.to_string();
+                                Ok(())
+                            },
+                            ("doc tab", Some(width)) => match width.parse() {
+                                Ok(width @ 1..=16) => {
+                                    settings.tab_width = width;
+                                    Ok(())
+                                },
+                                _ => Err("`width` must be between 1 and 16"),
+                            },
+                            ("doc wrap", Some("off")) => {
+                                settings.wrap = None;
+                                Ok(())
+                            },
+                            ("doc wrap", Some(columns)) => match columns.parse() {
+                                Ok(columns @ 1..) => {
+                                    settings.wrap = Some(columns);
+                                    Ok(())
+                                },
+                                _ => Err("`columns` must be a positive number or `off`"),
+                            },
+                            _ => Err("Expected format `doc lang:<language>`, `doc tab:<width>` or `doc wrap:<columns|off>`"),
+                        };
+
+                        match changed {
+                            Ok(()) => {
+                                let meta = engine.documents.set_settings(settings);
+                                engine.publish(&mut network, Message::Meta(meta));
+                                println!("Updated document settings for every peer");
+                            },
+                            Err(e) => println!("{e}"),
+                        }
+                    },
+                    "doc view" => {
+                        let meta = engine.documents.active_meta();
+                        let language = if meta.settings.language.is_empty() { "plain text" } else { &meta.settings.language };
+
+                        println!("`{}` ({language}):", meta.name);
+                        println!("{}", meta.settings.layout(engine.documents.active().text()));
+                    },
+                    "doc restore" => {
+                        if !config.is_host() {
+                            println!("Only the room host can restore documents");
+                        } else if let Some(name) = value {
+                            match engine.documents.restore(name) {
+                                Ok(meta) => {
+                                    engine.publish(&mut network, Message::Meta(meta));
+                                    println!("Restored document: `{name}`");
+                                },
+                                Err(e) => println!("{e}"),
+                            }
+                        } else {
+                            println!("Expected format `doc restore:name`");
+                        }
+                    },
+                    "init" => {
+                        // Peers start empty, so a room is seeded by having one of them
+                        // publish the documents it loaded for everyone else to adopt.
+                        engine.publish_snapshots(&mut network);
+                        println!("Published the current documents as the room's initial state");
+                    },
+                    "export" => {
+                        if let Some(path) = value {
+                            export(&engine.documents, path, None);
+                        } else {
+                            println!("Expected format `export:path`");
+                        }
+                    },
+                    "export --encrypt" => {
+                        if let (Some(path), Some(passphrase)) = (value, char) {
+                            export(&engine.documents, path, Some(passphrase));
+                        } else {
+                            println!("Expected format `export --encrypt:path:passphrase`");
+                        }
+                    },
+                    "export --text" => {
+                        if let Some(path) = value {
+                            match std::fs::write(path, engine.export_text()) {
+                                Ok(()) => println!("Exported document to `{path}`"),
+                                Err(e) => println!("Export error: {e}"),
+                            }
+                        } else {
+                            println!("Expected format `export --text:path`");
+                        }
+                    },
+                    "export --patch" => {
+                        if let (Some(path), Some(since)) = (value, char) {
+                            match engine.patch_since(since) {
+                                Ok(patch) if patch.is_empty() => println!("No changes since `{since}`"),
+                                Ok(patch) => match std::fs::write(path, patch) {
+                                    Ok(()) => println!("Exported the changes since `{since}` to `{path}`"),
+                                    Err(e) => println!("Export error: {e}"),
+                                },
+                                Err(e) => println!("{e}"),
+                            }
+                        } else {
+                            println!("Expected format `export --patch:path:<tag or operation number>`");
+                        }
+                    },
+                    "apply" => {
+                        if let Some(path) = value {
+                            let patch = std::fs::read_to_string(path).map_err(NotepadError::from);
+
+                            match patch.and_then(|patch| engine.apply_patch(&mut network, &patch)) {
+                                Ok(diffs) => println!("Applied `{path}` with {diffs} diffs, checksum: {}", engine.documents.active().checksum()),
+                                Err(e) => println!("Apply error: {e}"),
+                            }
+                        } else {
+                            println!("Expected format `apply:path`");
+                        }
+                    },
+                    "import --text" => {
+                        if let Some(path) = value {
+                            let text = std::fs::read_to_string(path).map_err(NotepadError::from);
+
+                            match text.and_then(|text| engine.import_text(&mut network, &text)) {
+                                Ok(()) => println!("Imported document from `{path}`"),
+                                Err(e) => println!("Import error: {e}"),
+                            }
+                        } else {
+                            println!("Expected format `import --text:path`");
+                        }
+                    },
+                    "import" => {
+                        if let Some(path) = value {
+                            let archive = std::fs::read(path)
+                                .map_err(NotepadError::from)
+                                .and_then(|data| Archive::decode(&data, char));
+
+                            match archive.and_then(|archive| engine.import(&mut network, archive)) {
+                                Ok(()) => println!("Imported room from `{path}`"),
+                                Err(e) => println!("Import error: {e}"),
+                            }
+                        } else {
+                            println!("Expected format `import:path[:passphrase]`");
+                        }
+                    },
+                    "replay" => {
+                        if let Some(path) = value {
+                            let payloads = std::fs::read_to_string(path)
+                                .map_err(NotepadError::from)
+                                .and_then(|capture| capture::replay(&capture));
+
+                            match payloads {
+                                Ok(payloads) => {
+                                    println!("Replaying {} captured payloads", payloads.len());
+                                    payloads.into_iter().for_each(|incoming| engine.receive(&mut network, incoming));
+                                },
+                                Err(e) => println!("Replay error: {e}"),
+                            }
+                        } else {
+                            println!("Expected format `replay:path`");
+                        }
+                    },
+                    "peers" => {
+                        let connected = network.connected();
+
+                        println!("Connected peers:");
+                        for peer in &connected {
+                            // Companion topics, such as cursors, are left out.
+                            let rooms: Vec<_> = peer.topics.iter().filter_map(|topic| engine.room_of(topic)).map(|room| format!("`{room}`")).collect();
+                            let rooms = if rooms.is_empty() { "no known rooms".to_string() } else { rooms.join(", ") };
+                            let rtt = peer.rtt.map_or("not pinged".to_string(), |rtt| format!("{rtt:?}"));
+                            let agent = peer.agent.as_ref().map_or("not identified".to_string(), |agent| format!("runs {} ({})", agent.version, agent.protocol));
+                            let score = match peer.score {
+                                Some(score) if peer.graylisted => format!(", score {score:.1}, graylisted"),
+                                Some(score) => format!(", score {score:.1}"),
+                                None => String::new(),
+                            };
+                            println!("  {} {}: {rooms}, last ping {rtt}{score}, {agent}", engine.peers.display_name(&peer.peer_id), peer.peer_id);
+                        }
+
+                        println!("In room `{}`:", engine.room());
+                        for (peer_id, peer) in engine.peers.iter() {
+                            let away = if peer.away { ", away" } else { "" };
+                            let relayed = if connected.iter().any(|connected| connected.peer_id == *peer_id) { "" } else { ", not connected directly" };
+                            let reading = peer.reading.as_ref().map_or(String::new(), |reading| {
+                                let document = engine.documents.get(&reading.document).map_or(reading.document.as_str(), |document| &document.meta.name);
+                                format!(", reading `{document}` lines {}-{}", reading.lines.start + 1, reading.lines.end)
+                            });
+                            println!("  {} {peer_id} (seen {}s ago{away}{relayed}{reading})", engine.peers.display_name(peer_id), peer.last_seen.elapsed().as_secs());
+                        }
+                    },
+                    "log" => {
+                        // Filters name modules with `::`, so take the rest of the line.
+                        match line.split_once(':').map(|(_, filter)| filter.trim()) {
+                            Some(filter) => match EnvFilter::try_new(filter) {
+                                Ok(parsed) => match log_filter.reload(parsed) {
+                                    Ok(()) => println!("Logging `{filter}`"),
+                                    Err(e) => println!("Couldn't change the log filter: {e}"),
+                                },
+                                Err(e) => println!("Invalid log filter `{filter}`: {e}"),
+                            },
+                            None => println!("Expected log:<filter>, such as log:p2p_notepad::engine=debug"),
+                        }
+                    },
+                    "find" | "findr" => {
+                        // Patterns may contain `:`, so take the rest of the line.
+                        let pattern = line.split_once(':').map_or("", |(_, pattern)| pattern);
+                        let notepad = engine.documents.active();
+                        let found = match op {
+                            "find" => escape::unescape(pattern).map(|pattern| search::find(notepad.text(), &pattern)),
+                            _ => pattern.parse::<Regex>().map(|regex| regex.find_iter(notepad.text())),
+                        };
+
+                        match found {
+                            Ok(found) if found.is_empty() => println!("No matches of `{pattern}`"),
+                            Ok(found) => {
+                                println!("{} matches of `{pattern}`:", found.len());
+                                for range in found {
+                                    let (line, column) = editor::position(notepad.text(), range.start);
+                                    println!("  {} (line {}, column {}): {:?}", range.start, line + 1, column + 1, &notepad.text()[range.clone()]);
+                                }
+                            },
+                            Err(e) => println!("{e}"),
+                        }
+                    },
+                    "repa" => {
+                        // Only the first `:` that isn't escaped ends the pattern.
+                        let rest = line.split_once(':').map_or("", |(_, rest)| rest);
+                        let parsed = escape::split(rest)
+                            .ok_or_else(|| NotepadError::command("Expected format `repa:pattern:replacement`"))
+                            .and_then(|(pattern, replacement)| Ok((escape::unescape(pattern)?, escape::unescape(replacement)?)));
+
+                        match parsed {
+                            Ok((pattern, _)) if pattern.is_empty() => println!("Expected a pattern to replace"),
+                            Ok((pattern, replacement)) => {
+                                let text = &engine.documents.active().text();
+
+                                match search::find(text, &pattern).len() {
+                                    0 => println!("No matches of `{pattern}`"),
+                                    count => {
+                                        // One edit, so peers apply every replacement or none.
+                                        message = diff::compute(text, &text.replace(&pattern, &replacement));
+                                        println!("Replacing {count} matches of `{pattern}`");
+                                    },
+                                }
+                            },
+                            Err(e) => println!("{e}"),
+                        }
+                    },
+                    "quit" => break,
+                    "mem" => println!("{}", engine.memory_usage()),
+                    "compact" => match engine.compact_oplog() {
+                        Ok((before, after)) => println!("Compacted the operation log from {} to {}", memory::bytes(before), memory::bytes(after)),
+                        Err(e) => println!("Compact error: {e}"),
+                    },
+                    "pending" => {
+                        let now = std::time::Instant::now();
+                        for (room, payloads, due) in engine.pending() {
+                            let due = due.map_or(String::new(), |due| format!(", next tried in {}s", due.saturating_duration_since(now).as_secs()));
+                            println!("Room `{room}`: {} waiting to be published{due}", payloads.len());
+                            for (i, payload) in payloads.iter().enumerate() {
+                                println!("  {} {payload}", i + 1);
+                            }
+                        }
+                    },
+                    "probe" => {
+                        engine.probe(&mut network);
+                        println!("Published a latency probe, see the results with `stats`");
+                    },
+                    "verify" => {
+                        engine.verify(&mut network);
+                        println!("Published a hash of `{}`, peers report whether theirs matches", engine.documents.active_meta().name);
+                    },
+                    "stats" => {
+                        println!("Round trip latency by peer:");
+                        for (peer_id, percentiles) in engine.latency.percentiles() {
+                            println!(
+                                "  {}: p50 {:?}, p90 {:?}, p99 {:?} ({} probes)",
+                                engine.peers.display_name(peer_id), percentiles.p50, percentiles.p90, percentiles.p99, percentiles.samples
+                            );
+                        }
+                    },
+                    "dashboard" => {
+                        let now = std::time::Instant::now();
+                        for (i, room) in engine.rooms(config.peer_timeout, now).iter().enumerate() {
+                            let marker = if room.current { "*" } else { " " };
+                            let last_edit = room.last_edit.map_or("never".to_string(), |at| format!("{}s ago", now.saturating_duration_since(at).as_secs()));
+                            println!("{marker}{} `{}`: {} unread, {} peers, last edit {last_edit}, {}", i + 1, room.room, room.unread, room.peers, room.health);
+                        }
+                    },
+                    "dial" => {
+                        // Addresses may contain `:`, as in `/ip6/::1/...`, so take the rest of the line.
+                        match line.split_once(':') {
+                            Some((_, address)) => match network.dial(address.trim()) {
+                                Ok(()) => println!("Dialing {}", address.trim()),
+                                Err(e) => println!("{e}"),
+                            },
+                            None => println!("Expected format `dial:<multiaddr>`, e.g. `dial:/ip4/203.0.113.7/tcp/4001/p2p/<peer id>`"),
+                        }
+                    },
+                    "dial --relay" => match value.map(|peer| network.dial_relayed(peer)) {
+                        Some(Ok(())) => println!("Dialing {} through the relay", value.unwrap_or_default()),
+                        Some(Err(e)) => println!("{e}"),
+                        None => println!("Expected format `dial --relay:<peer id>`"),
+                    },
+                    "bootstrap" => {
+                        let added = match line.split_once(':') {
+                            Some((_, address)) => address.trim().parse().map_err(NotepadError::command).and_then(|address| network.add_bootstrap(address)),
+                            None => Ok(()),
+                        };
+
+                        match added.and_then(|()| network.bootstrap()) {
+                            Ok(()) => println!("Looking up room `{}` in the DHT", engine.room()),
+                            Err(e) => println!("{e}"),
+                        }
+                    },
+                    "mesh" => {
+                        println!("Peers in room `{}`, by ping round trip:", engine.room());
+                        for peer in network.mesh(engine.topic()) {
+                            let link = if peer.in_mesh { "mesh" } else { "gossip" };
+                            let rtt = peer.rtt.map_or("not pinged".to_string(), |rtt| format!("{rtt:?}"));
+                            let score = peer.score.map_or(String::new(), |score| format!(", score {score:.1}"));
+                            println!("  {} ({link}): {rtt}{score}", engine.peers.display_name(&peer.peer_id));
+                        }
+                    },
+                    "conflicts" => {
+                        println!("Remote edits landing where you were typing, by peer:");
+                        for (peer_id, count) in engine.conflicts.by_peer() {
+                            let name = peer_id.map_or("unknown".to_string(), |peer_id| engine.peers.display_name(peer_id));
+                            println!("  {name}: {count}");
+                        }
+
+                        println!("By region:");
+                        for (document, range, count) in engine.conflicts.by_region() {
+                            let name = engine.documents.get(document).map_or(document, |document| &document.meta.name);
+                            println!("  `{name}` {range:?}: {count}");
+                        }
+                    },
+                    "graph" => {
+                        println!("Operations per {}s over the last {} minutes, by peer:", BUCKET_LEN.as_secs(), BUCKETS * BUCKET_LEN.as_secs() / 60);
+                        for (source, counts) in engine.activity.histograms(std::time::Instant::now()) {
+                            let name = source.map_or("you".to_string(), |peer_id| engine.peers.display_name(&peer_id));
+                            println!("  {name:>20} |{}| {}", activity::sparkline(&counts), counts.iter().sum::<usize>());
+                        }
+                    },
+                    "user" => {
+                        match engine.users.switch(value) {
+                            Ok(()) => match value {
+                                Some(nickname) => println!("Editing as `{nickname}`"),
+                                None => println!("Editing as this node"),
+                            },
+                            Err(e) => println!("{e}, add them with `user new:{}`", value.unwrap_or_default()),
+                        }
+                    },
+                    "user new" => {
+                        if let Some(nickname) = value {
+                            match engine.users.add(nickname) {
+                                Ok(user) => println!("Added user `{nickname}` ({}), editing as them", user.peer_id()),
+                                Err(e) => println!("{e}"),
+                            }
+                        } else {
+                            println!("Expected format `user new:nickname`");
+                        }
+                    },
+                    "user list" => {
+                        let active = engine.users.active().map(|user| user.peer_id());
+
+                        for user in engine.users.iter() {
+                            let marker = if Some(user.peer_id()) == active { "*" } else { " " };
+                            println!("{marker} {} {}", user.nickname, user.peer_id());
+                        }
+                    },
+                    "nick" => {
+                        if let Some(nickname) = value {
+                            match engine.set_nickname(&mut network, nickname) {
+                                Ok(()) => println!("Nickname set to `{nickname}`"),
+                                Err(e) => println!("{e}"),
+                            }
+                        } else {
+                            println!("Expected format `nick:name`");
+                        }
+                    },
+                    "clip set" => {
+                        if let Some((_, text)) = line.split_once(':') {
+                            engine.publish(&mut network, Message::Clipboard(text.to_string()));
+                            println!("Shared {} characters to the room clipboard", text.chars().count());
+                        } else {
+                            println!("Expected format `clip set:text`");
+                        }
+                    },
+                    "say" => {
+                        // Chat may contain `:`, so take the rest of the line.
+                        match line.split_once(':').map(|(_, text)| text).filter(|text| !text.trim().is_empty()) {
+                            Some(text) => {
+                                engine.publish(&mut network, Message::Chat(text.to_string()));
+                                println!("<you> {text}");
+
+                                let name = engine.peers.nickname.clone().unwrap_or_else(|| network.peer_id().to_string());
+                                let line = format!("#{} <{name}> {text}", engine.room());
+                                if let Some(bridged) = &mut engine.bridged {
+                                    bridged.push(line);
+                                }
+                            },
+                            None => println!("Expected format `say:text`"),
+                        }
+                    },
+                    "matrix" if value == Some("snap") => {
+                        if engine.bridged.is_none() {
+                            println!("Chat isn't bridged with Matrix, start the node with `--matrix`");
+                        } else {
+                            let name = engine.documents.active_meta().name.clone();
+                            let text = engine.documents.active().text().to_string();
+
+                            match post_sender.try_send(Post::Snapshot { name: name.clone(), text }) {
+                                Ok(()) => println!("Posting a snapshot of `{name}` to Matrix"),
+                                Err(_) => println!("The Matrix bridge is busy, try again"),
+                            }
+                        }
+                    },
+                    "clip get" => {
+                        match &engine.clipboard {
+                            Some((Some(peer_id), text)) => println!("Clipboard from {}:\n{text}", engine.peers.display_name(peer_id)),
+                            Some((None, text)) => println!("Clipboard:\n{text}"),
+                            None => println!("Nothing has been shared to the clipboard"),
+                        }
+                    },
+                    "board" => match value.map(|name| (name, engine.documents.boards.get(name))) {
+                        Some((name, Some(board))) if config.plain_output => {
+                            println!("Board `{name}` has {} strokes", board.strokes().len());
+                            for stroke in board.strokes() {
+                                let points: Vec<_> = stroke.points.iter().map(|(x, y)| format!("{x},{y}")).collect();
+                                println!("  {} through {}", stroke.color, points.join(" "));
+                            }
+                        },
+                        Some((name, Some(board))) => println!("Board `{name}`, {} strokes:\n{}", board.strokes().len(), board.render(true)),
+                        Some((name, None)) => println!("No board `{name}`, draw on it with `board draw:{name}:<color> <x>,<y>...`"),
+                        None => {
+                            let boards: Vec<_> = engine.documents.boards.keys().map(String::as_str).collect();
+                            println!("Boards: {}", boards.join(", "));
+                        },
+                    },
+                    "board draw" => {
+                        let stroke = char.and_then(|stroke| stroke.split_once(' ')).map(|(color, points)| -> Result<Stroke, NotepadError> {
+                            Ok(Stroke { id: rand::random(), color: color.parse::<Color>()?, points: whiteboard::parse_points(points)? })
+                        });
+
+                        match (value, stroke) {
+                            (Some(name), Some(stroke)) => match stroke.and_then(|stroke| engine.draw(&mut network, name, vec![BoardOp::Draw(stroke)])) {
+                                Ok(()) => println!("Drew on board `{name}`"),
+                                Err(e) => println!("{e}"),
+                            },
+                            _ => println!("Expected format `board draw:<board>:<color> <x>,<y> <x>,<y>...`, within {}x{}", whiteboard::WIDTH, whiteboard::HEIGHT),
+                        }
                     },
-                    "ins" => {
-                        if let Some(index) = value {
-                            if let Some(char) = char {
-                                // cannot handle escaped i.e '\n'
-                                if char.len() == 1 {
-                                    message.messages.push( 
-                                        Diff { 
-                                            opcode: Operation::Ins, 
-                                            operand: Some( char
-                                                .chars()
-                                                .nth(0)
-                                                .unwrap()
-                                            ), 
-                                            index: index
-                                                .parse::<u8>()
-                                                .expect("`index` failed to parse to `u8`") 
-                                        }
-                                    );
-                                } else {
-                                    println!("Expects char to be a single character")
+                    "board erase" | "board clear" => {
+                        let erase = |board: &whiteboard::Whiteboard| match op {
+                            "board clear" => Ok(board.clear()),
+                            _ => match char.map(whiteboard::parse_points) {
+                                Some(Ok(points)) if points.len() == 1 => Ok(board.erase_at(points[0])),
+                                _ => Err(NotepadError::command("Expected format `board erase:<board>:<x>,<y>`")),
+                            },
+                        };
+
+                        match value.map(|name| (name, engine.documents.boards.get(name).map(erase))) {
+                            Some((name, Some(Ok(ops)))) => {
+                                let count = ops.len();
+
+                                match engine.draw(&mut network, name, ops) {
+                                    Ok(()) => println!("Erased {count} strokes from board `{name}`"),
+                                    Err(e) => println!("{e}"),
                                 }
-                            } else {
-                                println!("Expected format `ins:index:char`");
+                            },
+                            Some((_, Some(Err(e)))) => println!("{e}"),
+                            Some((name, None)) => println!("No board `{name}`"),
+                            None => println!("Expected format `{op}:<board>`"),
+                        }
+                    },
+                    "attach" => {
+                        if let Some(path) = value {
+                            let index = char.map_or(Ok(0), str::parse::<usize>).map_err(|_| NotepadError::command("`index` failed to parse to `usize`"));
+                            let name = std::path::Path::new(path).file_name().map_or(path.into(), |name| name.to_string_lossy());
+                            let attached = index.and_then(|index| {
+                                let data = std::fs::read(path)?;
+                                engine.attach(&mut network, &name, data, index)
+                            });
+
+                            match attached {
+                                Ok(meta) => println!("Attached `{name}` as {}", attachment::reference(&meta.hash)),
+                                Err(e) => println!("Attach error: {e}"),
+                            }
+                        } else {
+                            println!("Expected format `attach:path[:index]`");
+                        }
+                    },
+                    "attachments" => {
+                        for (meta, held) in engine.attachments.iter() {
+                            let held = if held { "" } else { ", not downloaded" };
+                            println!("{} `{}` ({}{held})", meta.hash, meta.name, memory::bytes(meta.size as usize));
+                        }
+                    },
+                    "fetch" => {
+                        if let Some(hash) = value {
+                            match engine.fetch(&mut network, hash) {
+                                Ok(()) => println!("Requested attachment {hash}"),
+                                Err(e) => println!("{e}"),
+                            }
+                        } else {
+                            println!("Expected format `fetch:hash`");
+                        }
+                    },
+                    "save" => {
+                        if let (Some(hash), Some(path)) = (value, char) {
+                            match engine.attachments.get(hash) {
+                                Some(data) => match std::fs::write(path, data) {
+                                    Ok(()) => println!("Saved attachment to `{path}`"),
+                                    Err(e) => println!("Save error: {e}"),
+                                },
+                                None => println!("Attachment {hash} isn't downloaded, fetch it with `fetch:{hash}`"),
                             }
                         } else {
-                            println!("Expected format `ins:index:char`");
+                            let path = value.map_or_else(|| room_file(engine.room()), PathBuf::from);
+
+                            match std::fs::write(&path, engine.export_text()) {
+                                Ok(()) => println!("Saved `{}` to `{}`", engine.documents.active_meta().name, path.display()),
+                                Err(e) => println!("Save error: {e}"),
+                            }
+                        }
+                    },
+                    "load" => {
+                        // The rest of the line, so paths may hold `:`.
+                        let path = line.split_once(':').map_or_else(|| room_file(engine.room()), |(_, path)| PathBuf::from(path));
+                        let text = std::fs::read_to_string(&path).map_err(NotepadError::from);
+
+                        match text.and_then(|text| engine.load_text(&mut network, &text)) {
+                            Ok(()) => println!("Loaded `{}`, checksum: {}", path.display(), engine.documents.active().checksum()),
+                            Err(e) => println!("Load error: {e}"),
+                        }
+                    },
+                    "set" => {
+                        match (value, char) {
+                            (Some(key), Some(value)) => match config.set(key, value) {
+                                Ok(()) => {
+                                    engine.memory_budget = config.memory_budget;
+                                    engine.control_chars = config.control_chars;
+                                    engine.host = config.is_host();
+                                    snapshot_timer = config.snapshot_interval.map(|period| time::interval_at(Instant::now() + period, period));
+                                    engine.enforce_memory_budget();
+                                    println!("Set `{key}` to `{value}`");
+                                },
+                                Err(e) => println!("{e}"),
+                            },
+                            (None, _) => {
+                                for key in config::TUNABLES {
+                                    println!("{key}: {}", config.tunable(key).unwrap_or("off".to_string()));
+                                }
+                            },
+                            _ => println!("Expected format `set:key:value`"),
+                        }
+                    },
+                    "config save" => {
+                        match config.save() {
+                            Ok(path) => println!("Saved settings to `{}`", path.display()),
+                            Err(e) => println!("{e}"),
+                        }
+                    },
+                    "telemetry status" => {
+                        match &config.telemetry {
+                            Some(endpoint) => println!("Sending this report to {}:{}{} every hour and on exit:", endpoint.host, endpoint.port, endpoint.path),
+                            None => println!("Telemetry is off, nothing is sent. With `--telemetry <endpoint>` this report would be:"),
+                        }
+                        println!("{}", telemetry.report(std::time::Instant::now()));
+                    },
+                    "room" => {
+                        let prefs = engine.prefs().cloned().unwrap_or_default();
+                        println!("Room `{}`: read-only {}, quiet {}", engine.room(), on_off(prefs.read_only), on_off(prefs.quiet));
+                        for peer_id in &prefs.muted {
+                            println!("  muted {}", engine.peers.display_name(peer_id));
+                        }
+                    },
+                    "room list" => {
+                        // Descriptions may contain `:`, so take the rest of the line.
+                        let description = line.split_once(':').map_or("", |(_, description)| description);
+
+                        match engine.list_room(&mut network, description) {
+                            Ok(()) => println!("Listing room `{}` in the directory", engine.room()),
+                            Err(e) => println!("{e}"),
+                        }
+                    },
+                    "room create" => {
+                        match value {
+                            Some(room) => match engine.create_room(&mut network, room, char, None) {
+                                Ok(manifest) => println!("Created room `{}`, which {manifest}, peers join it by that name", engine.room()),
+                                Err(e) => println!("{e}"),
+                            },
+                            None => println!("Expected format `room create:<room>[:passphrase]`"),
+                        }
+                    },
+                    "room create --from" => {
+                        if let (Some(room), Some(path)) = (value, char) {
+                            let template = std::fs::read_to_string(path).map_err(NotepadError::from);
+
+                            match template.and_then(|template| engine.create_room(&mut network, room, None, Some(&template))) {
+                                Ok(manifest) => println!("Created room `{}` from `{path}`, which {manifest}, peers join it by that name", engine.room()),
+                                Err(e) => println!("{e}"),
+                            }
+                        } else {
+                            println!("Expected format `room create --from:<room>:<path>`");
+                        }
+                    },
+                    "room manifest" => match engine.manifest() {
+                        Some(manifest) => println!("Room `{}` {manifest}", engine.room()),
+                        None => println!("Room `{}` has no manifest, it wasn't made with `room create`", engine.room()),
+                    },
+                    "room unlist" => {
+                        engine.unlist_room();
+                        println!("Stopped listing room `{}`, the directory drops it shortly", engine.room());
+                    },
+                    "backup restore" => match engine.restore_backup(&mut network) {
+                        Ok(()) => println!("Asked the backup peer for the backup of room `{}`", engine.room()),
+                        Err(e) => println!("{e}"),
+                    },
+                    "watch" => {
+                        // Patterns are separated by `|` and may contain `:`, so take the rest of the line.
+                        let patterns = char.map_or_else(
+                            || engine.peers.nickname.iter().map(|nickname| format!("@{nickname}")).collect(),
+                            |patterns| patterns.split('|').filter(|pattern| !pattern.is_empty()).map(str::to_string).collect(),
+                        );
+
+                        match value.map(|room| (room, engine.watch(&mut network, room, None, patterns))) {
+                            Some((room, Ok(()))) => println!("Watching room `{room}`"),
+                            Some((_, Err(e))) => println!("{e}"),
+                            None => println!("Expected format `watch:<room>[:pattern|pattern...]`, watching for your @nickname by default"),
+                        }
+                    },
+                    "aliases" => {
+                        let mut aliases: Vec<_> = config.aliases.iter().collect();
+                        aliases.sort();
+                        for (name, commands) in aliases {
+                            println!("  {name}: {}", commands.join("; ").replace('\n', "\\n"));
+                        }
+                    },
+                    "watch list" => {
+                        for watch in engine.watches.values() {
+                            println!("  {}: {}", watch.room, watch.patterns.join(" | "));
+                        }
+                        println!("{} rooms watched", engine.watches.len());
+                    },
+                    "unwatch" => match value.map(|room| engine.unwatch(&mut network, room)) {
+                        Some(Ok(())) => println!("Stopped watching room `{}`", value.unwrap_or_default()),
+                        Some(Err(e)) => println!("{e}"),
+                        None => println!("Expected format `unwatch:<room>`"),
+                    },
+                    "rooms" => {
+                        if let Err(e) = engine.query_directory(&mut network) {
+                            println!("{e}");
+                        }
+                    },
+                    "ro" => match value {
+                        Some(setting @ ("on" | "off")) => {
+                            engine.observe = setting == "on";
+                            println!("Turned observing {setting}");
+                        },
+                        None => println!("Observing {}", if engine.observe { "on" } else { "off" }),
+                        _ => println!("Expected format `ro:on|off`"),
+                    },
+                    "room read-only" | "room quiet" => {
+                        match value {
+                            Some(setting @ ("on" | "off")) => {
+                                let prefs = engine.prefs_mut();
+                                let flag = if op == "room quiet" { &mut prefs.quiet } else { &mut prefs.read_only };
+                                *flag = setting == "on";
+                                println!("Turned `{op}` {setting} for this room");
+                            },
+                            _ => println!("Expected format `{op}:on|off`"),
+                        }
+                    },
+                    "room mute" | "room unmute" => {
+                        let peer_id = value.and_then(|name| {
+                            engine.peers.iter().map(|(peer_id, _)| *peer_id).find(|peer_id| engine.peers.display_name(peer_id) == name).or_else(|| name.parse().ok())
+                        });
+
+                        match peer_id {
+                            Some(peer_id) if op == "room mute" => {
+                                engine.prefs_mut().muted.insert(peer_id);
+                                println!("Muted {} in this room", engine.peers.display_name(&peer_id));
+                            },
+                            Some(peer_id) => {
+                                engine.prefs_mut().muted.remove(&peer_id);
+                                println!("Unmuted {} in this room", engine.peers.display_name(&peer_id));
+                            },
+                            None => println!("Expected format `{op}:<peer>`, with a name or peer id from `peers`"),
+                        }
+                    },
+                    "room grant" | "room revoke" => {
+                        let peer_id = value.and_then(|name| {
+                            engine.peers.iter().map(|(peer_id, _)| *peer_id).find(|peer_id| engine.peers.display_name(peer_id) == name).or_else(|| name.parse().ok())
+                        });
+
+                        match peer_id.map(|peer_id| (peer_id, engine.permit(&mut network, peer_id, op == "room grant"))) {
+                            Some((peer_id, Ok(()))) if op == "room grant" => println!("Granted {} write access", engine.peers.display_name(&peer_id)),
+                            Some((peer_id, Ok(()))) => println!("Revoked write access from {}", engine.peers.display_name(&peer_id)),
+                            Some((_, Err(e))) => println!("{e}"),
+                            None => println!("Expected format `{op}:<peer>`, with a name or peer id from `peers`"),
                         }
                     },
-                    "del" => {
-                        if let Some(index) = value {
-                            message.messages.push(
-                                Diff {
-                                    opcode: Operation::Del,
-                                    operand: None,
-                                    index: index
-                                        .parse::<u8>()
-                                        .expect("`index` failed to parse to `u8`")
+                    "allow" | "block" => {
+                        let peer_id = value.and_then(|name| {
+                            engine.peers.iter().map(|(peer_id, _)| *peer_id).find(|peer_id| engine.peers.display_name(peer_id) == name).or_else(|| name.parse().ok())
+                        });
+
+                        match peer_id {
+                            Some(peer_id) if op == "block" => {
+                                let name = engine.peers.display_name(&peer_id);
+                                engine.block(peer_id);
+                                network.block(peer_id);
+                                println!("Blocked {name}");
+                            },
+                            Some(peer_id) => {
+                                engine.access.allow(peer_id);
+                                network.unblock(peer_id);
+                                println!("Allowed {peer_id}");
+                            },
+                            None if op == "block" && value.is_none() => {
+                                for peer_id in engine.access.blocked() {
+                                    println!("{peer_id}");
                                 }
-                            )
-                        } else {
-                            println!("Expected format `del:index`");
-                        }
-                    },
-                    "rep" => {
-                        if let Some(index) = value {
-                            if let Some(char) = char {
-                                // cannot handle escaped i.e '\n'
-                                if char.len() == 1 {
-                                    message.messages.push( 
-                                        Diff { 
-                                            opcode: Operation::Rep, 
-                                            operand: Some( char
-                                                .chars()
-                                                .nth(0)
-                                                .unwrap()
-                                            ), 
-                                            index: index
-                                                .parse::<u8>()
-                                                .expect("`index` failed to parse to `u8`") 
-                                        }
-                                    );
-                                } else {
-                                    println!("Expects char to be a single character")
+                            },
+                            None => println!("Expected format `{op}:<peer>`, with a name or peer id from `peers`"),
+                        }
+                    },
+                    "links" => {
+                        let links = links::find(engine.documents.active().text());
+
+                        for (i, link) in links.iter().enumerate() {
+                            println!("{:>3}. {} (line {})", i + 1, link.url, link.line + 1);
+                        }
+                        println!("{} links, open one with `open:<n>`", links.len());
+                    },
+                    "open" => match value.and_then(|n| n.parse::<usize>().ok()) {
+                        Some(n) => {
+                            let links = links::find(engine.documents.active().text());
+
+                            match n.checked_sub(1).and_then(|i| links.get(i)) {
+                                Some(link) => match links::open(link.url) {
+                                    Ok(()) => println!("Opening {}", link.url),
+                                    Err(e) => println!("{e}"),
+                                },
+                                None => println!("No link {n}, `links` lists them"),
+                            }
+                        },
+                        None => println!("Expected format `open:<n>`, with a number from `links`, files are read with `load:<path>`"),
+                    },
+                    "write" => {
+                        // The rest of the line, so paths may hold `:`.
+                        if let Some((_, path)) = line.split_once(':') {
+                            match std::fs::write(path, engine.export_text()) {
+                                Ok(()) => println!("Wrote `{}` to `{path}`", engine.documents.active_meta().name),
+                                Err(e) => println!("Write error: {e}"),
+                            }
+                        } else {
+                            println!("Expected format `write:<path>`");
+                        }
+                    },
+                    "spell" => match &dictionary {
+                        Some(dictionary) => {
+                            let text = &engine.documents.active().text();
+                            let misspellings = dictionary.check(text);
+
+                            for (i, line) in text.lines().enumerate() {
+                                let on_line: Vec<_> = misspellings.iter().filter(|misspelling| misspelling.line == i).collect();
+
+                                if !on_line.is_empty() {
+                                    println!("{:>4} | {line}", i + 1);
+                                    println!("     | {}", spell::underline(&on_line));
                                 }
-                            } else {
-                                println!("Expected format `rep:index:char`");
+                            }
+                            println!("{} possibly misspelled words", misspellings.len());
+                        },
+                        None => println!("No dictionary loaded, start with `--dictionary <path>`"),
+                    },
+                    "session" => match &engine.session {
+                        Some(session) => {
+                            let left = session.ends.saturating_duration_since(std::time::Instant::now());
+                            println!("Session ends in {}s, {} ops so far", left.as_secs(), session.ops);
+                        },
+                        None => println!("No session in progress, start one with `session start:<duration>[:read-only]`"),
+                    },
+                    "session start" => {
+                        match (value.map(session::parse_duration), char) {
+                            (Some(Ok(duration)), None | Some("read-only")) => {
+                                engine.session = Some(Session::new(duration, char.is_some(), std::time::Instant::now()));
+                                println!("Session started, ending in {}s", duration.as_secs());
+                            },
+                            (Some(Err(e)), _) => println!("{e}"),
+                            _ => println!("Expected format `session start:<duration>[:read-only]`"),
+                        }
+                    },
+                    "session end" => end_session(&mut engine, &mut network),
+                    "swi" => {
+                        if let Some(value) = value {
+                            match engine.switch_room(&mut network, value, char) {
+                                Ok(()) if char.is_some() => println!("Switching to private room: `{:?}`", value),
+                                Ok(()) => println!("Switching to room: `{:?}`", value),
+                                Err(e) => println!("{e}"),
                             }
                         } else {
-                            println!("Expected format `rep:index:char`");
+                            println!("Expected format `swi:value[:passphrase]`");
+                        } 
+                    },
+                    "tag" => match value.filter(|name| !name.is_empty()) {
+                        Some(name) => {
+                            engine.tag(name);
+                            println!("Tagged `{}` as `{name}`, checksum: {}", engine.documents.active_meta().name, engine.documents.active().checksum());
+                        },
+                        None => println!("Expected format `tag:name`"),
+                    },
+                    "tags" => {
+                        for (name, tag) in &engine.documents.tags {
+                            let document = engine.documents.get(&tag.document).map_or(tag.document.as_str(), |document| &document.meta.name);
+                            println!("`{name}` of `{document}` ({})", memory::bytes(tag.text.len()));
                         }
-                    }
-                    _ => {
-                        println!("Unknown opcode: {op:?}");
                     },
-                }
+                    "restore" => match value {
+                        Some(name) => match engine.restore_tag(&mut network, name) {
+                            Ok(0) => println!("Already at `{name}`"),
+                            Ok(diffs) => println!("Restored `{name}` with {diffs} diffs, checksum: {}", engine.documents.active().checksum()),
+                            Err(e) => println!("{e}"),
+                        },
+                        None => println!("Expected format `restore:name`"),
+                    },
+                    "lock" => match engine.lock(&mut network) {
+                        Ok(()) => println!("Locked `{}`, other peers can't edit it until you `unlock`", engine.documents.active_meta().name),
+                        Err(e) => println!("{e}"),
+                    },
+                    "unlock" => match engine.unlock(&mut network) {
+                        Ok(()) => println!("Unlocked `{}`", engine.documents.active_meta().name),
+                        Err(e) => println!("{e}"),
+                    },
+                    "claim" => {
+                        let lines = value.zip(char).and_then(|(first, last)| Some((first.parse().ok()?, last.parse().ok()?)));
+
+                        match lines {
+                            Some((first, last)) => match engine.claim(&mut network, first, last) {
+                                Ok(()) => println!("Claimed lines {first} to {last} of `{}`, peers editing them are warned", engine.documents.active_meta().name),
+                                Err(e) => println!("{e}"),
+                            },
+                            None => println!("Expected format `claim:first:last`"),
+                        }
+                    },
+                    "unclaim" => match engine.unclaim(&mut network) {
+                        Ok(()) => println!("Released the lines claimed"),
+                        Err(e) => println!("{e}"),
+                    },
+                    "select" => {
+                        let bounds = value.zip(char).and_then(|(first, last)| Some((first.parse().ok()?, last.parse().ok()?)));
+
+                        match bounds {
+                            Some((first, last)) => match lines::range(engine.documents.active().text(), first, last) {
+                                Ok(range) => {
+                                    engine.select(&mut network, Some(range));
+                                    println!("Selected lines {first} to {last} of `{}` for peers to see", engine.documents.active_meta().name);
+                                },
+                                Err(e) => println!("{e}"),
+                            },
+                            None if value.is_none() => {
+                                engine.select(&mut network, None);
+                                println!("Cleared the selection");
+                            },
+                            None => println!("Expected format `select:first:last`, or `select` to clear it"),
+                        }
+                    },
+                    "claims" => {
+                        for (peer_id, (first, last)) in engine.claimed_lines() {
+                            let name = peer_id.map_or("You".to_string(), |peer_id| engine.peers.display_name(&peer_id));
+                            println!("{name}: lines {first} to {last}");
+                        }
+                    },
+                    "undo" | "redo" => {
+                        let result = if op == "undo" { engine.undo(&mut network) } else { engine.redo(&mut network) };
+
+                        match result {
+                            Ok(summary) => println!("{op}: {summary}, checksum: {}", engine.documents.active().checksum()),
+                            Err(e) => println!("{e}"),
+                        }
+                    },
+                    "insl" | "dell" | "repl" => {
+                        match parse_line_edit(op, value, char).and_then(|edit| edit.diffs(engine.documents.active().text())) {
+                            Ok(diffs) => message = diffs,
+                            Err(e) => {
+                                println!("{e}");
+                                failed = true;
+                            },
+                        }
+                    },
+                    "ins" | "del" | "rep" | "inss" | "pas" | "delr" => {
+                        match parse_diff(op, value, char) {
+                            Ok(diff) => message.messages.push(diff),
+                            Err(e) => {
+                                println!("{e}");
+                                failed = true;
+                            },
+                        }
+                    },
+                    // From another room with a `..` range, otherwise to the clipboard.
+                    "copy" if char.is_some_and(|range| range.contains("..")) => {
+                        let range = char.and_then(|range| range.split_once("..")).and_then(|(start, end)| Some(start.parse::<usize>().ok()?..end.parse::<usize>().ok()?));
+
+                        match value.zip(range) {
+                            Some((room, range)) => {
+                                let index = engine.cursor.unwrap_or(engine.documents.active().len());
 
-                current_notepad.apply_message_buf(&message);
+                                match engine.copy_from(&mut network, room, range, index) {
+                                    Ok(text) => println!("Inserted {} characters from `{room}`", text.chars().count()),
+                                    Err(e) => println!("{e}"),
+                                }
+                            },
+                            None => println!("Expected format `copy:<room>:<start>..<end>`"),
+                        }
+                    },
+                    "copy" => {
+                        let bounds = value.zip(char).and_then(|(start, end)| Some((start.parse::<usize>().ok()?, end.parse::<usize>().ok()?)));
 
-                let message_bytes: Vec<u8> = message.into();
+                        match bounds.map(|(start, end)| (start, end, engine.documents.active().text().get(start..end))) {
+                            Some((_, _, Some(text))) => match clipboard::set(text) {
+                                Ok(()) => println!("Copied {} characters to the clipboard", text.chars().count()),
+                                Err(e) => println!("{e}"),
+                            },
+                            Some((start, end, None)) => println!("`{start}..{end}` isn't a range of whole characters in the document"),
+                            None => println!("Expected format `copy:start:end`, or `copy:<room>:<start>..<end>`"),
+                        }
+                    },
+                    // Through the same insert of a whole string as `inss`.
+                    "pastec" => match value.map(str::parse::<usize>) {
+                        Some(Ok(index)) => match clipboard::get() {
+                            Ok(text) if text.is_empty() => println!("The clipboard is empty"),
+                            Ok(text) => message.messages.push(Diff { opcode: Operation::InsStr(LineEnding::normalize(&text)), operand: None, index }),
+                            Err(e) => {
+                                println!("{e}");
+                                failed = true;
+                            },
+                        },
+                        _ => {
+                            println!("Expected format `pastec:index`");
+                            failed = true;
+                        },
+                    },
+                    "run" => match (value, char) {
+                        _ if script.is_some() => {
+                            println!("A script is already running, scripts can't run others");
+                            failed = true;
+                        },
+                        (Some(path), None | Some("abort")) => match std::fs::read_to_string(path) {
+                            Ok(text) => {
+                                let running = Script::new(path, &text, char.is_some());
+                                println!("Running {} commands from `{path}`", running.len());
+                                script = Some(running);
+                            },
+                            Err(e) => println!("Run error: {e}"),
+                        },
+                        _ => println!("Expected format `run:<path>[:abort]`"),
+                    },
+                    "help" => match value {
+                        Some(name) => match help::get(name) {
+                            Some(command) => println!("`{}`: {}, e.g. `{}`", command.syntax, command.about, command.example),
+                            None => println!("No command `{name}`, see `help`"),
+                        },
+                        None => {
+                            for command in help::COMMANDS {
+                                println!("  {:<44} {}", command.syntax, command.about);
+                            }
+                            println!("Arguments follow a `:`, see `help:<command>` for an example. Aliases from the config are listed by `aliases`");
+                        },
+                    },
+                    _ => {
+                        let names = help::COMMANDS.iter().map(|command| command.name).chain(config.aliases.iter().map(|(name, _)| name.as_str()));
 
-                if let Err(e) = swarm
-                    .behaviour_mut().gossipsub
-                    .publish(current_topic.clone(), message_bytes) {
-                        match e {
-                            gossipsub::PublishError::InsufficientPeers => {},
-                            _ => println!("Publish error: {e:?}")
+                        match help::closest(op, names) {
+                            Some(name) => println!("Unknown command `{op}`, did you mean `{name}`? See `help`"),
+                            None => println!("Unknown command `{op}`, see `help`"),
                         }
+                        failed = true;
+                    },
                 }
 
-            }
-            event = swarm.select_next_some() => match event {
-                SwarmEvent::Behaviour(MyBehaviourEvent::Mdns(mdns::Event::Discovered(list))) => {
-                    for (peer_id, _multiaddr) in list {
-                        println!("mDNS discovered a new peer: {peer_id}");
-                        swarm.behaviour_mut().gossipsub.add_explicit_peer(&peer_id);
+                if !message.messages.is_empty() {
+                    match engine.edit(&mut network, message) {
+                        Ok(()) => println!("checksum: {}", engine.documents.active().checksum()),
+                        Err(e) => {
+                            println!("{e}");
+                            failed = true;
+                        },
                     }
-                },
-                SwarmEvent::Behaviour(MyBehaviourEvent::Mdns(mdns::Event::Expired(list))) => {
-                    for (peer_id, _multiaddr) in list {
-                        println!("mDNS discover peer has expired: {peer_id}");
-                        swarm.behaviour_mut().gossipsub.remove_explicit_peer(&peer_id);
+                }
+
+                if let Some(stopped) = script.take_if(|running| failed && running.abort) {
+                    println!("Stopped running `{}` at line {}, {} commands left", stopped.path, stopped.line(), stopped.len());
+                }
+            }
+            Some(event) = network.next_event() => {
+                if let (Some(capture), Event::Message(incoming)) = (&mut capture, &event) {
+                    if let Err(e) = capture.record(incoming) {
+                        println!("Capture error: {e}");
                     }
-                },
-                SwarmEvent::Behaviour(MyBehaviourEvent::Gossipsub(gossipsub::Event::Message {
-                    propagation_source: _peer_id,
-                    message_id: _id,
-                    message,
-                })) => {
-                    let msg: MessageBuf = message.data.into();
-                    println!("Current notepad: {current_notepad:?}");
-                    current_notepad.apply_message_buf(&msg);
-                    println!("Updated notepad: {current_notepad:?}");
-                },
-                SwarmEvent::NewListenAddr { address, .. } => {
-                    println!("Local node is listening on {address}");
                 }
-                _ => {}
+
+                engine.handle(&mut network, event);
+            },
+            // A chunk a turn, so peers and the user are kept up with meanwhile.
+            _ = tokio::task::yield_now(), if engine.applying() => {
+                engine.apply_chunk();
+            },
+            _ = render_timer.tick(), if engine.ops_since_render > 0 => {
+                match &mut screen {
+                    Some(screen) => draw(screen, &mut editor, dashboard.as_mut(), &mut engine, config.peer_timeout, false),
+                    None => {
+                        let notepad = engine.documents.active();
+                        println!("Updated notepad ({} ops): {notepad:?} [{}]", engine.ops_since_render, notepad.checksum());
+                    },
+                }
+                engine.ops_since_render = 0;
+            },
+            _ = presence_timer.tick() => {
+                engine.peers.away = last_input.elapsed() > AWAY_AFTER;
+                engine.heartbeat(&mut network, config.peer_timeout, std::time::Instant::now());
+                telemetry.peers(engine.peers.iter().count());
+                save_workspace(&engine, workspace.as_mut());
+
+                let now = std::time::Instant::now();
+                if config.autosave_idle.is_some_and(|idle| engine.vacancy.due(last_input.into_std(), idle, now)) {
+                    save_final_state(&engine);
+                }
+
+                if let Some(screen) = &mut screen {
+                    // Whatever the engine printed since is drawn over.
+                    draw(screen, &mut editor, dashboard.as_mut(), &mut engine, config.peer_timeout, true);
+                }
+            },
+            _ = telemetry_timer.tick(), if config.telemetry.is_some() => {
+                let endpoint = config.telemetry.clone().expect("telemetry is enabled");
+                let report = telemetry.report(std::time::Instant::now());
+
+                tokio::spawn(async move {
+                    if let Err(e) = telemetry::send(&endpoint, report).await {
+                        println!("Telemetry error: {e}");
+                    }
+                });
+            },
+            Some(call) = calls.recv() => {
+                let response = control_request(&mut engine, &mut network, call.request);
+                let _ = call.reply.send(response);
+            },
+            Some(Heard { sender, body }) = heard.recv() => {
+                let text = format!("{}<{sender}> {body}", matrix::RELAYED);
+                println!("<{sender}> {body}");
+                engine.publish(&mut network, Message::Chat(text));
+            },
+            _ = signal::ctrl_c() => break,
+            _ = time::sleep_until(engine.session.as_ref().map_or_else(Instant::now, |session| Instant::from_std(session.ends))), if engine.session.is_some() => {
+                end_session(&mut engine, &mut network);
+            },
+            _ = time::sleep_until(engine.next_retry().map_or_else(Instant::now, Instant::from_std)), if engine.next_retry().is_some() => {
+                engine.flush_outbox(&mut network, std::time::Instant::now());
+            },
+            _ = time::sleep_until(engine.next_edits().map_or_else(Instant::now, Instant::from_std)), if engine.next_edits().is_some() => {
+                engine.flush_edits(&mut network, std::time::Instant::now());
+            },
+            _ = time::sleep_until(engine.next_bulk().map_or_else(Instant::now, Instant::from_std)), if engine.next_bulk().is_some() => {
+                engine.flush_bulk(&mut network, std::time::Instant::now());
+            },
+            _ = tick(&mut snapshot_timer) => {
+                engine.publish_snapshots(&mut network);
+            }
+        }
+
+        if config.snapshot_ops.is_some_and(|ops| engine.ops_since_snapshot >= ops) {
+            engine.publish_snapshots(&mut network);
+        }
+        for line in engine.bridged.as_mut().map(std::mem::take).unwrap_or_default() {
+            if post_sender.try_send(Post::Chat(line)).is_err() {
+                println!("Dropped chat the Matrix bridge couldn't keep up with");
+            }
+        }
+    }
+
+    if screen.is_some() {
+        ratatui::restore();
+    }
+
+    match engine.leave(&mut network) {
+        0 => {},
+        unsent => println!("{unsent} messages couldn't be published before quitting"),
+    }
+    if workspace.is_some() {
+        save_workspace(&engine, workspace.as_mut());
+    } else {
+        match save_archive(&engine) {
+            Ok(path) => println!("Saved the documents of room `{}` to `{}`", engine.room(), path.display()),
+            Err(e) => println!("Save error: {e}"),
+        }
+    }
+    network.shutdown(SHUTDOWN_GRACE).await;
+
+    // The client of `quit` is answered once everything was saved.
+    if let Some(reply) = reply.take() {
+        let _ = reply.send(ipc::captured());
+    }
+
+    if let Some(endpoint) = &config.telemetry {
+        let report = telemetry.report(std::time::Instant::now());
+        let _ = time::timeout(Duration::from_secs(5), telemetry::send(endpoint, report)).await;
+    }
+
+    if terminal {
+        bracketed_paste(paste::DISABLE);
+    }
+
+    Ok(())
+}
+
+/// Parses the `ins`, `del`, `rep`, `inss`, `pas` and `delr` commands into a
+/// diff. `pas` is another name for `inss`, pasting text at an index. The
+/// text of both runs to the end of the line, `:` included. Characters
+/// and text may be escaped, see [`escape::unescape`].
+fn parse_diff(op: &str, index: Option<&str>, char: Option<&str>) -> Result<Diff, NotepadError> {
+    let (opcode, format) = match op {
+        "ins" => (Operation::Ins, "ins:index:char"),
+        "del" => (Operation::Del, "del:index"),
+        "rep" => (Operation::Rep, "rep:index:char"),
+        "inss" => (Operation::InsStr(escape::unescape(char.unwrap_or_default())?), "inss:index:text"),
+        "pas" => (Operation::InsStr(escape::unescape(char.unwrap_or_default())?), "pas:index:text"),
+        "delr" => (Operation::DelRange(0), "delr:start:len"),
+        _ => return Err(NotepadError::command(format!("Unknown opcode: {op:?}"))),
+    };
+    let expected = || NotepadError::command(format!("Expected format `{format}`"));
+
+    let index = index
+        .ok_or_else(expected)?
+        .parse::<usize>()
+        .map_err(|_| NotepadError::command("`index` failed to parse to `usize`"))?;
+
+    let (opcode, operand) = match opcode {
+        Operation::Del => (opcode, None),
+        Operation::InsStr(text) if text.is_empty() => return Err(expected()),
+        Operation::InsStr(_) => (opcode, None),
+        Operation::DelRange(_) => {
+            let len = char
+                .ok_or_else(expected)?
+                .parse::<usize>()
+                .map_err(|_| NotepadError::command("`len` failed to parse to `usize`"))?;
+
+            (Operation::DelRange(len), None)
+        },
+        Operation::Ins | Operation::Rep => {
+            let char = escape::unescape(char.ok_or_else(expected)?)?;
+
+            if char.chars().count() != 1 {
+                return Err(NotepadError::command("Expects char to be a single character"));
+            }
+
+            (opcode, char.chars().next())
+        }
+    };
+
+    Ok(Diff { opcode, operand, index })
+}
+
+/// Answers a request of the control API.
+fn control_request(engine: &mut Engine, network: &mut Network, request: Request) -> Response {
+    match request {
+        Request::Document => Response::ok(format!(
+            "{{\"room\":{},\"document\":{},\"checksum\":{},\"text\":{}}}",
+            output::string(engine.room()),
+            output::string(&engine.documents.active_meta().name),
+            output::string(&engine.documents.active().checksum()),
+            output::string(engine.documents.active().text()),
+        )),
+        Request::Edits(body) => {
+            let diffs = body.lines().filter(|line| !line.trim().is_empty()).map(|line| {
+                let mut parts = line.splitn(3, ':');
+                parse_diff(parts.next().unwrap_or_default(), parts.next(), parts.next())
+            });
+
+            match diffs.collect::<Result<Vec<_>, _>>().and_then(|messages| engine.edit(network, MessageBuf { messages })) {
+                Ok(()) => Response::ok(format!("{{\"checksum\":{}}}", output::string(&engine.documents.active().checksum()))),
+                Err(e) => Response::error(400, e),
             }
+        },
+        Request::Room(room) => match engine.switch_room(network, &room, None) {
+            Ok(()) => Response::ok(format!("{{\"room\":{}}}", output::string(engine.room()))),
+            Err(e) => Response::error(400, e),
+        },
+        Request::Peers => {
+            let peers: Vec<_> = engine.peers.iter().map(|(peer_id, _)| format!(
+                "{{\"id\":{},\"name\":{}}}",
+                output::string(&peer_id.to_string()),
+                output::string(&engine.peers.display_name(peer_id)),
+            )).collect();
+
+            Response::ok(format!("[{}]", peers.join(",")))
+        },
+    }
+}
+
+/// Parses the `insl`, `dell` and `repl` commands into a line edit. The text
+/// runs to the end of the input line, `:` included, and may be escaped.
+fn parse_line_edit(op: &str, line: Option<&str>, text: Option<&str>) -> Result<LineEdit, NotepadError> {
+    let format = match op {
+        "insl" => "insl:line:text",
+        "dell" => "dell:line",
+        _ => "repl:line:text",
+    };
+    let line = line
+        .ok_or_else(|| NotepadError::command(format!("Expected format `{format}`")))?
+        .parse::<usize>()
+        .map_err(|_| NotepadError::command("`line` failed to parse to `usize`"))?;
+    let text = escape::unescape(text.unwrap_or_default())?;
+
+    Ok(match op {
+        "insl" => LineEdit::Insert(line, text),
+        "dell" => LineEdit::Delete(line),
+        _ => LineEdit::Replace(line, text),
+    })
+}
+
+/// The next command to run and whether it was typed, running the commands
+/// an alias stands for, then those of a script, before reading more from
+/// stdin, or from the daemon socket if there is no stdin, in which case the
+/// output is kept for `reply`.
+async fn next_line(
+    stdin: Option<&mut io::Lines<io::BufReader<io::Stdin>>>,
+    commands: &mut mpsc::Receiver<ipc::Command>,
+    reply: &mut Option<oneshot::Sender<String>>,
+    queued: &mut VecDeque<String>,
+    script: &mut Option<Script>,
+) -> std::io::Result<Option<(String, bool)>> {
+    if let Some(line) = queued.pop_front() {
+        return Ok(Some((line, false)));
+    }
+
+    if let Some(line) = script.as_mut().and_then(Script::next) {
+        return Ok(Some((line, false)));
+    }
+
+    match stdin {
+        Some(stdin) => Ok(stdin.next_line().await?.map(|line| (line, true))),
+        None => Ok(commands.recv().await.map(|command| {
+            ipc::capture();
+            *reply = Some(command.reply);
+            (command.line, true)
+        })),
+    }
+}
+
+/// Runs `ctl <socket> <command>`: has the daemon listening at the socket run
+/// the command and prints what it printed.
+async fn ctl(mut args: impl Iterator<Item = String>) -> Result<(), NotepadError> {
+    let socket = args.next();
+    let command = args.collect::<Vec<_>>().join(" ");
+    let Some(socket) = socket.filter(|_| !command.is_empty()) else {
+        return Err(NotepadError::command("Expected format `ctl <socket> <command>`"));
+    };
+
+    print!("{}", ipc::send(Path::new(&socket), &command).await?);
+
+    Ok(())
+}
+
+/// Draws the dashboard if it is open, or else the editor, clearing the screen first if `clear`.
+/// The lines the editor shows are then given to the engine, see [`Engine::view`].
+fn draw(screen: &mut DefaultTerminal, editor: &mut Editor, dashboard: Option<&mut Dashboard>, engine: &mut Engine, timeout: Duration, clear: bool) {
+    if clear {
+        let _ = screen.clear();
+    }
+
+    if let Some(dashboard) = dashboard {
+        let now = std::time::Instant::now();
+        let rooms = engine.rooms(timeout, now);
+        let _ = screen.draw(|frame| dashboard.draw(frame, &rooms, now));
+        return;
+    }
+
+    let notepad = engine.documents.active();
+    let keys: String = [(Binding::Dashboard, "for rooms"), (Binding::Quit, "to quit")]
+        .into_iter()
+        .filter_map(|(binding, what)| Some(format!(", {} {what}", editor.keymap().key(binding)?)))
+        .collect();
+    let title = format!(" {} in `{}` [{}]{keys} ", engine.documents.active_meta().name, engine.room(), notepad.checksum());
+
+    let others: Vec<_> = engine.cursors
+        .in_document(&engine.documents.active_meta().id)
+        .into_iter()
+        .map(|(peer_id, index)| (engine.peers.display_name(&peer_id), index))
+        .collect();
+
+    let unseen = engine.unseen.in_document(&engine.documents.active_meta().id);
+    editor.selections = engine.peers.selections(&engine.documents.active_meta().id).into_iter().map(|(_, range)| range).collect();
+
+    let _ = screen.draw(|frame| editor.draw(frame, &title, notepad.text(), engine.cursor.unwrap_or_default(), &others, unseen));
+    engine.view(editor.visible());
+}
+
+/// Completes on the next tick of `timer`, or never if there is no timer.
+async fn tick(timer: &mut Option<Interval>) {
+    match timer {
+        Some(timer) => {
+            timer.tick().await;
+        },
+        None => std::future::pending().await,
+    }
+}
+
+fn on_off(flag: bool) -> &'static str {
+    if flag { "on" } else { "off" }
+}
+
+/// Writes a bracketed paste mode escape to the terminal.
+fn bracketed_paste(escape: &str) {
+    let mut stdout = std::io::stdout();
+    let _ = stdout.write_all(escape.as_bytes()).and_then(|()| stdout.flush());
+}
+
+/// Saves the session for the next launch, if a workspace is configured.
+fn save_workspace(engine: &Engine, workspace: Option<&mut (Box<dyn Storage>, String)>) {
+    if let Some((storage, name)) = workspace {
+        if let Err(e) = Workspace::new(engine).save(storage.as_mut(), name) {
+            println!("Workspace error: {e}");
         }
     }
 }
 
+/// Exports the room's documents next to `save`'s file for the room, as
+/// every other peer left and the user is idle. The workspace was just saved.
+fn save_final_state(engine: &Engine) {
+    match save_archive(engine) {
+        Ok(path) => println!("Every peer left room `{}` while you were idle, saved its final state to `{}`", engine.room(), path.display()),
+        Err(e) => println!("Every peer left room `{}` while you were idle, export error: {e}", engine.room()),
+    }
+}
+
+/// Exports the room's documents to a file of their own next to `save`'s file for the room.
+fn save_archive(engine: &Engine) -> Result<PathBuf, NotepadError> {
+    let secs = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs();
+    let path = room_file(engine.room()).with_extension(format!("{secs}.archive"));
+    std::fs::write(&path, Archive::new(&engine.documents).encode(None))?;
+
+    Ok(path)
+}
+
+/// Ends the session in progress, exporting the active document and printing the summary published to the room.
+fn end_session(engine: &mut Engine, network: &mut Network) {
+    let read_only = engine.session.as_ref().is_some_and(|session| session.read_only);
+    let Some(summary) = engine.end_session(network) else {
+        println!("No session in progress");
+        return;
+    };
+
+    let secs = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs();
+    let path = format!("session-{secs}.txt");
+    match std::fs::write(&path, engine.export_text()) {
+        Ok(()) => println!("Session ended, exported `{}` to `{path}`", summary.document),
+        Err(e) => println!("Session ended, export error: {e}"),
+    }
+
+    println!("{} participants, {} ops, checksum {}", summary.participants, summary.ops, summary.checksum);
+    if read_only {
+        println!("Room `{}` is now read-only, allow edits with `room read-only:off`", engine.room());
+    }
+}
+
+/// Where `save` and `load` keep the active document of `room` unless given a path,
+/// named after the room with anything but letters, digits, `-` and `_` replaced.
+fn room_file(room: &str) -> PathBuf {
+    let name: String = room.chars().map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' }).collect();
+
+    PathBuf::from(format!("{name}.txt"))
+}
+
+/// Writes the room's documents to `path` as an archive, encrypted if a passphrase is given.
+fn export(documents: &Documents, path: &str, passphrase: Option<&str>) {
+    match std::fs::write(path, Archive::new(documents).encode(passphrase)) {
+        Ok(()) => println!("Exported room to `{path}`"),
+        Err(e) => println!("Export error: {e}"),
+    }
+}
@@ -0,0 +1,122 @@
+use std::fmt;
+
+/// Bits of the requirement flags in a manifest. Bits this build doesn't
+/// know are requirements it can't meet.
+pub const PRIVATE: u8 = 1;
+pub const CRDT: u8 = 2;
+pub const ROLES: u8 = 4;
+
+/// Requirements this build can meet. CRDT mode is only met by peers started
+/// with `--crdt`, see [`RoomManifest::admit`], and every peer has the same rights.
+const SUPPORTED: u8 = PRIVATE | CRDT;
+
+/// What a room requires of the peers that join it, published by the peer
+/// that created it with `room create`. The room is named after the creator's
+/// peer id and gossipsub signs every message with the publishing peer's key,
+/// so only the creator's manifest is trusted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RoomManifest {
+    /// Requirement flags, see [`PRIVATE`], [`CRDT`] and [`ROLES`].
+    pub flags: u8,
+    /// Largest document the room takes, in bytes.
+    pub max_document_size: Option<u32>,
+    /// blake3 hash of the template the room's first document started as,
+    /// if it was created from one, see [`crate::engine::Engine::create_room`].
+    pub genesis: Option<[u8; 32]>,
+}
+
+/// How a peer takes part in a room, given the room's manifest.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Admission {
+    Full,
+    /// Documents are followed, but local edits are refused and peers drop the ones made anyway.
+    ReadOnly,
+    /// The peer leaves the room.
+    Refused,
+}
+
+impl RoomManifest {
+    /// Whether a peer that joined privately or not, publishes edits as CRDT
+    /// ops or not, and holds documents of up to `largest` bytes, can take
+    /// part in the room, with the reason if not fully. Peers publishing
+    /// diffs only follow CRDT rooms, their edits would diverge.
+    pub fn admit(&self, private: bool, crdt: bool, largest: usize) -> (Admission, Option<String>) {
+        if self.flags & PRIVATE != 0 && !private {
+            return (Admission::Refused, Some("the room must be joined with a passphrase".to_string()));
+        }
+
+        let supported = if crdt { SUPPORTED } else { SUPPORTED & !CRDT };
+        let unsupported = self.flags & !supported;
+        if unsupported != 0 {
+            return (Admission::ReadOnly, Some(format!("this peer doesn't support {}", requirements(unsupported))));
+        }
+
+        match self.max_document_size {
+            Some(max) if largest > max as usize => (Admission::ReadOnly, Some(format!("a document is over the room's limit of {max} bytes"))),
+            _ => (Admission::Full, None),
+        }
+    }
+}
+
+impl fmt::Display for RoomManifest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.flags {
+            0 => write!(f, "no requirements")?,
+            flags => write!(f, "requires {}", requirements(flags))?,
+        }
+
+        if let Some(max) = self.max_document_size {
+            write!(f, ", documents up to {max} bytes")?;
+        }
+
+        match self.genesis {
+            Some(genesis) => write!(f, ", started from template {}", &blake3::Hash::from(genesis).to_hex()[..8]),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for Admission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Admission::Full => write!(f, "full"),
+            Admission::ReadOnly => write!(f, "read-only"),
+            Admission::Refused => write!(f, "refused"),
+        }
+    }
+}
+
+/// The requirements set in `flags`, e.g. `a passphrase, CRDT mode`.
+fn requirements(flags: u8) -> String {
+    let names = [(PRIVATE, "a passphrase"), (CRDT, "CRDT mode"), (ROLES, "roles")];
+    let mut requirements: Vec<_> = names.iter().filter(|(flag, _)| flags & flag != 0).map(|(_, name)| name.to_string()).collect();
+
+    let unknown = flags & !(PRIVATE | CRDT | ROLES);
+    if unknown != 0 {
+        requirements.push(format!("unknown requirements {unknown:#04x}"));
+    }
+
+    requirements.join(", ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn admits_capable_peers() {
+        let manifest = RoomManifest { flags: PRIVATE, max_document_size: Some(100), genesis: None };
+
+        assert_eq!(manifest.admit(true, false, 100).0, Admission::Full);
+        assert_eq!(manifest.admit(false, false, 0).0, Admission::Refused);
+        assert_eq!(manifest.admit(true, false, 101).0, Admission::ReadOnly);
+
+        let manifest = RoomManifest { flags: CRDT | 0x80, max_document_size: None, genesis: Some(*blake3::hash(b"# Standup").as_bytes()) };
+        assert_eq!(manifest.admit(false, false, 0), (Admission::ReadOnly, Some("this peer doesn't support CRDT mode, unknown requirements 0x80".to_string())));
+        assert_eq!(manifest.admit(false, true, 0), (Admission::ReadOnly, Some("this peer doesn't support unknown requirements 0x80".to_string())));
+        assert_eq!(manifest.to_string(), format!("requires CRDT mode, unknown requirements 0x80, started from template {}", &blake3::hash(b"# Standup").to_hex()[..8]));
+
+        let manifest = RoomManifest { flags: CRDT, max_document_size: None, genesis: None };
+        assert_eq!(manifest.admit(false, true, 0).0, Admission::Full);
+    }
+}
@@ -0,0 +1,316 @@
+//! The Matrix bridge, for teams following a session from a Matrix room: the
+//! room's chat is mirrored both ways, and `matrix:snap` posts the active
+//! document there as a file. Only there when built with the `matrix`
+//! feature, otherwise `--matrix` fails saying so. Like telemetry endpoints,
+//! homeservers are reached over plain HTTP, so one served over TLS is
+//! reached through a local proxy such as pantalaimon.
+
+#[cfg(feature = "matrix")]
+use std::{collections::HashMap, time::Duration};
+
+#[cfg(feature = "matrix")]
+use serde::Deserialize;
+#[cfg(feature = "matrix")]
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time
+};
+use tokio::sync::mpsc;
+
+use crate::{error::NotepadError, telemetry::Endpoint};
+#[cfg(feature = "matrix")]
+use crate::output::string;
+
+/// What chat relayed from Matrix starts with, so peers bridging the same
+/// Matrix room don't post it back there.
+pub const RELAYED: &str = "[matrix] ";
+
+/// How long the homeserver holds a sync open waiting for new events.
+#[cfg(feature = "matrix")]
+const SYNC_TIMEOUT: Duration = Duration::from_secs(30);
+/// Wait before syncing again after the homeserver failed to answer.
+#[cfg(feature = "matrix")]
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// A Matrix room chat is bridged with, and the homeserver and access token
+/// of the account that posts there.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bridge {
+    pub homeserver: Endpoint,
+    /// Id of the Matrix room, like `!abc:example.org`.
+    pub room: String,
+    pub token: String,
+}
+
+/// What the bridge posts to the Matrix room.
+#[derive(Debug, PartialEq)]
+pub enum Post {
+    /// A chat line from the room, with who said it.
+    Chat(String),
+    /// A snapshot of a document, uploaded and posted as a file.
+    Snapshot { name: String, text: String },
+}
+
+/// A message posted to the Matrix room by someone else, to relay to the room.
+#[derive(Debug, PartialEq)]
+pub struct Heard {
+    pub sender: String,
+    pub body: String,
+}
+
+/// Bridges chat with the Matrix room until the node exits: `posts` are
+/// posted there, and what others post there arrives on `heard`. Messages
+/// posted before the bridge started aren't relayed.
+#[cfg(feature = "matrix")]
+pub async fn serve(bridge: Bridge, mut posts: mpsc::Receiver<Post>, heard: mpsc::Sender<Heard>) -> Result<(), NotepadError> {
+    let user = bridge.whoami().await?;
+    println!("Bridging chat with Matrix room {} as {user}", bridge.room);
+
+    let listen = async {
+        let mut since = None;
+
+        loop {
+            match bridge.sync(since.as_deref()).await {
+                Ok(sync) => {
+                    // The first sync only marks where relaying starts.
+                    let messages = if since.is_some() { messages(&sync, &bridge.room, &user) } else { Vec::new() };
+                    since = Some(sync.next_batch);
+
+                    for message in messages {
+                        if heard.send(message).await.is_err() {
+                            return;
+                        }
+                    }
+                },
+                Err(e) => {
+                    println!("Matrix bridge error: {e}");
+                    time::sleep(RETRY_DELAY).await;
+                },
+            }
+        }
+    };
+    let post = async {
+        while let Some(post) = posts.recv().await {
+            if let Err(e) = bridge.post(post).await {
+                println!("Matrix bridge error: {e}");
+            }
+        }
+    };
+
+    tokio::join!(listen, post);
+
+    Ok(())
+}
+
+#[cfg(not(feature = "matrix"))]
+pub async fn serve(_bridge: Bridge, _posts: mpsc::Receiver<Post>, _heard: mpsc::Sender<Heard>) -> Result<(), NotepadError> {
+    Err(NotepadError::command("Built without the Matrix bridge, rebuild with `--features matrix`"))
+}
+
+#[cfg(feature = "matrix")]
+#[derive(Debug, Deserialize)]
+struct Whoami {
+    user_id: String,
+}
+
+#[cfg(feature = "matrix")]
+#[derive(Debug, Deserialize)]
+struct Uploaded {
+    content_uri: String,
+}
+
+#[cfg(feature = "matrix")]
+#[derive(Debug, Default, Deserialize)]
+struct Sync {
+    next_batch: String,
+    #[serde(default)]
+    rooms: Rooms,
+}
+
+#[cfg(feature = "matrix")]
+#[derive(Debug, Default, Deserialize)]
+struct Rooms {
+    #[serde(default)]
+    join: HashMap<String, JoinedRoom>,
+}
+
+#[cfg(feature = "matrix")]
+#[derive(Debug, Default, Deserialize)]
+struct JoinedRoom {
+    #[serde(default)]
+    timeline: Timeline,
+}
+
+#[cfg(feature = "matrix")]
+#[derive(Debug, Default, Deserialize)]
+struct Timeline {
+    #[serde(default)]
+    events: Vec<RoomEvent>,
+}
+
+#[cfg(feature = "matrix")]
+#[derive(Debug, Deserialize)]
+struct RoomEvent {
+    #[serde(rename = "type")]
+    kind: String,
+    sender: String,
+    #[serde(default)]
+    content: Content,
+}
+
+#[cfg(feature = "matrix")]
+#[derive(Debug, Default, Deserialize)]
+struct Content {
+    msgtype: Option<String>,
+    body: Option<String>,
+}
+
+#[cfg(feature = "matrix")]
+impl Bridge {
+    async fn whoami(&self) -> Result<String, NotepadError> {
+        let whoami: Whoami = parse(&self.request("GET", "/_matrix/client/v3/account/whoami", "application/json", &[]).await?)?;
+
+        Ok(whoami.user_id)
+    }
+
+    async fn sync(&self, since: Option<&str>) -> Result<Sync, NotepadError> {
+        let mut path = format!("/_matrix/client/v3/sync?timeout={}", SYNC_TIMEOUT.as_millis());
+        if let Some(since) = since {
+            path.push_str(&format!("&since={}", encode(since)));
+        }
+
+        parse(&self.request("GET", &path, "application/json", &[]).await?)
+    }
+
+    async fn post(&self, post: Post) -> Result<(), NotepadError> {
+        let content = match post {
+            Post::Chat(line) => format!("{{\"msgtype\":\"m.text\",\"body\":{}}}", string(&line)),
+            Post::Snapshot { name, text } => {
+                let file = format!("{name}.txt");
+                let path = format!("/_matrix/media/v3/upload?filename={}", encode(&file));
+                let uploaded: Uploaded = parse(&self.request("POST", &path, "text/plain; charset=utf-8", text.as_bytes()).await?)?;
+
+                format!(
+                    "{{\"msgtype\":\"m.file\",\"body\":{},\"url\":{},\"info\":{{\"mimetype\":\"text/plain\",\"size\":{}}}}}",
+                    string(&file), string(&uploaded.content_uri), text.len()
+                )
+            },
+        };
+
+        // Sends aren't retried, so every message takes a transaction id of its own.
+        let path = format!("/_matrix/client/v3/rooms/{}/send/m.room.message/{}", encode(&self.room), rand::random::<u64>());
+        self.request("PUT", &path, "application/json", content.as_bytes()).await?;
+
+        Ok(())
+    }
+
+    /// Makes a request of the homeserver, returning the body of a 2xx answer.
+    /// It is made over HTTP/1.0, so the answer isn't chunked.
+    async fn request(&self, method: &str, path: &str, content_type: &str, body: &[u8]) -> Result<Vec<u8>, NotepadError> {
+        let Endpoint { host, port, path: base } = &self.homeserver;
+        let mut stream = TcpStream::connect((host.as_str(), *port)).await?;
+        let mut request = format!(
+            "{method} {}{path} HTTP/1.0\r\nHost: {host}\r\nAuthorization: Bearer {}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\n\r\n",
+            base.trim_end_matches('/'), self.token, body.len()
+        ).into_bytes();
+        request.extend(body);
+        stream.write_all(&request).await?;
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+
+        let end = response.windows(4).position(|window| window == b"\r\n\r\n").ok_or_else(|| NotepadError::network("Malformed answer from the homeserver"))?;
+        let status = response.split(|&byte| byte == b' ').nth(1).and_then(|status| std::str::from_utf8(status).ok()?.parse::<u16>().ok());
+        let body = response.split_off(end + 4);
+
+        match status {
+            Some(200..=299) => Ok(body),
+            Some(status) => Err(NotepadError::network(format!("Homeserver answered {status}: {}", String::from_utf8_lossy(&body)))),
+            None => Err(NotepadError::network("Malformed answer from the homeserver")),
+        }
+    }
+}
+
+#[cfg(feature = "matrix")]
+fn parse<'a, T: Deserialize<'a>>(body: &'a [u8]) -> Result<T, NotepadError> {
+    serde_json::from_slice(body).map_err(|e| NotepadError::network(format!("Unexpected answer from the homeserver: {e}")))
+}
+
+/// The text messages others posted to `room` in `sync`, in order. The
+/// bridge's own, posted as `user`, are left out.
+#[cfg(feature = "matrix")]
+fn messages(sync: &Sync, room: &str, user: &str) -> Vec<Heard> {
+    let Some(joined) = sync.rooms.join.get(room) else {
+        return Vec::new();
+    };
+
+    joined.timeline.events.iter()
+        .filter(|event| event.kind == "m.room.message" && event.sender != user)
+        .filter(|event| matches!(event.content.msgtype.as_deref(), Some("m.text" | "m.notice" | "m.emote")))
+        .filter_map(|event| Some(Heard { sender: event.sender.clone(), body: event.content.body.clone()? }))
+        .collect()
+}
+
+/// Percent-encodes `s` for a path segment or query value.
+#[cfg(feature = "matrix")]
+fn encode(s: &str) -> String {
+    s.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(all(test, feature = "matrix"))]
+mod test {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn messages_of_others_are_heard() {
+        let sync: Sync = serde_json::from_str(r#"{
+            "next_batch": "s2",
+            "rooms": {"join": {"!room:example.org": {"timeline": {"events": [
+                {"type": "m.room.message", "sender": "@alice:example.org", "content": {"msgtype": "m.text", "body": "hi all"}},
+                {"type": "m.room.message", "sender": "@bridge:example.org", "content": {"msgtype": "m.text", "body": "<bob> hello"}},
+                {"type": "m.room.message", "sender": "@alice:example.org", "content": {"msgtype": "m.image", "body": "cat.png"}},
+                {"type": "m.room.member", "sender": "@carol:example.org", "content": {"membership": "join"}}
+            ]}}}}
+        }"#).unwrap();
+
+        assert_eq!(messages(&sync, "!room:example.org", "@bridge:example.org"), vec![Heard { sender: "@alice:example.org".to_string(), body: "hi all".to_string() }]);
+        assert!(messages(&sync, "!other:example.org", "@bridge:example.org").is_empty());
+    }
+
+    #[test]
+    fn paths_are_encoded() {
+        assert_eq!(encode("!room:example.org"), "%21room%3Aexample.org");
+        assert_eq!(encode("notes v2.txt"), "notes%20v2.txt");
+    }
+
+    #[tokio::test]
+    async fn chat_is_posted() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = vec![0; 1024];
+            let len = stream.read(&mut request).await.unwrap();
+            stream.write_all(b"HTTP/1.0 200 OK\r\n\r\n{\"event_id\":\"$1\"}").await.unwrap();
+            String::from_utf8(request[..len].to_vec()).unwrap()
+        });
+
+        let homeserver = format!("http://127.0.0.1:{port}").parse().unwrap();
+        let bridge = Bridge { homeserver, room: "!room:example.org".to_string(), token: "secret".to_string() };
+        bridge.post(Post::Chat("<bob> hi".to_string())).await.unwrap();
+
+        let request = server.await.unwrap();
+        assert!(request.starts_with("PUT /_matrix/client/v3/rooms/%21room%3Aexample.org/send/m.room.message/"));
+        assert!(request.contains("Authorization: Bearer secret\r\n"));
+        assert!(request.ends_with("{\"msgtype\":\"m.text\",\"body\":\"<bob> hi\"}"));
+    }
+}
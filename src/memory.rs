@@ -0,0 +1,51 @@
+use std::fmt;
+
+/// Approximate bytes held by each part of the engine's state.
+#[derive(Debug, Default, PartialEq)]
+pub struct MemoryUsage {
+    /// Text of every document, archived ones included.
+    pub documents: usize,
+    pub clipboard: usize,
+    /// Attachment metadata and the blobs held locally.
+    pub attachments: usize,
+    /// Recent edit summaries, conflict tracking and latency samples.
+    pub caches: usize,
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> usize {
+        self.documents + self.clipboard + self.attachments + self.caches
+    }
+}
+
+impl fmt::Display for MemoryUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "documents: {}", bytes(self.documents))?;
+        writeln!(f, "clipboard: {}", bytes(self.clipboard))?;
+        writeln!(f, "attachments: {}", bytes(self.attachments))?;
+        writeln!(f, "caches: {}", bytes(self.caches))?;
+        write!(f, "total: {}", bytes(self.total()))
+    }
+}
+
+/// Formats a byte count with a binary unit.
+pub fn bytes(n: usize) -> String {
+    match n {
+        0..1024 => format!("{n} B"),
+        1024..1_048_576 => format!("{:.1} KiB", n as f64 / 1024.0),
+        _ => format!("{:.1} MiB", n as f64 / 1_048_576.0),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn memory_usage_display() {
+        let usage = MemoryUsage { documents: 2048, clipboard: 10, attachments: 0, caches: 3 * 1_048_576 };
+
+        assert_eq!(usage.total(), 2048 + 10 + 3 * 1_048_576);
+        assert_eq!(usage.to_string(), "documents: 2.0 KiB\nclipboard: 10 B\nattachments: 0 B\ncaches: 3.0 MiB\ntotal: 3.0 MiB");
+    }
+}
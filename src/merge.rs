@@ -0,0 +1,104 @@
+use crate::diff::{self, Operation};
+
+/// A change to a span of the base text, in byte offsets of the base.
+#[derive(Debug, Clone, PartialEq)]
+struct Hunk {
+    start: usize,
+    end: usize,
+    text: String,
+}
+
+/// Merges the changes `ours` and `theirs` each made to `base`. Changes to
+/// different spans are both kept. Where they touch the same span the two are
+/// kept one after the other, theirs first, unless one already holds the
+/// other, as when an edit reached the other side before the split.
+pub fn three_way(base: &str, ours: &str, theirs: &str) -> String {
+    let mut ours = hunks(base, ours).into_iter().peekable();
+    let mut theirs = hunks(base, theirs).into_iter().peekable();
+    let mut merged = String::new();
+    let mut at = 0;
+
+    loop {
+        let hunk = match (ours.peek(), theirs.peek()) {
+            (Some(a), Some(b)) if overlap(a, b) => {
+                let (a, b) = (ours.next().unwrap(), theirs.next().unwrap());
+                if (a.start, a.end) == (b.start, b.end) && b.text.contains(&a.text) {
+                    b
+                } else if (a.start, a.end) == (b.start, b.end) && a.text.contains(&b.text) {
+                    a
+                } else {
+                    Hunk { start: a.start.min(b.start), end: a.end.max(b.end), text: b.text + &a.text }
+                }
+            },
+            (Some(a), Some(b)) if b.start < a.start => theirs.next().unwrap(),
+            (Some(_), _) => ours.next().unwrap(),
+            (None, Some(_)) => theirs.next().unwrap(),
+            (None, None) => break,
+        };
+
+        // A hunk widened by a conflict may cover the start of the next ones.
+        let start = hunk.start.max(at);
+        merged.push_str(&base[at..start]);
+        merged.push_str(&hunk.text);
+        at = hunk.end.max(at);
+    }
+
+    merged.push_str(&base[at..]);
+    merged
+}
+
+/// Whether two hunks change the same span, or insert at the same place.
+fn overlap(a: &Hunk, b: &Hunk) -> bool {
+    a.start < b.end && b.start < a.end || a.start == b.start
+}
+
+/// The changes turning `base` into `text`, in order and not overlapping.
+fn hunks(base: &str, text: &str) -> Vec<Hunk> {
+    let mut hunks: Vec<Hunk> = Vec::new();
+    // Bytes inserted less bytes deleted so far, as the diffs are applied in order.
+    let mut shift = 0isize;
+
+    for diff in diff::compute(base, text).messages {
+        let start = (diff.index as isize - shift) as usize;
+        let (end, inserted) = match diff.opcode {
+            Operation::DelRange(len) => {
+                shift -= len as isize;
+                (start + len, String::new())
+            },
+            Operation::InsStr(inserted) => {
+                shift += inserted.len() as isize;
+                (start, inserted)
+            },
+            _ => unreachable!("computed diffs are ranges"),
+        };
+
+        match hunks.last_mut() {
+            Some(last) if last.end == start => {
+                last.end = end;
+                last.text.push_str(&inserted);
+            },
+            _ => hunks.push(Hunk { start, end, text: inserted }),
+        }
+    }
+
+    hunks
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn merges_both_sides() {
+        let base = "one\ntwo\nthree\n";
+
+        assert_eq!(three_way(base, "one!\ntwo\nthree\n", "one\ntwo\nthree 3\n"), "one!\ntwo\nthree 3\n");
+        assert_eq!(three_way(base, "two\nthree\n", "one\ntwo\n"), "two\n");
+        assert_eq!(three_way(base, base, "zero\none\ntwo\nthree\n"), "zero\none\ntwo\nthree\n");
+        // Edits that reached the other side already aren't made twice.
+        assert_eq!(three_way(base, "one\n2\nthree\n", "one\n2\nthree\n"), "one\n2\nthree\n");
+        assert_eq!(three_way(base, "a one\ntwo\nthree\n", "b a one\ntwo\nthree\n"), "b a one\ntwo\nthree\n");
+        // Conflicting edits are both kept.
+        assert_eq!(three_way(base, "one\nTWO\nthree\n", "one\n2\nthree\n"), "one\n2TWO\nthree\n");
+    }
+}
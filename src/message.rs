@@ -0,0 +1,1784 @@
+use std::{
+    io::Read,
+    ops::Range
+};
+
+use libp2p::PeerId;
+
+use crate::{
+    archive::Archive,
+    backfill::Missed,
+    attachment::AttachmentMeta,
+    crdt::{Op, Sequence},
+    directory::RoomListing,
+    diff::{Diff, Diffs, MessageBuf, Run},
+    document::{DocumentMeta, DocumentSettings, LineEnding},
+    error::NotepadError,
+    manifest::{Admission, RoomManifest},
+    seal::NONCE_LEN,
+    session::SessionSummary,
+    users::Signed,
+    varint,
+    whiteboard::BoardOp
+};
+
+/// Everything that is published on a room topic. On the wire each message is
+/// wrapped in an envelope of `MAGIC`, `VERSION` and a type tag, so traffic
+/// from other applications sharing a topic is rejected before it is parsed.
+/// Payloads of at least [`COMPRESS_MIN`] bytes are sent compressed whole,
+/// behind an envelope of their own with the `COMPRESSED` tag.
+#[derive(Debug, PartialEq)]
+pub enum Message {
+    /// Edits to a document. `seq` numbers the edits of the publishing peer so
+    /// they can be applied in order, 0 if the peer doesn't number them.
+    Diffs {
+        document: String,
+        seq: u64,
+        diffs: MessageBuf,
+    },
+    Snapshot(Snapshot),
+    Meta(DocumentMeta),
+    /// Text shared for peers to paste locally, without touching any document.
+    Clipboard(String),
+    /// A line of chat to the room, shown to its peers but never part of a document.
+    Chat(String),
+    /// Heartbeat telling the room this peer is still around.
+    Presence(Presence),
+    /// Asks every peer to echo the id back, to measure how long messages take to arrive.
+    Probe(u64),
+    ProbeAck(u64),
+    /// Announces an attachment that can be requested from the publishing peer.
+    Attachment(AttachmentMeta),
+    /// Sent directly to a peer to download an attachment by hash.
+    AttachmentRequest(String),
+    /// The answer to an `AttachmentRequest`, `None` if the peer doesn't hold it.
+    AttachmentData {
+        hash: String,
+        data: Option<Vec<u8>>,
+    },
+    /// Asks the room host to publish snapshots, sent when rejoining a room.
+    SnapshotRequest,
+    /// Diffs with runs of typing collapsed, smaller than `Diffs` for the same edit.
+    Runs {
+        document: String,
+        seq: u64,
+        runs: Vec<Run>,
+    },
+    /// Sent directly to a directory to list a public room, echoed back once listed.
+    Listing(RoomListing),
+    /// Sent directly to a directory to ask for the rooms listed with it.
+    DirectoryRequest,
+    /// The answer to a `DirectoryRequest`.
+    Directory(Vec<RoomListing>),
+    /// Published when a time-boxed session ends.
+    SessionSummary(SessionSummary),
+    /// Sent directly to a peer by one that just joined or missed edits, to
+    /// catch up on the room. `versions` numbers the last edit the asking
+    /// peer applied from every peer, itself included, empty if it has no
+    /// copies to build on and needs every document whole.
+    SyncRequest {
+        versions: Vec<(PeerId, u64)>,
+    },
+    /// The answer to a `SyncRequest`: every document, the number of the
+    /// last edit the answering peer published and of the last it applied
+    /// from every other peer, so the joiner knows which edits the documents
+    /// already include and skips them if they arrive again. Peers editing
+    /// with the sequence CRDT add its sequence of every document, by id,
+    /// whose replica is left for the joiner to set.
+    Sync {
+        seq: u64,
+        versions: Vec<(PeerId, u64)>,
+        sequences: Vec<(String, Sequence)>,
+        archive: Archive,
+    },
+    /// The answer to a `SyncRequest` from a peer that missed few enough
+    /// edits, sent rather than a `Sync`: the edits it missed, in the order
+    /// the answering peer applied them.
+    Backfill(Vec<Missed>),
+    /// An edit made by one of several users sharing the publishing node, signed with the user's key.
+    Signed(Signed),
+    /// What the room requires of the peers that join it, published by its creator.
+    Manifest(RoomManifest),
+    /// How the publishing peer takes part in the room, given its manifest.
+    ManifestAck(Admission),
+    /// A blake3 hash of a document and the number of the last edit applied
+    /// from each peer, so peers can tell whether their copies match. Peers
+    /// answer with their own, unless it is an answer.
+    Verify {
+        document: String,
+        hash: [u8; 32],
+        versions: Vec<(PeerId, u64)>,
+        answer: bool,
+    },
+    /// Grants `peer` write access to the room, or revokes it, only followed
+    /// when published by the room's creator, see [`crate::manifest`].
+    Permission {
+        peer: PeerId,
+        write: bool,
+    },
+    /// Sent directly to a backup peer with framed records of an operation
+    /// log to append to the owner's copy, if the copy is `offset` bytes long.
+    /// Also the answer to a `BackupRequest`, holding the whole copy.
+    Backup {
+        owner: String,
+        log: String,
+        offset: Option<u64>,
+        frames: Vec<u8>,
+    },
+    /// Sent directly to a backup peer to fetch the owner's copy of a log.
+    BackupRequest {
+        owner: String,
+        log: String,
+    },
+    /// The answer to a `Backup`, with the length of the copy it was appended to.
+    BackupAck {
+        log: String,
+        len: u64,
+    },
+    /// Where a peer's cursor is in a document, published on the room's
+    /// cursor topic every so often while it edits.
+    Cursor {
+        document: String,
+        index: u64,
+    },
+    /// Takes the lock on a document for turn-taking, or releases it, see
+    /// [`Locks`](crate::lock::Locks). Published again with every heartbeat
+    /// while held.
+    Lock {
+        document: String,
+        held: bool,
+    },
+    /// Claims the bytes `range` of a document as being edited by the
+    /// publishing peer, for others to be warned of editing them too, or
+    /// releases its claim if `None`. Published again with every heartbeat
+    /// while held, moved along with the edits before it.
+    Claim {
+        document: String,
+        range: Option<Range<u64>>,
+    },
+    /// Acknowledges that the publishing peer applied the edits of `author`
+    /// up to the one numbered `seq`, for the author to learn its edits
+    /// arrived. Only sent by peers that turned acknowledgements on.
+    Applied {
+        author: PeerId,
+        seq: u64,
+    },
+    /// Like a `Verify` that isn't answered, published every
+    /// [`REPAIR_INTERVAL`](crate::engine::REPAIR_INTERVAL) in the background
+    /// for peers to repair copies that stay apart.
+    Digest {
+        document: String,
+        hash: [u8; 32],
+        versions: Vec<(PeerId, u64)>,
+    },
+    /// Edits to a document as ops of its sequence CRDT, published instead
+    /// of `Diffs` by peers with [`Engine::crdt`](crate::engine::Engine::crdt)
+    /// on, numbered the same way.
+    Ops {
+        document: String,
+        seq: u64,
+        ops: Vec<Op>,
+    },
+    /// Strokes drawn on or erased from a whiteboard of the room.
+    Board {
+        board: String,
+        ops: Vec<BoardOp>,
+    },
+    /// Another message encrypted with the key of a private room, see [`RoomKey`](crate::seal::RoomKey).
+    Sealed {
+        nonce: [u8; NONCE_LEN],
+        ciphertext: Vec<u8>,
+    },
+}
+
+/// The full text of a document, compressed on the wire behind a blake3 hash
+/// of the text so corruption is caught rather than adopted.
+#[derive(Debug, PartialEq)]
+pub struct Snapshot {
+    pub document: String,
+    pub text: String,
+}
+
+/// A heartbeat announcing the nickname the peer goes by, if any, whether
+/// its user has stepped away, where it last read, if it has an editor, and
+/// what it selected for the others to look at. Also published straight
+/// away when the selection changes.
+#[derive(Debug, PartialEq)]
+pub struct Presence {
+    pub nickname: Option<String>,
+    pub away: bool,
+    pub reading: Option<Reading>,
+    pub selection: Option<Selection>,
+}
+
+/// The lines of a document a peer last had on screen, from 0.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reading {
+    pub document: String,
+    pub lines: Range<usize>,
+}
+
+/// Text of a document a peer selected, by byte offsets, never empty.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Selection {
+    pub document: String,
+    pub range: Range<usize>,
+}
+
+/// Every payload published by the notepad starts with these bytes.
+const MAGIC: &[u8; 2] = b"PN";
+/// Bumped whenever the encoding of an existing message type changes, while
+/// new message types only take a new tag. Payloads of a newer version, or
+/// with a tag past `LAST_TAG`, are never guessed at but ignored with a
+/// warning, and aren't held against the peer, see [`check`].
+pub const VERSION: u8 = 1;
+/// Start of the gossipsub topics of rooms, followed by the wire format
+/// version, see [`topic_prefix`].
+const NAMESPACE: &str = "p2p-notepad/v";
+
+/// Diffs in the fixed layout with single byte indices, only decoded for older peers.
+const FIXED_DIFFS: u8 = 0;
+const SNAPSHOT: u8 = 1;
+const META: u8 = 2;
+const CLIPBOARD: u8 = 3;
+const PRESENCE: u8 = 4;
+const PROBE: u8 = 5;
+const PROBE_ACK: u8 = 6;
+const ATTACHMENT: u8 = 7;
+const ATTACHMENT_REQUEST: u8 = 8;
+const ATTACHMENT_DATA: u8 = 9;
+const SNAPSHOT_REQUEST: u8 = 10;
+const RUNS: u8 = 11;
+const DIFFS: u8 = 12;
+const LISTING: u8 = 13;
+const DIRECTORY_REQUEST: u8 = 14;
+const DIRECTORY: u8 = 15;
+const SESSION_SUMMARY: u8 = 16;
+const SYNC_REQUEST: u8 = 17;
+/// A sync without the versions of other peers, only decoded for older peers.
+const SYNC: u8 = 18;
+const SIGNED: u8 = 19;
+const MANIFEST: u8 = 20;
+const MANIFEST_ACK: u8 = 21;
+const BACKUP: u8 = 22;
+const BACKUP_REQUEST: u8 = 23;
+const BACKUP_ACK: u8 = 24;
+const CURSOR: u8 = 25;
+const SEALED: u8 = 26;
+const BOARD: u8 = 27;
+const PERMISSION: u8 = 28;
+const VERIFY: u8 = 29;
+/// Not a message of its own, followed by a whole payload compressed with zstd.
+const COMPRESSED: u8 = 30;
+const CHAT: u8 = 31;
+const VERSIONED_SYNC: u8 = 32;
+const LOCK: u8 = 33;
+const CLAIM: u8 = 34;
+const APPLIED: u8 = 35;
+const DIGEST: u8 = 36;
+const OPS: u8 = 37;
+/// A versioned sync followed by sequences, before the archive.
+const SEQUENCED_SYNC: u8 = 38;
+/// Diffs serialized with postcard, so `Diff` can take new fields and
+/// operations without older peers misreading them, see [`Diff::push_postcard`].
+/// `DIFFS` in the varint layout is still decoded for older peers.
+const POSTCARD_DIFFS: u8 = 39;
+const BACKFILL: u8 = 40;
+/// Tags past this one are of messages added after this build.
+const LAST_TAG: u8 = BACKFILL;
+
+/// Payloads at least this long, such as large pastes, imports and syncs,
+/// are compressed, as long as that makes them shorter.
+pub const COMPRESS_MIN: usize = 4 * 1024;
+/// Longest a compressed payload may expand to, so that a small one can't
+/// claim a lot of memory.
+const MAX_DECOMPRESSED: u64 = 16 * 1024 * 1024;
+
+/// Kinds of run in a `Runs` message.
+const RUN_DIFF: u8 = 0;
+const RUN_INSERT: u8 = 1;
+const RUN_DELETE: u8 = 2;
+
+/// Bits of the document flags byte in a `Meta` message.
+const ARCHIVED: u8 = 1;
+const CRLF: u8 = 2;
+
+/// Bits of the flags byte after the nickname in a `Presence` message. Peers
+/// that predate it send no flags byte, which reads as not away. `READING`
+/// is followed by the document and the first and end line, as varints,
+/// then `SELECTION` by the document and the start and end byte offset.
+const AWAY: u8 = 1;
+const READING: u8 = 2;
+const SELECTION: u8 = 4;
+
+impl TryFrom<Vec<u8>> for Message {
+    type Error = NotepadError;
+
+    fn try_from(data: Vec<u8>) -> Result<Self, Self::Error> {
+        Message::try_from(data.as_slice())
+    }
+}
+
+impl TryFrom<&[u8]> for Message {
+    type Error = NotepadError;
+
+    #[tracing::instrument(name = "decode", level = "debug", skip_all, fields(len = data.len()), err(level = "debug"))]
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        let (tag, data) = split_envelope(data)?;
+
+        match tag {
+            COMPRESSED => {
+                let data = decompress(data)?;
+
+                match split_envelope(&data)? {
+                    (COMPRESSED, _) => Err(NotepadError::Decode("Payload is compressed twice")),
+                    _ => Message::try_from(data.as_slice()),
+                }
+            },
+            DIFFS | POSTCARD_DIFFS => {
+                let (document, data) = split_str(data)?;
+                let (seq, data) = varint::split(data)?;
+                let diffs = if tag == DIFFS { MessageBuf::decode(data)? } else { MessageBuf::decode_postcard(data)? };
+
+                Ok(Message::Diffs { document, seq: seq as u64, diffs })
+            },
+            FIXED_DIFFS => {
+                let (document, data) = split_str(data)?;
+
+                Ok(Message::Diffs { document, seq: 0, diffs: MessageBuf::decode_fixed(data)? })
+            },
+            SNAPSHOT => {
+                let (document, data) = split_str(data)?;
+                let (hash, data) = data.split_first_chunk::<{ blake3::OUT_LEN }>().ok_or(NotepadError::Decode("Missing snapshot hash"))?;
+                let text = decompress(data)?;
+
+                if blake3::hash(&text) != *hash {
+                    return Err(NotepadError::Integrity("Snapshot doesn't match its hash"));
+                }
+
+                let text = String::from_utf8(text).map_err(|_| NotepadError::Decode("Snapshot is not valid UTF-8"))?;
+
+                Ok(Message::Snapshot(Snapshot { document, text }))
+            },
+            META => {
+                let (document, data) = split_str(data)?;
+                let (name, data) = split_str(data)?;
+                let (flags, data) = match data {
+                    [flags, data @ ..] if flags & !(ARCHIVED | CRLF) == 0 => (flags, data),
+                    _ => return Err(NotepadError::Decode("Invalid document flags")),
+                };
+                let line_ending = if flags & CRLF != 0 { LineEnding::Crlf } else { LineEnding::Lf };
+                let settings = match data {
+                    [] => DocumentSettings::default(),
+                    data => {
+                        let (language, data) = split_str(data)?;
+                        let [tab_width, wrap @ ..] = data else {
+                            return Err(NotepadError::Decode("Invalid document settings"));
+                        };
+                        let wrap = u16::from_le_bytes(wrap.try_into().map_err(|_| NotepadError::Decode("Invalid document settings"))?);
+
+                        DocumentSettings { language, tab_width: *tab_width, wrap: (wrap > 0).then_some(wrap) }
+                    },
+                };
+
+                Ok(Message::Meta(DocumentMeta { id: document, name, archived: flags & ARCHIVED != 0, line_ending, settings }))
+            },
+            CLIPBOARD => {
+                let text = String::from_utf8(data.to_vec()).map_err(|_| NotepadError::Decode("Clipboard is not valid UTF-8"))?;
+
+                Ok(Message::Clipboard(text))
+            },
+            CHAT => {
+                let text = String::from_utf8(data.to_vec()).map_err(|_| NotepadError::Decode("Chat is not valid UTF-8"))?;
+
+                Ok(Message::Chat(text))
+            },
+            PRESENCE => {
+                let (nickname, data) = split_str(data)?;
+                let (flags, data) = match data.split_first() {
+                    None => (0, data),
+                    Some((&flags, data)) if flags & !(AWAY | READING | SELECTION) == 0 => (flags, data),
+                    _ => return Err(NotepadError::Decode("Invalid presence")),
+                };
+
+                let (reading, data) = if flags & READING != 0 {
+                    let (document, data) = split_str(data)?;
+                    let (start, data) = varint::split(data)?;
+                    let (end, data) = varint::split(data)?;
+
+                    (Some(Reading { document, lines: start..end }), data)
+                } else {
+                    (None, data)
+                };
+                let (selection, data) = if flags & SELECTION != 0 {
+                    let (document, data) = split_str(data)?;
+                    let (start, data) = varint::split(data)?;
+                    let (end, data) = varint::split(data)?;
+
+                    (Some(Selection { document, range: start..end }), data)
+                } else {
+                    (None, data)
+                };
+                if !data.is_empty()
+                    || reading.as_ref().is_some_and(|reading| reading.lines.is_empty())
+                    || selection.as_ref().is_some_and(|selection| selection.range.is_empty())
+                {
+                    return Err(NotepadError::Decode("Invalid presence"));
+                }
+
+                Ok(Message::Presence(Presence {
+                    nickname: Some(nickname).filter(|nickname| !nickname.is_empty()),
+                    away: flags & AWAY != 0,
+                    reading,
+                    selection,
+                }))
+            },
+            PROBE | PROBE_ACK => {
+                let id = u64::from_le_bytes(data.try_into().map_err(|_| NotepadError::Decode("Invalid probe id"))?);
+
+                Ok(if tag == PROBE { Message::Probe(id) } else { Message::ProbeAck(id) })
+            },
+            ATTACHMENT => {
+                let (hash, data) = split_str(data)?;
+                let (name, data) = split_str(data)?;
+                let size = u32::from_le_bytes(data.try_into().map_err(|_| NotepadError::Decode("Invalid attachment size"))?);
+
+                Ok(Message::Attachment(AttachmentMeta { hash, name, size }))
+            },
+            ATTACHMENT_REQUEST => {
+                let (hash, data) = split_str(data)?;
+
+                if !data.is_empty() {
+                    return Err(NotepadError::Decode("Invalid attachment request"));
+                }
+
+                Ok(Message::AttachmentRequest(hash))
+            },
+            ATTACHMENT_DATA => {
+                let (hash, data) = split_str(data)?;
+                let data = match data.split_first() {
+                    Some((0, [])) => None,
+                    Some((1, data)) => Some(data.to_vec()),
+                    _ => return Err(NotepadError::Decode("Invalid attachment data")),
+                };
+
+                Ok(Message::AttachmentData { hash, data })
+            },
+            SNAPSHOT_REQUEST if data.is_empty() => Ok(Message::SnapshotRequest),
+            SNAPSHOT_REQUEST => Err(NotepadError::Decode("Invalid snapshot request")),
+            RUNS => {
+                let (document, data) = split_str(data)?;
+                let (seq, mut data) = varint::split(data)?;
+                let mut runs = Vec::new();
+
+                while let Some((&kind, rest)) = data.split_first() {
+                    let (run, rest) = match kind {
+                        RUN_DIFF => {
+                            let (diff, rest) = Diff::decode(rest)?;
+                            (Run::Diff(diff), rest)
+                        },
+                        RUN_INSERT => {
+                            let (index, rest) = varint::split(rest)?;
+                            let (text, rest) = split_str(rest)?;
+                            (Run::Insert { index, text }, rest)
+                        },
+                        RUN_DELETE => {
+                            let (index, rest) = varint::split(rest)?;
+                            let (count, rest) = varint::split(rest)?;
+                            (Run::Delete { index, count }, rest)
+                        },
+                        _ => return Err(NotepadError::Decode("Invalid run")),
+                    };
+
+                    runs.push(run);
+                    data = rest;
+                }
+
+                Ok(Message::Runs { document, seq: seq as u64, runs })
+            },
+            LISTING => match split_listing(data)? {
+                (listing, []) => Ok(Message::Listing(listing)),
+                _ => Err(NotepadError::Decode("Invalid listing")),
+            },
+            DIRECTORY_REQUEST if data.is_empty() => Ok(Message::DirectoryRequest),
+            DIRECTORY_REQUEST => Err(NotepadError::Decode("Invalid directory request")),
+            DIRECTORY => {
+                let mut data = data;
+                let mut listings = Vec::new();
+
+                while !data.is_empty() {
+                    let (listing, rest) = split_listing(data)?;
+                    listings.push(listing);
+                    data = rest;
+                }
+
+                Ok(Message::Directory(listings))
+            },
+            SESSION_SUMMARY => {
+                let (document, data) = split_str(data)?;
+                let (participants, data) = varint::split(data)?;
+                let (ops, data) = varint::split(data)?;
+                let (checksum, data) = split_str(data)?;
+
+                if !data.is_empty() {
+                    return Err(NotepadError::Decode("Invalid session summary"));
+                }
+
+                let participants = participants.try_into().map_err(|_| NotepadError::Decode("Invalid participant count"))?;
+                let ops = ops.try_into().map_err(|_| NotepadError::Decode("Invalid op count"))?;
+
+                Ok(Message::SessionSummary(SessionSummary { document, participants, ops, checksum }))
+            },
+            SYNC_REQUEST => Ok(Message::SyncRequest { versions: split_versions(data)? }),
+            SYNC => {
+                let (seq, data) = varint::split(data)?;
+
+                Ok(Message::Sync { seq: seq as u64, versions: Vec::new(), sequences: Vec::new(), archive: Archive::decode(data, None)? })
+            },
+            VERSIONED_SYNC | SEQUENCED_SYNC => {
+                let (seq, data) = varint::split(data)?;
+                let (count, mut data) = varint::split(data)?;
+                let mut versions = Vec::new();
+
+                for _ in 0..count {
+                    let (peer, rest) = split_bytes(data)?;
+                    let (seq, rest) = varint::split(rest)?;
+                    let peer = PeerId::from_bytes(&peer).map_err(|_| NotepadError::Decode("Invalid sync peer id"))?;
+
+                    versions.push((peer, seq as u64));
+                    data = rest;
+                }
+
+                let mut sequences = Vec::new();
+                if tag == SEQUENCED_SYNC {
+                    let (count, rest) = varint::split(data)?;
+                    data = rest;
+
+                    for _ in 0..count {
+                        let (document, rest) = split_str(data)?;
+                        let (sequence, rest) = split_bytes(rest)?;
+
+                        sequences.push((document, Sequence::decode(0, &sequence)?));
+                        data = rest;
+                    }
+                }
+
+                Ok(Message::Sync { seq: seq as u64, versions, sequences, archive: Archive::decode(data, None)? })
+            },
+            BACKFILL => {
+                let mut missed = Vec::new();
+                let mut data = data;
+
+                while !data.is_empty() {
+                    let (peer, rest) = split_bytes(data)?;
+                    let (seq, rest) = varint::split(rest)?;
+                    let (document, rest) = split_str(rest)?;
+                    let (author, rest) = split_bytes(rest)?;
+                    let (diffs, rest) = split_bytes(rest)?;
+
+                    let peer = PeerId::from_bytes(&peer).map_err(|_| NotepadError::Decode("Invalid backfill peer id"))?;
+                    let author = if author.is_empty() {
+                        None
+                    } else {
+                        Some(PeerId::from_bytes(&author).map_err(|_| NotepadError::Decode("Invalid backfill author"))?)
+                    };
+
+                    missed.push(Missed { peer, seq: seq as u64, document, diffs: MessageBuf::decode_postcard(&diffs)?, author });
+                    data = rest;
+                }
+
+                Ok(Message::Backfill(missed))
+            },
+            SIGNED => {
+                let (public_key, data) = split_bytes(data)?;
+                let (nickname, data) = split_str(data)?;
+                let (signature, data) = split_bytes(data)?;
+
+                Ok(Message::Signed(Signed { public_key, nickname, signature, payload: data.to_vec() }))
+            },
+            MANIFEST => {
+                let [flags, data @ ..] = data else {
+                    return Err(NotepadError::Decode("Invalid room manifest"));
+                };
+                let (max_document_size, data) = varint::split(data)?;
+                // Manifests of rooms not made from a template end here.
+                let genesis = match data {
+                    [] => None,
+                    hash => Some(hash.try_into().map_err(|_| NotepadError::Decode("Invalid room manifest"))?),
+                };
+
+                let max_document_size = max_document_size.try_into().map_err(|_| NotepadError::Decode("Invalid document size"))?;
+
+                Ok(Message::Manifest(RoomManifest { flags: *flags, max_document_size: (max_document_size > 0).then_some(max_document_size), genesis }))
+            },
+            MANIFEST_ACK => match data {
+                [0] => Ok(Message::ManifestAck(Admission::Full)),
+                [1] => Ok(Message::ManifestAck(Admission::ReadOnly)),
+                [2] => Ok(Message::ManifestAck(Admission::Refused)),
+                _ => Err(NotepadError::Decode("Invalid manifest acknowledgement")),
+            },
+            BACKUP => {
+                let (owner, data) = split_str(data)?;
+                let (log, data) = split_str(data)?;
+                let (offset, data) = varint::split(data)?;
+
+                Ok(Message::Backup { owner, log, offset: offset.checked_sub(1).map(|offset| offset as u64), frames: data.to_vec() })
+            },
+            BACKUP_REQUEST => {
+                let (owner, data) = split_str(data)?;
+                let (log, data) = split_str(data)?;
+
+                if !data.is_empty() {
+                    return Err(NotepadError::Decode("Invalid backup request"));
+                }
+
+                Ok(Message::BackupRequest { owner, log })
+            },
+            BACKUP_ACK => {
+                let (log, data) = split_str(data)?;
+                let (len, data) = varint::split(data)?;
+
+                if !data.is_empty() {
+                    return Err(NotepadError::Decode("Invalid backup acknowledgement"));
+                }
+
+                Ok(Message::BackupAck { log, len: len as u64 })
+            },
+            CURSOR => {
+                let (document, data) = split_str(data)?;
+                let (index, data) = varint::split(data)?;
+
+                if !data.is_empty() {
+                    return Err(NotepadError::Decode("Invalid cursor"));
+                }
+
+                Ok(Message::Cursor { document, index: index as u64 })
+            },
+            LOCK => {
+                let (document, data) = split_str(data)?;
+
+                match data {
+                    [held @ (0 | 1)] => Ok(Message::Lock { document, held: *held == 1 }),
+                    _ => Err(NotepadError::Decode("Invalid lock")),
+                }
+            },
+            CLAIM => {
+                let (document, data) = split_str(data)?;
+                if data.is_empty() {
+                    return Ok(Message::Claim { document, range: None });
+                }
+
+                let (start, data) = varint::split(data)?;
+                let (end, data) = varint::split(data)?;
+                if !data.is_empty() || start > end {
+                    return Err(NotepadError::Decode("Invalid claim"));
+                }
+
+                Ok(Message::Claim { document, range: Some(start as u64..end as u64) })
+            },
+            APPLIED => {
+                let (seq, author) = varint::split(data)?;
+                let author = PeerId::from_bytes(author).map_err(|_| NotepadError::Decode("Invalid acknowledged author"))?;
+
+                Ok(Message::Applied { author, seq: seq as u64 })
+            },
+            SEALED => {
+                let (nonce, data) = data.split_first_chunk::<NONCE_LEN>().ok_or(NotepadError::Decode("Missing sealed message nonce"))?;
+
+                Ok(Message::Sealed { nonce: *nonce, ciphertext: data.to_vec() })
+            },
+            BOARD => {
+                let (board, mut data) = split_str(data)?;
+                let mut ops = Vec::new();
+
+                while !data.is_empty() {
+                    let (op, rest) = BoardOp::decode(data)?;
+                    ops.push(op);
+                    data = rest;
+                }
+
+                Ok(Message::Board { board, ops })
+            },
+            PERMISSION => match data.split_first() {
+                Some((&write @ (0 | 1), peer)) => {
+                    let peer = PeerId::from_bytes(peer).map_err(|_| NotepadError::Decode("Invalid permission peer id"))?;
+
+                    Ok(Message::Permission { peer, write: write == 1 })
+                },
+                _ => Err(NotepadError::Decode("Invalid permission")),
+            },
+            VERIFY => {
+                let (document, data) = split_str(data)?;
+                let (hash, data) = data.split_first_chunk::<32>().ok_or(NotepadError::Decode("Missing document hash"))?;
+                let (answer, data) = match data.split_first() {
+                    Some((&answer @ (0 | 1), data)) => (answer == 1, data),
+                    _ => return Err(NotepadError::Decode("Invalid verification")),
+                };
+
+                Ok(Message::Verify { document, hash: *hash, versions: split_versions(data)?, answer })
+            },
+            DIGEST => {
+                let (document, data) = split_str(data)?;
+                let (hash, data) = data.split_first_chunk::<32>().ok_or(NotepadError::Decode("Missing document hash"))?;
+
+                Ok(Message::Digest { document, hash: *hash, versions: split_versions(data)? })
+            },
+            OPS => {
+                let (document, data) = split_str(data)?;
+                let (seq, mut data) = varint::split(data)?;
+                let mut ops = Vec::new();
+
+                while !data.is_empty() {
+                    let (op, rest) = Op::decode(data)?;
+                    ops.push(op);
+                    data = rest;
+                }
+
+                Ok(Message::Ops { document, seq: seq as u64, ops })
+            },
+            _ => Err(NotepadError::Decode("Invalid message tag byte")),
+        }
+    }
+}
+
+impl From<Message> for Vec<u8> {
+    #[tracing::instrument(name = "encode", level = "debug", skip_all)]
+    fn from(message: Message) -> Self {
+        let mut data = MAGIC.to_vec();
+        data.push(VERSION);
+
+        match message {
+            Message::Diffs { document, seq, diffs } => {
+                data.push(POSTCARD_DIFFS);
+                push_str(&mut data, &document);
+                varint::push(&mut data, seq as usize);
+                for diff in &diffs.messages {
+                    diff.push_postcard(&mut data);
+                }
+            },
+            Message::Snapshot(Snapshot { document, text }) => {
+                data.push(SNAPSHOT);
+                push_str(&mut data, &document);
+                data.extend(blake3::hash(text.as_bytes()).as_bytes());
+                data.extend(zstd::encode_all(text.as_bytes(), 0).expect("compressing into memory can't fail"));
+            },
+            Message::Meta(DocumentMeta { id, name, archived, line_ending, settings }) => {
+                let crlf = line_ending == LineEnding::Crlf;
+
+                data.push(META);
+                push_str(&mut data, &id);
+                push_str(&mut data, &name);
+                data.push(if archived { ARCHIVED } else { 0 } | if crlf { CRLF } else { 0 });
+
+                // Left out when default, so peers that predate settings can still read the metadata.
+                if settings != DocumentSettings::default() {
+                    push_str(&mut data, &settings.language);
+                    data.push(settings.tab_width);
+                    data.extend(settings.wrap.unwrap_or(0).to_le_bytes());
+                }
+            },
+            Message::Clipboard(text) => {
+                data.push(CLIPBOARD);
+                data.extend(text.into_bytes());
+            },
+            Message::Chat(text) => {
+                data.push(CHAT);
+                data.extend(text.into_bytes());
+            },
+            Message::Presence(Presence { nickname, away, reading, selection }) => {
+                data.push(PRESENCE);
+                push_str(&mut data, nickname.as_deref().unwrap_or_default());
+                data.push(if away { AWAY } else { 0 } | if reading.is_some() { READING } else { 0 } | if selection.is_some() { SELECTION } else { 0 });
+
+                if let Some(Reading { document, lines }) = reading {
+                    push_str(&mut data, &document);
+                    varint::push(&mut data, lines.start);
+                    varint::push(&mut data, lines.end);
+                }
+                if let Some(Selection { document, range }) = selection {
+                    push_str(&mut data, &document);
+                    varint::push(&mut data, range.start);
+                    varint::push(&mut data, range.end);
+                }
+            },
+            Message::Probe(id) => {
+                data.push(PROBE);
+                data.extend(id.to_le_bytes());
+            },
+            Message::ProbeAck(id) => {
+                data.push(PROBE_ACK);
+                data.extend(id.to_le_bytes());
+            },
+            Message::Attachment(AttachmentMeta { hash, name, size }) => {
+                data.push(ATTACHMENT);
+                push_str(&mut data, &hash);
+                push_str(&mut data, &name);
+                data.extend(size.to_le_bytes());
+            },
+            Message::AttachmentRequest(hash) => {
+                data.push(ATTACHMENT_REQUEST);
+                push_str(&mut data, &hash);
+            },
+            Message::AttachmentData { hash, data: blob } => {
+                data.push(ATTACHMENT_DATA);
+                push_str(&mut data, &hash);
+                match blob {
+                    Some(blob) => {
+                        data.push(1);
+                        data.extend(blob);
+                    },
+                    None => data.push(0),
+                }
+            },
+            Message::SnapshotRequest => data.push(SNAPSHOT_REQUEST),
+            Message::Runs { document, seq, runs } => {
+                data.push(RUNS);
+                push_str(&mut data, &document);
+                varint::push(&mut data, seq as usize);
+
+                for run in runs {
+                    match run {
+                        Run::Diff(diff) => {
+                            data.push(RUN_DIFF);
+                            diff.encode(&mut data);
+                        },
+                        Run::Insert { index, text } => {
+                            data.push(RUN_INSERT);
+                            varint::push(&mut data, index);
+                            push_str(&mut data, &text);
+                        },
+                        Run::Delete { index, count } => {
+                            data.push(RUN_DELETE);
+                            varint::push(&mut data, index);
+                            varint::push(&mut data, count);
+                        },
+                    }
+                }
+            },
+            Message::Listing(listing) => {
+                data.push(LISTING);
+                push_listing(&mut data, &listing);
+            },
+            Message::DirectoryRequest => data.push(DIRECTORY_REQUEST),
+            Message::Directory(listings) => {
+                data.push(DIRECTORY);
+
+                for listing in &listings {
+                    push_listing(&mut data, listing);
+                }
+            },
+            Message::SessionSummary(SessionSummary { document, participants, ops, checksum }) => {
+                data.push(SESSION_SUMMARY);
+                push_str(&mut data, &document);
+                varint::push(&mut data, participants as usize);
+                varint::push(&mut data, ops as usize);
+                push_str(&mut data, &checksum);
+            },
+            Message::SyncRequest { versions } => {
+                data.push(SYNC_REQUEST);
+                push_versions(&mut data, versions);
+            },
+            Message::Sync { seq, versions, sequences, archive } => {
+                data.push(if sequences.is_empty() { VERSIONED_SYNC } else { SEQUENCED_SYNC });
+                varint::push(&mut data, seq as usize);
+                varint::push(&mut data, versions.len());
+                for (peer, seq) in versions {
+                    let peer = peer.to_bytes();
+                    varint::push(&mut data, peer.len());
+                    data.extend(peer);
+                    varint::push(&mut data, seq as usize);
+                }
+                if !sequences.is_empty() {
+                    varint::push(&mut data, sequences.len());
+                    for (document, sequence) in sequences {
+                        push_str(&mut data, &document);
+                        let sequence = sequence.encode();
+                        varint::push(&mut data, sequence.len());
+                        data.extend(sequence);
+                    }
+                }
+                data.extend(archive.encode(None));
+            },
+            Message::Backfill(missed) => {
+                data.push(BACKFILL);
+
+                for Missed { peer, seq, document, diffs, author } in missed {
+                    let peer = peer.to_bytes();
+                    varint::push(&mut data, peer.len());
+                    data.extend(peer);
+                    varint::push(&mut data, seq as usize);
+                    push_str(&mut data, &document);
+                    let author = author.map(|author| author.to_bytes()).unwrap_or_default();
+                    varint::push(&mut data, author.len());
+                    data.extend(author);
+
+                    let mut encoded = Vec::new();
+                    for diff in &diffs.messages {
+                        diff.push_postcard(&mut encoded);
+                    }
+                    varint::push(&mut data, encoded.len());
+                    data.extend(encoded);
+                }
+            },
+            Message::Signed(Signed { public_key, nickname, signature, payload }) => {
+                data.push(SIGNED);
+                varint::push(&mut data, public_key.len());
+                data.extend(public_key);
+                push_str(&mut data, &nickname);
+                varint::push(&mut data, signature.len());
+                data.extend(signature);
+                data.extend(payload);
+            },
+            Message::Manifest(RoomManifest { flags, max_document_size, genesis }) => {
+                data.push(MANIFEST);
+                data.push(flags);
+                varint::push(&mut data, max_document_size.unwrap_or(0) as usize);
+                if let Some(genesis) = genesis {
+                    data.extend(genesis);
+                }
+            },
+            Message::ManifestAck(admission) => {
+                data.push(MANIFEST_ACK);
+                data.push(match admission {
+                    Admission::Full => 0,
+                    Admission::ReadOnly => 1,
+                    Admission::Refused => 2,
+                });
+            },
+            Message::Backup { owner, log, offset, frames } => {
+                data.push(BACKUP);
+                push_str(&mut data, &owner);
+                push_str(&mut data, &log);
+                varint::push(&mut data, offset.map_or(0, |offset| offset as usize + 1));
+                data.extend(frames);
+            },
+            Message::BackupRequest { owner, log } => {
+                data.push(BACKUP_REQUEST);
+                push_str(&mut data, &owner);
+                push_str(&mut data, &log);
+            },
+            Message::BackupAck { log, len } => {
+                data.push(BACKUP_ACK);
+                push_str(&mut data, &log);
+                varint::push(&mut data, len as usize);
+            },
+            Message::Cursor { document, index } => {
+                data.push(CURSOR);
+                push_str(&mut data, &document);
+                varint::push(&mut data, index as usize);
+            },
+            Message::Lock { document, held } => {
+                data.push(LOCK);
+                push_str(&mut data, &document);
+                data.push(held as u8);
+            },
+            Message::Claim { document, range } => {
+                data.push(CLAIM);
+                push_str(&mut data, &document);
+                if let Some(range) = range {
+                    varint::push(&mut data, range.start as usize);
+                    varint::push(&mut data, range.end as usize);
+                }
+            },
+            Message::Applied { author, seq } => {
+                data.push(APPLIED);
+                varint::push(&mut data, seq as usize);
+                data.extend(author.to_bytes());
+            },
+            Message::Sealed { nonce, ciphertext } => {
+                data.push(SEALED);
+                data.extend(nonce);
+                data.extend(ciphertext);
+            },
+            Message::Board { board, ops } => {
+                data.push(BOARD);
+                push_str(&mut data, &board);
+                for op in &ops {
+                    op.encode(&mut data);
+                }
+            },
+            Message::Verify { document, hash, versions, answer } => {
+                data.push(VERIFY);
+                push_str(&mut data, &document);
+                data.extend(hash);
+                data.push(answer as u8);
+                push_versions(&mut data, versions);
+            },
+            Message::Digest { document, hash, versions } => {
+                data.push(DIGEST);
+                push_str(&mut data, &document);
+                data.extend(hash);
+                push_versions(&mut data, versions);
+            },
+            Message::Ops { document, seq, ops } => {
+                data.push(OPS);
+                push_str(&mut data, &document);
+                varint::push(&mut data, seq as usize);
+                for op in ops {
+                    op.encode(&mut data);
+                }
+            },
+            Message::Permission { peer, write } => {
+                data.push(PERMISSION);
+                data.push(write as u8);
+                data.extend(peer.to_bytes());
+            },
+        }
+
+        compress(data)
+    }
+}
+
+/// Wraps a payload of at least [`COMPRESS_MIN`] bytes into a compressed one
+/// if that is shorter. Snapshots are compressed already and sealed payloads
+/// don't compress, so those are left as they are.
+fn compress(data: Vec<u8>) -> Vec<u8> {
+    if data.len() < COMPRESS_MIN || matches!(split_envelope(&data), Ok((SNAPSHOT | SEALED, _))) {
+        return data;
+    }
+
+    let mut compressed = MAGIC.to_vec();
+    compressed.push(VERSION);
+    compressed.push(COMPRESSED);
+    compressed.extend(zstd::encode_all(data.as_slice(), 0).expect("compressing into memory can't fail"));
+
+    if compressed.len() < data.len() { compressed } else { data }
+}
+
+/// Expands a compressed payload, failing if it expands past [`MAX_DECOMPRESSED`].
+fn decompress(data: &[u8]) -> Result<Vec<u8>, NotepadError> {
+    let decoder = zstd::Decoder::new(data).map_err(|_| NotepadError::Decode("Invalid payload compression"))?;
+    let mut expanded = Vec::new();
+
+    decoder
+        .take(MAX_DECOMPRESSED + 1)
+        .read_to_end(&mut expanded)
+        .map_err(|_| NotepadError::Decode("Invalid payload compression"))?;
+
+    if expanded.len() as u64 > MAX_DECOMPRESSED {
+        return Err(NotepadError::Decode("Compressed payload expands too far"));
+    }
+
+    Ok(expanded)
+}
+
+/// Splits bytes prefixed by a varint length off the front of `data`.
+fn split_bytes(data: &[u8]) -> Result<(Vec<u8>, &[u8]), NotepadError> {
+    let (len, data) = varint::split(data)?;
+
+    if data.len() < len {
+        return Err(NotepadError::Decode("Bytes are longer than the message"));
+    }
+
+    let (bytes, data) = data.split_at(len);
+
+    Ok((bytes.to_vec(), data))
+}
+
+/// A listing as its room, description and a varint participant count.
+fn push_listing(data: &mut Vec<u8>, listing: &RoomListing) {
+    push_str(data, &listing.room);
+    push_str(data, &listing.description);
+    varint::push(data, listing.participants as usize);
+}
+
+fn split_listing(data: &[u8]) -> Result<(RoomListing, &[u8]), NotepadError> {
+    let (room, data) = split_str(data)?;
+    let (description, data) = split_str(data)?;
+    let (participants, data) = varint::split(data)?;
+    let participants = participants.try_into().map_err(|_| NotepadError::Decode("Invalid participant count"))?;
+
+    Ok((RoomListing { room, description, participants }, data))
+}
+
+/// Versions as a peer id prefixed by its length and a varint edit number each,
+/// to the end of the message.
+fn push_versions(data: &mut Vec<u8>, versions: Vec<(PeerId, u64)>) {
+    for (peer, seq) in versions {
+        let peer = peer.to_bytes();
+        varint::push(data, peer.len());
+        data.extend(peer);
+        varint::push(data, seq as usize);
+    }
+}
+
+fn split_versions(mut data: &[u8]) -> Result<Vec<(PeerId, u64)>, NotepadError> {
+    let mut versions = Vec::new();
+
+    while !data.is_empty() {
+        let (peer, rest) = split_bytes(data)?;
+        let (seq, rest) = varint::split(rest)?;
+        let peer = PeerId::from_bytes(&peer).map_err(|_| NotepadError::Decode("Invalid verification peer id"))?;
+
+        versions.push((peer, seq as u64));
+        data = rest;
+    }
+
+    Ok(versions)
+}
+
+/// Appends `s` prefixed by its length, truncated to the last whole character within 255 bytes.
+pub fn push_str(data: &mut Vec<u8>, s: &str) {
+    let mut len = s.len().min(u8::MAX as usize);
+    while !s.is_char_boundary(len) {
+        len -= 1;
+    }
+
+    data.push(len as u8);
+    data.extend(&s.as_bytes()[..len]);
+}
+
+/// Checks the envelope of a payload, returning its tag and the message after it.
+fn split_envelope(data: &[u8]) -> Result<(u8, &[u8]), NotepadError> {
+    let data = data.strip_prefix(MAGIC).ok_or(NotepadError::Decode("Not a p2p notepad message"))?;
+    let (&version, data) = data.split_first().ok_or(NotepadError::Decode("Missing message version"))?;
+
+    if version != VERSION {
+        return Err(NotepadError::Decode("Unsupported message version"));
+    }
+
+    let (&tag, data) = data.split_first().ok_or(NotepadError::Decode("Missing message tag"))?;
+
+    Ok((tag, data))
+}
+
+/// How a payload received from the network checks out, see [`check`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Check {
+    Valid,
+    /// From a newer build, which this one can't tell from garbage.
+    Unknown,
+    Malformed,
+}
+
+/// Checks that a payload decodes, so gossipsub only forwards well formed
+/// ones. Sealed payloads are only checked up to their nonce, and diffs only
+/// for their opcodes, their indices are checked against the text they are
+/// applied to. Compressed payloads are checked for what they expand to.
+///
+/// Nothing is copied out of `data`. Edits, most of the traffic, are walked
+/// in place with [`diffs_view`] rather than decoded into diffs of their own,
+/// which the engine does once it takes them.
+pub fn check(data: &[u8]) -> Check {
+    if let Ok((COMPRESSED, compressed)) = split_envelope(data) {
+        return match decompress(compressed) {
+            Ok(data) if !matches!(split_envelope(&data), Ok((COMPRESSED, _))) => check(&data),
+            _ => Check::Malformed,
+        };
+    }
+
+    let newer = data.strip_prefix(MAGIC).and_then(|data| data.first()).is_some_and(|&version| version > VERSION);
+    let unknown_tag = matches!(split_envelope(data), Ok((tag, _)) if tag > LAST_TAG);
+
+    let valid = match split_envelope(data) {
+        Ok((DIFFS | POSTCARD_DIFFS, _)) => diffs_view(data).is_some_and(|(_, mut diffs)| diffs.all(|diff| diff.is_ok())),
+        _ => Message::try_from(data).is_ok(),
+    };
+
+    match valid {
+        true => Check::Valid,
+        false if newer || unknown_tag => Check::Unknown,
+        false => Check::Malformed,
+    }
+}
+
+/// The document and diffs of a `Diffs` payload, the diffs decoded in place
+/// as they are iterated, see [`Diffs`]. `None` for any other payload.
+pub fn diffs_view(data: &[u8]) -> Option<(String, Diffs<'_>)> {
+    let (tag @ (DIFFS | POSTCARD_DIFFS), data) = split_envelope(data).ok()? else {
+        return None;
+    };
+    let (document, data) = split_str(data).ok()?;
+    let (_, data) = varint::split(data).ok()?;
+
+    Some((document, if tag == DIFFS { Diffs::new(data) } else { Diffs::postcard(data) }))
+}
+
+/// Splits a length-prefixed string off the front of `data`.
+pub fn split_str(data: &[u8]) -> Result<(String, &[u8]), NotepadError> {
+    let (&len, data) = data.split_first().ok_or(NotepadError::Decode("Missing string length"))?;
+
+    if data.len() < len as usize {
+        return Err(NotepadError::Decode("String is longer than the message"));
+    }
+
+    let (s, data) = data.split_at(len as usize);
+    let s = String::from_utf8(s.to_vec()).map_err(|_| NotepadError::Decode("String is not valid UTF-8"))?;
+
+    Ok((s, data))
+}
+
+/// What room names are prefixed with to form topics by default,
+/// `p2p-notepad/<version>/`, so nodes with different wire formats are in
+/// rooms of their own and never have to decode each other's payloads.
+pub fn topic_prefix() -> String {
+    format!("{NAMESPACE}{VERSION}/")
+}
+
+/// The wire format version of a topic in the namespace of any version and
+/// the rest of the topic after it, from which the room is derived.
+pub fn topic_version(topic: &str) -> Option<(u8, &str)> {
+    let (version, room) = topic.strip_prefix(NAMESPACE)?.split_once('/')?;
+
+    Some((version.parse().ok()?, room))
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::{collection::vec, prelude::*};
+
+    use super::*;
+    use crate::{
+        diff::{Diff, Operation},
+        manifest,
+        whiteboard::{Color, Stroke}
+    };
+
+    /// `body` wrapped in the envelope header.
+    fn envelope(body: &[u8]) -> Vec<u8> {
+        [b"PN\x01".as_slice(), body].concat()
+    }
+
+    fn def_diffs() -> Message {
+        Message::Diffs {
+            document: "main".to_string(),
+            seq: 1,
+            diffs: MessageBuf {
+                messages: vec![Diff { opcode: Operation::Ins, operand: Some('a'), index: 0 }]
+            },
+        }
+    }
+
+    #[test]
+    fn diffs_round_trip() {
+        let data: Vec<u8> = def_diffs().into();
+        // Ins, then the operand as a present one character string, then the index.
+        assert_eq!(data, envelope(&[39, 4, b'm', b'a', b'i', b'n', 1, 1, 1, 1, 97, 0]));
+
+        let (document, mut diffs) = diffs_view(&data).unwrap();
+        assert_eq!(document, "main");
+        assert_eq!(diffs.next().unwrap().unwrap().to_diff(), Diff { opcode: Operation::Ins, operand: Some('a'), index: 0 });
+        assert!(diffs.next().is_none());
+
+        let message: Message = data.try_into().unwrap();
+        assert_eq!(message, def_diffs());
+
+        let varint = envelope(&[12, 4, b'm', b'a', b'i', b'n', 1, 1, 97, 0]);
+        assert_eq!(diffs_view(&varint).unwrap().1.next().unwrap().unwrap().to_diff(), Diff { opcode: Operation::Ins, operand: Some('a'), index: 0 });
+        assert_eq!(Message::try_from(varint).unwrap(), def_diffs());
+
+        let fixed = envelope(&[0, 4, b'm', b'a', b'i', b'n', 1, 97, 0]);
+        assert!(diffs_view(&fixed).is_none());
+        assert!(matches!(Message::try_from(fixed).unwrap(), Message::Diffs { seq: 0, .. }));
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        let snapshot = || Snapshot { document: "todo".to_string(), text: "hello world ".repeat(100) };
+
+        let data: Vec<u8> = Message::Snapshot(snapshot()).into();
+        assert!(data.len() < snapshot().text.len());
+
+        let message: Message = data.try_into().unwrap();
+        assert_eq!(message, Message::Snapshot(snapshot()));
+    }
+
+    #[test]
+    fn corrupted_snapshot() {
+        let mut data: Vec<u8> = Message::Snapshot(Snapshot { document: "todo".to_string(), text: "hello".to_string() }).into();
+        data[9] ^= 1;
+
+        assert!(matches!(Message::try_from(data), Err(NotepadError::Integrity(_))));
+    }
+
+    #[test]
+    fn snapshots_expand_within_bounds() {
+        let text = vec![b'a'; MAX_DECOMPRESSED as usize + 1];
+        let mut data = envelope(&[SNAPSHOT]);
+        push_str(&mut data, "todo");
+        data.extend(blake3::hash(&text).as_bytes());
+        data.extend(zstd::encode_all(&text[..], 0).unwrap());
+
+        assert!(matches!(Message::try_from(data), Err(NotepadError::Decode("Compressed payload expands too far"))));
+    }
+
+    fn def_meta() -> DocumentMeta {
+        DocumentMeta {
+            id: "main".to_string(),
+            name: "notes".to_string(),
+            archived: true,
+            line_ending: LineEnding::Lf,
+            settings: DocumentSettings::default(),
+        }
+    }
+
+    #[test]
+    fn meta_round_trip() {
+        let meta = def_meta;
+
+        let data: Vec<u8> = Message::Meta(meta()).into();
+        assert_eq!(data, envelope(&[2, 4, b'm', b'a', b'i', b'n', 5, b'n', b'o', b't', b'e', b's', 1]));
+
+        let message: Message = data.try_into().unwrap();
+        assert_eq!(message, Message::Meta(meta()));
+
+        let crlf = || DocumentMeta { line_ending: LineEnding::Crlf, archived: false, ..meta() };
+        let data: Vec<u8> = Message::Meta(crlf()).into();
+        assert_eq!(data.last(), Some(&2));
+        assert_eq!(Message::try_from(data).unwrap(), Message::Meta(crlf()));
+
+        let settings = || DocumentMeta { settings: DocumentSettings { language: "rust".to_string(), tab_width: 2, wrap: Some(80) }, ..meta() };
+        let data: Vec<u8> = Message::Meta(settings()).into();
+        assert!(data.ends_with(&[1, 4, b'r', b'u', b's', b't', 2, 80, 0]));
+        assert_eq!(Message::try_from(data).unwrap(), Message::Meta(settings()));
+    }
+
+    #[test]
+    fn clipboard_round_trip() {
+        let data: Vec<u8> = Message::Clipboard("a: b".to_string()).into();
+        assert_eq!(data, envelope(&[3, b'a', b':', b' ', b'b']));
+
+        let message: Message = data.try_into().unwrap();
+        assert_eq!(message, Message::Clipboard("a: b".to_string()));
+    }
+
+    #[test]
+    fn chat_round_trip() {
+        let data: Vec<u8> = Message::Chat("hi all".to_string()).into();
+        assert_eq!(split_envelope(&data).unwrap().0, CHAT);
+
+        let message: Message = data.try_into().unwrap();
+        assert_eq!(message, Message::Chat("hi all".to_string()));
+    }
+
+    #[test]
+    fn lock_round_trip() {
+        let data: Vec<u8> = Message::Lock { document: "main".to_string(), held: true }.into();
+        assert_eq!(data, envelope(&[33, 4, b'm', b'a', b'i', b'n', 1]));
+        assert_eq!(Message::try_from(data).unwrap(), Message::Lock { document: "main".to_string(), held: true });
+
+        assert!(Message::try_from(envelope(&[33, 4, b'm', b'a', b'i', b'n', 2])).is_err());
+        assert!(Message::try_from(envelope(&[33, 4, b'm', b'a', b'i', b'n'])).is_err());
+    }
+
+    #[test]
+    fn claim_round_trip() {
+        let claim = |range| Message::Claim { document: "main".to_string(), range };
+
+        let data: Vec<u8> = claim(Some(10..300)).into();
+        assert_eq!(Message::try_from(data).unwrap(), claim(Some(10..300)));
+        let data: Vec<u8> = claim(None).into();
+        assert_eq!(data, envelope(&[34, 4, b'm', b'a', b'i', b'n']));
+        assert_eq!(Message::try_from(data).unwrap(), claim(None));
+
+        assert!(Message::try_from(envelope(&[34, 4, b'm', b'a', b'i', b'n', 5, 3])).is_err());
+        assert!(Message::try_from(envelope(&[34, 4, b'm', b'a', b'i', b'n', 5])).is_err());
+    }
+
+    #[test]
+    fn topics_are_namespaced_by_version() {
+        assert_eq!(topic_prefix(), format!("p2p-notepad/v{VERSION}/"));
+        assert_eq!(topic_version(&format!("{}notes", topic_prefix())), Some((VERSION, "notes")));
+        assert_eq!(topic_version("p2p-notepad/v12/a/b"), Some((12, "a/b")));
+        assert_eq!(topic_version("p2p-notepad/vx/notes"), None);
+        assert_eq!(topic_version("notes"), None);
+    }
+
+    #[test]
+    fn applied_round_trip() {
+        let author = PeerId::random();
+
+        let data: Vec<u8> = Message::Applied { author, seq: 300 }.into();
+        assert_eq!(Message::try_from(data).unwrap(), Message::Applied { author, seq: 300 });
+        assert!(Message::try_from(envelope(&[35, 1, 2, 3])).is_err());
+    }
+
+    #[test]
+    fn presence_round_trip() {
+        let presence = |nickname: Option<&str>, away| Message::Presence(Presence { nickname: nickname.map(str::to_string), away, reading: None, selection: None });
+
+        let data: Vec<u8> = presence(None, false).into();
+        assert_eq!(data, envelope(&[4, 0, 0]));
+        assert_eq!(Message::try_from(data).unwrap(), presence(None, false));
+
+        let data: Vec<u8> = presence(Some("bob"), true).into();
+        assert_eq!(data, envelope(&[4, 3, b'b', b'o', b'b', 1]));
+        assert_eq!(Message::try_from(data).unwrap(), presence(Some("bob"), true));
+
+        assert_eq!(Message::try_from(envelope(&[4, 3, b'b', b'o', b'b'])).unwrap(), presence(Some("bob"), false));
+
+        let reading = Message::Presence(Presence { nickname: None, away: false, reading: Some(Reading { document: "main".to_string(), lines: 200..240 }), selection: None });
+        let data: Vec<u8> = reading.into();
+        assert_eq!(data, envelope(&[4, 0, 2, 4, b'm', b'a', b'i', b'n', 0xc8, 1, 0xf0, 1]));
+        assert!(matches!(Message::try_from(data).unwrap(), Message::Presence(Presence { reading: Some(Reading { lines, .. }), .. }) if lines == (200..240)));
+        assert!(Message::try_from(envelope(&[4, 0, 2, 4, b'm', b'a', b'i', b'n', 5, 5])).is_err());
+
+        let selection = Some(Selection { document: "main".to_string(), range: 3..9 });
+        let data: Vec<u8> = Message::Presence(Presence { nickname: None, away: false, reading: None, selection: selection.clone() }).into();
+        assert_eq!(data, envelope(&[4, 0, 4, 4, b'm', b'a', b'i', b'n', 3, 9]));
+        assert!(matches!(Message::try_from(data).unwrap(), Message::Presence(Presence { selection: found, .. }) if found == selection));
+        assert!(Message::try_from(envelope(&[4, 0, 4, 4, b'm', b'a', b'i', b'n', 9, 3])).is_err());
+    }
+
+    #[test]
+    fn probe_round_trip() {
+        let data: Vec<u8> = Message::Probe(1).into();
+        assert_eq!(data, envelope(&[5, 1, 0, 0, 0, 0, 0, 0, 0]));
+        assert_eq!(Message::try_from(data).unwrap(), Message::Probe(1));
+
+        let data: Vec<u8> = Message::ProbeAck(u64::MAX).into();
+        assert_eq!(Message::try_from(data).unwrap(), Message::ProbeAck(u64::MAX));
+    }
+
+    #[test]
+    fn attachment_round_trip() {
+        let meta = || AttachmentMeta { hash: "abc".to_string(), name: "a".to_string(), size: 5 };
+
+        let data: Vec<u8> = Message::Attachment(meta()).into();
+        assert_eq!(data, envelope(&[7, 3, b'a', b'b', b'c', 1, b'a', 5, 0, 0, 0]));
+        assert_eq!(Message::try_from(data).unwrap(), Message::Attachment(meta()));
+
+        let data: Vec<u8> = Message::AttachmentRequest("abc".to_string()).into();
+        assert_eq!(Message::try_from(data).unwrap(), Message::AttachmentRequest("abc".to_string()));
+
+        for blob in [None, Some(vec![]), Some(b"hello".to_vec())] {
+            let message = || Message::AttachmentData { hash: "abc".to_string(), data: blob.clone() };
+            let data: Vec<u8> = message().into();
+            assert_eq!(Message::try_from(data).unwrap(), message());
+        }
+    }
+
+    #[test]
+    fn snapshot_request_round_trip() {
+        let data: Vec<u8> = Message::SnapshotRequest.into();
+        assert_eq!(data, envelope(&[10]));
+        assert_eq!(Message::try_from(data).unwrap(), Message::SnapshotRequest);
+    }
+
+    #[test]
+    fn runs_round_trip() {
+        let runs = || Message::Runs {
+            document: "main".to_string(),
+            seq: 300,
+            runs: vec![
+                Run::Insert { index: 0, text: "hello".to_string() },
+                Run::Delete { index: 2, count: 3 },
+                Run::Diff(Diff { opcode: Operation::Rep, operand: Some('a'), index: 1 }),
+            ],
+        };
+
+        let data: Vec<u8> = runs().into();
+        assert_eq!(data, envelope(&[11, 4, b'm', b'a', b'i', b'n', 0xac, 0x02, 1, 0, 5, b'h', b'e', b'l', b'l', b'o', 2, 2, 3, 0, 2, b'a', 1]));
+        assert_eq!(Message::try_from(data).unwrap(), runs());
+    }
+
+    #[test]
+    fn directory_round_trip() {
+        let listing = || RoomListing { room: "rust".to_string(), description: "hi".to_string(), participants: 3 };
+
+        let data: Vec<u8> = Message::Listing(listing()).into();
+        assert_eq!(data, envelope(&[13, 4, b'r', b'u', b's', b't', 2, b'h', b'i', 3]));
+        assert_eq!(Message::try_from(data).unwrap(), Message::Listing(listing()));
+
+        let data: Vec<u8> = Message::DirectoryRequest.into();
+        assert_eq!(Message::try_from(data).unwrap(), Message::DirectoryRequest);
+
+        let directory = || Message::Directory(vec![listing(), listing()]);
+        let data: Vec<u8> = directory().into();
+        assert_eq!(Message::try_from(data).unwrap(), directory());
+        assert_eq!(Message::try_from(envelope(&[15])).unwrap(), Message::Directory(Vec::new()));
+    }
+
+    #[test]
+    fn session_summary_round_trip() {
+        let summary = || SessionSummary { document: "notes".to_string(), participants: 3, ops: 200, checksum: "abcd".to_string() };
+
+        let data: Vec<u8> = Message::SessionSummary(summary()).into();
+        assert_eq!(data, envelope(&[16, 5, b'n', b'o', b't', b'e', b's', 3, 0xc8, 0x01, 4, b'a', b'b', b'c', b'd']));
+        assert_eq!(Message::try_from(data).unwrap(), Message::SessionSummary(summary()));
+    }
+
+    #[test]
+    fn sync_round_trip() {
+        let peer = PeerId::random();
+        let sync = |versions| Message::Sync {
+            seq: 7,
+            versions,
+            sequences: Vec::new(),
+            archive: Archive { documents: vec![(def_meta(), "hello".to_string())] },
+        };
+
+        let request = |versions| Message::SyncRequest { versions };
+        let data: Vec<u8> = request(Vec::new()).into();
+        assert_eq!(data, envelope(&[17]));
+        assert_eq!(Message::try_from(data).unwrap(), request(Vec::new()));
+        let data: Vec<u8> = request(vec![(peer, 3)]).into();
+        assert_eq!(Message::try_from(data).unwrap(), request(vec![(peer, 3)]));
+
+        let data: Vec<u8> = sync(vec![(peer, 3)]).into();
+        assert_eq!(&data[3..6], &[32, 7, 1]);
+        assert_eq!(Message::try_from(data).unwrap(), sync(vec![(peer, 3)]));
+
+        // Older peers answer without versions.
+        let mut data = envelope(&[18, 7]);
+        data.extend(Archive { documents: vec![(def_meta(), "hello".to_string())] }.encode(None));
+        assert_eq!(Message::try_from(data).unwrap(), sync(Vec::new()));
+
+        let sequenced = || Message::Sync {
+            seq: 7,
+            versions: Vec::new(),
+            sequences: vec![("main".to_string(), Sequence::from_text(0, "hello"))],
+            archive: Archive { documents: vec![(def_meta(), "hello".to_string())] },
+        };
+        let data: Vec<u8> = sequenced().into();
+        assert_eq!(data[3], 38);
+        assert_eq!(Message::try_from(data).unwrap(), sequenced());
+    }
+
+    #[test]
+    fn backfill_round_trip() {
+        let (peer, author) = (PeerId::random(), PeerId::random());
+        let diffs = MessageBuf { messages: vec![Diff { opcode: Operation::InsStr("hi".to_string()), operand: None, index: 2 }] };
+        let backfill = || Message::Backfill(vec![
+            Missed { peer, seq: 4, document: "main".to_string(), diffs: diffs.clone(), author: None },
+            Missed { peer, seq: 5, document: "notes".to_string(), diffs: diffs.clone(), author: Some(author) },
+        ]);
+
+        let data: Vec<u8> = backfill().into();
+        assert_eq!(data[3], 40);
+        assert_eq!(Message::try_from(data).unwrap(), backfill());
+        assert!(Message::try_from(envelope(&[40, 1])).is_err());
+    }
+
+    #[test]
+    fn ops_round_trip() {
+        let mut sequence = Sequence::new(1);
+        let mut ops: Vec<_> = "hé".chars().enumerate().filter_map(|(i, c)| sequence.insert(i, c)).collect();
+        ops.extend(sequence.delete(0));
+        let message = || Message::Ops { document: "main".to_string(), seq: 3, ops: ops.clone() };
+        let data: Vec<u8> = message().into();
+
+        assert_eq!(Message::try_from(data).unwrap(), message());
+        assert!(Message::try_from(envelope(&[37, 1, b'a', 3, 9])).is_err());
+    }
+
+    #[test]
+    fn manifest_round_trip() {
+        let manifest = Message::Manifest(RoomManifest { flags: manifest::PRIVATE, max_document_size: Some(1000), genesis: None });
+
+        let data: Vec<u8> = Message::Manifest(RoomManifest { flags: manifest::PRIVATE, max_document_size: Some(1000), genesis: None }).into();
+        assert_eq!(data, envelope(&[20, 1, 0xe8, 0x07]));
+        assert_eq!(Message::try_from(data).unwrap(), manifest);
+
+        let templated = || Message::Manifest(RoomManifest { flags: 0, max_document_size: None, genesis: Some([7; 32]) });
+        let data: Vec<u8> = templated().into();
+        assert_eq!(data.len(), 3 + 3 + 32);
+        assert_eq!(Message::try_from(data).unwrap(), templated());
+        assert!(Message::try_from(envelope(&[20, 0, 0, 7, 7])).is_err());
+
+        let data: Vec<u8> = Message::ManifestAck(Admission::ReadOnly).into();
+        assert_eq!(Message::try_from(data).unwrap(), Message::ManifestAck(Admission::ReadOnly));
+        assert!(Message::try_from(envelope(&[21, 3])).is_err());
+
+        let peer = PeerId::random();
+        let data: Vec<u8> = Message::Permission { peer, write: false }.into();
+        assert_eq!(data[3..5], [28, 0]);
+        assert_eq!(Message::try_from(data).unwrap(), Message::Permission { peer, write: false });
+        assert!(Message::try_from(envelope(&[28, 2])).is_err());
+    }
+
+    #[test]
+    fn backup_round_trip() {
+        let backup = |offset| Message::Backup { owner: "ab12".to_string(), log: "cd34.oplog".to_string(), offset, frames: vec![3, 0, 1, 2] };
+
+        for offset in [None, Some(0), Some(300)] {
+            let data: Vec<u8> = backup(offset).into();
+            assert_eq!(Message::try_from(data).unwrap(), backup(offset));
+        }
+
+        let request = || Message::BackupRequest { owner: "ab12".to_string(), log: "cd34.oplog".to_string() };
+        let data: Vec<u8> = request().into();
+        assert_eq!(Message::try_from(data).unwrap(), request());
+
+        let data: Vec<u8> = Message::BackupAck { log: "cd34.oplog".to_string(), len: 300 }.into();
+        assert_eq!(Message::try_from(data).unwrap(), Message::BackupAck { log: "cd34.oplog".to_string(), len: 300 });
+        assert!(Message::try_from(envelope(&[23, 1, b'a', 0, 0])).is_err());
+    }
+
+    #[test]
+    fn cursor_round_trip() {
+        let cursor = || Message::Cursor { document: "main".to_string(), index: 1000 };
+        let data: Vec<u8> = cursor().into();
+
+        assert_eq!(Message::try_from(data).unwrap(), cursor());
+        assert!(Message::try_from(envelope(&[25, 1, b'a'])).is_err());
+    }
+
+    #[test]
+    fn board_round_trip() {
+        let board = || Message::Board {
+            board: "arch".to_string(),
+            ops: vec![BoardOp::Draw(Stroke { id: 1, color: Color::Blue, points: vec![(0, 0), (10, 5)] }), BoardOp::Erase(2)],
+        };
+        let data: Vec<u8> = board().into();
+
+        assert_eq!(Message::try_from(data).unwrap(), board());
+        assert!(Message::try_from(envelope(&[27, 1, b'a', 1, 0])).is_err());
+    }
+
+    #[test]
+    fn large_payloads_are_compressed() {
+        let clipboard = || Message::Clipboard("hello world ".repeat(COMPRESS_MIN));
+        let data: Vec<u8> = clipboard().into();
+        assert_eq!(split_envelope(&data).unwrap().0, COMPRESSED);
+        assert!(data.len() < COMPRESS_MIN);
+        assert_eq!(Message::try_from(data.clone()).unwrap(), clipboard());
+        assert_eq!(check(&data), Check::Valid);
+
+        let twice = [envelope(&[COMPRESSED]), zstd::encode_all(data.as_slice(), 0).unwrap()].concat();
+        assert!(Message::try_from(twice.clone()).is_err());
+        assert_eq!(check(&twice), Check::Malformed);
+        assert_eq!(check(&envelope(&[COMPRESSED, 1, 2, 3])), Check::Malformed);
+
+        let noise: Vec<u8> = (0..COMPRESS_MIN).map(|_| rand::random()).collect();
+        let data: Vec<u8> = Message::AttachmentData { hash: "abc".to_string(), data: Some(noise) }.into();
+        assert_eq!(split_envelope(&data).unwrap().0, ATTACHMENT_DATA);
+    }
+
+    #[test]
+    fn payloads_are_checked() {
+        let diffs = Vec::<u8>::from(def_diffs());
+        assert_eq!(check(&diffs), Check::Valid);
+        // An edit is only valid if every diff in it decodes.
+        assert_eq!(check(&diffs[..diffs.len() - 1]), Check::Malformed);
+        assert_eq!(check(&envelope(&[20, 0, 0, 7, 7])), Check::Malformed);
+        assert_eq!(check(b"hello"), Check::Malformed);
+        assert_eq!(check(&envelope(&[LAST_TAG + 1, 1, 2])), Check::Unknown);
+        assert_eq!(check(&[b'P', b'N', VERSION + 1, 0]), Check::Unknown);
+    }
+
+    #[test]
+    fn verify_round_trip() {
+        let versions = vec![(PeerId::random(), 3), (PeerId::random(), 300)];
+        let verify = || Message::Verify { document: "main".to_string(), hash: [9; 32], versions: versions.clone(), answer: true };
+        let data: Vec<u8> = verify().into();
+
+        assert_eq!(Message::try_from(data).unwrap(), verify());
+        let mut unanswerable = vec![29, 1, b'a'];
+        unanswerable.extend([9; 32]);
+        unanswerable.push(2);
+        assert!(Message::try_from(envelope(&unanswerable)).is_err());
+    }
+
+    #[test]
+    fn digest_round_trip() {
+        let versions = vec![(PeerId::random(), 7)];
+        let digest = || Message::Digest { document: "main".to_string(), hash: [3; 32], versions: versions.clone() };
+        let data: Vec<u8> = digest().into();
+
+        assert_eq!(Message::try_from(data).unwrap(), digest());
+        assert!(Message::try_from(envelope(&[36, 1, b'a', 3])).is_err());
+    }
+
+    #[test]
+    fn sealed_round_trip() {
+        let sealed = || Message::Sealed { nonce: [7; NONCE_LEN], ciphertext: vec![1, 2, 3] };
+        let data: Vec<u8> = sealed().into();
+
+        assert_eq!(Message::try_from(data).unwrap(), sealed());
+        assert!(Message::try_from(envelope(&[26, 0, 0, 0])).is_err());
+    }
+
+    #[test]
+    fn signed_round_trip() {
+        let signed = || Message::Signed(Signed {
+            public_key: vec![1; 36],
+            nickname: "alice".to_string(),
+            signature: vec![2; 64],
+            payload: def_diffs().into(),
+        });
+
+        let data: Vec<u8> = signed().into();
+        assert_eq!(&data[3..5], &[19, 36]);
+        assert_eq!(Message::try_from(data).unwrap(), signed());
+    }
+
+    #[test]
+    fn invalid_messages() {
+        assert!(Message::try_from(envelope(&[])).is_err());
+        assert!(Message::try_from(envelope(&[0])).is_err());
+        assert!(Message::try_from(envelope(&[0, 4, b'm'])).is_err());
+        assert!(Message::try_from(envelope(&[0xff, 0, 0, 0, 0])).is_err());
+        assert!(Message::try_from(envelope(&[1, 0, 0, 0, 0])).is_err());
+        assert!(Message::try_from(envelope(&[2, 0])).is_err());
+        assert!(Message::try_from(envelope(&[2, 0, 0, 4])).is_err());
+        assert!(Message::try_from(envelope(&[2, 0, 0, 0, 0, 4, 80])).is_err());
+        assert!(Message::try_from(envelope(&[3, 0xff])).is_err());
+        assert!(Message::try_from(envelope(&[4])).is_err());
+        assert!(Message::try_from(envelope(&[4, 0, 2])).is_err());
+        assert!(Message::try_from(envelope(&[4, 0, 0, 0])).is_err());
+        assert!(Message::try_from(envelope(&[5, 1, 0])).is_err());
+        assert!(Message::try_from(envelope(&[7, 0, 0, 1])).is_err());
+        assert!(Message::try_from(envelope(&[8, 0, 0])).is_err());
+        assert!(Message::try_from(envelope(&[9, 0])).is_err());
+        assert!(Message::try_from(envelope(&[9, 0, 0, 1])).is_err());
+        assert!(Message::try_from(envelope(&[10, 0])).is_err());
+        assert!(Message::try_from(envelope(&[11, 0, 1, 2, 0])).is_err());
+        assert!(Message::try_from(envelope(&[11, 0, 1, 3, 0, 0])).is_err());
+        assert!(Message::try_from(envelope(&[11, 0, 1, 0, 2, b'a'])).is_err());
+        assert!(Message::try_from(envelope(&[13, 0, 0, 1, 0])).is_err());
+        assert!(Message::try_from(envelope(&[14, 0])).is_err());
+        assert!(Message::try_from(envelope(&[15, 0, 0])).is_err());
+        assert!(Message::try_from(envelope(&[16, 0, 1, 1, 0, 0])).is_err());
+        assert!(Message::try_from(envelope(&[17, 0])).is_err());
+        assert!(Message::try_from(envelope(&[18, 1, 0])).is_err());
+    }
+
+    #[test]
+    fn malformed_messages_are_dropped() {
+        let messages = vec![
+            def_diffs(),
+            Message::Presence(Presence { nickname: Some("alice".to_string()), away: true, reading: None, selection: None }),
+            Message::Snapshot(Snapshot { document: "main".to_string(), text: "hello".to_string() }),
+            Message::AttachmentData { hash: "abc".to_string(), data: Some(vec![1, 2]) },
+            Message::Listing(RoomListing { room: "rust".to_string(), description: "hi".to_string(), participants: 300 }),
+        ];
+
+        // Decoding must return, never panic, however the payload is cut short.
+        for message in messages {
+            let data: Vec<u8> = message.into();
+            for len in 0..data.len() {
+                let _ = Message::try_from(data[..len].to_vec());
+            }
+        }
+
+        for tag in 0..=u8::MAX {
+            for filler in [0, 0x7f, 0x80, 0xff] {
+                for len in 0..8 {
+                    let mut payload = vec![tag];
+                    payload.resize(len + 1, filler);
+                    let _ = Message::try_from(envelope(&payload));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn foreign_messages() {
+        assert!(Message::try_from(vec![]).is_err());
+        assert!(Message::try_from(b"hello world".to_vec()).is_err());
+        assert!(Message::try_from(vec![3, b'h', b'i']).is_err());
+        assert!(Message::try_from(b"PN".to_vec()).is_err());
+        assert!(Message::try_from(b"PN\x02\x03hi".to_vec()).is_err());
+        assert_eq!(Message::try_from(b"PN\x01\x03hi".to_vec()).unwrap(), Message::Clipboard("hi".to_string()));
+    }
+
+    proptest! {
+        /// Payloads come from any peer, so they must fail to decode rather than panic.
+        #[test]
+        fn arbitrary_payloads_decode_without_panicking(body in vec(any::<u8>(), 0..256)) {
+            let _ = Message::try_from(body.clone());
+            let _ = Message::try_from(envelope(&body));
+        }
+    }
+}
@@ -0,0 +1,1226 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    error::Error,
+    hash::{
+        Hash, Hasher
+    },
+    io,
+    path::PathBuf,
+    str::FromStr,
+    time::{Duration, Instant}
+};
+
+use async_trait::async_trait;
+use futures::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    stream::StreamExt
+};
+use libp2p::{
+    allow_block_list, autonat, dcutr, gossipsub, identify, kad, mdns, noise, ping, relay, request_response, tcp, yamux, Multiaddr, PeerId, StreamProtocol, Swarm,
+    core::{transport::MemoryTransport, upgrade, Transport as _},
+    identity::Keypair,
+    multiaddr::Protocol,
+    kad::store::MemoryStore,
+    request_response::{ProtocolSupport, ResponseChannel},
+    swarm::{
+        behaviour::toggle::Toggle,
+        dial_opts::DialOpts,
+        ConnectionId, NetworkBehaviour, SwarmEvent
+    }
+};
+
+use crate::{
+    config::{Behaviours, Config},
+    error::NotepadError,
+    fanout::Fanout,
+    identity,
+    message::{self, Check},
+    output,
+    rendezvous::{self, Registry},
+    retry::RetryPolicy,
+    scoring::Scoring,
+    transport::{Event, Incoming, Transport}
+};
+
+/// Protocol for direct requests between peers, such as attachment downloads.
+const REQUEST_PROTOCOL: StreamProtocol = StreamProtocol::new("/p2p-notepad/request/1");
+/// Protocol rooms are registered at a rendezvous point over, see [`rendezvous`].
+const RENDEZVOUS_PROTOCOL: StreamProtocol = StreamProtocol::new("/p2p-notepad/rendezvous/1");
+const KAD_PROTOCOL: StreamProtocol = StreamProtocol::new("/p2p-notepad/kad/1");
+const IDENTIFY_PROTOCOL: &str = "/p2p-notepad/1";
+/// Software and version told to peers through identify, to tell builds apart when they don't understand each other.
+const AGENT_VERSION: &str = concat!("p2p-notepad/", env!("CARGO_PKG_VERSION"));
+
+/// Largest request or response accepted over [`REQUEST_PROTOCOL`].
+const MAX_REQUEST_LEN: u64 = 16 * 1024 * 1024;
+
+/// Pings in a row a peer can leave unanswered before it is taken for dead
+/// and disconnected, even though its connection is still open.
+const PING_FAILURES: u32 = 3;
+
+/// How long the swarm goes without an event when shutting down before what
+/// was queued counts as handed to the connections, which send it on their own.
+const FLUSH_IDLE: Duration = Duration::from_millis(50);
+
+#[derive(NetworkBehaviour)]
+pub struct MyBehaviour {
+    gossipsub: gossipsub::Behaviour,
+    mdns: Toggle<mdns::tokio::Behaviour>,
+    kad: Toggle<kad::Behaviour<MemoryStore>>,
+    relay: Toggle<relay::Behaviour>,
+    relay_client: Toggle<relay::client::Behaviour>,
+    dcutr: Toggle<dcutr::Behaviour>,
+    rendezvous: Toggle<request_response::Behaviour<BytesCodec>>,
+    identify: Toggle<identify::Behaviour>,
+    ping: Toggle<ping::Behaviour>,
+    request_response: Toggle<request_response::Behaviour<BytesCodec>>,
+    autonat: Toggle<autonat::Behaviour>,
+    blocked: Toggle<allow_block_list::Behaviour<allow_block_list::BlockedPeers>>,
+}
+
+/// How gossipsub checks messages before applying and forwarding them, see
+/// [`gossipsub::ValidationMode`]. Peers of a room must agree on it.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Validation {
+    /// Every message signed by its publisher, with a sequence number.
+    #[default]
+    Strict,
+    /// Checked like `Strict` when signed, accepted unsigned.
+    Permissive,
+    /// No author, signature or sequence number allowed.
+    Anonymous,
+    None,
+}
+
+impl FromStr for Validation {
+    type Err = NotepadError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "strict" => Ok(Validation::Strict),
+            "permissive" => Ok(Validation::Permissive),
+            "anonymous" => Ok(Validation::Anonymous),
+            "none" => Ok(Validation::None),
+            _ => Err(NotepadError::command(format!("Unknown validation mode {s:?}, expected `strict`, `permissive`, `anonymous` or `none`"))),
+        }
+    }
+}
+
+impl From<Validation> for gossipsub::ValidationMode {
+    fn from(validation: Validation) -> Self {
+        match validation {
+            Validation::Strict => gossipsub::ValidationMode::Strict,
+            Validation::Permissive => gossipsub::ValidationMode::Permissive,
+            Validation::Anonymous => gossipsub::ValidationMode::Anonymous,
+            Validation::None => gossipsub::ValidationMode::None,
+        }
+    }
+}
+
+/// Assembles the swarm from the behaviours enabled in the [`Config`].
+#[derive(Debug, Clone)]
+pub struct SwarmFactory {
+    flood_publish: bool,
+    latency_mesh: bool,
+    heartbeat: Duration,
+    validation: Validation,
+    scoring: Scoring,
+    behaviours: Behaviours,
+    /// Whether a relay is configured, for the relay client and hole punching.
+    relayed: bool,
+    /// Whether a rendezvous point is configured, or this node serves as one.
+    rendezvous: bool,
+    /// File the node's keypair is kept in, see [`identity::load_or_create`].
+    identity: Option<PathBuf>,
+    /// Peers connections are refused from, if set, see [`Config::refuse_blocked`].
+    blocked: Option<Vec<PeerId>>,
+}
+
+impl SwarmFactory {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            flood_publish: config.flood_publish,
+            latency_mesh: config.latency_mesh,
+            heartbeat: config.heartbeat,
+            validation: config.validation,
+            scoring: config.scoring,
+            behaviours: config.behaviours,
+            relayed: config.relay.is_some(),
+            rendezvous: config.rendezvous.is_some() || config.behaviours.rendezvous,
+            identity: config.identity.clone(),
+            blocked: config.refuse_blocked.then(|| config.blocked.clone()),
+        }
+    }
+
+    pub fn build(&self) -> Result<Swarm<MyBehaviour>, NotepadError> {
+        let keypair = match &self.identity {
+            Some(path) => identity::load_or_create(path)?,
+            None => Keypair::generate_ed25519(),
+        };
+
+        let swarm = libp2p::SwarmBuilder::with_existing_identity(keypair)
+            .with_tokio()
+            .with_tcp(
+                tcp::Config::default(),
+                noise::Config::new,
+                yamux::Config::default
+            )
+            .map_err(NotepadError::network)?
+            .with_quic()
+            // Listening on `/memory/<port>` connects nodes in the same
+            // process, as the integration tests do.
+            .with_other_transport(|key| -> Result<_, Box<dyn Error + Send + Sync>> {
+                Ok(MemoryTransport::default()
+                    .upgrade(upgrade::Version::V1)
+                    .authenticate(noise::Config::new(key)?)
+                    .multiplex(yamux::Config::default()))
+            })
+            .map_err(NotepadError::network)?
+            .with_relay_client(noise::Config::new, yamux::Config::default)
+            .map_err(NotepadError::network)?
+            .with_behaviour(|key, relay_client| self.behaviour(key, relay_client))
+            .map_err(NotepadError::network)?
+            .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(60)))
+            .build();
+
+        Ok(swarm)
+    }
+
+    fn behaviour(&self, key: &Keypair, relay_client: relay::client::Behaviour) -> Result<MyBehaviour, Box<dyn Error + Send + Sync>> {
+        let peer_id = key.public().to_peer_id();
+        let behaviours = self.behaviours;
+
+        let message_id_fn = |message: &gossipsub::Message| {
+            let mut s = DefaultHasher::new();
+
+            message.sequence_number.hash(&mut s);
+            message.data.hash(&mut s);
+
+            gossipsub::MessageId::from(s.finish().to_string())
+        };
+
+        let gossipsub_config = gossipsub::ConfigBuilder::default()
+            .heartbeat_interval(self.heartbeat)
+            .validation_mode(self.validation.into())
+            // Payloads are only forwarded once they decode, see [`message::check`].
+            .validate_messages()
+            .flood_publish(self.flood_publish)
+            .message_id_fn(message_id_fn)
+            .build()
+            .map_err(io::Error::other)?;
+
+        let mut gossipsub = gossipsub::Behaviour::new(
+            gossipsub::MessageAuthenticity::Signed(key.clone()),
+            gossipsub_config,
+        )?;
+
+        // Application scores come from pings, see [`Fanout`], and the rooms'
+        // topics are scored as they are joined, see [`Network::subscribe`].
+        if self.scoring.enabled || self.latency_mesh && behaviours.ping {
+            gossipsub.with_peer_score(self.scoring.params(), self.scoring.thresholds())?;
+        }
+
+        let mdns = behaviours.mdns
+            .then(|| mdns::tokio::Behaviour::new(mdns::Config::default(), peer_id))
+            .transpose()?;
+
+        let kad = behaviours.kad.then(|| {
+            let mut kad = kad::Behaviour::with_config(peer_id, MemoryStore::new(peer_id), kad::Config::new(KAD_PROTOCOL));
+            // Nodes rarely confirm an external address without autonat, and
+            // would otherwise stay clients, answering nobody's lookups.
+            kad.set_mode(Some(kad::Mode::Server));
+            kad
+        });
+
+        let relay = behaviours.relay.then(|| relay::Behaviour::new(peer_id, relay::Config::default()));
+
+        let relay_client = self.relayed.then_some(relay_client);
+        let dcutr = self.relayed.then(|| dcutr::Behaviour::new(peer_id));
+
+        let rendezvous = self.rendezvous.then(|| request_response::Behaviour::with_codec(
+            BytesCodec,
+            [(RENDEZVOUS_PROTOCOL, ProtocolSupport::Full)],
+            request_response::Config::default(),
+        ));
+
+        let identify = behaviours.identify.then(|| identify::Behaviour::new(
+            identify::Config::new(IDENTIFY_PROTOCOL.to_string(), key.public()).with_agent_version(AGENT_VERSION.to_string()),
+        ));
+
+        let ping = behaviours.ping.then(ping::Behaviour::default);
+
+        let request_response = behaviours.request_response.then(|| request_response::Behaviour::with_codec(
+            BytesCodec,
+            [(REQUEST_PROTOCOL, ProtocolSupport::Full)],
+            request_response::Config::default(),
+        ));
+
+        let autonat = behaviours.autonat.then(|| autonat::Behaviour::new(peer_id, autonat::Config::default()));
+
+        let blocked = self.blocked.as_ref().map(|peers| {
+            let mut blocked = allow_block_list::Behaviour::<allow_block_list::BlockedPeers>::default();
+            for &peer in peers {
+                blocked.block_peer(peer);
+            }
+            blocked
+        });
+
+        Ok(MyBehaviour {
+            gossipsub,
+            mdns: mdns.into(),
+            kad: kad.into(),
+            relay: relay.into(),
+            relay_client: relay_client.into(),
+            dcutr: dcutr.into(),
+            rendezvous: rendezvous.into(),
+            identify: identify.into(),
+            ping: ping.into(),
+            request_response: request_response.into(),
+            autonat: autonat.into(),
+            blocked: blocked.into(),
+        })
+    }
+}
+
+/// A peer subscribed to a room, as listed by [`Network::mesh`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct MeshPeer {
+    pub peer_id: PeerId,
+    /// Whether messages are forwarded to it, rather than it only hearing of them by gossip.
+    pub in_mesh: bool,
+    pub rtt: Option<Duration>,
+    pub score: Option<f64>,
+}
+
+/// A peer with an open connection, as listed by [`Network::connected`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectedPeer {
+    pub peer_id: PeerId,
+    /// Topics it is subscribed to, as far as gossipsub knows.
+    pub topics: Vec<String>,
+    /// Round trip of its last ping.
+    pub rtt: Option<Duration>,
+    /// Its gossipsub score, if peers are scored.
+    pub score: Option<f64>,
+    /// Whether its score fell below the graylist threshold, so whatever it sends is ignored.
+    pub graylisted: bool,
+    /// Its software and protocol versions, once identify told them.
+    pub agent: Option<Agent>,
+}
+
+/// What a peer says it runs, as exchanged by identify.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Agent {
+    /// Such as `p2p-notepad/0.1.0`.
+    pub version: String,
+    pub protocol: String,
+}
+
+/// The libp2p [`Transport`]: gossipsub rooms over tcp and quic, with the
+/// optional behaviours for finding and reaching peers.
+pub struct Network {
+    swarm: Swarm<MyBehaviour>,
+    flood_publish: bool,
+    fanout: Fanout,
+    /// Relay listened through and dialed through by [`Network::dial_relayed`].
+    relay: Option<Multiaddr>,
+    /// Whether a slot on the relay was asked for, see [`Network::reserve_relay`].
+    relay_reserved: bool,
+    /// Rendezvous point the rooms joined are registered at, see [`Network::register`].
+    rendezvous: Option<PeerId>,
+    /// Rooms registered at this node, if it serves as a rendezvous point.
+    registry: Option<Registry>,
+    /// Addresses dialed with [`Network::dial`] that haven't connected yet, by connection.
+    dialing: HashMap<ConnectionId, Multiaddr>,
+    /// Channels of requests that haven't been answered yet, by the id given out in [`Event::Request`].
+    responses: HashMap<u64, ResponseChannel<Vec<u8>>>,
+    next_request: u64,
+    /// Peers warned about for publishing messages this build can't read, so it's only once each.
+    newer_peers: HashSet<PeerId>,
+    /// What connected peers run, by peer id, see [`Agent`].
+    agents: HashMap<PeerId, Agent>,
+    /// Peers from the config or dialed by hand kept connected, see [`StaticPeers`].
+    static_peers: StaticPeers,
+    /// Pings in a row each connected peer didn't answer, see [`PING_FAILURES`].
+    ping_failures: HashMap<PeerId, u32>,
+    /// How rooms' topics are scored as they are joined, if at all.
+    scoring: Option<Scoring>,
+}
+
+/// Peers given in the config or dialed by hand, dialed again whenever the
+/// connection to one drops or dialing it fails, waiting longer after every
+/// failure in a row. Those dialed by hand are given up on after as many
+/// failures in a row as the retry policy's attempts, those in the config never.
+#[derive(Debug, Default)]
+struct StaticPeers {
+    peers: HashMap<PeerId, StaticPeer>,
+    policy: RetryPolicy,
+}
+
+#[derive(Debug)]
+struct StaticPeer {
+    address: Multiaddr,
+    /// Dialed by hand rather than given in the config.
+    manual: bool,
+    /// Dials that failed since it was last connected.
+    failures: u32,
+    /// When to dial it again, if it isn't connected.
+    due: Option<Instant>,
+}
+
+impl StaticPeers {
+    /// Addresses have to end in `/p2p/<peer id>`, others are left out.
+    fn new(addresses: &[Multiaddr], policy: RetryPolicy) -> Self {
+        let peers = addresses
+            .iter()
+            .filter_map(|address| match address.iter().last() {
+                Some(Protocol::P2p(peer_id)) => Some((peer_id, StaticPeer { address: address.clone(), manual: false, failures: 0, due: None })),
+                _ => None,
+            })
+            .collect();
+
+        Self { peers, policy }
+    }
+
+    /// Keeps `peer_id`, dialed by hand at `address`, connected from now on,
+    /// unless it already is.
+    fn add(&mut self, peer_id: PeerId, address: Multiaddr) {
+        self.peers.entry(peer_id).or_insert(StaticPeer { address, manual: true, failures: 0, due: None });
+    }
+
+    fn connected(&mut self, peer_id: &PeerId) {
+        if let Some(peer) = self.peers.get_mut(peer_id) {
+            peer.failures = 0;
+            peer.due = None;
+        }
+    }
+
+    /// Schedules dialing `peer_id` again, after its connection dropped or a
+    /// dial failed, returning its address if it was dialed by hand and is
+    /// given up on instead.
+    fn lost(&mut self, peer_id: &PeerId, now: Instant) -> Option<Multiaddr> {
+        let peer = self.peers.get_mut(peer_id)?;
+        peer.failures += 1;
+
+        if peer.manual && peer.failures > self.policy.attempts {
+            return self.peers.remove(peer_id).map(|peer| peer.address);
+        }
+        peer.due = Some(now + self.policy.delay(peer.failures));
+
+        None
+    }
+
+    /// When the next peer is to be dialed again.
+    fn next_due(&self) -> Option<Instant> {
+        self.peers.values().filter_map(|peer| peer.due).min()
+    }
+
+    /// The addresses of the peers due to be dialed again by `now`.
+    fn due(&mut self, now: Instant) -> Vec<Multiaddr> {
+        self.peers
+            .values_mut()
+            .filter(|peer| peer.due.is_some_and(|due| due <= now))
+            .map(|peer| {
+                peer.due = None;
+                peer.address.clone()
+            })
+            .collect()
+    }
+}
+
+impl Network {
+    pub fn new(config: &Config) -> Result<Self, NotepadError> {
+        let mut swarm = SwarmFactory::new(config).build()?;
+
+        for address in config.listen_addresses() {
+            swarm.listen_on(address).map_err(NotepadError::network)?;
+        }
+
+        // Rooms are registered once connected to it.
+        let mut rendezvous = None;
+        if let Some(address) = &config.rendezvous {
+            if let Some(Protocol::P2p(peer_id)) = address.iter().last() {
+                swarm.dial(address.clone()).map_err(NotepadError::network)?;
+                rendezvous = Some(peer_id);
+            }
+        }
+
+        let mut network = Self {
+            swarm,
+            relay: config.relay.clone(),
+            relay_reserved: false,
+            rendezvous,
+            registry: config.behaviours.rendezvous.then(Registry::default),
+            flood_publish: config.flood_publish,
+            fanout: Fanout::default(),
+            dialing: HashMap::new(),
+            responses: HashMap::new(),
+            next_request: 0,
+            newer_peers: HashSet::new(),
+            agents: HashMap::new(),
+            static_peers: StaticPeers::new(&config.peers, config.retry),
+            ping_failures: HashMap::new(),
+            scoring: config.scoring.enabled.then_some(config.scoring),
+        };
+
+        // With autonat, only once peers find this one unreachable.
+        if !config.behaviours.autonat {
+            network.reserve_relay()?;
+        }
+
+        for address in &config.bootstrap {
+            network.add_bootstrap(address.clone())?;
+        }
+        if !config.bootstrap.is_empty() {
+            network.bootstrap()?;
+        }
+
+        for address in &config.peers {
+            network.dial(&address.to_string())?;
+        }
+
+        Ok(network)
+    }
+
+    /// Reserves a slot on the relay, if one is set, for peers to reach this
+    /// one through it.
+    fn reserve_relay(&mut self) -> Result<(), NotepadError> {
+        if let Some(relay) = self.relay.as_ref().filter(|_| !self.relay_reserved) {
+            self.swarm
+                .listen_on(relay.clone().with(Protocol::P2pCircuit))
+                .map_err(NotepadError::network)?;
+            self.relay_reserved = true;
+        }
+
+        Ok(())
+    }
+
+    /// Dials the static peers due again that aren't connected by now.
+    fn redial_static_peers(&mut self) {
+        for address in self.static_peers.due(Instant::now()) {
+            let Some(Protocol::P2p(peer_id)) = address.iter().last() else {
+                continue;
+            };
+            if self.swarm.is_connected(&peer_id) {
+                continue;
+            }
+
+            println!("Dialing {address} again");
+            if let Err(e) = self.dial(&address.to_string()) {
+                output::error(&format!("Dialing {address} failed: {e}"));
+                self.lost_static_peer(&peer_id);
+            }
+        }
+    }
+
+    /// Schedules dialing a static peer again, reporting it if it is given up on.
+    fn lost_static_peer(&mut self, peer_id: &PeerId) {
+        if let Some(address) = self.static_peers.lost(peer_id, Instant::now()) {
+            output::error(&format!("Gave up on dialing {address} again, dial it by hand once it is back"));
+        }
+    }
+
+    fn kad(&mut self) -> Result<&mut kad::Behaviour<MemoryStore>, NotepadError> {
+        self.swarm.behaviour_mut().kad
+            .as_mut()
+            .ok_or_else(|| NotepadError::network("kad behaviour is disabled"))
+    }
+
+    /// Adds a node to join the DHT through, dialing it.
+    pub fn add_bootstrap(&mut self, address: Multiaddr) -> Result<(), NotepadError> {
+        let Some(Protocol::P2p(peer_id)) = address.iter().last() else {
+            return Err(NotepadError::command(format!("Bootstrap address `{address}` doesn't end in /p2p/<peer id>")));
+        };
+
+        self.kad()?.add_address(&peer_id, address.clone());
+        self.dial(&address.to_string())
+    }
+
+    /// Closes and refuses connections with `peer`, if blocked peers are refused.
+    pub fn block(&mut self, peer: PeerId) {
+        if let Some(blocked) = self.swarm.behaviour_mut().blocked.as_mut() {
+            blocked.block_peer(peer);
+        }
+    }
+
+    pub fn unblock(&mut self, peer: PeerId) {
+        if let Some(blocked) = self.swarm.behaviour_mut().blocked.as_mut() {
+            blocked.unblock_peer(peer);
+        }
+    }
+
+    /// Joins the DHT through the nodes known to it and looks up peers
+    /// providing every subscribed topic, to dial them.
+    pub fn bootstrap(&mut self) -> Result<(), NotepadError> {
+        self.kad()?.bootstrap().map_err(|_| NotepadError::network("No bootstrap nodes known, add one with `bootstrap:<multiaddr>`"))?;
+
+        let topics: Vec<_> = self.swarm.behaviour().gossipsub.topics().cloned().collect();
+        for topic in topics {
+            self.kad()?.get_providers(kad::RecordKey::new(&topic.as_str()));
+        }
+
+        Ok(())
+    }
+
+    /// Dials `address`, such as one another peer printed on starting, for
+    /// peers mDNS can't find. The peer is made an explicit gossipsub peer,
+    /// so every message reaches it whether or not it makes the mesh, and if
+    /// the address ends in its peer id it is dialed again whenever the
+    /// connection drops, see [`StaticPeers`].
+    pub fn dial(&mut self, address: &str) -> Result<(), NotepadError> {
+        let address: Multiaddr = address.parse().map_err(|e| NotepadError::command(format!("Invalid address `{address}`: {e}")))?;
+
+        // Without a peer id it is known once connected.
+        if let Some(Protocol::P2p(peer_id)) = address.iter().last() {
+            self.swarm.behaviour_mut().gossipsub.add_explicit_peer(&peer_id);
+            self.static_peers.add(peer_id, address.clone());
+        }
+
+        let dial = DialOpts::from(address.clone());
+        let connection_id = dial.connection_id();
+        self.swarm.dial(dial).map_err(NotepadError::network)?;
+        self.dialing.insert(connection_id, address);
+
+        Ok(())
+    }
+
+    /// Dials `peer` through the configured relay, for peers behind NAT.
+    /// The connection is upgraded to a direct one if hole punching succeeds.
+    pub fn dial_relayed(&mut self, peer: &str) -> Result<(), NotepadError> {
+        let relay = self.relay.as_ref().ok_or_else(|| NotepadError::command("No relay configured, start with `--relay <multiaddr>`"))?;
+        let peer_id: PeerId = peer.parse().map_err(|e| NotepadError::command(format!("Invalid peer id `{peer}`: {e}")))?;
+        let address = relay.clone().with(Protocol::P2pCircuit).with(Protocol::P2p(peer_id));
+
+        self.dial(&address.to_string())
+    }
+
+    /// Registers this peer at the rendezvous point under `topic`, answered
+    /// with the others registered under it to dial.
+    fn register(&mut self, topic: &str) {
+        let addresses = self.swarm.external_addresses().cloned().collect();
+        self.send_rendezvous(&rendezvous::Request::Register { topic: topic.to_string(), addresses });
+    }
+
+    fn send_rendezvous(&mut self, request: &rendezvous::Request) {
+        let Some(rendezvous_node) = self.rendezvous.filter(|peer_id| self.swarm.is_connected(peer_id)) else {
+            return;
+        };
+
+        if let Some(client) = self.swarm.behaviour_mut().rendezvous.as_mut() {
+            client.send_request(&rendezvous_node, request.into());
+        }
+    }
+
+    /// Every peer connected to, by peer id.
+    pub fn connected(&self) -> Vec<ConnectedPeer> {
+        let gossipsub = &self.swarm.behaviour().gossipsub;
+        let topics: HashMap<_, _> = gossipsub.all_peers().collect();
+
+        let mut peers: Vec<_> = self.swarm.connected_peers()
+            .map(|&peer_id| {
+                let mut topics: Vec<_> = topics.get(&peer_id).into_iter().flatten().map(|topic| topic.to_string()).collect();
+                topics.sort();
+                let score = gossipsub.peer_score(&peer_id);
+                let graylisted = score.zip(self.scoring).is_some_and(|(score, scoring)| score < scoring.graylist_threshold);
+
+                ConnectedPeer { peer_id, topics, rtt: self.fanout.last(&peer_id), score, graylisted, agent: self.agents.get(&peer_id).cloned() }
+            })
+            .collect();
+        peers.sort_by_key(|peer| peer.peer_id);
+
+        peers
+    }
+
+    /// The peers subscribed to `topic`, those in its mesh first, each by round trip.
+    pub fn mesh(&self, topic: &str) -> Vec<MeshPeer> {
+        let gossipsub = &self.swarm.behaviour().gossipsub;
+        let topic = gossipsub::IdentTopic::new(topic).hash();
+        let mesh: Vec<_> = gossipsub.mesh_peers(&topic).collect();
+
+        let mut peers: Vec<_> = gossipsub.all_peers()
+            .filter(|(_, topics)| topics.contains(&&topic))
+            .map(|(&peer_id, _)| MeshPeer {
+                peer_id,
+                in_mesh: mesh.contains(&&peer_id),
+                rtt: self.fanout.rtt(&peer_id),
+                score: gossipsub.peer_score(&peer_id),
+            })
+            .collect();
+        peers.sort_by_key(|peer| (!peer.in_mesh, peer.rtt.unwrap_or(Duration::MAX)));
+
+        peers
+    }
+
+    /// Shuts the swarm down before the node exits: sends what is still
+    /// queued, such as the unsubscriptions of the rooms left, then closes
+    /// every connection, giving each step at most `grace`. Each step ends as
+    /// soon as it is done, the first once the swarm goes quiet.
+    pub async fn shutdown(&mut self, grace: Duration) {
+        let _ = tokio::time::timeout(grace, async {
+            while tokio::time::timeout(FLUSH_IDLE, self.swarm.select_next_some()).await.is_ok() {}
+        }).await;
+
+        let peers: Vec<_> = self.swarm.connected_peers().copied().collect();
+        for peer_id in peers {
+            let _ = self.swarm.disconnect_peer_id(peer_id);
+        }
+
+        let _ = tokio::time::timeout(grace, async {
+            while self.swarm.connected_peers().next().is_some() {
+                self.swarm.select_next_some().await;
+            }
+        }).await;
+    }
+
+    /// Number of peers a message published on `topic` is sent to.
+    fn room_peers(&self, topic: &gossipsub::TopicHash) -> usize {
+        let gossipsub = &self.swarm.behaviour().gossipsub;
+
+        if self.flood_publish {
+            gossipsub.all_peers().filter(|(_, topics)| topics.contains(&topic)).count()
+        } else {
+            gossipsub.mesh_peers(topic).count()
+        }
+    }
+
+    fn request_response(&mut self) -> Result<&mut request_response::Behaviour<BytesCodec>, NotepadError> {
+        self.swarm.behaviour_mut().request_response
+            .as_mut()
+            .ok_or_else(|| NotepadError::network("request-response behaviour is disabled"))
+    }
+}
+
+#[async_trait]
+impl Transport for Network {
+    fn peer_id(&self) -> PeerId {
+        *self.swarm.local_peer_id()
+    }
+
+    fn publish(&mut self, topic: &str, data: Vec<u8>) -> Result<usize, NotepadError> {
+        let topic = gossipsub::IdentTopic::new(topic);
+
+        match self.swarm.behaviour_mut().gossipsub.publish(topic.clone(), data) {
+            Ok(_) => Ok(self.room_peers(&topic.hash())),
+            Err(gossipsub::PublishError::InsufficientPeers) => Ok(0),
+            Err(e @ gossipsub::PublishError::TransformFailed(_)) => Err(NotepadError::Transient(e.to_string())),
+            Err(e) => Err(NotepadError::network(e)),
+        }
+    }
+
+    /// Also advertises this peer as a provider of the topic in the DHT, if
+    /// kad is enabled, and looks up the others.
+    fn subscribe(&mut self, topic: &str) -> Result<(), NotepadError> {
+        let gossipsub = &mut self.swarm.behaviour_mut().gossipsub;
+        gossipsub
+            .subscribe(&gossipsub::IdentTopic::new(topic))
+            .map_err(NotepadError::network)?;
+
+        if let Some(scoring) = self.scoring {
+            gossipsub.set_topic_params(gossipsub::IdentTopic::new(topic), scoring.topic_params()).map_err(NotepadError::network)?;
+        }
+
+        if let Some(kad) = self.swarm.behaviour_mut().kad.as_mut() {
+            let key = kad::RecordKey::new(&topic);
+
+            if let Err(e) = kad.start_providing(key.clone()) {
+                println!("Couldn't provide the room in the DHT: {e}");
+            }
+            kad.get_providers(key);
+        }
+
+        self.register(topic);
+
+        Ok(())
+    }
+
+    fn unsubscribe(&mut self, topic: &str) -> Result<(), NotepadError> {
+        self.swarm.behaviour_mut().gossipsub
+            .unsubscribe(&gossipsub::IdentTopic::new(topic))
+            .map_err(NotepadError::network)?;
+
+        if let Some(kad) = self.swarm.behaviour_mut().kad.as_mut() {
+            kad.stop_providing(&kad::RecordKey::new(&topic));
+        }
+
+        self.send_rendezvous(&rendezvous::Request::Unregister { topic: topic.to_string() });
+
+        Ok(())
+    }
+
+    fn request(&mut self, peer: &PeerId, data: Vec<u8>) -> Result<(), NotepadError> {
+        self.request_response()?.send_request(peer, data);
+
+        Ok(())
+    }
+
+    fn respond(&mut self, id: u64, data: Vec<u8>) -> Result<(), NotepadError> {
+        let channel = self.responses.remove(&id).ok_or_else(|| NotepadError::network(format!("Unknown request {id}")))?;
+
+        self.request_response()?
+            .send_response(channel, data)
+            .map_err(|_| NotepadError::network("Requesting peer is gone"))
+    }
+
+    fn rtt(&self, peer: &PeerId) -> Option<Duration> {
+        self.fanout.rtt(peer)
+    }
+
+    async fn next_event(&mut self) -> Option<Event> {
+        loop {
+            let event = match self.static_peers.next_due() {
+                Some(due) => tokio::select! {
+                    event = self.swarm.select_next_some() => event,
+                    _ = tokio::time::sleep_until(due.into()) => {
+                        self.redial_static_peers();
+                        continue;
+                    },
+                },
+                None => self.swarm.select_next_some().await,
+            };
+            let _span = tracing::debug_span!("swarm_event").entered();
+            tracing::trace!(?event);
+
+            match &event {
+                SwarmEvent::ConnectionEstablished { peer_id, num_established, .. } => {
+                    self.static_peers.connected(peer_id);
+
+                    if num_established.get() == 1 && Some(*peer_id) == self.rendezvous {
+                        println!("Connected to the rendezvous point, registering the rooms joined");
+                        let topics: Vec<_> = self.swarm.behaviour().gossipsub.topics().map(|topic| topic.to_string()).collect();
+                        for topic in topics {
+                            self.register(&topic);
+                        }
+                    }
+                },
+                SwarmEvent::ConnectionClosed { peer_id, num_established: 0, .. }
+                | SwarmEvent::OutgoingConnectionError { peer_id: Some(peer_id), .. } => self.lost_static_peer(peer_id),
+                _ => {},
+            }
+
+            match event {
+                SwarmEvent::Behaviour(MyBehaviourEvent::Mdns(mdns::Event::Discovered(list))) => {
+                    for (peer_id, multiaddr) in list {
+                        output::event(
+                            "peer_discovered",
+                            &[("peer", (&peer_id.to_string()).into()), ("address", (&multiaddr.to_string()).into())],
+                            Some(&format!("mDNS discovered a new peer: {peer_id}"))
+                        );
+                        let behaviour = self.swarm.behaviour_mut();
+
+                        behaviour.gossipsub.add_explicit_peer(&peer_id);
+                        if let Some(kad) = behaviour.kad.as_mut() {
+                            kad.add_address(&peer_id, multiaddr);
+                        }
+                    }
+                },
+                SwarmEvent::Behaviour(MyBehaviourEvent::Mdns(mdns::Event::Expired(list))) => {
+                    for (peer_id, _multiaddr) in list {
+                        output::event(
+                            "peer_expired",
+                            &[("peer", (&peer_id.to_string()).into())],
+                            Some(&format!("mDNS discover peer has expired: {peer_id}"))
+                        );
+                        self.swarm.behaviour_mut().gossipsub.remove_explicit_peer(&peer_id);
+                    }
+                },
+                SwarmEvent::Behaviour(MyBehaviourEvent::Gossipsub(gossipsub::Event::Message {
+                    propagation_source,
+                    message_id,
+                    message,
+                })) => {
+                    let check = message::check(&message.data);
+                    let acceptance = match check {
+                        Check::Valid => gossipsub::MessageAcceptance::Accept,
+                        // Not held against the peer, it may just run a newer build.
+                        Check::Unknown => gossipsub::MessageAcceptance::Ignore,
+                        Check::Malformed => gossipsub::MessageAcceptance::Reject,
+                    };
+                    if let Err(e) = self.swarm.behaviour_mut().gossipsub.report_message_validation_result(&message_id, &propagation_source, acceptance) {
+                        output::error(&format!("Couldn't forward the message from {propagation_source}: {e}"));
+                    }
+
+                    match check {
+                        Check::Valid => {},
+                        Check::Unknown => {
+                            let peer_id = message.source.unwrap_or(propagation_source);
+                            if self.newer_peers.insert(peer_id) {
+                                println!("Ignoring messages from {peer_id} in a newer version of the protocol, update to read them");
+                            }
+                            continue;
+                        },
+                        Check::Malformed => {
+                            println!("Dropped malformed message from {propagation_source}, it isn't forwarded");
+                            continue;
+                        },
+                    }
+
+                    return Some(Event::Message(Incoming {
+                        topic: message.topic.into_string(),
+                        source: message.source,
+                        data: message.data,
+                    }));
+                },
+                SwarmEvent::Behaviour(MyBehaviourEvent::Gossipsub(gossipsub::Event::Subscribed { peer_id, topic })) => {
+                    return Some(Event::Subscribed { peer: peer_id, topic: topic.into_string() });
+                },
+                SwarmEvent::Behaviour(MyBehaviourEvent::Gossipsub(gossipsub::Event::Unsubscribed { peer_id, topic })) => {
+                    return Some(Event::Unsubscribed { peer: peer_id, topic: topic.into_string() });
+                },
+                SwarmEvent::Behaviour(MyBehaviourEvent::Identify(identify::Event::Received { peer_id, info, .. })) => {
+                    self.agents.insert(peer_id, Agent { version: info.agent_version, protocol: info.protocol_version });
+                    // Registrations carry external addresses, and the
+                    // rendezvous point sees this peer's from outside.
+                    if Some(peer_id) == self.rendezvous {
+                        self.swarm.add_external_address(info.observed_addr.clone());
+                    }
+                    if let Some(kad) = self.swarm.behaviour_mut().kad.as_mut() {
+                        for address in info.listen_addrs {
+                            kad.add_address(&peer_id, address);
+                        }
+                    }
+                },
+                SwarmEvent::Behaviour(MyBehaviourEvent::Kad(kad::Event::OutboundQueryProgressed {
+                    result: kad::QueryResult::GetProviders(Ok(kad::GetProvidersOk::FoundProviders { providers, .. })),
+                    ..
+                })) => {
+                    for peer_id in providers {
+                        if peer_id == *self.swarm.local_peer_id() || self.swarm.is_connected(&peer_id) {
+                            continue;
+                        }
+
+                        println!("Found {peer_id} in the DHT, dialing it");
+                        if let Err(e) = self.swarm.dial(peer_id) {
+                            println!("Dialing {peer_id} failed: {e}");
+                        }
+                    }
+                },
+                SwarmEvent::Behaviour(MyBehaviourEvent::RequestResponse(request_response::Event::Message {
+                    peer,
+                    message: request_response::Message::Request { request, channel, .. },
+                })) => {
+                    let id = self.next_request;
+                    self.next_request += 1;
+                    self.responses.insert(id, channel);
+
+                    return Some(Event::Request { id, peer, data: request });
+                },
+                SwarmEvent::Behaviour(MyBehaviourEvent::RequestResponse(request_response::Event::Message {
+                    peer,
+                    message: request_response::Message::Response { response, .. },
+                })) => {
+                    return Some(Event::Response { peer, data: response });
+                },
+                SwarmEvent::Behaviour(MyBehaviourEvent::RequestResponse(request_response::Event::OutboundFailure { peer, error, .. })) => {
+                    println!("Request to {peer} failed: {error}");
+                },
+                SwarmEvent::Behaviour(MyBehaviourEvent::RelayClient(relay::client::Event::ReservationReqAccepted { relay_peer_id, renewal: false, .. })) => {
+                    println!("Reserved a slot on relay {relay_peer_id}, peers behind NAT can reach this one through it");
+                },
+                SwarmEvent::Behaviour(MyBehaviourEvent::Dcutr(dcutr::Event { remote_peer_id, result })) => match result {
+                    Ok(_) => println!("Upgraded the relayed connection to {remote_peer_id} to a direct one"),
+                    Err(e) => println!("Hole punching to {remote_peer_id} failed, staying relayed: {e}"),
+                },
+                SwarmEvent::Behaviour(MyBehaviourEvent::Rendezvous(request_response::Event::Message {
+                    peer,
+                    message: request_response::Message::Request { request, channel, .. },
+                })) => {
+                    // Peers that aren't one only get their stream closed.
+                    let Some(registry) = self.registry.as_mut() else {
+                        continue;
+                    };
+                    let response = match rendezvous::Request::try_from(&request[..]) {
+                        Ok(request) => registry.handle(peer, request),
+                        Err(e) => {
+                            println!("Dropped malformed rendezvous request from {peer}: {e}");
+                            continue;
+                        },
+                    };
+
+                    if let Some(server) = self.swarm.behaviour_mut().rendezvous.as_mut() {
+                        let _ = server.send_response(channel, response);
+                    }
+                },
+                SwarmEvent::Behaviour(MyBehaviourEvent::Rendezvous(request_response::Event::Message {
+                    message: request_response::Message::Response { response, .. },
+                    ..
+                })) => {
+                    let registrations = match rendezvous::decode_registrations(&response) {
+                        Ok(registrations) => registrations,
+                        Err(e) => {
+                            println!("Couldn't read the rendezvous point's registrations: {e}");
+                            continue;
+                        },
+                    };
+
+                    for registration in registrations {
+                        let peer_id = registration.peer_id;
+                        if peer_id == *self.swarm.local_peer_id() || self.swarm.is_connected(&peer_id) {
+                            continue;
+                        }
+
+                        println!("Found {peer_id} at the rendezvous point, dialing it");
+                        let dial = DialOpts::peer_id(peer_id).addresses(registration.addresses).build();
+                        if let Err(e) = self.swarm.dial(dial) {
+                            println!("Dialing {peer_id} failed: {e}");
+                        }
+                    }
+                },
+                SwarmEvent::Behaviour(MyBehaviourEvent::Rendezvous(request_response::Event::OutboundFailure { error, .. })) => {
+                    println!("Couldn't register the room at the rendezvous point: {error}");
+                },
+                SwarmEvent::Behaviour(MyBehaviourEvent::Ping(ping::Event { peer, result: Ok(rtt), .. })) => {
+                    self.ping_failures.remove(&peer);
+                    let score = self.fanout.sample(peer, rtt);
+                    // Refused when scoring is off, leaving the mesh as it was.
+                    self.swarm.behaviour_mut().gossipsub.set_application_score(&peer, score);
+                },
+                SwarmEvent::Behaviour(MyBehaviourEvent::Ping(ping::Event { peer, result: Err(error), .. })) => {
+                    let failures = self.ping_failures.entry(peer).or_default();
+                    *failures += 1;
+
+                    if *failures >= PING_FAILURES {
+                        output::error(&format!("{peer} stopped answering pings ({error}), disconnecting"));
+                        let _ = self.swarm.disconnect_peer_id(peer);
+                    }
+                },
+                SwarmEvent::Behaviour(MyBehaviourEvent::Autonat(autonat::Event::StatusChanged { new, .. })) => {
+                    let (status, line) = match &new {
+                        autonat::NatStatus::Public(address) => ("public", format!("Reachable from outside at {address}")),
+                        autonat::NatStatus::Private => ("private", "Not reachable from outside, peers connect through the relay or hole punching".to_string()),
+                        autonat::NatStatus::Unknown => ("unknown", "Reachability from outside is unknown".to_string()),
+                    };
+                    output::event("nat_status", &[("status", status.into())], Some(&line));
+
+                    if matches!(new, autonat::NatStatus::Private) {
+                        if let Err(e) = self.reserve_relay() {
+                            output::error(&format!("Couldn't reserve a slot on the relay: {e}"));
+                        }
+                    }
+                },
+                SwarmEvent::ConnectionClosed { peer_id, num_established: 0, .. } => {
+                    self.fanout.remove(&peer_id);
+                    self.agents.remove(&peer_id);
+                    self.ping_failures.remove(&peer_id);
+                    if let Some(registry) = self.registry.as_mut() {
+                        registry.remove(&peer_id);
+                    }
+                    // Gossipsub would otherwise dial it every heartbeat. Static
+                    // peers are dialed with backoff instead, and every peer is
+                    // made explicit again once it connects.
+                    self.swarm.behaviour_mut().gossipsub.remove_explicit_peer(&peer_id);
+                },
+                SwarmEvent::ConnectionEstablished { peer_id, connection_id, .. } if self.dialing.contains_key(&connection_id) => {
+                    let address = self.dialing.remove(&connection_id).expect("dial is pending");
+                    output::event(
+                        "connected",
+                        &[("peer", (&peer_id.to_string()).into()), ("address", (&address.to_string()).into())],
+                        Some(&format!("Connected to {peer_id} at {address}"))
+                    );
+                    self.swarm.behaviour_mut().gossipsub.add_explicit_peer(&peer_id);
+                },
+                // Other dials, e.g. by kad, fail quietly.
+                SwarmEvent::OutgoingConnectionError { connection_id, error, .. } if self.dialing.contains_key(&connection_id) => {
+                    let address = self.dialing.remove(&connection_id).expect("dial is pending");
+                    output::error(&format!("Dialing {address} failed: {error}"));
+                },
+                SwarmEvent::ExternalAddrConfirmed { address } => {
+                    let address = format!("{address}/p2p/{}", self.swarm.local_peer_id());
+                    output::event("external_address", &[("address", (&address).into())], Some(&format!("Reachable from outside at {address}")));
+                },
+                SwarmEvent::NewListenAddr { address, .. } => {
+                    // With the peer id appended, as peers elsewhere dial it.
+                    let address = format!("{address}/p2p/{}", self.swarm.local_peer_id());
+                    output::event("listening", &[("address", (&address).into())], Some(&format!("Local node is listening on {address}")));
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Sends requests and responses as raw bytes, the payload is up to the protocol using it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BytesCodec;
+
+#[async_trait]
+impl request_response::Codec for BytesCodec {
+    type Protocol = StreamProtocol;
+    type Request = Vec<u8>;
+    type Response = Vec<u8>;
+
+    async fn read_request<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<Vec<u8>>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_bytes(io).await
+    }
+
+    async fn read_response<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<Vec<u8>>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_bytes(io).await
+    }
+
+    async fn write_request<T>(&mut self, _: &StreamProtocol, io: &mut T, request: Vec<u8>) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_bytes(io, request).await
+    }
+
+    async fn write_response<T>(&mut self, _: &StreamProtocol, io: &mut T, response: Vec<u8>) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_bytes(io, response).await
+    }
+}
+
+async fn read_bytes(io: &mut (impl AsyncRead + Unpin + Send)) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    io.take(MAX_REQUEST_LEN).read_to_end(&mut data).await?;
+
+    Ok(data)
+}
+
+async fn write_bytes(io: &mut (impl AsyncWrite + Unpin + Send), data: Vec<u8>) -> io::Result<()> {
+    io.write_all(&data).await?;
+    io.close().await
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::select;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn factory_toggles_behaviours() {
+        let swarm = SwarmFactory::new(&Config::default()).build().unwrap();
+        let behaviour = swarm.behaviour();
+
+        assert!(behaviour.mdns.is_enabled() && behaviour.identify.is_enabled() && behaviour.ping.is_enabled());
+        assert!(!behaviour.kad.is_enabled() && !behaviour.relay.is_enabled() && behaviour.request_response.is_enabled());
+        assert!(!behaviour.relay_client.is_enabled() && !behaviour.dcutr.is_enabled() && !behaviour.rendezvous.is_enabled());
+
+        let config = Config {
+            behaviours: "kad,relay,rendezvous".parse().unwrap(),
+            ..Config::default()
+        };
+        let swarm = SwarmFactory::new(&config).build().unwrap();
+        let behaviour = swarm.behaviour();
+
+        assert!(!behaviour.mdns.is_enabled() && !behaviour.identify.is_enabled() && !behaviour.ping.is_enabled());
+        assert!(behaviour.kad.is_enabled() && behaviour.relay.is_enabled() && !behaviour.request_response.is_enabled());
+        assert!(!behaviour.autonat.is_enabled() && behaviour.rendezvous.is_enabled());
+
+        let config = Config {
+            relay: Some("/ip4/203.0.113.7/tcp/4001/p2p/12D3KooWDpJ7As7BWAwRMfu1VU2WCqNjvq387JEYKDBj4kx6nXTN".parse().unwrap()),
+            rendezvous: Some("/ip4/203.0.113.7/tcp/4002/p2p/12D3KooWDpJ7As7BWAwRMfu1VU2WCqNjvq387JEYKDBj4kx6nXTN".parse().unwrap()),
+            ..Config::default()
+        };
+        let swarm = SwarmFactory::new(&config).build().unwrap();
+        assert!(swarm.behaviour().relay_client.is_enabled() && swarm.behaviour().dcutr.is_enabled());
+        assert!(swarm.behaviour().rendezvous.is_enabled());
+    }
+
+    #[tokio::test]
+    async fn factory_keeps_the_identity() {
+        let path = std::env::temp_dir().join(format!("p2p-notepad-node-{}.key", rand::random::<u64>()));
+        let config = Config { identity: Some(path.clone()), ..Config::default() };
+
+        let first = *SwarmFactory::new(&config).build().unwrap().local_peer_id();
+        assert_eq!(*SwarmFactory::new(&config).build().unwrap().local_peer_id(), first);
+        assert_ne!(*SwarmFactory::new(&Config::default()).build().unwrap().local_peer_id(), first);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn dials_addresses_peers_share() {
+        let mut a = Network::new(&Config::default()).unwrap();
+        let mut b = Network::new(&Config::default()).unwrap();
+        let address = format!("/ip4/127.0.0.1/tcp/{}/p2p/{}", listening_port(&mut b).await, b.swarm.local_peer_id());
+
+        assert!(a.dial("localhost:4001").is_err());
+        a.dial(&address).unwrap();
+        assert_eq!(a.dialing.len(), 1);
+
+        // Both ends are driven until the connection is up.
+        let b_id = *b.swarm.local_peer_id();
+        while !a.swarm.is_connected(&b_id) {
+            select! {
+                _ = a.swarm.select_next_some() => {},
+                _ = b.swarm.select_next_some() => {},
+            }
+        }
+        assert!(a.connected().iter().any(|peer| peer.peer_id == b_id));
+    }
+
+    #[test]
+    fn static_peers_are_dialed_again_with_backoff() {
+        let address: Multiaddr = "/ip4/203.0.113.7/tcp/4001/p2p/12D3KooWDpJ7As7BWAwRMfu1VU2WCqNjvq387JEYKDBj4kx6nXTN".parse().unwrap();
+        let Some(Protocol::P2p(peer_id)) = address.iter().last() else { unreachable!() };
+        let policy = RetryPolicy { attempts: 5, backoff: Duration::from_secs(1) };
+        let mut peers = StaticPeers::new(&[address.clone(), "/ip4/203.0.113.7/tcp/4002".parse().unwrap()], policy);
+        let now = Instant::now();
+
+        assert_eq!(peers.peers.len(), 1);
+        assert_eq!(peers.next_due(), None);
+
+        peers.lost(&peer_id, now);
+        peers.lost(&peer_id, now);
+        assert_eq!(peers.next_due(), Some(now + Duration::from_secs(2)));
+        assert!(peers.due(now).is_empty());
+        assert_eq!(peers.due(now + Duration::from_secs(2)), vec![address]);
+        assert_eq!(peers.next_due(), None);
+
+        peers.lost(&peer_id, now);
+        peers.connected(&peer_id);
+        assert_eq!(peers.next_due(), None);
+        peers.lost(&peer_id, now);
+        assert_eq!(peers.next_due(), Some(now + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn peers_dialed_by_hand_are_given_up_on() {
+        let address: Multiaddr = "/ip4/203.0.113.7/tcp/4001/p2p/12D3KooWDpJ7As7BWAwRMfu1VU2WCqNjvq387JEYKDBj4kx6nXTN".parse().unwrap();
+        let Some(Protocol::P2p(peer_id)) = address.iter().last() else { unreachable!() };
+        let policy = RetryPolicy { attempts: 2, backoff: Duration::from_secs(1) };
+        let mut peers = StaticPeers::new(&[], policy);
+        let now = Instant::now();
+
+        peers.add(peer_id, address.clone());
+        assert_eq!(peers.lost(&peer_id, now), None);
+        assert_eq!(peers.lost(&peer_id, now), None);
+        assert_eq!(peers.lost(&peer_id, now), Some(address.clone()));
+        assert!(peers.peers.is_empty());
+
+        // Those in the config are kept however often they fail.
+        let mut peers = StaticPeers::new(std::slice::from_ref(&address), policy);
+        peers.add(peer_id, address);
+        for _ in 0..10 {
+            assert_eq!(peers.lost(&peer_id, now), None);
+        }
+    }
+
+    async fn listening_port(network: &mut Network) -> u16 {
+        loop {
+            if let SwarmEvent::NewListenAddr { address, .. } = network.swarm.select_next_some().await {
+                if let Some(Protocol::Tcp(port)) = address.iter().find(|protocol| matches!(protocol, Protocol::Tcp(_))) {
+                    return port;
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn bytes_codec_round_trip() {
+        let mut buf = Vec::new();
+        write_bytes(&mut futures::io::Cursor::new(&mut buf), b"hello".to_vec()).await.unwrap();
+
+        let data = read_bytes(&mut futures::io::Cursor::new(buf)).await.unwrap();
+        assert_eq!(data, b"hello");
+    }
+}
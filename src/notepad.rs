@@ -1,84 +1,279 @@
-use crate::diff::{Diff, MessageBuf, Operation};
+use std::{cell::OnceCell, fmt};
 
-#[derive(Debug, Default)]
+use crate::{
+    diff::{Diff, MessageBuf, Operation},
+    error::NotepadError,
+    rope::Rope
+};
+
+/// A document's text, held as a [`Rope`] so an edit only shifts the bytes
+/// around it however long the text is. It is read as one string with
+/// [`Notepad::text`], put together when first read after an edit, so a
+/// burst of edits between reads costs one copy of the text rather than one
+/// each.
+#[derive(Default, Clone)]
 pub struct Notepad {
-    pub text: String,
+    rope: Rope,
+    text: OnceCell<String>,
 }
 
 impl Notepad {
-    pub fn apply_message_buf(&mut self, msg: &MessageBuf) {
-        msg.messages.iter().for_each(|d| self.apply_diff(d) );
-    }
-
-    pub fn apply_diff(&mut self, diff: &Diff) {
-        let Diff { opcode, operand, index } = diff;
-        let index = *index as usize;
-
-        match opcode {
-            Operation::Del => {
-                self.remove(index);
-            },
-            Operation::Ins => {
-                self.insert(index, operand.expect("Char not given to Operation: Insert"));
-            },
-            Operation::Rep => {
-                self.replace(index, operand.expect("Char not given to Operation: Rep"));
-            }
-        }
+    pub fn new(text: String) -> Self {
+        Self { rope: Rope::from(text.as_str()), text: OnceCell::from(text) }
+    }
+
+    pub fn text(&self) -> &str {
+        self.text.get_or_init(|| self.rope.to_string())
+    }
+
+    pub fn into_text(self) -> String {
+        self.text.into_inner().unwrap_or_else(|| self.rope.into())
+    }
+
+    /// Length of the text in bytes, without putting it together.
+    pub fn len(&self) -> usize {
+        self.rope.len()
     }
 
-    fn insert(&mut self, index: usize, value: char) {
-        self.text.insert(index, value);
+    pub fn is_empty(&self) -> bool {
+        self.rope.is_empty()
     }
 
-    fn remove(&mut self, index: usize) {
-        self.text.remove(index);
+    /// The characters of the text in order, without putting it together.
+    pub fn chars(&self) -> impl Iterator<Item = char> + '_ {
+        self.rope.chunks().flat_map(str::chars)
+    }
+
+    pub fn set_text(&mut self, text: String) {
+        *self = Self::new(text);
+    }
+
+    /// Approximate bytes held, the rope and the text put together from it.
+    pub fn memory(&self) -> usize {
+        self.rope.memory() + self.text.get().map_or(0, String::capacity)
+    }
+
+    /// Drops the text put together from the rope, until it is next read.
+    pub fn shrink(&mut self) {
+        self.text.take();
+    }
+
+    /// Short hash of the text, so peers can check they are looking at identical documents.
+    pub fn checksum(&self) -> String {
+        let mut hasher = blake3::Hasher::new();
+        self.rope.chunks().for_each(|chunk| { hasher.update(chunk.as_bytes()); });
+
+        hasher.finalize().to_hex()[..8].to_string()
     }
 
-    fn replace(&mut self, index: usize, value: char) {
-        self.remove(index);
-        self.insert(index, value);
+    /// Applies the diffs in order, stopping at the first one that doesn't fit the text.
+    #[tracing::instrument(name = "apply", level = "debug", skip_all, fields(diffs = msg.messages.len(), len = self.len()), err(level = "debug"))]
+    pub fn apply_message_buf(&mut self, msg: &MessageBuf) -> Result<(), NotepadError> {
+        msg.messages.iter().try_for_each(|d| self.apply_diff(d))
     }
 
+    /// Applies the diffs like [`Notepad::apply_message_buf`], returning the
+    /// diffs that revert the ones applied.
+    pub fn apply_inverting(&mut self, msg: &MessageBuf) -> Result<MessageBuf, NotepadError> {
+        self.apply_moving(msg, &mut [])
+    }
+
+    /// Applies the diffs like [`Notepad::apply_inverting`], moving each of
+    /// `positions`, such as the cursor, along with the text after it. A
+    /// position inside deleted text ends up where it was, after anything
+    /// inserted in its place.
+    pub fn apply_moving(&mut self, msg: &MessageBuf, positions: &mut [&mut usize]) -> Result<MessageBuf, NotepadError> {
+        let mut inverse = MessageBuf::default();
+
+        for diff in &msg.messages {
+            let previous = self.rope.char_at(diff.index);
+
+            let (opcode, operand) = match &diff.opcode {
+                Operation::Ins => (Operation::Del, None),
+                Operation::Del => (Operation::Ins, previous),
+                Operation::Rep => (Operation::Rep, previous),
+                Operation::InsStr(text) => (Operation::DelRange(text.len()), None),
+                Operation::DelRange(len) => (Operation::InsStr(self.rope.get(diff.index, diff.index + len).unwrap_or_default()), None),
+            };
+            let removed = match &diff.opcode {
+                Operation::Del | Operation::Rep => previous.map_or(0, char::len_utf8),
+                Operation::DelRange(len) => *len,
+                Operation::Ins | Operation::InsStr(_) => 0,
+            };
+            let inserted = match &diff.opcode {
+                Operation::Ins | Operation::Rep => diff.operand.map_or(0, char::len_utf8),
+                Operation::InsStr(text) => text.len(),
+                Operation::Del | Operation::DelRange(_) => 0,
+            };
+
+            self.apply_diff(diff)?;
+            inverse.messages.push(Diff { opcode, operand, index: diff.index });
+
+            for position in positions.iter_mut().filter(|position| diff.index < ***position) {
+                **position = if diff.index + removed > **position { diff.index + inserted } else { **position - removed + inserted };
+            }
+        }
+
+        inverse.messages.reverse();
+        Ok(inverse)
+    }
+
+    /// Applies one diff, leaving the text untouched if it doesn't fit.
+    pub fn apply_diff(&mut self, diff: &Diff) -> Result<(), NotepadError> {
+        self.rope.apply_diff(diff)?;
+        self.text.take();
+
+        Ok(())
+    }
+}
+
+/// Shows the text alone, as users are shown it by `see` and the render timer.
+impl fmt::Debug for Notepad {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Notepad").field("text", &self.text()).finish()
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn debug_shows_the_text() {
+        let mut notepad = Notepad::new("hello".to_string());
+        notepad.apply_diff(&Diff { opcode: Operation::Ins, operand: Some('!'), index: 5 }).unwrap();
+
+        assert_eq!(format!("{notepad:?}"), r#"Notepad { text: "hello!" }"#);
+    }
+
+    #[test]
+    fn checksum() {
+        let notepad = Notepad::new("hello world".to_string());
+
+        assert_eq!(notepad.checksum().len(), 8);
+        assert_eq!(notepad.checksum(), Notepad::new("hello world".to_string()).checksum());
+        assert_ne!(notepad.checksum(), Notepad::default().checksum());
+    }
+
+    #[test]
+    fn cursor_follows_text() {
+        let mut notepad = Notepad::new("héllo world".to_string());
+        let mut cursor = "héllo ".len();
+
+        let diffs = MessageBuf { messages: vec![
+            Diff { opcode: Operation::Del, operand: None, index: 1 },
+            Diff { opcode: Operation::InsStr("ey".to_string()), operand: None, index: 0 },
+            Diff { opcode: Operation::Ins, operand: Some('!'), index: cursor },
+        ] };
+        notepad.apply_moving(&diffs, &mut [&mut cursor]).unwrap();
+        // Text inserted right at the cursor goes after it.
+        assert_eq!(&notepad.text()[cursor..], "!world");
+
+        let diffs = MessageBuf { messages: vec![Diff { opcode: Operation::DelRange(5), operand: None, index: 3 }] };
+        notepad.apply_moving(&diffs, &mut [&mut cursor]).unwrap();
+        assert_eq!((notepad.text(), cursor), ("eyhworld", 3));
+    }
+
     #[test]
     fn del_diff() {
         let text = "1: This is my notepad\n2: The next line".to_string();
-        let mut notepad = Notepad { text }; 
+        let mut notepad = Notepad::new(text); 
 
         let diff = Diff { opcode: Operation::Del, operand: None, index: 2 };
 
-        notepad.apply_diff(&diff);
+        notepad.apply_diff(&diff).unwrap();
 
-        assert_eq!(&notepad.text, "1:This is my notepad\n2: The next line")
+        assert_eq!(notepad.text(), "1:This is my notepad\n2: The next line")
     }
 
     #[test]
     fn ins_diff() {
         let text = "1: This is my notepad\n2: The next line".to_string();
-        let mut notepad = Notepad { text }; 
+        let mut notepad = Notepad::new(text); 
 
         let diff = Diff { opcode: Operation::Ins, operand: Some('\n'), index: 2 };
 
-        notepad.apply_diff(&diff);
+        notepad.apply_diff(&diff).unwrap();
 
-        assert_eq!(&notepad.text, "1:\n This is my notepad\n2: The next line")
+        assert_eq!(notepad.text(), "1:\n This is my notepad\n2: The next line")
     }
 
     #[test]
     fn rep_diff() {
         let text = "1: This is my notepad\n2: The next line".to_string();
-        let mut notepad = Notepad { text };
+        let mut notepad = Notepad::new(text);
 
         let diff = Diff { opcode: Operation::Rep, operand: Some('3'), index: 22 };
 
-        notepad.apply_diff(&diff);
+        notepad.apply_diff(&diff).unwrap();
 
-        assert_eq!(&notepad.text, "1: This is my notepad\n3: The next line");  
+        assert_eq!(notepad.text(), "1: This is my notepad\n3: The next line");  
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn inverse_diffs() {
+        let mut notepad = Notepad::new("héllo".to_string());
+        let msg = MessageBuf { messages: vec![
+            Diff { opcode: Operation::Del, operand: None, index: 1 },
+            Diff { opcode: Operation::Rep, operand: Some('a'), index: 0 },
+            Diff { opcode: Operation::Ins, operand: Some('!'), index: 4 },
+        ] };
+
+        let inverse = notepad.apply_inverting(&msg).unwrap();
+        assert_eq!(notepad.text(), "allo!");
+
+        notepad.apply_message_buf(&inverse).unwrap();
+        assert_eq!(notepad.text(), "héllo");
+    }
+
+    #[test]
+    fn range_diffs() {
+        let mut notepad = Notepad::new("héllo".to_string());
+        let msg = MessageBuf { messages: vec![
+            Diff { opcode: Operation::InsStr(", wörld".to_string()), operand: None, index: 6 },
+            Diff { opcode: Operation::DelRange(3), operand: None, index: 0 },
+        ] };
+
+        let inverse = notepad.apply_inverting(&msg).unwrap();
+        assert_eq!(notepad.text(), "llo, wörld");
+
+        assert!(notepad.apply_diff(&Diff { opcode: Operation::DelRange(7), operand: None, index: 0 }).is_err());
+        assert!(notepad.apply_diff(&Diff { opcode: Operation::DelRange(12), operand: None, index: 0 }).is_err());
+
+        notepad.apply_message_buf(&inverse).unwrap();
+        assert_eq!(notepad.text(), "héllo");
+    }
+
+    #[test]
+    fn text_is_put_together_after_edits() {
+        let mut notepad = Notepad::new("héllo ".repeat(1000));
+        let msg = MessageBuf { messages: (0..100).map(|i| Diff { opcode: Operation::Rep, operand: Some('a'), index: i * 7 * 10 }).collect() };
+        let mut expected = notepad.text().to_string();
+        for diff in &msg.messages {
+            expected.replace_range(diff.index..diff.index + 1, "a");
+        }
+
+        notepad.apply_message_buf(&msg).unwrap();
+        assert_eq!(notepad.len(), expected.len());
+        assert_eq!(notepad.text(), expected);
+        assert_eq!(notepad.checksum(), Notepad::new(expected).checksum());
+
+        let mut invalid = msg.clone();
+        invalid.messages.insert(50, Diff { opcode: Operation::Del, operand: None, index: 2 });
+        assert!(notepad.apply_message_buf(&invalid).is_err());
+    }
+
+    #[test]
+    fn invalid_diffs() {
+        let mut notepad = Notepad::new("héllo".to_string());
+
+        assert!(notepad.apply_diff(&Diff { opcode: Operation::Del, operand: None, index: 6 }).is_err());
+        assert!(notepad.apply_diff(&Diff { opcode: Operation::Ins, operand: Some('a'), index: 7 }).is_err());
+        assert!(notepad.apply_diff(&Diff { opcode: Operation::Rep, operand: Some('a'), index: 2 }).is_err());
+        assert!(notepad.apply_diff(&Diff { opcode: Operation::Ins, operand: None, index: 0 }).is_err());
+        assert_eq!(notepad.text(), "héllo");
+
+        notepad.apply_diff(&Diff { opcode: Operation::Ins, operand: Some('!'), index: 6 }).unwrap();
+        assert_eq!(notepad.text(), "héllo!");
+    }
+}
@@ -0,0 +1,250 @@
+use crate::{
+    diff::MessageBuf,
+    error::NotepadError,
+    message::{push_str, split_str},
+    storage::Storage,
+    varint
+};
+
+const DIFFS: u8 = 0;
+const TEXT: u8 = 1;
+const TAG: u8 = 2;
+
+/// Bytes of the blake3 hash of each record kept after it, to tell a record
+/// cut short by a crash from a whole one.
+const CHECKSUM_LEN: usize = 4;
+
+/// Bytes a log grows to before it is compacted by default, see [`OpLog::compact`].
+pub const COMPACT_AFTER: usize = 4 * 1024 * 1024;
+
+/// A change to one of the room's documents, as recorded in the log.
+#[derive(Debug, PartialEq)]
+pub enum Record {
+    /// Diffs applied to a document, made locally or by a peer.
+    Diffs { document: String, diffs: MessageBuf },
+    /// A document's text replaced whole, by a snapshot, sync or import.
+    Text { document: String, text: String },
+    /// A document's text labelled `name`, see [`crate::document::Tag`].
+    Tag { document: String, name: String, text: String },
+}
+
+/// What was read back when a log was opened.
+#[derive(Debug, Default)]
+pub struct Recovered {
+    pub records: Vec<Record>,
+    /// Bytes at the end of the log that didn't form a whole record and were cut off.
+    pub torn: usize,
+}
+
+/// Append-only log of every change made to a room's documents, replayed to
+/// rebuild them after the process exits or crashes. Each record is framed as
+/// a varint length, the record and the start of its checksum.
+#[derive(Debug)]
+pub struct OpLog {
+    storage: Box<dyn Storage>,
+    /// Name of the journal in the storage, see [`name`].
+    name: String,
+    /// Bytes of records in the log.
+    len: usize,
+    /// Bytes the log was left with when last compacted.
+    compacted: usize,
+}
+
+/// File name of the log of the room with `topic`. Logs are named after a
+/// hash of the topic, so private room names don't show on disk.
+pub fn name(topic: &str) -> String {
+    format!("{}.oplog", &blake3::hash(topic.as_bytes()).to_hex()[..16])
+}
+
+/// A framed record of diffs applied to `document`, as appended to the log.
+pub fn diffs_frame(document: &str, diffs: &MessageBuf) -> Vec<u8> {
+    let mut record = vec![DIFFS];
+    push_str(&mut record, document);
+    diffs.messages.iter().for_each(|diff| diff.encode(&mut record));
+
+    frame(record)
+}
+
+/// A framed record of the whole text of `document`, as appended to the log.
+pub fn text_frame(document: &str, text: &str) -> Vec<u8> {
+    let mut record = vec![TEXT];
+    push_str(&mut record, document);
+    record.extend(text.as_bytes());
+
+    frame(record)
+}
+
+/// A framed record of `document`'s text being tagged `name`, as appended to the log.
+pub fn tag_frame(document: &str, name: &str, text: &str) -> Vec<u8> {
+    let mut record = vec![TAG];
+    push_str(&mut record, document);
+    push_str(&mut record, name);
+    record.extend(text.as_bytes());
+
+    frame(record)
+}
+
+fn frame(record: Vec<u8>) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(record.len() + CHECKSUM_LEN + 4);
+    varint::push(&mut frame, record.len());
+    frame.extend(&record);
+    frame.extend(&blake3::hash(&record).as_bytes()[..CHECKSUM_LEN]);
+
+    frame
+}
+
+impl OpLog {
+    /// Opens the log of the room with `topic` in `storage` for appending and
+    /// reads back its records. A record cut short by a crash is dropped from the end.
+    pub fn open(mut storage: Box<dyn Storage>, topic: &str) -> Result<(Self, Recovered), NotepadError> {
+        let name = name(topic);
+        let data = storage.journal(&name)?;
+        let (records, len) = read(&data);
+
+        if len < data.len() {
+            storage.truncate(&name, len)?;
+        }
+
+        Ok((Self { storage, name, len, compacted: 0 }, Recovered { records, torn: data.len() - len }))
+    }
+
+    /// Gives the storage back, to open the log of another room in it.
+    pub fn into_storage(self) -> Box<dyn Storage> {
+        self.storage
+    }
+
+    pub fn append_diffs(&mut self, document: &str, diffs: &MessageBuf) -> Result<(), NotepadError> {
+        self.append(&diffs_frame(document, diffs))
+    }
+
+    pub fn append_text(&mut self, document: &str, text: &str) -> Result<(), NotepadError> {
+        self.append(&text_frame(document, text))
+    }
+
+    /// Writes framed records in a single write, so a crash leaves at most
+    /// one record torn.
+    pub fn append(&mut self, frames: &[u8]) -> Result<(), NotepadError> {
+        self.storage.append(&self.name, frames)?;
+        self.len += frames.len();
+
+        Ok(())
+    }
+
+    /// Replaces every record with `frames`, the whole text of each document,
+    /// so replaying the log on launch takes as long as the documents are big
+    /// rather than as long as they have been edited.
+    pub fn compact(&mut self, frames: &[u8]) -> Result<(), NotepadError> {
+        self.storage.rewrite(&self.name, frames)?;
+        self.len = frames.len();
+        self.compacted = frames.len();
+
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Bytes appended since the log was opened or last compacted.
+    pub fn appended(&self) -> usize {
+        self.len - self.compacted
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// Reads whole records off the front of `data`, returning them with the bytes they took.
+pub fn read(mut data: &[u8]) -> (Vec<Record>, usize) {
+    let total = data.len();
+    let mut records = Vec::new();
+
+    while let Ok((record, rest)) = split_frame(data) {
+        records.push(record);
+        data = rest;
+    }
+
+    (records, total - data.len())
+}
+
+fn split_frame(data: &[u8]) -> Result<(Record, &[u8]), NotepadError> {
+    let (len, data) = varint::split(data)?;
+    let record = data.get(..len).ok_or(NotepadError::Decode("Truncated record"))?;
+    let checksum = data.get(len..len + CHECKSUM_LEN).ok_or(NotepadError::Decode("Truncated record"))?;
+
+    if blake3::hash(record).as_bytes()[..CHECKSUM_LEN] != *checksum {
+        return Err(NotepadError::Integrity("Record checksum mismatch"));
+    }
+
+    let (&kind, record) = record.split_first().ok_or(NotepadError::Decode("Empty record"))?;
+    let (document, record) = split_str(record)?;
+    let record = match kind {
+        DIFFS => Record::Diffs { document, diffs: MessageBuf::decode(record)? },
+        TEXT => Record::Text {
+            document,
+            text: String::from_utf8(record.to_vec()).map_err(|_| NotepadError::Decode("Text is not valid UTF-8"))?,
+        },
+        TAG => {
+            let (name, record) = split_str(record)?;
+
+            Record::Tag {
+                document,
+                name,
+                text: String::from_utf8(record.to_vec()).map_err(|_| NotepadError::Decode("Text is not valid UTF-8"))?,
+            }
+        },
+        _ => return Err(NotepadError::Decode("Unknown record kind")),
+    };
+
+    Ok((record, &data[len + CHECKSUM_LEN..]))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        diff::{Diff, Operation},
+        storage::Files
+    };
+
+    #[test]
+    fn recovers_torn_logs() {
+        let dir = std::env::temp_dir().join(format!("p2p-notepad-oplog-{}", rand::random::<u64>()));
+        let path = dir.join(name("room"));
+        let storage = || Box::new(Files::new(dir.clone()));
+        let diffs = MessageBuf { messages: vec![Diff { opcode: Operation::Ins, operand: Some('é'), index: 300 }] };
+
+        let (mut oplog, recovered) = OpLog::open(storage(), "room").unwrap();
+        assert!(recovered.records.is_empty());
+        oplog.append_text("main", "hello").unwrap();
+        oplog.append_diffs("main", &diffs).unwrap();
+        oplog.append_text("notes", "cut short").unwrap();
+        drop(oplog);
+
+        let len = std::fs::metadata(&path).unwrap().len();
+        std::fs::OpenOptions::new().write(true).open(&path).unwrap().set_len(len - 2).unwrap();
+
+        let (mut oplog, recovered) = OpLog::open(storage(), "room").unwrap();
+        assert_eq!(recovered.records, vec![
+            Record::Text { document: "main".to_string(), text: "hello".to_string() },
+            Record::Diffs { document: "main".to_string(), diffs },
+        ]);
+        assert_eq!(recovered.torn, "notes".len() + "cut short".len() + 5);
+
+        oplog.append_text("main", "").unwrap();
+        let (mut oplog, recovered) = OpLog::open(storage(), "room").unwrap();
+        assert_eq!((recovered.records.len(), recovered.torn), (3, 0));
+
+        let frames = [text_frame("main", "hello"), tag_frame("main", "v1", "hi")].concat();
+        oplog.compact(&frames).unwrap();
+        assert_eq!((oplog.len(), oplog.appended()), (frames.len(), 0));
+        let (_, recovered) = OpLog::open(storage(), "room").unwrap();
+        assert_eq!(recovered.records, vec![
+            Record::Text { document: "main".to_string(), text: "hello".to_string() },
+            Record::Tag { document: "main".to_string(), name: "v1".to_string(), text: "hi".to_string() },
+        ]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
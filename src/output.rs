@@ -0,0 +1,134 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::ipc;
+
+/// Whether lines are printed as JSON events, see [`set_json`].
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// A value of a field of an [`event`].
+#[derive(Debug, Clone, Copy)]
+pub enum Value<'a> {
+    Str(&'a str),
+    Num(u64),
+}
+
+impl<'a> From<&'a str> for Value<'a> {
+    fn from(s: &'a str) -> Self {
+        Value::Str(s)
+    }
+}
+
+impl<'a> From<&'a String> for Value<'a> {
+    fn from(s: &'a String) -> Self {
+        Value::Str(s)
+    }
+}
+
+impl From<u64> for Value<'_> {
+    fn from(n: u64) -> Self {
+        Value::Num(n)
+    }
+}
+
+impl From<usize> for Value<'_> {
+    fn from(n: usize) -> Self {
+        Value::Num(n as u64)
+    }
+}
+
+/// Prints one JSON object per line rather than sentences, for scripts and
+/// tools that follow the node. Structured events have their own `event`
+/// kind and fields, every other line is an `output` event with its `text`.
+pub fn set_json(json: bool) {
+    JSON.store(json, Ordering::Relaxed);
+}
+
+pub fn is_json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+/// Prints a line, as an `output` event when printing JSON.
+pub fn print(line: &str) {
+    if is_json() {
+        emit(&object("output", &[("text", Value::Str(line))]));
+    } else {
+        emit(line);
+    }
+}
+
+/// Reports an event of `kind` with `fields` when printing JSON, otherwise
+/// prints `line` if there is one.
+pub fn event(kind: &str, fields: &[(&str, Value)], line: Option<&str>) {
+    if is_json() {
+        emit(&object(kind, fields));
+    } else if let Some(line) = line {
+        emit(line);
+    }
+}
+
+/// Reports an error, as an `error` event with its `text` when printing JSON.
+pub fn error(line: &str) {
+    event("error", &[("text", Value::Str(line))], Some(line));
+}
+
+fn emit(line: &str) {
+    std::println!("{line}");
+    ipc::keep(line);
+}
+
+/// The JSON object `{"event": kind, ...fields}`.
+fn object(kind: &str, fields: &[(&str, Value)]) -> String {
+    let mut json = format!("{{\"event\":{}", string(kind));
+
+    for (name, value) in fields {
+        json.push(',');
+        json.push_str(&string(name));
+        json.push(':');
+        match value {
+            Value::Str(s) => json.push_str(&string(s)),
+            Value::Num(n) => json.push_str(&n.to_string()),
+        }
+    }
+
+    json.push('}');
+    json
+}
+
+/// `s` as a JSON string, quoted and escaped.
+pub fn string(s: &str) -> String {
+    let mut json = String::with_capacity(s.len() + 2);
+    json.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn strings_are_escaped() {
+        assert_eq!(string("say \"hi\"\n\\\u{1}"), "\"say \\\"hi\\\"\\n\\\\\\u0001\"");
+    }
+
+    #[test]
+    fn events_are_objects() {
+        assert_eq!(
+            object("peer_discovered", &[("peer", "12D3".into()), ("ops", 3usize.into())]),
+            "{\"event\":\"peer_discovered\",\"peer\":\"12D3\",\"ops\":3}"
+        );
+    }
+}
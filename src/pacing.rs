@@ -0,0 +1,136 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant}
+};
+
+/// Bytes of bulk payloads published per [`BULK_INTERVAL`], about 640 KiB a second.
+pub const BULK_BUDGET: usize = 64 * 1024;
+
+pub const BULK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long bulk payloads hold back after an interactive one is published,
+/// so keystrokes don't queue behind them in the network.
+pub const INTERACTIVE_GRACE: Duration = Duration::from_millis(50);
+
+/// The lane for bulk messages, such as snapshots and imports, published in
+/// the background at a steady rate so that interactive ones, such as
+/// keystrokes, which are published straight away, stay quick during large
+/// transfers. Bulk messages are kept in order among themselves.
+#[derive(Debug)]
+pub struct Pacer<T> {
+    queue: VecDeque<T>,
+    /// When the current interval started and the bytes published in it.
+    window: Option<(Instant, usize)>,
+    last_interactive: Option<Instant>,
+}
+
+impl<T> Default for Pacer<T> {
+    fn default() -> Self {
+        Self { queue: VecDeque::new(), window: None, last_interactive: None }
+    }
+}
+
+impl<T> Pacer<T> {
+    pub fn push(&mut self, item: T) {
+        self.queue.push_back(item);
+    }
+
+    /// Notes an interactive message being published.
+    pub fn interactive(&mut self, now: Instant) {
+        self.last_interactive = Some(now);
+    }
+
+    /// When bulk messages may next be published, if any are waiting.
+    pub fn due(&self) -> Option<Instant> {
+        if self.queue.is_empty() {
+            return None;
+        }
+
+        let grace = self.last_interactive.map(|last| last + INTERACTIVE_GRACE);
+        let window = self.window.filter(|&(_, sent)| sent >= BULK_BUDGET).map(|(start, _)| start + BULK_INTERVAL);
+
+        Some(grace.max(window).unwrap_or_else(Instant::now))
+    }
+
+    /// The next bulk message to publish, if there is one and it may be
+    /// published now. Its size is then given to [`Pacer::sent`]. The last one
+    /// let through in an interval may go over its budget.
+    pub fn next(&mut self, now: Instant) -> Option<T> {
+        if self.last_interactive.is_some_and(|last| now.saturating_duration_since(last) < INTERACTIVE_GRACE) {
+            return None;
+        }
+
+        let window = match self.window {
+            Some((start, sent)) if now.saturating_duration_since(start) < BULK_INTERVAL => (start, sent),
+            _ => (now, 0),
+        };
+        self.window = Some(window);
+
+        if window.1 >= BULK_BUDGET {
+            return None;
+        }
+
+        self.queue.pop_front()
+    }
+
+    /// Counts `bytes` published against the budget of the current interval.
+    pub fn sent(&mut self, bytes: usize) {
+        if let Some((_, sent)) = &mut self.window {
+            *sent += bytes;
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.queue.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Drops every message, for when they were meant for a room that was left.
+    pub fn clear(&mut self) -> usize {
+        let dropped = self.queue.len();
+        self.queue.clear();
+
+        dropped
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bulk_waits_for_typing_and_its_budget() {
+        let now = Instant::now();
+        let mut pacer = Pacer::default();
+        let take = |pacer: &mut Pacer<usize>, now| {
+            let mut taken = Vec::new();
+            while let Some(len) = pacer.next(now) {
+                pacer.sent(len);
+                taken.push(len);
+            }
+            taken
+        };
+
+        pacer.interactive(now);
+        for len in [BULK_BUDGET / 2, BULK_BUDGET, 10] {
+            pacer.push(len);
+        }
+        assert!(take(&mut pacer, now).is_empty());
+        assert_eq!(pacer.due(), Some(now + INTERACTIVE_GRACE));
+
+        // The second message goes over the budget, leaving the third for the next interval.
+        let later = now + INTERACTIVE_GRACE;
+        assert_eq!(take(&mut pacer, later), vec![BULK_BUDGET / 2, BULK_BUDGET]);
+        assert!(take(&mut pacer, later + BULK_INTERVAL / 2).is_empty());
+        assert_eq!(pacer.due(), Some(later + BULK_INTERVAL));
+        assert_eq!(take(&mut pacer, later + BULK_INTERVAL), vec![10]);
+        assert_eq!(pacer.due(), None);
+    }
+}
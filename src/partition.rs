@@ -0,0 +1,116 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant}
+};
+
+use crate::{
+    diff::{Diff, MessageBuf},
+    notepad::Notepad
+};
+
+/// Local edits kept while they may not have reached anyone, so they can be
+/// merged into the room's documents when this peer is cut off from every
+/// other and later finds one again. Each is held as the diffs reverting it,
+/// from which the text the room last saw is rebuilt.
+#[derive(Debug, Default)]
+pub struct Partition {
+    /// Edits published lately, with when. Peers that time out may have been
+    /// gone for a while before, and missed them.
+    recent: VecDeque<(Instant, String, MessageBuf)>,
+    /// Set while every peer is gone: the edits to merge once one is back,
+    /// oldest first.
+    held: Option<Vec<(String, MessageBuf)>>,
+}
+
+impl Partition {
+    /// Records a local edit to `document` by the diffs reverting it.
+    pub fn record(&mut self, document: &str, inverse: MessageBuf, now: Instant) {
+        match &mut self.held {
+            Some(held) => held.push((document.to_string(), inverse)),
+            None => self.recent.push_back((now, document.to_string(), inverse)),
+        }
+    }
+
+    /// Forgets edits older than `window`, which peers still around have had time to get.
+    pub fn expire(&mut self, window: Duration, now: Instant) {
+        while self.recent.front().is_some_and(|(at, ..)| now.saturating_duration_since(*at) > window) {
+            self.recent.pop_front();
+        }
+    }
+
+    /// Starts holding edits as every peer is gone, along with the recent ones
+    /// they may have missed. Returns whether this peer wasn't cut off already.
+    pub fn lose(&mut self) -> bool {
+        if self.held.is_some() {
+            return false;
+        }
+
+        self.held = Some(self.recent.drain(..).map(|(_, document, inverse)| (document, inverse)).collect());
+        true
+    }
+
+    pub fn is_lost(&self) -> bool {
+        self.held.is_some()
+    }
+
+    /// The text of `document` before the held edits, the one to merge the
+    /// room's current text and `text` from.
+    pub fn base(&self, document: &str, text: &str) -> String {
+        let mut notepad = Notepad::new(text.to_string());
+
+        for (_, inverse) in self.held.iter().flatten().rev().filter(|(id, _)| id == document) {
+            if let Err(e) = notepad.apply_message_buf(inverse) {
+                println!("Dropped held edit: {e}");
+            }
+        }
+
+        notepad.into_text()
+    }
+
+    /// Stops holding edits, once they are merged.
+    pub fn rejoined(&mut self) {
+        self.held = None;
+    }
+
+    pub fn clear(&mut self) {
+        self.recent.clear();
+        self.held = None;
+    }
+
+    pub fn memory(&self) -> usize {
+        self.recent.iter().map(|(_, document, inverse)| (document, inverse))
+            .chain(self.held.iter().flatten().map(|(document, inverse)| (document, inverse)))
+            .map(|(document, inverse)| document.capacity() + inverse.messages.capacity() * size_of::<Diff>())
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::diff::Operation;
+
+    #[test]
+    fn rebuilds_the_text_before_held_edits() {
+        let mut partition = Partition::default();
+        let mut notepad = Notepad::new("hello".to_string());
+        let now = Instant::now();
+        let mut edit = |partition: &mut Partition, at, text: &str| {
+            let diffs = MessageBuf { messages: vec![Diff { opcode: Operation::InsStr(text.to_string()), operand: None, index: 0 }] };
+            partition.record("main", notepad.apply_inverting(&diffs).unwrap(), at);
+        };
+
+        edit(&mut partition, now, "old ");
+        edit(&mut partition, now + Duration::from_secs(20), "recent ");
+        partition.expire(Duration::from_secs(10), now + Duration::from_secs(25));
+        assert!(partition.lose() && !partition.lose());
+        edit(&mut partition, now + Duration::from_secs(30), "held ");
+
+        assert_eq!(notepad.text(), "held recent old hello");
+        assert_eq!(partition.base("main", notepad.text()), "old hello");
+        assert_eq!(partition.base("other", "text"), "text");
+
+        partition.rejoined();
+        assert_eq!(partition.base("main", notepad.text()), notepad.text());
+    }
+}
@@ -0,0 +1,77 @@
+/// Asks the terminal to wrap pasted text in [`START`] and [`END`].
+pub const ENABLE: &str = "\x1b[?2004h";
+pub const DISABLE: &str = "\x1b[?2004l";
+
+pub const START: &str = "\x1b[200~";
+pub const END: &str = "\x1b[201~";
+
+/// A line of stdin once bracketed pastes are taken out of it.
+#[derive(Debug, PartialEq)]
+pub enum Input {
+    Command(String),
+    /// A whole pasted block, never to be run as commands.
+    Paste(String),
+    /// Part of a paste that hasn't ended yet.
+    Pending,
+}
+
+/// Collects the lines of a bracketed paste into one block, so a pasted line
+/// that happens to look like a command isn't run.
+#[derive(Debug, Default)]
+pub struct Paste {
+    block: Option<String>,
+}
+
+impl Paste {
+    /// Takes the next line of stdin. Anything typed on the same line before
+    /// a paste starts, or after it ends, is dropped.
+    pub fn feed(&mut self, line: String) -> Input {
+        let mut block = match self.block.take() {
+            Some(mut block) => {
+                block.push('\n');
+                block.push_str(&line);
+                block
+            },
+            None => match line.split_once(START) {
+                Some((_, pasted)) => pasted.to_string(),
+                None => return Input::Command(line),
+            },
+        };
+
+        match block.find(END) {
+            Some(end) => {
+                block.truncate(end);
+                Input::Paste(block)
+            },
+            None => {
+                self.block = Some(block);
+                Input::Pending
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn commands_pass_through() {
+        let mut paste = Paste::default();
+
+        assert_eq!(paste.feed("see".to_string()), Input::Command("see".to_string()));
+    }
+
+    #[test]
+    fn pastes_are_collected() {
+        let mut paste = Paste::default();
+
+        assert_eq!(paste.feed(format!("{START}one line{END}")), Input::Paste("one line".to_string()));
+
+        assert_eq!(paste.feed(format!("{START}ins:0:a")), Input::Pending);
+        assert_eq!(paste.feed("del:0".to_string()), Input::Pending);
+        assert_eq!(paste.feed(END.to_string()), Input::Paste("ins:0:a\ndel:0\n".to_string()));
+
+        assert_eq!(paste.feed("see".to_string()), Input::Command("see".to_string()));
+    }
+}
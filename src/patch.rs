@@ -0,0 +1,252 @@
+use std::iter::Peekable;
+
+use crate::{
+    diff::{shortest_edit, Edit},
+    error::NotepadError
+};
+
+/// Unchanged lines shown around each change, as `diff -u` does.
+const CONTEXT: usize = 3;
+
+/// The changes turning `old` into `new` as a unified diff, with `old_name`
+/// and `new_name` in its header, to be read or applied with `patch`. Empty
+/// if the texts are the same.
+pub fn unified(old: &str, new: &str, old_name: &str, new_name: &str) -> String {
+    let a: Vec<&str> = old.split_inclusive('\n').collect();
+    let b: Vec<&str> = new.split_inclusive('\n').collect();
+    let lines = line_edits(&a, &b);
+
+    // Where each line of the script starts in the old and new text.
+    let mut at = Vec::with_capacity(lines.len() + 1);
+    let (mut old_line, mut new_line) = (0, 0);
+    for &(tag, _) in &lines {
+        at.push((old_line, new_line));
+        old_line += usize::from(tag != '+');
+        new_line += usize::from(tag != '-');
+    }
+    at.push((old_line, new_line));
+
+    // Changes closer than twice the context share a hunk.
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for i in (0..lines.len()).filter(|&i| lines[i].0 != ' ') {
+        match hunks.last_mut() {
+            Some((_, end)) if i - *end <= 2 * CONTEXT => *end = i + 1,
+            _ => hunks.push((i, i + 1)),
+        }
+    }
+
+    if hunks.is_empty() {
+        return String::new();
+    }
+
+    let mut patch = format!("--- {old_name}\n+++ {new_name}\n");
+
+    for (start, end) in hunks {
+        let (start, end) = (start.saturating_sub(CONTEXT), (end + CONTEXT).min(lines.len()));
+        let ((old_start, new_start), (old_end, new_end)) = (at[start], at[end]);
+        patch.push_str(&format!("@@ -{} +{} @@\n", range(old_start, old_end), range(new_start, new_end)));
+
+        for &(tag, line) in &lines[start..end] {
+            patch.push(tag);
+            patch.push_str(line);
+            if !line.ends_with('\n') {
+                patch.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+    }
+
+    patch
+}
+
+/// Applies the unified diff `patch` to `text`, such as one written by
+/// [`unified`] or `diff -u`, returning the patched text. Each hunk has to
+/// match the text exactly at the line it names, as nothing is guessed.
+/// Headers and anything else around the hunks are skipped.
+pub fn apply(text: &str, patch: &str) -> Result<String, NotepadError> {
+    let old: Vec<&str> = text.split_inclusive('\n').collect();
+    let mut new = String::with_capacity(text.len());
+    let mut lines = patch.split_inclusive('\n').enumerate().peekable();
+    let mut copied = 0;
+    let mut hunks = 0;
+
+    while let Some((i, line)) = lines.next() {
+        let Some(header) = line.strip_prefix("@@ ") else {
+            continue;
+        };
+        let malformed = || NotepadError::command(format!("Malformed hunk on line {} of the patch", i + 1));
+
+        let (old_start, old_len, new_len) = parse_header(header).ok_or_else(malformed)?;
+        let hunk = read_hunk(&mut lines, old_len, new_len).ok_or_else(malformed)?;
+        // An empty range starts at the line before it.
+        let start = if old_len == 0 { old_start } else { old_start.checked_sub(1).ok_or_else(malformed)? };
+
+        if start < copied || start > old.len() {
+            return Err(NotepadError::command(format!("Hunk on line {} of the patch is out of order or past the end of the document", i + 1)));
+        }
+        old[copied..start].iter().for_each(|line| new.push_str(line));
+
+        let mut at = start;
+        for (tag, content) in hunk {
+            if tag == '+' {
+                new.push_str(&content);
+                continue;
+            }
+
+            if old.get(at) != Some(&content.as_str()) {
+                return Err(NotepadError::command(format!("Hunk on line {} of the patch doesn't match line {} of the document", i + 1, at + 1)));
+            }
+            if tag == ' ' {
+                new.push_str(&content);
+            }
+            at += 1;
+        }
+
+        copied = at;
+        hunks += 1;
+    }
+
+    if hunks == 0 {
+        return Err(NotepadError::command("No hunks in the patch"));
+    }
+    old[copied..].iter().for_each(|line| new.push_str(line));
+
+    Ok(new)
+}
+
+/// The start and length of the old lines and the length of the new ones,
+/// from a hunk header after its `@@ `.
+fn parse_header(header: &str) -> Option<(usize, usize, usize)> {
+    let range = |range: &str| match range.split_once(',') {
+        Some((start, len)) => Some((start.parse().ok()?, len.parse().ok()?)),
+        None => Some((range.parse().ok()?, 1)),
+    };
+
+    let mut ranges = header.split(' ');
+    let (old_start, old_len) = range(ranges.next()?.strip_prefix('-')?)?;
+    let (_, new_len) = range(ranges.next()?.strip_prefix('+')?)?;
+
+    Some((old_start, old_len, new_len))
+}
+
+/// The lines of a hunk with their tags, as many old and new ones as its
+/// header counts, each ending in a newline unless a `\` line says it doesn't.
+fn read_hunk<'a>(lines: &mut Peekable<impl Iterator<Item = (usize, &'a str)>>, old_len: usize, new_len: usize) -> Option<Vec<(char, String)>> {
+    let mut hunk: Vec<(char, String)> = Vec::new();
+    let (mut old_seen, mut new_seen) = (0, 0);
+
+    while let Some(&(_, line)) = lines.peek() {
+        let done = old_seen == old_len && new_seen == new_len;
+
+        match line.chars().next() {
+            // `\ No newline at end of file`, which can follow the last line.
+            Some('\\') => {
+                let (_, content) = hunk.last_mut()?;
+                if content.ends_with('\n') {
+                    content.pop();
+                }
+            },
+            _ if done => break,
+            Some(tag @ (' ' | '-' | '+')) => {
+                hunk.push((tag, line[1..].to_string()));
+                old_seen += usize::from(tag != '+');
+                new_seen += usize::from(tag != '-');
+            },
+            // Some tools drop the space of empty context lines.
+            Some('\n') => {
+                hunk.push((' ', line.to_string()));
+                old_seen += 1;
+                new_seen += 1;
+            },
+            _ => return None,
+        }
+
+        if old_seen > old_len || new_seen > new_len {
+            return None;
+        }
+        lines.next();
+    }
+
+    (old_seen == old_len && new_seen == new_len).then_some(hunk)
+}
+
+/// Lines `start..end` as in a hunk header, counting from 1: the count is left
+/// out when it is 1, and an empty range starts at the line before it.
+fn range(start: usize, end: usize) -> String {
+    match end - start {
+        0 => format!("{start},0"),
+        1 => format!("{}", start + 1),
+        len => format!("{},{len}", start + 1),
+    }
+}
+
+/// Every line of `a` and `b` in the order of the shortest edit between them,
+/// tagged with ` `, `-` or `+`. Past [`crate::diff::MAX_EDIT_DISTANCE`], the
+/// lines from the first difference to the last are replaced whole.
+fn line_edits<'a>(a: &[&'a str], b: &[&'a str]) -> Vec<(char, &'a str)> {
+    let prefix = a.iter().zip(b).take_while(|(a, b)| a == b).count();
+    let suffix = a[prefix..].iter().rev().zip(b[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+    let (a_changed, b_changed) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+
+    let edits = shortest_edit(a_changed, b_changed).unwrap_or_else(|| {
+        a_changed.iter().map(|_| Edit::Delete).chain(b_changed.iter().map(|&line| Edit::Insert(line))).collect()
+    });
+
+    let mut lines: Vec<(char, &str)> = a[..prefix].iter().map(|&line| (' ', line)).collect();
+    let mut old = a_changed.iter();
+    for edit in edits {
+        match edit {
+            Edit::Keep => lines.extend(old.next().map(|&line| (' ', line))),
+            Edit::Delete => lines.extend(old.next().map(|&line| ('-', line))),
+            Edit::Insert(line) => lines.push(('+', line)),
+        }
+    }
+    lines.extend(a[a.len() - suffix..].iter().map(|&line| (' ', line)));
+
+    lines
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unified_diffs_match_diff_u() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n";
+        let new = "1\ntwo\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n13";
+
+        assert_eq!(unified(old, new, "a/notes", "b/notes"), "\
+--- a/notes
++++ b/notes
+@@ -1,5 +1,5 @@
+ 1
+-2
++two
+ 3
+ 4
+ 5
+@@ -10,3 +10,4 @@
+ 10
+ 11
+ 12
++13
+\\ No newline at end of file
+");
+        assert_eq!(unified("", "a\n", "a", "b"), "--- a\n+++ b\n@@ -0,0 +1 @@\n+a\n");
+        assert_eq!(unified(old, old, "a", "b"), "");
+    }
+
+    #[test]
+    fn patches_are_applied() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12";
+        let new = "one\n2\n3\n4\n5\n6\n8\n9\n10\n11\n12\n";
+        let patch = unified(old, new, "a", "b");
+
+        assert_eq!(apply(old, &format!("diff -u a b\n{patch}")).unwrap(), new);
+        assert_eq!(apply(old, "@@ -2 +2,2 @@\n-2\n+two\n+2.5\n").unwrap(), old.replace("2\n3", "two\n2.5\n3"));
+        assert_eq!(apply("a\n\nb\n", "@@ -1,3 +1,3 @@\n a\n\n-b\n+c\n").unwrap(), "a\n\nc\n");
+
+        assert!(apply(new, &patch).is_err());
+        assert!(apply(old, "not a patch").is_err());
+        assert!(apply(old, "@@ -1,2 +1,2 @@\n-1\n+one\n").is_err());
+    }
+}
@@ -0,0 +1,206 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+    time::{Duration, Instant}
+};
+
+use libp2p::PeerId;
+
+use crate::{
+    document::MAX_NAME_LEN,
+    error::NotepadError,
+    message::{Reading, Selection}
+};
+
+/// How often a presence heartbeat is published to the room.
+pub const PRESENCE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long without local input before this peer announces itself as away.
+pub const AWAY_AFTER: Duration = Duration::from_secs(5 * 60);
+
+/// Number of trailing peer id characters used to tell apart peers sharing a nickname.
+const SUFFIX_LEN: usize = 4;
+
+/// Nicknames must be non-empty, fit in a length prefix and not contain `#`,
+/// which is reserved for telling apart peers sharing a nickname.
+pub fn validate_nickname(nickname: &str) -> Result<(), NotepadError> {
+    if nickname.is_empty() || nickname.len() > MAX_NAME_LEN || nickname.contains('#') {
+        return Err(NotepadError::command(format!("Invalid nickname: `{nickname}`")));
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Peer {
+    pub last_seen: Instant,
+    pub nickname: Option<String>,
+    /// Whether the peer's user has been inactive for a while.
+    pub away: bool,
+    /// Where the peer's editor was last scrolled to, if it runs one.
+    pub reading: Option<Reading>,
+    /// What the peer selected for the others to look at.
+    pub selection: Option<Selection>,
+}
+
+/// Peers of the current room, by when their last presence heartbeat was seen.
+#[derive(Debug, Default)]
+pub struct Peers {
+    /// Nickname this peer announces in its own heartbeats.
+    pub nickname: Option<String>,
+    /// Whether this peer announces itself as away, see [`AWAY_AFTER`].
+    pub away: bool,
+    peers: HashMap<PeerId, Peer>,
+    /// Peers seen subscribing to the room that haven't sent a heartbeat yet.
+    joining: HashSet<PeerId>,
+    /// Nicknames signed into edits by the users of shared nodes, by user id.
+    users: HashMap<PeerId, String>,
+}
+
+impl Peers {
+    /// Records a heartbeat from `peer_id`, returning what was known about the peer before.
+    pub fn seen(&mut self, peer_id: PeerId, nickname: Option<String>, away: bool, reading: Option<Reading>, selection: Option<Selection>, now: Instant) -> Option<Peer> {
+        self.peers.insert(peer_id, Peer { last_seen: now, nickname, away, reading, selection })
+    }
+
+    /// Records `peer_id` subscribing to the room, unless it is known already.
+    pub fn joining(&mut self, peer_id: PeerId) -> bool {
+        !self.peers.contains_key(&peer_id) && self.joining.insert(peer_id)
+    }
+
+    /// Whether `peer_id` was seen subscribing before its first heartbeat, which it is now.
+    pub fn joined(&mut self, peer_id: &PeerId) -> bool {
+        self.joining.remove(peer_id)
+    }
+
+    /// Removes a peer that left the room, returning the name it was displayed as.
+    pub fn remove(&mut self, peer_id: &PeerId) -> Option<String> {
+        self.joining.remove(peer_id);
+        let name = self.display_name(peer_id);
+
+        self.peers.remove(peer_id).map(|_| name)
+    }
+
+    /// Removes the peers that haven't sent a heartbeat within `timeout`,
+    /// returning them with the name they were displayed as.
+    pub fn prune(&mut self, timeout: Duration, now: Instant) -> Vec<(PeerId, String)> {
+        let expired: Vec<_> = self.peers
+            .iter()
+            .filter(|(_, peer)| now.saturating_duration_since(peer.last_seen) > timeout)
+            .map(|(&peer_id, _)| (peer_id, self.display_name(&peer_id)))
+            .collect();
+
+        for (peer_id, _) in &expired {
+            self.peers.remove(peer_id);
+        }
+
+        expired
+    }
+
+    /// Name to show for `peer_id`: its nickname, suffixed with the end of its
+    /// peer id when another peer (or this one) uses the same nickname.
+    pub fn display_name(&self, peer_id: &PeerId) -> String {
+        let nickname = self.peers.get(peer_id).and_then(|peer| peer.nickname.as_deref());
+        let Some(nickname) = nickname.or_else(|| self.users.get(peer_id).map(String::as_str)) else {
+            return peer_id.to_string();
+        };
+
+        if self.nickname_users(nickname) > 1 {
+            let id = peer_id.to_string();
+            format!("{nickname}#{}", &id[id.len() - SUFFIX_LEN..])
+        } else {
+            nickname.to_string()
+        }
+    }
+
+    /// Number of peers, including this one, announcing `nickname`.
+    pub fn nickname_users(&self, nickname: &str) -> usize {
+        let local = usize::from(self.nickname.as_deref() == Some(nickname));
+
+        local + self.peers.values().filter(|peer| peer.nickname.as_deref() == Some(nickname)).count()
+    }
+
+    /// Records the nickname a user of a shared node signed an edit with.
+    pub fn user(&mut self, user_id: PeerId, nickname: String) {
+        self.users.insert(user_id, nickname);
+    }
+
+    /// What the peers selected in `document`, in order through it.
+    pub fn selections(&self, document: &str) -> Vec<(PeerId, Range<usize>)> {
+        let mut selections: Vec<_> = self.peers
+            .iter()
+            .filter_map(|(&peer_id, peer)| {
+                let selection = peer.selection.as_ref().filter(|selection| selection.document == document)?;
+                Some((peer_id, selection.range.clone()))
+            })
+            .collect();
+        selections.sort_by_key(|(peer_id, range)| (range.start, *peer_id));
+
+        selections
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&PeerId, &Peer)> {
+        self.peers.iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.peers.clear();
+        self.joining.clear();
+        self.users.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn prunes_stale_peers() {
+        let mut peers = Peers::default();
+        let (a, b) = (PeerId::random(), PeerId::random());
+        let start = Instant::now();
+
+        assert!(peers.seen(a, None, false, None, None, start).is_none());
+        assert!(peers.seen(b, None, false, None, None, start).is_none());
+        assert!(peers.seen(b, None, false, None, None, start + Duration::from_secs(20)).is_some());
+
+        assert!(peers.prune(Duration::from_secs(30), start + Duration::from_secs(30)).is_empty());
+        assert_eq!(peers.prune(Duration::from_secs(30), start + Duration::from_secs(31)), vec![(a, a.to_string())]);
+        assert_eq!(peers.iter().count(), 1);
+    }
+
+    #[test]
+    fn disambiguates_shared_nicknames() {
+        let mut peers = Peers::default();
+        let (a, b) = (PeerId::random(), PeerId::random());
+        let now = Instant::now();
+
+        peers.seen(a, Some("alice".to_string()), false, None, None, now);
+        peers.seen(b, Some("bob".to_string()), false, None, None, now);
+        assert_eq!(peers.display_name(&a), "alice");
+
+        peers.nickname = Some("alice".to_string());
+        let a_id = a.to_string();
+        assert_eq!(peers.display_name(&a), format!("alice#{}", &a_id[a_id.len() - 4..]));
+
+        peers.nickname = None;
+        peers.seen(b, Some("alice".to_string()), false, None, None, now);
+        assert_eq!(peers.nickname_users("alice"), 2);
+        assert_ne!(peers.display_name(&a), peers.display_name(&b));
+    }
+
+    #[test]
+    fn lists_selections_by_document() {
+        let mut peers = Peers::default();
+        let (a, b) = (PeerId::random(), PeerId::random());
+        let selection = |document: &str, range| Some(Selection { document: document.to_string(), range });
+        let now = Instant::now();
+
+        peers.seen(a, None, false, None, selection("main", 5..9), now);
+        peers.seen(b, None, false, None, selection("main", 0..2), now);
+        assert_eq!(peers.selections("main"), vec![(b, 0..2), (a, 5..9)]);
+
+        peers.seen(b, None, false, None, selection("notes", 0..2), now);
+        assert_eq!(peers.selections("main"), vec![(a, 5..9)]);
+    }
+}
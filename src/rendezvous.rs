@@ -0,0 +1,199 @@
+//! Rendezvous points, to find the other peers of a room beyond the local
+//! network. Peers register the rooms they join at a point with the addresses
+//! they're reachable at, and are told who registered before them to dial.
+//! Registrations last as long as the connection to the point.
+
+use std::collections::HashMap;
+
+use libp2p::{Multiaddr, PeerId};
+
+use crate::{error::NotepadError, message, varint};
+
+/// Most registrations given back for a room, so a busy room doesn't make
+/// every new peer dial everyone.
+pub const MAX_REGISTRATIONS: usize = 64;
+
+const REGISTER: u8 = 0;
+const UNREGISTER: u8 = 1;
+
+/// A request to a rendezvous point, under a room's topic.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Request {
+    /// Answered with the peers registered under the topic before.
+    Register { topic: String, addresses: Vec<Multiaddr> },
+    Unregister { topic: String },
+}
+
+/// A peer registered under a topic and where to reach it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Registration {
+    pub peer_id: PeerId,
+    pub addresses: Vec<Multiaddr>,
+}
+
+impl From<&Request> for Vec<u8> {
+    fn from(request: &Request) -> Self {
+        let mut data = Vec::new();
+
+        match request {
+            Request::Register { topic, addresses } => {
+                data.push(REGISTER);
+                message::push_str(&mut data, topic);
+                push_addresses(&mut data, addresses);
+            },
+            Request::Unregister { topic } => {
+                data.push(UNREGISTER);
+                message::push_str(&mut data, topic);
+            },
+        }
+
+        data
+    }
+}
+
+impl TryFrom<&[u8]> for Request {
+    type Error = NotepadError;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        let (&tag, data) = data.split_first().ok_or(NotepadError::Decode("Empty rendezvous request"))?;
+        let (topic, data) = message::split_str(data)?;
+
+        match tag {
+            REGISTER => Ok(Request::Register { topic, addresses: split_addresses(data)?.0 }),
+            UNREGISTER => Ok(Request::Unregister { topic }),
+            _ => Err(NotepadError::Decode("Unknown rendezvous request")),
+        }
+    }
+}
+
+/// Encodes the registrations a point answers with.
+pub fn encode_registrations(registrations: &[Registration]) -> Vec<u8> {
+    let mut data = Vec::new();
+
+    for registration in registrations {
+        push_bytes(&mut data, &registration.peer_id.to_bytes());
+        push_addresses(&mut data, &registration.addresses);
+    }
+
+    data
+}
+
+pub fn decode_registrations(mut data: &[u8]) -> Result<Vec<Registration>, NotepadError> {
+    let mut registrations = Vec::new();
+
+    while !data.is_empty() {
+        let (peer_id, rest) = split_bytes(data)?;
+        let peer_id = PeerId::from_bytes(peer_id).map_err(|_| NotepadError::Decode("Invalid registered peer id"))?;
+        let (addresses, rest) = split_addresses(rest)?;
+
+        registrations.push(Registration { peer_id, addresses });
+        data = rest;
+    }
+
+    Ok(registrations)
+}
+
+/// The rooms peers registered at this node, when it serves as a rendezvous
+/// point.
+#[derive(Debug, Default)]
+pub struct Registry {
+    /// Addresses of the peers registered under each topic, by peer id.
+    topics: HashMap<String, HashMap<PeerId, Vec<Multiaddr>>>,
+}
+
+impl Registry {
+    /// Handles `request` from `peer`, returning the response to send.
+    pub fn handle(&mut self, peer: PeerId, request: Request) -> Vec<u8> {
+        match request {
+            Request::Register { topic, addresses } => {
+                let peers = self.topics.entry(topic).or_default();
+                let registrations: Vec<_> = peers.iter()
+                    .filter(|(&peer_id, _)| peer_id != peer)
+                    .take(MAX_REGISTRATIONS)
+                    .map(|(&peer_id, addresses)| Registration { peer_id, addresses: addresses.clone() })
+                    .collect();
+
+                peers.insert(peer, addresses);
+                encode_registrations(&registrations)
+            },
+            Request::Unregister { topic } => {
+                if let Some(peers) = self.topics.get_mut(&topic) {
+                    peers.remove(&peer);
+                    if peers.is_empty() {
+                        self.topics.remove(&topic);
+                    }
+                }
+                Vec::new()
+            },
+        }
+    }
+
+    /// Drops every registration of `peer`, once it disconnected.
+    pub fn remove(&mut self, peer: &PeerId) {
+        self.topics.retain(|_, peers| {
+            peers.remove(peer);
+            !peers.is_empty()
+        });
+    }
+}
+
+fn push_bytes(data: &mut Vec<u8>, bytes: &[u8]) {
+    varint::push(data, bytes.len());
+    data.extend(bytes);
+}
+
+fn split_bytes(data: &[u8]) -> Result<(&[u8], &[u8]), NotepadError> {
+    let (len, data) = varint::split(data)?;
+
+    if data.len() < len {
+        return Err(NotepadError::Decode("Bytes are longer than the message"));
+    }
+
+    Ok(data.split_at(len))
+}
+
+fn push_addresses(data: &mut Vec<u8>, addresses: &[Multiaddr]) {
+    varint::push(data, addresses.len());
+    for address in addresses {
+        push_bytes(data, address.as_ref());
+    }
+}
+
+fn split_addresses(data: &[u8]) -> Result<(Vec<Multiaddr>, &[u8]), NotepadError> {
+    let (count, mut data) = varint::split(data)?;
+    let mut addresses = Vec::new();
+
+    for _ in 0..count {
+        let (address, rest) = split_bytes(data)?;
+        addresses.push(Multiaddr::try_from(address.to_vec()).map_err(|_| NotepadError::Decode("Invalid registered address"))?);
+        data = rest;
+    }
+
+    Ok((addresses, data))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn registered_peers_are_found() {
+        let (a, b) = (PeerId::random(), PeerId::random());
+        let address: Multiaddr = "/ip4/203.0.113.7/tcp/4001".parse().unwrap();
+        let register = Request::Register { topic: "notes".to_string(), addresses: vec![address.clone()] };
+
+        let data = Vec::from(&register);
+        assert_eq!(Request::try_from(&data[..]).unwrap(), register);
+
+        let mut registry = Registry::default();
+        assert!(decode_registrations(&registry.handle(a, register.clone())).unwrap().is_empty());
+        assert_eq!(
+            decode_registrations(&registry.handle(b, register.clone())).unwrap(),
+            vec![Registration { peer_id: a, addresses: vec![address] }],
+        );
+
+        registry.remove(&a);
+        registry.handle(b, Request::Unregister { topic: "notes".to_string() });
+        assert!(decode_registrations(&registry.handle(a, register)).unwrap().is_empty());
+    }
+}
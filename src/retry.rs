@@ -0,0 +1,213 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant}
+};
+
+use crate::error::NotepadError;
+
+/// Longest wait between two attempts at a payload, however many failed before.
+pub const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Most payloads waiting to be published. Past this the oldest are dropped,
+/// peers that join later catch up with a sync request anyway.
+pub const MAX_QUEUED: usize = 256;
+
+/// How publishes that failed for a passing reason are tried again.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Attempts at a payload before it is given up on.
+    pub attempts: u32,
+    /// Wait before the first retry, doubled for each one after it up to [`MAX_BACKOFF`].
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self { attempts: 5, backoff: Duration::from_millis(500) }
+    }
+}
+
+impl RetryPolicy {
+    /// Wait after `failures` failed attempts at a payload.
+    pub fn delay(&self, failures: u32) -> Duration {
+        self.backoff
+            .saturating_mul(2u32.saturating_pow(failures.saturating_sub(1)))
+            .min(MAX_BACKOFF)
+    }
+}
+
+/// What became of the payloads tried by [`Outbox::flush`].
+#[derive(Debug, Default)]
+pub struct Flushed {
+    /// Payloads published to at least one peer.
+    pub sent: usize,
+    /// Payloads dropped, because of a permanent failure or too many transient ones.
+    pub failed: Vec<NotepadError>,
+}
+
+/// Payloads waiting to be published, in the order they were made. Only the
+/// oldest is tried at a time, so edits reach peers in order.
+#[derive(Debug, Default)]
+pub struct Outbox {
+    queue: VecDeque<Vec<u8>>,
+    /// Transient failures of the oldest payload so far.
+    failures: u32,
+    /// When to try the oldest payload again.
+    due: Option<Instant>,
+}
+
+impl Outbox {
+    /// Queues `data` behind the payloads already waiting. If none are, it is
+    /// tried again at `retry`, having failed `failures` times already.
+    /// Returns how many old payloads were dropped to make room.
+    pub fn push(&mut self, data: Vec<u8>, failures: u32, retry: Instant) -> usize {
+        if self.queue.is_empty() {
+            self.failures = failures;
+            self.due = Some(retry);
+        }
+
+        self.queue.push_back(data);
+
+        let dropped = self.queue.len().saturating_sub(MAX_QUEUED);
+        self.queue.drain(..dropped);
+        if dropped > 0 {
+            self.failures = 0;
+        }
+
+        dropped
+    }
+
+    /// When the oldest payload is next tried, if any are waiting.
+    pub fn due(&self) -> Option<Instant> {
+        self.due
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// The payloads waiting, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &[u8]> {
+        self.queue.iter().map(Vec::as_slice)
+    }
+
+    /// Makes the payloads waiting due at `now`, for when a peer that can
+    /// receive them has just turned up.
+    pub fn wake(&mut self, now: Instant) {
+        if !self.queue.is_empty() {
+            self.due = Some(self.due.map_or(now, |due| due.min(now)));
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Drops every payload, for when they were meant for a room that was left.
+    pub fn clear(&mut self) -> usize {
+        let dropped = self.queue.len();
+        *self = Self::default();
+
+        dropped
+    }
+
+    pub fn memory(&self) -> usize {
+        self.queue.iter().map(Vec::capacity).sum()
+    }
+
+    /// Publishes waiting payloads in order with `publish` once they are due,
+    /// until one can't be sent yet. A payload nobody received is kept without
+    /// counting as a failure, as the room may just be empty for now.
+    pub fn flush(&mut self, policy: &RetryPolicy, now: Instant, mut publish: impl FnMut(Vec<u8>) -> Result<usize, NotepadError>) -> Flushed {
+        let mut flushed = Flushed::default();
+
+        if self.due.is_none_or(|due| due > now) {
+            return flushed;
+        }
+
+        while let Some(data) = self.queue.front() {
+            match publish(data.clone()) {
+                Ok(0) => {
+                    self.due = Some(now + policy.backoff);
+                    return flushed;
+                },
+                Ok(_) => flushed.sent += 1,
+                Err(NotepadError::Transient(_)) if self.failures + 1 < policy.attempts => {
+                    self.failures += 1;
+                    self.due = Some(now + policy.delay(self.failures));
+                    return flushed;
+                },
+                Err(NotepadError::Transient(e)) => {
+                    flushed.failed.push(NotepadError::network(format!("gave up after {} attempts: {e}", policy.attempts)));
+                },
+                Err(e) => flushed.failed.push(e),
+            }
+
+            self.queue.pop_front();
+            self.failures = 0;
+        }
+
+        self.due = None;
+        flushed
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles() {
+        let policy = RetryPolicy { attempts: 10, backoff: Duration::from_secs(1) };
+
+        assert_eq!(policy.delay(1), Duration::from_secs(1));
+        assert_eq!(policy.delay(3), Duration::from_secs(4));
+        assert_eq!(policy.delay(9), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn flushes_in_order() {
+        let policy = RetryPolicy { attempts: 3, backoff: Duration::from_secs(1) };
+        let now = Instant::now();
+        let mut outbox = Outbox::default();
+        let mut sent = Vec::new();
+
+        outbox.push(vec![1], 1, now + policy.delay(1));
+        outbox.push(vec![2], 0, now);
+        assert_eq!(outbox.flush(&policy, now, |_| Ok(1)).sent, 0);
+
+        let flushed = outbox.flush(&policy, now + policy.backoff, |_| Err(NotepadError::Transient("busy".to_string())));
+        assert!(flushed.failed.is_empty());
+        assert_eq!(outbox.due(), Some(now + policy.backoff + policy.delay(2)));
+
+        let flushed = outbox.flush(&policy, now + MAX_BACKOFF, |data| match data[..] {
+            [1] => Err(NotepadError::Transient("busy".to_string())),
+            _ => {
+                sent.push(data);
+                Ok(1)
+            },
+        });
+        assert_eq!((flushed.sent, flushed.failed.len()), (1, 1));
+        assert_eq!(sent, vec![vec![2]]);
+        assert!(outbox.is_empty() && outbox.due().is_none());
+    }
+
+    #[test]
+    fn waits_for_peers() {
+        let policy = RetryPolicy::default();
+        let now = Instant::now();
+        let mut outbox = Outbox::default();
+
+        outbox.push(vec![1], 0, now);
+        for _ in 0..policy.attempts * 2 {
+            assert_eq!(outbox.flush(&policy, now + MAX_BACKOFF, |_| Ok(0)).sent, 0);
+        }
+        assert_eq!(outbox.len(), 1);
+
+        for i in 0..MAX_QUEUED {
+            outbox.push(vec![i as u8], 0, now);
+        }
+        assert_eq!(outbox.len(), MAX_QUEUED);
+        assert_eq!(outbox.clear(), MAX_QUEUED);
+    }
+}
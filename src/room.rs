@@ -0,0 +1,109 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use libp2p::PeerId;
+
+use crate::{
+    error::NotepadError,
+    message::{push_str, split_str}
+};
+
+const READ_ONLY: u8 = 1;
+const QUIET: u8 = 2;
+
+/// Preferences for one room, remembered for when it is joined again.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RoomPrefs {
+    /// Refuse local edits, so a room can be followed without changing it by accident.
+    pub read_only: bool,
+    /// Hide notices about peers joining, leaving and stepping away.
+    pub quiet: bool,
+    /// Peers whose clipboard shares and attachment announcements are ignored.
+    /// Their edits are still applied, or documents would drift apart.
+    pub muted: BTreeSet<PeerId>,
+}
+
+/// Preferences of every room joined, keyed by topic so private rooms sharing
+/// a name with a public one keep separate preferences.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RoomSettings {
+    rooms: BTreeMap<String, RoomPrefs>,
+}
+
+impl RoomSettings {
+    pub fn get(&self, topic: &str) -> Option<&RoomPrefs> {
+        self.rooms.get(topic)
+    }
+
+    pub fn get_mut(&mut self, topic: &str) -> &mut RoomPrefs {
+        self.rooms.entry(topic.to_string()).or_default()
+    }
+
+    /// Each room as its topic, a flags byte, the number of muted peers and their ids.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+
+        for (topic, prefs) in &self.rooms {
+            push_str(&mut data, topic);
+            data.push(if prefs.read_only { READ_ONLY } else { 0 } | if prefs.quiet { QUIET } else { 0 });
+            data.push(prefs.muted.len().min(u8::MAX as usize) as u8);
+
+            for peer_id in prefs.muted.iter().take(u8::MAX as usize) {
+                push_str(&mut data, &peer_id.to_string());
+            }
+        }
+
+        data
+    }
+
+    pub fn decode(mut data: &[u8]) -> Result<Self, NotepadError> {
+        let mut rooms = BTreeMap::new();
+
+        while !data.is_empty() {
+            let (topic, rest) = split_str(data)?;
+            let [flags, muted, rest @ ..] = rest else {
+                return Err(NotepadError::Decode("Truncated room settings"));
+            };
+
+            if flags & !(READ_ONLY | QUIET) != 0 {
+                return Err(NotepadError::Decode("Invalid room flags"));
+            }
+
+            let mut prefs = RoomPrefs { read_only: flags & READ_ONLY != 0, quiet: flags & QUIET != 0, muted: BTreeSet::new() };
+            data = rest;
+
+            for _ in 0..*muted {
+                let (peer_id, rest) = split_str(data)?;
+                prefs.muted.insert(peer_id.parse().map_err(|_| NotepadError::Decode("Invalid muted peer id"))?);
+                data = rest;
+            }
+
+            rooms.insert(topic, prefs);
+        }
+
+        Ok(Self { rooms })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn settings_round_trip() {
+        let mut settings = RoomSettings::default();
+        settings.get_mut("p/room").read_only = true;
+        settings.get_mut("p/other").muted.insert(PeerId::random());
+        settings.get_mut("p/other").quiet = true;
+
+        assert_eq!(RoomSettings::decode(&settings.encode()).unwrap(), settings);
+        assert_eq!(settings.get("p/unknown"), None);
+    }
+
+    #[test]
+    fn invalid_settings() {
+        assert!(RoomSettings::decode(&[1, b'a']).is_err());
+        assert!(RoomSettings::decode(&[1, b'a', 4, 0]).is_err());
+        assert!(RoomSettings::decode(&[1, b'a', 0, 1]).is_err());
+        assert!(RoomSettings::decode(&[1, b'a', 0, 1, 1, b'x']).is_err());
+    }
+}
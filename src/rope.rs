@@ -0,0 +1,355 @@
+use std::fmt;
+
+use crate::{
+    diff::{Diff, Operation},
+    error::NotepadError
+};
+
+/// Bytes a chunk is cut to, chunks grow to twice this before being cut again.
+pub const CHUNK_LEN: usize = 1024;
+
+/// Text held as chunks of about [`CHUNK_LEN`] bytes, each cut at a character
+/// boundary, so an edit only shifts the bytes of the chunks it touches
+/// rather than the rest of the text, and finds them in logarithmic time.
+/// There is always at least one chunk.
+#[derive(Debug, Clone)]
+pub struct Rope {
+    chunks: Vec<String>,
+    lengths: Lengths,
+    len: usize,
+}
+
+impl Default for Rope {
+    fn default() -> Self {
+        Self::from("")
+    }
+}
+
+impl PartialEq for Rope {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.chunks().flat_map(str::bytes).eq(other.chunks().flat_map(str::bytes))
+    }
+}
+
+impl Rope {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The text in order, a chunk at a time.
+    pub fn chunks(&self) -> impl Iterator<Item = &str> {
+        self.chunks.iter().map(String::as_str)
+    }
+
+    /// Approximate bytes held.
+    pub fn memory(&self) -> usize {
+        self.chunks.iter().map(String::capacity).sum::<usize>() + self.chunks.capacity() * size_of::<String>() + self.lengths.memory()
+    }
+
+    /// Bytes `start..end` of the text, if both are in it and at character boundaries.
+    pub fn get(&self, start: usize, end: usize) -> Option<String> {
+        if start > end || end > self.len || !self.is_char_boundary(start) || !self.is_char_boundary(end) {
+            return None;
+        }
+
+        let mut text = String::with_capacity(end - start);
+        let mut index = start;
+        while index < end {
+            let (chunk, offset) = self.locate(index);
+            let taken = (end - index).min(self.chunks[chunk].len() - offset);
+            text.push_str(&self.chunks[chunk][offset..offset + taken]);
+            index += taken;
+        }
+
+        Some(text)
+    }
+
+    /// The character starting at byte `index`, if one does.
+    pub fn char_at(&self, index: usize) -> Option<char> {
+        if index >= self.len {
+            return None;
+        }
+
+        let (chunk, offset) = self.locate(index);
+        self.chunks[chunk].get(offset..)?.chars().next()
+    }
+
+    /// Applies a diff, leaving the text untouched if it doesn't fit: if its
+    /// index is past the end or inside a character, or it lacks a character.
+    pub fn apply_diff(&mut self, diff: &Diff) -> Result<(), NotepadError> {
+        let Diff { opcode, operand, index } = diff;
+        let index = *index;
+        let error = |reason| NotepadError::Apply { diff: diff.to_string(), reason };
+
+        let in_bounds = match opcode {
+            Operation::Ins | Operation::InsStr(_) => index <= self.len,
+            Operation::Del | Operation::Rep => index < self.len,
+            Operation::DelRange(len) => index.checked_add(*len).is_some_and(|end| end <= self.len),
+        };
+
+        if !in_bounds {
+            return Err(error("index is past the end of the text"));
+        }
+
+        if !self.is_char_boundary(index) {
+            return Err(error("index is inside a character"));
+        }
+
+        if let Operation::DelRange(len) = opcode {
+            if !self.is_char_boundary(index + len) {
+                return Err(error("range ends inside a character"));
+            }
+        }
+
+        match opcode {
+            Operation::Del => {
+                let len = self.char_at(index).map_or(0, char::len_utf8);
+                self.remove(index, len);
+            },
+            Operation::Ins => {
+                let value = operand.ok_or(error("char not given to Operation: Insert"))?;
+                self.insert(index, value.encode_utf8(&mut [0; 4]));
+            },
+            Operation::Rep => {
+                let value = operand.ok_or(error("char not given to Operation: Rep"))?;
+                let len = self.char_at(index).map_or(0, char::len_utf8);
+                self.remove(index, len);
+                self.insert(index, value.encode_utf8(&mut [0; 4]));
+            },
+            Operation::InsStr(text) => self.insert(index, text),
+            Operation::DelRange(len) => self.remove(index, *len),
+        }
+
+        Ok(())
+    }
+
+    pub fn is_char_boundary(&self, index: usize) -> bool {
+        if index >= self.len {
+            return index == self.len;
+        }
+
+        let (chunk, offset) = self.locate(index);
+        self.chunks[chunk].is_char_boundary(offset)
+    }
+
+    /// The chunk byte `index` is in, which must be before the end of the
+    /// text, and where in the chunk it is.
+    fn locate(&self, index: usize) -> (usize, usize) {
+        self.lengths.find(index)
+    }
+
+    /// Inserts `text` at byte `index`, appending to the chunk that ends there if there is one.
+    fn insert(&mut self, index: usize, text: &str) {
+        let (chunk, offset) = match index.checked_sub(1) {
+            Some(last) => {
+                let (chunk, offset) = self.locate(last);
+                (chunk, offset + 1)
+            },
+            None => (0, 0),
+        };
+
+        self.chunks[chunk].insert_str(offset, text);
+        self.len += text.len();
+        self.lengths.add(chunk, text.len() as isize);
+
+        if self.chunks[chunk].len() > 2 * CHUNK_LEN {
+            let pieces = cut(&self.chunks[chunk]);
+            self.chunks.splice(chunk..=chunk, pieces);
+            self.lengths = Lengths::new(&self.chunks);
+        }
+    }
+
+    /// Removes `len` bytes from byte `index`, dropping chunks left empty.
+    fn remove(&mut self, index: usize, mut len: usize) {
+        while len > 0 {
+            let (chunk, offset) = self.locate(index);
+            let removed = len.min(self.chunks[chunk].len() - offset);
+
+            self.chunks[chunk].drain(offset..offset + removed);
+            self.lengths.add(chunk, -(removed as isize));
+            if self.chunks[chunk].is_empty() && self.chunks.len() > 1 {
+                self.chunks.remove(chunk);
+                self.lengths = Lengths::new(&self.chunks);
+            }
+
+            self.len -= removed;
+            len -= removed;
+        }
+    }
+}
+
+impl From<&str> for Rope {
+    fn from(text: &str) -> Self {
+        let chunks = cut(text);
+
+        Self { lengths: Lengths::new(&chunks), chunks, len: text.len() }
+    }
+}
+
+impl From<Rope> for String {
+    fn from(rope: Rope) -> Self {
+        rope.chunks.concat()
+    }
+}
+
+impl fmt::Display for Rope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.chunks.iter().try_for_each(|chunk| f.write_str(chunk))
+    }
+}
+
+/// Lengths of the chunks as a Fenwick tree, so the chunk an index is in is
+/// found, and a length changed, in logarithmic time. Rebuilt when chunks
+/// are cut or dropped, which only happens every so many bytes.
+#[derive(Debug, Clone)]
+struct Lengths {
+    /// Node `i`, counting from one, sums the lengths of the `i & i.wrapping_neg()`
+    /// chunks up to chunk `i - 1`.
+    tree: Vec<usize>,
+}
+
+impl Lengths {
+    fn new(chunks: &[String]) -> Self {
+        let mut tree = vec![0; chunks.len() + 1];
+
+        for i in 1..tree.len() {
+            tree[i] += chunks[i - 1].len();
+            let parent = i + (i & i.wrapping_neg());
+            if parent < tree.len() {
+                tree[parent] += tree[i];
+            }
+        }
+
+        Self { tree }
+    }
+
+    fn add(&mut self, chunk: usize, delta: isize) {
+        let mut i = chunk + 1;
+
+        while i < self.tree.len() {
+            self.tree[i] = self.tree[i].wrapping_add_signed(delta);
+            i += i & i.wrapping_neg();
+        }
+    }
+
+    /// The chunk byte `index` is in and its offset there. Skips empty
+    /// chunks, and is one past the last chunk when `index` is past the end.
+    fn find(&self, mut index: usize) -> (usize, usize) {
+        let chunks = self.tree.len() - 1;
+        let mut chunk = 0;
+        let mut step = chunks.checked_ilog2().map_or(0, |log| 1 << log);
+
+        while step > 0 {
+            if chunk + step <= chunks && self.tree[chunk + step] <= index {
+                chunk += step;
+                index -= self.tree[chunk];
+            }
+            step >>= 1;
+        }
+
+        (chunk, index)
+    }
+
+    fn memory(&self) -> usize {
+        self.tree.capacity() * size_of::<usize>()
+    }
+}
+
+/// Cuts `text` into chunks of up to [`CHUNK_LEN`] bytes, fewer where a
+/// character would be split, and a single empty one if there is no text.
+fn cut(text: &str) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut rest = text;
+
+    while !rest.is_empty() {
+        let mut end = CHUNK_LEN.min(rest.len());
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        let (chunk, tail) = rest.split_at(end);
+        chunks.push(chunk.to_string());
+        rest = tail;
+    }
+
+    if chunks.is_empty() {
+        chunks.push(String::new());
+    }
+
+    chunks
+}
+
+#[cfg(test)]
+mod test {
+    use rand::Rng;
+
+    use super::*;
+
+    /// Applies `diff` to plain `text` the way a rope should, whether it fits.
+    fn apply_to_string(text: &mut String, diff: &Diff) -> bool {
+        let index = diff.index;
+        let fits = match &diff.opcode {
+            Operation::Ins | Operation::InsStr(_) => index <= text.len(),
+            Operation::Del | Operation::Rep => index < text.len(),
+            Operation::DelRange(len) => index + len <= text.len() && text.is_char_boundary(index + len),
+        } && text.is_char_boundary(index) && (diff.operand.is_some() || !matches!(diff.opcode, Operation::Ins | Operation::Rep));
+
+        if fits {
+            match &diff.opcode {
+                Operation::Del => drop(text.remove(index)),
+                Operation::Ins => text.insert(index, diff.operand.unwrap()),
+                Operation::Rep => {
+                    text.remove(index);
+                    text.insert(index, diff.operand.unwrap());
+                },
+                Operation::InsStr(inserted) => text.insert_str(index, inserted),
+                Operation::DelRange(len) => drop(text.drain(index..index + len)),
+            }
+        }
+
+        fits
+    }
+
+    #[test]
+    fn edits_match_a_string() {
+        let mut rng = rand::thread_rng();
+        let text = "héllo wörld, ".repeat(500);
+        let mut string = text.clone();
+        let mut rope = Rope::from(text.as_str());
+
+        for _ in 0..5000 {
+            let index = rng.gen_range(0..=string.len() + 1);
+            let opcode = match rng.gen_range(0..5) {
+                0 => Operation::Ins,
+                1 => Operation::Del,
+                2 => Operation::Rep,
+                3 => Operation::InsStr("ab€".repeat(rng.gen_range(0..400))),
+                _ => Operation::DelRange(rng.gen_range(0..300)),
+            };
+            let diff = Diff { opcode, operand: rng.gen_bool(0.95).then_some('ü'), index };
+
+            assert_eq!(rope.apply_diff(&diff).is_ok(), apply_to_string(&mut string, &diff), "{diff}");
+            assert_eq!(rope.len(), string.len());
+            assert_eq!(rope.char_at(index), string.get(index..).and_then(|rest| rest.chars().next()));
+        }
+
+        assert!(rope.chunks.iter().all(|chunk| chunk.len() <= 2 * CHUNK_LEN));
+        assert_eq!(rope.get(10, string.len() - 10), string.get(10..string.len() - 10).map(str::to_string));
+        assert_eq!(String::from(rope), string);
+    }
+
+    #[test]
+    fn empty_text() {
+        let mut rope = Rope::from("");
+        assert!(rope.apply_diff(&Diff { opcode: Operation::Del, operand: None, index: 0 }).is_err());
+
+        rope.apply_diff(&Diff { opcode: Operation::Ins, operand: Some('a'), index: 0 }).unwrap();
+        rope.apply_diff(&Diff { opcode: Operation::Del, operand: None, index: 0 }).unwrap();
+        assert!(rope.is_empty());
+        assert_eq!(rope.to_string(), "");
+    }
+}
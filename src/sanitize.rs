@@ -0,0 +1,121 @@
+use std::{
+    fmt,
+    str::FromStr
+};
+
+use crate::{
+    diff::{Diff, MessageBuf, Operation},
+    error::NotepadError
+};
+
+/// What to do with terminal control characters arriving from peers. Without
+/// the escape character an ANSI sequence is just printable text, so filtering
+/// single characters is enough to keep peers from driving the terminal.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ControlChars {
+    Keep,
+    /// Drop them. Edits that would insert one are dropped too.
+    #[default]
+    Strip,
+    /// Replace them with a visible stand-in, e.g. `␛` for escape.
+    Escape,
+}
+
+impl FromStr for ControlChars {
+    type Err = NotepadError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(ControlChars::Keep),
+            "strip" => Ok(ControlChars::Strip),
+            "escape" => Ok(ControlChars::Escape),
+            _ => Err(NotepadError::command(format!("Unknown control character policy: {s:?}"))),
+        }
+    }
+}
+
+impl fmt::Display for ControlChars {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ControlChars::Keep => "keep",
+            ControlChars::Strip => "strip",
+            ControlChars::Escape => "escape",
+        })
+    }
+}
+
+impl ControlChars {
+    /// What `c` becomes under this policy, `None` if it is dropped.
+    pub fn filter(self, c: char) -> Option<char> {
+        if !is_unsafe(c) {
+            return Some(c);
+        }
+
+        match self {
+            ControlChars::Keep => Some(c),
+            ControlChars::Strip => None,
+            ControlChars::Escape => Some(match c as u32 {
+                c @ 0..=0x1f => char::from_u32(0x2400 + c).expect("control pictures are valid chars"),
+                0x7f => '\u{2421}',
+                _ => char::REPLACEMENT_CHARACTER,
+            }),
+        }
+    }
+
+    pub fn filter_str(self, s: &str) -> String {
+        s.chars().filter_map(|c| self.filter(c)).collect()
+    }
+
+    /// Filters the operands of inserts and replacements. Changing the text this
+    /// way means it can drift from peers until the next snapshot, which is
+    /// filtered as well.
+    pub fn filter_diffs(self, diffs: MessageBuf) -> MessageBuf {
+        let messages = diffs.messages.into_iter().filter_map(|diff| match (&diff.opcode, diff.operand) {
+            (Operation::InsStr(text), _) => Some(Diff { opcode: Operation::InsStr(self.filter_str(text)), ..diff }),
+            (_, Some(c)) => self.filter(c).map(|c| Diff { operand: Some(c), ..diff }),
+            (_, None) => Some(diff),
+        }).collect();
+
+        MessageBuf { messages }
+    }
+}
+
+/// Control characters other than newlines and tabs.
+fn is_unsafe(c: char) -> bool {
+    c.is_control() && c != '\n' && c != '\t'
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::diff::Operation;
+
+    #[test]
+    fn filters_control_chars() {
+        let text = "a\x1b[31mb\n\tc\x7f\u{85}";
+
+        assert_eq!(ControlChars::Keep.filter_str(text), text);
+        assert_eq!(ControlChars::Strip.filter_str(text), "a[31mb\n\tc");
+        assert_eq!(ControlChars::Escape.filter_str(text), "a\u{241b}[31mb\n\tc\u{2421}\u{fffd}");
+    }
+
+    #[test]
+    fn filters_diffs() {
+        let diff = |opcode, operand| Diff { opcode, operand, index: 0 };
+        let diffs = || MessageBuf { messages: vec![
+            diff(Operation::Ins, Some('\x1b')),
+            diff(Operation::Del, None),
+            diff(Operation::Rep, Some('a')),
+        ] };
+
+        assert_eq!(ControlChars::Strip.filter_diffs(diffs()).messages, &diffs().messages[1..]);
+        assert_eq!(ControlChars::Escape.filter_diffs(diffs()).messages[0].operand, Some('\u{241b}'));
+    }
+
+    #[test]
+    fn parse_policy() {
+        assert_eq!("escape".parse::<ControlChars>().unwrap(), ControlChars::Escape);
+        assert_eq!(ControlChars::Strip.to_string().parse::<ControlChars>().unwrap(), ControlChars::Strip);
+        assert!("bogus".parse::<ControlChars>().is_err());
+    }
+}
@@ -0,0 +1,80 @@
+use libp2p::gossipsub::{PeerScoreParams, PeerScoreThresholds, TopicScoreParams};
+
+use crate::error::NotepadError;
+
+/// How gossipsub scores peers. Payloads [`crate::message::check`] finds
+/// malformed count against the peer that forwarded them, as do broken gossip
+/// promises and grafting too eagerly, and peers scored below the graylist
+/// threshold are pruned from the mesh with everything they send ignored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Scoring {
+    /// Score peers in the rooms joined, and not only by ping round trips.
+    pub enabled: bool,
+    /// Weight of each room's topic in the scores.
+    pub topic_weight: f64,
+    /// Penalty per malformed payload, squared as they add up so a stray one
+    /// is forgiven and a stream of them isn't.
+    pub invalid_message_weight: f64,
+    /// Score below which a peer is graylisted. Gossip to a peer stops at an
+    /// eighth of it, and its own publishes are dropped at five eighths.
+    pub graylist_threshold: f64,
+}
+
+impl Default for Scoring {
+    fn default() -> Self {
+        Self { enabled: true, topic_weight: 1.0, invalid_message_weight: -10.0, graylist_threshold: -80.0 }
+    }
+}
+
+impl Scoring {
+    pub fn params(&self) -> PeerScoreParams {
+        PeerScoreParams {
+            // Peers of a room often share a network, or a machine.
+            ip_colocation_factor_weight: 0.0,
+            ..Default::default()
+        }
+    }
+
+    pub fn thresholds(&self) -> PeerScoreThresholds {
+        PeerScoreThresholds {
+            gossip_threshold: self.graylist_threshold / 8.0,
+            publish_threshold: self.graylist_threshold * 5.0 / 8.0,
+            graylist_threshold: self.graylist_threshold,
+            ..Default::default()
+        }
+    }
+
+    /// Params of a room's topic. Rooms go quiet whenever nobody types, so
+    /// mesh peers aren't expected to deliver any number of messages.
+    pub fn topic_params(&self) -> TopicScoreParams {
+        TopicScoreParams {
+            topic_weight: self.topic_weight,
+            mesh_message_deliveries_weight: 0.0,
+            mesh_failure_penalty_weight: 0.0,
+            invalid_message_deliveries_weight: self.invalid_message_weight,
+            ..Default::default()
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), NotepadError> {
+        self.params().validate().map_err(NotepadError::command)?;
+        self.thresholds().validate().map_err(NotepadError::command)?;
+        self.topic_params().validate().map_err(NotepadError::command)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn params_are_valid() {
+        assert!(Scoring::default().validate().is_ok());
+        assert_eq!(Scoring::default().thresholds().publish_threshold, -50.0);
+
+        assert!(Scoring { graylist_threshold: 10.0, ..Default::default() }.validate().is_err());
+        assert!(Scoring { invalid_message_weight: 1.0, ..Default::default() }.validate().is_err());
+    }
+}
@@ -0,0 +1,70 @@
+use std::collections::VecDeque;
+
+/// Commands read from a file by `run`, one per line, run in turn as if they
+/// were typed, though aliases in them aren't expanded. Blank lines and
+/// lines starting with `#` are skipped.
+#[derive(Debug)]
+pub struct Script {
+    pub path: String,
+    /// Whether a command failing stops the rest from running.
+    pub abort: bool,
+    /// Commands left with their line numbers, from 1.
+    lines: VecDeque<(usize, String)>,
+    /// Line of the command run last.
+    line: usize,
+}
+
+impl Script {
+    pub fn new(path: &str, text: &str, abort: bool) -> Self {
+        let lines = text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+            .map(|(i, line)| (i + 1, line.to_string()))
+            .collect();
+
+        Self { path: path.to_string(), abort, lines, line: 0 }
+    }
+
+    /// Line of the command run last, 0 before any is.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// How many commands are left to run.
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+}
+
+impl Iterator for Script {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        let (line, command) = self.lines.pop_front()?;
+        self.line = line;
+
+        Some(command)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn skips_blank_lines_and_comments() {
+        let mut script = Script::new("seed.txt", "# A template\ninss:0:Title\n\n  # indented\nsee\n", true);
+
+        assert_eq!(script.len(), 2);
+        assert_eq!(script.next().as_deref(), Some("inss:0:Title"));
+        assert_eq!(script.line(), 2);
+        assert_eq!(script.next().as_deref(), Some("see"));
+        assert_eq!(script.line(), 5);
+        assert!(script.is_empty() && script.next().is_none());
+    }
+}
@@ -0,0 +1,107 @@
+use std::fmt;
+
+use chacha20poly1305::{
+    aead::{
+        Aead, KeyInit
+    },
+    ChaCha20Poly1305, Key, Nonce
+};
+
+use crate::{container, error::NotepadError, message::Message};
+
+pub const NONCE_LEN: usize = 12;
+
+/// The key of a private room, derived from its passphrase with Argon2,
+/// salted with the room's name. Every payload published to the room is
+/// sealed with it, so a peer that finds the room's topic on the gossip
+/// mesh without the passphrase sees only ciphertext, and what it
+/// publishes there fails to open and is dropped.
+#[derive(Clone)]
+pub struct RoomKey {
+    key: [u8; 32],
+    cipher: ChaCha20Poly1305,
+}
+
+impl RoomKey {
+    pub fn derive(room: &str, passphrase: &str) -> Self {
+        let salt = blake3::derive_key("p2p-notepad 2024 room key salt", room.as_bytes());
+
+        Self::from_bytes(container::derive_key(passphrase, &salt))
+    }
+
+    /// The key as saved in a [`Workspace`](crate::workspace::Workspace), in place of the passphrase.
+    pub fn from_bytes(key: [u8; 32]) -> Self {
+        Self { key, cipher: ChaCha20Poly1305::new(Key::from_slice(&key)) }
+    }
+
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.key
+    }
+
+    /// The hex id a private room's topic is named by, from a key derived
+    /// from this one, so the slow passphrase hashing guards the topic too
+    /// and the topic gives nothing away about the key.
+    pub fn topic_id(&self, room: &str) -> String {
+        let key = blake3::derive_key("p2p-notepad 2024 room topic key", &self.key);
+
+        blake3::keyed_hash(&key, room.as_bytes()).to_hex().to_string()
+    }
+
+    /// Encrypts an encoded message under a random nonce, as a [`Message::Sealed`] payload.
+    pub fn seal(&self, data: &[u8]) -> Vec<u8> {
+        let nonce: [u8; NONCE_LEN] = rand::random();
+
+        let ciphertext = self.cipher
+            .encrypt(Nonce::from_slice(&nonce), data)
+            .expect("encrypting into memory can't fail");
+
+        Message::Sealed { nonce, ciphertext }.into()
+    }
+
+    /// Decrypts and decodes a sealed message, which must not be sealed again.
+    pub fn open(&self, nonce: &[u8; NONCE_LEN], ciphertext: &[u8]) -> Result<Message, NotepadError> {
+        let data = self.cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| NotepadError::Decode("Sealed message doesn't open with the room key"))?;
+
+        match Message::try_from(data)? {
+            Message::Sealed { .. } => Err(NotepadError::Decode("Sealed message inside a sealed message")),
+            message => Ok(message),
+        }
+    }
+}
+
+/// Keeps the key out of logs.
+impl fmt::Debug for RoomKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RoomKey(..)")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn open(key: &RoomKey, data: Vec<u8>) -> Result<Message, NotepadError> {
+        let Message::Sealed { nonce, ciphertext } = Message::try_from(data).unwrap() else {
+            panic!("expected a sealed message");
+        };
+
+        key.open(&nonce, &ciphertext)
+    }
+
+    #[test]
+    fn sealed_round_trip() {
+        let key = RoomKey::derive("room", "secret");
+        let message = || Message::Clipboard("the plan".to_string());
+
+        let data: Vec<u8> = message().into();
+        let sealed = key.seal(&data);
+        assert!(!sealed.windows(8).any(|window| window == b"the plan"));
+        assert_eq!(open(&key, sealed.clone()).unwrap(), message());
+
+        assert!(open(&RoomKey::derive("room", "other"), sealed.clone()).is_err());
+        assert!(open(&RoomKey::derive("other", "secret"), sealed.clone()).is_err());
+        assert!(open(&key, key.seal(&sealed)).is_err());
+    }
+}
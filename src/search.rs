@@ -0,0 +1,227 @@
+use std::{
+    ops::Range,
+    str::FromStr
+};
+
+use crate::error::NotepadError;
+
+/// Where `pattern` occurs in `text`, in byte offsets like the indices of
+/// diffs, without overlapping.
+pub fn find(text: &str, pattern: &str) -> Vec<Range<usize>> {
+    if pattern.is_empty() {
+        return Vec::new();
+    }
+
+    text.match_indices(pattern).map(|(start, found)| start..start + found.len()).collect()
+}
+
+/// A simple regular expression, enough to look for text without knowing it
+/// exactly: `.`, classes such as `[a-z]` or `[^ ]`, `\d`, `\w` and `\s`,
+/// the repeats `*`, `+` and `?`, and `^` and `$` anchoring to the start and
+/// end of the text. Other characters match themselves, or after a `\`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Regex {
+    nodes: Vec<Node>,
+    start: bool,
+    end: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Node {
+    atom: Atom,
+    repeat: Repeat,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Atom {
+    Char(char),
+    Any,
+    Class { negated: bool, ranges: Vec<(char, char)> },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Repeat {
+    One,
+    Optional,
+    Any,
+    Many,
+}
+
+impl Atom {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Atom::Char(expected) => c == *expected,
+            Atom::Any => c != '\n',
+            Atom::Class { negated, ranges } => ranges.iter().any(|&(low, high)| (low..=high).contains(&c)) != *negated,
+        }
+    }
+
+    /// The class a `\` followed by `c` stands for, or `c` itself.
+    fn escaped(c: char) -> Atom {
+        let class = |ranges: &[(char, char)]| Atom::Class { negated: false, ranges: ranges.to_vec() };
+
+        match c {
+            'd' => class(&[('0', '9')]),
+            'w' => class(&[('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')]),
+            's' => class(&[(' ', ' '), ('\t', '\t'), ('\n', '\n'), ('\r', '\r')]),
+            'n' => Atom::Char('\n'),
+            't' => Atom::Char('\t'),
+            c => Atom::Char(c),
+        }
+    }
+}
+
+impl FromStr for Regex {
+    type Err = NotepadError;
+
+    fn from_str(pattern: &str) -> Result<Self, Self::Err> {
+        let (start, pattern) = match pattern.strip_prefix('^') {
+            Some(rest) => (true, rest),
+            None => (false, pattern),
+        };
+        let (end, pattern) = match pattern.strip_suffix('$').filter(|rest| !rest.ends_with('\\')) {
+            Some(rest) => (true, rest),
+            None => (false, pattern),
+        };
+
+        let mut nodes: Vec<Node> = Vec::new();
+        let mut chars = pattern.chars();
+
+        while let Some(c) = chars.next() {
+            let atom = match c {
+                '*' | '+' | '?' => {
+                    let node = nodes.last_mut()
+                        .filter(|node| node.repeat == Repeat::One)
+                        .ok_or_else(|| NotepadError::command(format!("`{c}` doesn't follow something to repeat")))?;
+
+                    node.repeat = match c {
+                        '*' => Repeat::Any,
+                        '+' => Repeat::Many,
+                        _ => Repeat::Optional,
+                    };
+                    continue;
+                },
+                '.' => Atom::Any,
+                '\\' => Atom::escaped(chars.next().ok_or_else(|| NotepadError::command("Pattern ends in a lone `\\`"))?),
+                '[' => {
+                    let mut negated = false;
+                    let mut ranges = Vec::new();
+                    let mut first = true;
+
+                    loop {
+                        let c = chars.next().ok_or_else(|| NotepadError::command("`[` isn't closed by a `]`"))?;
+                        match c {
+                            '^' if first => negated = true,
+                            ']' if !first || !ranges.is_empty() => break,
+                            '\\' => match Atom::escaped(chars.next().ok_or_else(|| NotepadError::command("Pattern ends in a lone `\\`"))?) {
+                                Atom::Class { ranges: escaped, .. } => ranges.extend(escaped),
+                                Atom::Char(c) => ranges.push((c, c)),
+                                Atom::Any => {},
+                            },
+                            '-' if !ranges.is_empty() && !chars.as_str().is_empty() && !chars.as_str().starts_with(']') => {
+                                let high = chars.next().expect("checked above");
+                                let (low, _) = ranges.pop().expect("checked above");
+                                if high < low {
+                                    return Err(NotepadError::command(format!("Range `{low}-{high}` is backwards")));
+                                }
+                                ranges.push((low, high));
+                            },
+                            c => ranges.push((c, c)),
+                        }
+                        first = false;
+                    }
+
+                    Atom::Class { negated, ranges }
+                },
+                c => Atom::Char(c),
+            };
+
+            nodes.push(Node { atom, repeat: Repeat::One });
+        }
+
+        Ok(Self { nodes, start, end })
+    }
+}
+
+impl Regex {
+    /// Where the expression matches `text`, leftmost and longest first,
+    /// without overlapping. Empty matches are left out.
+    pub fn find_iter(&self, text: &str) -> Vec<Range<usize>> {
+        let mut found = Vec::new();
+        let mut start = 0;
+
+        while start <= text.len() {
+            match self.match_at(&self.nodes, text, start) {
+                Some(end) if end > start => {
+                    found.push(start..end);
+                    start = end;
+                },
+                _ => start += text[start..].chars().next().map_or(1, char::len_utf8),
+            }
+
+            if self.start {
+                break;
+            }
+        }
+
+        found
+    }
+
+    /// Where a match of `nodes` starting at `pos` ends, if there is one.
+    fn match_at(&self, nodes: &[Node], text: &str, pos: usize) -> Option<usize> {
+        let Some((node, rest)) = nodes.split_first() else {
+            return (!self.end || pos == text.len()).then_some(pos);
+        };
+        let next = |pos: usize| text[pos..].chars().next().filter(|&c| node.atom.matches(c)).map(|c| pos + c.len_utf8());
+
+        match node.repeat {
+            Repeat::One => next(pos).and_then(|pos| self.match_at(rest, text, pos)),
+            Repeat::Optional => next(pos)
+                .and_then(|after| self.match_at(rest, text, after))
+                .or_else(|| self.match_at(rest, text, pos)),
+            Repeat::Any | Repeat::Many => {
+                let mut ends = vec![pos];
+                while let Some(end) = next(*ends.last().expect("starts with pos")) {
+                    ends.push(end);
+                }
+
+                let min = usize::from(node.repeat == Repeat::Many);
+                ends[min..].iter().rev().find_map(|&end| self.match_at(rest, text, end))
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn regex(pattern: &str, text: &str) -> Vec<Range<usize>> {
+        pattern.parse::<Regex>().unwrap().find_iter(text)
+    }
+
+    #[test]
+    fn finds_text() {
+        assert_eq!(find("abcabc", "bc"), vec![1..3, 4..6]);
+        assert_eq!(find("aaaa", "aa"), vec![0..2, 2..4]);
+        assert!(find("abc", "").is_empty());
+    }
+
+    #[test]
+    fn finds_regex_matches() {
+        assert_eq!(regex("a.c", "abc aXc ac"), vec![0..3, 4..7]);
+        assert_eq!(regex(r"\d+", "x 12 y 345"), vec![2..4, 7..10]);
+        assert_eq!(regex("colou?r", "color colour"), vec![0..5, 6..12]);
+        assert_eq!(regex("[a-c]+", "xxbcaxa"), vec![2..5, 6..7]);
+        assert_eq!(regex("[^ ]+$", "one two"), vec![4..7]);
+        assert_eq!(regex("^one", "one one"), vec![0..3]);
+        assert_eq!(regex("b*", "abbb"), vec![1..4]);
+        assert_eq!(regex(r"\.", "a.b"), vec![1..2]);
+        assert_eq!(regex("é.", "héllo"), vec![1..4]);
+
+        assert!("*a".parse::<Regex>().is_err());
+        assert!("a**".parse::<Regex>().is_err());
+        assert!("[ab".parse::<Regex>().is_err());
+        assert!("[z-a]".parse::<Regex>().is_err());
+    }
+}
@@ -0,0 +1,108 @@
+use std::{
+    collections::BTreeSet,
+    time::{Duration, Instant}
+};
+
+use libp2p::PeerId;
+
+use crate::error::NotepadError;
+
+/// A time-boxed edit session started with `session start`, counting who took
+/// part and how many operations were applied until it ends.
+#[derive(Debug)]
+pub struct Session {
+    pub ends: Instant,
+    /// Turn the room read-only once the session ends.
+    pub read_only: bool,
+    /// Operations applied locally or by peers during the session.
+    pub ops: usize,
+    /// Peers heard from during the session, not counting this one.
+    participants: BTreeSet<PeerId>,
+}
+
+/// Published to the room when a session ends, so everyone can check they
+/// finished with the same text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionSummary {
+    /// Name of the document the session was about.
+    pub document: String,
+    /// Peers that took part, including the one publishing the summary.
+    pub participants: u32,
+    pub ops: u32,
+    /// Checksum of the document text when the session ended.
+    pub checksum: String,
+}
+
+impl Session {
+    pub fn new(duration: Duration, read_only: bool, now: Instant) -> Self {
+        Self { ends: now + duration, read_only, ops: 0, participants: BTreeSet::new() }
+    }
+
+    /// Counts `ops` applied during the session, made by `peer` or locally if `None`.
+    pub fn record(&mut self, peer: Option<PeerId>, ops: usize) {
+        self.ops += ops;
+        self.seen(peer);
+    }
+
+    pub fn seen(&mut self, peer: Option<PeerId>) {
+        self.participants.extend(peer);
+    }
+
+    pub fn summary(&self, document: &str, checksum: String) -> SessionSummary {
+        SessionSummary {
+            document: document.to_string(),
+            participants: (self.participants.len() + 1).try_into().unwrap_or(u32::MAX),
+            ops: self.ops.try_into().unwrap_or(u32::MAX),
+            checksum,
+        }
+    }
+}
+
+/// Parses a duration such as `90s`, `30m` or `1h`, plain numbers are minutes.
+pub fn parse_duration(s: &str) -> Result<Duration, NotepadError> {
+    let error = || NotepadError::command(format!("Invalid duration: {s:?}, expected e.g. `90s`, `30m` or `1h`"));
+    let (number, unit) = s.find(|c: char| !c.is_ascii_digit()).map_or((s, "m"), |i| s.split_at(i));
+    let number: u64 = number.parse().map_err(|_| error())?;
+
+    let secs = match unit {
+        "s" => number,
+        "m" => number.saturating_mul(60),
+        "h" => number.saturating_mul(60 * 60),
+        _ => return Err(error()),
+    };
+
+    match secs {
+        0 => Err(error()),
+        secs => Ok(Duration::from_secs(secs)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("90s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("30m").unwrap(), Duration::from_secs(30 * 60));
+        assert_eq!(parse_duration("2").unwrap(), Duration::from_secs(2 * 60));
+        assert_eq!(parse_duration("1h").unwrap(), Duration::from_secs(60 * 60));
+
+        assert!(parse_duration("0s").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("5d").is_err());
+    }
+
+    #[test]
+    fn summarizes_sessions() {
+        let mut session = Session::new(Duration::from_secs(60), false, Instant::now());
+        let peer = PeerId::random();
+
+        session.record(None, 3);
+        session.record(Some(peer), 2);
+        session.seen(Some(peer));
+
+        let summary = session.summary("notes", "abc".to_string());
+        assert_eq!(summary, SessionSummary { document: "notes".to_string(), participants: 2, ops: 5, checksum: "abc".to_string() });
+    }
+}
@@ -0,0 +1,124 @@
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    time::Duration
+};
+
+use libp2p::PeerId;
+
+/// Which peer to catch up from. Peers are ranked by how reliably they
+/// answered sync requests before, how many edits they advertised having
+/// applied and how quickly they answer, and the next best is asked should
+/// one not answer.
+#[derive(Debug, Default)]
+pub struct Sources {
+    /// Edits each peer last advertised having applied in its digests.
+    heads: HashMap<PeerId, u64>,
+    /// Sync requests each peer answered and left unanswered.
+    outcomes: HashMap<PeerId, (u32, u32)>,
+    /// The peer last asked, with the versions the request was sent with.
+    asked: Option<(PeerId, Vec<(PeerId, u64)>)>,
+    /// Peers asked since catching up began, so none is asked twice.
+    tried: HashSet<PeerId>,
+}
+
+impl Sources {
+    /// Records that `peer` advertised having applied `head` edits in all.
+    pub fn advertised(&mut self, peer: PeerId, head: u64) {
+        self.heads.insert(peer, head);
+    }
+
+    /// Starts catching up anew, so every peer may be asked again.
+    pub fn begin(&mut self) {
+        self.asked = None;
+        self.tried.clear();
+    }
+
+    /// Ranks `candidates` not yet asked, given their round trips where
+    /// measured, best first, putting `preferred` first if it is among them.
+    pub fn rank(&self, preferred: Option<PeerId>, candidates: Vec<(PeerId, Option<Duration>)>) -> Vec<PeerId> {
+        let mut candidates: Vec<_> = candidates.into_iter().filter(|(peer, _)| !self.tried.contains(peer)).collect();
+        candidates.sort_by_key(|&(peer, rtt)| (Some(peer) != preferred, self.score(peer, rtt)));
+
+        candidates.into_iter().map(|(peer, _)| peer).collect()
+    }
+
+    /// Records that `peer` was asked, with the `versions` of the request.
+    pub fn asked(&mut self, peer: PeerId, versions: Vec<(PeerId, u64)>) {
+        self.tried.insert(peer);
+        self.asked = Some((peer, versions));
+    }
+
+    pub fn answered(&mut self, peer: PeerId) {
+        self.outcomes.entry(peer).or_default().0 += 1;
+        self.begin();
+    }
+
+    /// Records that `peer` couldn't be asked or didn't answer.
+    pub fn failed(&mut self, peer: PeerId) {
+        self.outcomes.entry(peer).or_default().1 += 1;
+        self.tried.insert(peer);
+    }
+
+    /// Takes the peer asked last and the versions it was asked with, once
+    /// it didn't answer in time, counting it as failed.
+    pub fn unanswered(&mut self) -> Option<(PeerId, Vec<(PeerId, u64)>)> {
+        let (peer, versions) = self.asked.take()?;
+        self.failed(peer);
+
+        Some((peer, versions))
+    }
+
+    /// Forgets the heads advertised in the room left. How reliable peers
+    /// were is kept, as they may be met in other rooms.
+    pub fn clear(&mut self) {
+        self.heads.clear();
+        self.begin();
+    }
+
+    pub fn memory(&self) -> usize {
+        self.heads.len() * size_of::<(PeerId, u64)>()
+            + self.outcomes.len() * size_of::<(PeerId, (u32, u32))>()
+            + self.asked.as_ref().map_or(0, |(_, versions)| versions.capacity() * size_of::<(PeerId, u64)>())
+            + self.tried.len() * size_of::<PeerId>()
+    }
+
+    /// Lower is better: peers that failed more often than they answered go
+    /// last, then those that advertised fewer edits, then slower ones.
+    fn score(&self, peer: PeerId, rtt: Option<Duration>) -> (bool, Reverse<u64>, Duration) {
+        let (answered, failed) = self.outcomes.get(&peer).copied().unwrap_or_default();
+        let head = self.heads.get(&peer).copied().unwrap_or_default();
+
+        (failed > answered, Reverse(head), rtt.unwrap_or(Duration::MAX))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn best_sources_are_asked_first() {
+        let (a, b, c) = (PeerId::random(), PeerId::random(), PeerId::random());
+        let ms = |ms| Some(Duration::from_millis(ms));
+        let candidates = || vec![(a, ms(80)), (b, ms(20)), (c, None)];
+        let mut sources = Sources::default();
+
+        assert_eq!(sources.rank(None, candidates()), vec![b, a, c]);
+        assert_eq!(sources.rank(Some(c), candidates()), vec![c, b, a]);
+
+        // Heads come before round trips, and reliability before heads.
+        sources.advertised(a, 10);
+        assert_eq!(sources.rank(None, candidates()), vec![a, b, c]);
+        sources.asked(a, Vec::new());
+        assert_eq!(sources.unanswered(), Some((a, Vec::new())));
+        assert_eq!(sources.rank(None, candidates()), vec![b, c]);
+        sources.failed(b);
+        assert_eq!(sources.rank(None, candidates()), vec![c]);
+
+        sources.answered(c);
+        assert_eq!(sources.rank(None, candidates()), vec![c, a, b]);
+        sources.answered(a);
+        assert_eq!(sources.rank(None, candidates()), vec![a, c, b]);
+    }
+}
@@ -0,0 +1,133 @@
+use std::{
+    collections::HashSet,
+    path::Path
+};
+
+use crate::error::NotepadError;
+
+/// A word in a document that isn't in the dictionary. Lines and columns
+/// count characters from 0.
+#[derive(Debug, PartialEq)]
+pub struct Misspelling<'a> {
+    pub line: usize,
+    pub column: usize,
+    pub word: &'a str,
+}
+
+/// Words considered correctly spelled, checked locally without sending anything to the room.
+#[derive(Debug, Default)]
+pub struct Dictionary {
+    words: HashSet<String>,
+}
+
+impl Dictionary {
+    pub fn load(path: &Path) -> Result<Self, NotepadError> {
+        Ok(Self::parse(&std::fs::read_to_string(path)?))
+    }
+
+    /// Reads a word per line, from either a plain word list or a hunspell
+    /// `.dic` file. The word count heading a `.dic` file and the affix flags
+    /// after each `/` are skipped, so words are only known in the form listed.
+    pub fn parse(text: &str) -> Self {
+        let words = text
+            .lines()
+            .map(|line| line.split('/').next().unwrap_or_default().trim())
+            .filter(|word| !word.is_empty() && !word.chars().all(|c| c.is_ascii_digit()))
+            .map(str::to_lowercase)
+            .collect();
+
+        Self { words }
+    }
+
+    pub fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    pub fn contains(&self, word: &str) -> bool {
+        self.words.contains(&word.to_lowercase())
+    }
+
+    /// Words of `text` missing from the dictionary. Words with digits in them
+    /// are left alone, they are more likely codes than prose.
+    pub fn check<'a>(&self, text: &'a str) -> Vec<Misspelling<'a>> {
+        text.lines()
+            .enumerate()
+            .flat_map(|(line, text)| words(text).map(move |(column, word)| Misspelling { line, column, word }))
+            .filter(|misspelling| !misspelling.word.chars().any(|c| c.is_numeric()) && !self.contains(misspelling.word))
+            .collect()
+    }
+}
+
+/// Words of a line with the column they start at. Apostrophes inside a word,
+/// as in `don't`, are part of it.
+fn words(line: &str) -> impl Iterator<Item = (usize, &str)> {
+    let is_word = |c: char| c.is_alphanumeric() || c == '\'';
+    let mut chars = line.char_indices().enumerate().peekable();
+
+    std::iter::from_fn(move || {
+        let (column, start) = loop {
+            let (column, (start, c)) = chars.next()?;
+            if c.is_alphanumeric() {
+                break (column, start);
+            }
+        };
+        let mut end = line.len();
+
+        while let Some(&(_, (i, c))) = chars.peek() {
+            if !is_word(c) {
+                end = i;
+                break;
+            }
+            chars.next();
+        }
+
+        Some((column, line[start..end].trim_end_matches('\'')))
+    })
+}
+
+/// A line of carets under the misspelled words of a line, for printing below it.
+pub fn underline(misspellings: &[&Misspelling]) -> String {
+    let mut carets = String::new();
+
+    for misspelling in misspellings {
+        let padding = misspelling.column.saturating_sub(carets.chars().count());
+        carets.extend(std::iter::repeat_n(' ', padding));
+        carets.extend(std::iter::repeat_n('^', misspelling.word.chars().count()));
+    }
+
+    carets
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_dictionaries() {
+        let dictionary = Dictionary::parse("3\nHello/MS\nworld\ndon't\n");
+
+        assert_eq!(dictionary.len(), 3);
+        assert!(dictionary.contains("hello"));
+        assert!(dictionary.contains("World"));
+        assert!(!dictionary.contains("3"));
+    }
+
+    #[test]
+    fn finds_misspellings() {
+        let dictionary = Dictionary::parse("hello\nworld\ndon't\n");
+        let misspellings = dictionary.check("Hello, wrold!\ndon't 42nd spel");
+
+        assert_eq!(misspellings, vec![
+            Misspelling { line: 0, column: 7, word: "wrold" },
+            Misspelling { line: 1, column: 11, word: "spel" },
+        ]);
+        assert_eq!(underline(&[&misspellings[0]]), "       ^^^^^");
+
+        let misspellings = dictionary.check("a hello b");
+        assert_eq!(underline(&misspellings.iter().collect::<Vec<_>>()), "^       ^");
+    }
+}
@@ -0,0 +1,267 @@
+use std::{
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    str::FromStr
+};
+
+use crate::error::NotepadError;
+
+/// Where saved documents and journals are kept. Documents, like the
+/// workspace, are replaced whole, journals, like the operation log, only
+/// ever grow at the end or are cut short after a crash.
+pub trait Storage: fmt::Debug {
+    /// The document saved as `name`, `None` if there is none.
+    fn load(&self, name: &str) -> Result<Option<Vec<u8>>, NotepadError>;
+
+    /// Replaces the document saved as `name`, so that a crash leaves either
+    /// the previous one or the new one.
+    fn save(&mut self, name: &str, data: &[u8]) -> Result<(), NotepadError>;
+
+    /// The journal `name`, empty if there is none.
+    fn journal(&self, name: &str) -> Result<Vec<u8>, NotepadError>;
+
+    /// Appends `data` to the journal `name` in a single write, creating it if needed.
+    fn append(&mut self, name: &str, data: &[u8]) -> Result<(), NotepadError>;
+
+    /// Cuts the journal `name` to its first `len` bytes.
+    fn truncate(&mut self, name: &str, len: usize) -> Result<(), NotepadError>;
+
+    /// Replaces the journal `name` with `data`, so that a crash leaves
+    /// either the previous journal or the new one.
+    fn rewrite(&mut self, name: &str, data: &[u8]) -> Result<(), NotepadError>;
+}
+
+/// Which [`Storage`] to keep the workspace and operation logs in.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Backend {
+    /// A file each, where they are configured to be.
+    #[default]
+    Files,
+    /// A sled database at the path, holding everything, for hosts of many rooms.
+    Sled(PathBuf),
+}
+
+impl FromStr for Backend {
+    type Err = NotepadError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            _ if s == "files" => Ok(Backend::Files),
+            Some(("sled", path)) if !path.is_empty() => Ok(Backend::Sled(PathBuf::from(path))),
+            _ => Err(NotepadError::command(format!("Unknown storage {s:?}, expected `files` or `sled:<path>`"))),
+        }
+    }
+}
+
+/// Documents and journals as files in a directory.
+#[derive(Debug)]
+pub struct Files {
+    dir: PathBuf,
+    /// The journal last appended to, kept open as appends come in bursts.
+    open: Option<(String, File)>,
+}
+
+impl Files {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir, open: None }
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+}
+
+impl Storage for Files {
+    fn load(&self, name: &str) -> Result<Option<Vec<u8>>, NotepadError> {
+        match std::fs::read(self.path(name)) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Writes next to the document first, then moves it in place.
+    fn save(&mut self, name: &str, data: &[u8]) -> Result<(), NotepadError> {
+        let path = self.path(name);
+        let temp = path.with_extension("tmp");
+
+        std::fs::create_dir_all(&self.dir)?;
+        std::fs::write(&temp, data)?;
+        std::fs::rename(temp, path)?;
+
+        Ok(())
+    }
+
+    fn journal(&self, name: &str) -> Result<Vec<u8>, NotepadError> {
+        Ok(self.load(name)?.unwrap_or_default())
+    }
+
+    fn append(&mut self, name: &str, data: &[u8]) -> Result<(), NotepadError> {
+        let file = match &mut self.open {
+            Some((open, file)) if open == name => file,
+            _ => {
+                std::fs::create_dir_all(&self.dir)?;
+                let file = OpenOptions::new().create(true).append(true).open(self.path(name))?;
+                &mut self.open.insert((name.to_string(), file)).1
+            },
+        };
+
+        file.write_all(data)?;
+
+        Ok(())
+    }
+
+    fn truncate(&mut self, name: &str, len: usize) -> Result<(), NotepadError> {
+        OpenOptions::new().write(true).open(self.path(name))?.set_len(len as u64)?;
+
+        Ok(())
+    }
+
+    /// Saved like a document, the file kept open for appends being the one replaced.
+    fn rewrite(&mut self, name: &str, data: &[u8]) -> Result<(), NotepadError> {
+        if self.open.as_ref().is_some_and(|(open, _)| open == name) {
+            self.open = None;
+        }
+
+        self.save(name, data)
+    }
+}
+
+/// Documents and journals in a sled database, which writes each change as
+/// a transaction, so many rooms don't mean many files. Several scopes can
+/// share a database, as it can only be opened once.
+#[derive(Debug, Clone)]
+pub struct Sled {
+    db: sled::Db,
+    /// Prefix of every name, standing in for a directory.
+    scope: String,
+}
+
+impl Sled {
+    pub fn open(path: &Path) -> Result<Self, NotepadError> {
+        Ok(Self { db: sled::open(path).map_err(io::Error::from)?, scope: String::new() })
+    }
+
+    /// A view of the same database whose names don't collide with those of other scopes.
+    pub fn scoped(&self, scope: &str) -> Self {
+        Self { db: self.db.clone(), scope: format!("{scope}/") }
+    }
+
+    fn journal_tree(&self, name: &str) -> Result<sled::Tree, NotepadError> {
+        Ok(self.db.open_tree(format!("journal:{}{name}", self.scope)).map_err(io::Error::from)?)
+    }
+}
+
+impl Storage for Sled {
+    fn load(&self, name: &str) -> Result<Option<Vec<u8>>, NotepadError> {
+        let data = self.db.get(format!("{}{name}", self.scope)).map_err(io::Error::from)?;
+
+        Ok(data.map(|data| data.to_vec()))
+    }
+
+    fn save(&mut self, name: &str, data: &[u8]) -> Result<(), NotepadError> {
+        self.db.insert(format!("{}{name}", self.scope), data).map_err(io::Error::from)?;
+        self.db.flush().map_err(io::Error::from)?;
+
+        Ok(())
+    }
+
+    /// Journals are a tree each, holding every append under an increasing id.
+    fn journal(&self, name: &str) -> Result<Vec<u8>, NotepadError> {
+        let mut journal = Vec::new();
+
+        for entry in self.journal_tree(name)?.iter() {
+            journal.extend(entry.map_err(io::Error::from)?.1.iter());
+        }
+
+        Ok(journal)
+    }
+
+    fn append(&mut self, name: &str, data: &[u8]) -> Result<(), NotepadError> {
+        let id = self.db.generate_id().map_err(io::Error::from)?;
+        self.journal_tree(name)?.insert(id.to_be_bytes(), data).map_err(io::Error::from)?;
+
+        Ok(())
+    }
+
+    fn truncate(&mut self, name: &str, len: usize) -> Result<(), NotepadError> {
+        let tree = self.journal_tree(name)?;
+        let mut kept = 0;
+
+        for entry in tree.iter() {
+            let (id, data) = entry.map_err(io::Error::from)?;
+
+            if kept >= len {
+                tree.remove(id).map_err(io::Error::from)?;
+            } else if kept + data.len() > len {
+                tree.insert(id, &data[..len - kept]).map_err(io::Error::from)?;
+            }
+
+            kept += data.len();
+        }
+
+        Ok(())
+    }
+
+    /// Every append is removed and the data inserted in one batch, which sled applies atomically.
+    fn rewrite(&mut self, name: &str, data: &[u8]) -> Result<(), NotepadError> {
+        let tree = self.journal_tree(name)?;
+        let mut batch = sled::Batch::default();
+
+        for id in tree.iter().keys() {
+            batch.remove(id.map_err(io::Error::from)?);
+        }
+        let id = self.db.generate_id().map_err(io::Error::from)?;
+        batch.insert(id.to_be_bytes().to_vec(), data);
+
+        tree.apply_batch(batch).map_err(io::Error::from)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn round_trip(storage: &mut dyn Storage) {
+        assert_eq!(storage.load("workspace").unwrap(), None);
+        storage.save("workspace", b"old").unwrap();
+        storage.save("workspace", b"new").unwrap();
+        assert_eq!(storage.load("workspace").unwrap(), Some(b"new".to_vec()));
+
+        assert!(storage.journal("room.oplog").unwrap().is_empty());
+        storage.append("room.oplog", b"abc").unwrap();
+        storage.append("other.oplog", b"x").unwrap();
+        storage.append("room.oplog", b"def").unwrap();
+        assert_eq!(storage.journal("room.oplog").unwrap(), b"abcdef");
+
+        storage.truncate("room.oplog", 4).unwrap();
+        storage.append("room.oplog", b"g").unwrap();
+        assert_eq!(storage.journal("room.oplog").unwrap(), b"abcdg");
+        assert_eq!(storage.journal("other.oplog").unwrap(), b"x");
+
+        storage.rewrite("room.oplog", b"hi").unwrap();
+        storage.append("room.oplog", b"!").unwrap();
+        assert_eq!(storage.journal("room.oplog").unwrap(), b"hi!");
+    }
+
+    #[test]
+    fn backends_keep_documents_and_journals() {
+        let dir = std::env::temp_dir().join(format!("p2p-notepad-storage-{}", rand::random::<u64>()));
+
+        round_trip(&mut Files::new(dir.join("files")));
+
+        let sled = Sled::open(&dir.join("sled")).unwrap();
+        round_trip(&mut sled.scoped("a"));
+        assert_eq!(sled.scoped("b").load("workspace").unwrap(), None);
+
+        assert_eq!("sled:db".parse::<Backend>().unwrap(), Backend::Sled(PathBuf::from("db")));
+        assert!("sled".parse::<Backend>().is_err());
+
+        drop(sled);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,182 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    str::FromStr,
+    time::{Duration, Instant}
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream
+};
+
+use crate::error::NotepadError;
+
+/// How often a report is sent while running, one is also sent on exit.
+pub const REPORT_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Commands whose use is counted. Anything else typed is never looked at,
+/// so document text or room names can't end up in a report.
+const COMMANDS: &[&str] = &[
+    "see", "hist", "at", "catchup", "doc", "doc list", "docs", "doc new", "doc rename", "doc archive", "doc eol", "doc restore",
+    "init", "export", "export --encrypt", "export --text", "export --patch", "apply", "import", "import --text", "replay",
+    "peers", "nick", "user", "user new", "user list", "log", "find", "findr", "repa", "quit", "mem", "compact", "tag", "tags", "restore", "lock", "unlock", "claim", "unclaim", "claims", "select", "pending", "probe", "verify", "stats", "mesh", "dial", "dial --relay", "bootstrap", "dashboard", "conflicts", "links", "open", "write", "clip set", "clip get", "say", "board", "board draw", "board erase", "board clear",
+    "attach", "attachments", "fetch", "save", "load", "set", "config save", "aliases", "help", "run", "watch", "watch list", "unwatch", "backup restore", "room", "room create", "room create --from", "room manifest", "room read-only", "room quiet", "room mute", "room unmute", "room grant", "room revoke", "allow", "block", "ro", "swi", "view", "ins", "del", "rep", "inss", "pas", "delr", "copy", "pastec", "insl", "dell", "repl", "undo", "redo",
+];
+
+/// Coarse, anonymous usage counters. No peer ids, nicknames, room names or
+/// text are recorded, only how long the session ran, roughly how many peers
+/// it had at most and how often each command was used.
+#[derive(Debug)]
+pub struct Telemetry {
+    started: Instant,
+    max_peers: usize,
+    commands: BTreeMap<&'static str, u64>,
+}
+
+impl Telemetry {
+    pub fn new(now: Instant) -> Self {
+        Self { started: now, max_peers: 0, commands: BTreeMap::new() }
+    }
+
+    pub fn command(&mut self, op: &str) {
+        if let Some(&command) = COMMANDS.iter().find(|&&command| command == op) {
+            *self.commands.entry(command).or_default() += 1;
+        }
+    }
+
+    pub fn peers(&mut self, count: usize) {
+        self.max_peers = self.max_peers.max(count);
+    }
+
+    /// The report exactly as it is sent, a JSON object.
+    pub fn report(&self, now: Instant) -> String {
+        let mut commands = String::new();
+        for (i, (command, count)) in self.commands.iter().enumerate() {
+            let separator = if i == 0 { "" } else { "," };
+            write!(commands, "{separator}\"{command}\":{count}").expect("writing to a string can't fail");
+        }
+
+        format!(
+            "{{\"version\":\"{}\",\"session_minutes\":{},\"peers\":\"{}\",\"commands\":{{{commands}}}}}",
+            env!("CARGO_PKG_VERSION"),
+            now.saturating_duration_since(self.started).as_secs() / 60,
+            peer_bucket(self.max_peers),
+        )
+    }
+}
+
+/// Peer counts are only reported as a range.
+pub fn peer_bucket(count: usize) -> &'static str {
+    match count {
+        0 => "0",
+        1 => "1",
+        2..=4 => "2-4",
+        5..=9 => "5-9",
+        _ => "10+",
+    }
+}
+
+/// Where reports are posted, a plain `http://host[:port][/path]` URL.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Endpoint {
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl FromStr for Endpoint {
+    type Err = NotepadError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || NotepadError::command(format!("Expected an `http://host[:port][/path]` endpoint, got {s:?}"));
+
+        let rest = s.strip_prefix("http://").ok_or_else(invalid)?;
+        let (authority, path) = rest.find('/').map_or((rest, "/"), |i| rest.split_at(i));
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| invalid())?),
+            None => (authority, 80),
+        };
+
+        if host.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(Self { host: host.to_string(), port, path: path.to_string() })
+    }
+}
+
+/// Posts `report` to `endpoint`, failing unless it answers with a 2xx status.
+pub async fn send(endpoint: &Endpoint, report: String) -> Result<(), NotepadError> {
+    let mut stream = TcpStream::connect((endpoint.host.as_str(), endpoint.port)).await?;
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{report}",
+        endpoint.path, endpoint.host, report.len()
+    );
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+
+    match response.split(|&byte| byte == b' ').nth(1) {
+        Some([b'2', _, _]) => Ok(()),
+        _ => Err(NotepadError::network("Telemetry endpoint rejected the report")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn report_is_coarse() {
+        let start = Instant::now();
+        let mut telemetry = Telemetry::new(start);
+
+        telemetry.command("ins");
+        telemetry.command("ins");
+        telemetry.command("clip set");
+        telemetry.command("my secret room");
+        telemetry.peers(3);
+        telemetry.peers(1);
+
+        assert_eq!(
+            telemetry.report(start + Duration::from_secs(150)),
+            format!("{{\"version\":\"{}\",\"session_minutes\":2,\"peers\":\"2-4\",\"commands\":{{\"clip set\":1,\"ins\":2}}}}", env!("CARGO_PKG_VERSION"))
+        );
+    }
+
+    #[test]
+    fn parse_endpoint() {
+        assert_eq!("http://example.com".parse::<Endpoint>().unwrap(), Endpoint { host: "example.com".to_string(), port: 80, path: "/".to_string() });
+        assert_eq!("http://localhost:8080/v1/usage".parse::<Endpoint>().unwrap().path, "/v1/usage");
+
+        assert!("https://example.com".parse::<Endpoint>().is_err());
+        assert!("http://:80".parse::<Endpoint>().is_err());
+        assert!("http://example.com:port".parse::<Endpoint>().is_err());
+    }
+
+    #[tokio::test]
+    async fn posts_reports() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = vec![0; 1024];
+            let len = stream.read(&mut request).await.unwrap();
+            stream.write_all(b"HTTP/1.1 204 No Content\r\n\r\n").await.unwrap();
+
+            String::from_utf8(request[..len].to_vec()).unwrap()
+        });
+
+        let endpoint = Endpoint { host: "127.0.0.1".to_string(), port, path: "/usage".to_string() };
+        send(&endpoint, "{}".to_string()).await.unwrap();
+
+        let request = server.await.unwrap();
+        assert!(request.starts_with("POST /usage HTTP/1.1\r\n"));
+        assert!(request.ends_with("\r\n\r\n{}"));
+    }
+}
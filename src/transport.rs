@@ -0,0 +1,72 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use libp2p::PeerId;
+
+use crate::error::NotepadError;
+
+/// A payload received on a room topic.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Incoming {
+    pub topic: String,
+    pub source: Option<PeerId>,
+    pub data: Vec<u8>,
+}
+
+/// Something another peer sent.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// A payload published on a subscribed topic.
+    Message(Incoming),
+    /// A direct request, to be answered with [`Transport::respond`] and its `id`.
+    Request {
+        id: u64,
+        peer: PeerId,
+        data: Vec<u8>,
+    },
+    /// The answer to a request made with [`Transport::request`].
+    Response {
+        peer: PeerId,
+        data: Vec<u8>,
+    },
+    /// A connected peer subscribed to `topic`, or was found subscribed to it on connecting.
+    Subscribed {
+        peer: PeerId,
+        topic: String,
+    },
+    Unsubscribed {
+        peer: PeerId,
+        topic: String,
+    },
+}
+
+/// What the document engine needs from the network, so it can be driven
+/// by libp2p or, in tests, by an in-memory `Loopback`.
+#[async_trait]
+pub trait Transport {
+    /// The id other peers see this one's messages come from.
+    fn peer_id(&self) -> PeerId;
+
+    /// Publishes `data` on `topic`, returning how many peers it was sent to.
+    /// Returns `Ok(0)` when nobody is subscribed yet, and
+    /// [`NotepadError::Transient`] for failures worth trying again.
+    fn publish(&mut self, topic: &str, data: Vec<u8>) -> Result<usize, NotepadError>;
+
+    fn subscribe(&mut self, topic: &str) -> Result<(), NotepadError>;
+
+    fn unsubscribe(&mut self, topic: &str) -> Result<(), NotepadError>;
+
+    /// Sends `data` directly to `peer`, its answer arrives as an [`Event::Response`].
+    fn request(&mut self, peer: &PeerId, data: Vec<u8>) -> Result<(), NotepadError>;
+
+    /// Answers the [`Event::Request`] with `id`.
+    fn respond(&mut self, id: u64, data: Vec<u8>) -> Result<(), NotepadError>;
+
+    /// The measured round trip to `peer`, if the transport measures them.
+    fn rtt(&self, _peer: &PeerId) -> Option<Duration> {
+        None
+    }
+
+    /// Waits for the next payload, request or response from another peer.
+    async fn next_event(&mut self) -> Option<Event>;
+}
@@ -0,0 +1,85 @@
+use std::ops::Range;
+
+use crate::editor;
+
+/// Remote edits to a document that haven't been on screen yet, by byte
+/// offset in the order they arrived, for the unread markers and catching up
+/// on them. Only the active document's are kept, the others' start over.
+#[derive(Debug, Default)]
+pub struct Unseen {
+    document: String,
+    changes: Vec<usize>,
+}
+
+impl Unseen {
+    /// Records a remote edit at `index` of `document`, forgetting any to another document.
+    pub fn push(&mut self, document: &str, index: usize) {
+        if self.document != document {
+            self.document = document.to_string();
+            self.changes.clear();
+        }
+
+        if !self.changes.contains(&index) {
+            self.changes.push(index);
+        }
+    }
+
+    /// The unseen changes to `document`, oldest first.
+    pub fn in_document(&self, document: &str) -> &[usize] {
+        if self.document == document { &self.changes } else { &[] }
+    }
+
+    /// The unseen changes to `document`, to move along with edits before them.
+    pub fn moving(&mut self, document: &str) -> Vec<&mut usize> {
+        if self.document == document { self.changes.iter_mut().collect() } else { Vec::new() }
+    }
+
+    /// Forgets the changes to `document` on `lines` of its `text`, which are on screen.
+    pub fn view(&mut self, document: &str, text: &str, lines: Range<usize>) {
+        if self.document == document {
+            self.changes.retain(|&index| !lines.contains(&editor::position(text, index).0));
+        }
+    }
+
+    /// The oldest unseen change to `document`, which is forgotten as it is caught up on.
+    pub fn catch_up(&mut self, document: &str) -> Option<usize> {
+        (self.document == document && !self.changes.is_empty()).then(|| self.changes.remove(0))
+    }
+
+    pub fn clear(&mut self) {
+        self.changes.clear();
+    }
+
+    pub fn memory(&self) -> usize {
+        self.document.capacity() + self.changes.capacity() * size_of::<usize>()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn changes_on_screen_are_seen() {
+        let text = "one\ntwo\nthree\nfour";
+        let mut unseen = Unseen::default();
+
+        unseen.push("main", text.find("four").unwrap());
+        unseen.push("main", text.find("two").unwrap());
+        unseen.push("main", 0);
+        unseen.view("main", text, 0..2);
+        assert_eq!(unseen.in_document("main"), [text.find("four").unwrap()]);
+        assert!(unseen.in_document("notes").is_empty());
+
+        unseen.push("main", text.find("three").unwrap());
+        assert_eq!(unseen.catch_up("main"), text.find("four"));
+        assert_eq!(unseen.catch_up("main"), text.find("three"));
+        assert_eq!(unseen.catch_up("main"), None);
+
+        // Edits to another document start over.
+        unseen.push("main", 0);
+        unseen.push("notes", 1);
+        assert_eq!(unseen.catch_up("main"), None);
+        assert_eq!(unseen.in_document("notes"), [1]);
+    }
+}
@@ -0,0 +1,154 @@
+use libp2p::{
+    identity::{Keypair, PublicKey},
+    PeerId
+};
+
+use crate::{
+    error::NotepadError,
+    message::push_str,
+    presence
+};
+
+/// Prepended to what a user signs, so the signature can't be passed off as
+/// one over anything else signed with the same key.
+const CONTEXT: &[u8] = b"p2p-notepad signed edit";
+
+/// An edit payload signed by one of the users of the node publishing it, so
+/// peers credit the user rather than the node.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signed {
+    /// The user's public key in its protobuf encoding.
+    pub public_key: Vec<u8>,
+    pub nickname: String,
+    pub signature: Vec<u8>,
+    /// The signed message, envelope included.
+    pub payload: Vec<u8>,
+}
+
+impl Signed {
+    /// Checks the signature, returning the id of the user that made it.
+    pub fn verify(&self) -> Result<PeerId, NotepadError> {
+        let public_key = PublicKey::try_decode_protobuf(&self.public_key)
+            .map_err(|_| NotepadError::Integrity("Invalid signing key"))?;
+
+        if !public_key.verify(&signed_bytes(&self.nickname, &self.payload), &self.signature) {
+            return Err(NotepadError::Integrity("Signature doesn't match the edit"));
+        }
+
+        Ok(public_key.to_peer_id())
+    }
+}
+
+/// Someone using a node shared by several people, such as a kiosk, with a
+/// key of their own that signs the edits they make.
+#[derive(Debug)]
+pub struct User {
+    pub nickname: String,
+    keypair: Keypair,
+}
+
+impl User {
+    pub fn new(nickname: &str) -> Result<Self, NotepadError> {
+        presence::validate_nickname(nickname)?;
+
+        Ok(Self { nickname: nickname.to_string(), keypair: Keypair::generate_ed25519() })
+    }
+
+    /// Id the user's edits are credited to, distinct from the node's.
+    pub fn peer_id(&self) -> PeerId {
+        self.keypair.public().to_peer_id()
+    }
+
+    pub fn sign(&self, payload: Vec<u8>) -> Result<Signed, NotepadError> {
+        let signature = self.keypair
+            .sign(&signed_bytes(&self.nickname, &payload))
+            .map_err(NotepadError::network)?;
+
+        Ok(Signed { public_key: self.keypair.public().encode_protobuf(), nickname: self.nickname.clone(), signature, payload })
+    }
+}
+
+/// The users of this node, and which of them is at the keyboard. Edits are
+/// made as the node itself while none is.
+#[derive(Debug, Default)]
+pub struct Users {
+    users: Vec<User>,
+    active: Option<usize>,
+}
+
+impl Users {
+    /// Adds a user with a fresh key and makes them the active one.
+    pub fn add(&mut self, nickname: &str) -> Result<&User, NotepadError> {
+        if self.users.iter().any(|user| user.nickname == nickname) {
+            return Err(NotepadError::command(format!("There is already a user `{nickname}`")));
+        }
+
+        self.users.push(User::new(nickname)?);
+        self.active = Some(self.users.len() - 1);
+
+        Ok(&self.users[self.users.len() - 1])
+    }
+
+    /// Makes `nickname` the active user, or the node itself if `None`.
+    pub fn switch(&mut self, nickname: Option<&str>) -> Result<(), NotepadError> {
+        self.active = nickname.map(|nickname| {
+            self.users
+                .iter()
+                .position(|user| user.nickname == nickname)
+                .ok_or_else(|| NotepadError::command(format!("No user `{nickname}`")))
+        }).transpose()?;
+
+        Ok(())
+    }
+
+    pub fn active(&self) -> Option<&User> {
+        self.active.map(|i| &self.users[i])
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &User> {
+        self.users.iter()
+    }
+}
+
+fn signed_bytes(nickname: &str, payload: &[u8]) -> Vec<u8> {
+    let mut data = CONTEXT.to_vec();
+    push_str(&mut data, nickname);
+    data.extend(payload);
+
+    data
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn signatures_are_checked() {
+        let user = User::new("alice").unwrap();
+        let signed = user.sign(b"edit".to_vec()).unwrap();
+        assert_eq!(signed.verify().unwrap(), user.peer_id());
+
+        let renamed = Signed { nickname: "mallory".to_string(), ..signed.clone() };
+        assert!(renamed.verify().is_err());
+
+        let tampered = Signed { payload: b"edits".to_vec(), ..signed };
+        assert!(tampered.verify().is_err());
+    }
+
+    #[test]
+    fn switches_users() {
+        let mut users = Users::default();
+
+        let alice = users.add("alice").unwrap().peer_id();
+        users.add("bob").unwrap();
+        assert!(users.add("bob").is_err());
+        assert_eq!(users.active().unwrap().nickname, "bob");
+
+        users.switch(Some("alice")).unwrap();
+        assert_eq!(users.active().unwrap().peer_id(), alice);
+        assert!(users.switch(Some("carol")).is_err());
+
+        users.switch(None).unwrap();
+        assert!(users.active().is_none());
+    }
+}
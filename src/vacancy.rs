@@ -0,0 +1,66 @@
+use std::time::{Duration, Instant};
+
+/// Notices a room dying out: every other peer gone while the local user is
+/// idle, as when everyone closed their laptops. The room's final state is
+/// then saved once, before this peer goes too and the notes with it.
+#[derive(Debug, Default)]
+pub struct Vacancy {
+    /// When the last other peer left, unless one has come back since.
+    since: Option<Instant>,
+    /// Whether the room was saved since it emptied.
+    saved: bool,
+}
+
+impl Vacancy {
+    /// Records that the last other peer left the room.
+    pub fn emptied(&mut self, now: Instant) {
+        if self.since.is_none() {
+            self.since = Some(now);
+            self.saved = false;
+        }
+    }
+
+    /// Records that a peer is in the room again.
+    pub fn occupied(&mut self) {
+        self.since = None;
+    }
+
+    /// Whether the room should be saved now: it has been empty, and the user
+    /// idle since `last_input`, for at least `idle`. Only once per emptying.
+    pub fn due(&mut self, last_input: Instant, idle: Duration, now: Instant) -> bool {
+        let Some(since) = self.since.filter(|_| !self.saved) else {
+            return false;
+        };
+
+        if now.saturating_duration_since(since.max(last_input)) < idle {
+            return false;
+        }
+
+        self.saved = true;
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn saves_once_when_empty_and_idle() {
+        let start = Instant::now();
+        let idle = Duration::from_secs(60);
+        let mut vacancy = Vacancy::default();
+
+        assert!(!vacancy.due(start, idle, start + idle * 2));
+
+        vacancy.emptied(start + Duration::from_secs(30));
+        assert!(!vacancy.due(start, idle, start + idle));
+        assert!(!vacancy.due(start + idle, idle, start + idle * 2 - Duration::from_secs(1)));
+        assert!(vacancy.due(start, idle, start + idle * 2));
+        assert!(!vacancy.due(start, idle, start + idle * 3));
+
+        vacancy.occupied();
+        vacancy.emptied(start + idle * 3);
+        assert!(vacancy.due(start, idle, start + idle * 4));
+    }
+}
@@ -0,0 +1,61 @@
+use crate::error::NotepadError;
+
+/// Appends `n` as an unsigned LEB128 varint: seven bits per byte, low bits
+/// first, the top bit set on every byte but the last.
+pub fn push(data: &mut Vec<u8>, mut n: usize) {
+    while n >= 0x80 {
+        data.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+
+    data.push(n as u8);
+}
+
+/// Splits a varint off the front of `data`.
+pub fn split(data: &[u8]) -> Result<(usize, &[u8]), NotepadError> {
+    let mut n: usize = 0;
+
+    for (i, &byte) in data.iter().enumerate() {
+        let bits = usize::from(byte & 0x7f);
+        let shift = 7 * i as u32;
+
+        if shift >= usize::BITS || (bits << shift) >> shift != bits {
+            return Err(NotepadError::Decode("Varint is too large"));
+        }
+
+        n |= bits << shift;
+
+        if byte & 0x80 == 0 {
+            return Ok((n, &data[i + 1..]));
+        }
+    }
+
+    Err(NotepadError::Decode("Truncated varint"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn varint_round_trip() {
+        for n in [0, 1, 127, 128, 255, 300, 16_384, usize::MAX] {
+            let mut data = Vec::new();
+            push(&mut data, n);
+            data.push(0xaa);
+
+            assert_eq!(split(&data).unwrap(), (n, [0xaa].as_slice()));
+        }
+
+        let mut data = Vec::new();
+        push(&mut data, 300);
+        assert_eq!(data, [0xac, 0x02]);
+    }
+
+    #[test]
+    fn invalid_varints() {
+        assert!(split(&[]).is_err());
+        assert!(split(&[0x80]).is_err());
+        assert!(split(&[0xff; 11]).is_err());
+    }
+}
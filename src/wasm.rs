@@ -0,0 +1,59 @@
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    diff::{self, MessageBuf},
+    notepad::Notepad
+};
+
+/// A document for a web frontend, changed by the same diffs in the same
+/// encoding as the `Diffs` messages of a native node.
+#[wasm_bindgen]
+pub struct Document {
+    notepad: Notepad,
+}
+
+#[wasm_bindgen]
+impl Document {
+    #[wasm_bindgen(constructor)]
+    pub fn new(text: &str) -> Self {
+        Self { notepad: Notepad::new(text.to_string()) }
+    }
+
+    pub fn text(&self) -> String {
+        self.notepad.text().to_string()
+    }
+
+    pub fn checksum(&self) -> String {
+        self.notepad.checksum()
+    }
+
+    /// Applies encoded diffs, such as those a peer published.
+    pub fn apply(&mut self, diffs: &[u8]) -> Result<(), JsError> {
+        let diffs = MessageBuf::decode(diffs).map_err(|e| JsError::new(&e.to_string()))?;
+
+        self.notepad.apply_message_buf(&diffs).map_err(|e| JsError::new(&e.to_string()))
+    }
+
+    /// Replaces the text with `text`, returning the encoded diffs that did
+    /// it, to be published.
+    pub fn edit(&mut self, text: &str) -> Vec<u8> {
+        let diffs = diff::compute(self.notepad.text(), text);
+        self.notepad.set_text(text.to_string());
+
+        diffs.into()
+    }
+}
+
+/// Encodes the diffs turning `old` into `new`.
+#[wasm_bindgen]
+pub fn encode(old: &str, new: &str) -> Vec<u8> {
+    diff::compute(old, new).into()
+}
+
+/// Decodes diffs into the form they are entered in on stdin, such as `ins:3:a`, one per diff.
+#[wasm_bindgen]
+pub fn decode(diffs: &[u8]) -> Result<Vec<String>, JsError> {
+    let diffs = MessageBuf::decode(diffs).map_err(|e| JsError::new(&e.to_string()))?;
+
+    Ok(diffs.messages.iter().map(ToString::to_string).collect())
+}
@@ -0,0 +1,96 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::diff::{DiffRef, OperationRef};
+
+/// A room followed without joining it: its documents aren't kept, only the
+/// lines of each that match one of the patterns, so many rooms can be
+/// watched for little memory.
+#[derive(Debug)]
+pub struct Watch {
+    pub room: String,
+    /// Text to be notified about, matched case-insensitively, e.g. `@alice` or `## Budget`.
+    pub patterns: Vec<String>,
+    /// Lines matching a pattern in each document, as of its last snapshot.
+    lines: HashMap<String, HashSet<String>>,
+}
+
+impl Watch {
+    pub fn new(room: &str, patterns: Vec<String>) -> Self {
+        Self { room: room.to_string(), patterns, lines: HashMap::new() }
+    }
+
+    /// Patterns found in the text inserted by `diffs`. Without the document
+    /// only insertions can be told apart, edits around a pattern show up
+    /// with the next snapshot instead. Diffs are taken borrowed, as they are
+    /// only looked at, see [`crate::message::diffs_view`].
+    pub fn inserted<'a>(&self, diffs: impl IntoIterator<Item = DiffRef<'a>>) -> Vec<&str> {
+        let mut text = String::new();
+
+        for diff in diffs {
+            match (diff.opcode, diff.operand) {
+                (OperationRef::InsStr(inserted), _) => text.push_str(inserted),
+                (OperationRef::Ins | OperationRef::Rep, Some(c)) => text.push(c),
+                _ => {},
+            }
+        }
+
+        self.matching(&text)
+    }
+
+    /// Takes the text of a document from a snapshot, returning the lines
+    /// matching a pattern that were added or removed since the last one.
+    /// The first snapshot of a document only sets what later ones are compared with.
+    pub fn snapshot(&mut self, document: &str, text: &str) -> Vec<(bool, String)> {
+        let lines: HashSet<String> = text.lines().filter(|line| !self.matching(line).is_empty()).map(str::to_string).collect();
+
+        let Some(previous) = self.lines.insert(document.to_string(), lines) else {
+            return Vec::new();
+        };
+        let lines = &self.lines[document];
+
+        let mut changed: Vec<_> = lines.difference(&previous).map(|line| (true, line.clone()))
+            .chain(previous.difference(lines).map(|line| (false, line.clone())))
+            .collect();
+        changed.sort();
+
+        changed
+    }
+
+    pub fn memory(&self) -> usize {
+        self.patterns.iter().map(String::capacity).sum::<usize>()
+            + self.lines.iter().map(|(id, lines)| id.capacity() + lines.iter().map(String::capacity).sum::<usize>()).sum::<usize>()
+    }
+
+    fn matching(&self, text: &str) -> Vec<&str> {
+        let text = text.to_lowercase();
+
+        self.patterns.iter().filter(|pattern| text.contains(&pattern.to_lowercase())).map(String::as_str).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::diff::{Diff, MessageBuf, Operation};
+
+    #[test]
+    fn notices_patterns() {
+        let mut watch = Watch::new("team", vec!["@alice".to_string(), "## Budget".to_string()]);
+
+        let diffs = MessageBuf { messages: vec![
+            Diff { opcode: Operation::InsStr("ping @Ali".to_string()), operand: None, index: 0 },
+            Diff { opcode: Operation::Ins, operand: Some('c'), index: 9 },
+            Diff { opcode: Operation::Ins, operand: Some('e'), index: 10 },
+        ] };
+        assert_eq!(watch.inserted(diffs.messages.iter().map(Diff::borrowed)), vec!["@alice"]);
+        assert!(watch.inserted([]).is_empty());
+
+        assert!(watch.snapshot("main", "## Budget\nnotes").is_empty());
+        assert!(watch.snapshot("main", "## Budget\nmore notes").is_empty());
+        assert_eq!(watch.snapshot("main", "## Budget 2025\n@alice"), vec![
+            (false, "## Budget".to_string()),
+            (true, "## Budget 2025".to_string()),
+            (true, "@alice".to_string()),
+        ]);
+    }
+}
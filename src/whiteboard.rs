@@ -0,0 +1,345 @@
+use std::{collections::HashSet, fmt, str::FromStr};
+
+use crate::{error::NotepadError, varint};
+
+/// Dots across and down a board, two by four to a braille character.
+pub const WIDTH: u16 = 120;
+pub const HEIGHT: u16 = 60;
+
+const DRAW: u8 = 0;
+const ERASE: u8 = 1;
+
+/// Most points a stroke may have, so one peer can't make everyone draw forever.
+const MAX_POINTS: usize = 1024;
+
+/// What strokes are drawn in, shown with their ANSI colors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Color {
+    Default,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+}
+
+impl Color {
+    const ALL: [Color; 7] = [Color::Default, Color::Red, Color::Green, Color::Yellow, Color::Blue, Color::Magenta, Color::Cyan];
+
+    fn ansi(self) -> u8 {
+        match self {
+            Color::Default => 39,
+            color => 30 + color as u8,
+        }
+    }
+}
+
+impl TryFrom<u8> for Color {
+    type Error = NotepadError;
+
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        Color::ALL.get(byte as usize).copied().ok_or(NotepadError::Decode("Invalid stroke color"))
+    }
+}
+
+impl FromStr for Color {
+    type Err = NotepadError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Color::ALL
+            .into_iter()
+            .find(|color| color.to_string() == s)
+            .ok_or_else(|| NotepadError::command(format!("Unknown color {s:?}, expected one of default, red, green, yellow, blue, magenta, cyan")))
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Color::Default => "default",
+            Color::Red => "red",
+            Color::Green => "green",
+            Color::Yellow => "yellow",
+            Color::Blue => "blue",
+            Color::Magenta => "magenta",
+            Color::Cyan => "cyan",
+        })
+    }
+}
+
+/// A line through `points`, in dots from the top left of the board.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stroke {
+    /// Picked at random by the peer that drew it, to erase it by.
+    pub id: u64,
+    pub color: Color,
+    pub points: Vec<(u16, u16)>,
+}
+
+/// A change to a board, published in a [`Message::Board`](crate::message::Message::Board).
+#[derive(Debug, Clone, PartialEq)]
+pub enum BoardOp {
+    Draw(Stroke),
+    Erase(u64),
+}
+
+impl BoardOp {
+    /// Appends the operation as its opcode and stroke id, then for a drawn
+    /// stroke its color, a varint point count and each point as two varints.
+    pub fn encode(&self, data: &mut Vec<u8>) {
+        match self {
+            BoardOp::Draw(Stroke { id, color, points }) => {
+                data.push(DRAW);
+                data.extend(id.to_le_bytes());
+                data.push(*color as u8);
+                varint::push(data, points.len());
+
+                for &(x, y) in points {
+                    varint::push(data, x as usize);
+                    varint::push(data, y as usize);
+                }
+            },
+            BoardOp::Erase(id) => {
+                data.push(ERASE);
+                data.extend(id.to_le_bytes());
+            },
+        }
+    }
+
+    /// Splits an operation written by [`BoardOp::encode`] off the front of `data`.
+    pub fn decode(data: &[u8]) -> Result<(Self, &[u8]), NotepadError> {
+        let [opcode, data @ ..] = data else {
+            return Err(NotepadError::Decode("Truncated board operation"));
+        };
+        let (id, data) = data.split_first_chunk::<8>().ok_or(NotepadError::Decode("Truncated board operation"))?;
+        let id = u64::from_le_bytes(*id);
+
+        match *opcode {
+            DRAW => {
+                let (color, data) = data.split_first().ok_or(NotepadError::Decode("Truncated board operation"))?;
+                let color = Color::try_from(*color)?;
+                let (len, mut data) = varint::split(data)?;
+
+                if len > MAX_POINTS {
+                    return Err(NotepadError::Decode("Stroke has too many points"));
+                }
+
+                let mut points = Vec::with_capacity(len);
+                for _ in 0..len {
+                    let (x, rest) = varint::split(data)?;
+                    let (y, rest) = varint::split(rest)?;
+
+                    if x >= WIDTH as usize || y >= HEIGHT as usize {
+                        return Err(NotepadError::Decode("Stroke is off the board"));
+                    }
+
+                    points.push((x as u16, y as u16));
+                    data = rest;
+                }
+
+                Ok((BoardOp::Draw(Stroke { id, color, points }), data))
+            },
+            ERASE => Ok((BoardOp::Erase(id), data)),
+            _ => Err(NotepadError::Decode("Invalid board opcode")),
+        }
+    }
+}
+
+/// A stroke-based drawing kept next to a room's documents, for quick
+/// diagrams alongside the notes. Strokes are only ever drawn whole or
+/// erased whole, so peers agree on a board whatever order its operations
+/// arrive in.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Whiteboard {
+    strokes: Vec<Stroke>,
+    /// Strokes erased, so drawing one that arrives after its erasure doesn't bring it back.
+    erased: HashSet<u64>,
+}
+
+impl Whiteboard {
+    /// Applies an operation, ignoring strokes already drawn or erased.
+    pub fn apply(&mut self, op: BoardOp) {
+        match op {
+            BoardOp::Draw(stroke) if self.erased.contains(&stroke.id) || self.strokes.iter().any(|drawn| drawn.id == stroke.id) => {},
+            BoardOp::Draw(stroke) => self.strokes.push(stroke),
+            BoardOp::Erase(id) => {
+                self.erased.insert(id);
+                self.strokes.retain(|stroke| stroke.id != id);
+            },
+        }
+    }
+
+    pub fn strokes(&self) -> &[Stroke] {
+        &self.strokes
+    }
+
+    /// Operations erasing every stroke passing within a dot of `point`.
+    pub fn erase_at(&self, (x, y): (u16, u16)) -> Vec<BoardOp> {
+        self.strokes
+            .iter()
+            .filter(|stroke| dots(&stroke.points).any(|(dx, dy)| dx.abs_diff(x) <= 1 && dy.abs_diff(y) <= 1))
+            .map(|stroke| BoardOp::Erase(stroke.id))
+            .collect()
+    }
+
+    /// Operations erasing every stroke.
+    pub fn clear(&self) -> Vec<BoardOp> {
+        self.strokes.iter().map(|stroke| BoardOp::Erase(stroke.id)).collect()
+    }
+
+    /// The board as lines of braille characters, each in the color of the
+    /// last stroke through it if `color` is set.
+    pub fn render(&self, color: bool) -> String {
+        let (columns, rows) = (WIDTH as usize / 2, HEIGHT as usize / 4);
+        let mut cells = vec![(0u8, Color::Default); columns * rows];
+
+        for stroke in &self.strokes {
+            for (x, y) in dots(&stroke.points) {
+                let cell = &mut cells[y as usize / 4 * columns + x as usize / 2];
+
+                cell.0 |= braille_bit(x % 2, y % 4);
+                cell.1 = stroke.color;
+            }
+        }
+
+        let mut lines = Vec::with_capacity(rows);
+        for row in cells.chunks(columns) {
+            let mut line = String::new();
+
+            for &(bits, cell_color) in row {
+                let c = char::from_u32(0x2800 + bits as u32).expect("braille patterns are characters");
+
+                match color && bits != 0 {
+                    true => line += &format!("\x1b[{}m{c}\x1b[39m", cell_color.ansi()),
+                    false => line.push(c),
+                }
+            }
+            lines.push(line);
+        }
+
+        lines.join("\n")
+    }
+}
+
+/// The bit of a braille pattern for the dot at column `x` and row `y` of its cell.
+fn braille_bit(x: u16, y: u16) -> u8 {
+    match (x, y) {
+        (0, 3) => 0x40,
+        (1, 3) => 0x80,
+        (0, y) => 1 << y,
+        (_, y) => 0x08 << y,
+    }
+}
+
+/// The dots a stroke covers, its points joined by straight lines.
+fn dots(points: &[(u16, u16)]) -> impl Iterator<Item = (u16, u16)> + '_ {
+    let single = (points.len() == 1).then(|| points[0]);
+
+    points.windows(2).flat_map(|pair| line(pair[0], pair[1])).chain(single)
+}
+
+/// The dots from `from` to `to`, both included, by Bresenham's algorithm.
+fn line(from: (u16, u16), to: (u16, u16)) -> Vec<(u16, u16)> {
+    let (mut x, mut y) = (from.0 as i32, from.1 as i32);
+    let (x1, y1) = (to.0 as i32, to.1 as i32);
+    let (dx, dy) = ((x1 - x).abs(), -(y1 - y).abs());
+    let (sx, sy) = ((x1 - x).signum(), (y1 - y).signum());
+    let mut error = dx + dy;
+    let mut dots = Vec::new();
+
+    loop {
+        dots.push((x as u16, y as u16));
+        if (x, y) == (x1, y1) {
+            return dots;
+        }
+
+        let doubled = 2 * error;
+        if doubled >= dy {
+            error += dy;
+            x += sx;
+        }
+        if doubled <= dx {
+            error += dx;
+            y += sy;
+        }
+    }
+}
+
+/// Parses points given as `<x>,<y>` separated by spaces, which must be on the board.
+pub fn parse_points(s: &str) -> Result<Vec<(u16, u16)>, NotepadError> {
+    let points = s.split_whitespace().map(|point| {
+        point
+            .split_once(',')
+            .and_then(|(x, y)| Some((x.parse::<u16>().ok()?, y.parse::<u16>().ok()?)))
+            .filter(|&(x, y)| x < WIDTH && y < HEIGHT)
+            .ok_or_else(|| NotepadError::command(format!("Expected a point `<x>,<y>` within {WIDTH}x{HEIGHT}, got {point:?}")))
+    }).collect::<Result<Vec<_>, _>>()?;
+
+    match points.len() {
+        0 => Err(NotepadError::command("Expected at least one point")),
+        len if len > MAX_POINTS => Err(NotepadError::command(format!("A stroke has at most {MAX_POINTS} points"))),
+        _ => Ok(points),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn stroke(id: u64, points: &[(u16, u16)]) -> Stroke {
+        Stroke { id, color: Color::Red, points: points.to_vec() }
+    }
+
+    #[test]
+    fn board_op_round_trip() {
+        let ops = [BoardOp::Draw(stroke(7, &[(0, 0), (119, 59)])), BoardOp::Erase(7)];
+        let mut data = Vec::new();
+        for op in &ops {
+            op.encode(&mut data);
+        }
+
+        let (first, data) = BoardOp::decode(&data).unwrap();
+        let (second, data) = BoardOp::decode(data).unwrap();
+        assert_eq!([first, second], ops);
+        assert!(data.is_empty());
+
+        let mut data = Vec::new();
+        BoardOp::Draw(stroke(7, &[(WIDTH, 0)])).encode(&mut data);
+        assert!(BoardOp::decode(&data).is_err());
+        assert!(BoardOp::decode(&[ERASE, 1, 2]).is_err());
+    }
+
+    #[test]
+    fn erased_strokes_stay_erased() {
+        let mut board = Whiteboard::default();
+        board.apply(BoardOp::Erase(1));
+        board.apply(BoardOp::Draw(stroke(1, &[(0, 0)])));
+        board.apply(BoardOp::Draw(stroke(2, &[(0, 10), (10, 10)])));
+        board.apply(BoardOp::Draw(stroke(2, &[(0, 10), (10, 10)])));
+        assert_eq!(board.strokes(), [stroke(2, &[(0, 10), (10, 10)])]);
+
+        assert_eq!(board.erase_at((5, 11)), vec![BoardOp::Erase(2)]);
+        assert!(board.erase_at((5, 13)).is_empty());
+        assert_eq!(board.clear(), vec![BoardOp::Erase(2)]);
+    }
+
+    #[test]
+    fn renders_braille() {
+        let mut board = Whiteboard::default();
+        board.apply(BoardOp::Draw(stroke(1, &[(0, 0), (3, 0)])));
+        board.apply(BoardOp::Draw(stroke(2, &[(1, 4), (1, 7)])));
+
+        let render = board.render(false);
+        let lines: Vec<_> = render.lines().collect();
+        assert_eq!(lines.len(), HEIGHT as usize / 4);
+        assert!(lines[0].starts_with("⠉⠉⠀"));
+        assert!(lines[1].starts_with("⢸⠀"));
+        assert!(board.render(true).starts_with("\x1b[31m⠉\x1b[39m"));
+
+        assert_eq!(parse_points("1,2 3,4").unwrap(), vec![(1, 2), (3, 4)]);
+        assert!(parse_points("").is_err());
+        assert!(parse_points("1;2").is_err());
+        assert!(parse_points("120,0").is_err());
+    }
+}
@@ -0,0 +1,150 @@
+use crate::{
+    archive::Archive,
+    container::{self, Section, SectionKind},
+    engine::Engine,
+    error::NotepadError,
+    message::{push_str, split_str},
+    room::RoomSettings,
+    storage::Storage
+};
+
+/// The session saved on exit and restored on the next launch: the room and
+/// its topic, the nickname, which document is open, every document's text and
+/// the preferences of every room joined.
+///
+/// The topic and the room key are stored rather than a private room's
+/// passphrase, so the passphrase never touches the disk.
+#[derive(Debug, PartialEq)]
+pub struct Workspace {
+    pub room: String,
+    pub topic: String,
+    /// Key of the room if it is private, see [`RoomKey`](crate::seal::RoomKey).
+    pub key: Option<[u8; 32]>,
+    pub nickname: Option<String>,
+    /// Id of the active document.
+    pub active: String,
+    pub archive: Archive,
+    pub rooms: RoomSettings,
+}
+
+impl Workspace {
+    pub fn new(engine: &Engine) -> Self {
+        Self {
+            room: engine.room().to_string(),
+            topic: engine.topic().to_string(),
+            key: engine.room_key().map(|key| key.to_bytes()),
+            nickname: engine.peers.nickname.clone(),
+            active: engine.documents.active_meta().id.clone(),
+            archive: Archive::new(&engine.documents),
+            rooms: engine.rooms.clone(),
+        }
+    }
+
+    /// Stored as a container holding a workspace section, a room settings
+    /// section and then the sections of the archive.
+    pub fn encode(self) -> Vec<u8> {
+        let mut data = Vec::new();
+        push_str(&mut data, &self.room);
+        push_str(&mut data, &self.topic);
+        push_str(&mut data, self.nickname.as_deref().unwrap_or_default());
+        push_str(&mut data, &self.active);
+        if let Some(key) = self.key {
+            data.extend(key);
+        }
+
+        let mut sections = vec![
+            Section { kind: SectionKind::Workspace, data },
+            Section { kind: SectionKind::RoomSettings, data: self.rooms.encode() },
+        ];
+        sections.extend(self.archive.into_sections());
+
+        container::encode(sections, None)
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, NotepadError> {
+        let mut sections = container::decode(data, None)?;
+
+        if sections.first().is_none_or(|section| section.kind != SectionKind::Workspace) {
+            return Err(NotepadError::Decode("Expected a workspace section"));
+        }
+
+        let data = sections.remove(0).data;
+        let (room, data) = split_str(&data)?;
+        let (topic, data) = split_str(data)?;
+        let (nickname, data) = split_str(data)?;
+        let (active, data) = split_str(data)?;
+        // Workspaces of public rooms end here.
+        let key = match data {
+            [] => None,
+            key => Some(key.try_into().map_err(|_| NotepadError::Decode("Invalid workspace section"))?),
+        };
+
+        let rooms = match sections.first() {
+            Some(section) if section.kind == SectionKind::RoomSettings => RoomSettings::decode(&sections.remove(0).data)?,
+            _ => RoomSettings::default(),
+        };
+
+        Ok(Self {
+            room,
+            topic,
+            key,
+            nickname: Some(nickname).filter(|nickname| !nickname.is_empty()),
+            active,
+            archive: Archive::from_sections(sections)?,
+            rooms,
+        })
+    }
+
+    /// Reads the workspace saved as `name`, `None` if nothing was saved yet.
+    pub fn load(storage: &dyn Storage, name: &str) -> Result<Option<Self>, NotepadError> {
+        storage.load(name)?.map(|data| Self::decode(&data)).transpose()
+    }
+
+    pub fn save(self, storage: &mut dyn Storage, name: &str) -> Result<(), NotepadError> {
+        storage.save(name, &self.encode())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{loopback::Loopback, notepad::Notepad};
+
+    fn def_engine() -> Engine {
+        let mut engine = Engine::new("room", "p/", Notepad::new("hello world".to_string()));
+        engine.documents.create("todo").unwrap();
+        engine.documents.switch("todo").unwrap();
+        engine.peers.nickname = Some("alice".to_string());
+        engine.rooms.get_mut("p/room").read_only = true;
+
+        engine
+    }
+
+    #[test]
+    fn workspace_round_trip() {
+        let engine = def_engine();
+        let workspace = Workspace::decode(&Workspace::new(&engine).encode()).unwrap();
+
+        assert_eq!(workspace, Workspace::new(&engine));
+        assert_eq!(workspace.nickname.as_deref(), Some("alice"));
+        assert_eq!(workspace.archive.documents.len(), 2);
+        assert!(workspace.rooms.get("p/room").unwrap().read_only);
+    }
+
+    #[test]
+    fn private_workspaces_keep_the_room_key() {
+        let mut engine = def_engine();
+        assert_eq!(Workspace::new(&engine).key, None);
+
+        engine.switch_room(&mut Loopback::default(), "private", Some("secret")).unwrap();
+        let workspace = Workspace::decode(&Workspace::new(&engine).encode()).unwrap();
+        assert_eq!(workspace.key, Some(engine.room_key().unwrap().to_bytes()));
+    }
+
+    #[test]
+    fn archives_are_not_workspaces() {
+        let archive = Archive::new(&def_engine().documents).encode(None);
+
+        assert!(Workspace::decode(&archive).is_err());
+    }
+}
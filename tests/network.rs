@@ -0,0 +1,153 @@
+//! Nodes of a room running the whole libp2p stack in one process, connected
+//! over the memory transport, so edits go through the codec, gossipsub and
+//! the engine's ordering and sync as they would between machines.
+//!
+//! Run with `cargo test --test network`.
+
+use std::time::{Duration, Instant};
+
+use p2p_notepad::{config::Config, Diff, Engine, MessageBuf, Network, Notepad, Operation, Transport};
+
+/// Longest a test waits for its nodes to agree.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+struct Node {
+    engine: Engine,
+    network: Network,
+    /// Where other nodes dial this one.
+    address: String,
+}
+
+impl Node {
+    fn new() -> Self {
+        // Memory ports are shared by the whole process, and 0 asks for any.
+        let port = rand::random::<u64>().max(1);
+        let config = Config {
+            listen: vec![format!("/memory/{port}").parse().unwrap()],
+            // Without mDNS, nodes only find each other by dialing.
+            behaviours: "identify,ping,request-response".parse().unwrap(),
+            heartbeat: Duration::from_millis(100),
+            room: "integration".to_string(),
+            ..Config::default()
+        };
+
+        let mut network = Network::new(&config).unwrap();
+        let engine = Engine::new(&config.room, &config.topic_prefix, Notepad::new("hello world".to_string()));
+        network.subscribe(&engine.cursor_topic()).unwrap();
+        network.subscribe(engine.topic()).unwrap();
+        let address = format!("/memory/{port}/p2p/{}", network.peer_id());
+
+        Self { engine, network, address }
+    }
+
+    fn edit(&mut self, diffs: MessageBuf) {
+        self.engine.edit(&mut self.network, diffs).unwrap();
+    }
+
+    fn text(&self) -> &str {
+        self.engine.documents.active().text()
+    }
+
+    /// How many connected peers are subscribed to the room.
+    fn room_peers(&self) -> usize {
+        let topic = self.engine.topic();
+
+        self.network.connected().iter().filter(|peer| peer.topics.iter().any(|subscribed| subscribed == topic)).count()
+    }
+}
+
+fn ins(index: usize, char: char) -> MessageBuf {
+    MessageBuf { messages: vec![Diff { opcode: Operation::Ins, operand: Some(char), index }] }
+}
+
+/// Drives every node's swarm and engine until `done` holds for them, or
+/// panics after [`TIMEOUT`]. A swarm only makes progress while polled, so
+/// each node gets a turn in every round.
+async fn run(nodes: &mut [Node], done: impl Fn(&[Node]) -> bool) {
+    let start = Instant::now();
+
+    while !done(nodes) {
+        let texts: Vec<_> = nodes.iter().map(Node::text).collect();
+        assert!(start.elapsed() < TIMEOUT, "nodes didn't agree in time: {texts:?}");
+
+        for node in nodes.iter_mut() {
+            while let Ok(Some(event)) = tokio::time::timeout(Duration::from_millis(10), node.network.next_event()).await {
+                node.engine.handle(&mut node.network, event);
+            }
+
+            let now = Instant::now();
+            node.engine.flush_edits(&mut node.network, now);
+            node.engine.flush_outbox(&mut node.network, now);
+        }
+    }
+}
+
+/// Dials the first node from every other, and waits until they all see each
+/// other in the room.
+async fn connect(nodes: &mut [Node]) {
+    let address = nodes[0].address.clone();
+    for node in &mut nodes[1..] {
+        node.network.dial(&address).unwrap();
+    }
+
+    let others = nodes.len() - 1;
+    run(nodes, |nodes| nodes[0].room_peers() == others && nodes[1..].iter().all(|node| node.room_peers() >= 1)).await;
+}
+
+fn agree(nodes: &[Node], text: &str) -> bool {
+    nodes.iter().all(|node| node.text() == text)
+}
+
+#[tokio::test]
+async fn edits_reach_every_node_in_order() {
+    let mut nodes = [Node::new(), Node::new(), Node::new()];
+    connect(&mut nodes).await;
+
+    for (i, char) in "abc".chars().enumerate() {
+        nodes[0].edit(ins(i, char));
+    }
+    run(&mut nodes, |nodes| agree(nodes, "abchello world")).await;
+
+    nodes[2].edit(ins(14, '!'));
+    run(&mut nodes, |nodes| agree(nodes, "abchello world!")).await;
+}
+
+#[tokio::test]
+async fn concurrent_edits_converge() {
+    let mut nodes = [Node::new(), Node::new(), Node::new()];
+    for node in &mut nodes {
+        node.engine.crdt = true;
+    }
+    connect(&mut nodes).await;
+
+    for (i, char) in "xyz".chars().enumerate() {
+        nodes[i].edit(ins(5, char));
+    }
+
+    run(&mut nodes, |nodes| nodes.iter().all(|node| node.text().len() == "hello world".len() + 3) && agree(nodes, nodes[0].text())).await;
+    assert!("xyz".chars().all(|char| nodes[0].text().contains(char)));
+}
+
+#[tokio::test]
+async fn late_joiners_sync() {
+    let mut nodes = [Node::new(), Node::new()];
+
+    nodes[0].edit(ins(0, '>'));
+    connect(&mut nodes).await;
+    run(&mut nodes, |nodes| agree(nodes, ">hello world")).await;
+
+    // The edit held for nobody reaching the new node again changes nothing.
+    run(&mut nodes, |nodes| nodes.iter().all(|node| node.engine.next_retry().is_none())).await;
+    assert_eq!(nodes[1].text(), ">hello world");
+}
+
+#[tokio::test]
+async fn quitting_waits_only_for_what_is_queued() {
+    let mut nodes = [Node::new(), Node::new()];
+    connect(&mut nodes).await;
+
+    let start = Instant::now();
+    nodes[0].network.shutdown(Duration::from_secs(10)).await;
+    assert!(start.elapsed() < Duration::from_secs(5), "shutdown took {:?}", start.elapsed());
+    assert!(nodes[0].network.connected().is_empty());
+}